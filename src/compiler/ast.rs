@@ -1,57 +1,114 @@
-#[derive(Debug, Clone, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Program {
     pub story: Story,
     pub concepts: Vec<Concept>,
     pub situations: Vec<Situation>,
+    // Concepts queried with `Instances of`, whose instances must be registered
+    pub tracked_concepts: Vec<String>,
+    // Files spliced in by `Include`, so a cached parse can tell they changed
+    pub includes: Vec<std::path::PathBuf>,
 }
 
 // Story: Main entry point
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Story {
     pub body: Vec<Statement>,
 }
 
 // Concept: Class definition
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Concept {
     pub name: String,
+    /// `Concept: Dog extends Animal` (or `is an Animal`)
+    pub parent: Option<String>,
     pub fields: Vec<String>,
+    /// `Age is 18`: evaluated on each Create instead of starting at 0
+    #[serde(serialize_with = "sorted")]
+    pub defaults: std::collections::HashMap<String, Expression>,
+    /// `Require Age > 0`: checked after Create and on every Set
+    pub requirements: Vec<Requirement>,
     pub methods: Vec<Method>,
+    #[serde(serialize_with = "sorted")]
     pub when_observers: std::collections::HashMap<String, Vec<Statement>>,
+    /// Fields whose observer also runs for the value given on Create
+    /// (`When Price changes, including Create:`)
+    pub observed_on_create: Vec<String>,
+    /// `When created:` runs after `Create`, with the initial fields set
+    pub when_created: Vec<Statement>,
+    /// `When destroyed:` runs on `Destroy`
+    pub when_destroyed: Vec<Statement>,
+    /// The module it was loaded from with `Use`, for error messages; None
+    /// in the main script
+    pub file: Option<String>,
+    /// The `##` comment above it, for `sfex doc`
+    pub doc: Option<String>,
+    /// The `##` comments above fields, by field name
+    pub field_docs: std::collections::BTreeMap<String, String>,
+}
+
+// A map field in key order, so a program serializes the same way every time
+fn sorted<V: Serialize, S: serde::Serializer>(
+    map: &std::collections::HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter()
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+// Require: a condition on a concept's fields
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Requirement {
+    pub condition: Expression,
+    pub line: usize,
 }
 
 // Situation: Context that modifies behavior
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Situation {
     pub name: String,
+    /// `Situation: Sale priority 10`: higher priorities run first (default 0)
+    pub priority: i64,
+    /// `Situation: Xmas when Time.Month = 12`: decides whether it is on
+    pub condition: Option<Expression>,
     pub adjustments: Vec<Adjustment>,
+    pub line: usize,
+    /// Like `Concept::doc`
+    pub doc: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Adjustment {
     pub concept_name: String,
     pub methods: Vec<Method>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Method {
     pub name: String,
     pub parameters: Vec<String>,
     pub body: Vec<Statement>,
+    /// Like `Concept::file`
+    pub file: Option<String>,
+    pub doc: Option<String>,
 }
 
 // Statements
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Statement {
     Use {
         module_path: String, // "models.User"
         line: usize,
     },
 
-    // Variable assignment: Name is "Johgn"
+    // Variable assignment: Name is "Johgn". Editions before 2026 store a
+    // deep copy of the value; later ones store the value itself.
     Assignment {
         target: String,
         value: Expression,
+        copy: bool,
         line: usize,
     },
 
@@ -63,10 +120,17 @@ pub enum Statement {
         line: usize,
     },
 
-    // Set statement: Set Score to 100
+    // Destroy statement: Destroy Player
+    Destroy {
+        name: String,
+        line: usize,
+    },
+
+    // Set statement: Set Score to 100 (`silently` skips When observers)
     Set {
         target: Expression,
         value: Expression,
+        silent: bool,
         line: usize,
     },
 
@@ -85,6 +149,21 @@ pub enum Statement {
         situation: String,
         line: usize,
     },
+    // Reevaluate Situations: check every `when` condition again
+    ReevaluateSituations {
+        line: usize,
+    },
+    // With Situation Busy: ... (switched on for the block only)
+    WithSituation {
+        situation: String,
+        body: Vec<Statement>,
+        line: usize,
+    },
+    // Batch: ... (When observers run once the block is done)
+    Batch {
+        body: Vec<Statement>,
+        line: usize,
+    },
 
     // If/Else: If Score > 100: ... Else: ...
     If {
@@ -97,20 +176,37 @@ pub enum Statement {
     // When/Is/Otherwise: When Score: Is 100: ... Is 90: ... Otherwise: ...
     When {
         value: Expression,
-        cases: Vec<(Expression, Vec<Statement>)>,
+        cases: Vec<WhenCase>,
         otherwise: Option<Vec<Statement>>,
         line: usize,
     },
 
     // Try/Catch/Always: Try: ... Catch error: ... Always: ...
+    // The first Catch whose `when` holds handles the error
     TryCatch {
         try_body: Vec<Statement>,
-        catch_var: Option<String>, // The error variable name (e.g., "error")
-        catch_body: Option<Vec<Statement>>,
+        catches: Vec<CatchClause>,
         always_body: Option<Vec<Statement>>,
         line: usize,
     },
 
+    // Using Log from Logs.Open("app.log"): ... calls Log.Close when the
+    // block ends, whether it finished, returned or failed
+    Using {
+        name: String,
+        resource: Expression,
+        body: Vec<Statement>,
+        line: usize,
+    },
+
+    // Raise "Validation.MissingField" with "Name is required", or Raise E
+    // to throw a caught error again
+    Raise {
+        error: Expression,
+        message: Option<Expression>,
+        line: usize,
+    },
+
     // Repeat N times: Repeat 5 times: ... or Repeat 5 times With I: ...
     RepeatTimes {
         count: Expression,
@@ -127,8 +223,10 @@ pub enum Statement {
     },
 
     // For each: For each Item in List: ...
+    // (`For each Key and Value in Map:` also names the value)
     ForEach {
         variable: String,
+        value_variable: Option<String>,
         iterable: Expression,
         body: Vec<Statement>,
         line: usize,
@@ -155,14 +253,64 @@ pub enum Statement {
     },
 }
 
+// One `Is` case of a When: Is [X, Y] where X > Y: ...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WhenCase {
+    pub pattern: Pattern,
+    // Checked once the pattern matches, with its names bound
+    pub guard: Option<Expression>,
+    pub body: Vec<Statement>,
+    pub line: usize,
+}
+
+// One Catch of a Try: Catch E when E.type = "Validation": ...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatchClause {
+    pub var: Option<String>, // The error variable name (e.g., "error")
+    // Checked with the error bound; a false one passes the error on
+    pub condition: Option<Expression>,
+    pub body: Vec<Statement>,
+}
+
+// What an `Is` case matches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Pattern {
+    // Is 100, Is Limit: equal to the expression's value
+    Value(Expression),
+
+    // Is between 1 and 10: both ends included
+    Between(Expression, Expression),
+
+    // Is a Number, Is a List, Is an Order (instance of a concept)
+    Type(String),
+
+    // X in a list or map pattern, or before `where`: matches anything and
+    // names it; `_` matches without naming
+    Bind(String),
+
+    // Is [First, Second, ...Rest]: exactly these items, or at least these
+    // when there is a rest name
+    List {
+        items: Vec<Pattern>,
+        rest: Option<String>,
+    },
+
+    // Is { Name: N, Age }: a map with at least these keys
+    Map(Vec<(String, Pattern)>),
+}
+
 // Expressions
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expression {
     // Literals
     Number(String),
+    Integer(String),
     String(String),
     Boolean(bool),
 
+    // Interpolated string: "Total: {A + B}", joined as display text
+    Interpolation(Vec<Expression>),
+
     // List: [1, 2, 3]
     List(Vec<Expression>),
 
@@ -215,24 +363,56 @@ pub enum Expression {
     },
 
     // Do in background: ... - Returns TaskHandle
+    // (`Do in background share Count and Results:` passes those by reference)
     DoInBackground {
         body: Vec<Statement>,
+        shared: Vec<String>,
     },
 
     // Proceed() - Call next adjustment layer in stack
     Proceed {
         arguments: Vec<Expression>,
     },
+
+    // 1 to 100, both ends included (only after `For each ... in`)
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+    },
+
+    // Try Number(Text) otherwise 0 - the fallback when the expression
+    // raises an error or gives an Err
+    TryOtherwise {
+        expression: Box<Expression>,
+        fallback: Box<Expression>,
+    },
+
+    // Copy of Items - a deep copy that shares no List or Map with Items
+    CopyOf(Box<Expression>),
+
+    // Instances of Order where Status = "open"
+    InstancesOf {
+        concept_name: String,
+        filter: Option<Box<Expression>>,
+    },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BinaryOperator {
     // Arithmetic
-    Add,      // +
-    Subtract, // -
-    Multiply, // *
-    Divide,   // /
-    Modulo,   // %
+    Add,       // +
+    Subtract,  // -
+    Multiply,  // *
+    Divide,    // /
+    IntDivide, // //
+    Modulo,    // %
+
+    // Bitwise (whole numbers only)
+    BitAnd,     // BitAnd
+    BitOr,      // BitOr
+    Xor,        // Xor
+    ShiftLeft,  // ShiftLeft
+    ShiftRight, // ShiftRight
 
     // Comparison
     Equal,     // =
@@ -247,10 +427,591 @@ pub enum BinaryOperator {
     Or,  // or
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum UnaryOperator {
-    Not,   // not
-    Minus, // -
+    Not,    // not
+    Minus,  // -
+    BitNot, // BitNot
+}
+
+/// The situations that can adjust one method, in the order calls go
+/// through them: the first runs first and its `Proceed` calls the next.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdjustmentStack {
+    pub concept: String,
+    pub method: String,
+    pub situations: Vec<(String, i64)>,
+}
+
+/// Two instances set to hold each other through fields (`Set A.Next to B`,
+/// then `Set B.Prev to A`). Reference counting frees neither of them while
+/// the other is alive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldCycle {
+    /// The link made first, as `A.Next`
+    pub first: String,
+    /// The link that closes the cycle, as `B.Prev`
+    pub second: String,
+    pub line: usize,
+}
+
+/// Something `sfex check` finds in a `When` on a field that a `Require`
+/// limits to a few values (`Require Status = "open" or Status = "paid"`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum WhenGap {
+    /// Values no `is` case handles, in a When without `Otherwise`
+    Unhandled {
+        /// As `Order.Status`
+        field: String,
+        /// As they are written in the `Require`
+        values: Vec<String>,
+        line: usize,
+    },
+    /// An `is` case or `Otherwise` that can never run, and why
+    Unreachable {
+        field: String,
+        message: String,
+        line: usize,
+    },
+}
+
+impl WhenGap {
+    /// Its rule ID in `sfex check` and the LSP
+    pub fn rule(&self) -> &'static str {
+        match self {
+            WhenGap::Unhandled { .. } => "unhandled-case",
+            WhenGap::Unreachable { .. } => "unreachable-case",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            WhenGap::Unhandled { field, values, .. } => format!(
+                "When on {} doesn't handle {}; add a case for each, or an Otherwise",
+                field,
+                values.join(", ")
+            ),
+            WhenGap::Unreachable { message, .. } => message.clone(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        match self {
+            WhenGap::Unhandled { line, .. } | WhenGap::Unreachable { line, .. } => *line,
+        }
+    }
+}
+
+/// `Field is value` in a method of a concept with that field: it makes a
+/// variable that hides the field for the rest of the method, and the
+/// field keeps its value. `Set Field to value` was probably meant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowedField {
+    pub concept: String,
+    pub field: String,
+    pub line: usize,
+}
+
+impl ShadowedField {
+    /// Its rule ID in `sfex check`, the LSP and `sfex run --warnings`
+    pub const RULE: &'static str = "implicit-variable";
+
+    pub fn message(&self) -> String {
+        format!(
+            "{} is ... makes a new variable that hides the field {}.{}; use Set {} to ... to change the field",
+            self.field, self.concept, self.field, self.field
+        )
+    }
+}
+
+// `owner.field` set to the instance named `value` on `line`
+struct FieldLink {
+    owner: String,
+    field: String,
+    value: String,
+    line: usize,
+}
+
+impl Program {
+    /// Fields set, in the story or within one method, to an instance that
+    /// already refers back to the owner. Links made through `WeakRef(...)`
+    /// don't count.
+    pub fn field_cycles(&self) -> Vec<FieldCycle> {
+        let bodies = std::iter::once(&self.story.body).chain(
+            self.concepts
+                .iter()
+                .flat_map(|concept| concept.methods.iter().map(|method| &method.body)),
+        );
+        let mut cycles = Vec::new();
+        for body in bodies {
+            let mut links = Vec::new();
+            field_links(body, &mut links);
+            for (i, link) in links.iter().enumerate() {
+                if let Some(earlier) = links[..=i]
+                    .iter()
+                    .find(|earlier| earlier.owner == link.value && earlier.value == link.owner)
+                {
+                    cycles.push(FieldCycle {
+                        first: format!("{}.{}", earlier.owner, earlier.field),
+                        second: format!("{}.{}", link.owner, link.field),
+                        line: link.line,
+                    });
+                }
+            }
+        }
+        cycles
+    }
+
+    /// `When` statements on `X.Field` or `This.Field`, where `X` is made by
+    /// `Create` in the same body, that leave a value of the field's
+    /// `Require` unhandled or have cases that can't match. Fields their
+    /// requirements don't limit to a list of values are skipped.
+    pub fn when_gaps(&self) -> Vec<WhenGap> {
+        let mut bodies: Vec<(Option<&str>, &[Statement])> = vec![(None, &self.story.body)];
+        for concept in &self.concepts {
+            let this = Some(concept.name.as_str());
+            bodies.extend(concept.methods.iter().map(|m| (this, m.body.as_slice())));
+            bodies.extend(
+                concept
+                    .when_observers
+                    .values()
+                    .map(|b| (this, b.as_slice())),
+            );
+            bodies.push((this, &concept.when_created));
+            bodies.push((this, &concept.when_destroyed));
+        }
+        for situation in &self.situations {
+            for adjustment in &situation.adjustments {
+                let this = Some(adjustment.concept_name.as_str());
+                bodies.extend(adjustment.methods.iter().map(|m| (this, m.body.as_slice())));
+            }
+        }
+
+        let mut gaps = Vec::new();
+        for (this, body) in bodies {
+            let mut owners = std::collections::HashMap::new();
+            created_instances(body, &mut owners);
+            if let Some(concept) = this {
+                owners.insert("This".to_string(), concept.to_string());
+            }
+            when_gaps(self, body, &owners, &mut gaps);
+        }
+        gaps.sort_by_key(WhenGap::line);
+        gaps
+    }
+
+    /// The first `Field is value` of each field in each method, observer
+    /// and adjustment that sets it this way rather than with `Set`.
+    /// Parameters of the same name are variables already, and are skipped.
+    pub fn shadowed_fields(&self) -> Vec<ShadowedField> {
+        let mut bodies: Vec<(&str, &[String], &[Statement])> = Vec::new();
+        for concept in &self.concepts {
+            let name = concept.name.as_str();
+            bodies.extend(
+                concept
+                    .methods
+                    .iter()
+                    .map(|m| (name, m.parameters.as_slice(), m.body.as_slice())),
+            );
+            bodies.extend(
+                concept
+                    .when_observers
+                    .values()
+                    .map(|b| (name, &[][..], b.as_slice())),
+            );
+            bodies.push((name, &[], &concept.when_created));
+            bodies.push((name, &[], &concept.when_destroyed));
+        }
+        for situation in &self.situations {
+            for adjustment in &situation.adjustments {
+                let name = adjustment.concept_name.as_str();
+                bodies.extend(
+                    adjustment
+                        .methods
+                        .iter()
+                        .map(|m| (name, m.parameters.as_slice(), m.body.as_slice())),
+                );
+            }
+        }
+
+        let mut found = Vec::new();
+        for (concept, parameters, body) in bodies {
+            let fields = self.all_fields(concept);
+            let mut seen: Vec<String> = parameters.to_vec();
+            shadowing_assignments(body, &fields, &mut seen, &mut |field, line| {
+                found.push(ShadowedField {
+                    concept: concept.to_string(),
+                    field: field.to_string(),
+                    line,
+                })
+            });
+        }
+        found.sort_by_key(|shadowed| shadowed.line);
+        found
+    }
+
+    /// The fields of `concept` and its parents.
+    fn all_fields(&self, concept: &str) -> Vec<&str> {
+        let mut fields = Vec::new();
+        let mut current = self.concepts.iter().find(|c| c.name == concept);
+        let mut depth = 0;
+        while let Some(concept) = current {
+            fields.extend(concept.fields.iter().map(String::as_str));
+            // A parent chain that loops is a runtime error; stop here
+            depth += 1;
+            if depth > self.concepts.len() {
+                break;
+            }
+            current = concept
+                .parent
+                .as_ref()
+                .and_then(|parent| self.concepts.iter().find(|c| &c.name == parent));
+        }
+        fields
+    }
+
+    /// The values a `Require` of `concept` or a parent limits `field` to.
+    fn fixed_values(&self, concept: &str, field: &str) -> Option<Vec<String>> {
+        let mut current = self.concepts.iter().find(|c| c.name == concept);
+        let mut depth = 0;
+        while let Some(concept) = current {
+            if let Some(values) = concept
+                .requirements
+                .iter()
+                .find_map(|requirement| one_of(&requirement.condition, field))
+            {
+                return Some(values);
+            }
+            // A parent chain that loops is a runtime error; stop here
+            depth += 1;
+            if depth > self.concepts.len() {
+                break;
+            }
+            current = concept
+                .parent
+                .as_ref()
+                .and_then(|parent| self.concepts.iter().find(|c| &c.name == parent));
+        }
+        None
+    }
+
+    /// Every concept method that situations adjust, with the adjusting
+    /// situations from highest priority to lowest. Situations of equal
+    /// priority are listed by name; when they run, the one switched on last
+    /// runs first.
+    pub fn adjustment_stacks(&self) -> Vec<AdjustmentStack> {
+        let mut stacks: Vec<AdjustmentStack> = Vec::new();
+        for situation in &self.situations {
+            for adjustment in &situation.adjustments {
+                for method in &adjustment.methods {
+                    let entry = (situation.name.clone(), situation.priority);
+                    match stacks.iter_mut().find(|stack| {
+                        stack.concept == adjustment.concept_name && stack.method == method.name
+                    }) {
+                        Some(stack) => stack.situations.push(entry),
+                        None => stacks.push(AdjustmentStack {
+                            concept: adjustment.concept_name.clone(),
+                            method: method.name.clone(),
+                            situations: vec![entry],
+                        }),
+                    }
+                }
+            }
+        }
+        for stack in &mut stacks {
+            stack
+                .situations
+                .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        }
+        stacks.sort_by(|a, b| (&a.concept, &a.method).cmp(&(&b.concept, &b.method)));
+        stacks
+    }
+}
+
+fn field_links(statements: &[Statement], links: &mut Vec<FieldLink>) {
+    // The instances an expression stores: a name, or names in a List
+    let names = |value: &Expression| -> Vec<String> {
+        match value {
+            Expression::Identifier(name) => vec![name.clone()],
+            Expression::List(items) => items
+                .iter()
+                .filter_map(|item| match item {
+                    Expression::Identifier(name) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    };
+    for statement in statements {
+        match statement {
+            Statement::Set {
+                target: Expression::MemberAccess { object, member },
+                value,
+                line,
+                ..
+            } => {
+                if let Expression::Identifier(owner) = object.as_ref() {
+                    for name in names(value) {
+                        links.push(FieldLink {
+                            owner: owner.clone(),
+                            field: member.clone(),
+                            value: name,
+                            line: *line,
+                        });
+                    }
+                }
+            }
+            Statement::Create {
+                instance_name,
+                initial_fields,
+                line,
+                ..
+            } => {
+                for (field, value) in initial_fields {
+                    for name in names(value) {
+                        links.push(FieldLink {
+                            owner: instance_name.clone(),
+                            field: field.clone(),
+                            value: name,
+                            line: *line,
+                        });
+                    }
+                }
+            }
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                field_links(then_body, links);
+                field_links(else_body.as_deref().unwrap_or_default(), links);
+            }
+            Statement::When {
+                cases, otherwise, ..
+            } => {
+                for case in cases {
+                    field_links(&case.body, links);
+                }
+                field_links(otherwise.as_deref().unwrap_or_default(), links);
+            }
+            Statement::TryCatch {
+                try_body,
+                catches,
+                always_body,
+                ..
+            } => {
+                field_links(try_body, links);
+                for catch in catches {
+                    field_links(&catch.body, links);
+                }
+                field_links(always_body.as_deref().unwrap_or_default(), links);
+            }
+            Statement::RepeatTimes { body, .. }
+            | Statement::RepeatWhile { body, .. }
+            | Statement::ForEach { body, .. }
+            | Statement::WithSituation { body, .. }
+            | Statement::Batch { body, .. }
+            | Statement::Using { body, .. } => field_links(body, links),
+            _ => {}
+        }
+    }
+}
+
+// The instances a body creates, by name; a name created as two different
+// concepts is left out
+fn created_instances(
+    statements: &[Statement],
+    owners: &mut std::collections::HashMap<String, String>,
+) {
+    for statement in statements {
+        if let Statement::Create {
+            concept_name,
+            instance_name,
+            ..
+        } = statement
+        {
+            let owner = owners
+                .entry(instance_name.clone())
+                .or_insert_with(|| concept_name.clone());
+            if owner != concept_name {
+                owner.clear();
+            }
+        }
+        for body in child_bodies(statement) {
+            created_instances(body, owners);
+        }
+    }
+}
+
+// Calls `found` for the first `Field is` of each field not in `seen`, in
+// the order the statements are written
+fn shadowing_assignments(
+    statements: &[Statement],
+    fields: &[&str],
+    seen: &mut Vec<String>,
+    found: &mut impl FnMut(&str, usize),
+) {
+    for statement in statements {
+        if let Statement::Assignment { target, line, .. } = statement
+            && !seen.contains(target)
+        {
+            if fields.contains(&target.as_str()) {
+                found(target, *line);
+            }
+            seen.push(target.clone());
+        }
+        for body in child_bodies(statement) {
+            shadowing_assignments(body, fields, seen, found);
+        }
+    }
+}
+
+fn child_bodies(statement: &Statement) -> Vec<&[Statement]> {
+    match statement {
+        Statement::If {
+            then_body,
+            else_body,
+            ..
+        } => vec![then_body, else_body.as_deref().unwrap_or_default()],
+        Statement::When {
+            cases, otherwise, ..
+        } => cases
+            .iter()
+            .map(|case| case.body.as_slice())
+            .chain(otherwise.as_deref())
+            .collect(),
+        Statement::TryCatch {
+            try_body,
+            catches,
+            always_body,
+            ..
+        } => std::iter::once(try_body.as_slice())
+            .chain(catches.iter().map(|catch| catch.body.as_slice()))
+            .chain(always_body.as_deref())
+            .collect(),
+        Statement::RepeatTimes { body, .. }
+        | Statement::RepeatWhile { body, .. }
+        | Statement::ForEach { body, .. }
+        | Statement::WithSituation { body, .. }
+        | Statement::Batch { body, .. }
+        | Statement::Using { body, .. } => vec![body],
+        _ => Vec::new(),
+    }
+}
+
+// A literal as it is written, so equal values compare equal
+fn literal_text(expression: &Expression) -> Option<String> {
+    match expression {
+        Expression::String(text) => Some(format!("\"{}\"", text)),
+        Expression::Number(n) | Expression::Integer(n) => Some(n.clone()),
+        Expression::Boolean(b) => Some(if *b { "True" } else { "False" }.to_string()),
+        _ => None,
+    }
+}
+
+// `Field = A or Field = B ...`: the values it allows
+fn one_of(condition: &Expression, field: &str) -> Option<Vec<String>> {
+    let Expression::BinaryOp {
+        left,
+        operator,
+        right,
+    } = condition
+    else {
+        return None;
+    };
+    match operator {
+        BinaryOperator::Or => {
+            let mut values = one_of(left, field)?;
+            values.extend(one_of(right, field)?);
+            Some(values)
+        }
+        BinaryOperator::Equal => match (left.as_ref(), right.as_ref()) {
+            (Expression::Identifier(name), value) | (value, Expression::Identifier(name))
+                if name == field =>
+            {
+                literal_text(value).map(|value| vec![value])
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn when_gaps(
+    program: &Program,
+    statements: &[Statement],
+    owners: &std::collections::HashMap<String, String>,
+    gaps: &mut Vec<WhenGap>,
+) {
+    for statement in statements {
+        if let Statement::When {
+            value: Expression::MemberAccess { object, member },
+            cases,
+            otherwise,
+            line,
+        } = statement
+            && let Expression::Identifier(owner) = object.as_ref()
+            && let Some(concept) = owners.get(owner)
+            && let Some(allowed) = program.fixed_values(concept, member)
+        {
+            let field = format!("{}.{}", concept, member);
+            let unreachable = |message: String, line: usize| WhenGap::Unreachable {
+                field: field.clone(),
+                message,
+                line,
+            };
+            let mut handled: Vec<String> = Vec::new();
+            // A case that matches values this can't tell (is Limit, is a
+            // String, ...) may handle the rest
+            let mut unknown = false;
+            for case in cases {
+                match &case.pattern {
+                    Pattern::Value(value) => match literal_text(value) {
+                        Some(value) if !allowed.contains(&value) => {
+                            gaps.push(unreachable(
+                                format!("is {} never matches: {} is never {}", value, field, value),
+                                case.line,
+                            ));
+                        }
+                        Some(value) if handled.contains(&value) => {
+                            gaps.push(unreachable(
+                                format!("is {} never runs: an earlier case handles it", value),
+                                case.line,
+                            ));
+                        }
+                        Some(value) if case.guard.is_none() => handled.push(value),
+                        Some(_) => {}
+                        None => unknown = true,
+                    },
+                    _ => unknown = true,
+                }
+            }
+
+            let missing: Vec<String> = allowed
+                .iter()
+                .filter(|value| !handled.contains(value))
+                .cloned()
+                .collect();
+            if otherwise.is_some() {
+                if missing.is_empty() {
+                    gaps.push(unreachable(
+                        format!("Otherwise never runs: the cases handle every {}", field),
+                        *line,
+                    ));
+                }
+            } else if !unknown && !missing.is_empty() {
+                gaps.push(WhenGap::Unhandled {
+                    field: field.clone(),
+                    values: missing,
+                    line: *line,
+                });
+            }
+        }
+        for body in child_bodies(statement) {
+            when_gaps(program, body, owners, gaps);
+        }
+    }
 }
 
 // Helper constructors for common patterns
@@ -259,6 +1020,10 @@ impl Expression {
         Expression::Number(value.to_string())
     }
 
+    pub fn integer(value: &str) -> Self {
+        Expression::Integer(value.to_string())
+    }
+
     pub fn string(value: &str) -> Self {
         Expression::String(value.to_string())
     }
@@ -285,6 +1050,7 @@ impl Statement {
         Statement::Assignment {
             target: target.to_string(),
             value,
+            copy: false,
             line: 0,
         }
     }
@@ -329,6 +1095,7 @@ mod tests {
                 target,
                 value,
                 line: 0,
+                ..
             } => {
                 assert_eq!(target, "Name");
                 assert_eq!(value, Expression::String("Temka".to_string()));
@@ -418,7 +1185,10 @@ mod tests {
     fn test_create_concept() {
         let concept = Concept {
             name: "User".to_string(),
+            parent: None,
             fields: vec!["Name".to_string(), "Score".to_string()],
+            defaults: std::collections::HashMap::new(),
+            requirements: Vec::new(),
             methods: vec![Method {
                 name: "AddPoints".to_string(),
                 parameters: vec!["Amount".to_string()],
@@ -429,14 +1199,163 @@ mod tests {
                         BinaryOperator::Add,
                         Expression::identifier("Amount"),
                     ),
+                    silent: false,
                     line: 0,
                 }],
+                file: None,
+                doc: None,
             }],
             when_observers: std::collections::HashMap::new(),
+            observed_on_create: Vec::new(),
+            when_created: Vec::new(),
+            when_destroyed: Vec::new(),
+            file: None,
+            doc: None,
+            field_docs: std::collections::BTreeMap::new(),
         };
 
         assert_eq!(concept.name, "User");
         assert_eq!(concept.fields.len(), 2);
         assert_eq!(concept.methods.len(), 1);
     }
+
+    #[test]
+    fn test_adjustment_stacks() {
+        let situation = |name: &str, priority: i64| Situation {
+            name: name.to_string(),
+            priority,
+            condition: None,
+            adjustments: vec![Adjustment {
+                concept_name: "Ticket".to_string(),
+                methods: vec![Method {
+                    name: "Total".to_string(),
+                    parameters: Vec::new(),
+                    body: Vec::new(),
+                    file: None,
+                    doc: None,
+                }],
+            }],
+            line: 0,
+            doc: None,
+        };
+        let program = Program {
+            story: Story { body: Vec::new() },
+            concepts: Vec::new(),
+            situations: vec![
+                situation("Weekend", 0),
+                situation("Member", 10),
+                situation("Audit", -1),
+            ],
+            tracked_concepts: Vec::new(),
+            includes: Vec::new(),
+        };
+
+        let stacks = program.adjustment_stacks();
+        assert_eq!(stacks.len(), 1);
+        assert_eq!(
+            stacks[0].situations,
+            vec![
+                ("Member".to_string(), 10),
+                ("Weekend".to_string(), 0),
+                ("Audit".to_string(), -1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_field_cycles() {
+        let cycles = |source: &str| {
+            let tokens = crate::Lexer::new(source).tokenize().unwrap();
+            crate::Parser::new(tokens).parse().unwrap().field_cycles()
+        };
+        let linked = "Concept: Node\n    Next, Prev\n\nStory:\n    Create Node Called A\n    Create Node Called B\n    Set A.Next to B\n    If True:\n        Set B.Prev to A\n    Set A.Prev to A\n";
+        assert_eq!(
+            cycles(linked),
+            vec![
+                FieldCycle {
+                    first: "A.Next".to_string(),
+                    second: "B.Prev".to_string(),
+                    line: 9,
+                },
+                FieldCycle {
+                    first: "A.Prev".to_string(),
+                    second: "A.Prev".to_string(),
+                    line: 10,
+                },
+            ]
+        );
+
+        let weak = "Concept: Node\n    Next, Prev\n\nStory:\n    Create Node Called A\n    Create Node Called B\n    Set A.Next to B\n    Set B.Prev to WeakRef(A)\n";
+        assert!(cycles(weak).is_empty());
+    }
+
+    #[test]
+    fn test_when_gaps() {
+        let gaps = |source: &str| {
+            let tokens = crate::Lexer::new(source).tokenize().unwrap();
+            crate::Parser::new(tokens).parse().unwrap().when_gaps()
+        };
+        let source = "Concept: Order\n    Status is \"open\"\n    Require Status = \"open\" or Status = \"paid\"\n\nConcept: Rush extends Order\n    To Describe:\n        When This.Status:\n            is \"open\":\n                Return 1\n            is \"paid\":\n                Return 2\n            Otherwise:\n                Return 3\n\nStory:\n    Create Order Called First\n    When First.Status:\n        is \"open\":\n            Print 1\n        is \"open\":\n            Print 2\n        is \"lost\":\n            Print 3\n    When First.Status:\n        is Other where Other != \"open\":\n            Print 4\n";
+        let found = gaps(source);
+        let messages: Vec<(&str, usize)> =
+            found.iter().map(|gap| (gap.rule(), gap.line())).collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("unreachable-case", 7),
+                ("unhandled-case", 17),
+                ("unreachable-case", 20),
+                ("unreachable-case", 22),
+            ]
+        );
+        assert_eq!(
+            found[1],
+            WhenGap::Unhandled {
+                field: "Order.Status".to_string(),
+                values: vec!["\"paid\"".to_string()],
+                line: 17,
+            }
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let source = "Concept: Order\n    Total is 0, Status is \"open\", Notes is \"\"\n\n    When Total changes:\n        Print New\n    When Status changes:\n        Print New\n\nStory:\n    Create Order Called First\n    Set First.Total to 5\n";
+        let tokens = crate::Lexer::new(source).tokenize().unwrap();
+        let program = crate::Parser::new(tokens).parse().unwrap();
+        let json = serde_json::to_value(&program).unwrap();
+        let defaults: Vec<&String> = json["concepts"][0]["defaults"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        assert_eq!(defaults, ["Notes", "Status", "Total"]);
+        let read: Program = serde_json::from_value(json).unwrap();
+        assert_eq!(read, program);
+    }
+
+    #[test]
+    fn test_shadowed_fields() {
+        let source = "Concept: Account\n    Balance, Owner\n\n    To Deposit with Amount:\n        Balance is Balance + Amount\n        Balance is Balance + 1\n\n    To Rename with Owner:\n        Owner is Owner + \"!\"\n\nConcept: Savings extends Account\n    To Reset:\n        If True:\n            Balance is 0\n        Set Balance to 0\n\nStory:\n    Balance is 1\n";
+        let tokens = crate::Lexer::new(source).tokenize().unwrap();
+        let found = crate::Parser::new(tokens)
+            .parse()
+            .unwrap()
+            .shadowed_fields();
+        assert_eq!(
+            found,
+            vec![
+                ShadowedField {
+                    concept: "Account".to_string(),
+                    field: "Balance".to_string(),
+                    line: 5,
+                },
+                ShadowedField {
+                    concept: "Savings".to_string(),
+                    field: "Balance".to_string(),
+                    line: 14,
+                },
+            ]
+        );
+    }
 }
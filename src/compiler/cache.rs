@@ -0,0 +1,192 @@
+use super::ast::{Program, Statement};
+use super::edition::Edition;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Programs parsed ahead of time by `sfex precompile`, so a server doesn't
+// tokenize and parse every handler and module on its first requests. Each
+// program is kept with a hash of the source it came from, and of the files it
+// `Include`s, and its edition; a file that changed since is parsed again as
+// usual.
+
+/// Where the cache is kept, relative to the project root
+pub const CACHE_FILE: &str = ".sfex/precompiled.bin";
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    // Programs from another sfex version may not match this AST
+    version: String,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    hash: Vec<u8>,
+    // Each included file with the hash of what it held
+    includes: Vec<(PathBuf, Vec<u8>)>,
+    edition: String,
+    program: Program,
+}
+
+static LOADED: OnceLock<HashMap<PathBuf, Entry>> = OnceLock::new();
+
+/// A parsed script to save, with the source and edition it was parsed from.
+pub struct Compiled {
+    pub path: PathBuf,
+    pub source: String,
+    pub edition: Edition,
+    pub program: Program,
+}
+
+/// Write the programs to the project's cache, replacing what was there.
+/// Returns the size of the file in bytes.
+pub fn write(root: &Path, compiled: Vec<Compiled>) -> Result<u64, String> {
+    let entries = compiled
+        .into_iter()
+        .map(|script| Entry {
+            path: script.path.canonicalize().unwrap_or(script.path),
+            hash: source_hash(&script.source),
+            includes: script
+                .program
+                .includes
+                .iter()
+                .map(|path| (path.clone(), file_hash(path)))
+                .collect(),
+            edition: script.edition.as_str().to_string(),
+            program: script.program,
+        })
+        .collect();
+    let file = CacheFile {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        entries,
+    };
+    let bytes = bincode::serialize(&file)
+        .map_err(|e| format!("Failed to encode precompiled programs: {}", e))?;
+
+    let path = root.join(CACHE_FILE);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&path, &bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(bytes.len() as u64)
+}
+
+/// Load the project's cache for `lookup`, if it has one. Returns how many
+/// programs it holds. Only the first successful load is kept.
+pub fn load(root: &Path) -> Result<Option<usize>, String> {
+    let path = root.join(CACHE_FILE);
+    let Ok(bytes) = fs::read(&path) else {
+        return Ok(None);
+    };
+    let file = decode(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let entries: HashMap<PathBuf, Entry> = file
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+    let count = entries.len();
+    let _ = LOADED.set(entries);
+    Ok(Some(count))
+}
+
+fn decode(bytes: &[u8]) -> Result<CacheFile, String> {
+    let invalid = |_| "not a precompiled program cache".to_string();
+    // The version comes first, so it can be checked before the programs
+    let version: String = bincode::deserialize(bytes).map_err(invalid)?;
+    if version != env!("CARGO_PKG_VERSION") {
+        return Err(format!(
+            "made by sfex {}, run `sfex precompile` again",
+            version
+        ));
+    }
+    bincode::deserialize(bytes).map_err(invalid)
+}
+
+/// The precompiled program for `path`, if one was loaded and it was parsed
+/// from this exact source, and the files it includes, with this edition.
+pub fn lookup(path: &Path, source: &str, edition: Edition) -> Option<Program> {
+    let loaded = LOADED.get()?;
+    let path = path.canonicalize().ok()?;
+    let entry = loaded.get(&path)?;
+    let unchanged = entry.hash == source_hash(source)
+        && entry
+            .includes
+            .iter()
+            .all(|(path, hash)| file_hash(path) == *hash);
+    (entry.edition == edition.as_str() && unchanged).then(|| entry.program.clone())
+}
+
+/// How long reading back a cache file of `bytes` takes, for reporting.
+pub fn time_load(bytes: &[u8]) -> Result<std::time::Duration, String> {
+    let started = std::time::Instant::now();
+    decode(bytes)?;
+    Ok(started.elapsed())
+}
+
+/// The modules a program's story `Use`s, as written.
+pub fn used_modules(program: &Program) -> Vec<&str> {
+    program
+        .story
+        .body
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Use { module_path, .. } => Some(module_path.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Empty for a file that can't be read, which no source hashes to
+fn file_hash(path: &Path) -> Vec<u8> {
+    fs::read_to_string(path)
+        .map(|source| source_hash(&source))
+        .unwrap_or_default()
+}
+
+fn source_hash(source: &str) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, source.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::lexer::Lexer;
+    use crate::compiler::parser::Parser;
+
+    #[test]
+    fn test_round_trip() {
+        let source = "Use models.User\n\nStory:\n    X is Try Integer(\"4\") otherwise 0\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert_eq!(used_modules(&program), vec!["models/User.sfex"]);
+
+        let file = CacheFile {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            entries: vec![Entry {
+                path: PathBuf::from("main.sfex"),
+                hash: source_hash(source),
+                includes: Vec::new(),
+                edition: Edition::default().as_str().to_string(),
+                program: program.clone(),
+            }],
+        };
+        let bytes = bincode::serialize(&file).unwrap();
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.entries[0].program, program);
+        assert_ne!(decoded.entries[0].hash, source_hash("Story:\n"));
+
+        let stale = CacheFile {
+            version: "0.0.0".to_string(),
+            entries: Vec::new(),
+        };
+        assert!(decode(&bincode::serialize(&stale).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use super::lexer::{Lexer, LexerError};
+use super::token::{Token, TokenType};
+use std::fmt;
+use std::str::FromStr;
+
+// A project declares its edition in sfex.toml (`edition = "2025"` under
+// [package]). Syntax that would break existing scripts, such as a new keyword
+// that used to be a valid variable name, only turns on from the edition that
+// introduces it; `sfex migrate` rewrites a project for a newer edition.
+//
+// To add a keyword in a new edition: add the edition below, list the word in
+// EDITION_KEYWORDS, and guard its lexer arm with `self.edition.reserves(..)`.
+//
+// Edition 2026 changes what `X is Y` does: it stores Y itself, as `Set`,
+// fields and method arguments already do, instead of a deep copy of it.
+// `Copy of Y` asks for the copy, and `sfex migrate` adds it where needed.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Edition {
+    /// Scripts that don't declare an edition get the first one, so they keep
+    /// working when later editions reserve new words.
+    #[default]
+    E2025,
+    E2026,
+}
+
+/// Keywords added after the first edition, with the edition that adds them.
+const EDITION_KEYWORDS: &[(&str, Edition)] = &[];
+
+impl Edition {
+    pub const ALL: &'static [Edition] = &[Edition::E2025, Edition::E2026];
+    /// What `sfex new` writes and `sfex migrate` upgrades to.
+    pub const LATEST: Edition = Edition::E2026;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Edition::E2025 => "2025",
+            Edition::E2026 => "2026",
+        }
+    }
+
+    /// Whether `X is Y` stores a deep copy of Y.
+    pub fn copies_on_assign(self) -> bool {
+        self < Edition::E2026
+    }
+
+    /// Whether `word` is a keyword in this edition that older editions read
+    /// as an identifier.
+    pub fn reserves(self, word: &str) -> bool {
+        EDITION_KEYWORDS
+            .iter()
+            .any(|(keyword, since)| *keyword == word && *since <= self)
+    }
+
+    /// Words that are identifiers in `self` but keywords in `target`.
+    pub fn new_keywords(self, target: Edition) -> Vec<&'static str> {
+        EDITION_KEYWORDS
+            .iter()
+            .filter(|(_, since)| *since > self && *since <= target)
+            .map(|(keyword, _)| *keyword)
+            .collect()
+    }
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Edition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Edition::ALL
+            .iter()
+            .copied()
+            .find(|edition| edition.as_str() == s.trim())
+            .ok_or_else(|| {
+                let known: Vec<&str> = Edition::ALL.iter().map(|e| e.as_str()).collect();
+                format!("Unknown edition '{}' (known: {})", s, known.join(", "))
+            })
+    }
+}
+
+/// Rename identifiers that become keywords to `<Word>_`, lexing `source` as
+/// `edition` so only real identifiers change (not strings or comments).
+/// Returns the new source and how many names were renamed.
+pub fn rename_identifiers(
+    source: &str,
+    edition: Edition,
+    words: &[&str],
+) -> Result<(String, usize), LexerError> {
+    if words.is_empty() {
+        return Ok((source.to_string(), 0));
+    }
+
+    let tokens = Lexer::with_edition(source, edition).tokenize()?;
+    let mut renames: Vec<(usize, usize)> = tokens
+        .iter()
+        .filter(|token| {
+            matches!(&token.token_type, TokenType::Identifier(name) if words.contains(&name.as_str()))
+        })
+        .map(|token| (token.line, token.column))
+        .collect();
+    if renames.is_empty() {
+        return Ok((source.to_string(), 0));
+    }
+    renames.sort_unstable();
+
+    // Token columns count characters from 1; insert the `_` after each name
+    let mut output = String::with_capacity(source.len() + renames.len());
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let mut ends: Vec<usize> = renames
+            .iter()
+            .filter(|(line_number, _)| *line_number == index + 1)
+            .filter_map(|(_, column)| {
+                let rest: String = line.chars().skip(column - 1).collect();
+                words
+                    .iter()
+                    .find(|word| rest.starts_with(**word))
+                    .map(|word| column - 1 + word.chars().count())
+            })
+            .collect();
+        ends.sort_unstable();
+        for (position, c) in line.chars().enumerate() {
+            if ends.first() == Some(&position) {
+                output.push('_');
+                ends.remove(0);
+            }
+            output.push(c);
+        }
+        if !ends.is_empty() {
+            output.push('_');
+        }
+    }
+    Ok((output, renames.len()))
+}
+
+/// Write `Copy of` into assignments that copied a List or Map in `edition`
+/// and would share it in later editions: those whose value is a name, a
+/// member or an index (`Backup is Items`, `Row is Table.Rows[1]`). Returns
+/// the new source and how many assignments changed.
+pub fn copy_assignments(source: &str, edition: Edition) -> Result<(String, usize), LexerError> {
+    let tokens = Lexer::with_edition(source, edition).tokenize()?;
+    let mut inserts: Vec<(usize, usize)> = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let starts_statement = index == 0
+            || matches!(
+                tokens[index - 1].token_type,
+                TokenType::Newline | TokenType::Indent | TokenType::Dedent
+            );
+        let assigns = matches!(token.token_type, TokenType::Identifier(_))
+            && tokens.get(index + 1).map(|t| &t.token_type) == Some(&TokenType::Is);
+        if starts_statement
+            && assigns
+            && let Some(value) = tokens.get(index + 2)
+            && is_place(&tokens[index + 2..])
+        {
+            inserts.push((value.line, value.column));
+        }
+    }
+    if inserts.is_empty() {
+        return Ok((source.to_string(), 0));
+    }
+
+    let mut output = String::with_capacity(source.len() + inserts.len() * 8);
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let column = inserts
+            .iter()
+            .find(|(line_number, _)| *line_number == index + 1)
+            .map(|(_, column)| column - 1);
+        for (position, c) in line.chars().enumerate() {
+            if column == Some(position) {
+                output.push_str("Copy of ");
+            }
+            output.push(c);
+        }
+    }
+    Ok((output, inserts.len()))
+}
+
+// Whether the tokens up to the end of the line are only a name followed by
+// `.Member`s and `[index]`es
+fn is_place(tokens: &[Token]) -> bool {
+    if !matches!(tokens.first().map(|t| &t.token_type), Some(TokenType::Identifier(name)) if name != "None")
+    {
+        return false;
+    }
+    let mut depth = 0;
+    let mut index = 1;
+    while let Some(token) = tokens.get(index) {
+        match &token.token_type {
+            TokenType::Newline
+            | TokenType::Dedent
+            | TokenType::Eof
+            | TokenType::Comment(_)
+            | TokenType::DocComment(_)
+                if depth == 0 =>
+            {
+                return true;
+            }
+            TokenType::LeftBracket => depth += 1,
+            TokenType::RightBracket if depth > 0 => depth -= 1,
+            TokenType::Dot if depth == 0 => {
+                if !matches!(
+                    tokens.get(index + 1).map(|t| &t.token_type),
+                    Some(TokenType::Identifier(_))
+                ) {
+                    return false;
+                }
+                index += 1;
+            }
+            _ if depth > 0 => {}
+            _ => return false,
+        }
+        index += 1;
+    }
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_edition() {
+        assert_eq!("2025".parse::<Edition>().unwrap(), Edition::E2025);
+        assert_eq!("2026".parse::<Edition>().unwrap(), Edition::E2026);
+        assert!("1999".parse::<Edition>().is_err());
+        assert!(Edition::LATEST >= Edition::default());
+        assert!(Edition::default().new_keywords(Edition::LATEST).len() <= EDITION_KEYWORDS.len());
+    }
+
+    #[test]
+    fn test_copy_assignments() {
+        let source = "Story:\n    Backup is Items # keep\n    Row is Table.Rows[I + 1]\n    Count is Items.Length + 1\n    Total is Sum(Items)\n    Empty is None\n    Copy is Backup";
+        let (migrated, count) = copy_assignments(source, Edition::E2025).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(
+            migrated,
+            "Story:\n    Backup is Copy of Items # keep\n    Row is Copy of Table.Rows[I + 1]\n    Count is Items.Length + 1\n    Total is Sum(Items)\n    Empty is None\n    Copy is Copy of Backup"
+        );
+        assert!(Edition::E2025.copies_on_assign());
+        assert!(!Edition::E2026.copies_on_assign());
+    }
+
+    #[test]
+    fn test_rename_identifiers() {
+        let source = "Story:\n    Yield is 3\n    Print \"Yield\" + Yield # Yield\n    Print Yield";
+        let (migrated, count) = rename_identifiers(source, Edition::E2025, &["Yield"]).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(
+            migrated,
+            "Story:\n    Yield_ is 3\n    Print \"Yield\" + Yield_ # Yield\n    Print Yield_"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::edition::Edition;
 use super::token::{Token, TokenType};
 use std::iter::Peekable;
 use std::str::Chars;
@@ -6,6 +7,21 @@ const MAX_INDENT: usize = 100;
 const TAB_SIZE: usize = 8;
 const ALT_TAB_SIZE: usize = 1;
 
+/// The indentation of `line` as the lexer measures it: a space is one
+/// column and a tab moves to the next multiple of 8.
+pub fn indent_width(line: &str) -> usize {
+    let mut col = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => col += 1,
+            '\t' => col = (col / TAB_SIZE + 1) * TAB_SIZE,
+            '\x0C' => col = 0,
+            _ => break,
+        }
+    }
+    col
+}
+
 #[derive(Debug, Clone)]
 pub enum LexerErrorKind {
     TooDeep,
@@ -14,18 +30,22 @@ pub enum LexerErrorKind {
     UnexpectedChar(char),
     UnterminatedString,
     NewlineInString,
+    InvalidEscape(String),
 }
 
 #[derive(Debug, Clone)]
 pub struct LexerError {
     pub kind: LexerErrorKind,
     pub line: usize,
+    /// In characters from 1, like `Token::column`
     pub column: usize,
+    /// Byte offset in the source
+    pub offset: usize,
 }
 
 pub struct Lexer<'a> {
     input: Peekable<Chars<'a>>,
-    _source: &'a str,
+    source: &'a str,
 
     // Position tracking
     line: usize,
@@ -40,13 +60,21 @@ pub struct Lexer<'a> {
 
     // Buffered tokens
     token_buffer: Vec<Token>,
+    // Set once Eof or an error has been returned
+    finished: bool,
+
+    edition: Edition,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str) -> Self {
+        Self::with_edition(source, Edition::default())
+    }
+
+    pub fn with_edition(source: &'a str, edition: Edition) -> Self {
         Self {
             input: source.chars().peekable(),
-            _source: source,
+            source,
             line: 1,
             column: 1,
             position: 0,
@@ -55,23 +83,18 @@ impl<'a> Lexer<'a> {
             atbol: true, // Start at beginning of line
             pendin: 0,
             token_buffer: Vec::new(),
+            finished: false,
+            edition,
         }
     }
 
+    pub fn edition(&self) -> Edition {
+        self.edition
+    }
+
     // Main tokenization function
     pub fn tokenize(&mut self) -> Result<Vec<Token>, LexerError> {
-        let mut tokens = Vec::new();
-
-        loop {
-            let token = self.next_token()?;
-            let is_eof = matches!(token.token_type, TokenType::Eof);
-            tokens.push(token);
-            if is_eof {
-                break;
-            }
-        }
-
-        Ok(tokens)
+        self.collect()
     }
 
     fn error(&self, kind: LexerErrorKind) -> LexerError {
@@ -79,6 +102,7 @@ impl<'a> Lexer<'a> {
             kind,
             line: self.line,
             column: self.column,
+            offset: self.position,
         }
     }
 
@@ -103,7 +127,10 @@ impl<'a> Lexer<'a> {
 
         // skip remaining whitespace, spaces between tokens
         self.skip_whitespace();
-        self.read_token()
+        let offset = self.position;
+        let mut token = self.read_token()?;
+        token.offset = offset;
+        Ok(token)
     }
 
     /// Indentation handling
@@ -208,7 +235,9 @@ impl<'a> Lexer<'a> {
             TokenType::Indent
         };
 
-        Token::new(token_type, self.line, self.column, 0)
+        let mut token = Token::new(token_type, self.line, self.column, 0);
+        token.offset = self.position;
+        token
     }
 
     /// Read the next token
@@ -247,34 +276,9 @@ impl<'a> Lexer<'a> {
 
             Some('#') => self.read_comment(),
 
-            Some('"') | Some('\'') => {
-                let quote = self.peek_char().unwrap();
-                // Save the first quote
-                self.advance();
+            Some('"') | Some('\'') => self.read_string(false),
 
-                if self.peek_char() == Some(quote) {
-                    self.advance(); // consume second quote
-
-                    // Check if third character is also a quote
-                    if self.peek_char() == Some(quote) {
-                        // Triple quote detected!
-                        self.advance(); // consume third quote
-                        return self.read_triple_quoted_string(quote);
-                    } else {
-                        // Only two quotes, this is a empty string "maybe"
-                        let length = 2;
-                        return Ok(Token::new(
-                            TokenType::String_(String::new()),
-                            self.line,
-                            self.column - length,
-                            length,
-                        ));
-                    }
-                } else {
-                    // Single quote - read regular string content
-                    return self.read_string_content(quote);
-                }
-            }
+            Some('r') if self.at_raw_string() => self.read_string(true),
 
             Some(c) if c.is_ascii_digit() => self.read_number(),
 
@@ -372,7 +376,17 @@ impl<'a> Lexer<'a> {
 
             Some('/') => {
                 self.advance();
-                Ok(Token::new(TokenType::Slash, self.line, self.column - 1, 1))
+                if self.peek_char() == Some('/') {
+                    self.advance();
+                    Ok(Token::new(
+                        TokenType::SlashSlash,
+                        self.line,
+                        self.column - 2,
+                        2,
+                    ))
+                } else {
+                    Ok(Token::new(TokenType::Slash, self.line, self.column - 1, 1))
+                }
             }
 
             Some('%') => {
@@ -465,7 +479,14 @@ impl<'a> Lexer<'a> {
 
     fn read_comment(&mut self) -> Result<Token, LexerError> {
         let start_col = self.column;
+        // `##` starts a doc comment; `###` and longer runs are still
+        // ordinary comments, so banners stay out of the docs
+        let rest = &self.source[self.position..];
+        let doc = rest.starts_with("##") && !rest.starts_with("###");
         self.advance(); // Skip '#'
+        if doc {
+            self.advance();
+        }
 
         let mut comment = String::new();
         while let Some(c) = self.peek_char() {
@@ -476,108 +497,90 @@ impl<'a> Lexer<'a> {
             self.advance();
         }
 
+        let text = comment.trim().to_string();
         Ok(Token::new(
-            TokenType::Comment(comment.trim().to_string()),
+            if doc {
+                TokenType::DocComment(text)
+            } else {
+                TokenType::Comment(text)
+            },
             self.line,
             start_col,
-            comment.len() + 1,
+            comment.chars().count() + if doc { 2 } else { 1 },
         ))
     }
 
-    fn read_string_content(&mut self, quote: char) -> Result<Token, LexerError> {
-        let start_col = self.column - 1;
-        let mut value = String::new();
-
-        loop {
-            match self.peek_char() {
-                None => return Err(self.error(LexerErrorKind::UnterminatedString)),
+    /// Whether the input is at `r"` or `r'`, the start of a raw string.
+    fn at_raw_string(&self) -> bool {
+        let rest = &self.source[self.position..];
+        rest.starts_with("r\"") || rest.starts_with("r'")
+    }
 
-                Some(c) if c == quote => {
-                    self.advance();
-                    break;
-                }
-                Some('\\') => {
-                    self.advance();
-                    if let Some(escaped) = self.advance() {
-                        match escaped {
-                            'n' => value.push('\n'),
-                            't' => value.push('\t'),
-                            'r' => value.push('\r'),
-                            '\\' => value.push('\\'),
-                            c if c == quote => value.push(c),
-                            c => {
-                                value.push('\\');
-                                value.push(c);
-                            }
-                        }
-                    }
-                }
-                Some('\r') | Some('\n') => {
-                    return Err(self.error(LexerErrorKind::NewlineInString));
-                }
-                Some(c) => {
-                    value.push(c);
-                    self.advance();
-                }
-            }
+    /// Read a string literal: `"..."`, `'...'`, a triple-quoted `"""..."""`
+    /// that may span lines, or any of these with an `r` prefix, which keeps
+    /// backslashes as written. The token's line and column point at the
+    /// opening quote (or the `r`), and its length counts source characters.
+    fn read_string(&mut self, raw: bool) -> Result<Token, LexerError> {
+        let (start_line, start_col, start_pos) = (self.line, self.column, self.position);
+        if raw {
+            self.advance(); // 'r'
         }
+        let quote = self.advance().unwrap_or('"');
+        let mut rest = self.source[self.position..].chars();
+        let triple = rest.next() == Some(quote) && rest.next() == Some(quote);
+        if triple {
+            self.advance();
+            self.advance();
+        }
+        let unterminated = LexerError {
+            kind: LexerErrorKind::UnterminatedString,
+            line: start_line,
+            column: start_col,
+            offset: start_pos,
+        };
 
-        let length = value.len() + 2;
-
-        Ok(Token::new(
-            TokenType::String_(value),
-            self.line,
-            start_col,
-            length,
-        ))
-    }
-
-    fn read_triple_quoted_string(&mut self, quote: char) -> Result<Token, LexerError> {
-        let start_col = self.column;
-        let start_line = self.line;
         let mut value = String::new();
-
         loop {
             match self.peek_char() {
-                None => return Err(self.error(LexerErrorKind::UnterminatedString)),
-
+                None => return Err(unterminated),
                 Some(c) if c == quote => {
-                    // Check if this is the start of closing triple quotes
                     self.advance();
-
+                    if !triple {
+                        break;
+                    }
+                    // Fewer than three quotes in a row are part of the value
                     if self.peek_char() == Some(quote) {
-                        self.advance(); // Second quote
-
+                        self.advance();
                         if self.peek_char() == Some(quote) {
-                            self.advance(); // Third quote - done
+                            self.advance();
                             break;
-                        } else {
-                            // Only two quotes - add them both to the value
-                            value.push(quote);
-                            value.push(quote);
                         }
-                    } else {
-                        // Only one quote - add it to the value
                         value.push(quote);
                     }
+                    value.push(quote);
                 }
-                Some('\\') => {
+                Some('\\') if !raw => {
+                    let at = (self.line, self.column, self.position);
                     self.advance();
-                    if let Some(escaped) = self.advance() {
-                        match escaped {
-                            'n' => value.push('\n'),
-                            't' => value.push('\t'),
-                            'r' => value.push('\r'),
-                            '\\' => value.push('\\'),
-                            c if c == quote => value.push(c),
-                            c => {
-                                value.push('\\');
-                                value.push(c);
-                            }
+                    match self.advance() {
+                        None => return Err(unterminated),
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('r') => value.push('\r'),
+                        Some('0') => value.push('\0'),
+                        Some('u') if self.peek_char() == Some('{') => {
+                            value.push(self.read_unicode_escape(at)?)
+                        }
+                        Some(c @ ('\\' | '"' | '\'')) => value.push(c),
+                        Some(c) => {
+                            value.push('\\');
+                            value.push(c);
                         }
                     }
                 }
-                // Allow literal newlines in triple-quoted strings
+                Some('\r') | Some('\n') if !triple => {
+                    return Err(self.error(LexerErrorKind::NewlineInString));
+                }
                 Some('\r') => {
                     self.advance();
                     // Handle CRLF as a single newline
@@ -601,14 +604,44 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        let length = value.len() + 6;
+        let length = self.source[start_pos..self.position].chars().count();
+        let token_type = if raw {
+            TokenType::RawString(value)
+        } else {
+            TokenType::String_(value)
+        };
+        Ok(Token::new(token_type, start_line, start_col, length))
+    }
 
-        Ok(Token::new(
-            TokenType::String_(value),
-            start_line,
-            start_col,
-            length,
-        ))
+    /// Read the `{HEX}` of a `\u{HEX}` escape; `at` is the line, column and
+    /// offset of the backslash.
+    fn read_unicode_escape(
+        &mut self,
+        (line, column, offset): (usize, usize, usize),
+    ) -> Result<char, LexerError> {
+        self.advance(); // '{'
+        let mut digits = String::new();
+        while let Some(c) = self.peek_char() {
+            if !c.is_ascii_hexdigit() || digits.len() == 6 {
+                break;
+            }
+            digits.push(c);
+            self.advance();
+        }
+        let closed = self.peek_char() == Some('}');
+        if closed {
+            self.advance();
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .filter(|_| closed)
+            .ok_or(LexerError {
+                kind: LexerErrorKind::InvalidEscape(format!("\\u{{{}", digits)),
+                line,
+                column,
+                offset,
+            })
     }
 
     fn read_number(&mut self) -> Result<Token, LexerError> {
@@ -625,12 +658,13 @@ impl<'a> Lexer<'a> {
         }
 
         let length = number.len();
-        Ok(Token::new(
-            TokenType::Number(number),
-            self.line,
-            start_col,
-            length,
-        ))
+        // Whole literals are exact Integers; a decimal point makes a Number
+        let token_type = if number.contains('.') {
+            TokenType::Number(number)
+        } else {
+            TokenType::Integer(number)
+        };
+        Ok(Token::new(token_type, self.line, start_col, length))
     }
 
     fn read_identifier_or_keyword(&mut self) -> Result<Token, LexerError> {
@@ -646,7 +680,7 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        let length = ident.len();
+        let length = ident.chars().count();
         let token_type = match ident.as_str() {
             // Length 2
             "Do" => TokenType::Do,
@@ -705,6 +739,9 @@ impl<'a> Lexer<'a> {
             // Length 10
             "background" => TokenType::Background,
 
+            // Keywords from later editions go above with a guard, e.g.
+            // "Yield" if self.edition.reserves("Yield") => TokenType::Yield,
+
             // Default
             _ => TokenType::Identifier(ident),
         };
@@ -713,9 +750,24 @@ impl<'a> Lexer<'a> {
     }
 }
 
-impl std::fmt::Display for LexerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let message = match &self.kind {
+/// Tokens one at a time, ending with Eof, or with the first error.
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let token = self.next_token();
+        self.finished = !matches!(&token, Ok(token) if token.token_type != TokenType::Eof);
+        Some(token)
+    }
+}
+
+impl LexerError {
+    /// What went wrong, without the position.
+    pub fn kind_message(&self) -> String {
+        match &self.kind {
             LexerErrorKind::TooDeep => "Indentation too deep".to_string(),
             LexerErrorKind::DedentError => "Invalid dedent level".to_string(),
             LexerErrorKind::IndentError => {
@@ -724,11 +776,21 @@ impl std::fmt::Display for LexerError {
             LexerErrorKind::UnexpectedChar(ch) => format!("Unexpected character '{}'", ch),
             LexerErrorKind::UnterminatedString => "Unterminated string literal".to_string(),
             LexerErrorKind::NewlineInString => "Newline in string literal".to_string(),
-        };
+            LexerErrorKind::InvalidEscape(escape) => {
+                format!("Invalid escape sequence '{}'", escape)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for LexerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "Lexer error at line {}, column {}: {}",
-            self.line, self.column, message
+            self.line,
+            self.column,
+            self.kind_message()
         )
     }
 }
@@ -763,6 +825,14 @@ mod tests {
         assert!(has_dedent, "Should have DEDENT token");
     }
 
+    #[test]
+    fn test_indent_width() {
+        assert_eq!(indent_width("Print 1"), 0);
+        assert_eq!(indent_width("    Print 1"), 4);
+        assert_eq!(indent_width("\tPrint 1"), 8);
+        assert_eq!(indent_width("  \t  Print 1"), 10);
+    }
+
     #[test]
     fn test_mixed_tabs_spaces_error() {
         let source = "Story:\n    Print \"Tab\"\n\tPrint \"Space\"";
@@ -772,4 +842,112 @@ mod tests {
 
         assert!(result.is_err(), "Should fail on mixed tabs/spaces");
     }
+
+    #[test]
+    fn test_string_literals() {
+        let source = "X is \"a\\tb \\\"q\\\" \\u{e9}\" + r\"C:\\n{x}\"\nY is \"\"\"<p>\n  \"hi\"\n</p>\"\"\" + 'é'";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let strings: Vec<&Token> = tokens
+            .iter()
+            .filter(|t| {
+                matches!(
+                    t.token_type,
+                    TokenType::String_(_) | TokenType::RawString(_)
+                )
+            })
+            .collect();
+
+        assert!(matches!(&strings[0].token_type, TokenType::String_(s) if s == "a\tb \"q\" é"));
+        assert_eq!(
+            (strings[0].line, strings[0].column, strings[0].length),
+            (1, 6, 19)
+        );
+        assert!(matches!(&strings[1].token_type, TokenType::RawString(s) if s == "C:\\n{x}"));
+        assert_eq!(
+            (strings[1].line, strings[1].column, strings[1].length),
+            (1, 28, 10)
+        );
+        assert!(
+            matches!(&strings[2].token_type, TokenType::String_(s) if s == "<p>\n  \"hi\"\n</p>")
+        );
+        assert_eq!(
+            (strings[2].line, strings[2].column, strings[2].length),
+            (2, 6, 21)
+        );
+        // Columns after a multi-line string continue on its last line
+        assert_eq!(
+            (strings[3].line, strings[3].column, strings[3].length),
+            (4, 11, 3)
+        );
+
+        let tokens = Lexer::new("X is \"\" + ''").tokenize().unwrap();
+        assert!(matches!(&tokens[2].token_type, TokenType::String_(s) if s.is_empty()));
+        assert!(matches!(&tokens[4].token_type, TokenType::String_(s) if s.is_empty()));
+
+        let error = Lexer::new("X is \"abc").tokenize().unwrap_err();
+        assert!(matches!(error.kind, LexerErrorKind::UnterminatedString));
+        assert_eq!((error.line, error.column), (1, 6));
+        let error = Lexer::new("X is \"\\u{110000}\"").tokenize().unwrap_err();
+        assert!(matches!(error.kind, LexerErrorKind::InvalidEscape(_)));
+        assert_eq!(error.column, 7);
+    }
+
+    #[test]
+    fn test_token_stream() {
+        use crate::compiler::parser::{ParseError, Parser};
+
+        let source = "Concept: Counter\n    Count\n\nStory:\n    Create Counter Called C\n    Print C.Count + 1\n";
+        let streamed = Parser::from_lexer(Lexer::new(source)).parse().unwrap();
+        let listed = Parser::new(Lexer::new(source).tokenize().unwrap())
+            .parse()
+            .unwrap();
+        assert_eq!(streamed, listed);
+
+        // The parse stops where lexing did, and reports the lexer's error
+        let mut lexer = Lexer::new("Story:\n    X is 1\n    Y is $\n");
+        let error = Parser::from_lexer(Lexer::new("Story:\n    X is 1\n    Y is $\n"))
+            .parse()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::Lexer(LexerError {
+                kind: LexerErrorKind::UnexpectedChar('$'),
+                ..
+            })
+        ));
+        assert_eq!(error.location(), (3, 10));
+        assert!(lexer.by_ref().any(|token| token.is_err()));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_multibyte_columns() {
+        let source = "Story:\n    名前 is \"🎉 party\" # 🎉🎉\n    Größe is 1\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let at = |text: &str| {
+            let token = tokens
+                .iter()
+                .find(|t| match &t.token_type {
+                    TokenType::Identifier(name) | TokenType::String_(name) => name == text,
+                    TokenType::Comment(comment) => comment == text,
+                    _ => false,
+                })
+                .unwrap();
+            (token.line, token.column, token.length, token.offset)
+        };
+        // Columns and lengths count characters; offsets count bytes
+        assert_eq!(at("名前"), (2, 5, 2, 11));
+        assert_eq!(at("🎉 party"), (2, 11, 9, 21));
+        assert_eq!(at("🎉🎉"), (2, 21, 4, 34));
+        assert_eq!(at("Größe"), (3, 5, 5, 49));
+        assert!(source[21..].starts_with("\"🎉") && source[49..].starts_with("Größe"));
+
+        let error = Lexer::new("Story:\n    日本 is 1 $ 2")
+            .tokenize()
+            .unwrap_err();
+        assert!(matches!(error.kind, LexerErrorKind::UnexpectedChar('$')));
+        assert_eq!((error.line, error.column, error.offset), (2, 13, 23));
+        let error = Lexer::new("X is \"😀\\u{zz}\"").tokenize().unwrap_err();
+        assert_eq!((error.column, error.offset), (8, 10));
+    }
 }
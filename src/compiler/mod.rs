@@ -1,4 +1,6 @@
 pub mod ast;
+pub mod cache;
+pub mod edition;
 pub mod lexer;
 pub mod parser;
 pub mod token;
@@ -1,7 +1,8 @@
 use super::ast::*;
+use super::edition::Edition;
+use super::lexer::{Lexer, LexerError};
 use super::token::{Token, TokenType};
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
 pub enum ParseError {
@@ -20,24 +21,208 @@ pub enum ParseError {
         line: usize,
         column: usize,
     },
+    /// The source didn't tokenize; only from a Parser reading a Lexer
+    Lexer(LexerError),
 }
 
-pub struct Parser {
-    tokens: Peekable<IntoIter<Token>>,
+/// The parser's tokens, either lexed beforehand or pulled from a Lexer one
+/// at a time as the parser needs them, so a large file's tokens are never
+/// all in memory together.
+struct TokenStream<'a> {
+    source: Box<dyn Iterator<Item = Result<Token, LexerError>> + 'a>,
+    peeked: Option<Token>,
+    // Where lexing stopped; to the parser that is the end of the input
+    error: Option<LexerError>,
+}
+
+impl TokenStream<'_> {
+    fn next(&mut self) -> Option<Token> {
+        self.peeked.take().or_else(|| self.pull())
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = self.pull();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn pull(&mut self) -> Option<Token> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.source.next()? {
+            Ok(token) => Some(token),
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+pub struct Parser<'a> {
+    tokens: TokenStream<'a>,
     current: Option<Token>,
+    tracked_concepts: Vec<String>,
+    edition: Edition,
+    // Files being included, innermost last, to catch an Include cycle
+    include_stack: Vec<PathBuf>,
+    includes: Vec<PathBuf>,
+    // How many blocks the current token is inside
+    depth: usize,
+    // Syntax errors the parse went on after, in order
+    errors: Vec<ParseError>,
+    // `##` lines read since the last token that wasn't a comment or a line
+    // break, for whatever comes next
+    doc: Vec<String>,
 }
 
-impl Parser {
+impl<'a> Parser<'a> {
     pub fn new(tokens: Vec<Token>) -> Self {
+        Self::with_edition(tokens, Edition::default())
+    }
+
+    /// `tokens` must come from a Lexer for the same edition.
+    pub fn with_edition(tokens: Vec<Token>, edition: Edition) -> Self {
+        Self::from_tokens(Box::new(tokens.into_iter().map(Ok)), edition)
+    }
+
+    /// Parse while lexing: each token is read from `lexer` when the parser
+    /// gets to it. A lexer error ends the parse as `ParseError::Lexer`.
+    pub fn from_lexer(lexer: Lexer<'a>) -> Self {
+        let edition = lexer.edition();
+        Self::from_tokens(Box::new(lexer), edition)
+    }
+
+    fn from_tokens(
+        source: Box<dyn Iterator<Item = Result<Token, LexerError>> + 'a>,
+        edition: Edition,
+    ) -> Self {
         let mut parser = Self {
-            tokens: tokens.into_iter().peekable(),
+            tokens: TokenStream {
+                source,
+                peeked: None,
+                error: None,
+            },
             current: None,
+            tracked_concepts: Vec::new(),
+            edition,
+            include_stack: Vec::new(),
+            includes: Vec::new(),
+            depth: 0,
+            errors: Vec::new(),
+            doc: Vec::new(),
         };
         parser.advance();
         parser
     }
 
+    /// The program, or the first syntax error in it.
     pub fn parse(&mut self) -> Result<Program, ParseError> {
+        let (program, mut errors) = self.parse_all();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// Parse to the end, going on after each syntax error from the next
+    /// statement or the next Story, Concept or Situation. Gives what did
+    /// parse and every error, for an editor to show them all at once.
+    pub fn parse_all(&mut self) -> (Program, Vec<ParseError>) {
+        let program = self.parse_program();
+        let lexer_error = self.tokens.error.take();
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.extend(lexer_error.map(ParseError::Lexer));
+        (program, errors)
+    }
+
+    /// Keep `error` for the end of the parse. Once lexing has stopped, the
+    /// parser only runs into the end of the input, which the lexer's error
+    /// explains better.
+    fn record(&mut self, error: ParseError) {
+        let repeated = self
+            .errors
+            .last()
+            .is_some_and(|last| last.location() == error.location());
+        if self.tokens.error.is_none() && !repeated {
+            self.errors.push(error);
+        }
+    }
+
+    /// After a syntax error in a statement of the block at `depth`: skip
+    /// to the next line of the block, along with any block under the
+    /// broken line and its Else, Catch or Always parts.
+    fn skip_statement(&mut self, error: ParseError, depth: usize) {
+        self.record(error);
+        let depth = depth.min(self.depth);
+        while !self.is_at_end() {
+            if self.depth == depth && self.check(&TokenType::Dedent) {
+                return;
+            }
+            let line_end = self.depth == depth && self.check(&TokenType::Newline);
+            self.advance();
+            if !line_end {
+                continue;
+            }
+            self.skip_ignorable();
+            if self.check(&TokenType::Indent) {
+                self.advance();
+                while !self.is_at_end() && self.depth > depth {
+                    self.advance();
+                }
+                self.skip_ignorable();
+            }
+            if !matches!(
+                self.peek_type(),
+                Some(
+                    TokenType::Else
+                        | TokenType::ElseIf
+                        | TokenType::Otherwise
+                        | TokenType::Catch
+                        | TokenType::Always
+                )
+            ) {
+                return;
+            }
+        }
+    }
+
+    /// After a syntax error outside a Story's statements: skip to the next
+    /// Story, Concept, Situation, Use or Include at the top of the file.
+    fn skip_item(&mut self, error: ParseError) {
+        self.record(error);
+        self.advance();
+        while !self.is_at_end() {
+            let top = self.depth == 0
+                && (matches!(
+                    self.peek_type(),
+                    Some(
+                        TokenType::Story
+                            | TokenType::Concept
+                            | TokenType::Situation
+                            | TokenType::Use
+                    )
+                ) || self.at_include());
+            if top {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    /// `result`, unless lexing stopped early: the parse then ran into the
+    /// end of the input there, and the lexer's error is the one to report.
+    fn lexed<T>(&mut self, result: Result<T, ParseError>) -> Result<T, ParseError> {
+        match self.tokens.error.take() {
+            Some(error) => Err(ParseError::Lexer(error)),
+            None => result,
+        }
+    }
+
+    fn parse_program(&mut self) -> Program {
         let mut concepts = Vec::new();
         let mut situations = Vec::new();
         let mut story_body = Vec::new();
@@ -45,51 +230,174 @@ impl Parser {
         while !self.is_at_end() {
             self.skip_ignorable();
 
-            match self.peek_type() {
+            if self.at_include() {
+                match self.parse_include() {
+                    Ok(statements) => story_body.extend(statements),
+                    Err(error) => self.skip_item(error),
+                }
+                continue;
+            }
+
+            let item = match self.peek_type() {
                 Some(TokenType::Use) => {
                     /* Use statements are treated as part of the Story execution flow
                     Just parse it as a statement and add it to story_body
                     This means "Use" happens at runtime, which is fine for an interpreter */
-                    let stmt = self.parse_statement()?;
-                    story_body.push(stmt);
-                }
-                Some(TokenType::Story) => {
-                    let segment = self.parse_story()?;
-                    story_body.extend(segment.body);
+                    self.parse_statement().map(|stmt| story_body.push(stmt))
                 }
+                Some(TokenType::Story) => self
+                    .parse_story()
+                    .map(|segment| story_body.extend(segment.body)),
                 Some(TokenType::Concept) => {
-                    concepts.push(self.parse_concept()?);
-                }
-                Some(TokenType::Situation) => {
-                    situations.push(self.parse_situation()?);
+                    self.parse_concept().map(|concept| concepts.push(concept))
                 }
+                Some(TokenType::Situation) => self
+                    .parse_situation()
+                    .map(|situation| situations.push(situation)),
                 Some(TokenType::Dedent) => {
                     self.advance();
+                    Ok(())
                 }
                 Some(TokenType::Eof) => break,
                 None => break,
-                _ => {
-                    return Err(self.make_invalid_syntax(format!(
-                        "Expected Story, Concept, or Situation. Found: {:?}",
-                        self.peek_type()
-                    )));
-                }
+                _ => Err(self.make_invalid_syntax(format!(
+                    "Expected Story, Concept, or Situation. Found: {:?}",
+                    self.peek_type()
+                ))),
+            };
+            if let Err(error) = item {
+                self.skip_item(error);
             }
         }
 
         let story = Story { body: story_body };
 
-        Ok(Program {
+        Program {
             story,
             concepts,
             situations,
-        })
+            tracked_concepts: std::mem::take(&mut self.tracked_concepts),
+            includes: std::mem::take(&mut self.includes),
+        }
+    }
+
+    // `Include` followed by a name; `Include is 5` is an assignment
+    fn at_include(&mut self) -> bool {
+        self.check_word("Include")
+            && matches!(
+                self.tokens.peek(),
+                Some(Token {
+                    token_type: TokenType::Identifier(_),
+                    ..
+                })
+            )
+    }
+
+    /// Include templates.Header: the statements in templates/Header.sfex,
+    /// parsed now to take the Include's place. Unlike Use, nothing is
+    /// loaded when the script runs.
+    fn parse_include(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let line = self.current_line();
+        let column = self.current_column();
+        self.advance(); // Eat "Include"
+
+        let mut path_parts = vec![self.expect_identifier()?];
+        while self.check(&TokenType::Dot) {
+            self.advance();
+            path_parts.push(self.expect_identifier()?);
+        }
+        let path = format!("{}.sfex", path_parts.join("/"));
+        let error = |message: String| ParseError::InvalidSyntax {
+            message,
+            line,
+            column,
+        };
+
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let resolved = crate::project::resolve_module_path(&path, &cwd).ok_or_else(|| {
+            error(format!(
+                "Include {}: no file {}",
+                path_parts.join("."),
+                path
+            ))
+        })?;
+        let resolved = resolved.canonicalize().unwrap_or(resolved);
+        if self.include_stack.contains(&resolved) {
+            return Err(error(format!("{} includes itself", path)));
+        }
+        let source = std::fs::read_to_string(&resolved)
+            .map_err(|e| error(format!("Failed to read {}: {}", path, e)))?;
+
+        // The included file is parsed with this file's edition
+        let mut parser = Parser::from_lexer(Lexer::with_edition(&source, self.edition));
+        parser.include_stack = self.include_stack.clone();
+        parser.include_stack.push(resolved.clone());
+        let statements =
+            parser
+                .parse_included()
+                .and_then(|statements| match parser.errors.drain(..).next() {
+                    Some(error) => Err(error),
+                    None => Ok(statements),
+                });
+        let statements = parser
+            .lexed(statements)
+            .map_err(|e| error(format!("In {}: {}", path, e)))?;
+
+        self.includes.push(resolved);
+        self.includes.append(&mut parser.includes);
+        for concept in parser.tracked_concepts {
+            if !self.tracked_concepts.contains(&concept) {
+                self.tracked_concepts.push(concept);
+            }
+        }
+        Ok(statements)
+    }
+
+    // An included file is statements, without Story:
+    fn parse_included(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = Vec::new();
+        loop {
+            statements.extend(self.parse_block()?);
+            self.skip_ignorable();
+            if self.is_at_end() {
+                return Ok(statements);
+            }
+            if !self.check(&TokenType::Dedent) {
+                return Err(self.make_invalid_syntax(format!(
+                    "Expected a statement. Found: {:?}",
+                    self.peek_type()
+                )));
+            }
+            self.advance();
+        }
+    }
+
+    /// Parse a source that is one expression, such as `Cart.Total * 2`.
+    pub fn parse_standalone_expression(&mut self) -> Result<Expression, ParseError> {
+        self.skip_ignorable();
+        let expression = self.parse_expression()?;
+        if !self.errors.is_empty() {
+            return Err(self.errors.remove(0));
+        }
+        self.skip_ignorable();
+        while let Some(TokenType::Dedent) = self.peek_type() {
+            self.advance();
+        }
+        if !self.is_at_end() {
+            return Err(self.make_invalid_syntax(format!(
+                "Expected the end of the expression. Found: {:?}",
+                self.peek_type()
+            )));
+        }
+        Ok(expression)
     }
 
     fn skip_ignorable(&mut self) {
         loop {
             match self.peek_type() {
-                Some(TokenType::Newline) | Some(TokenType::Comment(_)) => {
+                Some(TokenType::Newline)
+                | Some(TokenType::Comment(_))
+                | Some(TokenType::DocComment(_)) => {
                     self.advance();
                 }
                 _ => break,
@@ -101,7 +409,7 @@ impl Parser {
     fn skip_ignorable_no_newline(&mut self) {
         loop {
             match self.peek_type() {
-                Some(TokenType::Comment(_)) => {
+                Some(TokenType::Comment(_)) | Some(TokenType::DocComment(_)) => {
                     self.advance();
                 }
                 _ => break,
@@ -115,6 +423,7 @@ impl Parser {
             match self.peek_type() {
                 Some(TokenType::Newline)
                 | Some(TokenType::Comment(_))
+                | Some(TokenType::DocComment(_))
                 | Some(TokenType::Indent)
                 | Some(TokenType::Dedent) => {
                     self.advance();
@@ -136,19 +445,46 @@ impl Parser {
     }
 
     fn parse_concept(&mut self) -> Result<Concept, ParseError> {
+        let doc = self.take_doc();
         self.expect(TokenType::Concept)?;
         self.expect(TokenType::Colon)?;
         let name = self.expect_identifier()?;
 
+        // Concept: Dog extends Animal / Concept: Dog is an Animal
+        let parent = if self.check_word("extends") {
+            self.advance();
+            Some(self.expect_identifier()?)
+        } else if self.check(&TokenType::Is) {
+            self.advance();
+            if self.check_word("a") || self.check_word("an") {
+                self.advance();
+            }
+            Some(self.expect_identifier()?)
+        } else {
+            None
+        };
+
         self.skip_ignorable();
         self.expect(TokenType::Indent)?;
 
         let mut fields = Vec::new();
+        let mut defaults = std::collections::HashMap::new();
+        let mut requirements = Vec::new();
         let mut methods = Vec::new();
         let mut when_observers = std::collections::HashMap::new();
+        let mut observed_on_create = Vec::new();
+        let mut when_created = Vec::new();
+        let mut when_destroyed = Vec::new();
+        let mut field_docs = std::collections::BTreeMap::new();
 
         loop {
             self.skip_ignorable();
+            // Require Age > 0 (a field named Require is still a field)
+            let requirement = self.check_word("Require")
+                && !matches!(
+                    self.tokens.peek().map(|t| &t.token_type),
+                    Some(TokenType::Comma | TokenType::Is | TokenType::Newline) | None
+                );
 
             match self.peek_type() {
                 Some(TokenType::Dedent) | Some(TokenType::Eof) => break,
@@ -156,17 +492,33 @@ impl Parser {
                     methods.push(self.parse_method()?);
                 }
                 Some(TokenType::When) => {
-                    // Parse: When [property] changes:
+                    // Parse: When [property] changes:, When created:, When destroyed:
                     self.advance(); // eat "When"
                     let property = self.expect_identifier()?;
 
-                    // Expect "changes" identifier
-                    let changes_word = self.expect_identifier()?;
-                    if changes_word != "changes" {
-                        return Err(self.make_unexpected_token(
-                            "changes".to_string(),
-                            TokenType::Identifier(changes_word),
-                        ));
+                    let lifecycle = self.check(&TokenType::Colon)
+                        && (property == "created" || property == "destroyed");
+                    if !lifecycle {
+                        // Expect "changes" identifier
+                        let changes_word = self.expect_identifier()?;
+                        if changes_word != "changes" {
+                            return Err(self.make_unexpected_token(
+                                "changes".to_string(),
+                                TokenType::Identifier(changes_word),
+                            ));
+                        }
+                        // When Price changes, including Create:
+                        if self.check(&TokenType::Comma) {
+                            self.advance();
+                            if !self.check_word("including") {
+                                return Err(self.make_invalid_syntax(
+                                    "Expected 'including Create' after the comma".to_string(),
+                                ));
+                            }
+                            self.advance();
+                            self.expect(TokenType::Create)?;
+                            observed_on_create.push(property.clone());
+                        }
                     }
 
                     self.expect(TokenType::Colon)?;
@@ -185,15 +537,40 @@ impl Parser {
 
                     self.expect(TokenType::Dedent)?;
 
-                    // Add to when_observers
-                    when_observers.insert(property, when_body);
+                    if !lifecycle {
+                        when_observers.insert(property, when_body);
+                    } else if property == "created" {
+                        when_created = when_body;
+                    } else {
+                        when_destroyed = when_body;
+                    }
+                }
+                Some(TokenType::Identifier(_)) if requirement => {
+                    let line = self.current_line();
+                    self.advance();
+                    let condition = self.parse_expression()?;
+                    requirements.push(Requirement { condition, line });
+                    self.skip_ignorable();
                 }
                 Some(TokenType::Identifier(_)) => {
-                    // Parse first field
-                    fields.push(self.expect_identifier()?);
+                    // Parse comma-separated fields on same line, each with an
+                    // optional default: Name is "unknown", Age is 0. A doc
+                    // comment above the line is for each of them.
+                    let doc = self.take_doc();
+                    loop {
+                        let field = self.expect_identifier()?;
+                        if let Some(doc) = &doc {
+                            field_docs.insert(field.clone(), doc.clone());
+                        }
+                        if self.check(&TokenType::Is) {
+                            self.advance();
+                            defaults.insert(field.clone(), self.parse_expression()?);
+                        }
+                        fields.push(field);
 
-                    // Parse comma-separated fields on same line
-                    while self.check(&TokenType::Comma) {
+                        if !self.check(&TokenType::Comma) {
+                            break;
+                        }
                         self.advance(); // eat comma
                         self.skip_ignorable_no_newline(); // Skip spaces but not newlines
 
@@ -201,8 +578,6 @@ impl Parser {
                         if self.check(&TokenType::Newline) {
                             break;
                         }
-
-                        fields.push(self.expect_identifier()?);
                     }
 
                     self.skip_ignorable();
@@ -215,16 +590,51 @@ impl Parser {
 
         Ok(Concept {
             name,
+            parent,
             fields,
+            defaults,
+            requirements,
             methods,
             when_observers,
+            observed_on_create,
+            when_created,
+            when_destroyed,
+            file: None,
+            doc,
+            field_docs,
         })
     }
 
     fn parse_situation(&mut self) -> Result<Situation, ParseError> {
+        let doc = self.take_doc();
+        let line = self.current_line();
         self.expect(TokenType::Situation)?;
         self.expect(TokenType::Colon)?;
         let name = self.expect_identifier()?;
+        let priority = if self.check_word("priority") {
+            self.advance();
+            let negative = self.check(&TokenType::Minus);
+            if negative {
+                self.advance();
+            }
+            let value = match self.peek_type() {
+                Some(TokenType::Integer(digits)) => digits.parse::<i64>().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                self.make_invalid_syntax("Expected a whole number after priority".to_string())
+            })?;
+            self.advance();
+            if negative { -value } else { value }
+        } else {
+            0
+        };
+        let condition = if self.check_word("when") {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
 
         self.skip_ignorable();
         self.expect(TokenType::Indent)?;
@@ -245,7 +655,14 @@ impl Parser {
 
         self.expect(TokenType::Dedent)?;
 
-        Ok(Situation { name, adjustments })
+        Ok(Situation {
+            name,
+            priority,
+            condition,
+            adjustments,
+            line,
+            doc,
+        })
     }
 
     fn parse_adjustment(&mut self) -> Result<Adjustment, ParseError> {
@@ -278,6 +695,7 @@ impl Parser {
     }
 
     fn parse_method(&mut self) -> Result<Method, ParseError> {
+        let doc = self.take_doc();
         self.expect(TokenType::To)?;
         let name = self.expect_identifier()?;
 
@@ -303,11 +721,14 @@ impl Parser {
             name,
             parameters,
             body,
+            file: None,
+            doc,
         })
     }
 
     fn parse_block(&mut self) -> Result<Vec<Statement>, ParseError> {
         let mut statements = Vec::new();
+        let depth = self.depth;
 
         loop {
             self.skip_ignorable();
@@ -321,8 +742,19 @@ impl Parser {
                 _ => {}
             }
 
+            if self.at_include() {
+                statements.extend(self.parse_include()?);
+                continue;
+            }
+
             // Parse first statement
-            statements.push(self.parse_statement()?);
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    self.skip_statement(error, depth);
+                    continue;
+                }
+            }
 
             // Check for comma-separated statements on same line
             while self.check(&TokenType::Comma) {
@@ -335,7 +767,13 @@ impl Parser {
                 }
 
                 // Parse next statement on same line
-                statements.push(self.parse_statement()?);
+                match self.parse_statement() {
+                    Ok(statement) => statements.push(statement),
+                    Err(error) => {
+                        self.skip_statement(error, depth);
+                        break;
+                    }
+                }
             }
         }
 
@@ -347,6 +785,25 @@ impl Parser {
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        // Destroy Player (a variable still named Destroy keeps working)
+        let destroy = self.check_word("Destroy")
+            && matches!(self.tokens.peek(), Some(token) if matches!(token.token_type, TokenType::Identifier(_)));
+
+        let reevaluate = self.check_word("Reevaluate") && self.next_is_identifier("Situations");
+        let with_situation = self.check_word("With")
+            && matches!(self.tokens.peek(), Some(token) if token.token_type == TokenType::Situation);
+        let batch = self.check_word("Batch")
+            && matches!(self.tokens.peek(), Some(token) if token.token_type == TokenType::Colon);
+        // Raise "Category.Subtype" with "message" (a variable named Raise still works)
+        let raise = self.check_word("Raise")
+            && matches!(self.tokens.peek(), Some(token) if matches!(token.token_type, TokenType::String_(_) | TokenType::Identifier(_)));
+        // Using Conn from TCP.Connect(..): (a variable named Using still works)
+        let using = self.check_word("Using")
+            && matches!(self.tokens.peek(), Some(token) if matches!(token.token_type, TokenType::Identifier(_)));
+        // Try Save(Data) otherwise False, as a statement of its own
+        let try_block = self.check(&TokenType::Try)
+            && matches!(self.tokens.peek(), Some(token) if token.token_type == TokenType::Colon);
+
         match self.peek_type() {
             Some(TokenType::Use) => {
                 self.advance(); // Eat "Use"
@@ -423,6 +880,14 @@ impl Parser {
                     return Ok(Statement::Print { value, line });
                 }
 
+                if destroy {
+                    let line = self.current_line();
+                    self.advance();
+                    let name = self.expect_identifier()?;
+                    self.skip_ignorable();
+                    return Ok(Statement::Destroy { name, line });
+                }
+
                 if name == "Set" {
                     let line = self.current_line();
                     self.advance();
@@ -440,14 +905,75 @@ impl Parser {
                     }
 
                     let value = self.parse_expression()?;
+                    let silent = self.check_word("silently");
+                    if silent {
+                        self.advance();
+                    }
                     self.skip_ignorable();
                     return Ok(Statement::Set {
                         target,
                         value,
+                        silent,
+                        line,
+                    });
+                }
+
+                if reevaluate {
+                    let line = self.current_line();
+                    self.advance();
+                    self.advance();
+                    self.skip_ignorable();
+                    return Ok(Statement::ReevaluateSituations { line });
+                }
+
+                if with_situation {
+                    let line = self.current_line();
+                    self.advance();
+                    self.advance();
+                    let situation = self.expect_identifier()?;
+                    self.expect(TokenType::Colon)?;
+                    self.skip_ignorable();
+                    self.expect(TokenType::Indent)?;
+                    let body = self.parse_block()?;
+                    return Ok(Statement::WithSituation {
+                        situation,
+                        body,
+                        line,
+                    });
+                }
+
+                if batch {
+                    let line = self.current_line();
+                    self.advance();
+                    self.advance();
+                    self.skip_ignorable();
+                    self.expect(TokenType::Indent)?;
+                    let body = self.parse_block()?;
+                    return Ok(Statement::Batch { body, line });
+                }
+
+                if raise {
+                    let line = self.current_line();
+                    self.advance();
+                    let error = self.parse_expression()?;
+                    let message = if self.check(&TokenType::With) {
+                        self.advance();
+                        Some(self.parse_expression()?)
+                    } else {
+                        None
+                    };
+                    self.skip_ignorable();
+                    return Ok(Statement::Raise {
+                        error,
+                        message,
                         line,
                     });
                 }
 
+                if using {
+                    return self.parse_using();
+                }
+
                 if name == "Switch" {
                     let line = self.current_line();
                     self.advance();
@@ -480,6 +1006,7 @@ impl Parser {
                     return Ok(Statement::Assignment {
                         target,
                         value,
+                        copy: self.edition.copies_on_assign(),
                         line,
                     });
                 }
@@ -492,7 +1019,7 @@ impl Parser {
 
             Some(TokenType::If) => self.parse_if(),
             Some(TokenType::When) => self.parse_when(),
-            Some(TokenType::Try) => self.parse_try_catch(),
+            Some(TokenType::Try) if try_block => self.parse_try_catch(),
             Some(TokenType::Repeat) => self.parse_repeat(),
             Some(TokenType::For) => self.parse_for(),
             Some(TokenType::Return) => self.parse_return(),
@@ -569,13 +1096,25 @@ impl Parser {
             self.skip_ignorable(); // Skip newlines/whitespace between cases
 
             if self.check(&TokenType::Is) {
+                let case_line = self.current_line();
                 self.advance();
-                let match_value = self.parse_expression()?;
+                let pattern = self.parse_pattern(false)?;
+                let guard = if self.check_word("where") {
+                    self.advance();
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
                 self.expect(TokenType::Colon)?;
                 self.skip_ignorable();
                 self.expect(TokenType::Indent)?;
                 let body = self.parse_block()?;
-                cases.push((match_value, body));
+                cases.push(WhenCase {
+                    pattern,
+                    guard,
+                    body,
+                    line: case_line,
+                });
             } else if self.check(&TokenType::Otherwise) {
                 self.advance();
                 self.expect(TokenType::Colon)?;
@@ -611,6 +1150,111 @@ impl Parser {
         })
     }
 
+    // Is 100 / Is between 1 and 10 / Is a Number / Is [X, ...Rest] / Is { Name: N }
+    // Inside list and map patterns (`nested`) a lone name binds the item
+    // instead of comparing with it; at the top it binds only before `where`.
+    fn parse_pattern(&mut self, nested: bool) -> Result<Pattern, ParseError> {
+        match self.peek_type() {
+            Some(TokenType::LeftBracket) => return self.parse_list_pattern(),
+            Some(TokenType::LeftBrace) => return self.parse_map_pattern(),
+            Some(TokenType::Identifier(word)) => {
+                let word = word.clone();
+                let next = self.tokens.peek().map(|token| token.token_type.clone());
+                let ends = next.as_ref().is_none_or(|next| {
+                    matches!(
+                        next,
+                        TokenType::Colon
+                            | TokenType::Comma
+                            | TokenType::RightBracket
+                            | TokenType::RightBrace
+                    )
+                });
+                let before_where =
+                    matches!(&next, Some(TokenType::Identifier(next)) if next == "where");
+
+                if (nested && ends) || before_where {
+                    self.advance();
+                    return Ok(Pattern::Bind(word));
+                }
+                if word == "between" && !ends {
+                    self.advance();
+                    let low = self.parse_bit_or()?;
+                    self.expect(TokenType::And)?;
+                    let high = self.parse_bit_or()?;
+                    return Ok(Pattern::Between(low, high));
+                }
+                if let ("a" | "an", Some(TokenType::Identifier(type_name))) = (word.as_str(), next)
+                {
+                    self.advance();
+                    self.advance();
+                    return Ok(Pattern::Type(type_name));
+                }
+            }
+            _ => {}
+        }
+        Ok(Pattern::Value(self.parse_expression()?))
+    }
+
+    fn parse_list_pattern(&mut self) -> Result<Pattern, ParseError> {
+        self.expect(TokenType::LeftBracket)?;
+        self.skip_ignorable_with_indent();
+
+        let mut items = Vec::new();
+        let mut rest = None;
+
+        while !self.check(&TokenType::RightBracket) && !self.is_at_end() {
+            // ...Rest takes the remaining items and must come last
+            if self.check(&TokenType::Dot) {
+                for _ in 0..3 {
+                    self.expect(TokenType::Dot)?;
+                }
+                rest = Some(self.expect_identifier()?);
+                self.skip_ignorable_with_indent();
+                break;
+            }
+
+            items.push(self.parse_pattern(true)?);
+            self.skip_ignorable_with_indent();
+
+            if !self.check(&TokenType::RightBracket) {
+                self.expect(TokenType::Comma)?;
+                self.skip_ignorable_with_indent();
+            }
+        }
+
+        self.expect(TokenType::RightBracket)?;
+        Ok(Pattern::List { items, rest })
+    }
+
+    // { Name: N, Age } binds the Age key to Age
+    fn parse_map_pattern(&mut self) -> Result<Pattern, ParseError> {
+        self.expect(TokenType::LeftBrace)?;
+        self.skip_ignorable_with_indent();
+
+        let mut entries = Vec::new();
+
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let key = self.expect_identifier()?;
+            let pattern = if self.check(&TokenType::Colon) {
+                self.advance();
+                self.skip_ignorable_with_indent();
+                self.parse_pattern(true)?
+            } else {
+                Pattern::Bind(key.clone())
+            };
+            entries.push((key, pattern));
+            self.skip_ignorable_with_indent();
+
+            if !self.check(&TokenType::RightBrace) {
+                self.expect(TokenType::Comma)?;
+                self.skip_ignorable_with_indent();
+            }
+        }
+
+        self.expect(TokenType::RightBrace)?;
+        Ok(Pattern::Map(entries))
+    }
+
     fn parse_try_catch(&mut self) -> Result<Statement, ParseError> {
         let line = self.current_line();
         self.expect(TokenType::Try)?;
@@ -619,24 +1263,35 @@ impl Parser {
         self.expect(TokenType::Indent)?;
         let try_body = self.parse_block()?;
 
-        let mut catch_var = None;
-        let mut catch_body = None;
+        let mut catches = Vec::new();
         let mut always_body = None;
 
         self.skip_ignorable();
 
-        // Parse optional Catch block
-        if self.check(&TokenType::Catch) {
+        // Parse optional Catch blocks, each with an optional `when` condition
+        while self.check(&TokenType::Catch) {
             self.advance();
+            let mut var = None;
             if let Some(TokenType::Identifier(var_name)) = self.peek_type() {
-                catch_var = Some(var_name.clone());
+                var = Some(var_name.clone());
                 self.advance();
             }
+            let condition = if self.check_word("when") {
+                self.advance();
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
 
             self.expect(TokenType::Colon)?;
             self.skip_ignorable();
             self.expect(TokenType::Indent)?;
-            catch_body = Some(self.parse_block()?);
+            let body = self.parse_block()?;
+            catches.push(CatchClause {
+                var,
+                condition,
+                body,
+            });
             self.skip_ignorable();
         }
 
@@ -649,20 +1304,41 @@ impl Parser {
             always_body = Some(self.parse_block()?);
         }
 
-        // Consume the final DEDENT that ends the TryCatch block
-        if self.check(&TokenType::Dedent) {
-            self.advance();
-        }
+        // Catch and Always sit beside Try, so each block above consumed its
+        // own DEDENT; a DEDENT left here ends the enclosing block
 
         Ok(Statement::TryCatch {
             try_body,
-            catch_var,
-            catch_body,
+            catches,
             always_body,
             line,
         })
     }
 
+    fn parse_using(&mut self) -> Result<Statement, ParseError> {
+        let line = self.current_line();
+        self.advance(); // Eat "Using"
+        let name = self.expect_identifier()?;
+        if !self.check_word("from") {
+            return Err(self.make_invalid_syntax(
+                "Expected 'from' and the resource after 'Using Name'".to_string(),
+            ));
+        }
+        self.advance();
+        let resource = self.parse_expression()?;
+        self.expect(TokenType::Colon)?;
+        self.skip_ignorable();
+        self.expect(TokenType::Indent)?;
+        let body = self.parse_block()?;
+
+        Ok(Statement::Using {
+            name,
+            resource,
+            body,
+            line,
+        })
+    }
+
     fn parse_repeat(&mut self) -> Result<Statement, ParseError> {
         let line = self.current_line();
         self.expect(TokenType::Repeat)?;
@@ -710,8 +1386,21 @@ impl Parser {
         self.expect(TokenType::For)?;
         self.expect(TokenType::Each)?;
         let variable = self.expect_identifier()?;
+        let value_variable = if self.check(&TokenType::And) {
+            self.advance();
+            Some(self.expect_identifier()?)
+        } else {
+            None
+        };
         self.expect(TokenType::In)?;
-        let iterable = self.parse_expression()?;
+        let mut iterable = self.parse_expression()?;
+        if self.check(&TokenType::To_) {
+            self.advance();
+            iterable = Expression::Range {
+                start: Box::new(iterable),
+                end: Box::new(self.parse_expression()?),
+            };
+        }
         self.expect(TokenType::Colon)?;
         self.skip_ignorable();
         self.expect(TokenType::Indent)?;
@@ -719,6 +1408,7 @@ impl Parser {
 
         Ok(Statement::ForEach {
             variable,
+            value_variable,
             iterable,
             body,
             line,
@@ -776,7 +1466,7 @@ impl Parser {
     }
 
     fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
-        let left = self.parse_additive()?;
+        let left = self.parse_bit_or()?;
 
         let op = match self.peek_type() {
             Some(TokenType::Equals) => {
@@ -806,7 +1496,7 @@ impl Parser {
             _ => return Ok(left),
         };
 
-        let right = self.parse_additive()?;
+        let right = self.parse_bit_or()?;
         Ok(Expression::BinaryOp {
             left: Box::new(left),
             operator: op,
@@ -814,6 +1504,81 @@ impl Parser {
         })
     }
 
+    // Bitwise operators bind tighter than comparisons and looser than
+    // arithmetic: BitOr < Xor < BitAnd < ShiftLeft/ShiftRight. They are
+    // plain words after a value, so variables with these names still work
+    fn parse_bit_or(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_bit_xor()?;
+
+        while self.check_word("BitOr") {
+            self.advance();
+            let right = self.parse_bit_xor()?;
+            left = Expression::BinaryOp {
+                left: Box::new(left),
+                operator: BinaryOperator::BitOr,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_bit_xor(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_bit_and()?;
+
+        while self.check_word("Xor") {
+            self.advance();
+            let right = self.parse_bit_and()?;
+            left = Expression::BinaryOp {
+                left: Box::new(left),
+                operator: BinaryOperator::Xor,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_bit_and(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_shift()?;
+
+        while self.check_word("BitAnd") {
+            self.advance();
+            let right = self.parse_shift()?;
+            left = Expression::BinaryOp {
+                left: Box::new(left),
+                operator: BinaryOperator::BitAnd,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_shift(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_additive()?;
+
+        loop {
+            let op = if self.check_word("ShiftLeft") {
+                BinaryOperator::ShiftLeft
+            } else if self.check_word("ShiftRight") {
+                BinaryOperator::ShiftRight
+            } else {
+                break;
+            };
+            self.advance();
+
+            let right = self.parse_additive()?;
+            left = Expression::BinaryOp {
+                left: Box::new(left),
+                operator: op,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
     fn parse_additive(&mut self) -> Result<Expression, ParseError> {
         let mut left = self.parse_multiplicative()?;
 
@@ -854,6 +1619,10 @@ impl Parser {
                     self.advance();
                     BinaryOperator::Divide
                 }
+                Some(TokenType::SlashSlash) => {
+                    self.advance();
+                    BinaryOperator::IntDivide
+                }
                 Some(TokenType::Percent) => {
                     self.advance();
                     BinaryOperator::Modulo
@@ -873,6 +1642,10 @@ impl Parser {
     }
 
     fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        // BitNot X (a variable named BitNot still works)
+        let bit_not = self.check_word("BitNot")
+            && matches!(self.tokens.peek(), Some(token) if matches!(token.token_type, TokenType::Identifier(_) | TokenType::Integer(_) | TokenType::Number(_) | TokenType::LeftParen));
+
         match self.peek_type() {
             Some(TokenType::Not) => {
                 self.advance();
@@ -890,6 +1663,14 @@ impl Parser {
                     operand: Box::new(operand),
                 })
             }
+            Some(TokenType::Identifier(_)) if bit_not => {
+                self.advance();
+                let operand = self.parse_unary()?;
+                Ok(Expression::UnaryOp {
+                    operator: UnaryOperator::BitNot,
+                    operand: Box::new(operand),
+                })
+            }
             _ => self.parse_postfix(),
         }
     }
@@ -964,17 +1745,31 @@ impl Parser {
     }
 
     fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let instances_of = matches!(self.peek_type(), Some(TokenType::Identifier(name)) if name == "Instances")
+            && self.next_is_identifier("of");
+        let copy_of = self.check_word("Copy") && self.next_is_identifier("of");
+
         match self.peek_type() {
             Some(TokenType::Number(n)) => {
                 let num = n.clone();
                 self.advance();
                 Ok(Expression::Number(num))
             }
+            Some(TokenType::Integer(n)) => {
+                let num = n.clone();
+                self.advance();
+                Ok(Expression::Integer(num))
+            }
             Some(TokenType::String_(s)) => {
                 let raw_string = s.clone();
                 self.advance();
                 self.parse_interpolated_string(&raw_string)
             }
+            Some(TokenType::RawString(s)) => {
+                let value = s.clone();
+                self.advance();
+                Ok(Expression::String(value))
+            }
             Some(TokenType::True_) => {
                 self.advance();
                 Ok(Expression::Boolean(true))
@@ -983,6 +1778,14 @@ impl Parser {
                 self.advance();
                 Ok(Expression::Boolean(false))
             }
+            Some(TokenType::Identifier(_)) if instances_of => self.parse_instances_of(),
+            // Copy of Items
+            Some(TokenType::Identifier(_)) if copy_of => {
+                self.advance();
+                self.advance();
+                let value = self.parse_unary()?;
+                Ok(Expression::CopyOf(Box::new(value)))
+            }
             Some(TokenType::Identifier(name)) => {
                 let name = name.clone();
                 self.advance();
@@ -1000,11 +1803,40 @@ impl Parser {
                 self.advance(); // eat "Do"
                 self.expect(TokenType::In)?;
                 self.expect(TokenType::Background)?;
+                let mut shared = Vec::new();
+                if self.check_word("share") {
+                    self.advance();
+                    loop {
+                        shared.push(self.expect_identifier()?);
+                        if self.check(&TokenType::Comma) || self.check(&TokenType::And) {
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                }
                 self.expect(TokenType::Colon)?;
                 self.skip_ignorable();
                 self.expect(TokenType::Indent)?;
                 let body = self.parse_block()?;
-                Ok(Expression::DoInBackground { body })
+                Ok(Expression::DoInBackground { body, shared })
+            }
+            // Try Number(Text) otherwise 0
+            Some(TokenType::Try) => {
+                self.advance();
+                let expression = self.parse_expression()?;
+                if !self.check_word("otherwise") {
+                    return Err(self.make_invalid_syntax(
+                        "Expected 'otherwise' and a fallback value after 'Try' expression"
+                            .to_string(),
+                    ));
+                }
+                self.advance();
+                let fallback = self.parse_expression()?;
+                Ok(Expression::TryOtherwise {
+                    expression: Box::new(expression),
+                    fallback: Box::new(fallback),
+                })
             }
             Some(TokenType::Proceed) => {
                 self.advance();
@@ -1047,97 +1879,78 @@ impl Parser {
         }
     }
 
+    // Anything between the braces of "Total: {A + B}" is parsed as an expression;
+    // `{{` and `}}` stand for literal braces. A `{` that can't start an expression
+    // (JSON text, `{ }`) is kept as it is.
     fn parse_interpolated_string(&self, content: &str) -> Result<Expression, ParseError> {
-        if !content.contains('{') {
+        if !content.contains('{') && !content.contains('}') {
             return Ok(Expression::String(content.to_string()));
         }
 
         let mut expressions = Vec::new();
-        let mut last_pos = 0;
+        let mut text = String::new();
         let mut chars = content.char_indices().peekable();
 
         while let Some((i, c)) = chars.next() {
-            if c == '{' {
-                let should_interpolate = match chars.peek() {
-                    Some((_, next_c)) => next_c.is_alphabetic() || *next_c == '_',
-                    None => false,
-                };
-
-                if !should_interpolate {
-                    continue;
+            match c {
+                '{' | '}' if chars.peek().map(|(_, next)| *next) == Some(c) => {
+                    chars.next();
+                    text.push(c);
                 }
-
-                if i > last_pos {
-                    let text_segment = &content[last_pos..i];
-                    expressions.push(Expression::String(text_segment.to_string()));
-                }
-
-                let start_ident = i + 1;
-                let mut end_ident = start_ident;
-                let mut found_close = false;
-
-                while let Some((j, c_inner)) = chars.peek() {
-                    if *c_inner == '}' {
-                        end_ident = *j;
-                        found_close = true;
-                        chars.next();
-                        break;
-                    } else {
-                        chars.next();
+                '{' => {
+                    let starts_expression = chars
+                        .peek()
+                        .is_some_and(|(_, next)| next.is_alphabetic() || matches!(next, '_' | '('));
+                    if !starts_expression {
+                        text.push(c);
+                        continue;
                     }
-                }
-
-                if !found_close {
-                    return Err(self.make_invalid_syntax(
-                        "Unclosed string interpolation brace '}'".to_string(),
-                    ));
-                }
-
-                let var_name = &content[start_ident..end_ident];
-                let trimmed = var_name.trim();
-
-                if trimmed.is_empty() {
-                    return Err(
-                        self.make_invalid_syntax("Empty string interpolation '{}'".to_string())
-                    );
-                }
 
-                if trimmed.contains('.') {
-                    let parts: Vec<&str> = trimmed.split('.').collect();
-                    if parts.len() == 2 {
-                        expressions.push(Expression::MemberAccess {
-                            object: Box::new(Expression::Identifier(parts[0].to_string())),
-                            member: parts[1].to_string(),
-                        });
-                    } else {
-                        expressions.push(Expression::Identifier(trimmed.to_string()));
+                    let end = interpolation_end(content, i + 1).ok_or_else(|| {
+                        self.make_invalid_syntax(
+                            "Unclosed string interpolation brace '}'".to_string(),
+                        )
+                    })?;
+                    if !text.is_empty() {
+                        expressions.push(Expression::String(std::mem::take(&mut text)));
                     }
-                } else {
-                    expressions.push(Expression::Identifier(trimmed.to_string()));
+                    expressions.push(self.parse_interpolation(&content[i + 1..end])?);
+                    while chars.next_if(|(j, _)| *j <= end).is_some() {}
                 }
-
-                last_pos = end_ident + 1;
+                _ => text.push(c),
             }
         }
 
-        if last_pos < content.len() {
-            expressions.push(Expression::String(content[last_pos..].to_string()));
-        }
-
         if expressions.is_empty() {
-            return Ok(Expression::String("".to_string()));
+            return Ok(Expression::String(text));
         }
+        if !text.is_empty() {
+            expressions.push(Expression::String(text));
+        }
+        Ok(Expression::Interpolation(expressions))
+    }
 
-        let mut iterator = expressions.into_iter();
-        let first = iterator.next().unwrap();
-
-        let final_expr = iterator.fold(first, |acc, expr| Expression::BinaryOp {
-            left: Box::new(acc),
-            operator: BinaryOperator::Add,
-            right: Box::new(expr),
-        });
+    fn parse_interpolation(&self, source: &str) -> Result<Expression, ParseError> {
+        let invalid = |reason: String| {
+            self.make_invalid_syntax(format!(
+                "Invalid expression in string interpolation '{{{}}}': {}",
+                source, reason
+            ))
+        };
 
-        Ok(final_expr)
+        let tokens = Lexer::with_edition(source.trim(), self.edition)
+            .tokenize()
+            .map_err(|e| invalid(e.kind_message()))?;
+        let mut parser = Parser::with_edition(tokens, self.edition);
+        let expression = parser.parse_expression().map_err(|e| invalid(e.reason()))?;
+        parser.skip_ignorable();
+        if !parser.is_at_end() {
+            return Err(invalid(format!(
+                "unexpected {:?}",
+                parser.peek_type().cloned().unwrap_or(TokenType::Eof)
+            )));
+        }
+        Ok(expression)
     }
 
     fn parse_list(&mut self) -> Result<Expression, ParseError> {
@@ -1184,10 +1997,58 @@ impl Parser {
         Ok(Expression::Map(entries))
     }
 
+    // Instances of Order
+    // Instances of Order where Status = "open" and Total > 100
+    fn parse_instances_of(&mut self) -> Result<Expression, ParseError> {
+        self.advance(); // eat "Instances"
+        self.advance(); // eat "of"
+        let concept_name = self.expect_identifier()?;
+
+        let filter = if self.check_word("where") {
+            self.advance();
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+
+        if !self.tracked_concepts.contains(&concept_name) {
+            self.tracked_concepts.push(concept_name.clone());
+        }
+        Ok(Expression::InstancesOf {
+            concept_name,
+            filter,
+        })
+    }
+
+    // Contextual words such as `where` are plain identifiers to the lexer
+    fn check_word(&self, word: &str) -> bool {
+        matches!(self.peek_type(), Some(TokenType::Identifier(current)) if current == word)
+    }
+
+    fn next_is_identifier(&mut self, word: &str) -> bool {
+        matches!(self.tokens.peek(), Some(token) if token.token_type == TokenType::Identifier(word.to_string()))
+    }
+
     fn advance(&mut self) {
+        match self.peek_type() {
+            Some(TokenType::Indent) => self.depth += 1,
+            Some(TokenType::Dedent) => self.depth = self.depth.saturating_sub(1),
+            Some(TokenType::DocComment(text)) => {
+                let text = text.clone();
+                self.doc.push(text);
+            }
+            Some(TokenType::Newline | TokenType::Comment(_)) => {}
+            _ => self.doc.clear(),
+        }
         self.current = self.tokens.next();
     }
 
+    /// The `##` lines right above the current token, one paragraph.
+    fn take_doc(&mut self) -> Option<String> {
+        let doc = std::mem::take(&mut self.doc).join("\n");
+        (!doc.is_empty()).then_some(doc)
+    }
+
     fn peek_type(&self) -> Option<&TokenType> {
         self.current.as_ref().map(|t| &t.token_type)
     }
@@ -1287,12 +2148,47 @@ impl Parser {
     }
 }
 
+// Byte offset of the `}` closing an interpolation that starts at `start`,
+// skipping brackets and string literals inside the expression
+fn interpolation_end(content: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in content[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']') => depth -= 1,
+            (None, '}') if depth == 0 => return Some(start + i),
+            (None, '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
 impl ParseError {
     pub fn location(&self) -> (usize, usize) {
         match self {
             ParseError::UnexpectedToken { line, column, .. }
             | ParseError::UnexpectedEof { line, column }
             | ParseError::InvalidSyntax { line, column, .. } => (*line, *column),
+            ParseError::Lexer(error) => (error.line, error.column),
+        }
+    }
+}
+
+impl ParseError {
+    /// What went wrong, without the position.
+    pub fn reason(&self) -> String {
+        match self {
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => format!("expected {}, found {:?}", expected, found),
+            ParseError::UnexpectedEof { .. } => "unexpected end of input".to_string(),
+            ParseError::InvalidSyntax { message, .. } => message.clone(),
+            ParseError::Lexer(error) => error.kind_message(),
         }
     }
 }
@@ -1330,6 +2226,7 @@ impl std::fmt::Display for ParseError {
                     line, column, message
                 )
             }
+            ParseError::Lexer(error) => write!(f, "{}", error),
         }
     }
 }
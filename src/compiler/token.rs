@@ -41,6 +41,7 @@ pub enum TokenType {
     Minus,
     Star,
     Slash,
+    SlashSlash,
     Percent,
 
     Equals,
@@ -55,7 +56,10 @@ pub enum TokenType {
     Not,
 
     Number(String),
+    Integer(String),
     String_(String),
+    /// `r"..."`: taken as written, with no escapes or interpolation
+    RawString(String),
     True_,
     False_,
     Identifier(String),
@@ -70,14 +74,20 @@ pub enum TokenType {
     Dot,
 
     Comment(String),
+    /// `## text` above a Concept, field, method or Situation, for `sfex doc`
+    DocComment(String),
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub line: usize,
+    /// Counted in characters from 1, so text after `é` or `日本` isn't shifted
     pub column: usize,
+    /// In characters, like `column`
     pub length: usize,
+    /// Byte offset of the token's first character in the source
+    pub offset: usize,
 }
 
 impl Token {
@@ -87,6 +97,7 @@ impl Token {
             line,
             column,
             length,
+            offset: 0,
         }
     }
 }
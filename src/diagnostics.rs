@@ -0,0 +1,192 @@
+// Problems `sfex check` finds, in a form code review tools can read. Each
+// has a rule ID that stays the same between releases and the place it was
+// found, and a list of them prints as text, JSON or SARIF 2.1.0.
+
+use serde_json::{Value as JsonValue, json};
+
+/// Every rule ID with what it reports. IDs are never renamed or reused.
+pub const RULES: &[(&str, &str)] = &[
+    ("manifest-syntax", "sfex.toml is not valid TOML"),
+    (
+        "manifest-unknown-key",
+        "Unknown section or key in sfex.toml",
+    ),
+    ("manifest-type", "A value in sfex.toml has the wrong type"),
+    ("manifest-edition", "Unknown language edition"),
+    (
+        "manifest-missing-path",
+        "A file or directory named in sfex.toml doesn't exist",
+    ),
+    (
+        "manifest-dependency",
+        "A dependency needs exactly one of path or git",
+    ),
+    ("syntax-error", "A script doesn't tokenize or parse"),
+    (
+        "adjustment-order",
+        "The order a method's adjustments run in",
+    ),
+    (
+        "same-priority",
+        "Two situations adjust a method at the same priority",
+    ),
+    (
+        "reference-cycle",
+        "Two instances refer to each other through fields",
+    ),
+    (
+        "unhandled-case",
+        "A When misses values its field's Require allows",
+    ),
+    (
+        "unreachable-case",
+        "A case or Otherwise of a When can never run",
+    ),
+    (
+        "implicit-variable",
+        "`Field is` in a method makes a variable that hides the field",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub level: Level,
+    /// Relative to the project root, with `/` separators
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// `file:line:column: level: message`, as compilers print them.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{}:{}:{}: {}: {}",
+            self.file,
+            self.line,
+            self.column,
+            self.level.name(),
+            self.message
+        )
+    }
+}
+
+pub fn to_json(diagnostics: &[Diagnostic]) -> JsonValue {
+    let count = |level| diagnostics.iter().filter(|d| d.level == level).count();
+    json!({
+        "diagnostics": diagnostics
+            .iter()
+            .map(|d| json!({
+                "rule": d.rule,
+                "level": d.level.name(),
+                "file": d.file,
+                "line": d.line,
+                "column": d.column,
+                "message": d.message,
+            }))
+            .collect::<Vec<_>>(),
+        "errors": count(Level::Error),
+        "warnings": count(Level::Warning),
+    })
+}
+
+pub fn to_sarif(diagnostics: &[Diagnostic]) -> JsonValue {
+    let rules: Vec<JsonValue> = RULES
+        .iter()
+        .map(|(id, description)| json!({ "id": id, "shortDescription": { "text": description } }))
+        .collect();
+    let results: Vec<JsonValue> = diagnostics
+        .iter()
+        .map(|d| {
+            let mut result = json!({
+                "ruleId": d.rule,
+                "level": d.level.name(),
+                "message": { "text": d.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": d.file, "uriBaseId": "%SRCROOT%" },
+                        "region": { "startLine": d.line, "startColumn": d.column },
+                    }
+                }],
+            });
+            if let Some(index) = RULES.iter().position(|(id, _)| *id == d.rule) {
+                result["ruleIndex"] = json!(index);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "sfex",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+            // Columns count characters, not UTF-16 code units
+            "columnKind": "unicodeCodePoints",
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sarif_results() {
+        let diagnostics = vec![Diagnostic {
+            rule: "syntax-error",
+            level: Level::Error,
+            file: "src/main.sfex".to_string(),
+            line: 3,
+            column: 5,
+            message: "expected expression".to_string(),
+        }];
+        assert_eq!(
+            diagnostics[0].to_text(),
+            "src/main.sfex:3:5: error: expected expression"
+        );
+
+        let sarif = to_sarif(&diagnostics);
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "syntax-error");
+        assert_eq!(
+            sarif["runs"][0]["tool"]["driver"]["rules"]
+                [result["ruleIndex"].as_u64().unwrap() as usize]["id"],
+            "syntax-error"
+        );
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/main.sfex");
+        assert_eq!(location["region"]["startLine"], 3);
+
+        let json = to_json(&diagnostics);
+        assert_eq!(json["errors"], 1);
+        assert_eq!(json["diagnostics"][0]["column"], 5);
+    }
+}
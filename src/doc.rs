@@ -0,0 +1,351 @@
+// `sfex doc`: a reference for a project, as Markdown or one HTML page. Its
+// concepts, fields, methods and situations are described by the `##`
+// comments above them; the standard library is listed from what
+// register_stdlib defines, so it never goes out of date.
+
+use crate::compiler::ast::{Concept, Method, Situation};
+use crate::runtime::interpreter::Interpreter;
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::value::Value;
+
+/// The concepts and situations of one script.
+pub struct ScriptDocs {
+    /// Relative to the project root, with `/` separators
+    pub file: String,
+    pub concepts: Vec<Concept>,
+    pub situations: Vec<Situation>,
+}
+
+/// A global of the standard library: a module with its members, or a
+/// function such as `Some`.
+pub struct StdlibEntry {
+    pub name: String,
+    /// `function`, or the type of a value
+    pub kind: &'static str,
+    pub members: Vec<(String, &'static str)>,
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::NativeFunction(_) => "function",
+        other => other.type_name(),
+    }
+}
+
+/// Every global a new interpreter has, by name.
+pub fn stdlib() -> Vec<StdlibEntry> {
+    let interpreter = Interpreter::new();
+    let mut entries: Vec<StdlibEntry> = interpreter
+        .builtin_globals()
+        .into_iter()
+        .map(|(name, value)| {
+            let members = match &value {
+                Value::Map(map) => map
+                    .read_recover()
+                    .iter()
+                    .map(|(member, value)| (member.clone(), kind(value)))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            StdlibEntry {
+                name,
+                kind: kind(&value),
+                members,
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// One piece of the reference, before it is written out as Markdown or HTML.
+enum Part {
+    Heading(usize, String),
+    /// Written as given: `code` in backquotes
+    Text(String),
+    /// A doc comment, whose blank `##` lines separate paragraphs
+    Doc(String),
+    /// A list entry: a name in code, and what it is
+    Item(String, Option<String>),
+}
+
+fn signature(method: &Method) -> String {
+    if method.parameters.is_empty() {
+        method.name.clone()
+    } else {
+        format!("{} with {}", method.name, method.parameters.join(" and "))
+    }
+}
+
+fn concept_parts(parts: &mut Vec<Part>, file: &str, concept: &Concept) {
+    parts.push(Part::Heading(3, concept.name.clone()));
+    let mut about = format!("Defined in `{}`", file);
+    if let Some(parent) = &concept.parent {
+        about.push_str(&format!(", extends `{}`", parent));
+    }
+    parts.push(Part::Text(about));
+    if let Some(doc) = &concept.doc {
+        parts.push(Part::Doc(doc.clone()));
+    }
+    if !concept.fields.is_empty() {
+        parts.push(Part::Heading(4, "Fields".to_string()));
+        for field in &concept.fields {
+            parts.push(Part::Item(
+                field.clone(),
+                concept.field_docs.get(field).cloned(),
+            ));
+        }
+    }
+    if !concept.methods.is_empty() {
+        parts.push(Part::Heading(4, "Methods".to_string()));
+        for method in &concept.methods {
+            parts.push(Part::Item(signature(method), method.doc.clone()));
+        }
+    }
+}
+
+fn situation_parts(parts: &mut Vec<Part>, file: &str, situation: &Situation) {
+    parts.push(Part::Heading(3, situation.name.clone()));
+    let mut about = format!("Defined in `{}`", file);
+    if situation.priority != 0 {
+        about.push_str(&format!(", priority {}", situation.priority));
+    }
+    if situation.condition.is_some() {
+        about.push_str(", switches itself on and off");
+    }
+    parts.push(Part::Text(about));
+    if let Some(doc) = &situation.doc {
+        parts.push(Part::Doc(doc.clone()));
+    }
+    let adjusted: Vec<(String, Option<String>)> = situation
+        .adjustments
+        .iter()
+        .flat_map(|adjustment| {
+            adjustment.methods.iter().map(|method| {
+                (
+                    format!("{}.{}", adjustment.concept_name, signature(method)),
+                    method.doc.clone(),
+                )
+            })
+        })
+        .collect();
+    if !adjusted.is_empty() {
+        parts.push(Part::Heading(4, "Adjusts".to_string()));
+        parts.extend(
+            adjusted
+                .into_iter()
+                .map(|(name, doc)| Part::Item(name, doc)),
+        );
+    }
+}
+
+fn parts(title: &str, scripts: &[ScriptDocs], stdlib: &[StdlibEntry]) -> Vec<Part> {
+    let mut parts = vec![Part::Heading(1, format!("{} reference", title))];
+
+    let concepts: Vec<(&str, &Concept)> = scripts
+        .iter()
+        .flat_map(|script| script.concepts.iter().map(|c| (script.file.as_str(), c)))
+        .collect();
+    if !concepts.is_empty() {
+        parts.push(Part::Heading(2, "Concepts".to_string()));
+        for (file, concept) in concepts {
+            concept_parts(&mut parts, file, concept);
+        }
+    }
+
+    let situations: Vec<(&str, &Situation)> = scripts
+        .iter()
+        .flat_map(|script| script.situations.iter().map(|s| (script.file.as_str(), s)))
+        .collect();
+    if !situations.is_empty() {
+        parts.push(Part::Heading(2, "Situations".to_string()));
+        for (file, situation) in situations {
+            situation_parts(&mut parts, file, situation);
+        }
+    }
+
+    if !stdlib.is_empty() {
+        parts.push(Part::Heading(2, "Standard library".to_string()));
+        let functions: Vec<&StdlibEntry> = stdlib
+            .iter()
+            .filter(|entry| entry.members.is_empty())
+            .collect();
+        for module in stdlib.iter().filter(|entry| !entry.members.is_empty()) {
+            parts.push(Part::Heading(3, module.name.clone()));
+            for (member, kind) in &module.members {
+                parts.push(Part::Item(
+                    format!("{}.{}", module.name, member),
+                    Some(kind.to_string()),
+                ));
+            }
+        }
+        if !functions.is_empty() {
+            parts.push(Part::Heading(3, "Globals".to_string()));
+            for entry in functions {
+                parts.push(Part::Item(entry.name.clone(), Some(entry.kind.to_string())));
+            }
+        }
+    }
+    parts
+}
+
+pub fn to_markdown(title: &str, scripts: &[ScriptDocs], stdlib: &[StdlibEntry]) -> String {
+    let mut out = String::new();
+    let mut in_list = false;
+    for part in parts(title, scripts, stdlib) {
+        let item = matches!(part, Part::Item(..));
+        if in_list && !item {
+            out.push('\n');
+        }
+        in_list = item;
+        match part {
+            Part::Heading(level, text) => {
+                out.push_str(&format!("{} {}\n\n", "#".repeat(level), text));
+            }
+            Part::Text(text) | Part::Doc(text) => out.push_str(&format!("{}\n\n", text)),
+            Part::Item(name, None) => out.push_str(&format!("- `{}`\n", name)),
+            Part::Item(name, Some(text)) => {
+                // A list entry is one paragraph
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                out.push_str(&format!("- `{}`: {}\n", name, text));
+            }
+        }
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escaped, with `code` in backquotes as <code>.
+fn inline(text: &str) -> String {
+    escape(text)
+        .split('`')
+        .enumerate()
+        .map(|(i, piece)| {
+            if i % 2 == 1 {
+                format!("<code>{}</code>", piece)
+            } else {
+                piece.to_string()
+            }
+        })
+        .collect()
+}
+
+fn anchor(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+pub fn to_html(title: &str, scripts: &[ScriptDocs], stdlib: &[StdlibEntry]) -> String {
+    let mut body = String::new();
+    let mut in_list = false;
+    for part in parts(title, scripts, stdlib) {
+        let item = matches!(part, Part::Item(..));
+        if item && !in_list {
+            body.push_str("<ul>\n");
+        } else if !item && in_list {
+            body.push_str("</ul>\n");
+        }
+        in_list = item;
+        match part {
+            Part::Heading(level, text) => body.push_str(&format!(
+                "<h{level} id=\"{}\">{}</h{level}>\n",
+                anchor(&text),
+                escape(&text)
+            )),
+            Part::Text(text) => body.push_str(&format!("<p>{}</p>\n", inline(&text))),
+            Part::Doc(text) => {
+                for paragraph in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+                    body.push_str(&format!("<p>{}</p>\n", inline(paragraph)));
+                }
+            }
+            Part::Item(name, text) => {
+                body.push_str(&format!("<li><code>{}</code>", escape(&name)));
+                if let Some(text) = text {
+                    body.push_str(&format!(": {}", inline(&text)));
+                }
+                body.push_str("</li>\n");
+            }
+        }
+    }
+    if in_list {
+        body.push_str("</ul>\n");
+    }
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{} reference</title>\n<style>\nbody {{ font-family: system-ui, sans-serif; max-width: 50rem; margin: 2rem auto; padding: 0 1rem; line-height: 1.5; }}\ncode {{ background: #f3f3f3; padding: 0 0.2rem; }}\nh3 {{ border-top: 1px solid #ddd; padding-top: 1rem; }}\n</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lexer, Parser};
+
+    #[test]
+    fn test_doc_comments() {
+        let source = "## Money someone keeps with us\n##\n## Balances are in cents.\nConcept: Account\n    ## In cents\n    Balance, Held\n    Owner\n\n    ## Adds `Amount` to the balance\n    To Deposit with Amount and Note:\n        # not a doc comment\n        Set Balance to Balance + Amount\n\n    To Close:\n        Return 0\n\n### Not for the docs\nSituation: Audit priority 5\n    Adjust Account:\n        ## Logs each deposit\n        To Deposit with Amount and Note:\n            Proceed()\n\nStory:\n    ## lost: no item follows\n    Print 1\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let account = &program.concepts[0];
+        assert_eq!(
+            account.doc.as_deref(),
+            Some("Money someone keeps with us\n\nBalances are in cents.")
+        );
+        assert_eq!(account.field_docs.get("Held").unwrap(), "In cents");
+        assert!(!account.field_docs.contains_key("Owner"));
+        assert_eq!(
+            account.methods[0].doc.as_deref(),
+            Some("Adds `Amount` to the balance")
+        );
+        assert_eq!(account.methods[1].doc, None);
+        assert_eq!(program.situations[0].doc, None);
+
+        let scripts = [ScriptDocs {
+            file: "models/Account.sfex".to_string(),
+            concepts: program.concepts,
+            situations: program.situations,
+        }];
+        let markdown = to_markdown("Bank", &scripts, &[]);
+        assert!(markdown.starts_with("# Bank reference\n\n## Concepts\n\n### Account\n\nDefined in `models/Account.sfex`\n\nMoney someone keeps with us\n\nBalances are in cents.\n\n#### Fields\n\n- `Balance`: In cents\n- `Held`: In cents\n- `Owner`\n"));
+        assert!(markdown.contains(
+            "- `Deposit with Amount and Note`: Adds `Amount` to the balance\n- `Close`\n"
+        ));
+        assert!(markdown.contains("### Audit\n\nDefined in `models/Account.sfex`, priority 5\n\n#### Adjusts\n\n- `Account.Deposit with Amount and Note`: Logs each deposit\n"));
+
+        let html = to_html("Bank", &scripts, &[]);
+        assert!(html.contains("<p>Balances are in cents.</p>"));
+        assert!(html.contains(
+            "<li><code>Deposit with Amount and Note</code>: Adds <code>Amount</code> to the balance</li>"
+        ));
+    }
+
+    #[test]
+    fn test_stdlib_listing() {
+        let entries = stdlib();
+        let math = entries.iter().find(|entry| entry.name == "Math").unwrap();
+        assert!(math.members.contains(&("Round".to_string(), "function")));
+        let some = entries.iter().find(|entry| entry.name == "Some").unwrap();
+        assert_eq!(some.kind, "function");
+        assert!(some.members.is_empty());
+        let markdown = to_markdown("Empty", &[], &entries);
+        assert!(markdown.contains("### Math\n\n"));
+        assert!(markdown.contains("- `Math.Round`: function\n"));
+        assert!(markdown.contains("### Globals\n\n"));
+    }
+}
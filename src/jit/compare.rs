@@ -0,0 +1,147 @@
+// `sfex run --compare-jit`: each call of a compiled method also runs in the
+// interpreter, and both are timed. The interpreter's result is the one the
+// script gets; the JIT's, computed on a copy of the instance, is only
+// compared with it. A JIT that works in f64 where the interpreter keeps exact
+// decimals shows up here as a divergence.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Relative difference `--compare-jit` allows unless told otherwise
+pub const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// How one compiled method did in both engines.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodComparison {
+    pub calls: u64,
+    pub interpreted_time: Duration,
+    pub jit_time: Duration,
+    /// Largest difference relative to the interpreter's result
+    pub max_divergence: f64,
+    /// Calls whose results differ by more than the tolerance
+    pub divergent_calls: u64,
+    /// The first such call: interpreter's result, then the JIT's
+    pub first_divergence: Option<(String, f64)>,
+}
+
+impl MethodComparison {
+    /// How many times faster the JIT ran; 0 when nothing was timed.
+    pub fn speedup(&self) -> f64 {
+        if self.jit_time.is_zero() {
+            return 0.0;
+        }
+        self.interpreted_time.as_secs_f64() / self.jit_time.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JitComparison {
+    pub tolerance: f64,
+    methods: BTreeMap<String, MethodComparison>,
+}
+
+impl JitComparison {
+    pub fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            methods: BTreeMap::new(),
+        }
+    }
+
+    /// Record one call of `method` (as `Concept.Method`). `interpreted` is
+    /// the interpreter's result as a number, or as text when it isn't one.
+    pub fn record(
+        &mut self,
+        method: &str,
+        interpreted: Result<f64, String>,
+        jit: f64,
+        interpreted_time: Duration,
+        jit_time: Duration,
+    ) {
+        let tolerance = self.tolerance;
+        let entry = self.methods.entry(method.to_string()).or_default();
+        entry.calls += 1;
+        entry.interpreted_time += interpreted_time;
+        entry.jit_time += jit_time;
+
+        let (shown, divergence) = match interpreted {
+            Ok(value) => (value.to_string(), relative_difference(value, jit)),
+            Err(shown) => (shown, f64::INFINITY),
+        };
+        entry.max_divergence = entry.max_divergence.max(divergence);
+        if divergence > tolerance {
+            entry.divergent_calls += 1;
+            entry.first_divergence.get_or_insert((shown, jit));
+        }
+    }
+
+    /// Every compared method, by name.
+    pub fn methods(&self) -> impl Iterator<Item = (&String, &MethodComparison)> {
+        self.methods.iter()
+    }
+
+    pub fn diverged(&self) -> bool {
+        self.methods
+            .values()
+            .any(|method| method.divergent_calls > 0)
+    }
+}
+
+fn relative_difference(expected: f64, actual: f64) -> f64 {
+    if expected == actual || (expected.is_nan() && actual.is_nan()) {
+        return 0.0;
+    }
+    (expected - actual).abs() / expected.abs().max(actual.abs()).max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut comparison = JitComparison::new(DEFAULT_TOLERANCE);
+        let ms = Duration::from_millis;
+        comparison.record("Counter.Add", Ok(0.3), 0.1 + 0.2, ms(4), ms(1));
+        comparison.record("Counter.Add", Ok(2.0), 2.0, ms(4), ms(1));
+        assert!(!comparison.diverged());
+
+        comparison.record("Big.Id", Ok(9007199254740993.0), 9.0e15, ms(1), ms(1));
+        comparison.record("Big.Name", Err("\"x\"".to_string()), 0.0, ms(1), ms(1));
+        let methods: Vec<_> = comparison.methods().collect();
+        assert_eq!(methods[2].0, "Counter.Add");
+        assert_eq!(methods[2].1.calls, 2);
+        assert_eq!(methods[2].1.speedup(), 4.0);
+        assert_eq!(methods[0].1.divergent_calls, 1);
+        assert_eq!(
+            methods[1].1.first_divergence,
+            Some(("\"x\"".to_string(), 0.0))
+        );
+        assert!(comparison.diverged());
+    }
+
+    #[test]
+    fn test_bitwise_parity() {
+        use crate::{Interpreter, Lexer, Parser};
+
+        // Hot enough to compile: Scale is, Shift stays interpreted and exact
+        let source = "Concept: Bits\n    To Scale with N:\n        Return N * 2\n\n    To Shift with N:\n        Return (N ShiftLeft 70) Xor (BitNot N)\n\nStory:\n    Create Bits Called B\n    Wide is 0\n    Repeat 300 times:\n        Last is B.Scale with 3\n        Wide is B.Shift with 3\n    Print Wide\n";
+        let program = Parser::new(Lexer::new(source).tokenize().unwrap())
+            .parse()
+            .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.compare_jit(DEFAULT_TOLERANCE);
+        interpreter.capture_output();
+        interpreter.run(program).unwrap();
+        assert_eq!(interpreter.take_output().trim(), "-3541774862152233910276");
+
+        let comparison = interpreter.jit_comparison().unwrap();
+        let methods: Vec<_> = comparison
+            .methods()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(methods, ["Bits.Scale"]);
+        assert!(!comparison.diverged());
+    }
+}
@@ -1,4 +1,5 @@
-use crate::compiler::ast::{Expression, Method, Statement};
+use crate::compiler::ast::{Concept, Expression, Method, Statement};
+use crate::runtime::lock::RwLockExt;
 use crate::runtime::value::Value as SfxValue;
 use bigdecimal::{BigDecimal, FromPrimitive};
 use cranelift::prelude::*;
@@ -416,7 +417,7 @@ impl JitCompiler {
         update_field_func_id: Option<cranelift_module::FuncId>,
     ) -> Result<Value, String> {
         match expr {
-            Expression::Number(n) => {
+            Expression::Number(n) | Expression::Integer(n) => {
                 let num: f64 = n.parse().unwrap_or(0.0);
                 Ok(builder.ins().f64const(num))
             }
@@ -446,6 +447,20 @@ impl JitCompiler {
                     BinaryOperator::Subtract => Ok(builder.ins().fsub(lhs, rhs)),
                     BinaryOperator::Multiply => Ok(builder.ins().fmul(lhs, rhs)),
                     BinaryOperator::Divide => Ok(builder.ins().fdiv(lhs, rhs)),
+                    BinaryOperator::IntDivide => {
+                        let quotient = builder.ins().fdiv(lhs, rhs);
+                        Ok(builder.ins().trunc(quotient))
+                    }
+                    // The interpreter's are exact on Integers of any size
+                    // and refuse fractions; in f64 and i64 they would wrap,
+                    // saturate or round, so methods using them stay interpreted
+                    BinaryOperator::BitAnd
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::Xor
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight => {
+                        Err("Bitwise operators are not supported by JIT".to_string())
+                    }
                     BinaryOperator::Modulo => {
                         Err("Modulo operator is not supported by JIT yet".to_string())
                     }
@@ -605,6 +620,10 @@ impl JitCompiler {
                         let one = builder.ins().f64const(1.0);
                         Ok(builder.ins().select(is_zero, one, zero))
                     }
+                    // Like the binary ones, BitNot stays exact in the interpreter
+                    UnaryOperator::BitNot => {
+                        Err("Bitwise operators are not supported by JIT".to_string())
+                    }
                 }
             }
             Expression::MethodCall {
@@ -659,6 +678,25 @@ impl JitCompiler {
         }
     }
 
+    /// Methods of `concept` that the JIT can't compile, with the reason. The
+    /// code is compiled into a throwaway module, so nothing is kept.
+    pub fn unsupported_methods(concept: &Concept) -> Vec<(&Method, String)> {
+        let mut compiler = Self::new();
+        let unsupported = concept
+            .methods
+            .iter()
+            .filter_map(|method| {
+                compiler
+                    .compile_method(&concept.name, method, &concept.methods)
+                    .err()
+                    .map(|reason| (method, reason))
+            })
+            .collect();
+        // No pointer to the compiled code escaped this function
+        unsafe { compiler.module.free_memory() };
+        unsupported
+    }
+
     pub fn get_function(&self, concept: &str, method: &str) -> Option<*const u8> {
         let key = (concept.to_string(), method.to_string());
         self.compiled_functions.get(&key).copied()
@@ -808,12 +846,12 @@ pub unsafe extern "C" fn jit_update_field(
     field_len: usize,
     value: f64,
 ) {
-    let rwlock = unsafe { &*(obj_ptr as *const RwLock<HashMap<String, SfxValue>>) };
+    let rwlock = unsafe { &*(obj_ptr as *const RwLock<indexmap::IndexMap<String, SfxValue>>) };
     let field_slice = unsafe { std::slice::from_raw_parts(field_ptr, field_len) };
     let field_name = unsafe { std::str::from_utf8_unchecked(field_slice) };
     let sfx_value =
         SfxValue::Number(BigDecimal::from_f64(value).unwrap_or_else(|| BigDecimal::from(0)));
-    let mut map = rwlock.write().expect("lock poisoned");
+    let mut map = rwlock.write_recover();
     if let Some(existing_val) = map.get_mut(field_name) {
         *existing_val = sfx_value;
     } else {
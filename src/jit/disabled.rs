@@ -0,0 +1,39 @@
+// Stands in for the Cranelift compiler when sfex is built without the `jit`
+// feature. Nothing is ever compiled, so every method runs in the interpreter.
+
+use crate::compiler::ast::{Concept, Method};
+
+#[derive(Default)]
+pub struct JitCompiler;
+
+impl JitCompiler {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn compile_method(
+        &mut self,
+        _concept_name: &str,
+        _method: &Method,
+        _available_methods: &[Method],
+    ) -> Result<*const u8, String> {
+        Err("sfex was built without the jit feature".to_string())
+    }
+
+    /// Empty: with no JIT, no method is expected to be compiled.
+    pub fn unsupported_methods(_concept: &Concept) -> Vec<(&Method, String)> {
+        Vec::new()
+    }
+
+    pub fn get_function(&self, _concept: &str, _method: &str) -> Option<*const u8> {
+        None
+    }
+
+    pub fn get_required_fields_by_key(&self, _concept: &str, _method_name: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    pub fn method_needs_obj_ptr(&self, _concept: &str, _method_name: &str) -> bool {
+        false
+    }
+}
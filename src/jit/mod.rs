@@ -1,7 +1,13 @@
 // JIT Compilation Module using Cranelift
+pub mod compare;
+#[cfg(feature = "jit")]
+pub mod compiler;
+#[cfg(not(feature = "jit"))]
+#[path = "disabled.rs"]
 pub mod compiler;
 pub mod profiler;
+pub use compare::{JitComparison, MethodComparison};
 pub use compiler::JitCompiler;
-pub use profiler::Profiler;
+pub use profiler::{ObserverCost, Profiler};
 /// Takes a pointer to interpreter state, returns a Value
 pub type JitFunction = unsafe extern "C" fn() -> i64;
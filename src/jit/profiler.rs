@@ -1,15 +1,27 @@
 // Profiler for detecting hot code paths
 
+use crate::runtime::lock::RwLockExt;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-/// JIT thresahold
-const JIT_THRESHOLD: usize = 100;
+/// What the runs of one `When Concept.Field changes` observer cost.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObserverCost {
+    pub runs: u64,
+    /// Statements run, those of observers it triggered included
+    pub statements: u64,
+    pub total_time: Duration,
+    pub max_time: Duration,
+}
 
 #[derive(Debug, Clone)]
 pub struct Profiler {
     call_counts: Arc<RwLock<HashMap<(String, String), usize>>>,
     jit_compiled: Arc<RwLock<HashMap<(String, String), bool>>>,
+    observer_costs: Arc<RwLock<HashMap<String, ObserverCost>>>,
+    // Calls before a method is compiled; None with the JIT switched off
+    threshold: Option<usize>,
 }
 
 impl Profiler {
@@ -17,51 +29,85 @@ impl Profiler {
         Self {
             call_counts: Arc::new(RwLock::new(HashMap::new())),
             jit_compiled: Arc::new(RwLock::new(HashMap::new())),
+            observer_costs: Arc::new(RwLock::new(HashMap::new())),
+            threshold: {
+                let config = crate::runtime::config::current();
+                config.jit.then_some(config.jit_threshold)
+            },
         }
     }
 
+    /// Never compile anything from now on.
+    pub fn disable_jit(&mut self) {
+        self.threshold = None;
+    }
+
     pub fn record_call(&self, concept: &str, method: &str) {
         let key = (concept.to_string(), method.to_string());
-        let mut counts = self.call_counts.write().expect("lock poisoned");
+        let mut counts = self.call_counts.write_recover();
         *counts.entry(key).or_insert(0) += 1;
     }
 
     pub fn should_jit(&self, concept: &str, method: &str) -> bool {
+        let Some(threshold) = self.threshold else {
+            return false;
+        };
         let key = (concept.to_string(), method.to_string());
 
         {
-            let compiled = self.jit_compiled.read().expect("lock poisoned");
+            let compiled = self.jit_compiled.read_recover();
             if compiled.get(&key).copied().unwrap_or(false) {
                 return false;
             }
         }
 
-        let counts = self.call_counts.read().expect("lock poisoned");
-        counts.get(&key).copied().unwrap_or(0) >= JIT_THRESHOLD
+        let counts = self.call_counts.read_recover();
+        counts.get(&key).copied().unwrap_or(0) >= threshold
     }
 
     pub fn mark_compiled(&self, concept: &str, method: &str) {
         let key = (concept.to_string(), method.to_string());
-        let mut compiled = self.jit_compiled.write().expect("lock poisoned");
+        let mut compiled = self.jit_compiled.write_recover();
         compiled.insert(key, true);
     }
 
     pub fn get_call_count(&self, concept: &str, method: &str) -> usize {
         let key = (concept.to_string(), method.to_string());
-        let counts = self.call_counts.read().expect("lock poisoned");
+        let counts = self.call_counts.read_recover();
         counts.get(&key).copied().unwrap_or(0)
     }
 
     pub fn get_hot_functions(&self) -> Vec<(String, String, usize)> {
-        let counts = self.call_counts.read().expect("lock poisoned");
+        let threshold = self.threshold.unwrap_or(usize::MAX);
+        let counts = self.call_counts.read_recover();
         let mut hot: Vec<_> = counts
             .iter()
-            .filter(|&(_, count)| *count >= JIT_THRESHOLD)
+            .filter(|&(_, count)| *count >= threshold)
             .map(|((c, m), count)| (c.clone(), m.clone(), *count))
             .collect();
         hot.sort_by(|a, b| b.2.cmp(&a.2)); // Sort by count descending
         hot
     }
+
+    pub fn record_observer(&self, observer: &str, statements: u64, time: Duration) {
+        let mut costs = self.observer_costs.write_recover();
+        let cost = costs.entry(observer.to_string()).or_default();
+        cost.runs += 1;
+        cost.statements += statements;
+        cost.total_time += time;
+        cost.max_time = cost.max_time.max(time);
+    }
+
+    /// Every observer that ran, the most expensive in total first.
+    pub fn observer_costs(&self) -> Vec<(String, ObserverCost)> {
+        let costs = self.observer_costs.read_recover();
+        let mut costs: Vec<_> = costs
+            .iter()
+            .map(|(observer, cost)| (observer.clone(), cost.clone()))
+            .collect();
+        costs.sort_by(|a, b| b.1.total_time.cmp(&a.1.total_time).then(a.0.cmp(&b.0)));
+        costs
+    }
 }
 
 impl Default for Profiler {
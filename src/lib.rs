@@ -1,13 +1,22 @@
 // Core Library
 pub mod compiler;
+pub mod diagnostics;
+pub mod doc;
 pub mod jit;
+pub mod literate;
+#[cfg(feature = "lsp")]
 pub mod lsp;
 pub mod project;
 pub mod runtime;
+pub mod scaffold;
 pub mod stdlib;
 pub use compiler::ast::*;
+pub use compiler::edition::Edition;
 pub use compiler::lexer::{Lexer, LexerError};
 pub use compiler::parser::{ParseError, Parser};
 pub use compiler::token::{Token, TokenType};
 pub use runtime::interpreter::{Interpreter, RuntimeError};
+pub use runtime::limits::Limits;
+pub use runtime::usage::{Usage, UsageReporter};
 pub use runtime::value::Value;
+pub use runtime::warnings::{Warning, WarningReporter};
@@ -0,0 +1,168 @@
+// Literate scripts: Markdown whose ```sfex blocks `sfex run --literate` runs
+// in order in one interpreter, so variables and concepts from earlier blocks
+// are there in later ones. A block without a top-level `Story:`, `Concept:` or
+// `Situation:` is run as the body of a Story.
+//
+// With `--emit` the Markdown is written back with each block's output in an
+// ```output block right after it. Output blocks from an earlier run are
+// replaced, so the same file can be run again.
+
+const OUTPUT_INFO: &str = "output";
+
+pub struct Block {
+    /// Line index of the opening ```sfex fence
+    pub fence: usize,
+    /// Line index just past the closing fence
+    pub end: usize,
+    /// Line index just past the block's old ```output block, or `end`
+    replace_end: usize,
+    pub code: String,
+}
+
+impl Block {
+    /// Source for the lexer, padded so that line numbers in errors are lines
+    /// of the Markdown file.
+    pub fn source(&self) -> String {
+        let is_program = self.code.lines().any(|line| {
+            ["Story:", "Concept:", "Situation:"]
+                .iter()
+                .any(|keyword| line.starts_with(keyword))
+        });
+        if is_program {
+            return "\n".repeat(self.fence + 1) + &self.code;
+        }
+
+        let mut source = "\n".repeat(self.fence) + "Story:\n";
+        for line in self.code.lines() {
+            if !line.trim().is_empty() {
+                source.push_str("    ");
+                source.push_str(line);
+            }
+            source.push('\n');
+        }
+        source
+    }
+}
+
+/// Find the ```sfex blocks of a Markdown document. Other fenced blocks are
+/// skipped, so code shown in ```text or ````markdown fences is not run.
+pub fn extract_blocks(markdown: &str) -> Result<Vec<Block>, String> {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some((fence, info)) = open_fence(lines[i]) else {
+            i += 1;
+            continue;
+        };
+        let start = i;
+        let end = close_fence(&lines, start, fence).ok_or_else(|| {
+            format!(
+                "line {}: code block is not closed with {}",
+                start + 1,
+                fence
+            )
+        })?;
+        i = end;
+        if info != "sfex" {
+            continue;
+        }
+
+        // An output block from an earlier --emit belongs to this block
+        let mut replace_end = end;
+        let mut next = end;
+        while lines.get(next).is_some_and(|line| line.trim().is_empty()) {
+            next += 1;
+        }
+        if let Some(line) = lines.get(next)
+            && let Some((output_fence, OUTPUT_INFO)) = open_fence(line)
+            && let Some(output_end) = close_fence(&lines, next, output_fence)
+        {
+            replace_end = output_end;
+            i = output_end;
+        }
+
+        blocks.push(Block {
+            fence: start,
+            end,
+            replace_end,
+            code: lines[start + 1..end - 1].join("\n"),
+        });
+    }
+
+    Ok(blocks)
+}
+
+/// The Markdown with `outputs[i]` shown after block `i`. Blocks past the end
+/// of `outputs` (not run) keep their old output; an empty output removes it.
+pub fn annotate(markdown: &str, blocks: &[Block], outputs: &[String]) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut annotated: Vec<&str> = Vec::with_capacity(lines.len());
+    let mut copied = 0;
+
+    for (block, output) in blocks.iter().zip(outputs) {
+        annotated.extend(&lines[copied..block.end]);
+        if !output.is_empty() {
+            annotated.push("");
+            annotated.push("```output");
+            annotated.extend(output.lines());
+            annotated.push("```");
+        }
+        copied = block.replace_end;
+    }
+    annotated.extend(&lines[copied..]);
+
+    let mut result = annotated.join("\n");
+    if markdown.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+/// The fence (```` ``` ```` or longer) and the language of an opening line.
+fn open_fence(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    let ticks = line.len() - line.trim_start_matches('`').len();
+    if ticks < 3 {
+        return None;
+    }
+    let (fence, info) = line.split_at(ticks);
+    Some((fence, info.split_whitespace().next().unwrap_or("")))
+}
+
+/// Line index just past the fence that closes the block opened at `start`.
+fn close_fence(lines: &[&str], start: usize, fence: &str) -> Option<usize> {
+    lines[start + 1..]
+        .iter()
+        .position(|line| line.trim() == fence)
+        .map(|offset| start + offset + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_and_annotate() {
+        let markdown = "# Title\n\n```sfex\nX is 2\nPrint X\n```\n\n```text\n```sfex\n```\n\n```sfex\nStory:\n    Print X + 1\n```\nEnd\n";
+        let blocks = extract_blocks(markdown).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].code, "X is 2\nPrint X");
+        assert_eq!(blocks[0].source(), "\n\nStory:\n    X is 2\n    Print X\n");
+        assert!(blocks[1].source().ends_with("\nStory:\n    Print X + 1"));
+
+        let outputs = vec!["2\n".to_string(), "3\n".to_string()];
+        let annotated = annotate(markdown, &blocks, &outputs);
+        assert!(annotated.contains("Print X\n```\n\n```output\n2\n```\n\n```text"));
+        assert!(annotated.ends_with("```\n\n```output\n3\n```\nEnd\n"));
+
+        // Running an annotated file again replaces the old output
+        let again = extract_blocks(&annotated).unwrap();
+        let outputs = vec!["2\n".to_string(), "4\n".to_string()];
+        let rerun = annotate(&annotated, &again, &outputs);
+        assert_eq!(rerun, annotated.replace("```output\n3", "```output\n4"));
+
+        assert!(extract_blocks("```sfex\nPrint 1\n").is_err());
+    }
+}
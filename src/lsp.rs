@@ -12,22 +12,107 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::compiler::ast::{Program, ShadowedField, Statement};
+use crate::compiler::edition::Edition;
 use crate::compiler::lexer::Lexer;
 use crate::compiler::parser::Parser;
+use crate::jit::JitCompiler;
+use crate::project;
+use crate::runtime::interpreter::Interpreter;
+use serde::Deserialize;
 use serde_json::{Value as JsonValue, json};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Settings come from `.sfex/settings.toml` in the workspace root, and the
+// editor's `sfex` section (asked for with workspace/configuration) overrides
+// them key by key:
+//
+//   [diagnostics]
+//   enabled = true   # lexer and parser errors
+//   jit = true       # note methods the JIT can't compile
+//
+//   [lint]
+//   undefined-concept = false
+const SETTINGS_FILE: &str = ".sfex/settings.toml";
+const CONFIGURATION_SECTION: &str = "sfex";
+
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+const SEVERITY_INFORMATION: u8 = 3;
+
+const COMPLETION_MODULE: u8 = 9;
+const COMPLETION_PROPERTY: u8 = 10;
+const COMPLETION_VALUE: u8 = 12;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+struct DiagnosticSettings {
+    enabled: bool,
+    jit: bool,
+}
+
+impl Default for DiagnosticSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            jit: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct Settings {
+    diagnostics: DiagnosticSettings,
+    /// Lint rules by name; rules not listed are on
+    lint: BTreeMap<String, bool>,
+}
+
+impl Settings {
+    fn lint_enabled(&self, rule: &str) -> bool {
+        self.lint.get(rule).copied().unwrap_or(true)
+    }
+}
 
 struct LspState {
     documents: HashMap<String, String>,
+    root: Option<PathBuf>,
+    settings: Settings,
+    // The editor's `sfex` section, kept to merge over the settings file
+    editor_settings: JsonValue,
+    // Whether the editor answers workspace/configuration requests
+    configuration_requests: bool,
+    next_request_id: u64,
 }
 
 impl LspState {
     fn new() -> Self {
         Self {
             documents: HashMap::new(),
+            root: None,
+            settings: Settings::default(),
+            editor_settings: JsonValue::Null,
+            configuration_requests: false,
+            next_request_id: 0,
         }
     }
+
+    /// Reload the settings file and merge the editor's settings over it.
+    fn reload_settings(&mut self) -> Result<(), String> {
+        let mut merged = match &self.root {
+            Some(root) => read_settings_file(&root.join(SETTINGS_FILE))?,
+            None => JsonValue::Null,
+        };
+        merge_json(&mut merged, &self.editor_settings);
+        self.settings = if merged.is_null() {
+            Settings::default()
+        } else {
+            serde_json::from_value(merged).map_err(|e| format!("Invalid sfex settings: {}", e))?
+        };
+        Ok(())
+    }
 }
 
 pub fn run() -> io::Result<()> {
@@ -59,11 +144,29 @@ fn handle_message(
     match method {
         Some("initialize") => {
             let id = message.get("id").cloned().unwrap_or(JsonValue::Null);
+            if let Some(params) = message.get("params") {
+                state.configuration_requests = params
+                    .pointer("/capabilities/workspace/configuration")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                state.root = params
+                    .get("rootUri")
+                    .and_then(|v| v.as_str())
+                    .and_then(|uri| uri.strip_prefix("file://"))
+                    .map(PathBuf::from);
+            }
+            if let Err(e) = state.reload_settings() {
+                show_warning(writer, &e)?;
+            }
+
             let result = json!({
                 "capabilities": {
                     "textDocumentSync": {
                         "openClose": true,
                         "change": 1
+                    },
+                    "completionProvider": {
+                        "triggerCharacters": ["[", "\""]
                     }
                 },
                 "serverInfo": {
@@ -73,6 +176,28 @@ fn handle_message(
             });
             write_response(writer, id, result)?;
         }
+        Some("initialized") if state.configuration_requests => {
+            request_configuration(writer, state)?;
+        }
+        Some("workspace/didChangeConfiguration") => {
+            // Editors that can be asked send an empty notification
+            if state.configuration_requests {
+                request_configuration(writer, state)?;
+            } else {
+                state.editor_settings = message
+                    .pointer(&format!("/params/settings/{}", CONFIGURATION_SECTION))
+                    .cloned()
+                    .unwrap_or(JsonValue::Null);
+                apply_settings(writer, state)?;
+            }
+        }
+        None if message.get("id").is_some() => {
+            // The editor's answer to our workspace/configuration request
+            if let Some(section) = message.pointer("/result/0") {
+                state.editor_settings = section.clone();
+                apply_settings(writer, state)?;
+            }
+        }
         Some("shutdown") => {
             let id = message.get("id").cloned().unwrap_or(JsonValue::Null);
             write_response(writer, id, JsonValue::Null)?;
@@ -89,10 +214,31 @@ fn handle_message(
                         .and_then(|v| v.as_str()),
                 ) {
                     state.documents.insert(uri.to_string(), text.to_string());
-                    publish_diagnostics(writer, uri, text)?;
+                    publish_diagnostics(writer, uri, text, &state.settings)?;
                 }
             }
         }
+        Some("textDocument/completion") => {
+            let id = message.get("id").cloned().unwrap_or(JsonValue::Null);
+            let params = message.get("params");
+            let uri = params
+                .and_then(|p| p.pointer("/textDocument/uri"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let position = |key: &str| {
+                params
+                    .and_then(|p| p.pointer(&format!("/position/{}", key)))
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(0) as usize
+            };
+            let items = match state.documents.get(uri) {
+                Some(text) if is_manifest(uri) => {
+                    manifest_completions(text, position("line"), position("character"))
+                }
+                _ => Vec::new(),
+            };
+            write_response(writer, id, JsonValue::Array(items))?;
+        }
         Some("textDocument/didChange") => {
             if let Some(params) = message.get("params") {
                 let uri = params
@@ -104,7 +250,7 @@ fn handle_message(
                     if let Some(last) = changes.last() {
                         if let Some(text) = last.get("text").and_then(|v| v.as_str()) {
                             state.documents.insert(uri.to_string(), text.to_string());
-                            publish_diagnostics(writer, uri, text)?;
+                            publish_diagnostics(writer, uri, text, &state.settings)?;
                         }
                     }
                 }
@@ -163,8 +309,84 @@ fn write_response(writer: &mut impl Write, id: JsonValue, result: JsonValue) ->
     write_message(writer, &response)
 }
 
-fn publish_diagnostics(writer: &mut impl Write, uri: &str, text: &str) -> io::Result<()> {
-    let diagnostics = build_diagnostics(text);
+/// The settings file as JSON, or null when there is none.
+fn read_settings_file(path: &Path) -> Result<JsonValue, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(JsonValue::Null),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Merge `overrides` into `base`, table by table.
+fn merge_json(base: &mut JsonValue, overrides: &JsonValue) {
+    match (base, overrides) {
+        (JsonValue::Object(base), JsonValue::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_json(base.entry(key.clone()).or_insert(JsonValue::Null), value);
+            }
+        }
+        (_, JsonValue::Null) => {}
+        (base, overrides) => *base = overrides.clone(),
+    }
+}
+
+fn request_configuration(writer: &mut impl Write, state: &mut LspState) -> io::Result<()> {
+    state.next_request_id += 1;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": format!("configuration-{}", state.next_request_id),
+        "method": "workspace/configuration",
+        "params": {
+            "items": [{ "section": CONFIGURATION_SECTION }]
+        }
+    });
+    write_message(writer, &request)
+}
+
+/// Use new settings and refresh the diagnostics of every open document.
+fn apply_settings(writer: &mut impl Write, state: &mut LspState) -> io::Result<()> {
+    if let Err(e) = state.reload_settings() {
+        show_warning(writer, &e)?;
+    }
+    for (uri, text) in &state.documents {
+        publish_diagnostics(writer, uri, text, &state.settings)?;
+    }
+    Ok(())
+}
+
+fn show_warning(writer: &mut impl Write, message: &str) -> io::Result<()> {
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "window/showMessage",
+        "params": { "type": 2, "message": message }
+    });
+    write_message(writer, &notification)
+}
+
+fn publish_diagnostics(
+    writer: &mut impl Write,
+    uri: &str,
+    text: &str,
+    settings: &Settings,
+) -> io::Result<()> {
+    // Unsaved or non-file documents fall back to the default edition
+    let edition = uri
+        .strip_prefix("file://")
+        .and_then(|path| project::edition_for(Path::new(path)).ok())
+        .unwrap_or_default();
+    let diagnostics = if !settings.diagnostics.enabled {
+        Vec::new()
+    } else if is_manifest(uri) {
+        let root = uri
+            .strip_prefix("file://")
+            .and_then(|path| Path::new(path).parent());
+        manifest_diagnostics(text, root)
+    } else {
+        build_diagnostics(text, edition, settings)
+    };
+    let diagnostics = to_utf16_ranges(text, diagnostics);
     let notification = json!({
         "jsonrpc": "2.0",
         "method": "textDocument/publishDiagnostics",
@@ -176,25 +398,261 @@ fn publish_diagnostics(writer: &mut impl Write, uri: &str, text: &str) -> io::Re
     write_message(writer, &notification)
 }
 
-fn build_diagnostics(text: &str) -> Vec<JsonValue> {
-    let mut lexer = Lexer::new(text);
-    let tokens = match lexer.tokenize() {
-        Ok(tokens) => tokens,
-        Err(err) => {
-            return vec![make_diagnostic(err.to_string(), err.line, err.column)];
+fn build_diagnostics(text: &str, edition: Edition, settings: &Settings) -> Vec<JsonValue> {
+    let mut parser = Parser::from_lexer(Lexer::with_edition(text, edition));
+    let (program, errors) = parser.parse_all();
+    let mut diagnostics: Vec<JsonValue> = errors
+        .iter()
+        .map(|err| {
+            let (line, column) = err.location();
+            make_diagnostic(err.to_string(), line, column, SEVERITY_ERROR)
+        })
+        .collect();
+
+    // The rest of the checks run on what did parse; a concept lost to a
+    // syntax error would show up as undefined wherever it is used
+    if errors.is_empty() && settings.lint_enabled("undefined-concept") {
+        diagnostics.extend(undefined_concepts(&program));
+    }
+    diagnostics.extend(
+        program
+            .when_gaps()
+            .into_iter()
+            .filter(|gap| settings.lint_enabled(gap.rule()))
+            .map(|gap| {
+                make_diagnostic(
+                    format!("{} ({})", gap.message(), gap.rule()),
+                    gap.line(),
+                    1,
+                    SEVERITY_WARNING,
+                )
+            }),
+    );
+    if settings.lint_enabled(ShadowedField::RULE) {
+        diagnostics.extend(program.shadowed_fields().into_iter().map(|shadowed| {
+            make_diagnostic(
+                format!("{} ({})", shadowed.message(), ShadowedField::RULE),
+                shadowed.line,
+                1,
+                SEVERITY_WARNING,
+            )
+        }));
+    }
+    if settings.diagnostics.jit {
+        diagnostics.extend(jit_notes(&program));
+    }
+    diagnostics
+}
+
+fn is_manifest(uri: &str) -> bool {
+    uri.rsplit('/').next() == Some("sfex.toml")
+}
+
+fn manifest_diagnostics(text: &str, root: Option<&Path>) -> Vec<JsonValue> {
+    project::check_manifest(text, root)
+        .into_iter()
+        .map(|issue| {
+            let severity = if issue.warning {
+                SEVERITY_WARNING
+            } else {
+                SEVERITY_ERROR
+            };
+            make_diagnostic(issue.message, issue.line, issue.column, severity)
+        })
+        .collect()
+}
+
+/// Section names after `[`, the keys of the current section, and editions
+/// inside `edition = "`.
+fn manifest_completions(text: &str, line: usize, character: usize) -> Vec<JsonValue> {
+    let lines: Vec<&str> = text.lines().collect();
+    let current = lines.get(line).copied().unwrap_or("");
+    // `character` counts UTF-16 code units
+    let mut units = 0;
+    let prefix: String = current
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .collect();
+    let prefix = prefix.trim_start();
+    let section = lines[..line.min(lines.len())]
+        .iter()
+        .rev()
+        .find_map(|l| {
+            l.trim()
+                .strip_prefix('[')
+                .and_then(|rest| rest.split(']').next())
+        })
+        .unwrap_or("");
+
+    let item = |label: &str, kind: u8, detail: &str| json!({ "label": label, "kind": kind, "detail": detail });
+    if prefix.starts_with('[') {
+        return project::MANIFEST_SECTIONS
+            .iter()
+            .map(|(name, _)| item(name, COMPLETION_MODULE, "sfex.toml section"))
+            .collect();
+    }
+    if section == "package" && prefix.starts_with("edition") && prefix.contains('"') {
+        return Edition::ALL
+            .iter()
+            .map(|edition| item(edition.as_str(), COMPLETION_VALUE, "language edition"))
+            .collect();
+    }
+    if prefix.contains('=') {
+        // Inside `name = { ... }` of a dependency
+        return if section == "dependencies" && prefix.contains('{') {
+            project::DEPENDENCY_KEYS
+                .iter()
+                .map(|key| item(key, COMPLETION_PROPERTY, "dependency source"))
+                .collect()
+        } else {
+            Vec::new()
+        };
+    }
+    project::MANIFEST_SECTIONS
+        .iter()
+        .find(|(name, _)| *name == section)
+        .map(|(_, keys)| {
+            keys.iter()
+                .map(|key| item(key, COMPLETION_PROPERTY, section))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `Create X` for a concept this file doesn't define. Files with `Use` may
+/// get it from a module, so they are skipped.
+fn undefined_concepts(program: &Program) -> Vec<JsonValue> {
+    let uses_modules = program
+        .story
+        .body
+        .iter()
+        .any(|stmt| matches!(stmt, Statement::Use { .. }));
+    if uses_modules {
+        return Vec::new();
+    }
+
+    let mut bodies: Vec<&[Statement]> = vec![&program.story.body];
+    for concept in &program.concepts {
+        bodies.extend(concept.methods.iter().map(|m| m.body.as_slice()));
+        bodies.extend(concept.when_observers.values().map(Vec::as_slice));
+        bodies.push(&concept.when_created);
+        bodies.push(&concept.when_destroyed);
+    }
+    for situation in &program.situations {
+        for adjustment in &situation.adjustments {
+            bodies.extend(adjustment.methods.iter().map(|m| m.body.as_slice()));
         }
-    };
+    }
+
+    let mut created = Vec::new();
+    for body in bodies {
+        collect_creates(body, &mut created);
+    }
+    created
+        .into_iter()
+        .filter(|(name, _)| !program.concepts.iter().any(|c| c.name == *name))
+        .map(|(name, line)| {
+            make_diagnostic(
+                format!("Concept '{}' is not defined (undefined-concept)", name),
+                line,
+                1,
+                SEVERITY_WARNING,
+            )
+        })
+        .collect()
+}
 
-    let mut parser = Parser::new(tokens);
-    if let Err(err) = parser.parse() {
-        let (line, column) = err.location();
-        return vec![make_diagnostic(err.to_string(), line, column)];
+fn collect_creates(statements: &[Statement], found: &mut Vec<(String, usize)>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Create {
+                concept_name, line, ..
+            } => found.push((concept_name.clone(), *line)),
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                collect_creates(then_body, found);
+                collect_creates(else_body.as_deref().unwrap_or_default(), found);
+            }
+            Statement::When {
+                cases, otherwise, ..
+            } => {
+                for case in cases {
+                    collect_creates(&case.body, found);
+                }
+                collect_creates(otherwise.as_deref().unwrap_or_default(), found);
+            }
+            Statement::TryCatch {
+                try_body,
+                catches,
+                always_body,
+                ..
+            } => {
+                collect_creates(try_body, found);
+                for catch in catches {
+                    collect_creates(&catch.body, found);
+                }
+                collect_creates(always_body.as_deref().unwrap_or_default(), found);
+            }
+            Statement::RepeatTimes { body, .. }
+            | Statement::RepeatWhile { body, .. }
+            | Statement::ForEach { body, .. }
+            | Statement::WithSituation { body, .. }
+            | Statement::Batch { body, .. }
+            | Statement::Using { body, .. } => collect_creates(body, found),
+            _ => {}
+        }
     }
+}
 
-    Vec::new()
+/// A note on each method that will always run in the interpreter.
+fn jit_notes(program: &Program) -> Vec<JsonValue> {
+    let mut notes = Vec::new();
+    for concept in &program.concepts {
+        for (method, reason) in JitCompiler::unsupported_methods(concept) {
+            let Some(first) = method.body.first() else {
+                continue;
+            };
+            // Reasons end with a dump of the statement, which is too long here
+            let reason = reason.split(": ").next().unwrap_or(&reason);
+            notes.push(make_diagnostic(
+                format!(
+                    "{}.{} is not JIT-compiled: {}",
+                    concept.name, method.name, reason
+                ),
+                Interpreter::get_statement_line(first),
+                1,
+                SEVERITY_INFORMATION,
+            ));
+        }
+    }
+    notes
 }
 
-fn make_diagnostic(message: String, line: usize, column: usize) -> JsonValue {
+/// Diagnostics are made with character columns, but LSP positions count
+/// UTF-16 code units, in which an emoji before the problem takes two.
+fn to_utf16_ranges(text: &str, mut diagnostics: Vec<JsonValue>) -> Vec<JsonValue> {
+    let lines: Vec<&str> = text.lines().collect();
+    for diagnostic in &mut diagnostics {
+        for end in ["start", "end"] {
+            let position = &mut diagnostic["range"][end];
+            let line = position["line"].as_u64().unwrap_or(0) as usize;
+            let character = position["character"].as_u64().unwrap_or(0) as usize;
+            let text = lines.get(line).copied().unwrap_or("");
+            let units: usize = text.chars().take(character).map(char::len_utf16).sum();
+            let past_end = character.saturating_sub(text.chars().count());
+            position["character"] = json!(units + past_end);
+        }
+    }
+    diagnostics
+}
+
+fn make_diagnostic(message: String, line: usize, column: usize, severity: u8) -> JsonValue {
     let line_idx = line.saturating_sub(1);
     let col_idx = column.saturating_sub(1);
     json!({
@@ -202,8 +660,102 @@ fn make_diagnostic(message: String, line: usize, column: usize) -> JsonValue {
             "start": { "line": line_idx, "character": col_idx },
             "end": { "line": line_idx, "character": col_idx.saturating_add(1) }
         },
-        "severity": 1,
+        "severity": severity,
         "source": "sfx",
         "message": message
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_and_lints() {
+        let mut settings = read_settings_file(Path::new("missing/settings.toml")).unwrap();
+        let file: JsonValue =
+            toml::from_str("[diagnostics]\njit = true\n[lint]\nundefined-concept = false\n")
+                .unwrap();
+        merge_json(&mut settings, &file);
+        merge_json(
+            &mut settings,
+            &json!({ "lint": { "undefined-concept": true } }),
+        );
+        let settings: Settings = serde_json::from_value(settings).unwrap();
+        assert!(settings.diagnostics.enabled && settings.diagnostics.jit);
+        assert!(settings.lint_enabled("undefined-concept"));
+
+        let source = "Concept: Counter\n    Count\n\n    To Add:\n        Print This.Count\n\nStory:\n    If True:\n        Create Missing Called M\n";
+        let diagnostics = build_diagnostics(source, Edition::default(), &settings);
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .filter_map(|d| d["message"].as_str())
+            .collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].starts_with("Concept 'Missing' is not defined"));
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 8);
+        assert!(messages[1].starts_with("Counter.Add is not JIT-compiled"));
+
+        let quiet = Settings {
+            lint: BTreeMap::from([("undefined-concept".to_string(), false)]),
+            ..Settings::default()
+        };
+        assert!(build_diagnostics(source, Edition::default(), &quiet).is_empty());
+
+        // The emoji is one character but two UTF-16 code units
+        let source = "Story:\n    X is \"😀\" $\n";
+        let diagnostics = to_utf16_ranges(
+            source,
+            build_diagnostics(source, Edition::default(), &settings),
+        );
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 14);
+        assert_eq!(diagnostics[0]["range"]["end"]["character"], 15);
+    }
+
+    #[test]
+    fn test_every_syntax_error() {
+        let source = "Concept: Counter\n    Count\n\n    To Add\n        Print 1\n\nStory:\n    X is 1 +\n    If X > 1\n        Print \"big\"\n    Else:\n        Print \"small\"\n    Print (3\n    Print \"fine\"\n";
+        let diagnostics = build_diagnostics(source, Edition::default(), &Settings::default());
+        let lines: Vec<&JsonValue> = diagnostics
+            .iter()
+            .map(|d| &d["range"]["start"]["line"])
+            .collect();
+        assert_eq!(lines, [3, 7, 8, 12], "{:?}", diagnostics);
+
+        // What did parse is still there
+        let (program, errors) =
+            Parser::from_lexer(Lexer::with_edition(source, Edition::default())).parse_all();
+        assert_eq!(errors.len(), 4);
+        assert!(program.concepts.is_empty());
+        assert_eq!(program.story.body.len(), 1);
+    }
+
+    #[test]
+    fn test_manifest_completions() {
+        let labels = |text: &str, line: usize, character: usize| -> Vec<String> {
+            manifest_completions(text, line, character)
+                .iter()
+                .map(|item| item["label"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let text = "[package]
+edition = \"\n[\n\n[dependencies]\nutils = { \n";
+        assert_eq!(labels(text, 1, 11), vec!["2025", "2026"]);
+        assert_eq!(labels(text, 1, 0), vec!["name", "version", "edition"]);
+        assert_eq!(
+            labels(text, 2, 1),
+            vec![
+                "package",
+                "dependencies",
+                "serve",
+                "observers",
+                "llm",
+                "tasks",
+                "workspace",
+                "config"
+            ]
+        );
+        assert_eq!(labels(text, 5, 10), vec!["path", "git"]);
+        assert!(labels(text, 6, 0).is_empty());
+    }
+}
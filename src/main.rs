@@ -1,9 +1,35 @@
-use clap::{Parser, Subcommand};
-use sfex_lang::stdlib::web;
-use sfex_lang::{Interpreter, Lexer, Parser as SFXParser, project};
+use clap::{Args, Parser, Subcommand};
+use sfex_lang::compiler::ast::{Program, ShadowedField};
+use sfex_lang::compiler::cache;
+use sfex_lang::compiler::edition::{Edition, copy_assignments, rename_identifiers};
+use sfex_lang::compiler::lexer::{LexerErrorKind, indent_width};
+use sfex_lang::diagnostics::{self, Diagnostic, Level};
+use sfex_lang::doc;
+use sfex_lang::runtime::config::{self, RuntimeConfig};
+use sfex_lang::runtime::permissions::{self, Kind, Permissions};
+use sfex_lang::runtime::{budget, executor, limits, memory, timeline};
+use sfex_lang::scaffold;
+#[cfg(feature = "tls")]
+use sfex_lang::stdlib::acme::AcmeConfig;
+use sfex_lang::stdlib::log;
+use sfex_lang::stdlib::watch::{self, Watcher};
+#[cfg(feature = "web")]
+use sfex_lang::stdlib::{page, web};
+use sfex_lang::{
+    Interpreter, Lexer, Parser as SFXParser, Token, TokenType, Warning, WarningReporter, literate,
+    project,
+};
+use std::collections::HashSet;
 use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Counts each thread's allocations, for handler memory limits
+#[global_allocator]
+static ALLOCATOR: limits::CountingAllocator = limits::CountingAllocator;
 
 #[derive(Parser)]
 #[command(name = "sfex")]
@@ -13,19 +39,94 @@ use std::process;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    // The global options are listed after each command's own ones
+    /// Async worker threads shared by scripts, tasks and the web server
+    /// (default: one per CPU core)
+    #[arg(long, global = true, value_name = "N", display_order = 1000)]
+    workers: Option<usize>,
+    /// Run every method in the interpreter, never compiling hot ones
+    #[arg(long, global = true, display_order = 1001)]
+    no_jit: bool,
+    /// Log level scripts read from Runtime.Config.LogLevel, and the Log
+    /// module's starting level (error, warn, info, debug or trace; default info)
+    #[arg(long, global = true, value_name = "LEVEL", display_order = 1002)]
+    log_level: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Run a script
     Run {
         file: PathBuf,
+        /// On exit, list values that are still strongly referenced
+        #[arg(long)]
+        report_leaks: bool,
+        /// Leave out the "Running SFX script" banner
+        #[arg(long)]
+        quiet: bool,
+        /// Compare the script's output with a golden file and report any difference
+        #[arg(long, value_name = "FILE", conflicts_with = "report_leaks")]
+        expect: Option<PathBuf>,
+        /// Run the ```sfex blocks of a Markdown file in order, in one interpreter
+        #[arg(long)]
+        literate: bool,
+        /// Write the Markdown with each block's output after it (with --literate)
+        #[arg(
+            long,
+            value_name = "FILE",
+            requires = "literate",
+            conflicts_with = "expect"
+        )]
+        emit: Option<PathBuf>,
+        /// Run every call of a JIT-compiled method in the interpreter too,
+        /// and report each method's speedup and any results that differ
+        #[arg(long, conflicts_with_all = ["expect", "literate"])]
+        compare_jit: bool,
+        /// Relative difference allowed between the two results (with --compare-jit)
+        #[arg(
+            long,
+            value_name = "F",
+            requires = "compare_jit",
+            default_value_t = sfex_lang::jit::compare::DEFAULT_TOLERANCE
+        )]
+        jit_tolerance: f64,
+        /// Run again whenever a script in the file's directory changes
+        #[arg(long, conflicts_with_all = ["expect", "literate"])]
+        watch: bool,
+        /// Print warnings, such as a `Field is` that makes a variable
+        /// instead of setting the field, to stderr as they happen
+        #[arg(long, conflicts_with_all = ["expect", "literate"])]
+        warnings: bool,
+        #[command(flatten)]
+        permissions: PermissionArgs,
     },
     Lex {
-        file: PathBuf,
+        /// Script to tokenize (with --interactive, lines to start from)
+        #[arg(required_unless_present = "interactive")]
+        file: Option<PathBuf>,
+        /// Tokenize lines as they are typed, showing the INDENT and DEDENT
+        /// each one produces after the lines before it
+        #[arg(short, long)]
+        interactive: bool,
     },
     Debug {
         file: PathBuf,
+        /// Print the recorded assignment history of a variable (repeatable)
+        #[arg(long, value_name = "NAME")]
+        history: Vec<String>,
+        /// Print every recorded assignment after the run
+        #[arg(long)]
+        timeline: bool,
+        /// Print the live instances of a concept after the run (repeatable)
+        #[arg(long, value_name = "CONCEPT")]
+        instances: Vec<String>,
+        /// Print how many runs, statements and how much time each When
+        /// observer took
+        #[arg(long)]
+        observers: bool,
     },
+    #[cfg(feature = "web")]
+    /// Serve the routes a script sets up over HTTP
     Serve {
         file: PathBuf,
         #[arg(short, long, default_value = "127.0.0.1:8000")]
@@ -36,55 +137,418 @@ enum Commands {
         tls_cert: Option<PathBuf>,
         #[arg(long)]
         tls_key: Option<PathBuf>,
+        /// Get and renew a Let's Encrypt certificate for this domain (repeatable)
+        #[cfg(feature = "tls")]
+        #[arg(long, value_name = "DOMAIN")]
+        acme_domain: Vec<String>,
+        /// Contact address for the ACME account
+        #[cfg(feature = "tls")]
+        #[arg(long, value_name = "EMAIL")]
+        acme_email: Option<String>,
+        /// Where the ACME account and certificates are kept
+        #[cfg(feature = "tls")]
+        #[arg(long, value_name = "DIR", default_value = ".sfex/acme")]
+        acme_cache: PathBuf,
+        /// Use the Let's Encrypt staging CA (untrusted certificates, higher rate limits)
+        #[cfg(feature = "tls")]
+        #[arg(long)]
+        acme_staging: bool,
+        /// Plain HTTP address answering ACME challenges
+        #[cfg(feature = "tls")]
+        #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:80")]
+        acme_http_addr: String,
+        /// Re-run router definition scripts when a script in their directory changes
+        #[arg(long)]
+        watch: bool,
+        /// Print an access log line per request (json or common)
+        #[arg(long, value_parser = ["json", "common"])]
+        log_format: Option<String>,
+        /// Expose Prometheus metrics at /metrics
+        #[arg(long)]
+        metrics: bool,
+        /// Serve App.State, routes and recent handler errors at /__sfex/inspect
+        #[arg(long)]
+        dev: bool,
+        /// Connections served at once; more wait to be accepted
+        #[arg(long, value_name = "N")]
+        max_connections: Option<usize>,
+        /// Largest request head in bytes (at least 8192)
+        #[arg(long, value_name = "BYTES")]
+        max_header_size: Option<usize>,
+        /// Largest request body in bytes; bigger ones get 413
+        #[arg(long, value_name = "BYTES", default_value_t = 10 * 1024 * 1024)]
+        max_body_size: usize,
+        /// Seconds to wait for data from a client, idle keep-alive included
+        #[arg(long, value_name = "SECS")]
+        read_timeout: Option<f64>,
+        /// Seconds to wait for a client to accept response data
+        #[arg(long, value_name = "SECS")]
+        write_timeout: Option<f64>,
+        /// Seconds a handler may run before the client gets a 503
+        #[arg(long, value_name = "SECS")]
+        request_timeout: Option<f64>,
+        /// Statements a handler may run before it fails with a 500
+        #[arg(long, value_name = "N")]
+        max_steps: Option<u64>,
+        /// Bytes a handler may allocate before it fails with a 500
+        #[arg(long, value_name = "BYTES")]
+        max_memory: Option<usize>,
+        /// Reuse HTTP/1.1 connections between requests
+        #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        keep_alive: bool,
+        /// Offer HTTP/2 (ALPN with TLS, prior-knowledge h2c without)
+        #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        http2: bool,
+        #[command(flatten)]
+        permissions: PermissionArgs,
     },
     New {
         name: String,
+        /// Start from a ready-to-run project: web, api, cli or worker
+        #[arg(long, value_parser = ["web", "api", "cli", "worker"])]
+        template: Option<String>,
     },
     Install,
+    /// Upgrade the current project to a newer language edition, renaming
+    /// identifiers that the new edition reserves as keywords
+    Migrate {
+        /// Edition to move to (default: the latest)
+        #[arg(long, value_name = "EDITION")]
+        to: Option<String>,
+        /// Only list what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print a script's syntax tree, for tools that read SFX programs
+    Parse {
+        file: PathBuf,
+        /// json, or bincode for a compact binary form of the same tree
+        #[arg(long, value_parser = ["json", "bincode"], default_value = "json")]
+        format: String,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write a reference for the current project: its concepts and
+    /// situations with their `##` comments, and the standard library
+    Doc {
+        /// markdown, or html for a single page
+        #[arg(long, value_parser = ["markdown", "html"], default_value = "markdown")]
+        format: String,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Leave out the standard library
+        #[arg(long)]
+        no_stdlib: bool,
+    },
+    /// Check sfex.toml and the syntax of every script in the current project,
+    /// and in each member of a workspace
+    Check {
+        /// How to print problems: text, or json / sarif for CI and code review tools
+        #[arg(long, value_parser = ["text", "json", "sarif"], default_value = "text")]
+        diagnostics_format: String,
+    },
+    /// Parse every script in the current project and the modules they use
+    /// into .sfex/precompiled.bin, which `sfex serve` loads at startup
+    Precompile,
+    /// Run a task from the [tasks] section of sfex.toml in the project root,
+    /// or list them
+    Task {
+        name: Option<String>,
+        /// Added after the task's own arguments
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    #[cfg(feature = "lsp")]
     Lsp,
     Version,
+    /// `sfex <task>`, for a task not named like a command
+    #[command(external_subcommand)]
+    External(Vec<String>),
+}
+
+// What the script may reach. Everything is allowed unless a flag limits it;
+// `--sandbox` starts from nothing. A flag without a list allows all of its
+// kind.
+#[derive(Args)]
+struct PermissionArgs {
+    /// Allow nothing that --allow-* doesn't grant
+    #[arg(long)]
+    sandbox: bool,
+    /// Files and directories the script may read (comma-separated)
+    #[arg(long, value_name = "PATHS", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    allow_read: Option<Vec<String>>,
+    /// Read no files
+    #[arg(long, conflicts_with = "allow_read")]
+    deny_read: bool,
+    /// Files and directories the script may write (comma-separated)
+    #[arg(long, value_name = "PATHS", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    allow_write: Option<Vec<String>>,
+    /// Write no files
+    #[arg(long, conflicts_with = "allow_write")]
+    deny_write: bool,
+    /// Hosts the script may connect to or listen on: host, host:port or *.domain
+    #[arg(long, value_name = "HOSTS", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    allow_net: Option<Vec<String>>,
+    /// Open no network connections and listen on no ports
+    #[arg(long, conflicts_with = "allow_net")]
+    deny_net: bool,
+    /// Environment variables the script may read and set
+    #[arg(long, value_name = "NAMES", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    allow_env: Option<Vec<String>>,
+    /// Read and set no environment variables
+    #[arg(long, conflicts_with = "allow_env")]
+    deny_env: bool,
+    /// Programs the script may start; only a bare --allow-run allows the shell
+    #[arg(long, value_name = "PROGRAMS", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    allow_run: Option<Vec<String>>,
+    /// Start no programs
+    #[arg(long, conflicts_with = "allow_run")]
+    deny_run: bool,
+}
+
+impl PermissionArgs {
+    /// The permissions the flags ask for, or None when there are none.
+    fn permissions(&self) -> Option<Permissions> {
+        let flags = [
+            (Kind::Read, &self.allow_read, self.deny_read),
+            (Kind::Write, &self.allow_write, self.deny_write),
+            (Kind::Net, &self.allow_net, self.deny_net),
+            (Kind::Env, &self.allow_env, self.deny_env),
+            (Kind::Run, &self.allow_run, self.deny_run),
+        ];
+        if !self.sandbox
+            && flags
+                .iter()
+                .all(|(_, allow, deny)| allow.is_none() && !deny)
+        {
+            return None;
+        }
+        let mut permissions = if self.sandbox {
+            Permissions::none()
+        } else {
+            Permissions::default()
+        };
+        for (kind, allow, deny) in flags {
+            if deny {
+                permissions.deny(kind);
+            } else if let Some(items) = allow {
+                permissions.allow(kind, items);
+            }
+        }
+        Some(permissions)
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    // SFEX_* environment variables, overridden by flags
+    let runtime_config = RuntimeConfig::from_env().and_then(|mut runtime_config| {
+        if cli.no_jit {
+            runtime_config.jit = false;
+        }
+        if let Some(workers) = cli.workers {
+            runtime_config.workers = Some(workers);
+        }
+        if let Some(level) = &cli.log_level {
+            runtime_config.set_log_level(level)?;
+        }
+        Ok(runtime_config)
+    });
+    let runtime_config = runtime_config.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    if let Some(workers) = runtime_config.workers
+        && let Err(e) = executor::configure_workers(workers)
+    {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    config::set(runtime_config);
+
+    // Every interpreter the command makes gets the project's observer budget
+    // and LLM provider
+    let script = match &cli.command {
+        Commands::Run { file, .. } | Commands::Debug { file, .. } => Some(file),
+        #[cfg(feature = "web")]
+        Commands::Serve { file, .. } => Some(file),
+        _ => None,
+    };
+    if let Some(script) = script {
+        let configured = project::observer_budget_for(script).map(budget::set_default);
+        #[cfg(feature = "llm")]
+        let configured = configured.and_then(|()| {
+            project::llm_config_for(script).map(sfex_lang::stdlib::llm::set_default)
+        });
+        if let Err(e) = configured {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    // --allow-* and --deny-* hold for every script the process runs
+    let flags = match &cli.command {
+        Commands::Run { permissions, .. } => Some(permissions),
+        #[cfg(feature = "web")]
+        Commands::Serve { permissions, .. } => Some(permissions),
+        _ => None,
+    };
+    if let Some(permissions) = flags.and_then(PermissionArgs::permissions) {
+        permissions::set_default(permissions);
+    }
+
     match cli.command {
-        Commands::Run { file } => {
-            if run_script(&file).is_err() {
+        Commands::Run {
+            file,
+            report_leaks,
+            quiet,
+            expect,
+            literate,
+            emit,
+            compare_jit,
+            jit_tolerance,
+            watch,
+            warnings,
+            permissions: _,
+        } => {
+            let compare_jit = compare_jit.then_some(jit_tolerance);
+            let result = match expect {
+                Some(expected) => expect_output(&file, &expected, literate),
+                None if literate => run_literate(&file, quiet, emit.as_deref()),
+                None if watch => watch_script(&file, report_leaks, quiet, compare_jit, warnings),
+                None => run_script(&file, report_leaks, quiet, compare_jit, warnings),
+            };
+            if result.is_err() {
                 process::exit(1);
             }
         }
-        Commands::Lex { file } => {
-            if lex_script(&file).is_err() {
+        Commands::Lex { file, interactive } => {
+            let result = match file {
+                Some(file) if !interactive => lex_script(&file),
+                file => lex_interactive(file.as_deref()),
+            };
+            if result.is_err() {
                 process::exit(1);
             }
         }
-        Commands::Debug { file } => {
-            if debug_script(&file).is_err() {
+        Commands::Debug {
+            file,
+            history,
+            timeline,
+            instances,
+            observers,
+        } => {
+            if debug_script(&file, &history, timeline, &instances, observers).is_err() {
                 process::exit(1);
             }
         }
+        #[cfg(feature = "web")]
         Commands::Serve {
             file,
             addr,
             static_dir,
             tls_cert,
             tls_key,
+            #[cfg(feature = "tls")]
+            acme_domain,
+            #[cfg(feature = "tls")]
+            acme_email,
+            #[cfg(feature = "tls")]
+            acme_cache,
+            #[cfg(feature = "tls")]
+            acme_staging,
+            #[cfg(feature = "tls")]
+            acme_http_addr,
+            watch,
+            log_format,
+            metrics,
+            dev,
+            max_connections,
+            max_header_size,
+            max_body_size,
+            read_timeout,
+            write_timeout,
+            request_timeout,
+            max_steps,
+            max_memory,
+            keep_alive,
+            http2,
+            permissions: _,
         } => {
+            if let Err(e) = web::configure_telemetry(log_format.as_deref(), metrics) {
+                eprintln!("Serve error: {}", e);
+                process::exit(1);
+            }
+            if dev {
+                match web::configure_dev() {
+                    Ok(token) => println!("Inspect at /__sfex/inspect?token={}", token),
+                    Err(e) => {
+                        eprintln!("Serve error: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            let options = web::ServerOptions {
+                max_connections,
+                max_header_size,
+                max_body_size,
+                read_timeout: read_timeout.map(Duration::from_secs_f64),
+                write_timeout: write_timeout.map(Duration::from_secs_f64),
+                request_timeout: request_timeout.map(Duration::from_secs_f64),
+                max_steps,
+                max_memory,
+                keep_alive,
+                http2,
+            };
+            if let Err(e) = web::configure_server(options) {
+                eprintln!("Serve error: {}", e);
+                process::exit(1);
+            }
+            #[cfg(feature = "tls")]
+            if !acme_domain.is_empty() {
+                if tls_cert.is_some() || tls_key.is_some() {
+                    eprintln!(
+                        "Serve error: --acme-domain cannot be combined with --tls-cert/--tls-key"
+                    );
+                    process::exit(1);
+                }
+                let acme = AcmeConfig {
+                    domains: acme_domain,
+                    email: acme_email,
+                    cache_dir: acme_cache,
+                    staging: acme_staging,
+                    http_addr: acme_http_addr,
+                };
+                if let Err(e) = web::configure_acme(acme) {
+                    eprintln!("Serve error: {}", e);
+                    process::exit(1);
+                }
+            }
+            load_precompiled(&file);
+            let hooks = project::serve_hooks_for(&file)
+                .and_then(|(on_start, on_stop)| web::configure_serve_hooks(on_start, on_stop));
+            if let Err(e) = hooks {
+                eprintln!("Serve error: {}", e);
+                process::exit(1);
+            }
             if serve_script(
                 &file,
                 &addr,
                 static_dir.as_ref(),
                 tls_cert.as_ref(),
                 tls_key.as_ref(),
+                watch,
             )
             .is_err()
             {
                 process::exit(1);
             }
         }
-        Commands::New { name } => {
-            if new_project(&name).is_err() {
+        Commands::New { name, template } => {
+            if new_project(&name, template.as_deref()).is_err() {
                 process::exit(1);
             }
         }
@@ -93,6 +557,40 @@ fn main() {
                 process::exit(1);
             }
         }
+        Commands::Migrate { to, dry_run } => {
+            if migrate_project(to.as_deref(), dry_run).is_err() {
+                process::exit(1);
+            }
+        }
+        Commands::Parse {
+            file,
+            format,
+            output,
+        } => {
+            if parse_script(&file, &format, output.as_deref()).is_err() {
+                process::exit(1);
+            }
+        }
+        Commands::Doc {
+            format,
+            output,
+            no_stdlib,
+        } => {
+            if document_project(&format, output.as_deref(), no_stdlib).is_err() {
+                process::exit(1);
+            }
+        }
+        Commands::Check { diagnostics_format } => {
+            if check_project(&diagnostics_format).is_err() {
+                process::exit(1);
+            }
+        }
+        Commands::Precompile => {
+            if precompile_project().is_err() {
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "lsp")]
         Commands::Lsp => {
             if sfex_lang::lsp::run().is_err() {
                 process::exit(1);
@@ -101,18 +599,37 @@ fn main() {
         Commands::Version => {
             print_version_info();
         }
+        Commands::Task { name, args } => {
+            if run_task(name.as_deref(), &args, false).is_err() {
+                process::exit(1);
+            }
+        }
+        Commands::External(args) => {
+            if run_task(Some(&args[0]), &args[1..], true).is_err() {
+                process::exit(1);
+            }
+        }
     }
 }
 
-fn run_script(path: &PathBuf) -> Result<(), ()> {
-    println!("Running SFX script: {}", path.display());
-    println!();
+fn run_script(
+    path: &PathBuf,
+    report_leaks: bool,
+    quiet: bool,
+    compare_jit: Option<f64>,
+    warnings: bool,
+) -> Result<(), ()> {
+    if !quiet {
+        println!("Running SFX script: {}", path.display());
+        println!();
+    }
 
     let source = fs::read_to_string(path).map_err(|e| {
         eprintln!("Error reading file: {}", e);
     })?;
 
-    let mut lexer = Lexer::new(&source);
+    let edition = script_edition(path)?;
+    let mut lexer = Lexer::with_edition(&source, edition);
     let tokens = lexer.tokenize().map_err(|e| {
         eprintln!("Lexer error: {}", e);
     })?;
@@ -121,17 +638,263 @@ fn run_script(path: &PathBuf) -> Result<(), ()> {
     //     println!("{:?}", token.token_type);
     // }
 
-    let mut parser = SFXParser::new(tokens);
-    let program = parser.parse().map_err(|e| {
-        eprintln!("Parser error: {}", e);
-    })?;
+    let mut parser = SFXParser::with_edition(tokens, edition);
+    let (program, errors) = parser.parse_all();
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("Parser error: {}", e);
+        }
+        return Err(());
+    }
 
     let mut interpreter = Interpreter::new();
-    interpreter.run(program).map_err(|e| {
+    if let Some(tolerance) = compare_jit {
+        interpreter.compare_jit(tolerance);
+    }
+    if warnings {
+        interpreter.set_warning_reporter(Arc::new(PrintWarnings(path.display().to_string())));
+    }
+    let mut result = interpreter.run(program).map_err(|e| {
         eprintln!("Runtime error: {}", e);
+        log::write_to_file(
+            log::Level::Error,
+            "Runtime error",
+            &[("error", &e.to_string())],
+        );
+    });
+
+    if report_leaks {
+        print_leak_report(&interpreter.memory_report());
+    }
+    if let Some(comparison) = interpreter.jit_comparison() {
+        println!();
+        print_jit_comparison(comparison);
+        if comparison.diverged() {
+            result = Err(());
+        }
+    }
+
+    result
+}
+
+/// `sfex run --warnings`: each warning on stderr, as compilers print them.
+struct PrintWarnings(String);
+
+impl WarningReporter for PrintWarnings {
+    fn warn(&self, warning: &Warning) {
+        eprintln!(
+            "{}:{}: warning: {} ({})",
+            self.0, warning.line, warning.message, warning.rule
+        );
+    }
+}
+
+/// `sfex run --watch`: run the script, then again whenever a script next to
+/// it changes, until interrupted.
+fn watch_script(
+    path: &PathBuf,
+    report_leaks: bool,
+    quiet: bool,
+    compare_jit: Option<f64>,
+    warnings: bool,
+) -> Result<(), ()> {
+    let dir = watch::script_dir(path);
+    let mut watcher = Watcher::new(&dir).map_err(|e| {
+        eprintln!("Error: {}", e);
     })?;
 
-    Ok(())
+    loop {
+        // A failed run has been reported; the next change may fix it
+        let _ = run_script(path, report_leaks, quiet, compare_jit, warnings);
+        println!();
+        println!("Watching {} for changes (Ctrl+C to stop)", dir.display());
+        let changed = loop {
+            let changes = watcher.wait();
+            if let Some(change) = changes.into_iter().find(|c| watch::is_script(&c.path)) {
+                break change.path;
+            }
+        };
+        println!("{} changed, running again", changed.display());
+        println!();
+    }
+}
+
+/// Run the ```sfex blocks of a Markdown file one after another in a single
+/// interpreter, stopping at the first error. With `emit`, also write the
+/// Markdown with each block's output below it.
+fn run_literate(path: &Path, quiet: bool, emit: Option<&Path>) -> Result<(), ()> {
+    if !quiet {
+        println!("Running SFX literate script: {}", path.display());
+        println!();
+    }
+
+    let markdown = fs::read_to_string(path).map_err(|e| {
+        eprintln!("Error reading file: {}", e);
+    })?;
+    let blocks = literate::extract_blocks(&markdown).map_err(|e| {
+        eprintln!("Error in {}: {}", path.display(), e);
+    })?;
+    let edition = script_edition(path)?;
+
+    let mut interpreter = Interpreter::new();
+    if emit.is_some() {
+        interpreter.capture_output();
+    }
+    let mut outputs = Vec::new();
+    let mut result = Ok(());
+    for block in &blocks {
+        let run = run_block(&mut interpreter, &block.source(), edition);
+        let mut output = interpreter.take_output();
+        print!("{}", output);
+        if let Err(e) = run {
+            eprintln!("{}", e);
+            output.push_str(&e);
+            output.push('\n');
+            result = Err(());
+        }
+        outputs.push(output);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    if let Some(emit) = emit {
+        fs::write(emit, literate::annotate(&markdown, &blocks, &outputs)).map_err(|e| {
+            eprintln!("Error writing {}: {}", emit.display(), e);
+        })?;
+    }
+    result
+}
+
+fn run_block(interpreter: &mut Interpreter, source: &str, edition: Edition) -> Result<(), String> {
+    let tokens = Lexer::with_edition(source, edition)
+        .tokenize()
+        .map_err(|e| format!("Lexer error: {}", e))?;
+    let program = SFXParser::with_edition(tokens, edition)
+        .parse()
+        .map_err(|e| format!("Parser error: {}", e))?;
+    interpreter
+        .run(program)
+        .map_err(|e| format!("Runtime error: {}", e))
+}
+
+/// Run the script in a child process (so output from tasks and native
+/// functions is captured too) and diff its stdout against a golden file.
+fn expect_output(path: &Path, expected_path: &Path, literate: bool) -> Result<(), ()> {
+    let expected = fs::read_to_string(expected_path).map_err(|e| {
+        eprintln!(
+            "Error reading expected output {}: {}",
+            expected_path.display(),
+            e
+        );
+    })?;
+
+    let exe = std::env::current_exe().map_err(|e| {
+        eprintln!("Cannot locate the sfex executable: {}", e);
+    })?;
+    let output = process::Command::new(exe)
+        .arg("run")
+        .arg("--quiet")
+        .args(literate.then_some("--literate"))
+        .arg(path)
+        .output()
+        .map_err(|e| {
+            eprintln!("Failed to run {}: {}", path.display(), e);
+        })?;
+    let actual = String::from_utf8_lossy(&output.stdout);
+
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let mismatch = (0..expected_lines.len().max(actual_lines.len()))
+        .find(|&i| expected_lines.get(i) != actual_lines.get(i));
+
+    if mismatch.is_none() && output.status.success() {
+        println!("PASS {}", path.display());
+        return Ok(());
+    }
+
+    println!("FAIL {}", path.display());
+    if let Some(i) = mismatch {
+        println!("  first difference at line {}:", i + 1);
+        println!(
+            "    expected: {}",
+            expected_lines.get(i).unwrap_or(&"<end of output>")
+        );
+        println!(
+            "    actual:   {}",
+            actual_lines.get(i).unwrap_or(&"<end of output>")
+        );
+    }
+    if !output.status.success() {
+        println!("  script exited with {}", output.status);
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            println!("    {}", line);
+        }
+    }
+    Err(())
+}
+
+fn print_leak_report(report: &memory::MemoryReport) {
+    eprintln!();
+    eprintln!("=== Leak report ===");
+    eprintln!(
+        "{} live values, ~{} bytes",
+        report.total, report.approx_bytes
+    );
+    for (kind, count) in &report.counts {
+        eprintln!("  {:<16} {}", kind, count);
+    }
+    if !report.instances.is_empty() {
+        eprintln!("Instances:");
+        for (concept, count) in &report.instances {
+            eprintln!("  {:<16} {}", concept, count);
+        }
+    }
+
+    if report.collections.is_empty() {
+        eprintln!("No Lists or Maps are still referenced.");
+        return;
+    }
+
+    eprintln!();
+    eprintln!("Still strongly referenced:");
+    for c in report.notable_collections() {
+        eprintln!(
+            "  {} ({}, {} items) strong={} weak={}{}",
+            c.path,
+            c.kind,
+            c.length,
+            c.strong_count,
+            c.weak_count,
+            if c.is_retained() {
+                "  <- retained outside the script"
+            } else {
+                ""
+            }
+        );
+    }
+
+    let retained = report.retained();
+    if !retained.is_empty() {
+        eprintln!();
+        eprintln!(
+            "{} collection(s) are held by references no variable accounts for \
+             (closures, background tasks, native handles).",
+            retained.len()
+        );
+    }
+
+    if !report.cycles.is_empty() {
+        eprintln!();
+        eprintln!(
+            "{} reference cycle(s); nothing in a cycle is freed until it is broken:",
+            report.cycles.len()
+        );
+        for cycle in report.cycles.iter().take(10) {
+            eprintln!("  {} -> {}", cycle.path, cycle.refers_to);
+        }
+        eprintln!("Hold one side of each with WeakRef(...) so the cycle doesn't keep both alive.");
+    }
 }
 
 fn lex_script(path: &PathBuf) -> Result<(), ()> {
@@ -142,7 +905,8 @@ fn lex_script(path: &PathBuf) -> Result<(), ()> {
         eprintln!("Error reading file: {}", e);
     })?;
 
-    let mut lexer = Lexer::new(&source);
+    let edition = script_edition(path)?;
+    let mut lexer = Lexer::with_edition(&source, edition);
     let tokens = lexer.tokenize().map_err(|e| {
         eprintln!("Lexer error: {}", e);
     })?;
@@ -154,51 +918,13 @@ fn lex_script(path: &PathBuf) -> Result<(), ()> {
     println!("└─────────────────────────────────────────────────────────────┘");
     println!();
 
-    use sfex_lang::TokenType;
-
     for (i, token) in tokens.iter().enumerate() {
-        let token_display = match &token.token_type {
-            TokenType::Eof => "EOF".to_string(),
-            TokenType::Newline => "NEWLINE".to_string(),
-            TokenType::Indent => "INDENT".to_string(),
-            TokenType::Dedent => "DEDENT".to_string(),
-            TokenType::ErrorToken => "ERROR".to_string(),
-            TokenType::Number(n) => format!("NUMBER({})", n),
-            TokenType::String_(s) => format!("STRING(\"{}\")", s),
-            TokenType::Identifier(id) => format!("ID({})", id),
-            TokenType::Comment(c) => format!("COMMENT({})", c),
-            TokenType::Story => "KEYWORD(Story)".to_string(),
-            TokenType::Concept => "KEYWORD(Concept)".to_string(),
-            TokenType::Situation => "KEYWORD(Situation)".to_string(),
-            TokenType::Adjust => "KEYWORD(Adjust)".to_string(),
-            TokenType::If => "KEYWORD(If)".to_string(),
-            TokenType::Else => "KEYWORD(Else)".to_string(),
-            TokenType::Repeat => "KEYWORD(Repeat)".to_string(),
-            TokenType::For => "KEYWORD(For)".to_string(),
-            TokenType::Return => "KEYWORD(Return)".to_string(),
-            TokenType::Break => "KEYWORD(Break)".to_string(),
-            TokenType::Continue => "KEYWORD(Continue)".to_string(),
-            TokenType::To => "KEYWORD(To)".to_string(),
-            TokenType::With => "KEYWORD(with)".to_string(),
-            TokenType::Is => "OPERATOR(is)".to_string(),
-            TokenType::Plus => "OPERATOR(+)".to_string(),
-            TokenType::Minus => "OPERATOR(-)".to_string(),
-            TokenType::Star => "OPERATOR(*)".to_string(),
-            TokenType::Slash => "OPERATOR(/)".to_string(),
-            TokenType::Equals => "OPERATOR(=)".to_string(),
-            TokenType::Greater => "OPERATOR(>)".to_string(),
-            TokenType::Less => "OPERATOR(<)".to_string(),
-            TokenType::True_ => "LITERAL(True)".to_string(),
-            TokenType::False_ => "LITERAL(False)".to_string(),
-            _ => format!("{:?}", token.token_type),
-        };
-
         println!(
             "{:4} │ Line {:3}, Col {:3} │ {}",
             i + 1,
             token.line,
             token.column,
-            token_display
+            token_display(&token.token_type)
         );
     }
 
@@ -207,7 +933,314 @@ fn lex_script(path: &PathBuf) -> Result<(), ()> {
     Ok(())
 }
 
-fn debug_script(path: &PathBuf) -> Result<(), ()> {
+/// The syntax tree of `path` as JSON, or bincode. Both start with the sfex
+/// version and the script's edition, since the tree's shape can change
+/// between versions.
+fn parse_script(path: &Path, format: &str, output: Option<&Path>) -> Result<(), ()> {
+    let source = fs::read_to_string(path).map_err(|e| {
+        eprintln!("Error reading file: {}", e);
+    })?;
+    let edition = script_edition(path)?;
+    let (program, errors) =
+        SFXParser::from_lexer(Lexer::with_edition(&source, edition)).parse_all();
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("Parser error: {}", e);
+        }
+        return Err(());
+    }
+
+    let version = env!("CARGO_PKG_VERSION");
+    let bytes = match format {
+        "bincode" => {
+            bincode::serialize(&(version, edition.to_string(), &program)).map_err(|e| {
+                eprintln!("Error encoding the syntax tree: {}", e);
+            })?
+        }
+        _ => {
+            let tree = serde_json::json!({
+                "version": version,
+                "edition": edition.to_string(),
+                "program": program,
+            });
+            format!("{:#}\n", tree).into_bytes()
+        }
+    };
+    let written = match output {
+        Some(output) => fs::write(output, &bytes),
+        None => std::io::stdout().write_all(&bytes),
+    };
+    written.map_err(|e| {
+        eprintln!("Error writing the syntax tree: {}", e);
+    })
+}
+
+fn token_display(token_type: &TokenType) -> String {
+    match token_type {
+        TokenType::Eof => "EOF".to_string(),
+        TokenType::Newline => "NEWLINE".to_string(),
+        TokenType::Indent => "INDENT".to_string(),
+        TokenType::Dedent => "DEDENT".to_string(),
+        TokenType::ErrorToken => "ERROR".to_string(),
+        TokenType::Number(n) => format!("NUMBER({})", n),
+        TokenType::Integer(n) => format!("INTEGER({})", n),
+        TokenType::String_(s) => format!("STRING(\"{}\")", s),
+        TokenType::RawString(s) => format!("RAW_STRING(\"{}\")", s),
+        TokenType::Identifier(id) => format!("ID({})", id),
+        TokenType::Comment(c) => format!("COMMENT({})", c),
+        TokenType::DocComment(c) => format!("DOC({})", c),
+        TokenType::Story => "KEYWORD(Story)".to_string(),
+        TokenType::Concept => "KEYWORD(Concept)".to_string(),
+        TokenType::Situation => "KEYWORD(Situation)".to_string(),
+        TokenType::Adjust => "KEYWORD(Adjust)".to_string(),
+        TokenType::If => "KEYWORD(If)".to_string(),
+        TokenType::Else => "KEYWORD(Else)".to_string(),
+        TokenType::Repeat => "KEYWORD(Repeat)".to_string(),
+        TokenType::For => "KEYWORD(For)".to_string(),
+        TokenType::Return => "KEYWORD(Return)".to_string(),
+        TokenType::Break => "KEYWORD(Break)".to_string(),
+        TokenType::Continue => "KEYWORD(Continue)".to_string(),
+        TokenType::To => "KEYWORD(To)".to_string(),
+        TokenType::With => "KEYWORD(with)".to_string(),
+        TokenType::Is => "OPERATOR(is)".to_string(),
+        TokenType::Plus => "OPERATOR(+)".to_string(),
+        TokenType::Minus => "OPERATOR(-)".to_string(),
+        TokenType::Star => "OPERATOR(*)".to_string(),
+        TokenType::Slash => "OPERATOR(/)".to_string(),
+        TokenType::SlashSlash => "OPERATOR(//)".to_string(),
+        TokenType::Equals => "OPERATOR(=)".to_string(),
+        TokenType::Greater => "OPERATOR(>)".to_string(),
+        TokenType::Less => "OPERATOR(<)".to_string(),
+        TokenType::True_ => "LITERAL(True)".to_string(),
+        TokenType::False_ => "LITERAL(False)".to_string(),
+        _ => format!("{:?}", token_type),
+    }
+}
+
+/// `sfex lex --interactive`: each line typed is tokenized after the lines
+/// before it, so its INDENT and DEDENT tokens come out as they would in a
+/// file, along with why.
+fn lex_interactive(start: Option<&Path>) -> Result<(), ()> {
+    let (edition, mut lines) = match start {
+        Some(path) => {
+            let source = fs::read_to_string(path).map_err(|e| {
+                eprintln!("Error reading file: {}", e);
+            })?;
+            let edition = script_edition(path)?;
+            Lexer::with_edition(&source, edition)
+                .tokenize()
+                .map_err(|e| {
+                    eprintln!("Lexer error: {}", e);
+                })?;
+            (edition, source.lines().map(str::to_string).collect())
+        }
+        None => {
+            let dir = std::env::current_dir().unwrap_or_default();
+            let edition = project::edition_in(&dir).map_err(|e| {
+                eprintln!("{}", e);
+            })?;
+            (edition, Vec::new())
+        }
+    };
+
+    println!("Type a line to see its tokens. Type :help for commands.");
+    if let Some(path) = start {
+        println!(
+            "Continuing after the {} lines of {}; open levels: {}",
+            lines.len(),
+            path.display(),
+            format_levels(&lex_levels(&lines, edition))
+        );
+    }
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("(lex) ");
+        let _ = std::io::stdout().flush();
+
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input).unwrap_or(0) == 0 {
+            println!();
+            break;
+        }
+        let line = input.trim_end_matches(['\n', '\r']);
+
+        match line.trim() {
+            ":quit" | ":q" => break,
+            ":help" | ":h" => {
+                println!("  <line>     tokenize a line after the ones before it");
+                println!("  :history   the lines so far");
+                println!("  :undo      drop the last line");
+                println!("  :reset     start again from an empty file");
+                println!("  :end       the DEDENTs the end of the file would add here");
+                println!("  :quit      leave");
+            }
+            ":history" => {
+                for (i, line) in lines.iter().enumerate() {
+                    println!("{:4} │ {}", i + 1, line);
+                }
+            }
+            ":undo" => {
+                lines.pop();
+                println!(
+                    "  open levels: {}",
+                    format_levels(&lex_levels(&lines, edition))
+                );
+            }
+            ":reset" => lines.clear(),
+            ":end" => {
+                let levels = lex_levels(&lines, edition);
+                println!(
+                    "  end of file: {} DEDENT, closing levels {}",
+                    levels.len() - 1,
+                    format_levels(&levels[1..])
+                );
+            }
+            _ => {
+                if lex_line(&lines, line, edition) {
+                    lines.push(line.to_string());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the tokens of `line` after `lines` and how its indentation was
+/// read. False when it can't follow them.
+fn lex_line(lines: &[String], line: &str, edition: Edition) -> bool {
+    let number = lines.len() + 1;
+    let mut all: Vec<&str> = lines.iter().map(String::as_str).collect();
+    all.push(line);
+    let source = format!("{}\n", all.join("\n"));
+
+    let tokens = match Lexer::with_edition(&source, edition).tokenize() {
+        Ok(tokens) => tokens,
+        // A triple-quoted string may go on over the next lines
+        Err(e) if matches!(e.kind, LexerErrorKind::UnterminatedString) => {
+            println!("  string still open; it continues on the next line");
+            return true;
+        }
+        Err(e) => {
+            println!("  {}", e);
+            if matches!(e.kind, LexerErrorKind::DedentError) {
+                println!(
+                    "  indent {} is none of the open levels {}",
+                    indent_width(line),
+                    format_levels(&open_levels(&tokens_before(lines, edition), &all))
+                );
+            }
+            println!("  (line not added)");
+            return false;
+        }
+    };
+
+    let before = open_levels(tokens.iter().filter(|token| token.line < number), &all);
+    let on_line: Vec<_> = tokens.iter().filter(|t| t.line == number).collect();
+    let indents = on_line
+        .iter()
+        .filter(|t| t.token_type == TokenType::Indent)
+        .count();
+    let dedents = on_line
+        .iter()
+        .filter(|t| t.token_type == TokenType::Dedent)
+        .count();
+    let width = indent_width(line);
+    let top = before.last().copied().unwrap_or(0);
+
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        println!("  blank or comment line: its indentation isn't read");
+    } else if on_line.iter().all(|t| t.token_type == TokenType::Newline) {
+        println!("  inside a string: its indentation isn't read");
+    } else if indents > 0 {
+        println!("  indent {} > {}: INDENT opens level {}", width, top, width);
+    } else if dedents > 0 {
+        println!(
+            "  indent {} < {}: {} DEDENT, back to level {}",
+            width, top, dedents, width
+        );
+    } else {
+        println!("  indent {}: same level, no INDENT or DEDENT", width);
+        let opener = lines
+            .iter()
+            .rev()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        if opener.is_some_and(|line| line.ends_with(':')) {
+            println!("  note: the line before ends with ':', so a deeper line is expected here");
+        }
+    }
+
+    for token in &on_line {
+        println!(
+            "  Col {:3} │ {}",
+            token.column,
+            token_display(&token.token_type)
+        );
+    }
+    println!(
+        "  open levels: {}",
+        format_levels(&open_levels(
+            tokens.iter().filter(|token| token.line <= number),
+            &all
+        ))
+    );
+    true
+}
+
+fn tokens_before(lines: &[String], edition: Edition) -> Vec<Token> {
+    let source = format!("{}\n", lines.join("\n"));
+    let mut tokens = Lexer::with_edition(&source, edition)
+        .tokenize()
+        .unwrap_or_default();
+    tokens.retain(|token| token.line <= lines.len());
+    tokens
+}
+
+/// The indentation levels open after `lines`, outermost first.
+fn lex_levels(lines: &[String], edition: Edition) -> Vec<usize> {
+    let all: Vec<&str> = lines.iter().map(String::as_str).collect();
+    open_levels(&tokens_before(lines, edition), &all)
+}
+
+// Replays the INDENT and DEDENT tokens, measuring each INDENT's line
+fn open_levels<'a>(tokens: impl IntoIterator<Item = &'a Token>, lines: &[&str]) -> Vec<usize> {
+    let mut levels = vec![0];
+    for token in tokens {
+        match token.token_type {
+            TokenType::Indent => levels.push(
+                lines
+                    .get(token.line - 1)
+                    .map_or(0, |line| indent_width(line)),
+            ),
+            TokenType::Dedent if levels.len() > 1 => {
+                levels.pop();
+            }
+            _ => {}
+        }
+    }
+    levels
+}
+
+fn format_levels(levels: &[usize]) -> String {
+    if levels.is_empty() {
+        return "none".to_string();
+    }
+    levels
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn debug_script(
+    path: &PathBuf,
+    history: &[String],
+    show_timeline: bool,
+    instances: &[String],
+    observers: bool,
+) -> Result<(), ()> {
     println!("Debugging SFX script: {}", path.display());
     println!();
 
@@ -215,31 +1248,213 @@ fn debug_script(path: &PathBuf) -> Result<(), ()> {
         eprintln!("Error reading file: {}", e);
     })?;
 
-    let mut lexer = Lexer::new(&source);
+    let edition = script_edition(path)?;
+    let mut lexer = Lexer::with_edition(&source, edition);
     let tokens = lexer.tokenize().map_err(|e| {
         eprintln!("Lexer error: {}", e);
     })?;
 
-    let mut parser = SFXParser::new(tokens);
+    let mut parser = SFXParser::with_edition(tokens, edition);
     let program = parser.parse().map_err(|e| {
         eprintln!("Parser error: {}", e);
     })?;
 
     let mut interpreter = Interpreter::new();
     interpreter.enable_trace();
-    interpreter.run(program).map_err(|e| {
+    interpreter.enable_timeline();
+    interpreter.track_all_instances();
+    let result = interpreter.run(program).map_err(|e| {
         eprintln!("Runtime error: {}", e);
-    })?;
+    });
 
-    Ok(())
+    // The timeline is most useful after a failure, so inspect it either way.
+    if let Some(timeline) = interpreter.timeline() {
+        if show_timeline {
+            println!();
+            print_timeline(timeline.records().iter());
+        }
+        for name in history {
+            println!();
+            println!("History of {}:", name);
+            print_timeline(timeline.history(name).into_iter());
+        }
+        for concept in instances {
+            println!();
+            println!("Instances of {}:", concept);
+            print_instances(&interpreter, concept);
+        }
+        if !show_timeline
+            && history.is_empty()
+            && instances.is_empty()
+            && !timeline.is_empty()
+            && std::io::stdin().is_terminal()
+        {
+            inspect_timeline(timeline, &interpreter);
+        }
+    }
+    if observers {
+        println!();
+        print_observer_costs(&interpreter);
+    }
+
+    result
+}
+
+fn print_observer_costs(interpreter: &Interpreter) {
+    let costs = interpreter.observer_costs();
+    if costs.is_empty() {
+        println!("No When observers ran.");
+        return;
+    }
+    let width = costs.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    println!(
+        "{:<width$}  {:>6}  {:>10}  {:>10}  {:>10}",
+        "Observer", "Runs", "Statements", "Total ms", "Max ms"
+    );
+    for (name, cost) in costs {
+        println!(
+            "{:<width$}  {:>6}  {:>10}  {:>10.3}  {:>10.3}",
+            name,
+            cost.runs,
+            cost.statements,
+            cost.total_time.as_secs_f64() * 1000.0,
+            cost.max_time.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+fn print_jit_comparison(comparison: &sfex_lang::jit::JitComparison) {
+    let methods: Vec<_> = comparison.methods().collect();
+    if methods.is_empty() {
+        println!("No methods were JIT-compiled.");
+        return;
+    }
+    let micros = |time: Duration, calls: u64| time.as_secs_f64() * 1e6 / calls as f64;
+    let width = methods
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:<width$}  {:>6}  {:>10}  {:>10}  {:>8}  {:>14}",
+        "Method", "Calls", "Interp us", "JIT us", "Speedup", "Max divergence"
+    );
+    for (name, method) in &methods {
+        println!(
+            "{:<width$}  {:>6}  {:>10.3}  {:>10.3}  {:>7.1}x  {:>14.3e}",
+            name,
+            method.calls,
+            micros(method.interpreted_time, method.calls),
+            micros(method.jit_time, method.calls),
+            method.speedup(),
+            method.max_divergence
+        );
+    }
+    for (name, method) in &methods {
+        if let Some((interpreted, jit)) = &method.first_divergence {
+            eprintln!(
+                "{}: {} of {} calls differ by more than {} (first: interpreter {}, JIT {})",
+                name, method.divergent_calls, method.calls, comparison.tolerance, interpreted, jit
+            );
+        }
+    }
 }
 
+fn print_timeline<'a>(records: impl Iterator<Item = &'a timeline::SetRecord>) {
+    let mut any = false;
+    for record in records {
+        println!("  {}", record);
+        any = true;
+    }
+    if !any {
+        println!("  (no assignments recorded)");
+    }
+}
+
+fn print_instances(interpreter: &Interpreter, concept: &str) {
+    let instances = interpreter.instances_of(concept);
+    if instances.is_empty() {
+        println!("  (no live instances)");
+    }
+    for (i, instance) in instances.iter().enumerate() {
+        println!("  [{}] {}", i + 1, instance);
+    }
+}
+
+fn inspect_timeline(timeline: &timeline::Timeline, interpreter: &Interpreter) {
+    println!();
+    println!(
+        "Recorded {} assignments. Type 'help' for timeline commands.",
+        timeline.records().len()
+    );
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("(timeline) ");
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            break;
+        }
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or("");
+        let arg = parts.next();
+
+        match (command, arg) {
+            ("", _) => {}
+            ("help", _) | ("?", _) => {
+                println!("  timeline          list every assignment in order");
+                println!("  history <name>    assignments to a variable or field");
+                println!("  at <step>         values of all variables as of a step");
+                println!("  vars              variables and fields that were assigned");
+                println!("  instances [name]  live instances of a concept (or list concepts)");
+                println!("  quit              leave the debugger");
+            }
+            ("timeline", _) | ("t", _) => print_timeline(timeline.records().iter()),
+            ("history", Some(name)) | ("h", Some(name)) => {
+                print_timeline(timeline.history(name).into_iter())
+            }
+            ("at", Some(step)) => match step.trim_start_matches('#').parse::<usize>() {
+                Ok(step) => {
+                    for (target, value) in timeline.snapshot(step) {
+                        println!("  {} = {}", target, value);
+                    }
+                }
+                Err(_) => println!("  Step must be a number"),
+            },
+            ("vars", _) | ("v", _) => {
+                for target in timeline.targets() {
+                    println!("  {}", target);
+                }
+            }
+            ("instances", Some(concept)) | ("i", Some(concept)) => {
+                print_instances(interpreter, concept)
+            }
+            ("instances", None) | ("i", None) => {
+                for concept in interpreter.instance_concepts() {
+                    println!(
+                        "  {} ({} live)",
+                        concept,
+                        interpreter.instances_of(&concept).len()
+                    );
+                }
+            }
+            ("quit", _) | ("q", _) | ("exit", _) => break,
+            _ => println!("  Unknown command '{}'. Type 'help'.", line.trim()),
+        }
+    }
+}
+
+#[cfg(feature = "web")]
 fn serve_script(
     path: &PathBuf,
     addr: &str,
     static_dir: Option<&PathBuf>,
     tls_cert: Option<&PathBuf>,
     tls_key: Option<&PathBuf>,
+    watch: bool,
 ) -> Result<(), ()> {
     let handler_path = path
         .to_str()
@@ -252,7 +1467,38 @@ fn serve_script(
     let tls_cert_str = tls_cert.and_then(|p| p.to_str()).map(|s| s.to_string());
     let tls_key_str = tls_key.and_then(|p| p.to_str()).map(|s| s.to_string());
 
+    // Pages reload their code and HTML on change, so --watch isn't needed
+    if page::is_page(path) {
+        let tls = match (tls_cert_str.as_deref(), tls_key_str.as_deref()) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => {
+                eprintln!("Serve error: --tls-cert and --tls-key must be provided together");
+                return Err(());
+            }
+        };
+        return web::serve_page(addr, &handler_path, static_str.as_deref(), tls).map_err(|e| {
+            eprintln!("Serve error: {}", e);
+        });
+    }
+
     match (tls_cert_str.as_deref(), tls_key_str.as_deref()) {
+        (Some(cert), Some(key)) if watch => {
+            web::serve_watch(
+                addr,
+                &handler_path,
+                static_str.as_deref(),
+                Some((cert, key)),
+            )
+            .map_err(|e| {
+                eprintln!("Serve error: {}", e);
+            })?;
+        }
+        (None, None) if watch => {
+            web::serve_watch(addr, &handler_path, static_str.as_deref(), None).map_err(|e| {
+                eprintln!("Serve error: {}", e);
+            })?;
+        }
         (Some(cert), Some(key)) => {
             web::serve_tls(addr, &handler_path, cert, key, static_str.as_deref()).map_err(|e| {
                 eprintln!("Serve error: {}", e);
@@ -272,12 +1518,15 @@ fn serve_script(
     Ok(())
 }
 
-fn new_project(name: &str) -> Result<(), ()> {
+fn new_project(name: &str, template: Option<&str>) -> Result<(), ()> {
     let project_dir = Path::new(name);
     if project_dir.exists() {
         eprintln!("Directory '{}' already exists", name);
         return Err(());
     }
+    let files = scaffold::files(template, name).map_err(|e| {
+        eprintln!("Error: {}", e);
+    })?;
 
     fs::create_dir_all(project_dir).map_err(|e| {
         eprintln!("Failed to create project directory: {}", e);
@@ -288,26 +1537,158 @@ fn new_project(name: &str) -> Result<(), ()> {
         eprintln!("Failed to create packages directory: {}", e);
     })?;
 
-    let manifest = format!(
-        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n",
-        name
-    );
-    fs::write(project_dir.join("sfex.toml"), manifest).map_err(|e| {
-        eprintln!("Failed to write sfex.toml: {}", e);
+    for (file, contents) in files {
+        let path = project_dir.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                eprintln!("Failed to create {}: {}", parent.display(), e);
+            })?;
+        }
+        fs::write(&path, contents).map_err(|e| {
+            eprintln!("Failed to write {}: {}", file, e);
+        })?;
+    }
+
+    println!("Created new SFX project at {}", project_dir.display());
+    Ok(())
+}
+
+/// Run the task `name` as another sfex in the project root, with `extra`
+/// after its arguments, or in each workspace member that has it when the
+/// root doesn't; without a name, list the tasks. `external` is set for
+/// `sfex <name>`, which may also be a mistyped command.
+fn run_task(name: Option<&str>, extra: &[String], external: bool) -> Result<(), ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        eprintln!("Failed to resolve current directory: {}", e);
     })?;
+    let Some(root) = project::find_project_root(&cwd) else {
+        match name {
+            Some(name) if external => eprintln!(
+                "Unknown command '{}' (tasks need an sfex.toml; see sfex --help)",
+                name
+            ),
+            _ => eprintln!("No sfex.toml found (run from a project directory)."),
+        }
+        return Err(());
+    };
+    let manifest = project::load_manifest(&root).map_err(|e| {
+        eprintln!("Error: {}", e);
+    })?;
+
+    // The members of a workspace and their tasks, for tasks the root lacks
+    let members: Vec<(String, PathBuf, project::ProjectManifest)> =
+        project::workspace_members(&root)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|member| {
+                let manifest = project::load_manifest(&member).ok()?;
+                let shown = member.strip_prefix(&root).unwrap_or(&member);
+                Some((shown.display().to_string(), member, manifest))
+            })
+            .collect();
+
+    let Some(name) = name else {
+        let mut listed = false;
+        for (label, manifest) in std::iter::once((None, &manifest))
+            .chain(members.iter().map(|(shown, _, m)| (Some(shown), m)))
+        {
+            let Some(tasks) = manifest.tasks.as_ref().filter(|tasks| !tasks.is_empty()) else {
+                continue;
+            };
+            if let Some(shown) = label {
+                println!("{}:", shown);
+            }
+            let width = tasks.keys().map(String::len).max().unwrap_or(0);
+            for (task, command) in tasks {
+                println!("  {:<width$}  sfex {}", task, command);
+            }
+            listed = true;
+        }
+        if !listed {
+            println!("No tasks in sfex.toml.");
+        }
+        return Ok(());
+    };
+
+    // A workspace root without the task runs it in every member that has it
+    let has_task = |manifest: &project::ProjectManifest| {
+        manifest
+            .tasks
+            .as_ref()
+            .is_some_and(|tasks| tasks.contains_key(name))
+    };
+    if !has_task(&manifest) && members.iter().any(|(_, _, m)| has_task(m)) {
+        let mut failed = Vec::new();
+        for (shown, member, manifest) in members.iter().filter(|(_, _, m)| has_task(m)) {
+            println!("== {}", shown);
+            if !spawn_task(member, manifest, name, extra)?.success() {
+                failed.push(shown.as_str());
+            }
+        }
+        if !failed.is_empty() {
+            eprintln!("Task '{}' failed in {}", name, failed.join(", "));
+            return Err(());
+        }
+        return Ok(());
+    }
 
-    let main_sfex = "Story:\n    Print \"Hello, SFX!\"\n";
-    fs::write(project_dir.join("main.sfex"), main_sfex).map_err(|e| {
-        eprintln!("Failed to write main.sfex: {}", e);
+    let args = project::task_args(&manifest, name).map_err(|e| {
+        if external {
+            eprintln!("Unknown command or task '{}': {}", name, e);
+        } else {
+            eprintln!("Error: {}", e);
+        }
     })?;
+    let status = spawn_task_args(&root, name, &args, extra)?;
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
 
-    let readme = format!("# {}\n\nRun:\n\n```\nsfex run main.sfex\n```\n", name);
-    fs::write(project_dir.join("README.md"), readme).map_err(|e| {
-        eprintln!("Failed to write README.md: {}", e);
+/// Run the task `name` of the project at `root` as another sfex there.
+fn spawn_task(
+    root: &Path,
+    manifest: &project::ProjectManifest,
+    name: &str,
+    extra: &[String],
+) -> Result<process::ExitStatus, ()> {
+    let args = project::task_args(manifest, name).map_err(|e| {
+        eprintln!("Error: {}", e);
     })?;
+    spawn_task_args(root, name, &args, extra)
+}
 
-    println!("Created new SFX project at {}", project_dir.display());
-    Ok(())
+fn spawn_task_args(
+    root: &Path,
+    name: &str,
+    args: &[String],
+    extra: &[String],
+) -> Result<process::ExitStatus, ()> {
+    // Tasks that run tasks pass the chain down, so one that ends up running
+    // itself stops instead of looping forever
+    let mut chain: Vec<String> = std::env::var("SFEX_TASK_CHAIN")
+        .map(|chain| chain.split(',').map(str::to_string).collect())
+        .unwrap_or_default();
+    if chain.iter().any(|task| task == name) {
+        chain.push(name.to_string());
+        eprintln!("Task '{}' runs itself: {}", name, chain.join(" -> "));
+        return Err(());
+    }
+    chain.push(name.to_string());
+
+    let exe = std::env::current_exe().map_err(|e| {
+        eprintln!("Failed to find the sfex executable: {}", e);
+    })?;
+    process::Command::new(exe)
+        .args(args)
+        .args(extra)
+        .current_dir(root)
+        .env("SFEX_TASK_CHAIN", chain.join(","))
+        .status()
+        .map_err(|e| {
+            eprintln!("Failed to run task '{}': {}", name, e);
+        })
 }
 
 fn install_project() -> Result<(), ()> {
@@ -335,6 +1716,543 @@ fn install_project() -> Result<(), ()> {
     Ok(())
 }
 
+/// A note per adjusted method with the order its situations run in, and a
+/// warning where two of the same priority adjust it. Returns the warnings.
+fn adjustment_diagnostics(shown: &str, program: &Program) -> Vec<Diagnostic> {
+    let line_of = |name: &str| {
+        program
+            .situations
+            .iter()
+            .find(|situation| situation.name == name)
+            .map_or(1, |situation| situation.line)
+    };
+    let mut diagnostics = Vec::new();
+    for stack in program.adjustment_stacks() {
+        let layers: Vec<String> = stack
+            .situations
+            .iter()
+            .map(|(name, priority)| format!("{} ({})", name, priority))
+            .chain(std::iter::once(stack.concept.clone()))
+            .collect();
+        let (top, _) = &stack.situations[0];
+        diagnostics.push(Diagnostic {
+            rule: "adjustment-order",
+            level: Level::Note,
+            file: shown.to_string(),
+            line: line_of(top),
+            column: 1,
+            message: format!(
+                "{}.{} runs {}",
+                stack.concept,
+                stack.method,
+                layers.join(" > ")
+            ),
+        });
+        for pair in stack.situations.windows(2) {
+            let ((first, priority), (second, other)) = (&pair[0], &pair[1]);
+            if priority == other {
+                diagnostics.push(Diagnostic {
+                    rule: "same-priority",
+                    level: Level::Warning,
+                    file: shown.to_string(),
+                    line: line_of(second),
+                    column: 1,
+                    message: format!(
+                        "{} and {} both adjust {}.{} at priority {}; whichever is switched on last runs first",
+                        first, second, stack.concept, stack.method, priority
+                    ),
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+/// A warning per pair of instances a script links to each other through
+/// fields, since neither is freed while the other holds it.
+fn cycle_diagnostics(shown: &str, program: &Program) -> Vec<Diagnostic> {
+    program
+        .field_cycles()
+        .into_iter()
+        .map(|cycle| Diagnostic {
+            rule: "reference-cycle",
+            level: Level::Warning,
+            file: shown.to_string(),
+            line: cycle.line,
+            column: 1,
+            message: if cycle.first == cycle.second {
+                format!(
+                    "{} refers to its own instance, so it is never freed; hold it with WeakRef(...)",
+                    cycle.first
+                )
+            } else {
+                format!(
+                    "{} and {} refer to each other, so neither is freed; hold one side with WeakRef(...)",
+                    cycle.first, cycle.second
+                )
+            },
+        })
+        .collect()
+}
+
+/// A warning per `When` on a field limited to a few values that misses
+/// one of them or has a case that can't run.
+fn when_diagnostics(shown: &str, program: &Program) -> Vec<Diagnostic> {
+    program
+        .when_gaps()
+        .into_iter()
+        .map(|gap| Diagnostic {
+            rule: gap.rule(),
+            level: Level::Warning,
+            file: shown.to_string(),
+            line: gap.line(),
+            column: 1,
+            message: gap.message(),
+        })
+        .collect()
+}
+
+/// `Field is value` in methods, which hides the field instead of setting it.
+fn shadow_diagnostics(shown: &str, program: &Program) -> Vec<Diagnostic> {
+    program
+        .shadowed_fields()
+        .into_iter()
+        .map(|shadowed| Diagnostic {
+            rule: ShadowedField::RULE,
+            level: Level::Warning,
+            file: shown.to_string(),
+            line: shadowed.line,
+            column: 1,
+            message: shadowed.message(),
+        })
+        .collect()
+}
+
+fn check_project(format: &str) -> Result<(), ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        eprintln!("Failed to resolve current directory: {}", e);
+    })?;
+    let root = project::find_project_root(&cwd).ok_or_else(|| {
+        eprintln!("No sfex.toml found (run from a project directory).");
+    })?;
+
+    // A workspace root is checked with each of its members, whose scripts
+    // keep their own edition
+    let members = project::workspace_members(&root).unwrap_or_default();
+    let mut found = Vec::new();
+    check_package(&root, &root, &members, &mut found)?;
+    for member in &members {
+        if member.join("sfex.toml").is_file() {
+            check_package(&root, member, &[], &mut found)?;
+        }
+    }
+
+    let errors = found.iter().filter(|d| d.level == Level::Error).count();
+    let warnings = found.iter().filter(|d| d.level == Level::Warning).count();
+    match format {
+        "json" => println!("{:#}", diagnostics::to_json(&found)),
+        "sarif" => println!("{:#}", diagnostics::to_sarif(&found)),
+        _ => {
+            for diagnostic in &found {
+                println!("{}", diagnostic.to_text());
+            }
+            if errors + warnings == 0 {
+                println!("No problems found.");
+            } else {
+                println!("{} error(s), {} warning(s)", errors, warnings);
+            }
+        }
+    }
+    if errors > 0 { Err(()) } else { Ok(()) }
+}
+
+/// Add the problems in the sfex.toml and scripts of the project at `dir` to
+/// `found`, with paths relative to `root`. Scripts under `skip` are left out.
+fn check_package(
+    root: &Path,
+    dir: &Path,
+    skip: &[PathBuf],
+    found: &mut Vec<Diagnostic>,
+) -> Result<(), ()> {
+    let shown_path = |path: &Path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    };
+    let manifest_path = dir.join("sfex.toml");
+    let manifest = fs::read_to_string(&manifest_path).map_err(|e| {
+        eprintln!("Error reading {}: {}", manifest_path.display(), e);
+    })?;
+
+    let before = found.len();
+    found.extend(
+        project::check_manifest(&manifest, Some(dir))
+            .into_iter()
+            .map(|issue| Diagnostic {
+                rule: issue.rule,
+                level: if issue.warning {
+                    Level::Warning
+                } else {
+                    Level::Error
+                },
+                file: shown_path(&manifest_path),
+                line: issue.line,
+                column: issue.column,
+                message: issue.message,
+            }),
+    );
+
+    // Scripts are only worth checking once the manifest gives their edition
+    if !found[before..].iter().any(|d| d.level == Level::Error) {
+        let edition = project::load_manifest(dir)
+            .and_then(|manifest| project::manifest_edition(&manifest))
+            .map_err(|e| {
+                eprintln!("{}", e);
+            })?;
+        let mut results = Vec::new();
+        let scripts = project::project_scripts(dir)
+            .into_iter()
+            .filter(|script| !skip.iter().any(|member| script.starts_with(member)));
+        for script in scripts {
+            let shown = shown_path(&script);
+            let source = fs::read_to_string(&script).map_err(|e| {
+                eprintln!("Error reading {}: {}", shown, e);
+            })?;
+            let (program, errors) =
+                SFXParser::from_lexer(Lexer::with_edition(&source, edition)).parse_all();
+            let parsed = if errors.is_empty() {
+                Ok(program)
+            } else {
+                Err(errors
+                    .iter()
+                    .map(|e| {
+                        let (line, column) = e.location();
+                        (line, column, e.reason())
+                    })
+                    .collect::<Vec<_>>())
+            };
+            results.push((script, shown, parsed));
+        }
+
+        // Files other scripts `Include` are statements, not programs; they
+        // are checked where they are included
+        let included: HashSet<PathBuf> = results
+            .iter()
+            .filter_map(|(_, _, parsed)| parsed.as_ref().ok())
+            .flat_map(|program| program.includes.iter().cloned())
+            .collect();
+        for (script, shown, parsed) in results {
+            match parsed {
+                Err(_) if included.contains(&script.canonicalize().unwrap_or(script)) => {}
+                Err(errors) => {
+                    found.extend(
+                        errors
+                            .into_iter()
+                            .map(|(line, column, message)| Diagnostic {
+                                rule: "syntax-error",
+                                level: Level::Error,
+                                file: shown.clone(),
+                                line,
+                                column,
+                                message,
+                            }),
+                    )
+                }
+                Ok(program) => {
+                    found.extend(adjustment_diagnostics(&shown, &program));
+                    found.extend(cycle_diagnostics(&shown, &program));
+                    found.extend(when_diagnostics(&shown, &program));
+                    found.extend(shadow_diagnostics(&shown, &program));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn document_project(format: &str, output: Option<&Path>, no_stdlib: bool) -> Result<(), ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        eprintln!("Failed to resolve current directory: {}", e);
+    })?;
+    let root = project::find_project_root(&cwd).ok_or_else(|| {
+        eprintln!("No sfex.toml found (run from a project directory).");
+    })?;
+    let title = project::load_manifest(&root)
+        .ok()
+        .and_then(|manifest| manifest.package?.name)
+        .or_else(|| Some(root.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "Project".to_string());
+
+    let mut scripts = project::project_scripts(&root);
+    scripts.sort();
+    let mut documented = Vec::new();
+    let mut failed = false;
+    for script in scripts {
+        let shown = script
+            .strip_prefix(&root)
+            .unwrap_or(&script)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let source = fs::read_to_string(&script).map_err(|e| {
+            eprintln!("Error reading {}: {}", shown, e);
+        })?;
+        let edition = script_edition(&script)?;
+        let (program, errors) =
+            SFXParser::from_lexer(Lexer::with_edition(&source, edition)).parse_all();
+        for e in &errors {
+            let (line, column) = e.location();
+            eprintln!("{}:{}:{}: error: {}", shown, line, column, e.reason());
+        }
+        failed |= !errors.is_empty();
+        if !program.concepts.is_empty() || !program.situations.is_empty() {
+            documented.push(doc::ScriptDocs {
+                file: shown,
+                concepts: program.concepts,
+                situations: program.situations,
+            });
+        }
+    }
+    if failed {
+        return Err(());
+    }
+
+    let stdlib = if no_stdlib { Vec::new() } else { doc::stdlib() };
+    let text = match format {
+        "html" => doc::to_html(&title, &documented, &stdlib),
+        _ => doc::to_markdown(&title, &documented, &stdlib),
+    };
+    let written = match output {
+        Some(output) => fs::write(output, text),
+        None => std::io::stdout().write_all(text.as_bytes()),
+    };
+    written.map_err(|e| {
+        eprintln!("Error writing the reference: {}", e);
+    })
+}
+
+fn precompile_project() -> Result<(), ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        eprintln!("Failed to resolve current directory: {}", e);
+    })?;
+    let root = project::find_project_root(&cwd).ok_or_else(|| {
+        eprintln!("No sfex.toml found (run from a project directory).");
+    })?;
+
+    let started = Instant::now();
+    let mut pending = project::project_scripts(&root);
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut compiled: Vec<cache::Compiled> = Vec::new();
+    let mut failures = Vec::new();
+    while let Some(script) = pending.pop() {
+        if !seen.insert(script.canonicalize().unwrap_or_else(|_| script.clone())) {
+            continue;
+        }
+        let shown = script.strip_prefix(&root).unwrap_or(&script).display();
+        let source = fs::read_to_string(&script).map_err(|e| {
+            eprintln!("Error reading {}: {}", shown, e);
+        })?;
+        let edition = project::edition_for(&script).map_err(|e| {
+            eprintln!("{}", e);
+        })?;
+        let parsed = SFXParser::from_lexer(Lexer::with_edition(&source, edition))
+            .parse()
+            .map_err(|e| e.to_string());
+        let program = match parsed {
+            Ok(program) => program,
+            Err(e) => {
+                failures.push((script.clone(), format!("{}: {}", shown, e)));
+                continue;
+            }
+        };
+
+        // Modules are resolved the way `sfex serve` run from the root does
+        for module in cache::used_modules(&program) {
+            match project::resolve_module_path(module, &root) {
+                Some(path) => pending.push(path),
+                None => eprintln!("{}: module '{}' not found, skipped", shown, module),
+            }
+        }
+        compiled.push(cache::Compiled {
+            path: script,
+            source,
+            edition,
+            program,
+        });
+    }
+    // Files that are only `Include`d are parsed as part of their includers
+    let included: HashSet<PathBuf> = compiled
+        .iter()
+        .flat_map(|script| script.program.includes.iter().cloned())
+        .collect();
+    let mut failed = false;
+    for (script, message) in failures {
+        if !included.contains(&script.canonicalize().unwrap_or(script)) {
+            eprintln!("{}", message);
+            failed = true;
+        }
+    }
+    if failed {
+        eprintln!("Nothing written; fix the errors above and run `sfex precompile` again.");
+        return Err(());
+    }
+    let parse_time = started.elapsed();
+
+    let count = compiled.len();
+    let size = cache::write(&root, compiled).map_err(|e| {
+        eprintln!("{}", e);
+    })?;
+    let load_time = fs::read(root.join(cache::CACHE_FILE))
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| cache::time_load(&bytes))
+        .map_err(|e| {
+            eprintln!("{}", e);
+        })?;
+    println!(
+        "Precompiled {} script(s) into {} ({:.1} KB)",
+        count,
+        cache::CACHE_FILE,
+        size as f64 / 1024.0
+    );
+    println!(
+        "Parsing took {:.1} ms; loading the cache takes {:.1} ms",
+        parse_time.as_secs_f64() * 1000.0,
+        load_time.as_secs_f64() * 1000.0
+    );
+    Ok(())
+}
+
+/// Use the programs `sfex precompile` saved for the project `script` is in.
+/// A missing or outdated cache only means scripts are parsed as usual.
+#[cfg(feature = "web")]
+fn load_precompiled(script: &Path) {
+    let Some(root) = script
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().and_then(project::find_project_root))
+    else {
+        return;
+    };
+    match cache::load(&root) {
+        Ok(Some(count)) => println!(
+            "Loaded {} precompiled program(s) from {}",
+            count,
+            cache::CACHE_FILE
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!("Ignoring {}", e),
+    }
+}
+
+fn migrate_project(to: Option<&str>, dry_run: bool) -> Result<(), ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        eprintln!("Failed to resolve current directory: {}", e);
+    })?;
+    let root = project::find_project_root(&cwd).ok_or_else(|| {
+        eprintln!("No sfex.toml found (run from a project directory).");
+    })?;
+    let manifest = project::load_manifest(&root).map_err(|e| {
+        eprintln!("{}", e);
+    })?;
+    let current = project::manifest_edition(&manifest).map_err(|e| {
+        eprintln!("{}", e);
+    })?;
+    let target = match to {
+        Some(edition) => edition.parse::<Edition>().map_err(|e| {
+            eprintln!("{}", e);
+        })?,
+        None => Edition::LATEST,
+    };
+    if target < current {
+        eprintln!(
+            "Edition {} is older than the project's edition {}",
+            target, current
+        );
+        return Err(());
+    }
+    let declared = manifest
+        .package
+        .as_ref()
+        .is_some_and(|package| package.edition.is_some());
+    if target == current && declared {
+        println!("Already on edition {}.", current);
+        return Ok(());
+    }
+
+    let keywords = current.new_keywords(target);
+    // `X is Y` stops copying Y from edition 2026
+    let add_copies = current.copies_on_assign() && !target.copies_on_assign();
+    let (mut renamed, mut copied) = (0, 0);
+    for script in project::project_scripts(&root) {
+        let source = fs::read_to_string(&script).map_err(|e| {
+            eprintln!("Error reading {}: {}", script.display(), e);
+        })?;
+        let (mut migrated, count) =
+            rename_identifiers(&source, current, &keywords).map_err(|e| {
+                eprintln!("{}: {}", script.display(), e);
+            })?;
+        let mut copies = 0;
+        if add_copies {
+            (migrated, copies) = copy_assignments(&migrated, target).map_err(|e| {
+                eprintln!("{}: {}", script.display(), e);
+            })?;
+        }
+        if count == 0 && copies == 0 {
+            continue;
+        }
+        let shown = script.strip_prefix(&root).unwrap_or(&script);
+        if count > 0 {
+            println!("  {}: renamed {} identifier(s)", shown.display(), count);
+        }
+        if copies > 0 {
+            println!(
+                "  {}: added 'Copy of' to {} assignment(s)",
+                shown.display(),
+                copies
+            );
+        }
+        renamed += count;
+        copied += copies;
+        if !dry_run {
+            fs::write(&script, migrated).map_err(|e| {
+                eprintln!("Error writing {}: {}", script.display(), e);
+            })?;
+        }
+    }
+
+    if dry_run {
+        println!(
+            "Moving edition {} -> {} would rename {} identifier(s) and add 'Copy of' to {} assignment(s).",
+            current, target, renamed, copied
+        );
+        return Ok(());
+    }
+    project::set_edition(&root, target).map_err(|e| {
+        eprintln!("{}", e);
+    })?;
+    if !keywords.is_empty() {
+        println!(
+            "Renamed names that are now keywords ({}) by adding '_'.",
+            keywords.join(", ")
+        );
+    }
+    if copied > 0 {
+        println!(
+            "'X is Y' no longer copies Y; 'Copy of' keeps the copy where Y is a name, member or index."
+        );
+    }
+    println!("Project is on edition {}.", target);
+    Ok(())
+}
+
+/// The edition for a script run directly, from the project it sits in.
+fn script_edition(path: &Path) -> Result<Edition, ()> {
+    project::edition_for(path).map_err(|e| {
+        eprintln!("{}", e);
+    })
+}
+
 fn print_version_info() {
     println!(
         "SFX (Situation Framework eXchange) v{}",
@@ -12,22 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::compiler::edition::Edition;
+use crate::runtime::budget::ObserverBudget;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Default)]
 pub struct ProjectManifest {
     pub package: Option<PackageInfo>,
     pub dependencies: Option<HashMap<String, DependencySpec>>,
+    pub serve: Option<ServeConfig>,
+    pub observers: Option<ObserversConfig>,
+    pub llm: Option<LlmConfig>,
+    pub tasks: Option<BTreeMap<String, String>>,
+    pub workspace: Option<WorkspaceConfig>,
+    pub config: Option<toml::Table>,
 }
 
 #[derive(Debug, Deserialize, Default)]
 pub struct PackageInfo {
     pub name: Option<String>,
     pub version: Option<String>,
+    pub edition: Option<String>,
 }
 
+/// `[serve]`: scripts `sfex serve` runs before it takes requests and after
+/// it stops, relative to the project root.
+#[derive(Debug, Deserialize, Default)]
+pub struct ServeConfig {
+    pub on_start: Option<String>,
+    pub on_stop: Option<String>,
+}
+
+/// `[observers]`: how much one run of a `When` observer may do.
+#[derive(Debug, Deserialize, Default)]
+pub struct ObserversConfig {
+    pub max_statements: Option<u64>,
+    pub max_time_ms: Option<u64>,
+}
+
+/// `[llm]`: the provider LLM calls use when they don't name one, with its
+/// model, base URL and the environment variable that holds its API key.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LlmConfig {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    pub api_key_env: Option<String>,
+}
+
+/// `[workspace]`: projects kept and checked together with this one, as
+/// directories relative to it, each with an sfex.toml of its own.
+#[derive(Debug, Deserialize, Default)]
+pub struct WorkspaceConfig {
+    pub members: Option<Vec<String>>,
+}
+
+/// The names `[llm] provider` takes.
+pub const LLM_PROVIDERS: &[&str] = &["openai", "anthropic", "ollama", "local"];
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum DependencySpec {
@@ -36,6 +81,35 @@ pub enum DependencySpec {
     Simple(String),
 }
 
+/// Sections of sfex.toml and the keys each takes. Dependencies, tasks and
+/// config settings can have any name, so those sections list none.
+pub const MANIFEST_SECTIONS: &[(&str, &[&str])] = &[
+    ("package", &["name", "version", "edition"]),
+    ("dependencies", &[]),
+    ("serve", &["on_start", "on_stop"]),
+    ("observers", &["max_statements", "max_time_ms"]),
+    ("llm", &["provider", "model", "base_url", "api_key_env"]),
+    ("tasks", &[]),
+    ("workspace", &["members"]),
+    ("config", &[]),
+];
+
+/// Keys of a table in [dependencies]; a dependency uses exactly one.
+pub const DEPENDENCY_KEYS: &[&str] = &["path", "git"];
+
+/// A problem in sfex.toml. Unknown names are warnings, since a newer sfex
+/// may know them; anything that would fail at install or run time is an error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestIssue {
+    /// 1-based
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    pub warning: bool,
+    /// One of the `manifest-` IDs in `diagnostics::RULES`
+    pub rule: &'static str,
+}
+
 pub fn find_project_root(start: &Path) -> Option<PathBuf> {
     let mut current = Some(start);
     while let Some(dir) = current {
@@ -54,6 +128,558 @@ pub fn load_manifest(root: &Path) -> Result<ProjectManifest, String> {
     toml::from_str(&contents).map_err(|e| format!("Failed to parse sfex.toml: {}", e))
 }
 
+/// Check the text of an sfex.toml. With the project `root`, path
+/// dependencies must also exist.
+pub fn check_manifest(source: &str, root: Option<&Path>) -> Vec<ManifestIssue> {
+    let mut checker = ManifestChecker {
+        source,
+        issues: Vec::new(),
+    };
+    match toml::de::DeTable::parse(source) {
+        Ok(table) => checker.check_top(table.get_ref(), root),
+        Err(e) => {
+            let at = e.span().map_or(0, |span| span.start);
+            checker.error("manifest-syntax", at, e.message().to_string());
+        }
+    }
+    let mut issues = checker.issues;
+    issues.sort_by_key(|issue| (issue.line, issue.column));
+    issues
+}
+
+struct ManifestChecker<'a> {
+    source: &'a str,
+    issues: Vec<ManifestIssue>,
+}
+
+impl ManifestChecker<'_> {
+    fn check_top(&mut self, table: &toml::de::DeTable, root: Option<&Path>) {
+        for (key, value) in table {
+            let name = key.get_ref().as_ref();
+            let Some((_, keys)) = MANIFEST_SECTIONS.iter().find(|(s, _)| *s == name) else {
+                let known: Vec<&str> = MANIFEST_SECTIONS.iter().map(|(s, _)| *s).collect();
+                let kind = if value.get_ref().is_table() {
+                    format!("section [{}]", name)
+                } else {
+                    format!("key '{}' outside a section", name)
+                };
+                self.warning(
+                    "manifest-unknown-key",
+                    key.span().start,
+                    format!("unknown {}{}", kind, did_you_mean(name, &known)),
+                );
+                continue;
+            };
+            let Some(entries) = value.get_ref().as_table() else {
+                self.error(
+                    "manifest-type",
+                    value.span().start,
+                    format!("'{}' must be a table", name),
+                );
+                continue;
+            };
+            match name {
+                "dependencies" => self.check_dependencies(entries, root),
+                "observers" => self.check_counts(name, entries, keys),
+                "tasks" => self.check_tasks(entries),
+                "workspace" => self.check_workspace(entries, keys, root),
+                // Config.Load decides what each setting must be
+                "config" => {}
+                _ => self.check_strings(name, entries, keys, root),
+            }
+        }
+    }
+
+    /// [package], [serve] and [llm], whose keys all take strings.
+    fn check_strings(
+        &mut self,
+        section: &str,
+        table: &toml::de::DeTable,
+        keys: &[&str],
+        root: Option<&Path>,
+    ) {
+        for (key, value) in table {
+            let name = key.get_ref().as_ref();
+            if !keys.contains(&name) {
+                self.warning(
+                    "manifest-unknown-key",
+                    key.span().start,
+                    format!(
+                        "unknown key '{}' in [{}]{}",
+                        name,
+                        section,
+                        did_you_mean(name, keys)
+                    ),
+                );
+                continue;
+            }
+            let Some(text) = value.get_ref().as_str() else {
+                self.error(
+                    "manifest-type",
+                    value.span().start,
+                    format!("{}.{} must be a string", section, name),
+                );
+                continue;
+            };
+            if name == "edition"
+                && let Err(e) = text.parse::<Edition>()
+            {
+                self.error("manifest-edition", value.span().start, e);
+            }
+            if section == "llm" && name == "provider" && !LLM_PROVIDERS.contains(&text) {
+                self.error(
+                    "manifest-type",
+                    value.span().start,
+                    format!(
+                        "llm.provider must be one of {}{}",
+                        LLM_PROVIDERS.join(", "),
+                        did_you_mean(text, LLM_PROVIDERS)
+                    ),
+                );
+            }
+            if section == "serve"
+                && let Some(root) = root
+                && !root.join(text).is_file()
+            {
+                self.error(
+                    "manifest-missing-path",
+                    value.span().start,
+                    format!("serve.{}: no file at '{}'", name, text),
+                );
+            }
+        }
+    }
+
+    /// [observers], whose keys all take whole numbers above zero.
+    fn check_counts(&mut self, section: &str, table: &toml::de::DeTable, keys: &[&str]) {
+        for (key, value) in table {
+            let name = key.get_ref().as_ref();
+            if !keys.contains(&name) {
+                self.warning(
+                    "manifest-unknown-key",
+                    key.span().start,
+                    format!(
+                        "unknown key '{}' in [{}]{}",
+                        name,
+                        section,
+                        did_you_mean(name, keys)
+                    ),
+                );
+                continue;
+            }
+            if !value
+                .get_ref()
+                .as_integer()
+                .is_some_and(|n| n.as_str().parse::<u64>().is_ok_and(|n| n > 0))
+            {
+                self.error(
+                    "manifest-type",
+                    value.span().start,
+                    format!("{}.{} must be a whole number above 0", section, name),
+                );
+            }
+        }
+    }
+
+    /// [tasks], whose keys are names and whose values are the arguments
+    /// to give sfex.
+    fn check_tasks(&mut self, table: &toml::de::DeTable) {
+        for (key, value) in table {
+            let message = match value.get_ref().as_str().map(split_args) {
+                Some(Ok(args)) if !args.is_empty() => continue,
+                Some(Err(e)) => format!("tasks.{}: {}", key.get_ref(), e),
+                _ => format!(
+                    "tasks.{} must be a string of sfex arguments, e.g. \"run main.sfex\"",
+                    key.get_ref()
+                ),
+            };
+            self.error("manifest-type", value.span().start, message);
+        }
+    }
+
+    /// [workspace], whose members are directories holding an sfex.toml.
+    fn check_workspace(&mut self, table: &toml::de::DeTable, keys: &[&str], root: Option<&Path>) {
+        for (key, value) in table {
+            let name = key.get_ref().as_ref();
+            if !keys.contains(&name) {
+                self.warning(
+                    "manifest-unknown-key",
+                    key.span().start,
+                    format!(
+                        "unknown key '{}' in [workspace]{}",
+                        name,
+                        did_you_mean(name, keys)
+                    ),
+                );
+                continue;
+            }
+            let Some(members) = value.get_ref().as_array() else {
+                self.error(
+                    "manifest-type",
+                    value.span().start,
+                    "workspace.members must be a list of directories".to_string(),
+                );
+                continue;
+            };
+            for member in members {
+                let Some(dir) = member.get_ref().as_str() else {
+                    self.error(
+                        "manifest-type",
+                        member.span().start,
+                        "workspace.members must be a list of directories".to_string(),
+                    );
+                    continue;
+                };
+                if let Some(root) = root
+                    && !root.join(dir).join("sfex.toml").is_file()
+                {
+                    self.error(
+                        "manifest-missing-path",
+                        member.span().start,
+                        format!("workspace member '{}' has no sfex.toml", dir),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_dependencies(&mut self, table: &toml::de::DeTable, root: Option<&Path>) {
+        for (key, value) in table {
+            let name = key.get_ref().as_ref();
+            let Some(spec) = value.get_ref().as_table() else {
+                self.error(
+                    "manifest-dependency",
+                    value.span().start,
+                    format!(
+                        "dependency '{}' must specify a path or git URL, e.g. {} = {{ path = \"../{}\" }}",
+                        name, name, name
+                    ),
+                );
+                continue;
+            };
+            let (mut sources, mut unknown) = (0, false);
+            for (field, setting) in spec {
+                let field_name = field.get_ref().as_ref();
+                if !DEPENDENCY_KEYS.contains(&field_name) {
+                    self.error(
+                        "manifest-unknown-key",
+                        field.span().start,
+                        format!(
+                            "unknown key '{}' in dependency '{}'{}",
+                            field_name,
+                            name,
+                            did_you_mean(field_name, DEPENDENCY_KEYS)
+                        ),
+                    );
+                    unknown = true;
+                    continue;
+                }
+                sources += 1;
+                let Some(text) = setting.get_ref().as_str() else {
+                    self.error(
+                        "manifest-type",
+                        setting.span().start,
+                        format!("'{}' of dependency '{}' must be a string", field_name, name),
+                    );
+                    continue;
+                };
+                if field_name == "path"
+                    && let Some(root) = root
+                    && !root.join(text).is_dir()
+                {
+                    self.error(
+                        "manifest-missing-path",
+                        setting.span().start,
+                        format!("dependency '{}': no directory at '{}'", name, text),
+                    );
+                }
+            }
+            if sources > 1 || (sources == 0 && !unknown) {
+                self.error(
+                    "manifest-dependency",
+                    key.span().start,
+                    format!("dependency '{}' needs exactly one of path or git", name),
+                );
+            }
+        }
+    }
+
+    fn error(&mut self, rule: &'static str, offset: usize, message: String) {
+        self.push(rule, offset, message, false);
+    }
+
+    fn warning(&mut self, rule: &'static str, offset: usize, message: String) {
+        self.push(rule, offset, message, true);
+    }
+
+    fn push(&mut self, rule: &'static str, offset: usize, message: String, warning: bool) {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        self.issues.push(ManifestIssue {
+            line,
+            column,
+            message,
+            warning,
+            rule,
+        });
+    }
+}
+
+/// ` (did you mean 'edition'?)` when `name` is a likely typo of a known name.
+fn did_you_mean(name: &str, known: &[&str]) -> String {
+    known
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| format!(" (did you mean '{}'?)", candidate))
+        .unwrap_or_default()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The edition of the project `script` belongs to. Scripts outside a project,
+/// and projects that don't name one, get the first edition.
+pub fn edition_for(script: &Path) -> Result<Edition, String> {
+    let dir = script
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    match dir {
+        Some(dir) => edition_in(&dir),
+        None => Ok(Edition::default()),
+    }
+}
+
+/// The edition of the project `dir` is in; the first edition outside one.
+pub fn edition_in(dir: &Path) -> Result<Edition, String> {
+    let Some(root) = find_project_root(dir) else {
+        return Ok(Edition::default());
+    };
+    manifest_edition(&load_manifest(&root)?)
+}
+
+/// The `[serve]` on_start and on_stop scripts of the project `script`
+/// belongs to, resolved against the project root.
+pub fn serve_hooks_for(script: &Path) -> Result<(Option<PathBuf>, Option<PathBuf>), String> {
+    let dir = script
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    let Some(root) = dir.as_deref().and_then(find_project_root) else {
+        return Ok((None, None));
+    };
+    let serve = load_manifest(&root)?.serve.unwrap_or_default();
+    Ok((
+        serve.on_start.map(|path| root.join(path)),
+        serve.on_stop.map(|path| root.join(path)),
+    ))
+}
+
+/// The `[observers]` budget of the project `script` belongs to; scripts
+/// outside a project, and limits it doesn't set, keep the defaults.
+pub fn observer_budget_for(script: &Path) -> Result<ObserverBudget, String> {
+    let dir = script
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    let mut budget = ObserverBudget::default();
+    let Some(root) = dir.as_deref().and_then(find_project_root) else {
+        return Ok(budget);
+    };
+    let observers = load_manifest(&root)?.observers.unwrap_or_default();
+    if let Some(statements) = observers.max_statements {
+        budget.statements = Some(statements);
+    }
+    if let Some(ms) = observers.max_time_ms {
+        budget.time = Some(Duration::from_millis(ms));
+    }
+    Ok(budget)
+}
+
+/// The `[llm]` settings of the project `script` belongs to; empty for
+/// scripts outside a project.
+pub fn llm_config_for(script: &Path) -> Result<LlmConfig, String> {
+    let dir = script
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    let Some(root) = dir.as_deref().and_then(find_project_root) else {
+        return Ok(LlmConfig::default());
+    };
+    Ok(load_manifest(&root)?.llm.unwrap_or_default())
+}
+
+/// The sfex arguments of the `[tasks]` entry `name`.
+pub fn task_args(manifest: &ProjectManifest, name: &str) -> Result<Vec<String>, String> {
+    let tasks = manifest.tasks.as_ref();
+    let Some(command) = tasks.and_then(|tasks| tasks.get(name)) else {
+        let known: Vec<&str> = tasks
+            .into_iter()
+            .flatten()
+            .map(|(task, _)| task.as_str())
+            .collect();
+        return Err(format!(
+            "no task '{}' in sfex.toml{}",
+            name,
+            did_you_mean(name, &known)
+        ));
+    };
+    split_args(command).map_err(|e| format!("tasks.{}: {}", name, e))
+}
+
+/// Split a task's command at whitespace; double quotes keep an argument
+/// with spaces together, and `\"` inside them is a quote.
+pub fn split_args(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.clone().next() == Some('"') => {
+                            arg.push('"');
+                            chars.next();
+                        }
+                        Some(c) => arg.push(c),
+                        None => return Err("unclosed quote".to_string()),
+                    }
+                }
+            }
+            c if c.is_whitespace() => args.extend(current.take()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+pub fn manifest_edition(manifest: &ProjectManifest) -> Result<Edition, String> {
+    match manifest.package.as_ref().and_then(|p| p.edition.as_deref()) {
+        Some(edition) => edition.parse().map_err(|e| format!("sfex.toml: {}", e)),
+        None => Ok(Edition::default()),
+    }
+}
+
+/// Write `edition` into the [package] table of the project's sfex.toml,
+/// keeping the rest of the file as it is.
+pub fn set_edition(root: &Path, edition: Edition) -> Result<(), String> {
+    let manifest_path = root.join("sfex.toml");
+    let contents = std::fs::read_to_string(&manifest_path)
+        .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+
+    let line = format!("edition = \"{}\"", edition);
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    let package = lines.iter().position(|l| l.trim() == "[package]");
+    let existing = package.and_then(|start| {
+        lines[start + 1..]
+            .iter()
+            .take_while(|l| !l.trim_start().starts_with('['))
+            .position(|l| l.trim_start().starts_with("edition"))
+            .map(|offset| start + 1 + offset)
+    });
+    match (existing, package) {
+        (Some(index), _) => lines[index] = line,
+        (None, Some(start)) => {
+            // After the last key of [package], before any blank lines
+            let mut end = lines[start + 1..]
+                .iter()
+                .position(|l| l.trim_start().starts_with('['))
+                .map_or(lines.len(), |offset| start + 1 + offset);
+            while end > start + 1 && lines[end - 1].trim().is_empty() {
+                end -= 1;
+            }
+            lines.insert(end, line);
+        }
+        (None, None) => {
+            lines.insert(0, String::new());
+            lines.insert(0, line);
+            lines.insert(0, "[package]".to_string());
+        }
+    }
+
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    std::fs::write(&manifest_path, updated)
+        .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))
+}
+
+/// Every .sfex file in the project, leaving out installed packages and
+/// hidden directories.
+pub fn project_scripts(root: &Path) -> Vec<PathBuf> {
+    fn walk(dir: &Path, top: bool, found: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if path.is_dir() {
+                let skip = name.starts_with('.') || (top && name == "packages");
+                if !skip {
+                    walk(&path, false, found);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "sfex") {
+                found.push(path);
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    walk(root, true, &mut found);
+    found.sort();
+    found
+}
+
+/// The member directories of the workspace at `root`; none if its
+/// sfex.toml has no [workspace].
+pub fn workspace_members(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let members = load_manifest(root)?
+        .workspace
+        .and_then(|workspace| workspace.members)
+        .unwrap_or_default();
+    Ok(members.iter().map(|member| root.join(member)).collect())
+}
+
+/// The root of the workspace the project around `dir` is in: that project,
+/// if it has [workspace] members, or the nearest one above that lists it.
+pub fn find_workspace_root(dir: &Path) -> Option<PathBuf> {
+    let project = find_project_root(dir)?.canonicalize().ok()?;
+    let mut current = Some(project.as_path());
+    while let Some(candidate) = current {
+        if candidate.join("sfex.toml").exists() {
+            let members: Vec<PathBuf> = workspace_members(candidate)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|member| member.canonicalize().ok())
+                .collect();
+            if !members.is_empty() && (candidate == project || members.contains(&project)) {
+                return Some(candidate.to_path_buf());
+            }
+        }
+        current = candidate.parent();
+    }
+    None
+}
+
 pub fn packages_dir(root: &Path) -> PathBuf {
     root.join("packages")
 }
@@ -75,38 +701,90 @@ pub fn resolve_module_path(module_path: &str, cwd: &Path) -> Option<PathBuf> {
         return Some(packaged);
     }
 
+    // Another member of the workspace, by its directory: `Use shared.Money`
+    // is shared/Money.sfex wherever in the workspace it is used from
+    let workspace = find_workspace_root(&root)?;
+    let sibling = workspace.join(module_path);
+    let members = workspace_members(&workspace).ok()?;
+    if sibling.exists() && members.iter().any(|member| sibling.starts_with(member)) {
+        return Some(sibling);
+    }
+
     None
 }
 
+/// Install the dependencies of the project at `root` into its packages
+/// directory, and theirs after them. A workspace root installs those of
+/// each member too; a path dependency on another member isn't copied,
+/// since modules find members where they are. Returns what was installed.
 pub fn install_dependencies(root: &Path) -> Result<Vec<String>, String> {
-    let manifest = load_manifest(root)?;
+    let members = workspace_members(root)?;
+    let mut installed = Vec::new();
+    install_graph(root, &packages_dir(root), &members, &mut installed)?;
+    for member in &members {
+        let mut from_member = Vec::new();
+        install_graph(member, &packages_dir(member), &members, &mut from_member)?;
+        let shown = member
+            .strip_prefix(root)
+            .unwrap_or(member)
+            .display()
+            .to_string();
+        installed.extend(
+            from_member
+                .into_iter()
+                .map(|name| format!("{}: {}", shown, name)),
+        );
+    }
+    Ok(installed)
+}
+
+/// Install the dependencies `project` names into `packages`, then the ones
+/// each of those names. A package already there is left as it is.
+fn install_graph(
+    project: &Path,
+    packages: &Path,
+    members: &[PathBuf],
+    installed: &mut Vec<String>,
+) -> Result<(), String> {
+    if !project.join("sfex.toml").is_file() {
+        return Ok(());
+    }
+    let manifest = load_manifest(project)?;
     let dependencies = manifest.dependencies.unwrap_or_default();
     if dependencies.is_empty() {
-        return Ok(Vec::new());
+        return Ok(());
     }
 
-    let packages_dir = packages_dir(root);
-    std::fs::create_dir_all(&packages_dir)
+    std::fs::create_dir_all(packages)
         .map_err(|e| format!("Failed to create packages directory: {}", e))?;
 
-    let mut installed = Vec::new();
-
-    for (name, spec) in dependencies {
-        let destination = packages_dir.join(&name);
+    let mut names: Vec<&String> = dependencies.keys().collect();
+    names.sort();
+    for name in names {
+        let destination = packages.join(name);
         if destination.exists() {
             continue;
         }
 
-        match spec {
+        // Where the package's own dependencies are relative to
+        let origin = match &dependencies[name] {
             DependencySpec::Path { path } => {
-                let source = root.join(path);
+                let source = project.join(path);
+                let is_member = source.canonicalize().is_ok_and(|source| {
+                    members
+                        .iter()
+                        .any(|member| member.canonicalize().is_ok_and(|member| member == source))
+                });
+                if is_member {
+                    continue;
+                }
                 copy_dir_recursive(&source, &destination)?;
-                installed.push(name);
+                source
             }
             DependencySpec::Git { git } => {
                 let status = std::process::Command::new("git")
                     .arg("clone")
-                    .arg(&git)
+                    .arg(git)
                     .arg(&destination)
                     .status()
                     .map_err(|e| format!("Failed to run git: {}", e))?;
@@ -114,7 +792,7 @@ pub fn install_dependencies(root: &Path) -> Result<Vec<String>, String> {
                 if !status.success() {
                     return Err(format!("git clone failed for {}", git));
                 }
-                installed.push(name);
+                destination
             }
             DependencySpec::Simple(_) => {
                 return Err(format!(
@@ -122,10 +800,12 @@ pub fn install_dependencies(root: &Path) -> Result<Vec<String>, String> {
                     name
                 ));
             }
-        }
+        };
+        installed.push(name.clone());
+        install_graph(&origin, packages, members, installed)?;
     }
 
-    Ok(installed)
+    Ok(())
 }
 
 fn copy_dir_recursive(source: &Path, destination: &Path) -> Result<(), String> {
@@ -168,3 +848,159 @@ fn copy_dir_recursive(source: &Path, destination: &Path) -> Result<(), String> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_manifest() {
+        let source = "[package]\nname = \"app\"\nedition = \"2025\"\n\n[dependencies]\nutils = { path = \"../utils\" }\n";
+        assert!(check_manifest(source, None).is_empty());
+
+        let source = "[pakage]\n\n[package]\nedition = \"1999\"\nverison = \"1\"\n\n[dependencies]\nutils = \"1.0\"\nlib = { git = \"x\", path = \"y\" }\n";
+        let issues = check_manifest(source, None);
+        let found: Vec<(usize, bool)> = issues.iter().map(|i| (i.line, i.warning)).collect();
+        assert_eq!(
+            found,
+            vec![(1, true), (4, false), (5, true), (8, false), (9, false)]
+        );
+        assert!(issues[0].message.contains("did you mean 'package'"));
+        let rules: Vec<&str> = issues.iter().map(|i| i.rule).collect();
+        assert_eq!(
+            rules,
+            vec![
+                "manifest-unknown-key",
+                "manifest-edition",
+                "manifest-unknown-key",
+                "manifest-dependency",
+                "manifest-dependency"
+            ]
+        );
+
+        let source = "[serve]\non_start = \"boot.sfex\"\non_stp = \"x\"\non_stop = 1\n";
+        let issues = check_manifest(source, None);
+        let found: Vec<(usize, bool)> = issues.iter().map(|i| (i.line, i.warning)).collect();
+        assert_eq!(found, vec![(3, true), (4, false)]);
+        assert!(issues[0].message.contains("did you mean 'on_stop'"));
+
+        let source = "[observers]\nmax_statements = 5000\nmax_time_ms = 0\nmax_time = 5\n";
+        let issues = check_manifest(source, None);
+        let found: Vec<(usize, bool)> = issues.iter().map(|i| (i.line, i.warning)).collect();
+        assert_eq!(found, vec![(3, false), (4, true)]);
+
+        let source = "[llm]\nprovider = \"openia\"\nmodel = \"gpt-4o\"\n";
+        let issues = check_manifest(source, None);
+        let found: Vec<(usize, bool)> = issues.iter().map(|i| (i.line, i.warning)).collect();
+        assert_eq!(found, vec![(2, false)]);
+        assert!(issues[0].message.contains("did you mean 'openai'"));
+
+        let source = "[tasks]\nstart = \"run main.sfex\"\ntest = 1\nlint = \"check \\\"a\"\n";
+        let issues = check_manifest(source, None);
+        let found: Vec<(usize, bool)> = issues.iter().map(|i| (i.line, i.warning)).collect();
+        assert_eq!(found, vec![(3, false), (4, false)]);
+
+        let issues = check_manifest("[package\n", None);
+        assert_eq!((issues[0].line, issues[0].column), (1, 9));
+    }
+
+    #[test]
+    fn test_workspace() {
+        use std::fs;
+        let root = std::env::temp_dir().join(format!("sfex-workspace-{}", std::process::id()));
+        let (api, shared, util) = (root.join("api"), root.join("shared"), root.join("util"));
+        for dir in [&api, &shared, &util] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(
+            root.join("sfex.toml"),
+            "[workspace]\nmembers = [\"api\", \"shared\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            api.join("sfex.toml"),
+            "[dependencies]\nshared = { path = \"../shared\" }\n",
+        )
+        .unwrap();
+        fs::write(
+            shared.join("sfex.toml"),
+            "[dependencies]\nutil = { path = \"../util\" }\n",
+        )
+        .unwrap();
+        fs::write(shared.join("Money.sfex"), "Concept: Money\n").unwrap();
+        fs::write(util.join("sfex.toml"), "[dependencies]\n").unwrap();
+        fs::write(util.join("Pad.sfex"), "Concept: Pad\n").unwrap();
+
+        let canonical = root.canonicalize().unwrap();
+        assert_eq!(find_workspace_root(&api), Some(canonical.clone()));
+        assert_eq!(find_workspace_root(&root), Some(canonical));
+        assert_eq!(find_workspace_root(&util), None);
+        let found = resolve_module_path("shared/Money.sfex", &api).unwrap();
+        assert!(found.ends_with("shared/Money.sfex"));
+        assert!(resolve_module_path("util/Pad.sfex", &api).is_none());
+
+        // shared is used where it is; util comes in through it
+        let installed = install_dependencies(&root).unwrap();
+        assert_eq!(installed, vec!["shared: util"]);
+        assert!(!api.join("packages/shared").exists());
+        assert!(shared.join("packages/util/Pad.sfex").exists());
+
+        let source = "[workspace]\nmembers = [\"api\", \"web\", 3]\n";
+        let issues = check_manifest(source, Some(&root));
+        let found: Vec<(usize, usize)> = issues.iter().map(|i| (i.line, i.column)).collect();
+        assert_eq!(found, vec![(2, 19), (2, 26)]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_task_args() {
+        assert_eq!(
+            split_args("  serve main.sfex  --addr \"0.0.0.0:80\" ").unwrap(),
+            vec!["serve", "main.sfex", "--addr", "0.0.0.0:80"]
+        );
+        assert_eq!(
+            split_args("run \"my app.sfex\" x\"\"y \"\"").unwrap(),
+            vec!["run", "my app.sfex", "xy", ""]
+        );
+        assert_eq!(
+            split_args(r#"run "say \"hi\"""#).unwrap(),
+            vec!["run", "say \"hi\""]
+        );
+        assert!(split_args("run \"main.sfex").is_err());
+
+        let manifest: ProjectManifest =
+            toml::from_str("[tasks]\nstart = \"run main.sfex\"\n").unwrap();
+        assert_eq!(
+            task_args(&manifest, "start").unwrap(),
+            vec!["run", "main.sfex"]
+        );
+        let e = task_args(&manifest, "stat").unwrap_err();
+        assert!(e.contains("did you mean 'start'"));
+    }
+
+    #[test]
+    fn test_serve_hooks() {
+        use std::fs;
+        let root = std::env::temp_dir().join(format!("sfex-serve-hooks-{}", std::process::id()));
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::write(
+            root.join("sfex.toml"),
+            "[serve]\non_start = \"hooks/boot.sfex\"\n",
+        )
+        .unwrap();
+        let script = root.join("app/main.sfex");
+        fs::write(&script, "Story:\n").unwrap();
+
+        // Hooks are found from a script anywhere in the project and
+        // resolved against its root
+        let canonical = root.canonicalize().unwrap();
+        assert_eq!(
+            serve_hooks_for(&script).unwrap(),
+            (Some(canonical.join("hooks/boot.sfex")), None)
+        );
+
+        fs::write(root.join("sfex.toml"), "[package]\nname = \"app\"\n").unwrap();
+        assert_eq!(serve_hooks_for(&script).unwrap(), (None, None));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use super::lock::RwLockExt;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+// A `When` observer runs inside every `Set` of its field, so one slow
+// observer slows down all of them. Each run of an observer gets a budget of
+// statements and of time, counting the statements of observers it triggers;
+// going over it raises an Observer.BudgetExceeded error at the `Set`.
+
+/// How much one run of a `When` observer may do. `None` is no limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObserverBudget {
+    pub statements: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+impl Default for ObserverBudget {
+    fn default() -> Self {
+        Self {
+            statements: Some(1_000_000),
+            time: None,
+        }
+    }
+}
+
+static DEFAULT_BUDGET: RwLock<Option<ObserverBudget>> = RwLock::new(None);
+
+/// The budget new interpreters start with, e.g. from a project's sfex.toml.
+pub fn set_default(budget: ObserverBudget) {
+    *DEFAULT_BUDGET.write_recover() = Some(budget);
+}
+
+pub fn default_budget() -> ObserverBudget {
+    DEFAULT_BUDGET.read_recover().unwrap_or_default()
+}
+
+/// One observer that is running: `Concept.Field`, and what it used so far.
+pub(crate) struct ObserverRun {
+    pub(crate) name: String,
+    pub(crate) started: Instant,
+    pub(crate) statements: u64,
+}
+
+impl ObserverRun {
+    pub(crate) fn new(name: String) -> Self {
+        Self {
+            name,
+            started: Instant::now(),
+            statements: 0,
+        }
+    }
+
+    /// Count one more statement; the error message once over `budget`.
+    pub(crate) fn charge(&mut self, budget: &ObserverBudget) -> Result<(), String> {
+        self.statements += 1;
+        if let Some(limit) = budget.statements
+            && self.statements > limit
+        {
+            return Err(format!(
+                "When {} changes ran more than {} statements",
+                self.name, limit
+            ));
+        }
+        if let Some(limit) = budget.time
+            && self.started.elapsed() > limit
+        {
+            return Err(format!(
+                "When {} changes ran longer than {} ms",
+                self.name,
+                limit.as_millis()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interpreter, Lexer, Parser};
+
+    #[test]
+    fn test_observer_budget() {
+        let source = "Concept: Meter\n    Level, Log\n\n    When Level changes:\n        Set This.Log to New\n        Repeat New times:\n            X is 1\n\n    When Log changes:\n        Y is 1\n\nStory:\n    Create Meter Called M\n    Set M.Level to 3\n    Set M.Level to 50\n";
+        let program = Parser::new(Lexer::new(source).tokenize().unwrap())
+            .parse()
+            .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_observer_budget(ObserverBudget {
+            statements: Some(20),
+            time: None,
+        });
+        let err = interpreter.run(program).unwrap_err();
+        let info = err.to_error_info();
+        assert_eq!(
+            (info.category.as_str(), info.subtype.as_str()),
+            ("Observer", "BudgetExceeded")
+        );
+        assert!(info.message.contains("Meter.Level"));
+
+        let costs = interpreter.observer_costs();
+        let level = &costs
+            .iter()
+            .find(|(name, _)| name == "Meter.Level")
+            .unwrap()
+            .1;
+        assert_eq!(level.runs, 2);
+        // 2 + 1 (Log's observer) + 3 the first time, then up to the limit
+        assert_eq!(level.statements, 6 + 21);
+    }
+}
@@ -0,0 +1,265 @@
+use super::budget::ObserverBudget;
+use super::limits::Limits;
+use super::lock::RwLockExt;
+use super::permissions::{Kind, Snapshot};
+use super::value::Value;
+use bigdecimal::num_bigint::BigInt;
+use indexmap::IndexMap;
+use std::sync::{Arc, RwLock};
+
+// How this sfex process runs scripts: the JIT, worker threads and log
+// level. It is set once at startup from SFEX_* environment variables and
+// command-line flags, flags winning, and scripts read it as Runtime.Config
+// along with their limits and which stdlib modules they may use.
+
+/// Log levels from least to most verbose
+pub const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub jit: bool,
+    /// Calls of a method before the JIT compiles it
+    pub jit_threshold: usize,
+    /// Async worker threads; `None` is one per CPU core
+    pub workers: Option<usize>,
+    /// One of `LOG_LEVELS`
+    pub log_level: String,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            jit: cfg!(feature = "jit"),
+            jit_threshold: 100,
+            workers: None,
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// The defaults, changed by whichever of SFEX_JIT, SFEX_JIT_THRESHOLD,
+    /// SFEX_WORKERS and SFEX_LOG_LEVEL are set.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Some(jit) = var("SFEX_JIT") {
+            config.jit = match jit.trim().to_ascii_lowercase().as_str() {
+                "1" | "true" | "on" => true,
+                "0" | "false" | "off" => false,
+                _ => return Err(format!("SFEX_JIT must be on or off, not '{}'", jit)),
+            };
+            if config.jit && !cfg!(feature = "jit") {
+                return Err(
+                    "SFEX_JIT is on, but sfex was built without the jit feature".to_string()
+                );
+            }
+        }
+        if let Some(threshold) = var("SFEX_JIT_THRESHOLD") {
+            config.jit_threshold = threshold.trim().parse().map_err(|_| {
+                format!(
+                    "SFEX_JIT_THRESHOLD must be a whole number, not '{}'",
+                    threshold
+                )
+            })?;
+        }
+        if let Some(workers) = var("SFEX_WORKERS") {
+            config.workers =
+                Some(workers.trim().parse().map_err(|_| {
+                    format!("SFEX_WORKERS must be a whole number, not '{}'", workers)
+                })?);
+        }
+        if let Some(level) = var("SFEX_LOG_LEVEL") {
+            config.set_log_level(&level)?;
+        }
+        Ok(config)
+    }
+
+    pub fn set_log_level(&mut self, level: &str) -> Result<(), String> {
+        let level = level.trim().to_ascii_lowercase();
+        if !LOG_LEVELS.contains(&level.as_str()) {
+            return Err(format!(
+                "Unknown log level '{}', expected one of: {}",
+                level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+        self.log_level = level;
+        Ok(())
+    }
+
+    /// Runtime.Config for a script with `budget`, `limits` and
+    /// `permissions`, allowed only `modules` if it is sandboxed.
+    pub fn to_value(
+        &self,
+        budget: &ObserverBudget,
+        limits: &Limits,
+        modules: Option<Vec<String>>,
+        permissions: &Snapshot,
+    ) -> Value {
+        let integer = |n: usize| Value::Integer(BigInt::from(n));
+        let limit = |n: Option<u64>| Value::Option(Box::new(n.map(|n| Value::Integer(n.into()))));
+        let list = |items: Option<Vec<String>>| {
+            Value::Option(Box::new(items.map(|items| {
+                Value::List(Arc::new(RwLock::new(
+                    items.into_iter().map(Value::String).collect(),
+                )))
+            })))
+        };
+
+        let jit = map([
+            ("Enabled", Value::Boolean(self.jit)),
+            ("Threshold", integer(self.jit_threshold)),
+        ]);
+        let limits = map([
+            ("ObserverStatements", limit(budget.statements)),
+            (
+                "ObserverTimeMs",
+                limit(budget.time.map(|time| time.as_millis() as u64)),
+            ),
+            ("MaxSteps", limit(limits.max_steps)),
+            (
+                "MaxMemory",
+                limit(limits.max_memory.map(|bytes| bytes as u64)),
+            ),
+            (
+                "WallTimeMs",
+                limit(limits.wall_timeout.map(|time| time.as_millis() as u64)),
+            ),
+        ]);
+        let restricted =
+            modules.is_some() || Kind::ALL.iter().any(|kind| permissions.restricts(*kind));
+        let sandbox = map([
+            ("Restricted", Value::Boolean(restricted)),
+            ("Modules", list(modules)),
+            ("Read", list(permissions.allowed(Kind::Read))),
+            ("Write", list(permissions.allowed(Kind::Write))),
+            ("Net", list(permissions.allowed(Kind::Net))),
+            ("Env", list(permissions.allowed(Kind::Env))),
+            ("Run", list(permissions.allowed(Kind::Run))),
+        ]);
+        map([
+            (
+                "Version",
+                Value::String(env!("CARGO_PKG_VERSION").to_string()),
+            ),
+            ("Jit", jit),
+            (
+                "Workers",
+                integer(self.workers.unwrap_or_else(num_cpus::get)),
+            ),
+            ("LogLevel", Value::String(self.log_level.clone())),
+            ("Limits", limits),
+            ("Sandbox", sandbox),
+        ])
+    }
+}
+
+fn map<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Map(Arc::new(RwLock::new(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect::<IndexMap<_, _>>(),
+    )))
+}
+
+static CONFIG: RwLock<Option<RuntimeConfig>> = RwLock::new(None);
+
+/// Set the process's configuration, before the first script runs.
+pub fn set(config: RuntimeConfig) {
+    *CONFIG.write_recover() = Some(config);
+}
+
+pub fn current() -> RuntimeConfig {
+    CONFIG.read_recover().clone().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_vars() {
+        let vars = |pairs: &'static [(&str, &str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            RuntimeConfig::from_vars(vars(&[])).unwrap(),
+            RuntimeConfig::default()
+        );
+
+        let config = RuntimeConfig::from_vars(vars(&[
+            ("SFEX_JIT", "off"),
+            ("SFEX_JIT_THRESHOLD", "500"),
+            ("SFEX_WORKERS", "2"),
+            ("SFEX_LOG_LEVEL", "WARN"),
+        ]))
+        .unwrap();
+        assert!(!config.jit);
+        assert_eq!(config.jit_threshold, 500);
+        assert_eq!(config.workers, Some(2));
+        assert_eq!(config.log_level, "warn");
+
+        assert!(RuntimeConfig::from_vars(vars(&[("SFEX_JIT", "maybe")])).is_err());
+        assert!(RuntimeConfig::from_vars(vars(&[("SFEX_LOG_LEVEL", "loud")])).is_err());
+    }
+
+    #[test]
+    fn test_sandboxed_config() {
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.restrict_modules(&["JSON".to_string()]);
+        let field = |value: &Value, name: &str| match value {
+            Value::Map(map) => map.read_recover()[name].clone(),
+            _ => panic!("expected a Map"),
+        };
+        let sandbox = field(&interpreter.runtime_config(), "Sandbox");
+        assert!(field(&sandbox, "Restricted").equals(&Value::Boolean(true)));
+        assert_eq!(
+            field(&sandbox, "Modules").to_display_string(),
+            "Some([JSON, Runtime])"
+        );
+        assert_eq!(field(&sandbox, "Net").to_display_string(), "None");
+        let limits = field(&interpreter.runtime_config(), "Limits");
+        assert_eq!(field(&limits, "MaxSteps").to_display_string(), "None");
+
+        interpreter.set_limits(crate::runtime::limits::Limits {
+            max_steps: Some(1000),
+            max_memory: Some(1 << 20),
+            wall_timeout: Some(std::time::Duration::from_secs(2)),
+        });
+        let limits = field(&interpreter.runtime_config(), "Limits");
+        assert_eq!(field(&limits, "MaxSteps").to_display_string(), "Some(1000)");
+        assert_eq!(
+            field(&limits, "MaxMemory").to_display_string(),
+            "Some(1048576)"
+        );
+        assert_eq!(
+            field(&limits, "WallTimeMs").to_display_string(),
+            "Some(2000)"
+        );
+
+        let mut permissions = crate::runtime::permissions::Permissions::none();
+        permissions.allow(Kind::Net, &["api.example.com".to_string()]);
+        permissions.allow(Kind::Read, &[]);
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.set_permissions(permissions);
+        let sandbox = field(&interpreter.runtime_config(), "Sandbox");
+        assert!(field(&sandbox, "Restricted").equals(&Value::Boolean(true)));
+        assert_eq!(field(&sandbox, "Modules").to_display_string(), "None");
+        assert_eq!(
+            field(&sandbox, "Net").to_display_string(),
+            "Some([api.example.com])"
+        );
+        assert_eq!(field(&sandbox, "Read").to_display_string(), "None");
+        assert_eq!(field(&sandbox, "Env").to_display_string(), "Some([])");
+    }
+}
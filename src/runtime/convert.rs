@@ -0,0 +1,244 @@
+use super::lock::RwLockExt;
+use super::value::Value;
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Conversions between Values and plain Rust types, for hosts that embed the
+// interpreter. Whole numbers become Integers, like literals in a script do;
+// converting back accepts any numeric type and fails on a fraction.
+
+macro_rules! from_integer {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Value {
+            fn from(n: $t) -> Self {
+                Value::Integer(BigInt::from(n))
+            }
+        }
+    )*};
+}
+
+from_integer!(i32, i64, u32, u64, usize);
+
+impl From<f64> for Value {
+    /// NaN and the infinities have no Number, so they stay FastNumbers.
+    fn from(n: f64) -> Self {
+        BigDecimal::from_f64(n)
+            .map(Value::Number)
+            .unwrap_or(Value::FastNumber(n))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(Arc::new(RwLock::new(
+            items.into_iter().map(Into::into).collect(),
+        )))
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(entries: HashMap<String, T>) -> Self {
+        Value::Map(Arc::new(RwLock::new(
+            entries.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        )))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        Value::Option(Box::new(option.map(Into::into)))
+    }
+}
+
+fn expected(what: &str, value: &Value) -> String {
+    format!("Expected {}, got {}", what, value.type_name())
+}
+
+fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        let whole = match &value {
+            Value::Number(n) if n.is_integer() => n.to_i64(),
+            Value::Integer(i) => i.to_i64(),
+            Value::FastNumber(f) if f.fract() == 0.0 => f.to_i64(),
+            _ => None,
+        };
+        whole.ok_or_else(|| expected("a whole number that fits in 64 bits", &value))
+    }
+}
+
+impl TryFrom<Value> for usize {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        let n = i64::try_from(value.clone())?;
+        usize::try_from(n).map_err(|_| expected("a whole number of 0 or more", &value))
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        to_f64(&value).ok_or_else(|| expected("a number", &value))
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            other => Err(expected("a Boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(expected("a String", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = String>> TryFrom<Value> for Vec<T> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::List(items) => items
+                .read_recover()
+                .iter()
+                .cloned()
+                .map(T::try_from)
+                .collect(),
+            other => Err(expected("a List", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = String>> TryFrom<Value> for HashMap<String, T> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Map(entries) => entries
+                .read_recover()
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), T::try_from(v.clone())?)))
+                .collect(),
+            other => Err(expected("a Map", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = String>> TryFrom<Value> for Option<T> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Option(option) => option.map(T::try_from).transpose(),
+            other => Err(expected("an Option", &other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        assert!(matches!(Value::from(42), Value::Integer(_)));
+        assert!(Value::from(42).equals(&Value::Number(BigDecimal::from(42))));
+        assert_eq!(i64::try_from(Value::from(42)), Ok(42));
+        assert_eq!(f64::try_from(Value::from(2.5)), Ok(2.5));
+        assert!(i64::try_from(Value::from(2.5)).is_err());
+        assert!(usize::try_from(Value::from(-1)).is_err());
+        assert_eq!(String::try_from(Value::from("hi")), Ok("hi".to_string()));
+        assert!(String::try_from(Value::from(true)).is_err());
+
+        let list = Value::from(vec![1, 2, 3]);
+        assert_eq!(Vec::<i64>::try_from(list), Ok(vec![1, 2, 3]));
+        let map = Value::from(HashMap::from([("a".to_string(), vec![true])]));
+        assert_eq!(
+            HashMap::<String, Vec<bool>>::try_from(map),
+            Ok(HashMap::from([("a".to_string(), vec![true])]))
+        );
+        assert_eq!(Option::<i64>::try_from(Value::from(None::<i64>)), Ok(None));
+    }
+
+    #[test]
+    fn test_embedding() {
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.register_function("Double", |args| {
+            let n = i64::try_from(args[0].clone())?;
+            Ok(Value::from(n * 2))
+        });
+        let source = "Concept: Counter\n    Count\n    To Add with Amount:\n        Set This.Count to This.Count + Amount\n        Return This.Count\n\nStory:\n    Create Counter Called Clicks\n    Set Clicks.Count to 10\n";
+        let tokens = crate::Lexer::new(source).tokenize().unwrap();
+        interpreter
+            .run(crate::Parser::new(tokens).parse().unwrap())
+            .unwrap();
+
+        let eval = |interpreter: &mut crate::Interpreter, source| {
+            i64::try_from(interpreter.eval(source).unwrap())
+        };
+        assert_eq!(eval(&mut interpreter, "Double(Clicks.Count) + 1"), Ok(21));
+        assert!(interpreter.eval("Clicks.Count +").is_err());
+
+        let call = |interpreter: &mut crate::Interpreter, path, args: &[Value]| {
+            i64::try_from(interpreter.call(path, args).unwrap())
+        };
+        assert_eq!(call(&mut interpreter, "Double", &[Value::from(4)]), Ok(8));
+        assert_eq!(
+            call(&mut interpreter, "Math.Abs", &[Value::from(-3)]),
+            Ok(3)
+        );
+        assert_eq!(
+            call(&mut interpreter, "Clicks.Add", &[Value::from(5)]),
+            Ok(15)
+        );
+        assert_eq!(eval(&mut interpreter, "Clicks.Count"), Ok(15));
+        assert_eq!(
+            call(&mut interpreter, "Counter.Add", &[Value::from(5)]),
+            Ok(5)
+        );
+        assert!(interpreter.call("Missing", &[]).is_err());
+    }
+}
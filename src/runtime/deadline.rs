@@ -0,0 +1,114 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+// A web handler that outlives its request timeout has already cost the client
+// a 503. The server sets a deadline on the handler thread; the interpreter
+// stops between statements once it passes, and blocking stdlib calls (HTTP,
+// TCP, LLM) shorten their waits to the time that is left.
+
+thread_local! {
+    static CURRENT: Cell<Option<Deadline>> = const { Cell::new(None) };
+}
+
+// std sockets reject a zero timeout, so waits never go below this
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: Instant,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Run `f` with `deadline` as the current thread's deadline.
+pub fn scope<T>(deadline: Option<Deadline>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Deadline>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.replace(deadline)));
+    f()
+}
+
+pub fn current() -> Option<Deadline> {
+    CURRENT.with(|current| current.get())
+}
+
+pub fn exceeded_message() -> String {
+    "Request deadline exceeded".to_string()
+}
+
+/// Fail once the current deadline has passed.
+pub fn check() -> Result<(), String> {
+    match current() {
+        Some(deadline) if deadline.is_expired() => Err(exceeded_message()),
+        _ => Ok(()),
+    }
+}
+
+/// The shorter of `timeout` and the time left before the current deadline.
+pub fn limit(timeout: Option<Duration>) -> Option<Duration> {
+    let remaining = current().map(|deadline| deadline.remaining().max(MIN_WAIT));
+    match (timeout, remaining) {
+        (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+        (timeout, remaining) => timeout.or(remaining),
+    }
+}
+
+/// `runtime.block_on(future)`, abandoning the future at the current deadline.
+pub fn block_on<F: Future>(runtime: &Runtime, future: F) -> Result<F::Output, String> {
+    match current() {
+        None => Ok(runtime.block_on(future)),
+        Some(deadline) => runtime
+            .block_on(tokio::time::timeout(deadline.remaining(), future))
+            .map_err(|_| exceeded_message()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_and_limit() {
+        assert!(current().is_none());
+        assert_eq!(
+            limit(Some(Duration::from_secs(5))),
+            Some(Duration::from_secs(5))
+        );
+
+        scope(Some(Deadline::after(Duration::from_secs(60))), || {
+            assert!(check().is_ok());
+            assert_eq!(
+                limit(Some(Duration::from_secs(5))),
+                Some(Duration::from_secs(5))
+            );
+            assert!(limit(None).unwrap() <= Duration::from_secs(60));
+        });
+        assert!(current().is_none());
+
+        scope(Some(Deadline::after(Duration::ZERO)), || {
+            assert!(check().is_err());
+            assert_eq!(limit(None), Some(MIN_WAIT));
+        });
+    }
+}
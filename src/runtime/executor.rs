@@ -0,0 +1,36 @@
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::{Builder, Runtime};
+
+/// The one tokio runtime behind every interpreter, background task and web
+/// server in the process. Building a runtime per interpreter used to start
+/// a full worker pool each time.
+static RUNTIME: OnceLock<Arc<Runtime>> = OnceLock::new();
+static WORKERS: OnceLock<usize> = OnceLock::new();
+
+/// Set the number of async worker threads (`--workers`). Must be called
+/// before the first interpreter is created.
+pub fn configure_workers(workers: usize) -> Result<(), String> {
+    if workers == 0 {
+        return Err("Worker count must be at least 1".to_string());
+    }
+    if RUNTIME.get().is_some() {
+        return Err("Worker count must be set before the runtime starts".to_string());
+    }
+    WORKERS
+        .set(workers)
+        .map_err(|_| "Worker count is already set".to_string())
+}
+
+pub fn shared_runtime() -> Arc<Runtime> {
+    RUNTIME
+        .get_or_init(|| {
+            let mut builder = Builder::new_multi_thread();
+            builder.enable_all().thread_name("sfex-worker");
+            // Without --workers tokio picks one per core (or TOKIO_WORKER_THREADS)
+            if let Some(workers) = WORKERS.get() {
+                builder.worker_threads(*workers);
+            }
+            Arc::new(builder.build().expect("Failed to create Tokio runtime"))
+        })
+        .clone()
+}
@@ -1,9 +1,22 @@
+use super::budget::{ObserverBudget, ObserverRun};
+use super::config;
+use super::deadline::{self, Deadline};
+use super::limits::{LimitTracker, Limits};
+use super::lock::{MutexExt, RwLockExt, panic_message};
+use super::memory::MemoryReport;
+use super::permissions::{self, Permissions};
+use super::registry::InstanceRegistry;
+use super::timeline::Timeline;
+use super::usage::{Usage, UsageReporter, UsageTracker};
 use super::value::{ErrorInfo, Value};
+use super::warnings::{self, WarningReporter, WarningTracker};
 use crate::compiler::ast::*;
 use crate::stdlib;
-use bigdecimal::FromPrimitive;
-use std::collections::HashMap;
-use std::sync::Arc;
+use bigdecimal::{FromPrimitive, ToPrimitive};
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum RuntimeError {
@@ -13,6 +26,77 @@ pub enum RuntimeError {
     TypeError(String),
     IndexError(String),
     Custom(String),
+    // From a Raise statement, with where it was raised
+    Raised(Arc<ErrorInfo>, SourceLine),
+    // A caught error thrown again with `Raise E`, unchanged
+    Rethrown(Box<RuntimeError>),
+    // Over one of the interpreter's Limits; Catch doesn't stop it
+    LimitExceeded(String),
+}
+
+/// A line of the main script, or of a module loaded with `Use`. Shows as
+/// `Line 42` or `models/User.sfex:42`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub file: Option<Arc<str>>,
+    pub line: usize,
+}
+
+impl std::fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "Line {}", self.line),
+        }
+    }
+}
+
+/// How a module's file shows in errors: relative to the working directory
+/// when it is inside it, always with `/` separators.
+fn module_file_name(path: &std::path::Path, cwd: &std::path::Path) -> Arc<str> {
+    let shown = path.strip_prefix(cwd).unwrap_or(path);
+    Arc::from(shown.display().to_string().replace('\\', "/"))
+}
+
+impl RuntimeError {
+    /// This error as an Error value, the way a task or a Catch reports it.
+    pub fn to_error_info(&self) -> Arc<ErrorInfo> {
+        let (category, subtype, message) = match self {
+            RuntimeError::UndefinedVariable(msg) => ("Lookup", "UndefinedVariable", msg),
+            RuntimeError::UndefinedConcept(msg) => ("Lookup", "UndefinedVariable", msg),
+            RuntimeError::UndefinedMethod(msg) => ("Lookup", "MethodNotFound", msg),
+            RuntimeError::TypeError(msg) => ("Validation", "InvalidType", msg),
+            RuntimeError::IndexError(msg) => ("Lookup", "IndexOutOfBounds", msg),
+            RuntimeError::Custom(msg) => ("Logic", "InvalidOperation", msg),
+            RuntimeError::LimitExceeded(msg) => ("Runtime", "LimitExceeded", msg),
+            RuntimeError::Raised(info, _) => return info.clone(),
+            RuntimeError::Rethrown(err) => return err.to_error_info(),
+        };
+        Arc::new(ErrorInfo {
+            category: category.to_string(),
+            subtype: subtype.to_string(),
+            message: message.clone(),
+        })
+    }
+}
+
+fn map_address(map: &Arc<RwLock<IndexMap<String, Value>>>) -> usize {
+    Arc::as_ptr(map) as *const () as usize
+}
+
+// A panic inside a native function becomes a script error the caller can
+// Try/Catch, rather than unwinding through the interpreter
+fn call_native(
+    func: &(dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync),
+    args: Vec<Value>,
+) -> Result<Value, RuntimeError> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| func(args))) {
+        Ok(result) => result.map_err(RuntimeError::Custom),
+        Err(payload) => Err(RuntimeError::Custom(format!(
+            "Native function panicked: {}",
+            panic_message(payload.as_ref())
+        ))),
+    }
 }
 
 #[derive(Clone)]
@@ -23,36 +107,59 @@ pub enum ExecutionResult {
     Continue,
 }
 
+// A field set inside a Batch, observed when the outermost Batch ends. `old`
+// is the value before the first set, so several sets of one field in a
+// Batch run its observer once.
+struct DeferredChange {
+    instance: Value,
+    member: String,
+    old: Value,
+    owner: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct Environment {
     scopes: Vec<HashMap<String, Value>>,
+    // Per scope, variables shared with a background task (`share Count`).
+    // Reads and writes of these go through the cell, which the task holds
+    // too; the plain binding keeps this side's last write.
+    shared: Vec<HashMap<String, Arc<RwLock<Value>>>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             scopes: vec![HashMap::new()],
+            shared: vec![HashMap::new()],
         }
     }
 
     pub fn push_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.shared.push(HashMap::new());
     }
 
     pub fn pop_scope(&mut self) {
         if self.scopes.len() > 1 {
             self.scopes.pop();
+            self.shared.pop();
         }
     }
 
     pub fn define(&mut self, name: String, value: Value) {
+        if let Some(cell) = self.shared.last().and_then(|cells| cells.get(&name)) {
+            *cell.write_recover() = value.clone();
+        }
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(name, value);
         }
     }
 
     pub fn get(&self, name: &str) -> Option<Value> {
-        for scope in self.scopes.iter().rev() {
+        for (scope, cells) in self.scopes.iter().zip(&self.shared).rev() {
+            if let Some(cell) = cells.get(name) {
+                return Some(cell.read_recover().clone());
+            }
             if let Some(value) = scope.get(name) {
                 return Some(value.clone());
             }
@@ -61,8 +168,11 @@ impl Environment {
     }
 
     pub fn assign(&mut self, name: &str, value: Value) -> bool {
-        for scope in self.scopes.iter_mut().rev() {
+        for (scope, cells) in self.scopes.iter_mut().zip(&self.shared).rev() {
             if scope.contains_key(name) {
+                if let Some(cell) = cells.get(name) {
+                    *cell.write_recover() = value.clone();
+                }
                 scope.insert(name.to_string(), value);
                 return true;
             }
@@ -70,20 +180,80 @@ impl Environment {
         false
     }
 
+    /// Remove the innermost binding of `name`.
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.scopes
+            .iter_mut()
+            .zip(&mut self.shared)
+            .rev()
+            .find_map(|(scope, cells)| {
+                cells.remove(name);
+                scope.remove(name)
+            })
+    }
+
+    /// The cell holding `name`, made on first use, so another environment
+    /// can `bind_shared` it. None if there is no such variable.
+    pub fn share(&mut self, name: &str) -> Option<Arc<RwLock<Value>>> {
+        let index = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))?;
+        let value = self.get(name)?;
+        let cell = self.shared[index]
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(RwLock::new(value)));
+        Some(cell.clone())
+    }
+
+    /// Make `name` read and write `cell` from now on.
+    pub fn bind_shared(&mut self, name: &str, cell: Arc<RwLock<Value>>) {
+        let value = cell.read_recover().clone();
+        match self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+        {
+            Some(index) => {
+                self.scopes[index].insert(name.to_string(), value);
+                self.shared[index].insert(name.to_string(), cell);
+            }
+            None => self.define(name.to_string(), value),
+        }
+    }
+
+    /// Every visible binding, innermost scope first.
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().map(|(k, v)| (k.as_str(), v)))
+    }
+
+    /// A copy whose values are all deep copies, shared ones included: a
+    /// variable stays shared only where `bind_shared` says so again.
     pub fn clone_deep(&self) -> Self {
         let deep_scopes = self
             .scopes
             .iter()
-            .map(|scope| {
+            .zip(&self.shared)
+            .map(|(scope, cells)| {
                 scope
                     .iter()
-                    .map(|(k, v)| (k.clone(), v.clone_deep()))
+                    .map(|(k, v)| {
+                        let value = match cells.get(k) {
+                            Some(cell) => cell.read_recover().clone_deep(),
+                            None => v.clone_deep(),
+                        };
+                        (k.clone(), value)
+                    })
                     .collect()
             })
             .collect();
 
         Self {
             scopes: deep_scopes,
+            shared: vec![HashMap::new(); self.scopes.len()],
         }
     }
 }
@@ -93,36 +263,117 @@ pub struct Interpreter {
     concepts: HashMap<String, Concept>,
     situations: HashMap<String, Situation>,
     pub active_situations: Vec<String>,
+    // Situations with a `when` condition that hasn't been checked yet
+    pending_situations: Vec<String>,
     current_line: usize,
+    // The module the running code came from; None for the main script
+    current_file: Option<Arc<str>>,
     trace: bool,
+    timeline: Option<Timeline>,
+    // Print output kept for the caller instead of going to stdout
+    output: Option<String>,
+    instances: InstanceRegistry,
+    usage: Option<UsageTracker>,
+    warnings: Option<WarningTracker>,
+    // Instances whose When created hooks are running, by map_address
+    constructing: Vec<usize>,
+    // Globals defined by the stdlib, left out of memory reports
+    builtins: HashSet<String>,
+    // Stdlib modules taken away by restrict_modules
+    denied_modules: HashSet<String>,
     pub runtime: std::sync::Arc<tokio::runtime::Runtime>,
     proceed_stack: Vec<(Vec<Method>, usize, Value, Vec<(String, Value)>)>,
-    observer_depth: usize,
+    // Observers running now, innermost last, with what each has used
+    observer_runs: Vec<ObserverRun>,
+    observer_budget: ObserverBudget,
+    // Inside Batch blocks, observed changes wait in deferred_changes
+    batch_depth: usize,
+    deferred_changes: Vec<DeferredChange>,
+    // Request deadline of the thread that created this interpreter
+    deadline: Option<Deadline>,
+    limits: Option<LimitTracker>,
+    // What this interpreter's scripts may reach beyond the process's own
+    // permissions; None adds no limits
+    permissions: Option<Arc<Permissions>>,
 
     profiler: crate::jit::Profiler,
+    jit_comparison: Option<crate::jit::JitComparison>,
     jit_compiler: crate::jit::JitCompiler,
 }
 
-impl Interpreter {
-    pub fn new() -> Self {
-        let runtime = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+/// The state a background task's interpreter starts with (see `task_seed`).
+struct TaskSeed {
+    env: Environment,
+    concepts: HashMap<String, Concept>,
+    situations: HashMap<String, Situation>,
+    active_situations: Vec<String>,
+    pending_situations: Vec<String>,
+    runtime: std::sync::Arc<tokio::runtime::Runtime>,
+    limits: Option<Limits>,
+    warnings: Option<Arc<dyn WarningReporter>>,
+    file: Option<Arc<str>>,
+    permissions: Option<Arc<Permissions>>,
+}
 
-        let mut interpreter = Self {
-            env: Environment::new(),
-            concepts: HashMap::new(),
-            situations: HashMap::new(),
-            active_situations: Vec::new(),
-            current_line: 0,
-            trace: false,
-            runtime: std::sync::Arc::new(runtime),
-            proceed_stack: Vec::new(),
-            observer_depth: 0,
-            profiler: crate::jit::Profiler::new(),
-            jit_compiler: crate::jit::JitCompiler::new(),
-        };
+impl TaskSeed {
+    /// Another seed for a second task, with its own copy of the variables.
+    fn fork(&self) -> TaskSeed {
+        let mut env = self.env.clone_deep();
+        if let Some(this) = self.env.get("This") {
+            env.assign("This", this);
+        }
+        TaskSeed {
+            env,
+            concepts: self.concepts.clone(),
+            situations: self.situations.clone(),
+            active_situations: self.active_situations.clone(),
+            pending_situations: self.pending_situations.clone(),
+            runtime: self.runtime.clone(),
+            limits: self.limits,
+            warnings: self.warnings.clone(),
+            file: self.file.clone(),
+            permissions: self.permissions.clone(),
+        }
+    }
 
-        stdlib::register_stdlib(&mut interpreter);
+    fn interpreter(self) -> Interpreter {
+        let mut interpreter = Interpreter::new_with_shared_runtime(self.runtime);
+        interpreter.concepts = self.concepts;
+        interpreter.situations = self.situations;
+        interpreter.active_situations = self.active_situations;
+        interpreter.pending_situations = self.pending_situations;
+        interpreter.env = self.env;
+        interpreter.current_file = self.file;
+        interpreter.permissions = self.permissions;
+        if let Some(limits) = self.limits {
+            interpreter.set_limits(limits);
+        }
+        if let Some(reporter) = self.warnings {
+            interpreter.set_warning_reporter(reporter);
+        }
+        interpreter
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::new_with_shared_runtime(super::executor::shared_runtime())
+    }
 
+    /// An interpreter whose runs stop with `RuntimeError::LimitExceeded`
+    /// after `max_steps` statements, `max_memory` bytes allocated or
+    /// `wall_timeout`, whichever comes first. See `set_limits`.
+    pub fn with_limits(
+        max_steps: Option<u64>,
+        max_memory: Option<usize>,
+        wall_timeout: Option<Duration>,
+    ) -> Self {
+        let mut interpreter = Self::new();
+        interpreter.set_limits(Limits {
+            max_steps,
+            max_memory,
+            wall_timeout,
+        });
         interpreter
     }
 
@@ -134,16 +385,38 @@ impl Interpreter {
             concepts: HashMap::new(),
             situations: HashMap::new(),
             active_situations: Vec::new(),
+            pending_situations: Vec::new(),
             current_line: 0,
+            current_file: None,
             trace: false,
+            timeline: None,
+            output: None,
+            instances: InstanceRegistry::new(),
+            usage: None,
+            warnings: None,
+            constructing: Vec::new(),
+            builtins: HashSet::new(),
+            denied_modules: HashSet::new(),
             runtime,
             proceed_stack: Vec::new(),
-            observer_depth: 0,
+            observer_runs: Vec::new(),
+            observer_budget: super::budget::default_budget(),
+            batch_depth: 0,
+            deferred_changes: Vec::new(),
+            deadline: deadline::current(),
+            limits: None,
+            permissions: permissions::current(),
             profiler: crate::jit::Profiler::new(),
+            jit_comparison: None,
             jit_compiler: crate::jit::JitCompiler::new(),
         };
 
         stdlib::register_stdlib(&mut interpreter);
+        interpreter.builtins = interpreter
+            .env
+            .bindings()
+            .map(|(name, _)| name.to_string())
+            .collect();
 
         interpreter
     }
@@ -152,24 +425,520 @@ impl Interpreter {
         self.env.define(name.to_string(), value);
     }
 
+    /// The globals the standard library defines, such as `Math` and `Some`,
+    /// with their values.
+    pub fn builtin_globals(&self) -> Vec<(String, Value)> {
+        self.builtins
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.env.get(name)?)))
+            .collect()
+    }
+
     pub fn get_global(&self, name: &str) -> Option<Value> {
         self.env.get(name)
     }
 
+    /// Make `function` callable from scripts as `Name(...)`.
+    pub fn register_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.define_global(name, Value::NativeFunction(Arc::new(Box::new(function))));
+    }
+
+    /// Evaluate one expression, such as `Cart.Total * 2`, seeing the
+    /// variables, concepts and functions left by earlier runs.
+    pub fn eval(&mut self, source: &str) -> Result<Value, RuntimeError> {
+        self.with_permissions(|interpreter| interpreter.eval_source(source))
+    }
+
+    fn eval_source(&mut self, source: &str) -> Result<Value, RuntimeError> {
+        let tokens = crate::compiler::lexer::Lexer::new(source)
+            .tokenize()
+            .map_err(|e| RuntimeError::Custom(format!("Lexer error: {}", e)))?;
+        let expression = crate::compiler::parser::Parser::new(tokens)
+            .parse_standalone_expression()
+            .map_err(|e| RuntimeError::Custom(format!("Parser error: {}", e)))?;
+        self.evaluate_expression(&expression)
+    }
+
+    /// Call `Name`, `Module.Function` or `Instance.Method` with `args`, like
+    /// a script would. `Concept.Method` calls it on a new instance with the
+    /// concept's defaults.
+    pub fn call(&mut self, path: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+        // The arguments and target are bound in a scope of their own
+        self.env.push_scope();
+        let result = self.with_permissions(|interpreter| interpreter.call_in_scope(path, args));
+        self.env.pop_scope();
+        result
+    }
+
+    fn call_in_scope(&mut self, path: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+        let arguments: Vec<Expression> = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                let name = format!("__arg{}", i);
+                self.env.define(name.clone(), arg.clone());
+                Expression::Identifier(name)
+            })
+            .collect();
+
+        let Some((target, name)) = path.rsplit_once('.') else {
+            let callee = Expression::Identifier(path.to_string());
+            return self.evaluate_expression(&Expression::Call {
+                callee: Box::new(callee),
+                arguments,
+            });
+        };
+
+        let object = Box::new(Expression::Identifier("__target".to_string()));
+        if self.env.get(target).is_none() && self.concepts.contains_key(target) {
+            self.execute_statement(&Statement::Create {
+                concept_name: target.to_string(),
+                instance_name: "__target".to_string(),
+                initial_fields: Vec::new(),
+                line: 0,
+            })?;
+        } else {
+            let mut parts = target.split('.');
+            let first = parts.next().unwrap_or_default();
+            let value = self
+                .env
+                .get(first)
+                .ok_or_else(|| RuntimeError::UndefinedVariable(first.to_string()))?;
+            self.env.define("__target".to_string(), value);
+            for member in parts {
+                let value = self.evaluate_expression(&Expression::MemberAccess {
+                    object: object.clone(),
+                    member: member.to_string(),
+                })?;
+                self.env.define("__target".to_string(), value);
+            }
+        }
+
+        let is_instance = matches!(
+            self.env.get("__target"),
+            Some(Value::Map(map)) if map.read_recover().contains_key("_concept")
+        );
+        let expression = if is_instance {
+            Expression::MethodCall {
+                object,
+                method: name.to_string(),
+                arguments: arguments
+                    .into_iter()
+                    .map(|argument| (String::new(), argument))
+                    .collect(),
+            }
+        } else {
+            Expression::Call {
+                callee: Box::new(Expression::MemberAccess {
+                    object,
+                    member: name.to_string(),
+                }),
+                arguments,
+            }
+        };
+        self.evaluate_expression(&expression)
+    }
+
+    /// Take away every stdlib module (`File`, `HTTP`, ...) not in `allowed`,
+    /// so scripts that use one fail with an error naming it. Functions such
+    /// as `Integer` and `Some` stay.
+    pub fn restrict_modules(&mut self, allowed: &[String]) {
+        let denied: Vec<String> = self
+            .builtins
+            .iter()
+            .filter(|name| !allowed.contains(name) && *name != "Runtime")
+            .filter(|name| matches!(self.env.get(name), Some(Value::Map(_))))
+            .cloned()
+            .collect();
+        for name in denied {
+            self.env.remove(&name);
+            self.denied_modules.insert(name);
+        }
+    }
+
+    /// Narrow what scripts may read, write, connect to and run, on top of
+    /// the process's permissions. Background tasks get the same.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = Some(Arc::new(permissions.within(self.permissions.take())));
+    }
+
+    /// Run `f` with this interpreter's permissions current, where the
+    /// stdlib checks them.
+    fn with_permissions<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        permissions::scope(self.permissions.clone(), || f(self))
+    }
+
     pub fn enable_trace(&mut self) {
         self.trace = true;
     }
 
+    /// Record every assignment with its old and new value (used by `sfex debug`).
+    pub fn enable_timeline(&mut self) {
+        self.timeline = Some(Timeline::new());
+    }
+
+    pub fn timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_ref()
+    }
+
+    /// Keep what `Print` writes instead of sending it to stdout, until
+    /// `take_output` (used by `sfex run --literate --emit`).
+    pub fn capture_output(&mut self) {
+        self.output = Some(String::new());
+    }
+
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Limit each run from now on. Methods always run in the interpreter
+    /// then, since JIT-compiled code can't be stopped between statements.
+    /// Memory is only counted with `CountingAllocator` installed, and only
+    /// from the first memory limit on.
+    pub fn set_limits(&mut self, limits: Limits) {
+        if limits.is_unlimited() {
+            self.limits = None;
+        } else {
+            self.limits = Some(LimitTracker::new(limits));
+            self.profiler.disable_jit();
+        }
+    }
+
+    /// Run every call of a JIT-compiled method in the interpreter too, and
+    /// compare the results and times (`sfex run --compare-jit`). Results
+    /// more than `tolerance` apart, relative to the interpreter's, count as
+    /// divergent.
+    pub fn compare_jit(&mut self, tolerance: f64) {
+        self.jit_comparison = Some(crate::jit::JitComparison::new(tolerance));
+    }
+
+    pub fn jit_comparison(&self) -> Option<&crate::jit::JitComparison> {
+        self.jit_comparison.as_ref()
+    }
+
+    /// Register instances of every concept, not just those queried with
+    /// `Instances of` (used by `sfex debug`).
+    /// Send feature counts to `reporter` after every `run`.
+    /// How much each run of a `When` observer may do before it fails with
+    /// Observer.BudgetExceeded.
+    pub fn set_observer_budget(&mut self, budget: ObserverBudget) {
+        self.observer_budget = budget;
+    }
+
+    /// The cost of each observer that ran, the most expensive first.
+    pub fn observer_costs(&self) -> Vec<(String, crate::jit::ObserverCost)> {
+        self.profiler.observer_costs()
+    }
+
+    pub fn set_usage_reporter(&mut self, reporter: Arc<dyn UsageReporter>) {
+        self.usage = Some(UsageTracker::new(reporter));
+    }
+
+    /// Send each warning, such as a `Field is` that hides a field, to
+    /// `reporter` as it happens. Background tasks report to it too.
+    pub fn set_warning_reporter(&mut self, reporter: Arc<dyn WarningReporter>) {
+        self.warnings = Some(WarningTracker::new(reporter));
+    }
+
+    fn warn(&mut self, rule: &'static str, message: impl FnOnce() -> String) {
+        let line = self.current_line;
+        if let Some(tracker) = self.warnings.as_mut()
+            && !tracker.given(rule, line)
+        {
+            tracker.warn(rule, line, message());
+        }
+    }
+
+    fn count_usage(&mut self, count: impl FnOnce(&mut Usage)) {
+        if let Some(tracker) = self.usage.as_mut() {
+            count(&mut tracker.usage);
+        }
+    }
+
+    pub fn track_all_instances(&mut self) {
+        self.instances.track_all();
+    }
+
+    /// Live instances of a concept, oldest first.
+    pub fn instances_of(&self, concept: &str) -> Vec<Value> {
+        self.instances.live(concept)
+    }
+
+    /// Concepts that currently have live registered instances.
+    pub fn instance_concepts(&self) -> Vec<String> {
+        self.instances.concepts()
+    }
+
+    /// `Runtime.Config`: the process's configuration with this interpreter's
+    /// observer budget, limits and permissions, and the modules it may use if some
+    /// were taken away.
+    pub fn runtime_config(&self) -> Value {
+        let modules = (!self.denied_modules.is_empty()).then(|| {
+            let mut modules: Vec<String> = self
+                .builtins
+                .iter()
+                .filter(|name| !self.denied_modules.contains(*name))
+                .filter(|name| matches!(self.env.get(name), Some(Value::Map(_))))
+                .cloned()
+                .collect();
+            modules.sort();
+            modules
+        });
+        let permissions = permissions::scope(self.permissions.clone(), permissions::snapshot);
+        let limits = self
+            .limits
+            .as_ref()
+            .map(|tracker| tracker.limits)
+            .unwrap_or_default();
+        config::current().to_value(&self.observer_budget, &limits, modules, &permissions)
+    }
+
+    /// `Reflect.DefineConcept(name, fields, methods)`: parse a concept and
+    /// add it to the running program. Existing concepts can't be replaced,
+    /// since instances and compiled methods depend on them.
+    fn define_concept(&mut self, arguments: &[Expression]) -> Result<Value, RuntimeError> {
+        if arguments.len() < 2 || arguments.len() > 3 {
+            return Err(RuntimeError::Custom(
+                "Reflect.DefineConcept requires 2-3 arguments (name, fields, optional methods)"
+                    .to_string(),
+            ));
+        }
+        let name = self.evaluate_expression(&arguments[0])?.to_display_string();
+        let fields = self.evaluate_expression(&arguments[1])?;
+        let source = match arguments.get(2) {
+            Some(source) => self.evaluate_expression(source)?.to_display_string(),
+            None => String::new(),
+        };
+        if self.concepts.contains_key(&name) {
+            return Err(RuntimeError::Custom(format!(
+                "Reflect.DefineConcept: concept {} is already defined",
+                name
+            )));
+        }
+        let (concept, tracked) = crate::stdlib::reflect::build_concept(&name, &fields, &source)
+            .map_err(RuntimeError::Custom)?;
+        for concept in &tracked {
+            self.instances.track(concept);
+        }
+        self.concepts.insert(name, concept);
+        Ok(Value::Option(Box::new(None)))
+    }
+
+    /// `LLM.Chat(messages, options)`, running each tool call the model makes
+    /// on its Handler: a concept instance's method named after the tool,
+    /// given the arguments by parameter name, or a native function given
+    /// the arguments Map.
+    #[cfg(feature = "llm")]
+    fn llm_chat(&mut self, arguments: &[Expression]) -> Result<Value, RuntimeError> {
+        let mut args = Vec::new();
+        for arg_expr in arguments {
+            args.push(self.evaluate_expression(arg_expr)?);
+        }
+        // An error in a handler stops the chat, and is raised as it was
+        let mut failed = None;
+        let result = crate::stdlib::llm::chat(&args, &mut |handler, name, arguments| {
+            self.call_tool(handler, name, arguments).map_err(|e| {
+                let message = e.to_string();
+                failed = Some(e);
+                message
+            })
+        });
+        match (result, failed) {
+            (Err(_), Some(error)) => Err(error),
+            (result, _) => result.map_err(RuntimeError::Custom),
+        }
+    }
+
+    #[cfg(feature = "llm")]
+    fn call_tool(
+        &mut self,
+        handler: &Value,
+        name: &str,
+        arguments: Value,
+    ) -> Result<Value, RuntimeError> {
+        let concept = match handler {
+            Value::NativeFunction(func) => return call_native(func.as_ref(), vec![arguments]),
+            Value::Map(map) => map
+                .read_recover()
+                .get("_concept")
+                .map(Value::to_display_string),
+            _ => None,
+        };
+        let Some(concept) = concept else {
+            return Err(RuntimeError::TypeError(format!(
+                "The Handler of tool {} must be a concept instance or a function, not {}",
+                name,
+                handler.type_name()
+            )));
+        };
+
+        self.settle_situations()?;
+        let (method_stack, _) = self.method_stack(&concept, name)?;
+        let Some(method) = method_stack.last() else {
+            return Err(RuntimeError::Custom(format!(
+                "Method '{}' not found on concept '{}'",
+                name, concept
+            )));
+        };
+        let given = match &arguments {
+            Value::Map(map) => map.read_recover().clone(),
+            _ => IndexMap::new(),
+        };
+        // Arguments the model left out are False, as JSON null is
+        let args = method
+            .parameters
+            .iter()
+            .map(|param| {
+                let value = given
+                    .get(param)
+                    .or_else(|| {
+                        given
+                            .iter()
+                            .find(|(key, _)| key.eq_ignore_ascii_case(param))
+                            .map(|(_, value)| value)
+                    })
+                    .cloned()
+                    .unwrap_or_else(Value::default_boolean);
+                (param.clone(), value)
+            })
+            .collect();
+        self.execute_method_stack(&method_stack, handler.clone(), args)
+    }
+
+    /// What a background task starts from: a copy of every variable, the
+    /// same This instance, and this interpreter's concepts, situations,
+    /// limits, permissions and warning reporter.
+    fn task_seed(&self) -> TaskSeed {
+        let mut env = self.env.clone_deep();
+        if let Some(this) = self.env.get("This") {
+            env.assign("This", this);
+        }
+        TaskSeed {
+            env,
+            concepts: self.concepts.clone(),
+            situations: self.situations.clone(),
+            active_situations: self.active_situations.clone(),
+            pending_situations: self.pending_situations.clone(),
+            runtime: self.runtime.clone(),
+            // A task has the same limits, counted on its own
+            limits: self.limits.as_ref().map(|tracker| tracker.limits),
+            warnings: self.warnings.as_ref().map(WarningTracker::reporter),
+            file: self.current_file.clone(),
+            permissions: self.permissions.clone(),
+        }
+    }
+
+    /// `Task.Pool(size)` and `Task.ParallelMap(list, handler, workers?)`,
+    /// whose handlers may be concept methods. Each worker runs them in an
+    /// interpreter of its own, started like a `Do in background` task.
+    fn task_workers(
+        &mut self,
+        member: &str,
+        arguments: &[Expression],
+    ) -> Result<Value, RuntimeError> {
+        let mut args = Vec::new();
+        for arg_expr in arguments {
+            args.push(self.evaluate_expression(arg_expr)?);
+        }
+        let seed = std::sync::Mutex::new(self.task_seed());
+        let workers: stdlib::task::WorkerFactory = Arc::new(move || {
+            let mut worker = seed.lock_recover().fork().interpreter();
+            Box::new(move |handler, args| worker.call_handler(handler, args))
+        });
+        let result = match member {
+            "Pool" => stdlib::task::pool(&args, &self.runtime, workers),
+            _ => stdlib::task::parallel_map(&args, &self.runtime, workers),
+        };
+        result.map_err(RuntimeError::Custom)
+    }
+
+    /// Call a Task handler: a function, or `[instance, "Method"]`.
+    fn call_handler(&mut self, handler: &Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let (instance, method) = match handler {
+            Value::NativeFunction(func) => return call_native(func.as_ref(), args),
+            Value::List(pair) => match pair.read_recover().as_slice() {
+                [instance, Value::String(method)] => (instance.clone(), method.clone()),
+                _ => (Value::default_boolean(), String::new()),
+            },
+            _ => (Value::default_boolean(), String::new()),
+        };
+        let concept = match &instance {
+            Value::Map(map) => map
+                .read_recover()
+                .get("_concept")
+                .map(Value::to_display_string),
+            _ => None,
+        };
+        let Some(concept) = concept else {
+            return Err(RuntimeError::TypeError(
+                "A handler must be a function, or a List of an instance and a method name"
+                    .to_string(),
+            ));
+        };
+
+        self.settle_situations()?;
+        let (method_stack, _) = self.method_stack(&concept, &method)?;
+        if method_stack.is_empty() {
+            return Err(RuntimeError::Custom(format!(
+                "Method '{}' not found on concept '{}'",
+                method, concept
+            )));
+        }
+        let args = args
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (format!("arg{}", i), value))
+            .collect();
+        self.execute_method_stack(&method_stack, instance, args)
+    }
+
+    fn is_runtime_global(expression: &Expression) -> bool {
+        matches!(expression, Expression::Identifier(name) if name == "Runtime")
+    }
+
+    /// Walk every value reachable from script variables (see `System.MemoryStats`).
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport::collect(
+            self.env
+                .bindings()
+                .filter(|(name, _)| !self.builtins.contains(*name)),
+        )
+    }
+
+    fn record_set(&mut self, target: String, old: Option<Value>, new: &Value) {
+        if let Some(timeline) = self.timeline.as_mut() {
+            timeline.record(
+                self.current_line,
+                target,
+                old,
+                new.clone(),
+                !self.observer_runs.is_empty(),
+            );
+        }
+    }
+
     pub fn run(&mut self, program: Program) -> Result<(), RuntimeError> {
+        for concept in &program.tracked_concepts {
+            self.instances.track(concept);
+        }
         for concept in program.concepts {
             self.concepts.insert(concept.name.clone(), concept);
         }
         for situation in program.situations {
-            self.situations.insert(situation.name.clone(), situation);
+            self.register_situation(situation);
+        }
+        if let Some(limits) = self.limits.as_mut() {
+            limits.restart();
         }
 
-        self.execute_story(&program.story)?;
-        Ok(())
+        let result = self.with_permissions(|interpreter| interpreter.execute_story(&program.story));
+        if let Some(tracker) = self.usage.as_mut() {
+            tracker.flush();
+        }
+        result
     }
 
     fn execute_story(&mut self, story: &Story) -> Result<(), RuntimeError> {
@@ -194,28 +963,73 @@ impl Interpreter {
             ))
         })?;
 
-        let mut lexer = crate::compiler::lexer::Lexer::new(&source);
-        let tokens = lexer.tokenize().map_err(|e| {
-            RuntimeError::Custom(format!("Lexer error in module '{}': {}", path, e))
-        })?;
+        let file = module_file_name(&resolved, &cwd);
+
+        // A package keeps the edition of its own project
+        let edition = crate::project::edition_for(&resolved).map_err(RuntimeError::Custom)?;
+        let program = match crate::compiler::cache::lookup(&resolved, &source, edition) {
+            Some(program) => program,
+            None => {
+                let mut lexer = crate::compiler::lexer::Lexer::with_edition(&source, edition);
+                let tokens = lexer
+                    .tokenize()
+                    .map_err(|e| RuntimeError::Custom(format!("Lexer error in {}: {}", file, e)))?;
+
+                let mut parser = crate::compiler::parser::Parser::with_edition(tokens, edition);
+                parser
+                    .parse()
+                    .map_err(|e| RuntimeError::Custom(format!("Parser error in {}: {}", file, e)))?
+            }
+        };
 
-        let mut parser = crate::compiler::parser::Parser::new(tokens);
-        let program = parser.parse().map_err(|e| {
-            RuntimeError::Custom(format!("Parser error in module '{}': {}", path, e))
-        })?;
+        for concept in &program.tracked_concepts {
+            self.instances.track(concept);
+        }
 
-        for concept in program.concepts {
-            if self.concepts.contains_key(&concept.name) {}
+        // Concepts and adjustments remember their module, so errors in
+        // their methods name it wherever they are called from
+        let stamp = |methods: &mut Vec<Method>| {
+            for method in methods {
+                method.file = Some(file.to_string());
+            }
+        };
+        for mut concept in program.concepts {
+            concept.file = Some(file.to_string());
+            stamp(&mut concept.methods);
             self.concepts.insert(concept.name.clone(), concept);
         }
 
-        for situation in program.situations {
-            self.situations.insert(situation.name.clone(), situation);
+        for mut situation in program.situations {
+            for adjustment in &mut situation.adjustments {
+                stamp(&mut adjustment.methods);
+            }
+            self.register_situation(situation);
         }
 
-        self.execute_story(&program.story)?;
+        let caller = self.current_file.replace(file);
+        let result = self.execute_story(&program.story);
+        self.current_file = caller;
+        result
+    }
+
+    /// Where the running statement is.
+    fn location(&self) -> SourceLine {
+        SourceLine {
+            file: self.current_file.clone(),
+            line: self.current_line,
+        }
+    }
 
-        Ok(())
+    /// Run `body` as code from `file`, the module a concept or method came
+    /// from.
+    fn in_file<T>(&mut self, file: Option<&str>, body: impl FnOnce(&mut Self) -> T) -> T {
+        if self.current_file.as_deref() == file {
+            return body(self);
+        }
+        let caller = std::mem::replace(&mut self.current_file, file.map(Arc::from));
+        let result = body(self);
+        self.current_file = caller;
+        result
     }
 
     fn execute_statement(&mut self, stmt: &Statement) -> Result<ExecutionResult, RuntimeError> {
@@ -230,60 +1044,167 @@ impl Interpreter {
                 initial_fields,
                 ..
             } => {
-                let concept = self
-                    .concepts
-                    .get(concept_name)
-                    .ok_or_else(|| RuntimeError::UndefinedConcept(concept_name.clone()))?
-                    .clone();
+                let chain: Vec<Concept> = self
+                    .concept_chain(concept_name)?
+                    .into_iter()
+                    .cloned()
+                    .collect();
 
-                let mut instance_data = HashMap::new();
+                let mut instance_data = IndexMap::new();
                 instance_data.insert("_concept".to_string(), Value::String(concept_name.clone()));
 
-                for field in &concept.fields {
-                    instance_data.insert(field.clone(), Value::default_number());
+                for concept in &chain {
+                    for field in &concept.fields {
+                        let value = match concept.defaults.get(field) {
+                            Some(default) => self.evaluate_expression(default)?,
+                            None => Value::default_number(),
+                        };
+                        instance_data.insert(field.clone(), value);
+                    }
                 }
 
-                let instance =
-                    Value::Map(std::sync::Arc::new(std::sync::RwLock::new(instance_data)));
+                let map = Arc::new(RwLock::new(instance_data));
+                let instance = Value::Map(map.clone());
 
-                // Store the instance (use shallow clone so we can modify it afterwards)
-                if !self.env.assign(instance_name, instance.clone()) {
-                    self.env.define(instance_name.clone(), instance.clone());
+                // Set initial field values if provided (modifies the shared Arc)
+                let mut replaced = Vec::new();
+                for (field_name, field_expr) in initial_fields {
+                    let field_value = self.evaluate_expression(field_expr)?;
+                    let old = map.write_recover().insert(field_name.clone(), field_value);
+                    replaced.push((field_name, old.unwrap_or_else(Value::default_number)));
                 }
 
-                // Set initial field values if provided (modifies the shared Arc)
-                if let Value::Map(m) = &instance {
-                    for (field_name, field_expr) in initial_fields {
-                        let field_value = self.evaluate_expression(field_expr)?;
-                        m.write()
-                            .expect("lock poisoned")
-                            .insert(field_name.clone(), field_value);
+                // Parents initialize first; an error leaves no instance behind.
+                // Requirements hold once the hooks are done, not during them.
+                let key = map_address(&map);
+                self.constructing.push(key);
+                let created = chain.iter().try_for_each(|concept| {
+                    self.run_lifecycle_hook(
+                        &concept.when_created,
+                        concept.file.as_deref(),
+                        &instance,
+                    )
+                });
+                self.constructing.retain(|k| *k != key);
+                created?;
+                self.check_requirements(&instance, &chain)?;
+
+                // `When Price changes, including Create:` sees the given value
+                for (field_name, old) in replaced {
+                    let on_create = chain
+                        .iter()
+                        .rev()
+                        .find(|concept| concept.when_observers.contains_key(field_name))
+                        .is_some_and(|concept| concept.observed_on_create.contains(field_name));
+                    if on_create {
+                        let owner = self.timeline.as_ref().map(|_| instance_name.clone());
+                        self.observe_change(&instance, field_name, old, owner)?;
                     }
                 }
 
+                // An instance is also one of each concept it extends
+                for concept in &chain {
+                    self.instances.register(&concept.name, &instance);
+                }
+
+                if !self.env.assign(instance_name, instance.clone()) {
+                    self.env.define(instance_name.clone(), instance);
+                }
+
                 Ok(ExecutionResult::Done)
             }
 
-            Statement::Assignment { target, value, .. } => {
-                let val = self.evaluate_expression(value)?;
-                if !self.env.assign(target, val.clone_deep()) {
-                    self.env.define(target.clone(), val.clone_deep());
+            Statement::Destroy { name, .. } => {
+                let instance = self
+                    .env
+                    .get(name)
+                    .ok_or_else(|| RuntimeError::UndefinedVariable(name.clone()))?;
+                let concept_name = match &instance {
+                    Value::Map(m) => m.read_recover().get("_concept").map(|v| v.to_string()),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    RuntimeError::TypeError(format!(
+                        "Destroy needs a concept instance, but '{}' is a value of type {}",
+                        name,
+                        instance.type_name()
+                    ))
+                })?;
+                let chain: Vec<Concept> = self
+                    .concept_chain(&concept_name)?
+                    .into_iter()
+                    .cloned()
+                    .collect();
+
+                // Children clean up before their parents
+                for concept in chain.iter().rev() {
+                    self.run_lifecycle_hook(
+                        &concept.when_destroyed,
+                        concept.file.as_deref(),
+                        &instance,
+                    )?;
+                }
+                for concept in &chain {
+                    self.instances.unregister(&concept.name, &instance);
+                }
+                self.env.remove(name);
+
+                Ok(ExecutionResult::Done)
+            }
+
+            Statement::Assignment {
+                target,
+                value,
+                copy,
+                ..
+            } => {
+                let mut val = self.evaluate_expression(value)?;
+                if *copy {
+                    val = val.clone_deep();
+                }
+                if self.timeline.is_some() {
+                    let old = self.env.get(target);
+                    self.record_set(target.clone(), old, &val);
+                }
+                if !self.env.assign(target, val.clone()) {
+                    if self.warnings.is_some() {
+                        self.warn_new_variable(target);
+                    }
+                    self.env.define(target.clone(), val);
                 }
                 Ok(ExecutionResult::Done)
             }
 
-            Statement::Set { target, value, .. } => {
+            Statement::Set {
+                target,
+                value,
+                silent,
+                ..
+            } => {
                 let val = self.evaluate_expression(value)?;
                 match target {
                     Expression::Identifier(name) => {
+                        if self.timeline.is_some() {
+                            let old = self.env.get(name).or_else(|| match self.env.get("This") {
+                                Some(Value::Map(m)) => m.read_recover().get(name).cloned(),
+                                _ => None,
+                            });
+                            self.record_set(name.clone(), old, &val);
+                        }
                         if self.env.assign(name, val.clone()) {
                         } else {
                             let this_val = self.env.get("This");
                             let mut updated = false;
 
                             if let Some(Value::Map(m)) = this_val {
-                                if m.read().expect("lock poisoned").contains_key(name) {
-                                    m.write().expect("lock poisoned").insert(name.clone(), val);
+                                if m.read_recover().contains_key(name) {
+                                    if Value::Map(m.clone()).is_frozen() {
+                                        return Err(RuntimeError::TypeError(format!(
+                                            "Cannot set '{}': the value is frozen",
+                                            name
+                                        )));
+                                    }
+                                    self.set_field(&m, name, val)?;
                                     updated = true;
                                 }
                             }
@@ -294,49 +1215,42 @@ impl Interpreter {
                         }
                     }
                     Expression::MemberAccess { object, member } => {
+                        if Self::is_runtime_global(object)
+                            || matches!(object.as_ref(), Expression::MemberAccess { object, .. } if Self::is_runtime_global(object))
+                        {
+                            return Err(RuntimeError::TypeError(format!(
+                                "Cannot set '{}': Runtime.Config is read-only",
+                                member
+                            )));
+                        }
                         let obj_val = self.evaluate_expression(object)?;
+                        if obj_val.is_frozen() {
+                            return Err(RuntimeError::TypeError(format!(
+                                "Cannot set '{}': the value is frozen",
+                                member
+                            )));
+                        }
                         if let Value::Map(m) = obj_val.clone() {
-                            m.write()
-                                .expect("lock poisoned")
-                                .insert(member.clone(), val);
-
-                            const MAX_OBSERVER_DEPTH: usize = 10;
-                            if self.observer_depth < MAX_OBSERVER_DEPTH {
-                                let concept_name = {
-                                    let map_read = m.read().expect("lock poisoned");
-                                    map_read.get("_concept").and_then(|v| {
-                                        if let Value::String(s) = v {
-                                            Some(s.clone())
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                };
-
-                                if let Some(c_name) = concept_name {
-                                    if let Some(concept) = self.concepts.get(&c_name).cloned() {
-                                        if let Some(observer_body) =
-                                            concept.when_observers.get(member)
-                                        {
-                                            let observer_code = observer_body.clone();
-
-                                            self.observer_depth += 1;
-                                            self.env.push_scope();
-                                            self.env.define("This".to_string(), obj_val.clone());
-
-                                            let _ = self.execute_block_no_scope(&observer_code)?;
-
-                                            self.env.pop_scope();
-                                            self.observer_depth -= 1;
-                                        }
-                                    }
-                                }
-                            } else {
-                                return Err(
-                                    RuntimeError::Custom(
-                                        "When observer recursion limit reached (infinite loop detected)".to_string()
-                                    )
-                                );
+                            let owner =
+                                self.timeline
+                                    .as_ref()
+                                    .map(|timeline| match object.as_ref() {
+                                        Expression::Identifier(name) => timeline.owner_name(name),
+                                        _ => "<object>".to_string(),
+                                    });
+                            if let Some(owner) = &owner {
+                                let old = m.read_recover().get(member).cloned();
+                                self.record_set(format!("{}.{}", owner, member), old, &val);
+                            }
+                            let old = m.read_recover().get(member).cloned();
+                            self.set_field(&m, member, val)?;
+                            if !*silent {
+                                self.observe_change(
+                                    &obj_val,
+                                    member,
+                                    old.unwrap_or_else(Value::default_number),
+                                    owner,
+                                )?;
                             }
                         } else {
                             return Err(RuntimeError::TypeError(
@@ -353,11 +1267,20 @@ impl Interpreter {
 
             Statement::Print { value, .. } => {
                 let val = self.evaluate_expression(value)?;
-                println!("{}", val);
+                match self.output.as_mut() {
+                    Some(output) => {
+                        output.push_str(&val.to_string());
+                        output.push('\n');
+                    }
+                    None => println!("{}", val),
+                }
                 Ok(ExecutionResult::Done)
             }
 
             Statement::SwitchOn { situation, .. } => {
+                self.count_usage(|usage| usage.situations += 1);
+                // Switching by hand overrides a `when` condition until the next Reevaluate
+                self.pending_situations.retain(|s| s != situation);
                 if !self.active_situations.contains(situation) {
                     self.active_situations.push(situation.clone());
                 }
@@ -365,10 +1288,63 @@ impl Interpreter {
             }
 
             Statement::SwitchOff { situation, .. } => {
+                self.pending_situations.retain(|s| s != situation);
                 self.active_situations.retain(|s| s != situation);
                 Ok(ExecutionResult::Done)
             }
 
+            Statement::ReevaluateSituations { .. } => {
+                let mut conditional: Vec<String> = self
+                    .situations
+                    .values()
+                    .filter(|situation| situation.condition.is_some())
+                    .map(|situation| situation.name.clone())
+                    .collect();
+                conditional.sort();
+                self.pending_situations = conditional;
+                self.settle_situations()?;
+                Ok(ExecutionResult::Done)
+            }
+
+            Statement::WithSituation {
+                situation, body, ..
+            } => {
+                self.count_usage(|usage| usage.situations += 1);
+                let was_active = self.active_situations.contains(situation);
+                let was_pending = self.pending_situations.contains(situation);
+                self.pending_situations.retain(|s| s != situation);
+                if !was_active {
+                    self.active_situations.push(situation.clone());
+                }
+
+                let result = self.execute_block(body);
+
+                if !was_active {
+                    self.active_situations.retain(|s| s != situation);
+                }
+                if was_pending {
+                    self.pending_situations.push(situation.clone());
+                }
+                result
+            }
+
+            Statement::Batch { body, .. } => {
+                self.batch_depth += 1;
+                let result = self.execute_block(body);
+                self.batch_depth -= 1;
+                // A nested Batch leaves its changes to the outer one, and a
+                // Batch that fails drops them
+                if self.batch_depth > 0 {
+                    return result;
+                }
+                let deferred = std::mem::take(&mut self.deferred_changes);
+                let result = result?;
+                for change in deferred {
+                    self.run_observer(&change.instance, &change.member, change.old, change.owner)?;
+                }
+                Ok(result)
+            }
+
             Statement::If {
                 condition,
                 then_body,
@@ -392,11 +1368,32 @@ impl Interpreter {
             } => {
                 let target_value = self.evaluate_expression(value)?;
 
-                for (match_expr, body) in cases {
-                    let match_value = self.evaluate_expression(match_expr)?;
+                for case in cases {
+                    let mut bindings = Vec::new();
+                    if !self.match_pattern(&case.pattern, &target_value, &mut bindings)? {
+                        continue;
+                    }
+
+                    // Names bound by the pattern are visible in the guard and body
+                    self.env.push_scope();
+                    for (name, value) in bindings {
+                        self.env.define(name, value);
+                    }
+                    let result = match &case.guard {
+                        Some(guard) => self.evaluate_expression(guard).map(|v| v.is_truthy()),
+                        None => Ok(true),
+                    }
+                    .and_then(|pass| {
+                        if pass {
+                            self.execute_block(&case.body).map(Some)
+                        } else {
+                            Ok(None)
+                        }
+                    });
+                    self.env.pop_scope();
 
-                    if target_value.equals(&match_value) {
-                        return self.execute_block(body);
+                    if let Some(result) = result? {
+                        return Ok(result);
                     }
                 }
 
@@ -409,59 +1406,14 @@ impl Interpreter {
 
             Statement::TryCatch {
                 try_body,
-                catch_var,
-                catch_body,
+                catches,
                 always_body,
                 ..
             } => {
                 let try_result = self.execute_block(try_body);
 
                 let final_result = match try_result {
-                    Err(err) => {
-                        if let Some(catch) = catch_body {
-                            if let Some(var_name) = catch_var {
-                                let (error_type, error_message) = match &err {
-                                    RuntimeError::UndefinedVariable(s) => {
-                                        ("UndefinedVariable", s.clone())
-                                    }
-                                    RuntimeError::UndefinedConcept(s) => {
-                                        ("UndefinedConcept", s.clone())
-                                    }
-                                    RuntimeError::UndefinedMethod(s) => {
-                                        ("UndefinedMethod", s.clone())
-                                    }
-                                    RuntimeError::TypeError(s) => ("TypeError", s.clone()),
-                                    RuntimeError::IndexError(s) => ("IndexError", s.clone()),
-                                    RuntimeError::Custom(s) => ("Custom", s.clone()),
-                                };
-
-                                let mut error_map = HashMap::new();
-                                error_map.insert(
-                                    "type".to_string(),
-                                    Value::String(error_type.to_string()),
-                                );
-                                error_map
-                                    .insert("message".to_string(), Value::String(error_message));
-                                error_map.insert(
-                                    "line".to_string(),
-                                    Value::Number(bigdecimal::BigDecimal::from(
-                                        self.current_line as i64,
-                                    )),
-                                );
-
-                                self.env.define(
-                                    var_name.clone(),
-                                    Value::Map(std::sync::Arc::new(std::sync::RwLock::new(
-                                        error_map,
-                                    ))),
-                                );
-                            }
-
-                            self.execute_block(catch)
-                        } else {
-                            Err(err)
-                        }
-                    }
+                    Err(err) => self.catch_error(err, catches),
                     Ok(result) => Ok(result),
                 };
 
@@ -477,6 +1429,50 @@ impl Interpreter {
                 final_result
             }
 
+            Statement::Using {
+                name,
+                resource,
+                body,
+                ..
+            } => {
+                let resource = self.evaluate_expression(resource)?;
+                if !self.has_close(&resource) {
+                    return Err(RuntimeError::TypeError(format!(
+                        "Using needs something with a Close method, got {}",
+                        resource.type_name()
+                    )));
+                }
+
+                self.env.push_scope();
+                self.env.define(name.clone(), resource.clone());
+                let result = self.execute_block_no_scope(body);
+                self.env.pop_scope();
+
+                // Close runs however the block ended; an error from the
+                // block wins over one from Close
+                let closed = self.close(resource);
+                let result = result?;
+                closed?;
+                Ok(result)
+            }
+
+            Statement::Raise {
+                error,
+                message,
+                line,
+            } => {
+                let error = self.evaluate_expression(error)?;
+                let message = match message {
+                    Some(message) => Some(self.evaluate_expression(message)?.to_display_string()),
+                    None => None,
+                };
+                let at = SourceLine {
+                    file: self.current_file.clone(),
+                    line: *line,
+                };
+                Err(Self::raised_error(error, message, at)?)
+            }
+
             Statement::RepeatTimes {
                 count,
                 variable,
@@ -484,41 +1480,43 @@ impl Interpreter {
                 ..
             } => {
                 let count_val = self.evaluate_expression(count)?;
-                if let Value::Number(n) = count_val {
-                    use bigdecimal::ToPrimitive;
-                    if let Some(times) = n.to_i64() {
-                        for i in 0..times {
-                            if let Some(var_name) = variable {
-                                self.env.push_scope();
-                                let loop_index = Value::Number(bigdecimal::BigDecimal::from(i + 1));
-                                self.env.define(var_name.clone(), loop_index);
-                                let result = self.execute_block_no_scope(body)?;
-                                self.env.pop_scope();
-                                match result {
-                                    ExecutionResult::Break => {
-                                        break;
-                                    }
-                                    ExecutionResult::Return(v) => {
-                                        return Ok(ExecutionResult::Return(v));
-                                    }
-                                    ExecutionResult::Continue => {
-                                        continue;
-                                    }
-                                    ExecutionResult::Done => {}
+                let count = match &count_val {
+                    Value::Number(n) => n.to_i64(),
+                    Value::Integer(i) => i.to_i64(),
+                    _ => None,
+                };
+                if let Some(times) = count {
+                    for i in 0..times {
+                        if let Some(var_name) = variable {
+                            self.env.push_scope();
+                            let loop_index = Value::Integer((i + 1).into());
+                            self.env.define(var_name.clone(), loop_index);
+                            let result = self.execute_block_no_scope(body)?;
+                            self.env.pop_scope();
+                            match result {
+                                ExecutionResult::Break => {
+                                    break;
                                 }
-                            } else {
-                                match self.execute_block(body)? {
-                                    ExecutionResult::Break => {
-                                        break;
-                                    }
-                                    ExecutionResult::Return(v) => {
-                                        return Ok(ExecutionResult::Return(v));
-                                    }
-                                    ExecutionResult::Continue => {
-                                        continue;
-                                    }
-                                    ExecutionResult::Done => {}
+                                ExecutionResult::Return(v) => {
+                                    return Ok(ExecutionResult::Return(v));
+                                }
+                                ExecutionResult::Continue => {
+                                    continue;
+                                }
+                                ExecutionResult::Done => {}
+                            }
+                        } else {
+                            match self.execute_block(body)? {
+                                ExecutionResult::Break => {
+                                    break;
+                                }
+                                ExecutionResult::Return(v) => {
+                                    return Ok(ExecutionResult::Return(v));
+                                }
+                                ExecutionResult::Continue => {
+                                    continue;
                                 }
+                                ExecutionResult::Done => {}
                             }
                         }
                     }
@@ -552,33 +1550,41 @@ impl Interpreter {
 
             Statement::ForEach {
                 variable,
+                value_variable,
                 iterable,
                 body,
                 ..
             } => {
-                let collection = self.evaluate_expression(iterable)?;
+                let items: Box<dyn Iterator<Item = (Value, Option<Value>)>> = match iterable {
+                    Expression::Range { start, end } => {
+                        let start = self.evaluate_expression(start)?;
+                        let end = self.evaluate_expression(end)?;
+                        Box::new(Self::range_values(&start, &end)?.map(|item| (item, None)))
+                    }
+                    _ => {
+                        let collection = self.evaluate_expression(iterable)?;
 
-                if let Value::Map(map) = &collection {
-                    let has_next = map.read().expect("lock poisoned").contains_key("Next");
-                    let has_hasmore = map.read().expect("lock poisoned").contains_key("HasMore");
+                        if let Value::Map(map) = &collection
+                            && value_variable.is_none()
+                        {
+                            let has_next = map.read_recover().contains_key("Next");
+                            let has_hasmore = map.read_recover().contains_key("HasMore");
 
-                    if has_next && has_hasmore {
-                        return self.iterate_stream(variable, collection, body);
-                    }
-                }
+                            if has_next && has_hasmore {
+                                return self.iterate_stream(variable, collection, body);
+                            }
+                        }
 
-                let items: Vec<Value> = match collection {
-                    Value::List(l) => l.read().expect("lock poisoned").iter().cloned().collect(),
-                    _ => {
-                        return Err(RuntimeError::TypeError(
-                            "Expected a list or stream".to_string(),
-                        ));
+                        Box::new(Self::iteration_items(collection, value_variable.is_some())?)
                     }
                 };
 
-                for item in items {
+                for (item, value) in items {
                     self.env.push_scope();
                     self.env.define(variable.clone(), item);
+                    if let (Some(name), Some(value)) = (value_variable, value) {
+                        self.env.define(name.clone(), value);
+                    }
                     let result = self.execute_block_no_scope(body)?;
                     self.env.pop_scope();
                     match result {
@@ -615,6 +1621,88 @@ impl Interpreter {
         }
     }
 
+    /// `1 to 5` counts up and `5 to 1` counts down, giving Integers when
+    /// both ends are Integers and Numbers otherwise.
+    fn range_values(
+        start: &Value,
+        end: &Value,
+    ) -> Result<impl Iterator<Item = Value> + use<>, RuntimeError> {
+        let bound = |value: &Value| {
+            value.as_integer().and_then(|n| n.to_i64()).ok_or_else(|| {
+                RuntimeError::TypeError(format!(
+                    "A range needs whole numbers, got {}",
+                    value.to_display_string()
+                ))
+            })
+        };
+        let (first, last) = (bound(start)?, bound(end)?);
+        let integers = matches!((start, end), (Value::Integer(_), Value::Integer(_)));
+
+        let counting: Box<dyn Iterator<Item = i64>> = if first <= last {
+            Box::new(first..=last)
+        } else {
+            Box::new((last..=first).rev())
+        };
+        Ok(counting.map(move |n| {
+            if integers {
+                Value::Integer(n.into())
+            } else {
+                Value::Number(bigdecimal::BigDecimal::from(n))
+            }
+        }))
+    }
+
+    /// What `For each` goes through: a List's items, a String's characters,
+    /// or a Map's entries in key order, each as a [key, value] pair or, with
+    /// two loop variables, as the key and the value.
+    fn iteration_items(
+        collection: Value,
+        key_and_value: bool,
+    ) -> Result<std::vec::IntoIter<(Value, Option<Value>)>, RuntimeError> {
+        let items: Vec<(Value, Option<Value>)> = match collection {
+            Value::Map(map) => {
+                let map = map.read_recover();
+                let mut keys: Vec<&String> = map.keys().filter(|key| *key != "_concept").collect();
+                keys.sort();
+                keys.into_iter()
+                    .map(|key| {
+                        let (key_value, value) = (Value::String(key.clone()), map[key].clone());
+                        if key_and_value {
+                            (key_value, Some(value))
+                        } else {
+                            let pair = vec![key_value, value];
+                            (Value::List(Arc::new(RwLock::new(pair))), None)
+                        }
+                    })
+                    .collect()
+            }
+            _ if key_and_value => {
+                return Err(RuntimeError::TypeError(format!(
+                    "For each with a key and a value needs a Map, got {}",
+                    collection.type_name()
+                )));
+            }
+            Value::List(l) => l
+                .read_recover()
+                .iter()
+                .map(|item| (item.clone(), None))
+                .collect(),
+            Value::String(s) => {
+                use unicode_segmentation::UnicodeSegmentation;
+                s.graphemes(true)
+                    .map(|character| (Value::String(character.to_string()), None))
+                    .collect()
+            }
+            _ => {
+                return Err(RuntimeError::TypeError(format!(
+                    "Expected a list, map, string or stream, got {}",
+                    collection.type_name()
+                )));
+            }
+        };
+        Ok(items.into_iter())
+    }
+
     fn iterate_stream(
         &mut self,
         variable: &str,
@@ -623,7 +1711,7 @@ impl Interpreter {
     ) -> Result<ExecutionResult, RuntimeError> {
         loop {
             let next_method = if let Value::Map(map) = &stream {
-                map.read().expect("lock poisoned").get("Next").cloned()
+                map.read_recover().get("Next").cloned()
             } else {
                 return Err(RuntimeError::TypeError("Invalid stream object".to_string()));
             };
@@ -633,7 +1721,7 @@ impl Interpreter {
             })?;
 
             let next_value = match next_method {
-                Value::NativeFunction(f) => f(vec![]).map_err(|e| RuntimeError::Custom(e))?,
+                Value::NativeFunction(f) => call_native(f.as_ref(), vec![])?,
                 _ => {
                     return Err(RuntimeError::TypeError(
                         "Stream.Next must be a function".to_string(),
@@ -672,76 +1760,720 @@ impl Interpreter {
                 }
             }
         }
-        Ok(ExecutionResult::Done)
+        Ok(ExecutionResult::Done)
+    }
+
+    /// Whether `value` matches a When pattern, collecting the names it binds.
+    /// Values of the wrong shape or type just don't match.
+    fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: &Value,
+        bindings: &mut Vec<(String, Value)>,
+    ) -> Result<bool, RuntimeError> {
+        match pattern {
+            Pattern::Value(expr) => Ok(value.equals(&self.evaluate_expression(expr)?)),
+            Pattern::Between(low, high) => {
+                let low = self.evaluate_expression(low)?;
+                let high = self.evaluate_expression(high)?;
+                Ok(value.compare(&low).is_ok_and(|o| o.is_ge())
+                    && value.compare(&high).is_ok_and(|o| o.is_le()))
+            }
+            Pattern::Type(type_name) => Ok(match (type_name.as_str(), value) {
+                ("Number", Value::Number(_) | Value::Integer(_) | Value::FastNumber(_)) => true,
+                (_, Value::Map(map))
+                    if map.read_recover().get("_concept").is_some_and(|concept| {
+                        // An instance is also one of the concepts it extends
+                        self.concept_chain(&concept.to_display_string())
+                            .is_ok_and(|chain| chain.iter().any(|c| c.name == *type_name))
+                    }) =>
+                {
+                    true
+                }
+                _ => value.type_name() == type_name,
+            }),
+            Pattern::Bind(name) => {
+                if name != "_" {
+                    bindings.push((name.clone(), value.clone()));
+                }
+                Ok(true)
+            }
+            Pattern::List { items, rest } => {
+                let Value::List(list) = value else {
+                    return Ok(false);
+                };
+                let list = list.read_recover().clone();
+                let fits = match rest {
+                    Some(_) => list.len() >= items.len(),
+                    None => list.len() == items.len(),
+                };
+                if !fits {
+                    return Ok(false);
+                }
+                for (item, value) in items.iter().zip(&list) {
+                    if !self.match_pattern(item, value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                if let Some(rest) = rest.as_ref().filter(|rest| *rest != "_") {
+                    let remaining = list[items.len()..].to_vec();
+                    bindings.push((
+                        rest.clone(),
+                        Value::List(Arc::new(std::sync::RwLock::new(remaining))),
+                    ));
+                }
+                Ok(true)
+            }
+            Pattern::Map(entries) => {
+                let Value::Map(map) = value else {
+                    return Ok(false);
+                };
+                for (key, pattern) in entries {
+                    let field = map.read_recover().get(key).cloned();
+                    match field {
+                        Some(field) if self.match_pattern(pattern, &field, bindings)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Run the first Catch whose `when` holds for `err`, or pass `err` on
+    /// when none does.
+    fn catch_error(
+        &mut self,
+        err: RuntimeError,
+        catches: &[CatchClause],
+    ) -> Result<ExecutionResult, RuntimeError> {
+        if matches!(err, RuntimeError::LimitExceeded(_)) {
+            return Err(err);
+        }
+        for clause in catches {
+            if let Some(var_name) = &clause.var {
+                let caught = self.caught_error(&err);
+                self.env.define(var_name.clone(), caught);
+            }
+            if let Some(condition) = &clause.condition
+                && !self.evaluate_expression(condition)?.is_truthy()
+            {
+                continue;
+            }
+            return self.execute_block(&clause.body);
+        }
+        Err(err)
+    }
+
+    // What a Catch variable holds: `type` is the error's name for built-in
+    // errors and its category for raised ones
+    fn caught_error(&self, err: &RuntimeError) -> Value {
+        let info = err.to_error_info();
+        let here = self.location();
+        let (error_type, message, at) = match err {
+            RuntimeError::UndefinedVariable(s) => ("UndefinedVariable", s, &here),
+            RuntimeError::UndefinedConcept(s) => ("UndefinedConcept", s, &here),
+            RuntimeError::UndefinedMethod(s) => ("UndefinedMethod", s, &here),
+            RuntimeError::TypeError(s) => ("TypeError", s, &here),
+            RuntimeError::IndexError(s) => ("IndexError", s, &here),
+            RuntimeError::Custom(s) => ("Custom", s, &here),
+            RuntimeError::LimitExceeded(s) => ("LimitExceeded", s, &here),
+            RuntimeError::Raised(info, at) => (info.category.as_str(), &info.message, at),
+            RuntimeError::Rethrown(err) => return self.caught_error(err),
+        };
+
+        let mut error_map = IndexMap::new();
+        error_map.insert("type".to_string(), Value::String(error_type.to_string()));
+        error_map.insert("message".to_string(), Value::String(message.clone()));
+        error_map.insert(
+            "line".to_string(),
+            Value::Number(bigdecimal::BigDecimal::from(at.line as i64)),
+        );
+        // Only errors from a module loaded with `Use` have a file
+        if let Some(file) = &at.file {
+            error_map.insert("file".to_string(), Value::String(file.to_string()));
+        }
+        error_map.insert("category".to_string(), Value::String(info.category.clone()));
+        error_map.insert("subtype".to_string(), Value::String(info.subtype.clone()));
+        error_map.insert("error".to_string(), Value::Error(info));
+        Value::Map(Arc::new(RwLock::new(error_map)))
+    }
+
+    /// The error a `Raise` throws: a new one from a "Category.Subtype" name
+    /// or an Error value, or a caught one again with its original line.
+    fn raised_error(
+        error: Value,
+        message: Option<String>,
+        at: SourceLine,
+    ) -> Result<RuntimeError, RuntimeError> {
+        let info = match error {
+            Value::String(name) => {
+                let Some((category, subtype)) = name
+                    .split_once('.')
+                    .filter(|(category, subtype)| !category.is_empty() && !subtype.is_empty())
+                else {
+                    return Err(RuntimeError::TypeError(format!(
+                        "Raise needs a \"Category.Subtype\" name, got \"{}\"",
+                        name
+                    )));
+                };
+                ErrorInfo {
+                    category: category.to_string(),
+                    subtype: subtype.to_string(),
+                    message: message.unwrap_or(name),
+                }
+            }
+            Value::Error(info) => match message {
+                Some(message) => ErrorInfo {
+                    message,
+                    ..info.as_ref().clone()
+                },
+                None => return Ok(RuntimeError::Raised(info, at)),
+            },
+            Value::Map(map) if message.is_none() => {
+                let map = map.read_recover();
+                let field = |name: &str| match map.get(name) {
+                    Some(Value::String(text)) => Some(text.clone()),
+                    _ => None,
+                };
+                let (Some(error_type), Some(text)) = (field("type"), field("message")) else {
+                    return Err(Self::not_an_error());
+                };
+                // A raised error's type is its category; a built-in one's isn't
+                if let Some(Value::Error(info)) = map.get("error")
+                    && info.category == error_type
+                {
+                    let raised_at = match map.get("line") {
+                        Some(line) => SourceLine {
+                            file: match map.get("file") {
+                                Some(Value::String(file)) => Some(Arc::from(file.as_str())),
+                                _ => None,
+                            },
+                            line: Self::value_to_f64(line).map_or(at.line, |line| line as usize),
+                        },
+                        None => at,
+                    };
+                    let info = ErrorInfo {
+                        message: text,
+                        ..info.as_ref().clone()
+                    };
+                    return Ok(RuntimeError::Raised(Arc::new(info), raised_at));
+                }
+                let original = match error_type.as_str() {
+                    "UndefinedVariable" => RuntimeError::UndefinedVariable(text),
+                    "UndefinedConcept" => RuntimeError::UndefinedConcept(text),
+                    "UndefinedMethod" => RuntimeError::UndefinedMethod(text),
+                    "TypeError" => RuntimeError::TypeError(text),
+                    "IndexError" => RuntimeError::IndexError(text),
+                    "Custom" => RuntimeError::Custom(text),
+                    _ => return Err(Self::not_an_error()),
+                };
+                return Ok(RuntimeError::Rethrown(Box::new(original)));
+            }
+            _ => return Err(Self::not_an_error()),
+        };
+        Ok(RuntimeError::Raised(Arc::new(info), at))
+    }
+
+    fn not_an_error() -> RuntimeError {
+        RuntimeError::TypeError(
+            "Raise needs a \"Category.Subtype\" name, an Error or a caught error".to_string(),
+        )
+    }
+
+    fn execute_block(&mut self, statements: &[Statement]) -> Result<ExecutionResult, RuntimeError> {
+        self.env.push_scope();
+        let result = self.execute_block_no_scope(statements);
+        self.env.pop_scope();
+        result
+    }
+
+    fn execute_block_no_scope(
+        &mut self,
+        statements: &[Statement],
+    ) -> Result<ExecutionResult, RuntimeError> {
+        for stmt in statements {
+            self.current_line = Self::get_statement_line(stmt);
+            if let Some(deadline) = &self.deadline
+                && deadline.is_expired()
+            {
+                return Err(Self::with_line(
+                    RuntimeError::Custom(deadline::exceeded_message()),
+                    &self.location(),
+                ));
+            }
+            if let Some(limits) = self.limits.as_mut()
+                && let Err(message) = limits.charge()
+            {
+                return Err(RuntimeError::LimitExceeded(format!(
+                    "{}: {}",
+                    self.location(),
+                    message
+                )));
+            }
+            if !self.observer_runs.is_empty() {
+                self.charge_observers()?;
+            }
+            if self.trace {
+                println!("[line {}] {:?}", self.current_line, stmt);
+            }
+            let result = match self.execute_statement(stmt) {
+                Ok(res) => res,
+                Err(err) => {
+                    return Err(Self::with_line(err, &self.location()));
+                }
+            };
+            if !matches!(result, ExecutionResult::Done) {
+                return Ok(result);
+            }
+        }
+        Ok(ExecutionResult::Done)
+    }
+
+    // Every running observer pays for the statement, so an observer's budget
+    // covers the observers it triggers
+    fn charge_observers(&mut self) -> Result<(), RuntimeError> {
+        let budget = self.observer_budget;
+        for run in &mut self.observer_runs {
+            if let Err(message) = run.charge(&budget) {
+                let info = ErrorInfo {
+                    category: "Observer".to_string(),
+                    subtype: "BudgetExceeded".to_string(),
+                    message,
+                };
+                return Err(RuntimeError::Raised(Arc::new(info), self.location()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Put where the error happened in front of its message, unless a
+    /// statement inside a method or a module's story already did.
+    fn with_line(err: RuntimeError, at: &SourceLine) -> RuntimeError {
+        let prefix = format!("{}: ", at);
+        let located = |msg: &str| {
+            msg.split_once(": ").is_some_and(|(place, _)| {
+                let line = place
+                    .strip_prefix("Line ")
+                    .or_else(|| place.rsplit_once(".sfex:").map(|(_, line)| line));
+                line.is_some_and(|line| {
+                    !line.is_empty() && line.bytes().all(|b| b.is_ascii_digit())
+                })
+            })
+        };
+        let message = match &err {
+            RuntimeError::UndefinedVariable(msg)
+            | RuntimeError::UndefinedConcept(msg)
+            | RuntimeError::UndefinedMethod(msg)
+            | RuntimeError::TypeError(msg)
+            | RuntimeError::IndexError(msg)
+            | RuntimeError::Custom(msg) => Some(msg),
+            _ => None,
+        };
+        if message.is_some_and(|msg| located(msg)) {
+            return err;
+        }
+        match err {
+            RuntimeError::UndefinedVariable(msg) => {
+                RuntimeError::UndefinedVariable(format!("{}{}", prefix, msg))
+            }
+            RuntimeError::UndefinedConcept(msg) => {
+                RuntimeError::UndefinedConcept(format!("{}{}", prefix, msg))
+            }
+            RuntimeError::UndefinedMethod(msg) => {
+                RuntimeError::UndefinedMethod(format!("{}{}", prefix, msg))
+            }
+            RuntimeError::TypeError(msg) => RuntimeError::TypeError(format!("{}{}", prefix, msg)),
+            RuntimeError::IndexError(msg) => RuntimeError::IndexError(format!("{}{}", prefix, msg)),
+            RuntimeError::Custom(msg) => RuntimeError::Custom(format!("{}{}", prefix, msg)),
+            // These keep the line they were first raised on
+            RuntimeError::Raised(..)
+            | RuntimeError::Rethrown(_)
+            | RuntimeError::LimitExceeded(_) => err,
+        }
+    }
+
+    pub(crate) fn get_statement_line(stmt: &Statement) -> usize {
+        match stmt {
+            Statement::Use { line, .. }
+            | Statement::Assignment { line, .. }
+            | Statement::Create { line, .. }
+            | Statement::Destroy { line, .. }
+            | Statement::Set { line, .. }
+            | Statement::Print { line, .. }
+            | Statement::SwitchOn { line, .. }
+            | Statement::SwitchOff { line, .. }
+            | Statement::ReevaluateSituations { line, .. }
+            | Statement::WithSituation { line, .. }
+            | Statement::Batch { line, .. }
+            | Statement::Using { line, .. }
+            | Statement::If { line, .. }
+            | Statement::When { line, .. }
+            | Statement::TryCatch { line, .. }
+            | Statement::Raise { line, .. }
+            | Statement::RepeatTimes { line, .. }
+            | Statement::RepeatWhile { line, .. }
+            | Statement::ForEach { line, .. }
+            | Statement::Return { line, .. }
+            | Statement::Break { line }
+            | Statement::Continue { line }
+            | Statement::Expression { line, .. } => *line,
+        }
+    }
+
+    // `where` conditions see the instance's fields as plain names, plus `This`
+    fn instance_matches(
+        &mut self,
+        instance: &Value,
+        filter: &Expression,
+    ) -> Result<bool, RuntimeError> {
+        self.env.push_scope();
+        if let Value::Map(map) = instance {
+            for (field, value) in map.read_recover().iter() {
+                if field != "_concept" {
+                    self.env.define(field.clone(), value.clone());
+                }
+            }
+        }
+        self.env.define("This".to_string(), instance.clone());
+
+        let result = self.evaluate_expression(filter);
+        self.env.pop_scope();
+        Ok(result?.is_truthy())
+    }
+
+    /// A concept and the concepts it extends, root first.
+    fn concept_chain(&self, name: &str) -> Result<Vec<&Concept>, RuntimeError> {
+        let mut chain: Vec<&Concept> = Vec::new();
+        let mut next = Some(name);
+        while let Some(current) = next {
+            if chain.iter().any(|c| c.name == current) {
+                let names: Vec<&str> = chain.iter().map(|c| c.name.as_str()).collect();
+                return Err(RuntimeError::Custom(format!(
+                    "Concept inheritance cycle: {} extends {}",
+                    names.join(" extends "),
+                    current
+                )));
+            }
+            let concept = self
+                .concepts
+                .get(current)
+                .ok_or_else(|| RuntimeError::UndefinedConcept(current.to_string()))?;
+            chain.push(concept);
+            next = concept.parent.as_deref();
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    fn register_situation(&mut self, situation: Situation) {
+        if situation.condition.is_some() && !self.pending_situations.contains(&situation.name) {
+            self.pending_situations.push(situation.name.clone());
+        }
+        self.situations.insert(situation.name.clone(), situation);
+    }
+
+    /// Switch each pending conditional situation on or off by its `when`
+    /// condition. Runs before a method is looked up, so conditions see the
+    /// variables of the story up to the first call that could be adjusted.
+    fn settle_situations(&mut self) -> Result<(), RuntimeError> {
+        // Taken first, so a condition that calls a method doesn't settle again
+        let mut pending = std::mem::take(&mut self.pending_situations).into_iter();
+        while let Some(name) = pending.next() {
+            let Some(condition) = self
+                .situations
+                .get(&name)
+                .and_then(|situation| situation.condition.clone())
+            else {
+                continue;
+            };
+            let on = match self.evaluate_expression(&condition) {
+                Ok(value) => value.is_truthy(),
+                Err(e) => {
+                    self.pending_situations.push(name);
+                    self.pending_situations.extend(pending);
+                    return Err(e);
+                }
+            };
+            self.active_situations.retain(|s| *s != name);
+            if on {
+                self.active_situations.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// The layers a call to `method` on an instance of `concept` runs through:
+    /// implementations from its ancestors (root first), its own, then
+    /// adjustments from active situations, lowest priority first. The last
+    /// layer runs and `Proceed()` calls the one below.
+    /// Also returns the index of the most derived concept implementation.
+    fn method_stack(
+        &self,
+        concept: &str,
+        method: &str,
+    ) -> Result<(Vec<Method>, usize), RuntimeError> {
+        let chain = self.concept_chain(concept)?;
+        let mut stack: Vec<Method> = chain
+            .iter()
+            .filter_map(|c| c.methods.iter().find(|m| m.name == method))
+            .cloned()
+            .collect();
+        let own = stack.len().saturating_sub(1);
+
+        // Higher priorities go on top and run first; a stable sort keeps
+        // equal priorities in activation order, so the latest runs first
+        let mut active: Vec<&Situation> = self
+            .active_situations
+            .iter()
+            .filter_map(|name| self.situations.get(name))
+            .collect();
+        active.sort_by_key(|situation| situation.priority);
+        for situation in active {
+            for concept in &chain {
+                if let Some(method_def) = situation
+                    .adjustments
+                    .iter()
+                    .find(|a| a.concept_name == concept.name)
+                    .and_then(|adj| adj.methods.iter().find(|m| m.name == method))
+                {
+                    stack.push(method_def.clone());
+                }
+            }
+        }
+
+        Ok((stack, own))
+    }
+
+    /// Whether `Using` can close `resource`: an instance whose concept has a
+    /// Close method, or a stdlib handle with a Close function.
+    fn has_close(&self, resource: &Value) -> bool {
+        let Value::Map(map) = resource else {
+            return false;
+        };
+        let map = map.read_recover();
+        match map.get("_concept") {
+            Some(concept) => self
+                .method_stack(&concept.to_display_string(), "Close")
+                .is_ok_and(|(stack, _)| !stack.is_empty()),
+            None => matches!(map.get("Close"), Some(Value::NativeFunction(_))),
+        }
+    }
+
+    fn close(&mut self, resource: Value) -> Result<(), RuntimeError> {
+        let (concept, close) = match &resource {
+            Value::Map(map) => {
+                let map = map.read_recover();
+                (map.get("_concept").cloned(), map.get("Close").cloned())
+            }
+            _ => (None, None),
+        };
+        match (concept, close) {
+            (Some(concept), _) => {
+                let (stack, _) = self.method_stack(&concept.to_display_string(), "Close")?;
+                self.execute_method_stack(&stack, resource, Vec::new())?;
+            }
+            (None, Some(Value::NativeFunction(close))) => {
+                call_native(close.as_ref(), Vec::new())?;
+            }
+            _ => {}
+        }
+        Ok(())
     }
 
-    fn execute_block(&mut self, statements: &[Statement]) -> Result<ExecutionResult, RuntimeError> {
+    /// Every method an instance of `concept` has, overrides replacing the
+    /// methods they override.
+    fn concept_methods(&self, concept: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = Vec::new();
+        for c in self.concept_chain(concept).unwrap_or_default() {
+            for method in &c.methods {
+                methods.retain(|m| m.name != method.name);
+                methods.push(method.clone());
+            }
+        }
+        methods
+    }
+
+    /// Set a field of a map, checking the `Require` clauses if it is an
+    /// instance. A value that breaks one is not kept.
+    /// Run the observer of `member` now, or at the end of the enclosing Batch.
+    fn observe_change(
+        &mut self,
+        instance: &Value,
+        member: &str,
+        old: Value,
+        owner: Option<String>,
+    ) -> Result<(), RuntimeError> {
+        if self.batch_depth == 0 {
+            return self.run_observer(instance, member, old, owner);
+        }
+        let pending = self.deferred_changes.iter().any(|change| {
+            change.member == member
+                && match (&change.instance, instance) {
+                    (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
+                    _ => false,
+                }
+        });
+        if !pending {
+            self.deferred_changes.push(DeferredChange {
+                instance: instance.clone(),
+                member: member.to_string(),
+                old,
+                owner,
+            });
+        }
+        Ok(())
+    }
+
+    /// Run the nearest `When member changes:` observer up the instance's
+    /// inheritance chain, with `This`, `Old` and `New` defined.
+    fn run_observer(
+        &mut self,
+        instance: &Value,
+        member: &str,
+        old: Value,
+        owner: Option<String>,
+    ) -> Result<(), RuntimeError> {
+        const MAX_OBSERVER_DEPTH: usize = 10;
+        if self.observer_runs.len() >= MAX_OBSERVER_DEPTH {
+            return Err(RuntimeError::Custom(
+                "When observer recursion limit reached (infinite loop detected)".to_string(),
+            ));
+        }
+        // Observers that set each other's fields nest one level per Set
+        if self.observer_runs.len() + 2 == MAX_OBSERVER_DEPTH {
+            let chain: Vec<&str> = self.observer_runs.iter().map(|r| r.name.as_str()).collect();
+            let chain = chain.join(" -> ");
+            self.warn(warnings::OBSERVER_DEPTH, || {
+                format!(
+                    "When observers are nested {} deep ({}); at {} the Set fails",
+                    MAX_OBSERVER_DEPTH - 2,
+                    chain,
+                    MAX_OBSERVER_DEPTH
+                )
+            });
+        }
+
+        let Value::Map(m) = instance else {
+            return Ok(());
+        };
+        let (concept_name, new) = {
+            let map_read = m.read_recover();
+            let concept_name = map_read.get("_concept").and_then(|v| {
+                if let Value::String(s) = v {
+                    Some(s.clone())
+                } else {
+                    None
+                }
+            });
+            (concept_name, map_read.get(member).cloned())
+        };
+        let Some(c_name) = concept_name else {
+            return Ok(());
+        };
+
+        let observer = self.concept_chain(&c_name).ok().and_then(|chain| {
+            chain.iter().rev().find_map(|concept| {
+                let body = concept.when_observers.get(member)?;
+                Some((body.clone(), concept.file.clone()))
+            })
+        });
+        let Some((observer_code, observer_file)) = observer else {
+            return Ok(());
+        };
+
+        self.count_usage(|usage| usage.observers += 1);
+        self.observer_runs
+            .push(ObserverRun::new(format!("{}.{}", c_name, member)));
         self.env.push_scope();
-        let result = self.execute_block_no_scope(statements);
+        self.env.define("This".to_string(), instance.clone());
+        self.env.define("Old".to_string(), old);
+        self.env
+            .define("New".to_string(), new.unwrap_or_else(Value::default_number));
+        if let (Some(timeline), Some(owner)) = (self.timeline.as_mut(), owner) {
+            timeline.enter_observer(owner);
+        }
+
+        let result = self.in_file(observer_file.as_deref(), |this| {
+            this.execute_block_no_scope(&observer_code)
+        });
+
+        if let Some(timeline) = self.timeline.as_mut() {
+            timeline.exit_observer();
+        }
         self.env.pop_scope();
-        result
+        if let Some(run) = self.observer_runs.pop() {
+            self.profiler
+                .record_observer(&run.name, run.statements, run.started.elapsed());
+        }
+        result.map(|_| ())
     }
 
-    fn execute_block_no_scope(
+    fn set_field(
         &mut self,
-        statements: &[Statement],
-    ) -> Result<ExecutionResult, RuntimeError> {
-        for stmt in statements {
-            self.current_line = Self::get_statement_line(stmt);
-            if self.trace {
-                println!("[line {}] {:?}", self.current_line, stmt);
-            }
-            let result = match self.execute_statement(stmt) {
-                Ok(res) => res,
-                Err(err) => {
-                    return Err(Self::with_line(err, self.current_line));
-                }
+        map: &Arc<RwLock<IndexMap<String, Value>>>,
+        field: &str,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let old = map.write_recover().insert(field.to_string(), value);
+        if self.constructing.contains(&map_address(map)) {
+            return Ok(());
+        }
+        let concept_name = map.read_recover().get("_concept").map(|v| v.to_string());
+        let Some(concept_name) = concept_name else {
+            return Ok(());
+        };
+        let instance = Value::Map(map.clone());
+        let chain: Vec<Concept> = self
+            .concept_chain(&concept_name)?
+            .into_iter()
+            .cloned()
+            .collect();
+        if let Err(e) = self.check_requirements(&instance, &chain) {
+            let mut fields = map.write_recover();
+            match old {
+                Some(old) => fields.insert(field.to_string(), old),
+                None => fields.shift_remove(field),
             };
-            if !matches!(result, ExecutionResult::Done) {
-                return Ok(result);
-            }
+            return Err(e);
         }
-        Ok(ExecutionResult::Done)
+        Ok(())
     }
 
-    fn with_line(err: RuntimeError, line: usize) -> RuntimeError {
-        let prefix = format!("Line {}: ", line);
-        match err {
-            RuntimeError::UndefinedVariable(msg) => {
-                RuntimeError::UndefinedVariable(format!("{}{}", prefix, msg))
-            }
-            RuntimeError::UndefinedConcept(msg) => {
-                RuntimeError::UndefinedConcept(format!("{}{}", prefix, msg))
-            }
-            RuntimeError::UndefinedMethod(msg) => {
-                RuntimeError::UndefinedMethod(format!("{}{}", prefix, msg))
+    /// Every `Require` of the concepts in `chain` must hold for `instance`.
+    fn check_requirements(
+        &mut self,
+        instance: &Value,
+        chain: &[Concept],
+    ) -> Result<(), RuntimeError> {
+        for concept in chain {
+            for requirement in &concept.requirements {
+                if !self.instance_matches(instance, &requirement.condition)? {
+                    return Err(RuntimeError::Custom(format!(
+                        "{} requirement on line {} failed",
+                        concept.name, requirement.line
+                    )));
+                }
             }
-            RuntimeError::TypeError(msg) => RuntimeError::TypeError(format!("{}{}", prefix, msg)),
-            RuntimeError::IndexError(msg) => RuntimeError::IndexError(format!("{}{}", prefix, msg)),
-            RuntimeError::Custom(msg) => RuntimeError::Custom(format!("{}{}", prefix, msg)),
         }
+        Ok(())
     }
 
-    fn get_statement_line(stmt: &Statement) -> usize {
-        match stmt {
-            Statement::Use { line, .. }
-            | Statement::Assignment { line, .. }
-            | Statement::Create { line, .. }
-            | Statement::Set { line, .. }
-            | Statement::Print { line, .. }
-            | Statement::SwitchOn { line, .. }
-            | Statement::SwitchOff { line, .. }
-            | Statement::If { line, .. }
-            | Statement::When { line, .. }
-            | Statement::TryCatch { line, .. }
-            | Statement::RepeatTimes { line, .. }
-            | Statement::RepeatWhile { line, .. }
-            | Statement::ForEach { line, .. }
-            | Statement::Return { line, .. }
-            | Statement::Break { line }
-            | Statement::Continue { line }
-            | Statement::Expression { line, .. } => *line,
+    /// Run a `When created:` or `When destroyed:` block with `This` set.
+    fn run_lifecycle_hook(
+        &mut self,
+        body: &[Statement],
+        file: Option<&str>,
+        this: &Value,
+    ) -> Result<(), RuntimeError> {
+        if body.is_empty() {
+            return Ok(());
         }
+        self.env.push_scope();
+        self.env.define("This".to_string(), this.clone());
+        let result = self.in_file(file, |this| this.execute_block_no_scope(body));
+        self.env.pop_scope();
+        result.map(|_| ())
     }
 
     fn execute_method_stack(
@@ -771,7 +2503,9 @@ impl Interpreter {
                 .push((stack.to_vec(), index - 1, this.clone(), args.clone()));
         }
 
-        let result = self.execute_block_no_scope(&method.body)?;
+        let result = self.in_file(method.file.as_deref(), |this| {
+            this.execute_block_no_scope(&method.body)
+        })?;
 
         if index > 0 {
             self.proceed_stack.pop();
@@ -788,7 +2522,15 @@ impl Interpreter {
     fn evaluate_expression(&mut self, expr: &Expression) -> Result<Value, RuntimeError> {
         match expr {
             Expression::Number(n) => Value::from_number_string(n).map_err(RuntimeError::Custom),
+            Expression::Integer(n) => Value::from_integer_string(n).map_err(RuntimeError::Custom),
             Expression::String(s) => Ok(Value::String(s.clone())),
+            Expression::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    text.push_str(&self.evaluate_expression(part)?.to_display_string());
+                }
+                Ok(Value::String(text))
+            }
             Expression::Boolean(b) => Ok(Value::Boolean(*b)),
             Expression::List(items) => {
                 let mut values = Vec::new();
@@ -800,7 +2542,7 @@ impl Interpreter {
                 ))))
             }
             Expression::Map(entries) => {
-                let mut map = HashMap::new();
+                let mut map = IndexMap::new();
                 for (key, value_expr) in entries {
                     map.insert(key.clone(), self.evaluate_expression(value_expr)?);
                 }
@@ -811,10 +2553,16 @@ impl Interpreter {
                     Ok(val)
                 } else {
                     if let Some(Value::Map(m)) = self.env.get("This") {
-                        if let Some(val) = m.read().expect("lock poisoned").get(name) {
+                        if let Some(val) = m.read_recover().get(name) {
                             return Ok(val.clone());
                         }
                     }
+                    if self.denied_modules.contains(name) {
+                        return Err(RuntimeError::Custom(format!(
+                            "Module {} is not allowed here",
+                            name
+                        )));
+                    }
                     Err(RuntimeError::UndefinedVariable(name.clone()))
                 }
             }
@@ -838,9 +2586,27 @@ impl Interpreter {
                     BinaryOperator::Divide => {
                         left_val.divide(&right_val).map_err(RuntimeError::TypeError)
                     }
+                    BinaryOperator::IntDivide => left_val
+                        .int_divide(&right_val)
+                        .map_err(RuntimeError::TypeError),
                     BinaryOperator::Modulo => {
                         left_val.modulo(&right_val).map_err(RuntimeError::TypeError)
                     }
+                    BinaryOperator::BitAnd => left_val
+                        .bit_and(&right_val)
+                        .map_err(RuntimeError::TypeError),
+                    BinaryOperator::BitOr => {
+                        left_val.bit_or(&right_val).map_err(RuntimeError::TypeError)
+                    }
+                    BinaryOperator::Xor => left_val
+                        .bit_xor(&right_val)
+                        .map_err(RuntimeError::TypeError),
+                    BinaryOperator::ShiftLeft => left_val
+                        .shift_left(&right_val)
+                        .map_err(RuntimeError::TypeError),
+                    BinaryOperator::ShiftRight => left_val
+                        .shift_right(&right_val)
+                        .map_err(RuntimeError::TypeError),
                     BinaryOperator::Equal => Ok(Value::Boolean(left_val.equals(&right_val))),
                     BinaryOperator::NotEqual => Ok(Value::Boolean(!left_val.equals(&right_val))),
                     BinaryOperator::Greater => {
@@ -882,12 +2648,15 @@ impl Interpreter {
                     UnaryOperator::Minus => {
                         if let Value::Number(n) = val {
                             Ok(Value::Number(-n))
+                        } else if let Value::Integer(i) = val {
+                            Ok(Value::Integer(-i))
                         } else {
                             Err(RuntimeError::TypeError(
                                 "Cannot negate non-number".to_string(),
                             ))
                         }
                     }
+                    UnaryOperator::BitNot => val.bit_not().map_err(RuntimeError::TypeError),
                 }
             }
             Expression::Index { object, index } => {
@@ -896,13 +2665,26 @@ impl Interpreter {
                 obj.index(&idx).map_err(RuntimeError::IndexError)
             }
             Expression::MemberAccess { object, member } => {
+                if self.usage.is_some()
+                    && let Expression::Identifier(name) = object.as_ref()
+                    && self.builtins.contains(name)
+                {
+                    let module = name.clone();
+                    self.count_usage(|usage| *usage.stdlib_modules.entry(module).or_default() += 1);
+                }
+
+                // Built on every read, so a script can't change what it or
+                // others see
+                if member == "Config" && Self::is_runtime_global(object) {
+                    return Ok(self.runtime_config());
+                }
+
                 let obj_val = self.evaluate_expression(object)?;
 
                 if member == "Length" || member == "Size" {
                     match obj_val.len() {
                         Ok(len) => {
-                            use bigdecimal::BigDecimal;
-                            return Ok(Value::Number(BigDecimal::from(len as i64)));
+                            return Ok(Value::Integer(len.into()));
                         }
                         Err(e) => {
                             return Err(RuntimeError::TypeError(e));
@@ -937,6 +2719,19 @@ impl Interpreter {
                     }
                 }
 
+                if (member == "IsOk" || member == "IsErr")
+                    && let Value::Result(result) = &obj_val
+                {
+                    return Ok(Value::Boolean(result.is_ok() == (member == "IsOk")));
+                }
+
+                // The value an Err holds, None for an Ok
+                if member == "Error"
+                    && let Value::Result(result) = &obj_val
+                {
+                    return Ok(Value::Option(Box::new(result.as_ref().clone().err())));
+                }
+
                 if member == "Unwrap" {
                     if matches!(obj_val, Value::Option(_)) {
                         let opt_clone = obj_val.clone();
@@ -944,10 +2739,16 @@ impl Interpreter {
                             move |_args| opt_clone.unwrap_option(),
                         ))));
                     }
+                    if matches!(obj_val, Value::Result(_)) {
+                        let result_clone = obj_val.clone();
+                        return Ok(Value::NativeFunction(std::sync::Arc::new(Box::new(
+                            move |_args| result_clone.unwrap_result(),
+                        ))));
+                    }
                 }
 
                 if member == "UnwrapOr" {
-                    if matches!(obj_val, Value::Option(_)) {
+                    if matches!(obj_val, Value::Option(_) | Value::Result(_)) {
                         let opt_clone = obj_val.clone();
                         return Ok(Value::NativeFunction(std::sync::Arc::new(Box::new(
                             move |args| {
@@ -968,7 +2769,7 @@ impl Interpreter {
                             let runtime_clone = self.runtime.clone();
                             return Ok(Value::NativeFunction(std::sync::Arc::new(Box::new(
                                 move |_args| {
-                                    let mut handle_lock = handle_mutex.lock().unwrap();
+                                    let mut handle_lock = handle_mutex.lock_recover();
                                     if let Some(handle) = handle_lock.take() {
                                         runtime_clone.block_on(async move {
                                             match handle.await {
@@ -985,143 +2786,85 @@ impl Interpreter {
                     }
                 }
 
+                if let Value::String(text) = &obj_val
+                    && let Some(member) = stdlib::text::string_member(text, member)
+                {
+                    return Ok(member);
+                }
+
+                if let Value::Bytes(bytes) = &obj_val
+                    && let Some(method) = stdlib::bytes::bytes_member(bytes, member)
+                {
+                    return Ok(method);
+                }
+
+                if let Value::Vector(values) = &obj_val
+                    && let Some(method) = stdlib::vector::vector_member(values, member)
+                {
+                    return Ok(method);
+                }
+
+                if let Value::Matrix(matrix) = &obj_val
+                    && let Some(member) = stdlib::vector::matrix_member(matrix, member)
+                {
+                    return Ok(member);
+                }
+
                 if let Value::Map(m) = &obj_val {
-                    if let Some(val) = m.read().expect("lock poisoned").get(member) {
+                    if let Some(val) = m.read_recover().get(member) {
                         return Ok(val.clone());
                     }
                 }
 
                 let concept_name = if let Value::Map(m) = &obj_val {
-                    m.read()
-                        .expect("lock poisoned")
-                        .get("_concept")
-                        .map(|v| v.to_string())
+                    m.read_recover().get("_concept").map(|v| v.to_string())
                 } else {
                     None
                 };
 
                 if let Some(c_name) = concept_name {
-                    let mut method_stack: Vec<Method> = Vec::new();
-
-                    if let Some(concept) = self.concepts.get(&c_name) {
-                        if let Some(method_def) = concept.methods.iter().find(|m| m.name == *member)
-                        {
-                            method_stack.push(method_def.clone());
-                        }
-                    }
-
-                    for situation_name in &self.active_situations {
-                        if let Some(situation) = self.situations.get(situation_name) {
-                            if let Some(adj) = situation
-                                .adjustments
-                                .iter()
-                                .find(|a| a.concept_name == c_name)
-                            {
-                                if let Some(method_def) =
-                                    adj.methods.iter().find(|m| m.name == *member)
-                                {
-                                    method_stack.push(method_def.clone());
-                                }
-                            }
-                        }
-                    }
+                    self.settle_situations()?;
+                    let (method_stack, own) = self.method_stack(&c_name, member)?;
 
                     if !method_stack.is_empty() {
                         self.profiler.record_call(&c_name, member);
 
                         if let Some(cached_ptr) = self.jit_compiler.get_function(&c_name, member) {
-                            let needs_obj_ptr =
-                                self.jit_compiler.method_needs_obj_ptr(&c_name, member);
-
-                            let required_fields = self
-                                .jit_compiler
-                                .get_required_fields_by_key(&c_name, member);
-
-                            let obj_ptr_count = if needs_obj_ptr { 1 } else { 0 };
-                            let total_args = obj_ptr_count + required_fields.len();
-                            let mut jit_args: Vec<f64> = Vec::with_capacity(total_args);
-
-                            if needs_obj_ptr {
-                                if let Value::Map(m) = &obj_val {
-                                    //let obj_ptr = m as *const _ as *const u8 as i64;
-                                    let obj_ptr = Arc::as_ptr(m) as *const u8 as i64;
-                                    jit_args.push(f64::from_bits(obj_ptr as u64));
-                                }
-                            }
-
-                            if let Value::Map(m) = &obj_val {
-                                let map = m.read().expect("lock poisoned");
-                                for field_name in &required_fields {
-                                    let val = map
-                                        .get(field_name)
-                                        .cloned()
-                                        .unwrap_or(Value::Number(bigdecimal::BigDecimal::from(0)));
-                                    jit_args.push(Self::value_to_f64(&val)?);
-                                }
-                            }
-
-                            let result = Self::call_jit_function(cached_ptr, &jit_args)?;
-                            return Ok(Value::Number(
-                                bigdecimal::BigDecimal::from_f64(result)
-                                    .unwrap_or_else(|| bigdecimal::BigDecimal::from(0)),
-                            ));
+                            return self.call_compiled(
+                                cached_ptr,
+                                &c_name,
+                                member,
+                                &method_stack,
+                                obj_val,
+                                Vec::new(),
+                            );
                         }
 
                         let should_compile = self.profiler.should_jit(&c_name, member);
                         if should_compile && !method_stack.is_empty() {
-                            let base_method = &method_stack[0];
+                            let base_method = &method_stack[own];
 
-                            let available_methods = self
-                                .concepts
-                                .get(&c_name)
-                                .map(|c| c.methods.as_slice())
-                                .unwrap_or(&[]);
+                            let available_methods = self.concept_methods(&c_name);
                             match self.jit_compiler.compile_method(
                                 &c_name,
                                 base_method,
-                                available_methods,
+                                &available_methods,
                             ) {
                                 Ok(_func_ptr) => {
+                                    self.count_usage(|usage| usage.jit_compiles += 1);
                                     self.profiler.mark_compiled(&c_name, member);
 
                                     if let Some(cached_ptr) =
                                         self.jit_compiler.get_function(&c_name, member)
                                     {
-                                        let needs_obj_ptr =
-                                            self.jit_compiler.method_needs_obj_ptr(&c_name, member);
-
-                                        let required_fields = self
-                                            .jit_compiler
-                                            .get_required_fields_by_key(&c_name, member);
-
-                                        let obj_ptr_count = if needs_obj_ptr { 1 } else { 0 };
-                                        let total_args = obj_ptr_count + required_fields.len();
-                                        let mut jit_args: Vec<f64> = Vec::with_capacity(total_args);
-
-                                        if needs_obj_ptr {
-                                            if let Value::Map(m) = &obj_val {
-                                                //let obj_ptr = m as *const _ as *const u8 as i64;
-                                                let obj_ptr = Arc::as_ptr(m) as *const u8 as i64;
-                                                jit_args.push(f64::from_bits(obj_ptr as u64));
-                                            }
-                                        }
-
-                                        if let Value::Map(m) = &obj_val {
-                                            let map = m.read().expect("lock poisoned");
-                                            for field_name in &required_fields {
-                                                let val = map.get(field_name).cloned().unwrap_or(
-                                                    Value::Number(bigdecimal::BigDecimal::from(0)),
-                                                );
-                                                jit_args.push(Self::value_to_f64(&val)?);
-                                            }
-                                        }
-
-                                        let result =
-                                            Self::call_jit_function(cached_ptr, &jit_args)?;
-                                        return Ok(Value::Number(
-                                            bigdecimal::BigDecimal::from_f64(result)
-                                                .unwrap_or_else(|| bigdecimal::BigDecimal::from(0)),
-                                        ));
+                                        return self.call_compiled(
+                                            cached_ptr,
+                                            &c_name,
+                                            member,
+                                            &method_stack,
+                                            obj_val,
+                                            Vec::new(),
+                                        );
                                     }
                                 }
                                 Err(e) => {
@@ -1149,16 +2892,20 @@ impl Interpreter {
                     .get(name)
                     .ok_or_else(|| RuntimeError::UndefinedVariable(name.clone()))?;
 
+                if let Value::NativeFunction(func) = &callee_val
+                    && arguments.is_empty()
+                    && stdlib::system::reports_memory(func)
+                {
+                    return Ok(self.memory_report().to_value());
+                }
+
                 if let Value::NativeFunction(func) = callee_val {
                     let mut args = Vec::new();
                     for arg_expr in arguments {
                         args.push(self.evaluate_expression(arg_expr)?);
                     }
 
-                    match func(args) {
-                        Ok(v) => Ok(v),
-                        Err(msg) => Err(RuntimeError::Custom(msg)),
-                    }
+                    call_native(func.as_ref(), args)
                 } else {
                     Err(RuntimeError::TypeError(format!(
                         "Identifier '{}' is not a callable function",
@@ -1168,18 +2915,57 @@ impl Interpreter {
             }
 
             Expression::Call { callee, arguments } => {
+                // Reflect.DefineConcept adds to the interpreter's own concepts
+                if let Expression::MemberAccess { object, member } = callee.as_ref()
+                    && member == "DefineConcept"
+                    && matches!(object.as_ref(), Expression::Identifier(name) if name == "Reflect")
+                    && self.builtins.contains("Reflect")
+                    && !self.denied_modules.contains("Reflect")
+                {
+                    return self.define_concept(arguments);
+                }
+
+                // LLM.Chat calls back into the script for tools whose
+                // handlers are concept instances
+                #[cfg(feature = "llm")]
+                if let Expression::MemberAccess { object, member } = callee.as_ref()
+                    && member == "Chat"
+                    && matches!(object.as_ref(), Expression::Identifier(name) if name == "LLM")
+                    && self.builtins.contains("LLM")
+                    && !self.denied_modules.contains("LLM")
+                {
+                    return self.llm_chat(arguments);
+                }
+
+                // Task.Pool and Task.ParallelMap call back into the script for
+                // handlers that are concept methods
+                if let Expression::MemberAccess { object, member } = callee.as_ref()
+                    && (member == "Pool" || member == "ParallelMap")
+                    && matches!(object.as_ref(), Expression::Identifier(name) if name == "Task")
+                    && self.builtins.contains("Task")
+                    && !self.denied_modules.contains("Task")
+                {
+                    return self.task_workers(member, arguments);
+                }
+
                 let callee_val = self.evaluate_expression(callee)?;
 
+                // System.MemoryStats() and System.MemoryReport() report on
+                // every script variable, which the native module cannot see
+                if let Value::NativeFunction(func) = &callee_val
+                    && arguments.is_empty()
+                    && stdlib::system::reports_memory(func)
+                {
+                    return Ok(self.memory_report().to_value());
+                }
+
                 if let Value::NativeFunction(func) = callee_val {
                     let mut args = Vec::new();
                     for arg_expr in arguments {
                         args.push(self.evaluate_expression(arg_expr)?);
                     }
 
-                    match func(args) {
-                        Ok(v) => Ok(v),
-                        Err(msg) => Err(RuntimeError::Custom(msg)),
-                    }
+                    call_native(func.as_ref(), args)
                 } else {
                     Err(RuntimeError::TypeError(
                         "Expression is not a callable function".to_string(),
@@ -1187,74 +2973,62 @@ impl Interpreter {
                 }
             }
 
-            Expression::DoInBackground { body } => {
-                let active_situations = self.active_situations.clone();
+            Expression::DoInBackground { body, shared } => {
+                self.count_usage(|usage| usage.background_tasks += 1);
                 let body = body.clone();
-                let concepts = self.concepts.clone();
-                let situations = self.situations.clone();
-                let env = self.env.clone_deep();
+
+                // The task gets a copy of every variable except the shared
+                // ones, and the same This instance as the caller
+                let mut seed = self.task_seed();
+                for name in shared {
+                    let cell = self.env.share(name).ok_or_else(|| {
+                        RuntimeError::UndefinedVariable(format!(
+                            "{} (a shared variable must exist before the task starts)",
+                            name
+                        ))
+                    })?;
+                    seed.env.bind_shared(name, cell);
+                }
                 let runtime_outer = self.runtime.clone();
-                let runtime_inner = runtime_outer.clone();
+                // A task started by a request handler stops with that request
+                let task_deadline = self.deadline;
+                let task_permissions = self.permissions.clone();
 
                 let cancel_token = Arc::new(std::sync::atomic::AtomicBool::new(false));
 
                 let handle = runtime_outer.spawn(async move {
                     tokio::task::spawn_blocking(move || {
-                        let mut task_interpreter =
-                            Interpreter::new_with_shared_runtime(runtime_inner);
-                        task_interpreter.concepts = concepts;
-                        task_interpreter.situations = situations;
-                        task_interpreter.active_situations = active_situations;
-                        task_interpreter.env = env;
-
-                        let mut result = Value::default_boolean();
-                        for statement in body {
-                            let line = Self::get_statement_line(&statement);
-                            task_interpreter.current_line = line;
-                            match task_interpreter.execute_statement(&statement) {
-                                Ok(ExecutionResult::Return(v)) => {
-                                    result = v;
-                                    break;
-                                }
-                                Ok(ExecutionResult::Break) => {
-                                    break;
-                                }
-                                Ok(ExecutionResult::Continue) => {
-                                    continue;
-                                }
-                                Ok(ExecutionResult::Done) => {}
-                                Err(e) => {
-                                    let e = Self::with_line(e, line);
-                                    let (category, subtype, message) = match &e {
-                                        RuntimeError::UndefinedVariable(msg) => {
-                                            ("Lookup", "UndefinedVariable", msg.clone())
+                        deadline::scope(task_deadline, || {
+                            permissions::scope(task_permissions, || {
+                                let mut task_interpreter = seed.interpreter();
+
+                                let mut result = Value::default_boolean();
+                                for statement in body {
+                                    let line = Self::get_statement_line(&statement);
+                                    task_interpreter.current_line = line;
+                                    match task_interpreter.execute_statement(&statement) {
+                                        Ok(ExecutionResult::Return(v)) => {
+                                            result = v;
+                                            break;
                                         }
-                                        RuntimeError::UndefinedConcept(msg) => {
-                                            ("Lookup", "UndefinedVariable", msg.clone())
+                                        Ok(ExecutionResult::Break) => {
+                                            break;
                                         }
-                                        RuntimeError::UndefinedMethod(msg) => {
-                                            ("Lookup", "MethodNotFound", msg.clone())
+                                        Ok(ExecutionResult::Continue) => {
+                                            continue;
                                         }
-                                        RuntimeError::TypeError(msg) => {
-                                            ("Validation", "InvalidType", msg.clone())
+                                        Ok(ExecutionResult::Done) => {}
+                                        Err(e) => {
+                                            let e =
+                                                Self::with_line(e, &task_interpreter.location());
+                                            result = Value::Error(e.to_error_info());
+                                            break;
                                         }
-                                        RuntimeError::IndexError(msg) => {
-                                            ("Lookup", "IndexOutOfBounds", msg.clone())
-                                        }
-                                        RuntimeError::Custom(msg) => {
-                                            ("Logic", "InvalidOperation", msg.clone())
-                                        }
-                                    };
-                                    result = Value::Error(Arc::new(ErrorInfo {
-                                        category: category.to_string(),
-                                        subtype: subtype.to_string(),
-                                        message,
-                                    }));
-                                    break;
+                                    }
                                 }
-                            }
-                        }
-                        result
+                                result
+                            })
+                        })
                     })
                     .await
                     .unwrap_or_else(|e| {
@@ -1272,6 +3046,47 @@ impl Interpreter {
                 ))
             }
 
+            Expression::Range { start, end } => {
+                let start = self.evaluate_expression(start)?;
+                let end = self.evaluate_expression(end)?;
+                let items = Self::range_values(&start, &end)?.collect();
+                Ok(Value::List(Arc::new(RwLock::new(items))))
+            }
+
+            Expression::CopyOf(value) => Ok(self.evaluate_expression(value)?.clone_deep()),
+            Expression::TryOtherwise {
+                expression,
+                fallback,
+            } => match self.evaluate_expression(expression) {
+                Ok(Value::Result(result)) => match *result {
+                    Ok(value) => Ok(value),
+                    Err(_) => self.evaluate_expression(fallback),
+                },
+                Ok(value) => Ok(value),
+                Err(_) => self.evaluate_expression(fallback),
+            },
+
+            Expression::InstancesOf {
+                concept_name,
+                filter,
+            } => {
+                if !self.concepts.contains_key(concept_name) {
+                    return Err(RuntimeError::UndefinedConcept(concept_name.clone()));
+                }
+
+                let mut matches = Vec::new();
+                for instance in self.instances.live(concept_name) {
+                    let keep = match filter {
+                        Some(filter) => self.instance_matches(&instance, filter)?,
+                        None => true,
+                    };
+                    if keep {
+                        matches.push(instance);
+                    }
+                }
+                Ok(Value::List(Arc::new(std::sync::RwLock::new(matches))))
+            }
+
             Expression::Proceed { arguments } => {
                 if let Some((stack, index, this, args)) = self.proceed_stack.last().cloned() {
                     let mut new_args = Vec::new();
@@ -1294,7 +3109,7 @@ impl Interpreter {
                 } else {
                     Err(
                         RuntimeError::Custom(
-                            "Proceed() can only be called within an adjustment method that has a lower layer to call".to_string()
+                            "Proceed() can only be called within an adjustment or overriding method that has a lower layer to call".to_string()
                         )
                     )
                 }
@@ -1308,7 +3123,7 @@ impl Interpreter {
                 let obj_val = self.evaluate_expression(object)?;
 
                 let concept_name = if let Value::Map(m) = &obj_val {
-                    let map_read = m.read().expect("lock poisoned");
+                    let map_read = m.read_recover();
                     map_read.get("_concept").and_then(|v| {
                         if let Value::String(s) = v {
                             Some(s.clone())
@@ -1321,30 +3136,8 @@ impl Interpreter {
                 };
 
                 if let Some(c_name) = concept_name {
-                    let mut method_stack: Vec<Method> = Vec::new();
-
-                    if let Some(concept) = self.concepts.get(&c_name) {
-                        if let Some(method_def) = concept.methods.iter().find(|m| m.name == *method)
-                        {
-                            method_stack.push(method_def.clone());
-                        }
-                    }
-
-                    for situation_name in &self.active_situations {
-                        if let Some(situation) = self.situations.get(situation_name) {
-                            if let Some(adj) = situation
-                                .adjustments
-                                .iter()
-                                .find(|a| a.concept_name == c_name)
-                            {
-                                if let Some(method_def) =
-                                    adj.methods.iter().find(|m| m.name == *method)
-                                {
-                                    method_stack.push(method_def.clone());
-                                }
-                            }
-                        }
-                    }
+                    self.settle_situations()?;
+                    let (method_stack, own) = self.method_stack(&c_name, method)?;
 
                     if method_stack.is_empty() {
                         return Err(RuntimeError::Custom(format!(
@@ -1362,115 +3155,53 @@ impl Interpreter {
                     self.profiler.record_call(&c_name, method);
 
                     if let Some(cached_ptr) = self.jit_compiler.get_function(&c_name, method) {
-                        let needs_obj_ptr = self.jit_compiler.method_needs_obj_ptr(&c_name, method);
-
-                        let required_fields = self
-                            .jit_compiler
-                            .get_required_fields_by_key(&c_name, method);
-
-                        let obj_ptr_count = if needs_obj_ptr { 1 } else { 0 };
-                        let total_args = obj_ptr_count + required_fields.len() + args.len();
-                        let mut jit_args: Vec<f64> = Vec::with_capacity(total_args);
-
-                        if needs_obj_ptr {
-                            if let Value::Map(m) = &obj_val {
-                                //let obj_ptr = m as *const _ as *const u8 as i64;
-                                let obj_ptr = Arc::as_ptr(m) as *const u8 as i64;
-                                jit_args.push(f64::from_bits(obj_ptr as u64));
-                            }
-                        }
-
-                        if let Value::Map(m) = &obj_val {
-                            let map_read = m.read().expect("lock poisoned");
-                            for field_name in &required_fields {
-                                if let Some(field_val) = map_read.get(field_name) {
-                                    jit_args.push(Self::value_to_f64(field_val)?);
-                                } else {
-                                    jit_args.push(0.0);
-                                }
-                            }
-                        }
-
-                        for (_, val) in &args {
-                            jit_args.push(Self::value_to_f64(val)?);
-                        }
-
-                        let result = Self::call_jit_function(cached_ptr, &jit_args)?;
-                        return Ok(Value::Number(
-                            bigdecimal::BigDecimal::from_f64(result)
-                                .unwrap_or_else(|| bigdecimal::BigDecimal::from(0)),
-                        ));
+                        return self.call_compiled(
+                            cached_ptr,
+                            &c_name,
+                            method,
+                            &method_stack,
+                            obj_val,
+                            args,
+                        );
                     }
 
                     let should_compile = self.profiler.should_jit(&c_name, method);
                     if should_compile && !method_stack.is_empty() {
-                        let base_method = &method_stack[0];
+                        let base_method = &method_stack[own];
 
-                        let available_methods = self
-                            .concepts
-                            .get(&c_name)
-                            .map(|c| c.methods.as_slice())
-                            .unwrap_or(&[]);
+                        let available_methods = self.concept_methods(&c_name);
                         match self.jit_compiler.compile_method(
                             &c_name,
                             base_method,
-                            available_methods,
+                            &available_methods,
                         ) {
                             Ok(_func_ptr) => {
+                                self.count_usage(|usage| usage.jit_compiles += 1);
                                 self.profiler.mark_compiled(&c_name, method);
 
                                 if let Some(cached_ptr) =
                                     self.jit_compiler.get_function(&c_name, method)
                                 {
-                                    let needs_obj_ptr =
-                                        self.jit_compiler.method_needs_obj_ptr(&c_name, method);
-
-                                    let required_fields = self
-                                        .jit_compiler
-                                        .get_required_fields_by_key(&c_name, method);
-
-                                    let obj_ptr_count = if needs_obj_ptr { 1 } else { 0 };
-                                    let total_args =
-                                        obj_ptr_count + required_fields.len() + args.len();
-                                    let mut jit_args: Vec<f64> = Vec::with_capacity(total_args);
-
-                                    if needs_obj_ptr {
-                                        if let Value::Map(m) = &obj_val {
-                                            //let obj_ptr = m as *const _ as *const u8 as i64;
-                                            let obj_ptr = Arc::as_ptr(m) as *const u8 as i64;
-                                            jit_args.push(f64::from_bits(obj_ptr as u64));
-                                        }
-                                    }
-
-                                    if let Value::Map(m) = &obj_val {
-                                        let map_read = m.read().expect("lock poisoned");
-                                        for field_name in &required_fields {
-                                            if let Some(field_val) = map_read.get(field_name) {
-                                                jit_args.push(Self::value_to_f64(field_val)?);
-                                            } else {
-                                                jit_args.push(0.0);
-                                            }
-                                        }
-                                    }
-
-                                    for (_, val) in &args {
-                                        jit_args.push(Self::value_to_f64(val)?);
-                                    }
-
-                                    let result = Self::call_jit_function(cached_ptr, &jit_args)?;
-                                    return Ok(Value::Number(
-                                        bigdecimal::BigDecimal::from_f64(result)
-                                            .unwrap_or_else(|| bigdecimal::BigDecimal::from(0)),
-                                    ));
+                                    return self.call_compiled(
+                                        cached_ptr,
+                                        &c_name,
+                                        method,
+                                        &method_stack,
+                                        obj_val,
+                                        args,
+                                    );
                                 }
                             }
                             Err(e) => {
                                 self.profiler.mark_compiled(&c_name, method);
 
                                 if !e.contains("side effects") {
-                                    eprintln!(
-                                        "JIT compilation failed for {}.{}: {}",
-                                        c_name, method, e
+                                    crate::stdlib::log::warn(
+                                        &format!(
+                                            "JIT compilation failed for {}.{}",
+                                            c_name, method
+                                        ),
+                                        &[("error", &e)],
                                     );
                                 }
                             }
@@ -1487,12 +3218,180 @@ impl Interpreter {
         }
     }
 
+    /// Call the compiled `concept.method` on `this`. Under `compare_jit`
+    /// the method also runs in the interpreter, and the script gets the
+    /// interpreter's result and effects.
+    fn call_compiled(
+        &mut self,
+        func_ptr: *const u8,
+        concept: &str,
+        method: &str,
+        method_stack: &[Method],
+        this: Value,
+        args: Vec<(String, Value)>,
+    ) -> Result<Value, RuntimeError> {
+        if self.warnings.is_some() {
+            self.warn_jit_precision(concept, method, &this, &args);
+        }
+        if self.jit_comparison.is_none() {
+            let result = self.run_compiled(func_ptr, concept, method, &this, &args)?;
+            return Ok(Value::Number(
+                bigdecimal::BigDecimal::from_f64(result)
+                    .unwrap_or_else(|| bigdecimal::BigDecimal::from(0)),
+            ));
+        }
+
+        // The JIT gets a copy, so fields it sets don't count twice
+        let copy = this.clone_deep();
+        let started = std::time::Instant::now();
+        let interpreted = self.execute_method_stack(method_stack, this, args.clone())?;
+        let interpreted_time = started.elapsed();
+        let started = std::time::Instant::now();
+        let jit = self.run_compiled(func_ptr, concept, method, &copy, &args)?;
+        let jit_time = started.elapsed();
+
+        let number = Self::value_to_f64(&interpreted).map_err(|_| interpreted.to_display_string());
+        if let Some(comparison) = self.jit_comparison.as_mut() {
+            comparison.record(
+                &format!("{}.{}", concept, method),
+                number,
+                jit,
+                interpreted_time,
+                jit_time,
+            );
+        }
+        Ok(interpreted)
+    }
+
+    fn run_compiled(
+        &self,
+        func_ptr: *const u8,
+        concept: &str,
+        method: &str,
+        this: &Value,
+        args: &[(String, Value)],
+    ) -> Result<f64, RuntimeError> {
+        let needs_obj_ptr = self.jit_compiler.method_needs_obj_ptr(concept, method);
+        let required_fields = self
+            .jit_compiler
+            .get_required_fields_by_key(concept, method);
+
+        let mut jit_args: Vec<f64> =
+            Vec::with_capacity(usize::from(needs_obj_ptr) + required_fields.len() + args.len());
+        if let Value::Map(m) = this {
+            if needs_obj_ptr {
+                let obj_ptr = Arc::as_ptr(m) as *const u8 as i64;
+                jit_args.push(f64::from_bits(obj_ptr as u64));
+            }
+            let map_read = m.read_recover();
+            for field_name in &required_fields {
+                match map_read.get(field_name) {
+                    Some(field_val) => jit_args.push(Self::value_to_f64(field_val)?),
+                    None => jit_args.push(0.0),
+                }
+            }
+        }
+        for (_, val) in args {
+            jit_args.push(Self::value_to_f64(val)?);
+        }
+        Self::call_jit_function(func_ptr, &jit_args)
+    }
+
+    /// Warn of a `Name is` that makes a variable hiding a field of `This`,
+    /// or one whose name differs from a variable's only in case.
+    fn warn_new_variable(&mut self, name: &str) {
+        if let Some(Value::Map(this)) = self.env.get("This")
+            && name != "_concept"
+            && this.read_recover().contains_key(name)
+        {
+            self.warn(warnings::IMPLICIT_VARIABLE, || {
+                format!(
+                    "{} is ... makes a new variable that hides the field This.{}; use Set {} to ... to change the field",
+                    name, name, name
+                )
+            });
+            return;
+        }
+        let similar = self
+            .env
+            .bindings()
+            .map(|(existing, _)| existing)
+            .find(|existing| {
+                *existing != name
+                    && existing.eq_ignore_ascii_case(name)
+                    && !self.builtins.contains(*existing)
+            })
+            .map(str::to_string);
+        if let Some(existing) = similar {
+            self.warn(warnings::IMPLICIT_VARIABLE, || {
+                format!(
+                    "{} is ... makes a new variable {}, next to the variable {}",
+                    name, name, existing
+                )
+            });
+        }
+    }
+
+    /// Warn when a number given to compiled `concept.method` changes on its
+    /// way to an f64, so the result may differ from the interpreter's.
+    fn warn_jit_precision(
+        &mut self,
+        concept: &str,
+        method: &str,
+        this: &Value,
+        args: &[(String, Value)],
+    ) {
+        let mut inputs: Vec<Value> = args.iter().map(|(_, value)| value.clone()).collect();
+        if let Value::Map(m) = this {
+            let map_read = m.read_recover();
+            for field in self
+                .jit_compiler
+                .get_required_fields_by_key(concept, method)
+            {
+                inputs.extend(map_read.get(&field).cloned());
+            }
+        }
+        let lossy: Vec<String> = inputs
+            .iter()
+            .filter(|value| !Self::fits_f64(value))
+            .map(Value::to_display_string)
+            .collect();
+        if !lossy.is_empty() {
+            self.warn(warnings::JIT_PRECISION, || {
+                format!(
+                    "{}.{} is compiled to use f64, which can't hold {} exactly; run with --no-jit for exact results",
+                    concept,
+                    method,
+                    lossy.join(", ")
+                )
+            });
+        }
+    }
+
+    /// Whether a number comes back from an f64 as the same number.
+    fn fits_f64(value: &Value) -> bool {
+        match value {
+            Value::Number(n) => Self::value_to_f64(value)
+                .ok()
+                .and_then(|f| f.to_string().parse::<bigdecimal::BigDecimal>().ok())
+                .is_some_and(|back| &back == n),
+            Value::Integer(i) => i
+                .to_f64()
+                .and_then(bigdecimal::num_bigint::BigInt::from_f64)
+                .is_some_and(|back| &back == i),
+            _ => true,
+        }
+    }
+
     fn value_to_f64(val: &Value) -> Result<f64, RuntimeError> {
         match val {
             Value::Number(n) => n
                 .to_string()
                 .parse::<f64>()
                 .map_err(|_| RuntimeError::TypeError("Cannot convert number to f64".to_string())),
+            Value::Integer(i) => i.to_f64().ok_or_else(|| {
+                RuntimeError::TypeError("Cannot convert integer to f64".to_string())
+            }),
             Value::FastNumber(f) => Ok(*f),
             _ => Err(RuntimeError::TypeError(format!(
                 "Cannot convert {:?} to f64 for JIT",
@@ -1593,6 +3492,13 @@ impl std::fmt::Display for RuntimeError {
             RuntimeError::TypeError(msg) => write!(f, "Type error: {}", msg),
             RuntimeError::IndexError(msg) => write!(f, "Index error: {}", msg),
             RuntimeError::Custom(msg) => write!(f, "Runtime error: {}", msg),
+            RuntimeError::LimitExceeded(msg) => write!(f, "Limit exceeded: {}", msg),
+            RuntimeError::Raised(info, at) => write!(
+                f,
+                "{}: Error.{}.{}: {}",
+                at, info.category, info.subtype, info.message
+            ),
+            RuntimeError::Rethrown(err) => err.fmt(f),
         }
     }
 }
@@ -0,0 +1,175 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// Limits an embedder puts on one interpreter, so a runaway script (a
+// `Repeat while True:` in a route handler, say) fails with LimitExceeded
+// instead of holding its thread forever. Statements are counted as they
+// run; memory is what the interpreter's thread allocated since the run
+// started, as counted by `CountingAllocator`.
+
+/// What one `Interpreter::run` may use. `None` is no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    /// Bytes allocated and not yet freed on the interpreter's thread
+    pub max_memory: Option<usize>,
+    pub wall_timeout: Option<Duration>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+thread_local! {
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+}
+
+// Off until an interpreter gets a memory limit, so a process without one
+// pays a single relaxed load per allocation and no thread-local update
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator, keeping count of the bytes each thread has
+/// allocated and not freed once any interpreter has a memory limit. Memory
+/// limits need it installed:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: sfex_lang::runtime::limits::CountingAllocator =
+///     sfex_lang::runtime::limits::CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+fn count(bytes: isize) {
+    if !COUNTING.load(Ordering::Relaxed) {
+        return;
+    }
+    // Threads being torn down have no counter left; they aren't running scripts
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + bytes));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            count(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        count(-(layout.size() as isize));
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            count(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            count(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+/// Bytes this thread has allocated and not freed since counting started;
+/// stays 0 unless `CountingAllocator` is the global allocator and some
+/// interpreter has had a memory limit.
+pub fn thread_allocated() -> isize {
+    ALLOCATED.with(Cell::get)
+}
+
+/// One run under `Limits`, and what it has used so far.
+pub(crate) struct LimitTracker {
+    pub(crate) limits: Limits,
+    steps: u64,
+    started: Instant,
+    memory_base: isize,
+}
+
+impl LimitTracker {
+    pub(crate) fn new(limits: Limits) -> Self {
+        if limits.max_memory.is_some() {
+            COUNTING.store(true, Ordering::Relaxed);
+        }
+        Self {
+            limits,
+            steps: 0,
+            started: Instant::now(),
+            memory_base: thread_allocated(),
+        }
+    }
+
+    /// Start counting again, for a new run.
+    pub(crate) fn restart(&mut self) {
+        *self = Self::new(self.limits);
+    }
+
+    /// Count one more statement; the error message once over a limit.
+    pub(crate) fn charge(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if let Some(limit) = self.limits.max_steps
+            && self.steps > limit
+        {
+            return Err(format!("Ran more than {} statements", limit));
+        }
+        if let Some(limit) = self.limits.max_memory
+            && thread_allocated() - self.memory_base > limit as isize
+        {
+            return Err(format!("Used more than {} bytes of memory", limit));
+        }
+        if let Some(limit) = self.limits.wall_timeout
+            && self.started.elapsed() > limit
+        {
+            return Err(format!("Ran longer than {} ms", limit.as_millis()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::interpreter::RuntimeError;
+    use crate::{Interpreter, Lexer, Parser};
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Result<(), RuntimeError> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        interpreter.run(Parser::new(tokens).parse().unwrap())
+    }
+
+    #[test]
+    fn test_limits() {
+        // Catch doesn't stop it, and each run gets the full budget again
+        let endless = "Story:\n    Try:\n        Repeat while True:\n            X is 1\n    Catch:\n        Print \"caught\"\n";
+        let mut interpreter = Interpreter::with_limits(Some(1000), None, None);
+        for _ in 0..2 {
+            let err = run(&mut interpreter, endless).unwrap_err();
+            assert!(matches!(err, RuntimeError::LimitExceeded(_)));
+            assert_eq!(err.to_error_info().subtype, "LimitExceeded");
+        }
+        assert!(run(&mut interpreter, "Story:\n    X is 1\n").is_ok());
+
+        let growing = "Story:\n    Text is \"0123456789\"\n    Repeat while True:\n        Text is Text + Text\n";
+        let mut interpreter = Interpreter::with_limits(None, Some(1 << 20), None);
+        let err = run(&mut interpreter, growing).unwrap_err();
+        assert!(err.to_string().contains("bytes of memory"));
+
+        let mut interpreter = Interpreter::with_limits(None, None, Some(Duration::from_millis(20)));
+        let err = run(&mut interpreter, endless).unwrap_err();
+        assert!(err.to_string().contains("longer than 20 ms"));
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// A panic while a lock is held (a crashing native call, a background task,
+// a request handler) poisons it. SFX values are plain data with no invariants
+// spanning a lock, so the guard is recovered instead of propagating the panic
+// to every later reader and taking the whole process down with it.
+
+/// Poison-tolerant access to `RwLock`s holding script data.
+pub trait RwLockExt<T: ?Sized> {
+    fn read_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T: ?Sized> RwLockExt<T> for RwLock<T> {
+    fn read_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(|poisoned| {
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+
+    fn write_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(|poisoned| {
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+/// Poison-tolerant access to `Mutex`es holding runtime state.
+pub trait MutexExt<T: ?Sized> {
+    fn lock_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T: ?Sized> MutexExt<T> for Mutex<T> {
+    fn lock_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+/// Text of a caught panic payload, for turning it into an error message.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -0,0 +1,269 @@
+use super::lock::RwLockExt;
+use super::value::Value;
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+// Collections at least this long are always listed individually
+const LARGE_COLLECTION: usize = 1000;
+// How many of the biggest collections to list when none are large
+const TOP_COLLECTIONS: usize = 10;
+// How many reference cycles to list; the rest are only counted
+const LISTED_CYCLES: usize = 10;
+
+/// A List or Map found while walking live values.
+#[derive(Debug, Clone)]
+pub struct CollectionInfo {
+    pub path: String,
+    pub kind: &'static str,
+    pub length: usize,
+    pub strong_count: usize,
+    pub weak_count: usize,
+    /// References to this collection seen from other live values
+    pub visible_refs: usize,
+}
+
+impl CollectionInfo {
+    /// Strong references that do not come from any variable or value we can
+    /// see (closures, background tasks, native handles) keep it alive.
+    pub fn is_retained(&self) -> bool {
+        self.strong_count > self.visible_refs
+    }
+}
+
+/// A List or Map that holds, directly or further down, a strong reference
+/// back to itself. Reference counting never frees either side.
+#[derive(Debug, Clone)]
+pub struct Cycle {
+    /// Where the reference back was found
+    pub path: String,
+    /// The collection it refers back to
+    pub refers_to: String,
+}
+
+/// Snapshot of every value reachable from a set of named roots.
+#[derive(Debug, Default)]
+pub struct MemoryReport {
+    pub counts: BTreeMap<&'static str, usize>,
+    /// Live concept instances by concept name
+    pub instances: BTreeMap<String, usize>,
+    pub total: usize,
+    pub approx_bytes: usize,
+    pub collections: Vec<CollectionInfo>,
+    pub cycles: Vec<Cycle>,
+}
+
+impl MemoryReport {
+    pub fn collect<'a>(roots: impl IntoIterator<Item = (&'a str, &'a Value)>) -> Self {
+        let mut walker = Walker::default();
+        for (name, value) in roots {
+            walker.visit(value, name.to_string());
+        }
+        walker.finish()
+    }
+
+    /// Report on a single value passed to a native function; the argument
+    /// copy itself holds one strong reference to the top-level collection.
+    pub fn collect_argument(value: &Value) -> Self {
+        let mut walker = Walker::default();
+        walker.visit(value, "Value".to_string());
+        if let Some(root) = walker.report.collections.first_mut() {
+            root.visible_refs += 1;
+        }
+        walker.finish()
+    }
+
+    /// Collections worth showing: all large ones, otherwise the biggest few,
+    /// plus anything retained by references we cannot see.
+    pub fn notable_collections(&self) -> Vec<&CollectionInfo> {
+        let mut notable: Vec<&CollectionInfo> = self
+            .collections
+            .iter()
+            .filter(|c| c.length >= LARGE_COLLECTION || c.is_retained())
+            .collect();
+
+        if notable.is_empty() {
+            notable = self.collections.iter().take(TOP_COLLECTIONS).collect();
+        }
+        notable
+    }
+
+    pub fn retained(&self) -> Vec<&CollectionInfo> {
+        self.collections
+            .iter()
+            .filter(|c| c.is_retained())
+            .collect()
+    }
+
+    pub fn to_value(&self) -> Value {
+        let counts = self
+            .counts
+            .iter()
+            .map(|(kind, count)| (kind.to_string(), number(*count)))
+            .collect();
+
+        let collections = self
+            .notable_collections()
+            .into_iter()
+            .map(|c| {
+                let mut map = IndexMap::new();
+                map.insert("Path".to_string(), Value::String(c.path.clone()));
+                map.insert("Type".to_string(), Value::String(c.kind.to_string()));
+                map.insert("Items".to_string(), number(c.length));
+                map.insert("StrongCount".to_string(), number(c.strong_count));
+                map.insert("WeakCount".to_string(), number(c.weak_count));
+                map.insert("Retained".to_string(), Value::Boolean(c.is_retained()));
+                Value::Map(Arc::new(RwLock::new(map)))
+            })
+            .collect();
+
+        let instances = self
+            .instances
+            .iter()
+            .map(|(concept, count)| (concept.clone(), number(*count)))
+            .collect();
+
+        let cycles = self
+            .cycles
+            .iter()
+            .take(LISTED_CYCLES)
+            .map(|cycle| {
+                let mut map = IndexMap::new();
+                map.insert("Path".to_string(), Value::String(cycle.path.clone()));
+                map.insert(
+                    "RefersTo".to_string(),
+                    Value::String(cycle.refers_to.clone()),
+                );
+                Value::Map(Arc::new(RwLock::new(map)))
+            })
+            .collect();
+
+        let mut stats = IndexMap::new();
+        stats.insert(
+            "Values".to_string(),
+            Value::Map(Arc::new(RwLock::new(counts))),
+        );
+        stats.insert(
+            "Instances".to_string(),
+            Value::Map(Arc::new(RwLock::new(instances))),
+        );
+        stats.insert(
+            "Cycles".to_string(),
+            Value::List(Arc::new(RwLock::new(cycles))),
+        );
+        stats.insert("CycleCount".to_string(), number(self.cycles.len()));
+        stats.insert("Total".to_string(), number(self.total));
+        stats.insert("ApproxBytes".to_string(), number(self.approx_bytes));
+        stats.insert(
+            "Collections".to_string(),
+            Value::List(Arc::new(RwLock::new(collections))),
+        );
+        Value::Map(Arc::new(RwLock::new(stats)))
+    }
+}
+
+fn number(n: usize) -> Value {
+    Value::Number(bigdecimal::BigDecimal::from(n as u64))
+}
+
+#[derive(Default)]
+struct Walker {
+    report: MemoryReport,
+    // Pointer -> index into report.collections
+    index: HashMap<usize, usize>,
+    // Collections whose contents are being walked, outermost first
+    walking: Vec<usize>,
+}
+
+impl Walker {
+    fn visit(&mut self, value: &Value, path: String) {
+        *self.report.counts.entry(value.type_name()).or_insert(0) += 1;
+        self.report.total += 1;
+        self.report.approx_bytes += std::mem::size_of::<Value>();
+
+        match value {
+            Value::String(s) => self.report.approx_bytes += s.len(),
+            Value::Bytes(b) => self.report.approx_bytes += b.len(),
+            Value::Integer(i) => self.report.approx_bytes += i.bits().div_ceil(8) as usize,
+            Value::Vector(v) => self.report.approx_bytes += std::mem::size_of_val(&v[..]),
+            Value::Matrix(m) => self.report.approx_bytes += std::mem::size_of_val(&m.data[..]),
+            Value::List(list) => {
+                if !self.enter(list, "List", &path, || list.read().map(|l| l.len())) {
+                    return;
+                }
+                // Walk under the read guard: cloning the items would bump the
+                // reference counts being measured
+                let items = list.read_recover();
+                for (i, item) in items.iter().enumerate() {
+                    self.visit(item, format!("{}[{}]", path, i));
+                }
+                self.walking.pop();
+            }
+            Value::Map(map) => {
+                if !self.enter(map, "Map", &path, || map.read().map(|m| m.len())) {
+                    return;
+                }
+                let entries = map.read_recover();
+                if let Some(Value::String(concept)) = entries.get("_concept") {
+                    *self.report.instances.entry(concept.clone()).or_insert(0) += 1;
+                }
+                for (key, item) in entries.iter() {
+                    self.report.approx_bytes += key.len();
+                    self.visit(item, format!("{}.{}", path, key));
+                }
+                self.walking.pop();
+            }
+            Value::Option(inner) => {
+                if let Some(inner) = inner.as_ref() {
+                    self.visit(inner, path);
+                }
+            }
+            Value::Result(result) => match result.as_ref() {
+                Ok(inner) | Err(inner) => self.visit(inner, path),
+            },
+            _ => {}
+        }
+    }
+
+    /// Record a reference to a shared collection; returns true the first time
+    /// it is seen so its contents are only walked once (cycles included).
+    /// The caller walks the contents and then pops `walking`.
+    fn enter<T, E>(
+        &mut self,
+        arc: &Arc<RwLock<T>>,
+        kind: &'static str,
+        path: &str,
+        len: impl Fn() -> Result<usize, E>,
+    ) -> bool {
+        let ptr = Arc::as_ptr(arc) as *const () as usize;
+        if let Some(&i) = self.index.get(&ptr) {
+            self.report.collections[i].visible_refs += 1;
+            if self.walking.contains(&ptr) {
+                self.report.cycles.push(Cycle {
+                    path: path.to_string(),
+                    refers_to: self.report.collections[i].path.clone(),
+                });
+            }
+            return false;
+        }
+
+        self.walking.push(ptr);
+        self.index.insert(ptr, self.report.collections.len());
+        self.report.collections.push(CollectionInfo {
+            path: path.to_string(),
+            kind,
+            length: len().unwrap_or(0),
+            strong_count: Arc::strong_count(arc),
+            weak_count: Arc::weak_count(arc),
+            visible_refs: 1,
+        });
+        true
+    }
+
+    fn finish(mut self) -> MemoryReport {
+        self.report
+            .collections
+            .sort_by(|a, b| b.length.cmp(&a.length).then(a.path.cmp(&b.path)));
+        self.report
+    }
+}
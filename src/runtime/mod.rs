@@ -1,2 +1,16 @@
+pub mod budget;
+pub mod config;
+pub mod convert;
+pub mod deadline;
+pub mod executor;
 pub mod interpreter;
+pub mod limits;
+pub mod lock;
+pub mod memory;
+pub mod numeric;
+pub mod permissions;
+pub mod registry;
+pub mod timeline;
+pub mod usage;
 pub mod value;
+pub mod warnings;
@@ -0,0 +1,212 @@
+// Dense f64 storage behind Value::Vector and Value::Matrix.
+//
+// The hot loops (sums, dot products, matrix multiply) keep several
+// independent accumulators so the compiler can spread them over SIMD lanes.
+// On x86_64 they are compiled a second time with AVX2 enabled and picked at
+// runtime when the CPU supports it; both builds add in the same order, so
+// results don't depend on the machine.
+
+/// A row-major matrix of f64.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Result<Self, String> {
+        if rows.checked_mul(cols) != Some(data.len()) {
+            return Err(format!(
+                "A {}x{} matrix needs {} values, got {}",
+                rows,
+                cols,
+                rows.saturating_mul(cols),
+                data.len()
+            ));
+        }
+        Ok(Self { rows, cols, data })
+    }
+
+    pub fn filled(rows: usize, cols: usize, value: f64) -> Result<Self, String> {
+        let len = rows
+            .checked_mul(cols)
+            .ok_or_else(|| format!("A {}x{} matrix is too large", rows, cols))?;
+        Self::new(rows, cols, vec![value; len])
+    }
+
+    pub fn identity(size: usize) -> Result<Self, String> {
+        let mut matrix = Self::filled(size, size, 0.0)?;
+        for i in 0..size {
+            matrix.data[i * size + i] = 1.0;
+        }
+        Ok(matrix)
+    }
+
+    pub fn row(&self, row: usize) -> &[f64] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut data = Vec::with_capacity(self.data.len());
+        for col in 0..self.cols {
+            data.extend((0..self.rows).map(|row| self.data[row * self.cols + col]));
+        }
+        Matrix {
+            rows: self.cols,
+            cols: self.rows,
+            data,
+        }
+    }
+
+    pub fn matmul(&self, other: &Matrix) -> Result<Matrix, String> {
+        if self.cols != other.rows {
+            return Err(format!(
+                "Cannot multiply a {}x{} matrix by a {}x{} matrix",
+                self.rows, self.cols, other.rows, other.cols
+            ));
+        }
+        let mut data = vec![0.0; self.rows * other.cols];
+        matmul_into(self, other, &mut data);
+        Ok(Matrix {
+            rows: self.rows,
+            cols: other.cols,
+            data,
+        })
+    }
+
+    pub fn matvec(&self, vector: &[f64]) -> Result<Vec<f64>, String> {
+        if self.cols != vector.len() {
+            return Err(format!(
+                "Cannot multiply a {}x{} matrix by a vector of length {}",
+                self.rows,
+                self.cols,
+                vector.len()
+            ));
+        }
+        Ok((0..self.rows)
+            .map(|row| dot(self.row(row), vector))
+            .collect())
+    }
+}
+
+/// Apply `op` pairwise. The caller checks that the lengths match.
+pub fn zip_with(a: &[f64], b: &[f64], op: impl Fn(f64, f64) -> f64) -> Vec<f64> {
+    a.iter().zip(b).map(|(x, y)| op(*x, *y)).collect()
+}
+
+const LANES: usize = 8;
+
+pub fn sum(values: &[f64]) -> f64 {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        return unsafe { sum_avx2(values) };
+    }
+    sum_lanes(values)
+}
+
+pub fn dot(a: &[f64], b: &[f64]) -> f64 {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        return unsafe { dot_avx2(a, b) };
+    }
+    dot_lanes(a, b)
+}
+
+fn matmul_into(a: &Matrix, b: &Matrix, out: &mut [f64]) {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        return unsafe { matmul_avx2(a, b, out) };
+    }
+    matmul_rows(a, b, out)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn sum_avx2(values: &[f64]) -> f64 {
+    sum_lanes(values)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_avx2(a: &[f64], b: &[f64]) -> f64 {
+    dot_lanes(a, b)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn matmul_avx2(a: &Matrix, b: &Matrix, out: &mut [f64]) {
+    matmul_rows(a, b, out)
+}
+
+#[inline(always)]
+fn sum_lanes(values: &[f64]) -> f64 {
+    let mut acc = [0.0; LANES];
+    let chunks = values.chunks_exact(LANES);
+    let tail: f64 = chunks.remainder().iter().sum();
+    for chunk in chunks {
+        for i in 0..LANES {
+            acc[i] += chunk[i];
+        }
+    }
+    acc.iter().sum::<f64>() + tail
+}
+
+#[inline(always)]
+fn dot_lanes(a: &[f64], b: &[f64]) -> f64 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let mut acc = [0.0; LANES];
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail: f64 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            acc[i] += x[i] * y[i];
+        }
+    }
+    acc.iter().sum::<f64>() + tail
+}
+
+// i-k-j order: each step scales a row of `b` into a row of `out`, which walks
+// both contiguously
+#[inline(always)]
+fn matmul_rows(a: &Matrix, b: &Matrix, out: &mut [f64]) {
+    for (row, out_row) in out.chunks_exact_mut(b.cols).enumerate() {
+        for (k, &scale) in a.row(row).iter().enumerate() {
+            for (o, x) in out_row.iter_mut().zip(b.row(k)) {
+                *o += scale * x;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernels() {
+        let values: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(sum(&values), 210.0);
+        assert_eq!(dot(&values, &values), 2870.0);
+
+        let a = Matrix::new(2, 3, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let product = a.matmul(&a.transpose()).unwrap();
+        assert_eq!(
+            product,
+            Matrix::new(2, 2, vec![14.0, 32.0, 32.0, 77.0]).unwrap()
+        );
+        assert_eq!(a.matvec(&[1.0, 0.0, 1.0]).unwrap(), vec![4.0, 10.0]);
+        assert!(a.matmul(&a).is_err());
+        assert!(Matrix::new(2, 2, vec![1.0]).is_err());
+    }
+}
@@ -0,0 +1,545 @@
+use super::lock::RwLockExt;
+use std::cell::RefCell;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+// What a script may reach outside the interpreter: files it reads and
+// writes, hosts it connects to, environment variables and programs it runs.
+// `sfex run --allow-read=./data --deny-env` sets them for the whole process;
+// an interpreter can narrow them further for itself (web tenants do). The
+// stdlib checks them before it touches anything, so a script that isn't
+// allowed fails with an error instead.
+
+/// The five kinds of access, named as in the `--allow-*` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Read,
+    Write,
+    Net,
+    Env,
+    Run,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 5] = [Kind::Read, Kind::Write, Kind::Net, Kind::Env, Kind::Run];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Read => "read",
+            Kind::Write => "write",
+            Kind::Net => "net",
+            Kind::Env => "env",
+            Kind::Run => "run",
+        }
+    }
+}
+
+/// Everything of one kind, or only the listed paths, hosts or names. An
+/// empty list allows nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    All,
+    Only(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permissions {
+    read: Access,
+    write: Access,
+    net: Access,
+    env: Access,
+    run: Access,
+    // Permissions these narrow: both have to allow
+    parent: Option<Arc<Permissions>>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            read: Access::All,
+            write: Access::All,
+            net: Access::All,
+            env: Access::All,
+            run: Access::All,
+            parent: None,
+        }
+    }
+}
+
+impl Permissions {
+    /// Nothing allowed, for `--sandbox` and tenants: each kind is granted
+    /// with `allow`.
+    pub fn none() -> Self {
+        Self {
+            read: Access::Only(Vec::new()),
+            write: Access::Only(Vec::new()),
+            net: Access::Only(Vec::new()),
+            env: Access::Only(Vec::new()),
+            run: Access::Only(Vec::new()),
+            parent: None,
+        }
+    }
+
+    /// These permissions, but never more than `parent` allows.
+    pub fn within(mut self, parent: Option<Arc<Permissions>>) -> Self {
+        self.parent = match (self.parent.take(), parent) {
+            (Some(own), Some(parent)) => Some(Arc::new((*own).clone().within(Some(parent)))),
+            (own, parent) => own.or(parent),
+        };
+        self
+    }
+
+    fn access(&self, kind: Kind) -> &Access {
+        match kind {
+            Kind::Read => &self.read,
+            Kind::Write => &self.write,
+            Kind::Net => &self.net,
+            Kind::Env => &self.env,
+            Kind::Run => &self.run,
+        }
+    }
+
+    fn access_mut(&mut self, kind: Kind) -> &mut Access {
+        match kind {
+            Kind::Read => &mut self.read,
+            Kind::Write => &mut self.write,
+            Kind::Net => &mut self.net,
+            Kind::Env => &mut self.env,
+            Kind::Run => &mut self.run,
+        }
+    }
+
+    /// Allow `items` of `kind`, or all of it when `items` is empty. Where
+    /// everything of the kind was allowed, only `items` are from now on.
+    /// Paths are resolved against the current directory now.
+    pub fn allow(&mut self, kind: Kind, items: &[String]) {
+        if items.is_empty() {
+            *self.access_mut(kind) = Access::All;
+            return;
+        }
+        let items = items.iter().map(|item| match kind {
+            Kind::Read | Kind::Write => absolute(Path::new(item)).to_string_lossy().to_string(),
+            Kind::Net => item.to_ascii_lowercase(),
+            Kind::Env | Kind::Run => item.clone(),
+        });
+        match self.access_mut(kind) {
+            Access::Only(allowed) => allowed.extend(items),
+            access => *access = Access::Only(items.collect()),
+        }
+    }
+
+    pub fn deny(&mut self, kind: Kind) {
+        *self.access_mut(kind) = Access::Only(Vec::new());
+    }
+
+    /// Whether anything of `kind` is denied.
+    pub fn restricts(&self, kind: Kind) -> bool {
+        *self.access(kind) != Access::All
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.restricts(kind))
+    }
+
+    /// The paths, hosts or names of `kind` these permissions allow, or None
+    /// when they allow all of it. Where an outer layer limits the kind too,
+    /// only entries it also allows are listed.
+    pub fn allowed(&self, kind: Kind) -> Option<Vec<String>> {
+        let outer = self.parent.as_ref().filter(|parent| parent.restricts(kind));
+        let Access::Only(own) = self.access(kind) else {
+            return outer.and_then(|parent| parent.allowed(kind));
+        };
+        let Some(outer) = outer else {
+            return Some(own.clone());
+        };
+        Some(
+            own.iter()
+                .filter(|item| outer.allows(kind, &Target::of(kind, item)))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn allows(&self, kind: Kind, target: &Target) -> bool {
+        if let Some(parent) = &self.parent
+            && !parent.allows(kind, target)
+        {
+            return false;
+        }
+        let Access::Only(allowed) = self.access(kind) else {
+            return true;
+        };
+        allowed.iter().any(|item| match target {
+            Target::Path(path) => path.starts_with(item),
+            Target::Host(host, port) => host_matches(item, host, *port),
+            Target::Name(name) => item == name,
+            Target::Shell => false,
+            Target::Program(program) => {
+                item == program
+                    || Path::new(program)
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        == Some(item.as_str())
+            }
+        })
+    }
+}
+
+enum Target {
+    Path(PathBuf),
+    Host(String, Option<u16>),
+    Name(String),
+    Program(String),
+    // A shell command, which can start any program
+    Shell,
+}
+
+impl Target {
+    /// What an `--allow-*` entry of `kind` names, to check it against
+    /// another layer's entries.
+    fn of(kind: Kind, item: &str) -> Target {
+        match kind {
+            Kind::Read | Kind::Write => Target::Path(PathBuf::from(item)),
+            Kind::Net => {
+                let (host, port) = split_host_port(item);
+                Target::Host(host, port)
+            }
+            Kind::Env => Target::Name(item.to_string()),
+            Kind::Run => Target::Program(item.to_string()),
+        }
+    }
+}
+
+/// `entry` is `host`, `host:port`, `[v6]:port` or `*.domain`.
+fn host_matches(entry: &str, host: &str, port: Option<u16>) -> bool {
+    let (entry_host, entry_port) = split_host_port(entry);
+    if entry_port.is_some() && entry_port != port {
+        return false;
+    }
+    match entry_host.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => entry_host == host,
+    }
+}
+
+/// `example.com:443` -> (`example.com`, 443); `[::1]:80` -> (`::1`, 80)
+fn split_host_port(address: &str) -> (String, Option<u16>) {
+    let address = address.trim();
+    if let Some(rest) = address.strip_prefix('[') {
+        let (host, after) = rest.split_once(']').unwrap_or((rest, ""));
+        let port = after.strip_prefix(':').and_then(|port| port.parse().ok());
+        return (host.to_ascii_lowercase(), port);
+    }
+    match address.rsplit_once(':') {
+        // More than one colon without brackets is a bare IPv6 address
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host.to_ascii_lowercase(), Some(port)),
+            Err(_) => (address.to_ascii_lowercase(), None),
+        },
+        _ => (address.to_ascii_lowercase(), None),
+    }
+}
+
+/// `path` from the current directory, with symlinks and `..` resolved as
+/// far as it exists, so neither can reach outside an allowed directory.
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(mut resolved) = existing.canonicalize() {
+            for component in rest.iter().rev() {
+                match component {
+                    Component::ParentDir => {
+                        resolved.pop();
+                    }
+                    Component::Normal(name) => resolved.push(name),
+                    _ => {}
+                }
+            }
+            return resolved;
+        }
+        match (existing.parent(), existing.components().next_back()) {
+            (Some(parent), Some(last)) => {
+                rest.push(last);
+                existing = parent;
+            }
+            _ => return path,
+        }
+    }
+}
+
+static PROCESS: RwLock<Option<Arc<Permissions>>> = RwLock::new(None);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Permissions>>> = const { RefCell::new(None) };
+}
+
+/// Limit every script this process runs, from the command line.
+pub fn set_default(permissions: Permissions) {
+    *PROCESS.write_recover() = Some(Arc::new(permissions));
+}
+
+/// Run `f` with `permissions` as the current thread's, as an interpreter
+/// does while it runs.
+pub fn scope<T>(permissions: Option<Arc<Permissions>>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Arc<Permissions>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.replace(permissions)));
+    f()
+}
+
+/// The permissions of the interpreter running on this thread, if it has
+/// its own.
+pub fn current() -> Option<Arc<Permissions>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// The permissions in force on this thread, for checks made on another,
+/// such as the redirects an HTTP client follows.
+#[derive(Clone)]
+pub struct Snapshot {
+    process: Option<Arc<Permissions>>,
+    current: Option<Arc<Permissions>>,
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        process: PROCESS.read_recover().clone(),
+        current: current(),
+    }
+}
+
+impl Snapshot {
+    fn allows(&self, kind: Kind, target: &Target) -> bool {
+        [&self.process, &self.current]
+            .into_iter()
+            .flatten()
+            .all(|permissions| permissions.allows(kind, target))
+    }
+
+    /// What of `kind` the process and the current interpreter both allow,
+    /// as `Permissions::allowed`.
+    pub fn allowed(&self, kind: Kind) -> Option<Vec<String>> {
+        match (&self.process, &self.current) {
+            (Some(process), Some(current)) => (**current)
+                .clone()
+                .within(Some(process.clone()))
+                .allowed(kind),
+            (Some(only), None) | (None, Some(only)) => only.allowed(kind),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether anything of `kind` is denied.
+    pub fn restricts(&self, kind: Kind) -> bool {
+        [&self.process, &self.current]
+            .into_iter()
+            .flatten()
+            .any(|permissions| permissions.restricts(kind))
+    }
+
+    pub fn check_host(&self, host: &str, port: Option<u16>) -> Result<(), String> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let target = Target::Host(host.to_ascii_lowercase(), port);
+        if self.allows(Kind::Net, &target) {
+            return Ok(());
+        }
+        match port {
+            Some(port) if host.contains(':') => {
+                Err(denied(Kind::Net, format!("[{}]:{}", host, port)))
+            }
+            Some(port) => Err(denied(Kind::Net, format!("{}:{}", host, port))),
+            None => Err(denied(Kind::Net, host)),
+        }
+    }
+
+    /// A request to `url`; the port is the scheme's when the URL has none.
+    pub fn check_url(&self, url: &str) -> Result<(), String> {
+        let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        let (host, port) = split_host_port(authority);
+        let port = port.or(match scheme.to_ascii_lowercase().as_str() {
+            "http" | "ws" => Some(80),
+            "https" | "wss" => Some(443),
+            "redis" => Some(6379),
+            "mqtt" | "tcp" => Some(1883),
+            "mqtts" | "ssl" => Some(8883),
+            _ => None,
+        });
+        self.check_host(&host, port)
+    }
+}
+
+fn allows(kind: Kind, target: &Target) -> bool {
+    snapshot().allows(kind, target)
+}
+
+fn denied(kind: Kind, what: impl std::fmt::Display) -> String {
+    format!(
+        "Permission denied: no {} access to {} (see --allow-{})",
+        kind.name(),
+        what,
+        kind.name()
+    )
+}
+
+pub fn check_read(path: impl AsRef<Path>) -> Result<(), String> {
+    check_path(Kind::Read, path.as_ref())
+}
+
+pub fn check_write(path: impl AsRef<Path>) -> Result<(), String> {
+    check_path(Kind::Write, path.as_ref())
+}
+
+fn check_path(kind: Kind, path: &Path) -> Result<(), String> {
+    let target = Target::Path(absolute(path));
+    if allows(kind, &target) {
+        Ok(())
+    } else {
+        Err(denied(kind, format!("'{}'", path.display())))
+    }
+}
+
+/// A connection to, or a socket bound at, `address` (`host:port`).
+pub fn check_net(address: &str) -> Result<(), String> {
+    let (host, port) = split_host_port(address);
+    check_host(&host, port)
+}
+
+pub fn check_host(host: &str, port: Option<u16>) -> Result<(), String> {
+    snapshot().check_host(host, port)
+}
+
+pub fn check_url(url: &str) -> Result<(), String> {
+    snapshot().check_url(url)
+}
+
+pub fn check_env(name: &str) -> Result<(), String> {
+    if allows_env(name) {
+        Ok(())
+    } else {
+        Err(denied(Kind::Env, format!("'{}'", name)))
+    }
+}
+
+/// Whether `name` may be read, for functions that list variables and
+/// leave out the rest.
+pub fn allows_env(name: &str) -> bool {
+    allows(Kind::Env, &Target::Name(name.to_string()))
+}
+
+/// Starting `program`, given as a name on the PATH or a path.
+pub fn check_run(program: &str) -> Result<(), String> {
+    if allows(Kind::Run, &Target::Program(program.to_string())) {
+        Ok(())
+    } else {
+        Err(denied(Kind::Run, format!("'{}'", program)))
+    }
+}
+
+/// Running `command` in the shell, which needs access to every program
+/// since the shell can start any of them.
+pub fn check_shell(command: &str) -> Result<(), String> {
+    if allows(Kind::Run, &Target::Shell) {
+        Ok(())
+    } else {
+        Err(denied(Kind::Run, format!("the shell for '{}'", command)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let dir = std::env::temp_dir().join(format!("sfex-permissions-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        let data = dir.join("data").to_string_lossy().to_string();
+
+        let mut permissions = Permissions::default();
+        assert!(!permissions.restricts(Kind::Read));
+        permissions.allow(Kind::Read, std::slice::from_ref(&data));
+        permissions.allow(
+            Kind::Net,
+            &["api.example.com".to_string(), "*.cdn.io:443".to_string()],
+        );
+        permissions.deny(Kind::Env);
+        permissions.allow(Kind::Run, &["git".to_string()]);
+
+        scope(Some(Arc::new(permissions)), || {
+            assert!(check_read(dir.join("data/new.txt")).is_ok());
+            assert!(check_read(dir.join("data/../secret.txt")).is_err());
+            assert!(check_read(dir.join("other")).is_err());
+            assert!(check_write("/tmp/anything").is_ok());
+
+            assert!(check_url("https://API.example.com/v1?q=1").is_ok());
+            assert!(check_url("http://user@api.example.com:8080/").is_ok());
+            assert!(check_url("https://evil.com/?api.example.com").is_err());
+            assert!(check_url("https://img.cdn.io/a.png").is_ok());
+            assert!(check_url("http://img.cdn.io/a.png").is_err());
+            assert!(check_net("cdn.io:443").is_err());
+
+            assert!(check_env("HOME").is_err());
+            assert!(check_run("git").is_ok());
+            assert!(check_run("/usr/bin/git").is_ok());
+            assert!(check_shell("git status").is_err());
+            assert_eq!(
+                check_run("rm").unwrap_err(),
+                "Permission denied: no run access to 'rm' (see --allow-run)"
+            );
+        });
+        assert!(current().is_none());
+        assert!(check_env("HOME").is_ok());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/", dir.join("data/root")).unwrap();
+            let mut permissions = Permissions::none();
+            permissions.allow(Kind::Read, &[data]);
+            scope(Some(Arc::new(permissions)), || {
+                assert!(check_read(dir.join("data/root/etc/passwd")).is_err());
+                assert!(check_net("127.0.0.1:80").is_err());
+            });
+        }
+
+        // A tenant's own permissions never go past the server's
+        let mut server = Permissions::default();
+        server.allow(Kind::Net, &["api.example.com".to_string()]);
+        let mut tenant = Permissions::none();
+        tenant.allow(Kind::Net, &[]);
+        tenant.allow(Kind::Env, &["PORT".to_string()]);
+        let tenant = tenant.within(Some(Arc::new(server)));
+        assert!(tenant.restricts(Kind::Net));
+        assert_eq!(
+            tenant.allowed(Kind::Net),
+            Some(vec!["api.example.com".to_string()])
+        );
+        assert_eq!(tenant.allowed(Kind::Env), Some(vec!["PORT".to_string()]));
+        assert_eq!(tenant.allowed(Kind::Write), Some(Vec::new()));
+        scope(Some(Arc::new(tenant)), || {
+            assert!(check_net("api.example.com:443").is_ok());
+            assert!(check_net("evil.com:443").is_err());
+            assert!(check_env("PORT").is_ok());
+            assert!(check_write("/tmp/anything").is_err());
+        });
+
+        assert_eq!(
+            split_host_port("[::1]:8080"),
+            ("::1".to_string(), Some(8080))
+        );
+        assert_eq!(split_host_port("::1"), ("::1".to_string(), None));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
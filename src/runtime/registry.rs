@@ -0,0 +1,97 @@
+use super::value::Value;
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock, Weak};
+
+type Instance = Weak<RwLock<IndexMap<String, Value>>>;
+
+/// Weak handles to every instance created for the concepts being tracked, so
+/// `Instances of Order` can find them without keeping them alive.
+#[derive(Debug)]
+pub struct InstanceRegistry {
+    // Concepts named in an `Instances of` expression; None tracks all of them
+    tracked: Option<HashSet<String>>,
+    instances: HashMap<String, Vec<Instance>>,
+}
+
+impl Default for InstanceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstanceRegistry {
+    pub fn new() -> Self {
+        Self {
+            tracked: Some(HashSet::new()),
+            instances: HashMap::new(),
+        }
+    }
+
+    pub fn track(&mut self, concept: &str) {
+        if let Some(tracked) = self.tracked.as_mut() {
+            tracked.insert(concept.to_string());
+        }
+    }
+
+    /// Track every concept (used by `sfex debug` for its instance views).
+    pub fn track_all(&mut self) {
+        self.tracked = None;
+    }
+
+    pub fn is_tracked(&self, concept: &str) -> bool {
+        match &self.tracked {
+            Some(tracked) => tracked.contains(concept),
+            None => true,
+        }
+    }
+
+    pub fn register(&mut self, concept: &str, instance: &Value) {
+        if !self.is_tracked(concept) {
+            return;
+        }
+        let Value::Map(map) = instance else {
+            return;
+        };
+
+        let entries = self.instances.entry(concept.to_string()).or_default();
+        // Drop dead handles whenever the list doubles so loops that create
+        // and discard instances don't grow it without bound
+        if entries.len() >= 64 && entries.len().is_power_of_two() {
+            entries.retain(|weak| weak.strong_count() > 0);
+        }
+        entries.push(Arc::downgrade(map));
+    }
+
+    /// Stop listing `instance` under `concept` (after `Destroy`).
+    pub fn unregister(&mut self, concept: &str, instance: &Value) {
+        let (Some(entries), Value::Map(map)) = (self.instances.get_mut(concept), instance) else {
+            return;
+        };
+        entries.retain(|weak| !std::ptr::eq(weak.as_ptr(), Arc::as_ptr(map)));
+    }
+
+    /// Live instances of `concept`, oldest first.
+    pub fn live(&self, concept: &str) -> Vec<Value> {
+        let Some(entries) = self.instances.get(concept) else {
+            return Vec::new();
+        };
+        entries
+            .iter()
+            .filter_map(|weak| weak.upgrade())
+            .map(Value::Map)
+            .collect()
+    }
+
+    /// Concept names with at least one live instance, sorted.
+    pub fn concepts(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .instances
+            .iter()
+            .filter(|(_, entries)| entries.iter().any(|weak| weak.strong_count() > 0))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+}
@@ -0,0 +1,181 @@
+use super::value::Value;
+
+/// One recorded assignment: `X is ...` or `Set X to ...`.
+#[derive(Debug, Clone)]
+pub struct SetRecord {
+    pub step: usize,
+    pub line: usize,
+    pub target: String,
+    pub old: Option<Value>,
+    pub new: Value,
+    /// Set from inside a When observer rather than by the script directly
+    pub from_observer: bool,
+}
+
+/// Time-travel log of every assignment made while debugging, in execution order.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    records: Vec<SetRecord>,
+    // Names `This` stands for inside the When observers currently running
+    observer_owners: Vec<String>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(
+        &mut self,
+        line: usize,
+        target: String,
+        old: Option<Value>,
+        new: Value,
+        from_observer: bool,
+    ) {
+        self.records.push(SetRecord {
+            step: self.records.len() + 1,
+            line,
+            target,
+            old: old.map(|v| v.clone_deep()),
+            new: new.clone_deep(),
+            from_observer,
+        });
+    }
+
+    pub fn enter_observer(&mut self, owner: String) {
+        self.observer_owners.push(owner);
+    }
+
+    pub fn exit_observer(&mut self) {
+        self.observer_owners.pop();
+    }
+
+    /// Name to record for `owner`, resolving `This` inside an observer to the
+    /// object whose change triggered it.
+    pub fn owner_name(&self, owner: &str) -> String {
+        match (owner, self.observer_owners.last()) {
+            ("This", Some(current)) => current.clone(),
+            _ => owner.to_string(),
+        }
+    }
+
+    pub fn records(&self) -> &[SetRecord] {
+        &self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Records touching `name`: the variable itself, its fields (`name.Field`),
+    /// or any field called `name` (`Object.name`).
+    pub fn history(&self, name: &str) -> Vec<&SetRecord> {
+        let prefix = format!("{}.", name);
+        let suffix = format!(".{}", name);
+        self.records
+            .iter()
+            .filter(|r| {
+                r.target == name || r.target.starts_with(&prefix) || r.target.ends_with(&suffix)
+            })
+            .collect()
+    }
+
+    /// Every target recorded so far, in first-assignment order.
+    pub fn targets(&self) -> Vec<&str> {
+        let mut targets: Vec<&str> = Vec::new();
+        for record in &self.records {
+            if !targets.contains(&record.target.as_str()) {
+                targets.push(&record.target);
+            }
+        }
+        targets
+    }
+
+    /// Latest value of each target as of `step` (inclusive).
+    pub fn snapshot(&self, step: usize) -> Vec<(&str, &Value)> {
+        let mut state: Vec<(&str, &Value)> = Vec::new();
+        for record in self.records.iter().take_while(|r| r.step <= step) {
+            match state
+                .iter_mut()
+                .find(|(target, _)| *target == record.target)
+            {
+                Some(entry) => entry.1 = &record.new,
+                None => state.push((&record.target, &record.new)),
+            }
+        }
+        state
+    }
+}
+
+impl std::fmt::Display for SetRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let old = match &self.old {
+            Some(value) => value.to_display_string(),
+            None => "(new)".to_string(),
+        };
+        write!(
+            f,
+            "#{} [line {}] {}: {} -> {}",
+            self.step,
+            self.line,
+            self.target,
+            old,
+            self.new.to_display_string()
+        )?;
+        if self.from_observer {
+            write!(f, " (When observer)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Interpreter, Lexer, Parser};
+
+    #[test]
+    fn test_timeline() {
+        let source = "Concept: Cart\n    Total, Label\n\n    When Total changes:\n        Set This.Label to \"Total \" + New\n\nStory:\n    Count is 1\n    Create Cart Called C\n    Set C.Total to 5\n    Set Count to Count + 1\n";
+        let program = Parser::new(Lexer::new(source).tokenize().unwrap())
+            .parse()
+            .unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.enable_timeline();
+        interpreter.run(program).unwrap();
+        let timeline = interpreter.timeline().unwrap();
+
+        let lines: Vec<String> = timeline
+            .history("Count")
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "#1 [line 8] Count: (new) -> 1",
+                "#4 [line 11] Count: 1 -> 2"
+            ]
+        );
+
+        // The observer's Set is recorded against C, not This
+        let label = timeline.history("Label");
+        assert_eq!(label.len(), 1);
+        assert_eq!(label[0].target, "C.Label");
+        assert!(label[0].from_observer);
+        assert_eq!(label[0].new.to_display_string(), "Total 5");
+
+        assert_eq!(timeline.targets(), ["Count", "C.Total", "C.Label"]);
+        let before = timeline.snapshot(label[0].step - 1);
+        assert!(!before.iter().any(|(target, _)| *target == "C.Label"));
+        let count = |step| {
+            timeline
+                .snapshot(step)
+                .into_iter()
+                .find(|(target, _)| *target == "Count")
+                .map(|(_, value)| value.to_display_string())
+        };
+        assert_eq!(count(3).as_deref(), Some("1"));
+        assert_eq!(count(4).as_deref(), Some("2"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Usage counters for applications that embed the interpreter. The crate
+// never sends them anywhere: an embedder that wants them installs a
+// `UsageReporter` and decides what to do with each report.
+//
+//   struct Log;
+//   impl UsageReporter for Log {
+//       fn report(&self, usage: &Usage) {
+//           eprintln!("{} situations, {} JIT compiles", usage.situations, usage.jit_compiles);
+//       }
+//   }
+//   interpreter.set_usage_reporter(Arc::new(Log));
+//
+// Counts are of features only. No names, values or source from the script
+// are recorded, apart from the names of the stdlib modules it touched.
+
+/// Features used by one run of a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    /// `Switch on` statements executed
+    pub situations: u64,
+    /// Methods compiled by the JIT
+    pub jit_compiles: u64,
+    /// `When X changes` observers that fired
+    pub observers: u64,
+    /// `Do in background` tasks started
+    pub background_tasks: u64,
+    /// Accesses to each stdlib module, e.g. `Math` or `File`
+    pub stdlib_modules: BTreeMap<String, u64>,
+}
+
+/// Receives a `Usage` after each `Interpreter::run`, including runs that end
+/// with an error.
+pub trait UsageReporter: Send + Sync {
+    fn report(&self, usage: &Usage);
+}
+
+/// The installed reporter and the counts since the last report.
+pub(crate) struct UsageTracker {
+    reporter: Arc<dyn UsageReporter>,
+    pub(crate) usage: Usage,
+}
+
+impl UsageTracker {
+    pub(crate) fn new(reporter: Arc<dyn UsageReporter>) -> Self {
+        Self {
+            reporter,
+            usage: Usage::default(),
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        let usage = std::mem::take(&mut self.usage);
+        self.reporter.report(&usage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::lock::MutexExt;
+    use crate::{Interpreter, Lexer, Parser};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<Usage>>);
+
+    impl UsageReporter for Collect {
+        fn report(&self, usage: &Usage) {
+            self.0.lock_recover().push(usage.clone());
+        }
+    }
+
+    #[test]
+    fn test_usage_report() {
+        let source = "Situation: Loud\n    Adjust Bell:\n        To Ring:\n            Return 2\n\nConcept: Bell\n    To Ring:\n        Return 1\n\nStory:\n    Switch on Loud\n    X is Math.Sqrt(16) + Math.Abs(-1)\n    Y is JSON.Stringify([1])\n";
+        let program = Parser::new(Lexer::new(source).tokenize().unwrap())
+            .parse()
+            .unwrap();
+
+        let reporter = Arc::new(Collect::default());
+        let mut interpreter = Interpreter::new();
+        interpreter.set_usage_reporter(reporter.clone());
+        interpreter.run(program).unwrap();
+
+        let reports = reporter.0.lock_recover();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].situations, 1);
+        assert_eq!(reports[0].stdlib_modules.get("Math"), Some(&2));
+        assert_eq!(reports[0].stdlib_modules.get("JSON"), Some(&1));
+        assert_eq!(reports[0].jit_compiles, 0);
+    }
+}
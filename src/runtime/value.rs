@@ -1,9 +1,13 @@
-use bigdecimal::{BigDecimal, ToPrimitive};
-use std::collections::HashMap;
+use super::lock::RwLockExt;
+use super::numeric::{self, Matrix};
+use bigdecimal::num_bigint::{BigInt, ToBigInt};
+use bigdecimal::{BigDecimal, FromPrimitive, RoundingMode, ToPrimitive, Zero};
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::str::FromStr;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, RwLock, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, RwLock, Weak};
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Clone, Debug)]
@@ -32,19 +36,24 @@ fn format_number_for_display(n: &BigDecimal) -> String {
 #[derive(Clone)]
 pub enum Value {
     Number(BigDecimal),
+    Integer(BigInt),
     FastNumber(f64),
     String(String),
     Boolean(bool),
 
     List(Arc<RwLock<Vec<Value>>>),
-    Map(Arc<RwLock<HashMap<String, Value>>>),
-    Vector(Vec<f32>),
+    Map(Arc<RwLock<indexmap::IndexMap<String, Value>>>),
+    Vector(Arc<[f64]>),
+    Matrix(Arc<Matrix>),
+    Bytes(bytes::Bytes),
     NativeFunction(Arc<Box<dyn (Fn(Vec<Value>) -> Result<Value, String>) + Send + Sync>>),
 
     WeakList(Weak<RwLock<Vec<Value>>>),
-    WeakMap(Weak<RwLock<HashMap<String, Value>>>),
+    WeakMap(Weak<RwLock<indexmap::IndexMap<String, Value>>>),
 
     Option(Box<Option<Value>>),
+    // Ok(value) or Err(error)
+    Result(Box<Result<Value, Value>>),
 
     TaskHandle(
         Arc<std::sync::Mutex<Option<tokio::task::JoinHandle<Value>>>>,
@@ -59,6 +68,10 @@ impl Value {
         Value::Number(BigDecimal::from(0))
     }
 
+    pub fn default_integer() -> Self {
+        Value::Integer(BigInt::zero())
+    }
+
     pub fn default_fast_number() -> Self {
         Value::FastNumber(0.0)
     }
@@ -76,11 +89,11 @@ impl Value {
     }
 
     pub fn default_map() -> Self {
-        Value::Map(Arc::new(RwLock::new(HashMap::new())))
+        Value::Map(Arc::new(RwLock::new(IndexMap::new())))
     }
 
     pub fn default_vector() -> Self {
-        Value::Vector(Vec::new())
+        Value::Vector(Arc::from([]))
     }
 
     pub fn to_weak_ref(&self) -> Result<Value, String> {
@@ -140,9 +153,20 @@ impl Value {
         }
     }
 
+    pub fn unwrap_result(&self) -> Result<Value, String> {
+        match self {
+            Value::Result(result) => match result.as_ref() {
+                Ok(value) => Ok(value.clone()),
+                Err(error) => Err(format!("Cannot unwrap Err({})", error.to_display_string())),
+            },
+            _ => Err(format!("{} is not a Result type", self.type_name())),
+        }
+    }
+
     pub fn unwrap_or(&self, default: Value) -> Result<Value, String> {
         match self {
             Value::Option(opt) => Ok(opt.as_ref().clone().unwrap_or(default)),
+            Value::Result(result) => Ok(result.as_ref().clone().unwrap_or(default)),
             _ => Err(format!("{} is not an Option type", self.type_name())),
         }
     }
@@ -151,6 +175,10 @@ impl Value {
         matches!(self, Value::Number(_))
     }
 
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Value::Integer(_))
+    }
+
     pub fn is_fast_number(&self) -> bool {
         matches!(self, Value::FastNumber(_))
     }
@@ -167,23 +195,118 @@ impl Value {
         match self {
             Value::Boolean(b) => *b,
             Value::Number(n) => n != &BigDecimal::from(0),
+            Value::Integer(i) => !i.is_zero(),
             Value::FastNumber(f) => *f != 0.0,
             Value::String(s) => !s.is_empty(),
-            Value::List(l) => !l.read().expect("lock poisoned").is_empty(),
-            Value::Map(m) => !m.read().expect("lock poisoned").is_empty(),
+            Value::List(l) => !l.read_recover().is_empty(),
+            Value::Map(m) => !m.read_recover().is_empty(),
             Value::Vector(v) => !v.is_empty(),
+            Value::Matrix(m) => !m.data.is_empty(),
+            Value::Bytes(b) => !b.is_empty(),
             Value::NativeFunction(_) => true,
             Value::WeakList(weak) => weak.strong_count() > 0,
             Value::WeakMap(weak) => weak.strong_count() > 0,
             Value::Option(opt) => opt.is_some(),
+            Value::Result(result) => result.is_ok(),
             Value::TaskHandle(_, _) => true,
             Value::Error(_) => true,
         }
     }
 
+    // An Integer meeting a Number, FastNumber or String stands for the exact
+    // decimal it holds, so the Number rules apply to the pair.
+    fn widen_integer(&self, other: &Value) -> Option<(Value, Value)> {
+        let widen = |value: &Value| match value {
+            Value::Integer(i) => Value::Number(BigDecimal::from(i.clone())),
+            value => value.clone(),
+        };
+        match (self, other) {
+            (Value::Integer(_), Value::Number(_) | Value::FastNumber(_) | Value::String(_))
+            | (Value::Number(_) | Value::FastNumber(_) | Value::String(_), Value::Integer(_)) => {
+                Some((widen(self), widen(other)))
+            }
+            _ => None,
+        }
+    }
+
+    /// The value as an f64, for plain numbers of any kind.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => n.to_f64(),
+            Value::Integer(i) => i.to_f64(),
+            Value::FastNumber(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    // Arithmetic on Vectors and Matrices works element by element, and a
+    // plain number on either side applies to every element. None when
+    // neither side is a Vector or Matrix.
+    fn elementwise(
+        &self,
+        other: &Value,
+        op: impl Fn(f64, f64) -> f64,
+    ) -> Option<Result<Value, String>> {
+        let result = match (self, other) {
+            (Value::Vector(a), Value::Vector(b)) => {
+                if a.len() != b.len() {
+                    Err(format!(
+                        "Vectors must have the same length, got {} and {}",
+                        a.len(),
+                        b.len()
+                    ))
+                } else {
+                    Ok(Value::Vector(numeric::zip_with(a, b, op).into()))
+                }
+            }
+            (Value::Matrix(a), Value::Matrix(b)) => {
+                if (a.rows, a.cols) != (b.rows, b.cols) {
+                    Err(format!(
+                        "Matrices must have the same shape, got {}x{} and {}x{}",
+                        a.rows, a.cols, b.rows, b.cols
+                    ))
+                } else {
+                    let data = numeric::zip_with(&a.data, &b.data, op);
+                    Matrix::new(a.rows, a.cols, data).map(|m| Value::Matrix(Arc::new(m)))
+                }
+            }
+            (Value::Vector(v), scalar) => match scalar.as_f64() {
+                Some(s) => Ok(Value::Vector(v.iter().map(|x| op(*x, s)).collect())),
+                None => return None,
+            },
+            (scalar, Value::Vector(v)) => match scalar.as_f64() {
+                Some(s) => Ok(Value::Vector(v.iter().map(|x| op(s, *x)).collect())),
+                None => return None,
+            },
+            (Value::Matrix(m), scalar) => match scalar.as_f64() {
+                Some(s) => {
+                    let data = m.data.iter().map(|x| op(*x, s)).collect();
+                    Matrix::new(m.rows, m.cols, data).map(|m| Value::Matrix(Arc::new(m)))
+                }
+                None => return None,
+            },
+            (scalar, Value::Matrix(m)) => match scalar.as_f64() {
+                Some(s) => {
+                    let data = m.data.iter().map(|x| op(s, *x)).collect();
+                    Matrix::new(m.rows, m.cols, data).map(|m| Value::Matrix(Arc::new(m)))
+                }
+                None => return None,
+            },
+            _ => return None,
+        };
+        Some(result)
+    }
+
     pub fn add(&self, other: &Value) -> Result<Value, String> {
+        if let Some(result) = self.elementwise(other, |x, y| x + y) {
+            return result;
+        }
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.add(&b);
+        }
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a + b)),
 
             (Value::FastNumber(a), Value::FastNumber(b)) => Ok(Value::FastNumber(a + b)),
             (Value::FastNumber(f), Value::Number(n)) => {
@@ -226,23 +349,22 @@ impl Value {
                 Ok(Value::String(format!("{}{}", self.to_display_string(), s)))
             }
 
-            (Value::String(s), Value::Option(_)) => {
+            (Value::String(s), Value::Option(_) | Value::Result(_)) => {
                 Ok(Value::String(format!("{}{}", s, other.to_display_string())))
             }
-            (Value::Option(_), Value::String(s)) => {
+            (Value::Option(_) | Value::Result(_), Value::String(s)) => {
                 Ok(Value::String(format!("{}{}", self.to_display_string(), s)))
             }
             (Value::List(a), Value::List(b)) => {
-                let mut result = a.read().expect("lock poisoned").clone();
-                result.extend(b.read().expect("lock poisoned").clone());
+                let mut result = a.read_recover().clone();
+                result.extend(b.read_recover().clone());
                 Ok(Value::List(Arc::new(RwLock::new(result))))
             }
-            (Value::Vector(a), Value::Vector(b)) => {
-                if a.len() != b.len() {
-                    return Err("Vectors must have same length for addition".to_string());
-                }
-                let result: Vec<f32> = a.iter().zip(b.iter()).map(|(x, y)| x + y).collect();
-                Ok(Value::Vector(result))
+            (Value::Bytes(a), Value::Bytes(b)) => {
+                let mut result = Vec::with_capacity(a.len() + b.len());
+                result.extend_from_slice(a);
+                result.extend_from_slice(b);
+                Ok(Value::Bytes(result.into()))
             }
             _ => Err(format!(
                 "Cannot add {:?} and {:?}",
@@ -253,8 +375,15 @@ impl Value {
     }
 
     pub fn subtract(&self, other: &Value) -> Result<Value, String> {
+        if let Some(result) = self.elementwise(other, |x, y| x - y) {
+            return result;
+        }
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.subtract(&b);
+        }
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
+            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a - b)),
             (Value::FastNumber(a), Value::FastNumber(b)) => Ok(Value::FastNumber(a - b)),
             (Value::FastNumber(f), Value::Number(n)) => {
                 let n_f64 = n.to_f64().unwrap_or(0.0);
@@ -264,13 +393,6 @@ impl Value {
                 let n_f64 = n.to_f64().unwrap_or(0.0);
                 Ok(Value::FastNumber(n_f64 - f))
             }
-            (Value::Vector(a), Value::Vector(b)) => {
-                if a.len() != b.len() {
-                    return Err("Vectors must have same length".to_string());
-                }
-                let result: Vec<f32> = a.iter().zip(b.iter()).map(|(x, y)| x - y).collect();
-                Ok(Value::Vector(result))
-            }
             _ => Err(format!(
                 "Cannot subtract {:?} from {:?}",
                 other.type_name(),
@@ -280,8 +402,15 @@ impl Value {
     }
 
     pub fn multiply(&self, other: &Value) -> Result<Value, String> {
+        if let Some(result) = self.elementwise(other, |x, y| x * y) {
+            return result;
+        }
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.multiply(&b);
+        }
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a * b)),
             (Value::FastNumber(a), Value::FastNumber(b)) => Ok(Value::FastNumber(a * b)),
             (Value::FastNumber(f), Value::Number(n)) => {
                 let n_f64 = n.to_f64().unwrap_or(0.0);
@@ -300,7 +429,28 @@ impl Value {
     }
 
     pub fn divide(&self, other: &Value) -> Result<Value, String> {
+        if other.as_f64() == Some(0.0) && matches!(self, Value::Vector(_) | Value::Matrix(_)) {
+            return Err("Division by zero".to_string());
+        }
+        if let Some(result) = self.elementwise(other, |x, y| x / y) {
+            return result;
+        }
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.divide(&b);
+        }
         match (self, other) {
+            // Exact quotients stay Integer, anything else becomes a decimal Number
+            (Value::Integer(a), Value::Integer(b)) => {
+                if b.is_zero() {
+                    Err("Division by zero".to_string())
+                } else if (a % b).is_zero() {
+                    Ok(Value::Integer(a / b))
+                } else {
+                    Ok(Value::Number(
+                        BigDecimal::from(a.clone()) / BigDecimal::from(b.clone()),
+                    ))
+                }
+            }
             (Value::Number(a), Value::Number(b)) => {
                 if b == &BigDecimal::from(0) {
                     Err("Division by zero".to_string())
@@ -339,8 +489,42 @@ impl Value {
         }
     }
 
+    /// `//`: the quotient rounded down. Integers and Numbers give an Integer,
+    /// FastNumbers stay FastNumber.
+    pub fn int_divide(&self, other: &Value) -> Result<Value, String> {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => {
+                if b.is_zero() {
+                    return Err("Division by zero".to_string());
+                }
+                // Truncated toward zero like %, so (a // b) * b + a % b is a
+                Ok(Value::Integer(a / b))
+            }
+            _ => match self.divide(other)? {
+                Value::Number(n) => Ok(Value::Integer(
+                    n.with_scale_round(0, RoundingMode::Down)
+                        .to_bigint()
+                        .unwrap_or_default(),
+                )),
+                Value::FastNumber(f) => Ok(Value::FastNumber(f.trunc())),
+                quotient => Ok(quotient),
+            },
+        }
+    }
+
     pub fn modulo(&self, other: &Value) -> Result<Value, String> {
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.modulo(&b);
+        }
         match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => {
+                if b.is_zero() {
+                    Err("Modulo by zero".to_string())
+                } else {
+                    // Truncated like Number's %: the remainder takes the sign of a
+                    Ok(Value::Integer(a % b))
+                }
+            }
             (Value::Number(a), Value::Number(b)) => {
                 if b == &BigDecimal::from(0) {
                     Err("Modulo by zero".to_string())
@@ -379,9 +563,83 @@ impl Value {
         }
     }
 
+    /// The exact whole number this value holds, if it holds one.
+    pub fn as_integer(&self) -> Option<BigInt> {
+        match self {
+            Value::Integer(i) => Some(i.clone()),
+            Value::Number(n) if n.is_integer() => n.to_bigint(),
+            Value::FastNumber(f) if f.fract() == 0.0 => BigInt::from_f64(*f),
+            _ => None,
+        }
+    }
+
+    // Bitwise operators work on whole numbers of any numeric type, in two's
+    // complement for negatives, and always produce an Integer.
+    fn integer_operands(&self, other: &Value, op: &str) -> Result<(BigInt, BigInt), String> {
+        match (self.as_integer(), other.as_integer()) {
+            (Some(a), Some(b)) => Ok((a, b)),
+            _ => Err(format!(
+                "{} needs whole numbers, got {} and {}",
+                op,
+                self.to_display_string(),
+                other.to_display_string()
+            )),
+        }
+    }
+
+    pub fn bit_and(&self, other: &Value) -> Result<Value, String> {
+        let (a, b) = self.integer_operands(other, "BitAnd")?;
+        Ok(Value::Integer(a & b))
+    }
+
+    pub fn bit_or(&self, other: &Value) -> Result<Value, String> {
+        let (a, b) = self.integer_operands(other, "BitOr")?;
+        Ok(Value::Integer(a | b))
+    }
+
+    pub fn bit_xor(&self, other: &Value) -> Result<Value, String> {
+        let (a, b) = self.integer_operands(other, "Xor")?;
+        Ok(Value::Integer(a ^ b))
+    }
+
+    /// Two's complement: `BitNot X` is `-X - 1`.
+    pub fn bit_not(&self) -> Result<Value, String> {
+        match self.as_integer() {
+            Some(a) => Ok(Value::Integer(!a)),
+            None => Err(format!(
+                "BitNot needs a whole number, got {}",
+                self.to_display_string()
+            )),
+        }
+    }
+
+    pub fn shift_left(&self, other: &Value) -> Result<Value, String> {
+        let (a, b) = self.integer_operands(other, "ShiftLeft")?;
+        Ok(Value::Integer(a << shift_amount(&b)?))
+    }
+
+    /// Arithmetic shift: negative values round toward negative infinity.
+    pub fn shift_right(&self, other: &Value) -> Result<Value, String> {
+        let (a, b) = self.integer_operands(other, "ShiftRight")?;
+        Ok(Value::Integer(a >> shift_amount(&b)?))
+    }
+
+    fn as_index(&self) -> Result<i64, String> {
+        match self {
+            Value::Number(n) => n.to_i64(),
+            Value::Integer(i) => i.to_i64(),
+            _ => None,
+        }
+        .ok_or("Index must be integer".to_string())
+    }
+
     pub fn equals(&self, other: &Value) -> bool {
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.equals(&b);
+        }
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
             (Value::FastNumber(a), Value::FastNumber(b)) => a == b,
 
             (Value::FastNumber(f), Value::Number(n)) => {
@@ -400,13 +658,20 @@ impl Value {
             }
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::Vector(a), Value::Vector(b)) => a == b,
+            (Value::Matrix(a), Value::Matrix(b)) => a == b,
             _ => false,
         }
     }
 
     pub fn compare(&self, other: &Value) -> Result<std::cmp::Ordering, String> {
+        if let Some((a, b)) = self.widen_integer(other) {
+            return a.compare(&b);
+        }
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => Ok(a.cmp(b)),
+            (Value::Integer(a), Value::Integer(b)) => Ok(a.cmp(b)),
             (Value::FastNumber(a), Value::FastNumber(b)) => a
                 .partial_cmp(b)
                 .ok_or("Cannot compare NaN values".to_string()),
@@ -436,14 +701,14 @@ impl Value {
 
     pub fn index(&self, idx: &Value) -> Result<Value, String> {
         match (self, idx) {
-            (Value::List(list), Value::Number(n)) => {
-                let idx_i64 = n.to_i64().ok_or("Index must be integer")?;
+            (Value::List(list), Value::Number(_) | Value::Integer(_)) => {
+                let idx_i64 = idx.as_index()?;
 
                 if idx_i64 == 0 {
                     return Err("SFX lists start at 1, not 0".to_string());
                 }
 
-                let len = list.read().expect("lock poisoned").len() as i64;
+                let len = list.read_recover().len() as i64;
                 let rust_idx_i64 = if idx_i64 > 0 {
                     idx_i64 - 1
                 } else {
@@ -456,14 +721,13 @@ impl Value {
 
                 let rust_idx = rust_idx_i64 as usize;
 
-                list.read()
-                    .expect("lock poisoned")
+                list.read_recover()
                     .get(rust_idx)
                     .cloned()
                     .ok_or_else(|| format!("Index {} out of bounds", idx_i64))
             }
-            (Value::String(s), Value::Number(n)) => {
-                let idx_i64 = n.to_i64().ok_or("Index must be integer")?;
+            (Value::String(s), Value::Number(_) | Value::Integer(_)) => {
+                let idx_i64 = idx.as_index()?;
 
                 if idx_i64 == 0 {
                     return Err(
@@ -484,11 +748,37 @@ impl Value {
                     .ok_or_else(|| format!("Index {} out of bounds", idx_i64))
             }
             (Value::Map(map), Value::String(key)) => map
-                .read()
-                .expect("lock poisoned")
+                .read_recover()
                 .get(key)
                 .cloned()
                 .ok_or_else(|| format!("Key '{}' not found", key)),
+            (Value::Vector(values), Value::Number(_) | Value::Integer(_)) => {
+                let i = one_based(idx.as_index()?, values.len())?;
+                Ok(Value::FastNumber(values[i]))
+            }
+            // A matrix row comes back as a Vector, so M[2][3] is row 2, column 3
+            (Value::Matrix(matrix), Value::Number(_) | Value::Integer(_)) => {
+                let i = one_based(idx.as_index()?, matrix.rows)?;
+                Ok(Value::Vector(matrix.row(i).into()))
+            }
+            (Value::Bytes(bytes), Value::Number(_) | Value::Integer(_)) => {
+                let idx_i64 = idx.as_index()?;
+
+                if idx_i64 == 0 {
+                    return Err("SFX bytes start at 1, not 0".to_string());
+                }
+
+                let len = bytes.len() as i64;
+                let rust_idx = if idx_i64 > 0 {
+                    idx_i64 - 1
+                } else {
+                    len + idx_i64
+                };
+                if rust_idx < 0 || rust_idx >= len {
+                    return Err(format!("Index {} out of bounds", idx_i64));
+                }
+                Ok(Value::Integer(BigInt::from(bytes[rust_idx as usize])))
+            }
             _ => Err(format!(
                 "Cannot index {:?} with {:?}",
                 self.type_name(),
@@ -498,22 +788,27 @@ impl Value {
     }
 
     pub fn clone_deep(&self) -> Value {
+        // Frozen values can't change, so every copy can share them
+        if self.is_frozen() {
+            return self.clone();
+        }
         match self {
             Value::Number(n) => Value::Number(n.clone()),
+            Value::Integer(i) => Value::Integer(i.clone()),
             Value::FastNumber(f) => Value::FastNumber(*f),
             Value::String(s) => Value::String(s.clone()),
             Value::Boolean(b) => Value::Boolean(*b),
 
             Value::List(l) => {
-                let inner = l.read().expect("lock poisoned");
+                let inner = l.read_recover();
 
                 let deep_copied_items: Vec<Value> = inner.iter().map(|v| v.clone_deep()).collect();
                 Value::List(Arc::new(RwLock::new(deep_copied_items)))
             }
 
             Value::Map(m) => {
-                let inner = m.read().expect("lock poisoned");
-                let deep_copied_entries: HashMap<String, Value> = inner
+                let inner = m.read_recover();
+                let deep_copied_entries: IndexMap<String, Value> = inner
                     .iter()
                     .map(|(k, v)| (k.clone(), v.clone_deep()))
                     .collect();
@@ -521,6 +816,8 @@ impl Value {
             }
 
             Value::Vector(v) => Value::Vector(v.clone()),
+            Value::Matrix(m) => Value::Matrix(m.clone()),
+            Value::Bytes(b) => Value::Bytes(b.clone()),
             Value::NativeFunction(f) => Value::NativeFunction(f.clone()),
 
             Value::WeakList(w) => Value::WeakList(w.clone()),
@@ -529,6 +826,10 @@ impl Value {
             Value::Option(opt) => {
                 Value::Option(Box::new(opt.as_ref().clone().map(|v| v.clone_deep())))
             }
+            Value::Result(result) => Value::Result(Box::new(match result.as_ref() {
+                Ok(value) => Ok(value.clone_deep()),
+                Err(error) => Err(error.clone_deep()),
+            })),
 
             Value::TaskHandle(h, c) => Value::TaskHandle(h.clone(), c.clone()),
 
@@ -536,15 +837,58 @@ impl Value {
         }
     }
 
+    /// Whether two values hold the same data, comparing what Lists and Maps
+    /// contain rather than which List or Map they are. Cycles are equal when
+    /// they repeat the same way.
+    pub fn deep_equals(&self, other: &Value) -> bool {
+        self.deep_equals_in(other, &mut HashSet::new())
+    }
+
+    fn deep_equals_in(&self, other: &Value, comparing: &mut HashSet<(usize, usize)>) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                if Arc::ptr_eq(a, b) || !comparing.insert((address(a), address(b))) {
+                    return true;
+                }
+                let (a, b) = (a.read_recover().clone(), b.read_recover().clone());
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(x, y)| x.deep_equals_in(y, comparing))
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                if Arc::ptr_eq(a, b) || !comparing.insert((address(a), address(b))) {
+                    return true;
+                }
+                let (a, b) = (a.read_recover().clone(), b.read_recover().clone());
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(key, x)| b.get(key).is_some_and(|y| x.deep_equals_in(y, comparing)))
+            }
+            (Value::Option(a), Value::Option(b)) => match (a.as_ref(), b.as_ref()) {
+                (Some(x), Some(y)) => x.deep_equals_in(y, comparing),
+                (None, None) => true,
+                _ => false,
+            },
+            (Value::Result(a), Value::Result(b)) => match (a.as_ref(), b.as_ref()) {
+                (Ok(x), Ok(y)) | (Err(x), Err(y)) => x.deep_equals_in(y, comparing),
+                _ => false,
+            },
+            _ => self.equals(other) || self == other,
+        }
+    }
+
     pub fn len(&self) -> Result<usize, String> {
         match self {
             Value::String(s) => {
                 use unicode_segmentation::UnicodeSegmentation;
                 Ok(s.graphemes(true).count())
             }
-            Value::List(l) => Ok(l.read().expect("lock poisoned").len()),
+            Value::List(l) => Ok(l.read_recover().len()),
             Value::Vector(v) => Ok(v.len()),
-            Value::Map(m) => Ok(m.read().expect("lock poisoned").len()),
+            Value::Matrix(m) => Ok(m.rows),
+            Value::Bytes(b) => Ok(b.len()),
+            Value::Map(m) => Ok(m.read_recover().len()),
             _ => Err(format!("{:?} has no length", self.type_name())),
         }
     }
@@ -552,6 +896,7 @@ impl Value {
     pub fn to_display_string(&self) -> String {
         match self {
             Value::Number(n) => format_number_for_display(n),
+            Value::Integer(i) => i.to_string(),
             Value::FastNumber(f) => {
                 if f.is_finite() {
                     format!("{}", f)
@@ -569,8 +914,7 @@ impl Value {
             Value::Boolean(b) => (if *b { "True" } else { "False" }).to_string(),
             Value::List(l) => {
                 let items: Vec<String> = l
-                    .read()
-                    .expect("lock poisoned")
+                    .read_recover()
                     .iter()
                     .map(|v| v.to_display_string())
                     .collect();
@@ -578,8 +922,7 @@ impl Value {
             }
             Value::Map(m) => {
                 let entries: Vec<String> = m
-                    .read()
-                    .expect("lock poisoned")
+                    .read_recover()
                     .iter()
                     .map(|(k, v)| format!("{}: {}", k, v.to_display_string()))
                     .collect();
@@ -588,6 +931,8 @@ impl Value {
             Value::Vector(v) => {
                 format!("Vector[{}]", v.len())
             }
+            Value::Matrix(m) => format!("Matrix[{}x{}]", m.rows, m.cols),
+            Value::Bytes(b) => format!("Bytes[{}]", b.len()),
             Value::NativeFunction(_) => "<native function>".to_string(),
             Value::WeakList(weak) => {
                 if weak.strong_count() > 0 {
@@ -607,6 +952,10 @@ impl Value {
                 Some(v) => format!("Some({})", v.to_display_string()),
                 None => "None".to_string(),
             },
+            Value::Result(result) => match result.as_ref() {
+                Ok(value) => format!("Ok({})", value.to_display_string()),
+                Err(error) => format!("Err({})", error.to_display_string()),
+            },
             Value::TaskHandle(_, _) => "<TaskHandle>".to_string(),
             Value::Error(err) => {
                 format!("Error.{}.{}: {}", err.category, err.subtype, err.message)
@@ -614,19 +963,23 @@ impl Value {
         }
     }
 
-    fn type_name(&self) -> &str {
+    pub(crate) fn type_name(&self) -> &'static str {
         match self {
             Value::Number(_) => "Number",
+            Value::Integer(_) => "Integer",
             Value::FastNumber(_) => "FastNumber",
             Value::String(_) => "String",
             Value::Boolean(_) => "Boolean",
             Value::List(_) => "List",
             Value::Map(_) => "Map",
             Value::Vector(_) => "Vector",
+            Value::Matrix(_) => "Matrix",
+            Value::Bytes(_) => "Bytes",
             Value::NativeFunction(_) => "NativeFunction",
             Value::WeakList(_) => "WeakRef (List)",
             Value::WeakMap(_) => "WeakRef (Map)",
             Value::Option(_) => "Option",
+            Value::Result(_) => "Result",
             Value::TaskHandle(_, _) => "TaskHandle",
             Value::Error(_) => "Error",
         }
@@ -652,13 +1005,18 @@ impl Value {
             .map_err(|e| format!("Invalid number: {}", e))
     }
 
+    pub fn from_integer_string(s: &str) -> Result<Value, String> {
+        BigInt::from_str(s)
+            .map(Value::Integer)
+            .map_err(|e| format!("Invalid integer: {}", e))
+    }
+
     pub fn to_debug_string(&self) -> String {
         match self {
             Value::String(s) => format!("\"{}\"", s),
             Value::List(l) => {
                 let items: Vec<String> = l
-                    .read()
-                    .expect("lock poisoned")
+                    .read_recover()
                     .iter()
                     .map(|v| v.to_debug_string())
                     .collect();
@@ -667,18 +1025,170 @@ impl Value {
             _ => self.to_string(),
         }
     }
+
+    /// Raw bytes for writing to files and sockets: Bytes as they are,
+    /// anything else as its UTF-8 text.
+    pub fn as_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        match self {
+            Value::Bytes(b) => std::borrow::Cow::Borrowed(b),
+            Value::String(s) => std::borrow::Cow::Borrowed(s.as_bytes()),
+            other => std::borrow::Cow::Owned(other.to_display_string().into_bytes()),
+        }
+    }
+}
+
+// Shifting by more than this many bits is almost certainly a mistake and
+// would allocate without bound
+const MAX_SHIFT: usize = 1 << 16;
+
+// Turn a 1-based (or negative, from the end) SFX index into a slice index
+fn one_based(idx: i64, len: usize) -> Result<usize, String> {
+    if idx == 0 {
+        return Err("SFX indices start at 1, not 0".to_string());
+    }
+    let i = if idx > 0 { idx - 1 } else { len as i64 + idx };
+    if i < 0 || i >= len as i64 {
+        return Err(format!("Index {} out of bounds", idx));
+    }
+    Ok(i as usize)
+}
+
+fn shift_amount(amount: &BigInt) -> Result<usize, String> {
+    amount
+        .to_usize()
+        .filter(|bits| *bits <= MAX_SHIFT)
+        .ok_or_else(|| format!("Shift amount must be between 0 and {}", MAX_SHIFT))
+}
+
+// Lists and Maps made read-only by Data.Freeze, keyed by allocation address.
+// Each entry holds a Weak handle so the address can't be handed to a new
+// collection while it is still listed.
+static FROZEN: LazyLock<RwLock<HashMap<usize, FrozenHandle>>> = LazyLock::new(Default::default);
+// Lets is_frozen skip the registry until something has been frozen
+static ANY_FROZEN: AtomicBool = AtomicBool::new(false);
+
+enum FrozenHandle {
+    List(Weak<RwLock<Vec<Value>>>),
+    Map(Weak<RwLock<IndexMap<String, Value>>>),
+}
+
+impl FrozenHandle {
+    fn is_live(&self) -> bool {
+        match self {
+            FrozenHandle::List(weak) => weak.strong_count() > 0,
+            FrozenHandle::Map(weak) => weak.strong_count() > 0,
+        }
+    }
+}
+
+fn address<T>(arc: &Arc<T>) -> usize {
+    Arc::as_ptr(arc) as *const () as usize
+}
+
+impl Value {
+    /// Make this List/Map and everything inside it read-only. Writes through
+    /// any alias fail from then on.
+    pub fn freeze(&self) {
+        let mut frozen = FROZEN.write_recover();
+        ANY_FROZEN.store(true, Ordering::Release);
+        // Forget collections that have been dropped whenever the table doubles
+        if frozen.len() >= 64 && frozen.len().is_power_of_two() {
+            frozen.retain(|_, handle| handle.is_live());
+        }
+        self.freeze_into(&mut frozen);
+    }
+
+    fn freeze_into(&self, frozen: &mut HashMap<usize, FrozenHandle>) {
+        match self {
+            Value::List(list) => {
+                let handle = FrozenHandle::List(Arc::downgrade(list));
+                // Already frozen: its contents are too (this also ends cycles)
+                if frozen.insert(address(list), handle).is_some() {
+                    return;
+                }
+                for item in list.read_recover().iter() {
+                    item.freeze_into(frozen);
+                }
+            }
+            Value::Map(map) => {
+                let handle = FrozenHandle::Map(Arc::downgrade(map));
+                if frozen.insert(address(map), handle).is_some() {
+                    return;
+                }
+                for item in map.read_recover().values() {
+                    item.freeze_into(frozen);
+                }
+            }
+            Value::Option(inner) => {
+                if let Some(inner) = inner.as_ref() {
+                    inner.freeze_into(frozen);
+                }
+            }
+            Value::Result(result) => match result.as_ref() {
+                Ok(inner) | Err(inner) => inner.freeze_into(frozen),
+            },
+            _ => {}
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        if !ANY_FROZEN.load(Ordering::Acquire) {
+            return false;
+        }
+        let key = match self {
+            Value::List(list) => address(list),
+            Value::Map(map) => address(map),
+            Value::Option(inner) => return inner.as_ref().as_ref().is_some_and(Value::is_frozen),
+            Value::Result(result) => {
+                return match result.as_ref() {
+                    Ok(inner) | Err(inner) => inner.is_frozen(),
+                };
+            }
+            _ => return false,
+        };
+        FROZEN.read_recover().contains_key(&key)
+    }
+
+    /// Deep copy in which nothing is frozen, for editing a copy of frozen data.
+    pub fn thaw(&self) -> Value {
+        match self {
+            Value::List(list) => {
+                let items = list.read_recover();
+                Value::List(Arc::new(RwLock::new(
+                    items.iter().map(Value::thaw).collect(),
+                )))
+            }
+            Value::Map(map) => {
+                let entries = map.read_recover();
+                Value::Map(Arc::new(RwLock::new(
+                    entries.iter().map(|(k, v)| (k.clone(), v.thaw())).collect(),
+                )))
+            }
+            Value::Option(inner) => {
+                Value::Option(Box::new(inner.as_ref().as_ref().map(Value::thaw)))
+            }
+            Value::Result(result) => Value::Result(Box::new(match result.as_ref() {
+                Ok(inner) => Ok(inner.thaw()),
+                Err(inner) => Err(inner.thaw()),
+            })),
+            _ => self.clone_deep(),
+        }
+    }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
 
             (Value::List(a), Value::List(b)) => Arc::ptr_eq(a, b),
             (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
             (Value::Vector(a), Value::Vector(b)) => a == b,
+            (Value::Matrix(a), Value::Matrix(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
 
             (Value::NativeFunction(a), Value::NativeFunction(b)) => Arc::ptr_eq(a, b),
 
@@ -690,6 +1200,10 @@ impl PartialEq for Value {
                 (None, None) => true,
                 _ => false,
             },
+            (Value::Result(a), Value::Result(b)) => match (a.as_ref(), b.as_ref()) {
+                (Ok(va), Ok(vb)) | (Err(va), Err(vb)) => va.equals(vb),
+                _ => false,
+            },
 
             (Value::TaskHandle(a, _), Value::TaskHandle(b, _)) => Arc::ptr_eq(a, b),
 
@@ -770,16 +1284,178 @@ mod tests {
         let b = a.clone_deep();
 
         if let Value::List(list) = &b {
-            list.write()
-                .expect("lock poisoned")
+            list.write_recover()
                 .push(Value::from_number_string("3").unwrap());
         }
 
         if let Value::List(list) = &a {
-            assert_eq!(list.read().expect("lock poisoned").len(), 2);
+            assert_eq!(list.read_recover().len(), 2);
         }
     }
 
+    #[test]
+    fn test_freeze() {
+        let inner = Value::List(Arc::new(RwLock::new(vec![Value::Boolean(true)])));
+        let mut entries = IndexMap::new();
+        entries.insert("Items".to_string(), inner.clone());
+        let map = Value::Map(Arc::new(RwLock::new(entries)));
+
+        map.freeze();
+        assert!(map.is_frozen());
+        assert!(inner.is_frozen());
+        assert!(map.clone_deep() == map);
+
+        let copy = map.thaw();
+        assert!(!copy.is_frozen());
+        assert!(copy != map);
+    }
+
+    #[test]
+    fn test_deep_equals() {
+        let list = |items: Vec<Value>| Value::List(Arc::new(RwLock::new(items)));
+        let a = list(vec![Value::from_number_string("1").unwrap(), list(vec![])]);
+        let b = list(vec![Value::from_integer_string("1").unwrap(), list(vec![])]);
+
+        assert!(a != b);
+        assert!(a.deep_equals(&b));
+        assert!(a.deep_equals(&a.clone_deep()));
+        assert!(!a.deep_equals(&list(vec![Value::Boolean(true), list(vec![])])));
+
+        // A List holding itself is compared without recursing forever
+        if let (Value::List(x), Value::List(y)) = (&a, &b) {
+            x.write_recover().push(a.clone());
+            y.write_recover().push(b.clone());
+        }
+        assert!(a.deep_equals(&b));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let a = Value::Bytes(bytes::Bytes::from_static(b"ab"));
+        let b = Value::Bytes(bytes::Bytes::from_static(&[0, 255]));
+
+        assert_eq!(a.len().unwrap(), 2);
+        assert_eq!(a.type_name(), "Bytes");
+        assert!(a.add(&b).unwrap() == Value::Bytes(bytes::Bytes::from_static(b"ab\0\xff")));
+        assert!(
+            b.index(&Value::Number(BigDecimal::from(-1)))
+                .unwrap()
+                .equals(&Value::Number(BigDecimal::from(255)))
+        );
+        assert!(b.index(&Value::Number(BigDecimal::from(3))).is_err());
+        assert!(!Value::Bytes(bytes::Bytes::new()).is_truthy());
+    }
+
+    #[test]
+    fn test_integer() {
+        let big = Value::from_integer_string("18446744073709551616").unwrap();
+        let seven = Value::from_integer_string("7").unwrap();
+        let two = Value::from_integer_string("2").unwrap();
+
+        assert_eq!(
+            big.add(&seven).unwrap().to_display_string(),
+            "18446744073709551623"
+        );
+        assert!(seven.divide(&two).unwrap().is_number());
+        let minus_seven = Value::from_integer_string("-7").unwrap();
+        assert_eq!(
+            minus_seven.int_divide(&two).unwrap().to_display_string(),
+            "-3"
+        );
+        assert_eq!(seven.bit_xor(&two).unwrap().to_display_string(), "5");
+        assert_eq!(seven.bit_not().unwrap().to_display_string(), "-8");
+        assert_eq!(two.shift_left(&seven).unwrap().to_display_string(), "256");
+        assert!(two.equals(&Value::from_number_string("2.0").unwrap()));
+        assert!(
+            seven
+                .bit_and(&Value::from_number_string("0.5").unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_integer_divide_truncates() {
+        let int = |text: &str| Value::from_integer_string(text).unwrap();
+        for (a, b, quotient) in [
+            ("-7", "2", "-3"),
+            ("7", "-2", "-3"),
+            ("-7", "-2", "3"),
+            ("7", "2", "3"),
+            ("-8", "2", "-4"),
+        ] {
+            let whole = int(a).int_divide(&int(b)).unwrap();
+            assert_eq!(whole.to_display_string(), quotient, "{} // {}", a, b);
+            // (a // b) * b + a % b gives a back
+            let back = whole
+                .multiply(&int(b))
+                .unwrap()
+                .add(&int(a).modulo(&int(b)).unwrap())
+                .unwrap();
+            assert!(back.equals(&int(a)), "{} // {} and {} % {}", a, b, a, b);
+        }
+        let number = |text: &str| Value::Number(text.parse().unwrap());
+        assert_eq!(
+            number("-7.5")
+                .int_divide(&number("2"))
+                .unwrap()
+                .to_display_string(),
+            "-3"
+        );
+    }
+
+    #[test]
+    fn test_integer_modulo_matches_number() {
+        let int = |text: &str| Value::from_integer_string(text).unwrap();
+        let number = |text: &str| Value::Number(text.parse().unwrap());
+        for (a, b, expected) in [
+            ("-7", "3", "-1"),
+            ("7", "-3", "1"),
+            ("-7", "-3", "-1"),
+            ("7", "3", "1"),
+            ("-6", "3", "0"),
+        ] {
+            let whole = int(a).modulo(&int(b)).unwrap();
+            assert_eq!(whole.to_display_string(), expected, "{} % {}", a, b);
+            let decimal = number(a).modulo(&number(b)).unwrap();
+            assert!(
+                whole.equals(&decimal),
+                "{} % {} as Integer and Number",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_poisoned_lock_recovers() {
+        let list = Value::List(Arc::new(RwLock::new(vec![Value::Boolean(true)])));
+        let shared = list.clone();
+        let _ = std::thread::spawn(move || {
+            if let Value::List(items) = &shared {
+                let _guard = items.write().unwrap();
+                panic!("poison the list");
+            }
+        })
+        .join();
+
+        assert_eq!(list.len().unwrap(), 1);
+        if let Value::List(items) = &list {
+            assert!(!items.is_poisoned());
+        }
+    }
+
+    #[test]
+    fn test_vector_arithmetic() {
+        let a = Value::Vector(Arc::from([1.0, 2.0, 3.0]));
+        let b = Value::Vector(Arc::from([4.0, 5.0, 6.0]));
+        let two = Value::from_number_string("2").unwrap();
+        assert!(a.add(&b).unwrap() == Value::Vector(Arc::from([5.0, 7.0, 9.0])));
+        assert!(two.subtract(&a).unwrap() == Value::Vector(Arc::from([1.0, 0.0, -1.0])));
+        assert!(a.index(&two).unwrap().equals(&Value::FastNumber(2.0)));
+        assert!(a.add(&Value::Vector(Arc::from([1.0]))).is_err());
+        assert!(a.divide(&Value::default_number()).is_err());
+    }
+
     #[test]
     fn test_no_null() {
         let defaults = vec![
@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+// Things a program does that work but are often mistakes: a `Field is`
+// that makes a variable instead of setting the field, a JIT-compiled call
+// whose numbers don't fit in an f64, observers nested close to the limit.
+// They never stop the program; an embedder (or `sfex run --warnings`)
+// that wants them installs a `WarningReporter`.
+//
+//   struct Stderr;
+//   impl WarningReporter for Stderr {
+//       fn warn(&self, warning: &Warning) {
+//           eprintln!("warning: line {}: {}", warning.line, warning.message);
+//       }
+//   }
+//   interpreter.set_warning_reporter(Arc::new(Stderr));
+
+/// One recoverable issue found while running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Stays the same between releases, as the rule IDs of `sfex check`
+    pub rule: &'static str,
+    pub line: usize,
+    pub message: String,
+}
+
+/// A variable made by `Name is` that hides a field, or differs from an
+/// existing variable only in case.
+pub const IMPLICIT_VARIABLE: &str = "implicit-variable";
+/// A number passed to a JIT-compiled method that an f64 can't hold exactly.
+pub const JIT_PRECISION: &str = "jit-precision";
+/// `When` observers triggering each other close to the nesting limit.
+pub const OBSERVER_DEPTH: &str = "observer-depth";
+
+/// Receives each warning once per rule and line, as it happens.
+pub trait WarningReporter: Send + Sync {
+    fn warn(&self, warning: &Warning);
+}
+
+/// The installed reporter and the warnings already given, so a loop
+/// doesn't repeat one on every pass.
+pub(crate) struct WarningTracker {
+    reporter: Arc<dyn WarningReporter>,
+    given: HashSet<(&'static str, usize)>,
+}
+
+impl WarningTracker {
+    pub(crate) fn new(reporter: Arc<dyn WarningReporter>) -> Self {
+        Self {
+            reporter,
+            given: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn reporter(&self) -> Arc<dyn WarningReporter> {
+        self.reporter.clone()
+    }
+
+    /// Whether `rule` has been given on `line` already.
+    pub(crate) fn given(&self, rule: &'static str, line: usize) -> bool {
+        self.given.contains(&(rule, line))
+    }
+
+    pub(crate) fn warn(&mut self, rule: &'static str, line: usize, message: String) {
+        if self.given.insert((rule, line)) {
+            self.reporter.warn(&Warning {
+                rule,
+                line,
+                message,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::lock::MutexExt;
+    use crate::{Interpreter, Lexer, Parser};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<Warning>>);
+
+    impl WarningReporter for Collect {
+        fn warn(&self, warning: &Warning) {
+            self.0.lock_recover().push(warning.clone());
+        }
+    }
+
+    #[test]
+    fn test_warnings() {
+        let source = "Concept: Account\n    Balance\n\n    To Deposit with Amount:\n        Balance is Balance + Amount\n        Return Balance\n\nConcept: Cell\n    Value\n\n    When Value changes:\n        If New < 10:\n            Set This.Value to New + 1\n\nStory:\n    Create Account Called Savings\n    Repeat 3 times:\n        Savings.Deposit with 5\n    Total is 1\n    total is 2\n    Create Cell Called C\n    Set C.Value to 1\n    Print Savings.Balance\n";
+        let program = Parser::new(Lexer::new(source).tokenize().unwrap())
+            .parse()
+            .unwrap();
+
+        let reporter = Arc::new(Collect::default());
+        let mut interpreter = Interpreter::new();
+        interpreter.set_warning_reporter(reporter.clone());
+        interpreter.capture_output();
+        interpreter.run(program).unwrap();
+        assert_eq!(interpreter.take_output().trim(), "0");
+
+        let warnings = reporter.0.lock_recover();
+        let found: Vec<(&str, usize)> = warnings.iter().map(|w| (w.rule, w.line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (IMPLICIT_VARIABLE, 5),
+                (IMPLICIT_VARIABLE, 20),
+                (OBSERVER_DEPTH, 13),
+            ]
+        );
+        assert!(
+            warnings[1].message.contains("Total"),
+            "{}",
+            warnings[1].message
+        );
+    }
+}
@@ -0,0 +1,350 @@
+// `sfex new --template`: the files of a ready-to-run project. Without a
+// template a project is only a hello-world main.sfex; each template adds
+// the directories its kind of program needs, a test under tests/ and a
+// [tasks] section naming the commands that start and test it.
+
+use crate::compiler::edition::Edition;
+
+/// Task names with their sfex arguments, or file paths with their contents
+type Pairs = &'static [(&'static str, &'static str)];
+
+/// The names `sfex new --template` takes.
+pub const TEMPLATES: &[&str] = &["web", "api", "cli", "worker"];
+
+/// The files of a new project called `name`, as paths relative to its
+/// directory and their contents.
+pub fn files(template: Option<&str>, name: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let Some(template) = template else {
+        return Ok(vec![
+            ("sfex.toml", manifest(name, &[])),
+            ("main.sfex", HELLO.to_string()),
+            (
+                "README.md",
+                format!("# {}\n\nRun:\n\n```\nsfex run main.sfex\n```\n", name),
+            ),
+        ]);
+    };
+    let (tasks, about, sources): (Pairs, &str, Pairs) = match template {
+        "web" => (WEB_TASKS, WEB_ABOUT, WEB),
+        "api" => (API_TASKS, API_ABOUT, API),
+        "cli" => (CLI_TASKS, CLI_ABOUT, CLI),
+        "worker" => (WORKER_TASKS, WORKER_ABOUT, WORKER),
+        other => {
+            return Err(format!(
+                "unknown template '{}' (expected one of {})",
+                other,
+                TEMPLATES.join(", ")
+            ));
+        }
+    };
+    let mut files = vec![
+        ("sfex.toml", manifest(name, tasks)),
+        ("README.md", readme(name, about, tasks)),
+    ];
+    files.extend(sources.iter().map(|(path, text)| (*path, text.to_string())));
+    Ok(files)
+}
+
+fn manifest(name: &str, tasks: Pairs) -> String {
+    let mut text = format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"{}\"\n\n[dependencies]\n",
+        name,
+        Edition::LATEST
+    );
+    if !tasks.is_empty() {
+        text.push_str("\n[tasks]\n");
+        for (task, command) in tasks {
+            text.push_str(&format!("{} = \"{}\"\n", task, command));
+        }
+    }
+    text
+}
+
+fn readme(name: &str, about: &str, tasks: Pairs) -> String {
+    let width = tasks.iter().map(|(task, _)| task.len()).max().unwrap_or(0);
+    let mut text = format!("# {}\n\n{}\n\n```\n", name, about);
+    for (task, command) in tasks {
+        text.push_str(&format!("sfex {:<width$}  # sfex {}\n", task, command));
+    }
+    text.push_str("```\n");
+    text
+}
+
+const HELLO: &str = "Story:\n    Print \"Hello, SFX!\"\n";
+
+const WEB_ABOUT: &str = "A web site: main.sfex maps each path to a handler in handlers/, and files in public/ are served under /assets. `test` checks the site while `start` or `dev` is running.";
+
+const WEB_TASKS: Pairs = &[
+    ("start", "run main.sfex"),
+    ("dev", "serve main.sfex --watch --dev"),
+    ("test", "run tests/site_test.sfex"),
+];
+
+const WEB: Pairs = &[
+    (
+        "main.sfex",
+        r#"# Routes: each path runs a handler script per request
+Story:
+    Router is Web.Router()
+    Router.Get("/", "handlers/home.sfex")
+    Router.Get("/health", "handlers/health.sfex")
+    Router.Static("/assets", "public", { Fingerprint: True })
+    Print "Listening on http://127.0.0.1:8000"
+    Router.Serve("127.0.0.1:8000")
+"#,
+    ),
+    (
+        "handlers/home.sfex",
+        r#"# GET /
+Story:
+    Head is "<title>Welcome</title><link rel=\"stylesheet\" href=\"/assets/style.css\">"
+    Body is "<h1>Welcome</h1><p>Edit handlers/home.sfex and reload.</p>"
+    Response is Web.Response("<!doctype html><html><head>" + Head + "</head><body>" + Body + "</body></html>", 200)
+    Set Response.ContentType to "text/html; charset=utf-8"
+"#,
+    ),
+    (
+        "handlers/health.sfex",
+        r#"# GET /health, for load balancers and uptime checks
+Story:
+    Response is Web.Json({ status: "ok" }, 200)
+"#,
+    ),
+    (
+        "public/style.css",
+        "body {\n    font-family: sans-serif;\n    margin: 4rem auto;\n    max-width: 40rem;\n}\n",
+    ),
+    (
+        "tests/site_test.sfex",
+        r#"# Checks the running site; start it first with `sfex start`
+Story:
+    Base is "http://127.0.0.1:8000"
+
+    Home is HTTP.Get(Base + "/")
+    If Home["Status"] = 200:
+        Print "PASS /"
+    Else:
+        Raise "Test.Failed" with "GET / returned " + Home["Status"]
+
+    Health is JSON.Parse(HTTP.Get(Base + "/health")["Body"])
+    If Health["status"] = "ok":
+        Print "PASS /health"
+    Else:
+        Raise "Test.Failed" with "GET /health is not ok"
+"#,
+    ),
+];
+
+const API_ABOUT: &str = "A JSON API: main.sfex maps each route to a handler in handlers/, and the items it keeps live in App.State. `test` checks the API while `start` or `dev` is running.";
+
+const API_TASKS: Pairs = &[
+    ("start", "run main.sfex"),
+    ("dev", "serve main.sfex --watch --dev"),
+    ("test", "run tests/api_test.sfex"),
+];
+
+const API: Pairs = &[
+    (
+        "main.sfex",
+        r#"# Routes: each one runs a handler script per request
+Story:
+    Router is Web.Router()
+    Router.Get("/health", "handlers/health.sfex")
+    Router.Get("/items", "handlers/list_items.sfex")
+    Router.Post("/items", "handlers/add_item.sfex")
+    Print "Listening on http://127.0.0.1:8000"
+    Router.Serve("127.0.0.1:8000")
+"#,
+    ),
+    (
+        "handlers/health.sfex",
+        r#"# GET /health, for load balancers and uptime checks
+Story:
+    Response is Web.Json({ status: "ok" }, 200)
+"#,
+    ),
+    (
+        "handlers/list_items.sfex",
+        r#"# GET /items: every item added since the server started
+Story:
+    Items is []
+    Try:
+        Items is App.State.Items
+    Catch Error:
+        Items is []
+    Response is Web.Json({ items: Items }, 200)
+"#,
+    ),
+    (
+        "handlers/add_item.sfex",
+        r#"# POST /items with a JSON body such as {"name": "Tea"}
+Story:
+    Try:
+        Item is JSON.Parse(Request.Body)
+        # Fails unless the body is an object with a name
+        Name is Item["name"]
+        Items is []
+        Try:
+            Items is App.State.Items
+        Catch Missing:
+            Items is []
+        Set App.State.Items to Items + [Item]
+        Response is Web.Json(Item, 201)
+    Catch Error:
+        Response is Web.Json({ error: "expected a JSON object with a name" }, 400)
+"#,
+    ),
+    (
+        "tests/api_test.sfex",
+        r#"# Checks the running API; start it first with `sfex start`
+Story:
+    Base is "http://127.0.0.1:8000"
+
+    Added is HTTP.Post(Base + "/items", "{\"name\": \"Tea\"}")
+    If Added["Status"] = 201:
+        Print "PASS POST /items"
+    Else:
+        Raise "Test.Failed" with "POST /items returned " + Added["Status"]
+
+    Listed is JSON.Parse(HTTP.Get(Base + "/items")["Body"])
+    If Listed["items"].Length > 0:
+        Print "PASS GET /items"
+    Else:
+        Raise "Test.Failed" with "GET /items is empty"
+
+    Bad is HTTP.Post(Base + "/items", "not json")
+    If Bad["Status"] = 400:
+        Print "PASS bad body"
+    Else:
+        Raise "Test.Failed" with "a bad body returned " + Bad["Status"]
+"#,
+    ),
+];
+
+const CLI_ABOUT: &str = "A command-line program: main.sfex reads its settings from the environment and the work is done by lib/Greeter.sfex, which tests/ checks. Try `NAME=Ada sfex start`.";
+
+const CLI_TASKS: Pairs = &[
+    ("start", "run main.sfex"),
+    (
+        "test",
+        "run tests/greeter_test.sfex --expect tests/greeter_test.out",
+    ),
+];
+
+const CLI: Pairs = &[
+    (
+        "main.sfex",
+        r#"# Greets whoever NAME names
+Use lib.Greeter
+
+Story:
+    Create Greeter Called Hello
+    Set Hello.Name to Env.Get("NAME", "world")
+    Print Hello.Greet
+"#,
+    ),
+    (
+        "lib/Greeter.sfex",
+        r#"## Makes the greeting main.sfex prints
+Concept: Greeter
+    ## Who to greet
+    Name
+
+    To Greet:
+        Return "Hello, " + This.Name + "!"
+"#,
+    ),
+    (
+        "tests/greeter_test.sfex",
+        r#"Use lib.Greeter
+
+Story:
+    Create Greeter Called Hello
+    Set Hello.Name to "Ada"
+    Print Hello.Greet
+"#,
+    ),
+    ("tests/greeter_test.out", "Hello, Ada!\n"),
+];
+
+const WORKER_ABOUT: &str = "A background worker: main.sfex sends jobs over a channel to a task running lib/Worker.sfex and adds up the results, which tests/ checks.";
+
+const WORKER_TASKS: Pairs = &[
+    ("start", "run main.sfex"),
+    (
+        "test",
+        "run tests/worker_test.sfex --expect tests/worker_test.out",
+    ),
+];
+
+const WORKER: Pairs = &[
+    (
+        "main.sfex",
+        r#"# Jobs go to a background task over one channel, results come back on another
+Use lib.Worker
+
+Story:
+    Jobs is Channel.Create(10)
+    Results is Channel.Create(10)
+
+    Producer is Do in background:
+        Repeat 5 times with I:
+            Jobs.Send(I)
+        Jobs.Close()
+
+    Consumer is Do in background:
+        Create Worker Called Squarer
+        For each Job in Jobs:
+            Results.Send(Squarer.Handle with Job)
+        Results.Close()
+
+    Total is 0
+    For each Result in Results:
+        Total is Total + Result
+    Print "Total: " + Total
+"#,
+    ),
+    (
+        "lib/Worker.sfex",
+        r#"## Does one job
+Concept: Worker
+    To Handle with Job:
+        Return Job * Job
+"#,
+    ),
+    (
+        "tests/worker_test.sfex",
+        r#"Use lib.Worker
+
+Story:
+    Create Worker Called Squarer
+    Print Squarer.Handle with 3
+"#,
+    ),
+    ("tests/worker_test.out", "9\n"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lexer, Parser};
+
+    #[test]
+    fn test_templates_parse() {
+        for template in TEMPLATES {
+            let files = files(Some(template), "app").unwrap();
+            let manifest = &files
+                .iter()
+                .find(|(path, _)| *path == "sfex.toml")
+                .unwrap()
+                .1;
+            assert!(crate::project::check_manifest(manifest, None).is_empty());
+            for (path, text) in files.iter().filter(|(path, _)| path.ends_with(".sfex")) {
+                let tokens = Lexer::new(text).tokenize().unwrap();
+                let parsed = Parser::new(tokens).parse();
+                assert!(parsed.is_ok(), "{} {}: {:?}", template, path, parsed.err());
+            }
+        }
+        assert!(files(Some("desktop"), "app").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::runtime::lock::RwLockExt;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
+    NewAccount, NewOrder, OrderStatus, RetryPolicy,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+// How long the CA may take to validate challenges and issue the certificate
+const ORDER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// HTTP-01 challenge responses waiting to be fetched, keyed by token.
+pub type Challenges = Arc<RwLock<HashMap<String, String>>>;
+
+/// Settings from `sfex serve --acme-domain ...`.
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    pub domains: Vec<String>,
+    pub email: Option<String>,
+    pub cache_dir: PathBuf,
+    pub staging: bool,
+    /// Plain HTTP listener answering challenges (the CA always connects to port 80)
+    pub http_addr: String,
+}
+
+impl AcmeConfig {
+    fn directory_url(&self) -> &'static str {
+        if self.staging {
+            LetsEncrypt::Staging.url()
+        } else {
+            LetsEncrypt::Production.url()
+        }
+    }
+
+    // Staging and production accounts/certificates are kept apart so trying
+    // the staging CA never shadows a real certificate
+    fn storage_dir(&self) -> PathBuf {
+        if self.staging {
+            self.cache_dir.join("staging")
+        } else {
+            self.cache_dir.clone()
+        }
+    }
+
+    fn cert_dir(&self) -> PathBuf {
+        self.storage_dir().join(&self.domains[0])
+    }
+}
+
+/// A certificate chain and its private key, both PEM encoded.
+#[derive(Debug, Clone)]
+pub struct IssuedCert {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+impl IssuedCert {
+    /// When the certificate should be replaced: two thirds of the way through
+    /// its validity period, the schedule Let's Encrypt recommends.
+    pub fn renew_at(&self) -> Result<SystemTime, String> {
+        let (not_before, not_after) = validity(&self.cert_pem)?;
+        let lifetime = not_after.duration_since(not_before).unwrap_or_default();
+        Ok(not_before + lifetime * 2 / 3)
+    }
+
+    pub fn expires_at(&self) -> Result<SystemTime, String> {
+        validity(&self.cert_pem).map(|(_, not_after)| not_after)
+    }
+}
+
+fn validity(cert_pem: &str) -> Result<(SystemTime, SystemTime), String> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes())
+        .map_err(|e| format!("Invalid certificate PEM: {}", e))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| format!("Invalid certificate: {}", e))?;
+    let validity = cert.validity();
+    let to_time = |secs: i64| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64);
+    Ok((
+        to_time(validity.not_before.timestamp()),
+        to_time(validity.not_after.timestamp()),
+    ))
+}
+
+/// The certificate saved by a previous run, if it exists and has not expired.
+pub fn load_cached(config: &AcmeConfig) -> Option<IssuedCert> {
+    let dir = config.cert_dir();
+    let cert = IssuedCert {
+        cert_pem: fs::read_to_string(dir.join("cert.pem")).ok()?,
+        key_pem: fs::read_to_string(dir.join("key.pem")).ok()?,
+    };
+    match cert.expires_at() {
+        Ok(expires) if expires > SystemTime::now() => Some(cert),
+        _ => None,
+    }
+}
+
+/// Order a certificate for every configured domain, answering HTTP-01
+/// challenges through `challenges`, and save it to the cache directory.
+pub async fn obtain(config: &AcmeConfig, challenges: &Challenges) -> Result<IssuedCert, String> {
+    let account = load_account(config).await?;
+    let identifiers: Vec<Identifier> = config
+        .domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect();
+    let mut order = account
+        .new_order(&NewOrder::new(&identifiers))
+        .await
+        .map_err(|e| format!("ACME order failed: {}", e))?;
+
+    let mut tokens = Vec::new();
+    let result = async {
+        let mut authorizations = order.authorizations();
+        while let Some(authz) = authorizations.next().await {
+            let mut authz = authz.map_err(|e| format!("ACME authorization failed: {}", e))?;
+            match authz.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => {
+                    return Err(format!(
+                        "ACME authorization for {} is {:?}",
+                        authz.identifier(),
+                        status
+                    ));
+                }
+            }
+
+            let mut challenge = authz
+                .challenge(ChallengeType::Http01)
+                .ok_or_else(|| "ACME server offered no http-01 challenge".to_string())?;
+            challenges.write_recover().insert(
+                challenge.token.clone(),
+                challenge.key_authorization().as_str().to_string(),
+            );
+            tokens.push(challenge.token.clone());
+            challenge
+                .set_ready()
+                .await
+                .map_err(|e| format!("ACME challenge failed: {}", e))?;
+        }
+
+        let retries = RetryPolicy::new().timeout(ORDER_TIMEOUT);
+        let status = order
+            .poll_ready(&retries)
+            .await
+            .map_err(|e| format!("ACME validation failed: {}", e))?;
+        if status != OrderStatus::Ready {
+            return Err(format!("ACME order is {:?}, expected Ready", status));
+        }
+
+        let key_pem = order
+            .finalize()
+            .await
+            .map_err(|e| format!("ACME finalize failed: {}", e))?;
+        let cert_pem = order
+            .poll_certificate(&retries)
+            .await
+            .map_err(|e| format!("ACME certificate download failed: {}", e))?;
+        Ok(IssuedCert { cert_pem, key_pem })
+    }
+    .await;
+
+    let mut pending = challenges.write_recover();
+    for token in tokens {
+        pending.remove(&token);
+    }
+    drop(pending);
+
+    let cert = result?;
+    save_cert(config, &cert)?;
+    Ok(cert)
+}
+
+async fn load_account(config: &AcmeConfig) -> Result<Account, String> {
+    let path = config.storage_dir().join("account.json");
+    let builder = Account::builder().map_err(|e| format!("ACME client error: {}", e))?;
+
+    if let Ok(saved) = fs::read_to_string(&path) {
+        let credentials: AccountCredentials = serde_json::from_str(&saved)
+            .map_err(|e| format!("Invalid ACME account {}: {}", path.display(), e))?;
+        return builder
+            .from_credentials(credentials)
+            .await
+            .map_err(|e| format!("ACME account error: {}", e));
+    }
+
+    let contact = config
+        .email
+        .as_ref()
+        .map(|email| format!("mailto:{}", email));
+    let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+    let (account, credentials) = builder
+        .create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            config.directory_url().to_string(),
+            None,
+        )
+        .await
+        .map_err(|e| format!("ACME account registration failed: {}", e))?;
+
+    let json = serde_json::to_string_pretty(&credentials)
+        .map_err(|e| format!("Failed to encode ACME account: {}", e))?;
+    write_private(&path, &json)?;
+    Ok(account)
+}
+
+fn save_cert(config: &AcmeConfig, cert: &IssuedCert) -> Result<(), String> {
+    let dir = config.cert_dir();
+    write_private(&dir.join("key.pem"), &cert.key_pem)?;
+    fs::write(dir.join("cert.pem"), &cert.cert_pem)
+        .map_err(|e| format!("Failed to save certificate in {}: {}", dir.display(), e))
+}
+
+// Account and certificate keys are only readable by the owner
+fn write_private(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue(not_before: (i32, u8, u8), not_after: (i32, u8, u8)) -> IssuedCert {
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        params.not_before = rcgen::date_time_ymd(not_before.0, not_before.1, not_before.2);
+        params.not_after = rcgen::date_time_ymd(not_after.0, not_after.1, not_after.2);
+        let key = rcgen::KeyPair::generate().unwrap();
+        IssuedCert {
+            cert_pem: params.self_signed(&key).unwrap().pem(),
+            key_pem: key.serialize_pem(),
+        }
+    }
+
+    #[test]
+    fn test_cached_cert() {
+        let cache_dir = std::env::temp_dir().join(format!("sfex-acme-{}", std::process::id()));
+        let config = AcmeConfig {
+            domains: vec!["example.com".to_string(), "www.example.com".to_string()],
+            email: None,
+            cache_dir: cache_dir.clone(),
+            staging: false,
+            http_addr: "0.0.0.0:80".to_string(),
+        };
+        assert!(load_cached(&config).is_none());
+
+        // Renewed two thirds of the way through: 90 days from 2026-01-01
+        let cert = issue((2026, 1, 1), (2026, 4, 1));
+        let start = UNIX_EPOCH + Duration::from_secs(1_767_225_600);
+        assert_eq!(
+            cert.renew_at().unwrap(),
+            start + Duration::from_secs(60 * 86_400)
+        );
+        assert_eq!(
+            cert.expires_at().unwrap(),
+            start + Duration::from_secs(90 * 86_400)
+        );
+
+        let current = issue((2026, 1, 1), (2099, 1, 1));
+        save_cert(&config, &current).unwrap();
+        let dir = cache_dir.join("example.com");
+        assert!(dir.join("cert.pem").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join("key.pem"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(load_cached(&config).unwrap().cert_pem, current.cert_pem);
+
+        // Staging certificates are kept apart from production ones
+        let staging = AcmeConfig {
+            staging: true,
+            ..config.clone()
+        };
+        assert!(load_cached(&staging).is_none());
+
+        // An expired certificate is ordered again rather than served
+        save_cert(&config, &issue((2020, 1, 1), (2020, 4, 1))).unwrap();
+        assert!(load_cached(&config).is_none());
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
@@ -0,0 +1,1050 @@
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bytes::Bytes;
+use flate2::Compression;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use indexmap::IndexMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Zip and tar are written and read here rather than through crates: both
+// formats are a header per entry plus, for zip, a directory at the end. File
+// contents always go through a fixed buffer, so archives bigger than memory
+// work. Zip64 isn't supported, so a zip entry or archive is limited to 4 GB;
+// tar has no such limit.
+
+/// A file or directory to put in an archive, under `name`
+pub struct Entry {
+    pub source: PathBuf,
+    pub name: String,
+    pub is_dir: bool,
+}
+
+/// The entries for archiving `paths`. Each path is stored under its own name,
+/// with a directory's contents below it; `.` stores a directory's contents at
+/// the top of the archive.
+pub fn collect(paths: &[String]) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for path in paths {
+        let source = PathBuf::from(path);
+        let meta = fs::metadata(&source).map_err(|e| format!("Cannot archive {}: {}", path, e))?;
+        let name = source
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        if !meta.is_dir() {
+            let name = name.ok_or_else(|| format!("Cannot archive {}: not a file name", path))?;
+            entries.push(Entry {
+                source,
+                name,
+                is_dir: false,
+            });
+            continue;
+        }
+        if let Some(name) = &name {
+            entries.push(Entry {
+                source: source.clone(),
+                name: format!("{}/", name),
+                is_dir: true,
+            });
+        }
+        collect_dir(&source, name.as_deref().unwrap_or(""), &mut entries)?;
+    }
+    Ok(entries)
+}
+
+fn collect_dir(dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> Result<(), String> {
+    let mut children: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?
+        .flatten()
+        .collect();
+    // Sorted so the same tree always makes the same archive
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let name = child.file_name().to_string_lossy().into_owned();
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let source = child.path();
+        // Links to directories aren't followed, like Path.Walk
+        let is_dir = child.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if is_dir {
+            entries.push(Entry {
+                source: source.clone(),
+                name: format!("{}/", name),
+                is_dir: true,
+            });
+            collect_dir(&source, &name, entries)?;
+        } else if source.is_file() {
+            entries.push(Entry {
+                source,
+                name,
+                is_dir: false,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Where an entry named `name` goes below `dest`. Names that are absolute or
+/// climb out with `..` are refused, so an archive can't write elsewhere.
+fn target(dest: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut path = dest.to_path_buf();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(format!(
+                    "Refusing to extract {}: it leaves {}",
+                    name,
+                    dest.display()
+                ));
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn copy_into(reader: &mut impl Read, path: &Path) -> Result<u64, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut file = BufWriter::new(
+        File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?,
+    );
+    let written = io::copy(reader, &mut file)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    file.flush()
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(written)
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.is_dir() { 0o755 } else { 0o644 }
+}
+
+/// Restores the executable bits of extracted files, such as deploy scripts
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    if mode & 0o111 != 0 {
+        let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777));
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) {}
+
+fn modified_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+fn is_gzip(path: &Path) -> Result<bool, String> {
+    let mut file =
+        File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut magic = [0; 2];
+    Ok(file.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b])
+}
+
+fn wants_gzip(archive: &Path) -> bool {
+    let name = archive.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+// Tar (ustar, reading GNU long names and pax paths too)
+
+const BLOCK: usize = 512;
+
+/// Writes a tar archive of `entries` to `archive`, gzipped when its name
+/// ends in `.tar.gz` or `.tgz`.
+pub fn create_tar(archive: &Path, entries: &[Entry]) -> Result<(), String> {
+    let file = create_archive(archive)?;
+    let failed = |e: io::Error| format!("Failed to write {}: {}", archive.display(), e);
+    if wants_gzip(archive) {
+        let encoder = write_tar(
+            GzEncoder::new(file, Compression::default()),
+            archive,
+            entries,
+        )?;
+        encoder
+            .finish()
+            .and_then(|mut file| file.flush())
+            .map_err(failed)
+    } else {
+        write_tar(file, archive, entries)?.flush().map_err(failed)
+    }
+}
+
+fn create_archive(archive: &Path) -> Result<BufWriter<File>, String> {
+    if let Some(parent) = archive.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    File::create(archive)
+        .map(BufWriter::new)
+        .map_err(|e| format!("Failed to create {}: {}", archive.display(), e))
+}
+
+fn write_tar<W: Write>(mut out: W, archive: &Path, entries: &[Entry]) -> Result<W, String> {
+    let failed = |e: io::Error| format!("Failed to write {}: {}", archive.display(), e);
+    for entry in entries {
+        let meta = fs::metadata(&entry.source)
+            .map_err(|e| format!("Cannot archive {}: {}", entry.source.display(), e))?;
+        let size = if entry.is_dir { 0 } else { meta.len() };
+        let kind = if entry.is_dir { b'5' } else { b'0' };
+        let mode = file_mode(&meta);
+        let mtime = modified_secs(&meta);
+
+        let header = match tar_header(&entry.name, size, mode, mtime, kind) {
+            Some(header) => header,
+            None => {
+                // Too long for ustar: a GNU long name entry comes first
+                let long = format!("{}\0", entry.name);
+                let mut header =
+                    tar_header("././@LongLink", long.len() as u64, 0o644, 0, b'L').unwrap();
+                header[257..265].copy_from_slice(b"ustar  \0");
+                checksum(&mut header);
+                out.write_all(&header).map_err(failed)?;
+                write_padded(&mut out, &mut long.as_bytes(), long.len() as u64).map_err(failed)?;
+                let mut end = 100;
+                while !entry.name.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let short = &entry.name[..end];
+                let mut header = tar_header(short, size, mode, mtime, kind).unwrap();
+                header[257..265].copy_from_slice(b"ustar  \0");
+                checksum(&mut header);
+                header
+            }
+        };
+        out.write_all(&header).map_err(failed)?;
+        if !entry.is_dir {
+            let mut file = File::open(&entry.source)
+                .map_err(|e| format!("Failed to open {}: {}", entry.source.display(), e))?;
+            write_padded(&mut out, &mut file, size).map_err(failed)?;
+        }
+    }
+    // Two zero blocks end the archive
+    out.write_all(&[0; BLOCK * 2]).map_err(failed)?;
+    Ok(out)
+}
+
+/// A header block, or None when the name doesn't fit a ustar name and prefix
+fn tar_header(name: &str, size: u64, mode: u32, mtime: u64, kind: u8) -> Option<[u8; BLOCK]> {
+    let (prefix, name) = if name.len() <= 100 {
+        ("", name)
+    } else {
+        // The prefix holds the directories, split at a `/`
+        let last = name.trim_end_matches('/').len();
+        let split = name
+            .match_indices('/')
+            .map(|(at, _)| at)
+            .rfind(|&at| at <= 155 && at + 1 < last && name.len() - at - 1 <= 100)?;
+        (&name[..split], &name[split + 1..])
+    };
+    let mut header = [0; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], mode as u64);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    number(&mut header[124..136], size);
+    number(&mut header[136..148], mtime);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    checksum(&mut header);
+    Some(header)
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+/// Octal while it fits, else GNU's base-256 for files of 8 GB and more
+fn number(field: &mut [u8], value: u64) {
+    if value < 1 << (3 * (field.len() - 1)) {
+        octal(field, value);
+    } else {
+        field.fill(0);
+        field[0] = 0x80;
+        let len = field.len();
+        field[len - 8..].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn checksum(header: &mut [u8; BLOCK]) {
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    let digits = format!("{:06o}\0 ", sum);
+    header[148..156].copy_from_slice(digits.as_bytes());
+}
+
+fn write_padded(out: &mut impl Write, data: &mut impl Read, size: u64) -> io::Result<()> {
+    let copied = io::copy(&mut data.take(size), out)?;
+    if copied != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "file changed size while archiving",
+        ));
+    }
+    let padding = (BLOCK - (size as usize % BLOCK)) % BLOCK;
+    out.write_all(&[0; BLOCK][..padding])
+}
+
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut value = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            value = value.checked_mul(256)? | b as u64;
+        }
+        return Some(value);
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Extracts a tar archive, gzipped or not, into `dest`, returning the paths
+/// written. Links and devices are skipped.
+pub fn extract_tar(archive: &Path, dest: &Path) -> Result<Vec<PathBuf>, String> {
+    let gzipped = is_gzip(archive)?;
+    let file = BufReader::new(
+        File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?,
+    );
+    if gzipped {
+        read_tar(MultiGzDecoder::new(file), archive, dest)
+    } else {
+        read_tar(file, archive, dest)
+    }
+}
+
+fn read_tar(mut input: impl Read, archive: &Path, dest: &Path) -> Result<Vec<PathBuf>, String> {
+    let failed = |e: io::Error| format!("Failed to read {}: {}", archive.display(), e);
+    let mut written = Vec::new();
+    let mut long_name = None;
+    loop {
+        let mut header = [0; BLOCK];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers leave out the zero blocks at the end
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && written.is_empty() => {
+                return Err(format!("{} is not a tar archive", archive.display()));
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(failed(e)),
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let mut copy = header;
+        let stored = parse_number(&header[148..156]);
+        checksum(&mut copy);
+        if stored != parse_number(&copy[148..156]) {
+            return Err(format!("{} is not a tar archive", archive.display()));
+        }
+
+        let size = parse_number(&header[124..136])
+            .ok_or_else(|| format!("{} has a corrupt entry size", archive.display()))?;
+        let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+        let kind = header[156];
+        if kind == b'L' || kind == b'x' {
+            let mut data = Vec::new();
+            (&mut input)
+                .take(size)
+                .read_to_end(&mut data)
+                .map_err(failed)?;
+            if kind == b'L' {
+                long_name = Some(field_str(&data));
+            } else if let Some(path) = pax_path(&data) {
+                long_name = Some(path);
+            }
+            skip(&mut input, padding).map_err(failed)?;
+            continue;
+        }
+
+        let name = match long_name.take() {
+            Some(name) => name,
+            None => {
+                let name = field_str(&header[..100]);
+                let prefix = field_str(&header[345..500]);
+                if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                    format!("{}/{}", prefix, name)
+                } else {
+                    name
+                }
+            }
+        };
+        let mode = parse_number(&header[100..108]).unwrap_or(0o644) as u32;
+        match kind {
+            b'0' | b'\0' | b'7' if !name.ends_with('/') => {
+                let path = target(dest, &name)?;
+                let copied = copy_into(&mut (&mut input).take(size), &path)?;
+                if copied != size {
+                    return Err(format!(
+                        "{} ends in the middle of {}",
+                        archive.display(),
+                        name
+                    ));
+                }
+                set_mode(&path, mode);
+                written.push(path);
+            }
+            b'5' | b'0' | b'\0' => {
+                let path = target(dest, &name)?;
+                fs::create_dir_all(&path)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+                skip(&mut input, size).map_err(failed)?;
+                written.push(path);
+            }
+            _ => skip(&mut input, size).map_err(failed)?,
+        }
+        skip(&mut input, padding).map_err(failed)?;
+    }
+    Ok(written)
+}
+
+fn skip(input: &mut impl Read, len: u64) -> io::Result<()> {
+    io::copy(&mut input.take(len), &mut io::sink()).map(|_| ())
+}
+
+/// The `path` record of a pax header: lines of `<length> <key>=<value>\n`
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines().find_map(|line| {
+        let (_, record) = line.split_once(' ')?;
+        record.strip_prefix("path=").map(str::to_string)
+    })
+}
+
+// Zip
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+/// Names are UTF-8
+const UTF8_FLAG: u16 = 0x0800;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+struct Written {
+    name: String,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    attributes: u32,
+    offset: u32,
+}
+
+/// Writes a zip archive of `entries`, deflating each file.
+pub fn create_zip(archive: &Path, entries: &[Entry]) -> Result<(), String> {
+    let mut out = create_archive(archive)?;
+    let failed = |e: io::Error| format!("Failed to write {}: {}", archive.display(), e);
+    let too_big = || {
+        format!(
+            "{} would be over 4 GB, which needs Zip64; use Tar instead",
+            archive.display()
+        )
+    };
+    let mut written = Vec::new();
+    for entry in entries {
+        let meta = fs::metadata(&entry.source)
+            .map_err(|e| format!("Cannot archive {}: {}", entry.source.display(), e))?;
+        let offset =
+            u32::try_from(out.stream_position().map_err(failed)?).map_err(|_| too_big())?;
+        let (time, date) = dos_time(meta.modified().ok());
+        let method = if entry.is_dir { STORED } else { DEFLATED };
+        write_local_header(&mut out, &entry.name, method, time, date).map_err(failed)?;
+
+        let (mut crc, mut compressed, mut size) = (0, 0, 0);
+        if !entry.is_dir {
+            let mut file = File::open(&entry.source)
+                .map_err(|e| format!("Failed to open {}: {}", entry.source.display(), e))?;
+            let mut hasher = crc32fast::Hasher::new();
+            let mut encoder = DeflateEncoder::new(
+                Counter {
+                    inner: &mut out,
+                    count: 0,
+                },
+                Compression::default(),
+            );
+            let mut buffer = vec![0; 64 * 1024];
+            let mut total = 0u64;
+            loop {
+                let read = file
+                    .read(&mut buffer)
+                    .map_err(|e| format!("Failed to read {}: {}", entry.source.display(), e))?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                encoder.write_all(&buffer[..read]).map_err(failed)?;
+                total += read as u64;
+            }
+            let counted = encoder.finish().map_err(failed)?.count;
+            crc = hasher.finalize();
+            size = u32::try_from(total).map_err(|_| too_big())?;
+            compressed = u32::try_from(counted).map_err(|_| too_big())?;
+
+            // The sizes are only known now, so go back and fill them in
+            let end = out.stream_position().map_err(failed)?;
+            out.seek(SeekFrom::Start(offset as u64 + 14))
+                .map_err(failed)?;
+            out.write_all(&crc.to_le_bytes()).map_err(failed)?;
+            out.write_all(&compressed.to_le_bytes()).map_err(failed)?;
+            out.write_all(&size.to_le_bytes()).map_err(failed)?;
+            out.seek(SeekFrom::Start(end)).map_err(failed)?;
+        }
+        // Unix permissions in the high half, and the MS-DOS directory bit
+        let kind = if entry.is_dir { 0o040000 } else { 0o100000 };
+        let attributes = ((kind | file_mode(&meta)) << 16) | if entry.is_dir { 0x10 } else { 0 };
+        written.push(Written {
+            name: entry.name.clone(),
+            method,
+            time,
+            date,
+            crc,
+            compressed,
+            size,
+            attributes,
+            offset,
+        });
+    }
+
+    let directory = out.stream_position().map_err(failed)?;
+    for entry in &written {
+        let name = entry.name.as_bytes();
+        let mut header = Vec::with_capacity(46 + name.len());
+        header.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        // Made by Unix, version 2.0, so readers use the permissions
+        header.extend_from_slice(&(3u16 << 8 | 20).to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes());
+        header.extend_from_slice(&UTF8_FLAG.to_le_bytes());
+        header.extend_from_slice(&entry.method.to_le_bytes());
+        header.extend_from_slice(&entry.time.to_le_bytes());
+        header.extend_from_slice(&entry.date.to_le_bytes());
+        header.extend_from_slice(&entry.crc.to_le_bytes());
+        header.extend_from_slice(&entry.compressed.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // Extra field, comment, disk and internal attributes
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&entry.attributes.to_le_bytes());
+        header.extend_from_slice(&entry.offset.to_le_bytes());
+        header.extend_from_slice(name);
+        out.write_all(&header).map_err(failed)?;
+    }
+    let end = out.stream_position().map_err(failed)?;
+    let count = u16::try_from(written.len()).map_err(|_| {
+        format!(
+            "{} would have over 65535 entries, which needs Zip64",
+            archive.display()
+        )
+    })?;
+    let directory_size = u32::try_from(end - directory).map_err(|_| too_big())?;
+    let directory = u32::try_from(directory).map_err(|_| too_big())?;
+
+    let mut footer = Vec::with_capacity(22);
+    footer.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+    footer.extend_from_slice(&[0; 4]);
+    footer.extend_from_slice(&count.to_le_bytes());
+    footer.extend_from_slice(&count.to_le_bytes());
+    footer.extend_from_slice(&directory_size.to_le_bytes());
+    footer.extend_from_slice(&directory.to_le_bytes());
+    footer.extend_from_slice(&[0; 2]);
+    out.write_all(&footer).map_err(failed)?;
+    out.flush().map_err(failed)
+}
+
+/// A local header with the CRC and sizes left at zero, to be filled in
+fn write_local_header(
+    out: &mut impl Write,
+    name: &str,
+    method: u16,
+    time: u16,
+    date: u16,
+) -> io::Result<()> {
+    let mut header = Vec::with_capacity(30 + name.len());
+    header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+    header.extend_from_slice(&20u16.to_le_bytes());
+    header.extend_from_slice(&UTF8_FLAG.to_le_bytes());
+    header.extend_from_slice(&method.to_le_bytes());
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&date.to_le_bytes());
+    header.extend_from_slice(&[0; 12]);
+    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(name.as_bytes());
+    out.write_all(&header)
+}
+
+/// Counts the compressed bytes on their way into the archive
+struct Counter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// MS-DOS local time and date, which start in 1980
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    use chrono::{Datelike, Local, Timelike};
+    let Some(time) = modified.map(chrono::DateTime::<Local>::from) else {
+        return (0, 0x21);
+    };
+    if time.year() < 1980 {
+        return (0, 0x21);
+    }
+    let clock = (time.hour() << 11 | time.minute() << 5 | (time.second() / 2)) as u16;
+    let date =
+        (((time.year() - 1980).min(127) as u32) << 9 | time.month() << 5 | time.day()) as u16;
+    (clock, date)
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Extracts a zip archive into `dest`, returning the paths written.
+pub fn extract_zip(archive: &Path, dest: &Path) -> Result<Vec<PathBuf>, String> {
+    let failed = |e: io::Error| format!("Failed to read {}: {}", archive.display(), e);
+    let not_zip = || format!("{} is not a zip archive", archive.display());
+    let mut file =
+        File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let len = file.metadata().map_err(failed)?.len();
+
+    // The end record is in the last 22 bytes, plus a comment of up to 64 KB
+    let tail_len = len.min(22 + 0xffff);
+    file.seek(SeekFrom::Start(len - tail_len)).map_err(failed)?;
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail).map_err(failed)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| u32_at(&tail, at) == END_OF_DIRECTORY)
+        .ok_or_else(not_zip)?;
+    let count = u16_at(&tail, end + 10) as usize;
+    let directory_size = u32_at(&tail, end + 12) as usize;
+    let directory_offset = u32_at(&tail, end + 16) as u64;
+    if directory_offset == 0xffffffff || count == 0xffff {
+        return Err(format!(
+            "{} is a Zip64 archive, which isn't supported",
+            archive.display()
+        ));
+    }
+
+    file.seek(SeekFrom::Start(directory_offset))
+        .map_err(failed)?;
+    let mut directory = vec![0; directory_size];
+    file.read_exact(&mut directory).map_err(|_| not_zip())?;
+
+    let mut written = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > directory.len() || u32_at(&directory, at) != CENTRAL_HEADER {
+            return Err(not_zip());
+        }
+        let host = u16_at(&directory, at + 4) >> 8;
+        let method = u16_at(&directory, at + 10);
+        let crc = u32_at(&directory, at + 16);
+        let compressed = u32_at(&directory, at + 20) as u64;
+        let size = u32_at(&directory, at + 24) as u64;
+        let name_len = u16_at(&directory, at + 28) as usize;
+        let extra_len = u16_at(&directory, at + 30) as usize;
+        let comment_len = u16_at(&directory, at + 32) as usize;
+        let attributes = u32_at(&directory, at + 38);
+        let offset = u32_at(&directory, at + 42) as u64;
+        let name = directory
+            .get(at + 46..at + 46 + name_len)
+            .map(|name| String::from_utf8_lossy(name).replace('\\', "/"))
+            .ok_or_else(not_zip)?;
+        at += 46 + name_len + extra_len + comment_len;
+
+        let path = target(dest, &name)?;
+        if name.ends_with('/') {
+            fs::create_dir_all(&path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            written.push(path);
+            continue;
+        }
+
+        // The local header repeats the name and may have a different extra field
+        let mut local = [0; 30];
+        file.seek(SeekFrom::Start(offset)).map_err(failed)?;
+        file.read_exact(&mut local).map_err(|_| not_zip())?;
+        if u32_at(&local, 0) != LOCAL_HEADER {
+            return Err(not_zip());
+        }
+        let data = offset + 30 + u16_at(&local, 26) as u64 + u16_at(&local, 28) as u64;
+        file.seek(SeekFrom::Start(data)).map_err(failed)?;
+
+        let raw = BufReader::new((&mut file).take(compressed));
+        let mut reader: Box<dyn Read + '_> = match method {
+            STORED => Box::new(raw),
+            DEFLATED => Box::new(DeflateDecoder::new(raw)),
+            other => {
+                return Err(format!(
+                    "{} in {} uses compression method {}, which isn't supported",
+                    name,
+                    archive.display(),
+                    other
+                ));
+            }
+        };
+        let mut checked = Checked {
+            inner: &mut reader,
+            hasher: crc32fast::Hasher::new(),
+        };
+        let copied = copy_into(&mut checked, &path)?;
+        if copied != size || checked.hasher.finalize() != crc {
+            return Err(format!("{} in {} is corrupt", name, archive.display()));
+        }
+        if host == 3 {
+            set_mode(&path, (attributes >> 16) & 0o7777);
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Computes the CRC-32 of what's read through it
+struct Checked<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+// Gzip
+
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| format!("Gzip.Compress failed: {}", e))
+}
+
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    MultiGzDecoder::new(data)
+        .read_to_end(&mut out)
+        .map_err(|e| format!("Gzip.Decompress failed: {}", e))?;
+    Ok(out)
+}
+
+/// Compresses the file `source` into `dest` a piece at a time.
+pub fn gzip_file(source: &Path, dest: &Path) -> Result<(), String> {
+    let mut input =
+        File::open(source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+    let mut encoder = GzEncoder::new(create_archive(dest)?, Compression::default());
+    io::copy(&mut input, &mut encoder)
+        .and_then(|_| encoder.finish())
+        .and_then(|mut out| out.flush())
+        .map_err(|e| format!("Failed to compress {}: {}", source.display(), e))
+}
+
+/// Decompresses the gzip file `source` into `dest` a piece at a time.
+pub fn gunzip_file(source: &Path, dest: &Path) -> Result<(), String> {
+    let input = BufReader::new(
+        File::open(source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?,
+    );
+    let mut decoder = MultiGzDecoder::new(input);
+    copy_into(&mut decoder, dest)
+        .map_err(|e| format!("Failed to decompress {}: {}", source.display(), e))
+        .map(|_| ())
+}
+
+fn data_bytes(value: &Value, function: &str) -> Result<Vec<u8>, String> {
+    match value {
+        Value::Bytes(bytes) => Ok(bytes.to_vec()),
+        Value::String(text) => Ok(text.as_bytes().to_vec()),
+        other => Err(format!(
+            "Gzip.{} expects Bytes or text, got {}",
+            function,
+            other.type_name()
+        )),
+    }
+}
+
+/// A list of paths, or a single path
+fn path_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::List(list) => list
+            .read()
+            .map(|items| items.iter().map(Value::to_display_string).collect())
+            .unwrap_or_default(),
+        other => vec![other.to_display_string()],
+    }
+}
+
+fn path_values(paths: Vec<PathBuf>) -> Value {
+    let paths = paths
+        .into_iter()
+        .map(|path| Value::String(path.to_string_lossy().into_owned()))
+        .collect();
+    Value::List(Arc::new(RwLock::new(paths)))
+}
+
+fn name_values(entries: &[Entry]) -> Value {
+    let names = entries
+        .iter()
+        .map(|entry| Value::String(entry.name.clone()))
+        .collect();
+    Value::List(Arc::new(RwLock::new(names)))
+}
+
+type CreateFn = fn(&Path, &[Entry]) -> Result<(), String>;
+type ExtractFn = fn(&Path, &Path) -> Result<Vec<PathBuf>, String>;
+
+fn create_format_module(module: &'static str, create: CreateFn, extract: ExtractFn) -> Value {
+    let mut methods = IndexMap::new();
+
+    // Zip.Create("site.zip", ["public", "index.sfex"]) -> List of entry names
+    methods.insert(
+        "Create".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 2 {
+                return Err(format!(
+                    "{}.Create requires 2 arguments (archive, path or list of paths)",
+                    module
+                ));
+            }
+            let archive = PathBuf::from(args[0].to_display_string());
+            let paths = path_list(&args[1]);
+            permissions::check_write(&archive)?;
+            for path in &paths {
+                permissions::check_read(path)?;
+            }
+            let mut entries = collect(&paths)?;
+            // An archive written into the directory it archives leaves
+            // out its own previous version
+            if let Ok(own) = fs::canonicalize(&archive) {
+                entries.retain(|entry| fs::canonicalize(&entry.source).ok() != Some(own.clone()));
+            }
+            create(&archive, &entries)?;
+            Ok(name_values(&entries))
+        }))),
+    );
+
+    // Zip.Extract("site.zip", "deploy") -> List of extracted paths
+    methods.insert(
+        "Extract".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(format!(
+                    "{}.Extract requires 1-2 arguments (archive, optional directory)",
+                    module
+                ));
+            }
+            let archive = PathBuf::from(args[0].to_display_string());
+            let dest = args
+                .get(1)
+                .map(|dest| PathBuf::from(dest.to_display_string()))
+                .unwrap_or_else(|| PathBuf::from("."));
+            permissions::check_read(&archive)?;
+            permissions::check_write(&dest)?;
+            extract(&archive, &dest).map(path_values)
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+pub fn create_zip_module() -> Value {
+    create_format_module("Zip", create_zip, extract_zip)
+}
+
+pub fn create_tar_module() -> Value {
+    create_format_module("Tar", create_tar, extract_tar)
+}
+
+pub fn create_gzip_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Gzip.Compress(data) -> Bytes
+    methods.insert(
+        "Compress".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Gzip.Compress requires 1 argument (Bytes or text)".to_string());
+            }
+            let data = data_bytes(&args[0], "Compress")?;
+            gzip(&data).map(|out| Value::Bytes(Bytes::from(out)))
+        }))),
+    );
+
+    // Gzip.Decompress(bytes) -> Bytes
+    methods.insert(
+        "Decompress".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Gzip.Decompress requires 1 argument (Bytes)".to_string());
+            }
+            let data = data_bytes(&args[0], "Decompress")?;
+            gunzip(&data).map(|out| Value::Bytes(Bytes::from(out)))
+        }))),
+    );
+
+    // Gzip.CompressFile("app.log") -> "app.log.gz"
+    methods.insert(
+        "CompressFile".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Gzip.CompressFile requires 1-2 arguments (path, optional destination)"
+                        .to_string(),
+                );
+            }
+            let source = args[0].to_display_string();
+            let dest = match args.get(1) {
+                Some(dest) => dest.to_display_string(),
+                None => format!("{}.gz", source),
+            };
+            permissions::check_read(&source)?;
+            permissions::check_write(&dest)?;
+            gzip_file(Path::new(&source), Path::new(&dest))?;
+            Ok(Value::String(dest))
+        }))),
+    );
+
+    // Gzip.DecompressFile("app.log.gz") -> "app.log"
+    methods.insert(
+        "DecompressFile".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Gzip.DecompressFile requires 1-2 arguments (path, optional destination)"
+                        .to_string(),
+                );
+            }
+            let source = args[0].to_display_string();
+            let dest = match args.get(1) {
+                Some(dest) => dest.to_display_string(),
+                None => match source.strip_suffix(".gz") {
+                    Some(stripped) => stripped.to_string(),
+                    None => {
+                        return Err(format!(
+                            "Gzip.DecompressFile needs a destination for {}, which doesn't end in .gz",
+                            source
+                        ));
+                    }
+                },
+            };
+            permissions::check_read(&source)?;
+            permissions::check_write(&dest)?;
+            gunzip_file(Path::new(&source), Path::new(&dest))?;
+            Ok(Value::String(dest))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let root = std::env::temp_dir().join(format!("sfex-archive-{}", std::process::id()));
+        let site = root.join("site");
+        let deep = format!("{}/{}", "d".repeat(90), "e".repeat(90));
+        fs::create_dir_all(site.join(&deep)).unwrap();
+        fs::write(site.join("index.html"), "<h1>Hi</h1>\n".repeat(1000)).unwrap();
+        fs::write(site.join(&deep).join("file.txt"), "deep").unwrap();
+        fs::write(site.join("f".repeat(120)), "long").unwrap();
+        fs::create_dir_all(site.join("empty")).unwrap();
+        let entries = collect(&[site.to_string_lossy().into_owned()]).unwrap();
+        assert_eq!(entries[0].name, "site/");
+
+        for (name, create, extract) in [
+            ("site.zip", create_zip as CreateFn, extract_zip as ExtractFn),
+            ("site.tar", create_tar, extract_tar),
+            ("site.tar.gz", create_tar, extract_tar),
+        ] {
+            let archive = root.join(name);
+            create(&archive, &entries).unwrap();
+            let out = root.join(format!("out-{}", name));
+            let written = extract(&archive, &out).unwrap();
+            assert_eq!(written.len(), entries.len(), "{}", name);
+            assert_eq!(
+                fs::read(out.join("site/index.html")).unwrap(),
+                fs::read(site.join("index.html")).unwrap()
+            );
+            assert_eq!(
+                fs::read_to_string(out.join("site").join(&deep).join("file.txt")).unwrap(),
+                "deep"
+            );
+            assert!(out.join("site/empty").is_dir());
+            assert!(out.join("site").join("f".repeat(120)).is_file());
+        }
+        assert!(fs::metadata(root.join("site.zip")).unwrap().len() < 6000);
+
+        // Entries can't climb out of the destination
+        let evil = [Entry {
+            source: site.join("index.html"),
+            name: "../evil.txt".to_string(),
+            is_dir: false,
+        }];
+        create_zip(&root.join("evil.zip"), &evil).unwrap();
+        assert!(extract_zip(&root.join("evil.zip"), &root.join("x")).is_err());
+        assert!(!root.join("evil.txt").exists());
+
+        let data = b"hello hello hello hello".repeat(10);
+        assert_eq!(gunzip(&gzip(&data).unwrap()).unwrap(), data);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use crate::runtime::lock::MutexExt;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+// Fingerprinted static assets: a file is also served under a name holding
+// a hash of its contents (`app.css` as `app.3fa2c1d9.css`), and links to it
+// in served HTML are rewritten to that name. A new version of the file gets
+// a new name, so the old one can be cached for good.
+
+/// Hex digits of the SHA-256 kept in a fingerprinted name
+const HASH_LEN: usize = 8;
+
+pub const IMMUTABLE_CACHE: &str = "public, max-age=31536000, immutable";
+
+// Size, modification time and hash of a file when it was last hashed
+type Fingerprinted = (u64, Option<SystemTime>, String);
+
+static FINGERPRINTS: OnceLock<Mutex<HashMap<PathBuf, Fingerprinted>>> = OnceLock::new();
+
+/// The fingerprint of a file's current contents, hashing it again only when
+/// its size or modification time changed.
+pub fn fingerprint(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let (len, modified) = (metadata.len(), metadata.modified().ok());
+    let cache = FINGERPRINTS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some((cached_len, cached_modified, hash)) = cache.lock_recover().get(path)
+        && *cached_len == len
+        && *cached_modified == modified
+    {
+        return Some(hash.clone());
+    }
+
+    let hash = content_hash(&fs::read(path).ok()?);
+    cache
+        .lock_recover()
+        .insert(path.to_path_buf(), (len, modified, hash.clone()));
+    Some(hash)
+}
+
+/// The first hex digits of the SHA-256 of `contents`.
+pub fn content_hash(contents: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, contents);
+    digest.as_ref()[..HASH_LEN / 2]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Hash every file under `dir`, so the first requests don't have to.
+pub fn warm(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            warm(&path);
+        } else {
+            fingerprint(&path);
+        }
+    }
+}
+
+/// `css/app.css` with hash `3fa2c1d9` is `css/app.3fa2c1d9.css`.
+pub fn fingerprinted_path(path: &str, hash: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{}.{}.{}", dir, stem, hash, ext),
+        _ => format!("{}{}.{}", dir, name, hash),
+    }
+}
+
+/// The path a fingerprinted name stands for, and the hash it carries:
+/// `css/app.3fa2c1d9.css` is `css/app.css` with `3fa2c1d9`.
+pub fn split_fingerprint(path: &str) -> Option<(String, String)> {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    let is_hash = |part: &str| {
+        part.len() == HASH_LEN
+            && part
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    };
+
+    let parts: Vec<&str> = name.split('.').collect();
+    let (plain, hash) = match parts.as_slice() {
+        [.., hash, ext] if is_hash(hash) && parts.len() > 2 => {
+            let stem = parts[..parts.len() - 2].join(".");
+            (format!("{}.{}", stem, ext), hash)
+        }
+        [.., hash] if is_hash(hash) && parts.len() > 1 => {
+            (parts[..parts.len() - 1].join("."), hash)
+        }
+        _ => return None,
+    };
+    Some((format!("{}{}", dir, plain), hash.to_string()))
+}
+
+/// Rewrite the `src` and `href` attributes of `html` whose URL `resolve`
+/// gives a new path for. A query or fragment after the path is kept.
+pub fn rewrite_urls(html: &str, resolve: impl Fn(&str) -> Option<String>) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut result = String::with_capacity(html.len());
+    let mut copied = 0;
+
+    while let Some((value_start, quote)) = find_attribute(&lower, copied) {
+        let end = html[value_start..]
+            .find(quote)
+            .map_or(html.len(), |offset| value_start + offset);
+        let url = &html[value_start..end];
+        let split = url.find(['?', '#']).unwrap_or(url.len());
+        result.push_str(&html[copied..value_start]);
+        match resolve(&url[..split]) {
+            Some(path) => {
+                result.push_str(&path);
+                result.push_str(&url[split..]);
+            }
+            None => result.push_str(url),
+        }
+        copied = end;
+    }
+    result.push_str(&html[copied..]);
+    result
+}
+
+/// Where the next quoted `src=` or `href=` value after `from` starts, and its
+/// quote character. `lower` is the HTML in lowercase.
+fn find_attribute(lower: &str, mut from: usize) -> Option<(usize, char)> {
+    while from < lower.len() {
+        let (start, len) = ["src=", "href="]
+            .iter()
+            .filter_map(|name| lower[from..].find(name).map(|i| (from + i, name.len())))
+            .min()?;
+        let preceded = start == 0 || lower.as_bytes()[start - 1].is_ascii_whitespace();
+        if preceded && let Some(quote @ ('"' | '\'')) = lower[start + len..].chars().next() {
+            return Some((start + len + 1, quote));
+        }
+        from = start + len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprinted_names() {
+        assert_eq!(
+            fingerprinted_path("css/app.css", "3fa2c1d9"),
+            "css/app.3fa2c1d9.css"
+        );
+        assert_eq!(
+            fingerprinted_path("LICENSE", "3fa2c1d9"),
+            "LICENSE.3fa2c1d9"
+        );
+        assert_eq!(
+            split_fingerprint("css/app.min.3fa2c1d9.css"),
+            Some(("css/app.min.css".to_string(), "3fa2c1d9".to_string()))
+        );
+        assert_eq!(
+            split_fingerprint("LICENSE.3fa2c1d9"),
+            Some(("LICENSE".to_string(), "3fa2c1d9".to_string()))
+        );
+        assert_eq!(split_fingerprint("app.css"), None);
+        assert_eq!(split_fingerprint("3fa2c1d9"), None);
+
+        let html =
+            "<link href=\"/app.css?v=1\"><img SRC='/logo.png'><a href=/x>data-src=\"/app.css\"";
+        let rewritten = rewrite_urls(html, |url| {
+            (url != "/x").then(|| fingerprinted_path(url, "00000000"))
+        });
+        assert_eq!(
+            rewritten,
+            "<link href=\"/app.00000000.css?v=1\"><img SRC='/logo.00000000.png'><a href=/x>data-src=\"/app.css\""
+        );
+    }
+}
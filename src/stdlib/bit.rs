@@ -0,0 +1,108 @@
+use crate::runtime::value::Value;
+use bigdecimal::num_bigint::BigInt;
+use indexmap::IndexMap;
+use std::sync::Arc;
+
+// The bitwise operators as functions, for masks built from several flags
+// and for fixed-width values such as checksums and protocol fields. Like
+// the operators they take whole numbers of any numeric type and give an
+// Integer. Where a width in bits is given, the result is cut to that many
+// bits and so is never negative.
+
+type BinaryOp = fn(&Value, &Value) -> Result<Value, String>;
+
+pub fn create_bit_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Bit.And(a, b, ...), Bit.Or(a, b, ...), Bit.Xor(a, b, ...)
+    let folds: [(&str, BinaryOp); 3] = [
+        ("And", Value::bit_and),
+        ("Or", Value::bit_or),
+        ("Xor", Value::bit_xor),
+    ];
+    for (name, op) in folds {
+        methods.insert(
+            name.to_string(),
+            Value::NativeFunction(Arc::new(Box::new(move |args| {
+                if args.len() < 2 {
+                    return Err(format!("Bit.{} requires at least 2 arguments", name));
+                }
+                args[1..]
+                    .iter()
+                    .try_fold(args[0].clone(), |result, value| op(&result, value))
+            }))),
+        );
+    }
+
+    // Bit.Not(value, bits?) -> every bit flipped
+    methods.insert(
+        "Not".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("Bit.Not requires 1 or 2 arguments (value, optional bits)".to_string());
+            }
+            let result = args[0].bit_not()?;
+            match args.get(1) {
+                Some(bits) => truncate(&result, bits),
+                None => Ok(result),
+            }
+        }))),
+    );
+
+    // Bit.ShiftLeft(value, amount, bits?)
+    methods.insert(
+        "ShiftLeft".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "Bit.ShiftLeft requires 2 or 3 arguments (value, amount, optional bits)"
+                        .to_string(),
+                );
+            }
+            let result = args[0].shift_left(&args[1])?;
+            match args.get(2) {
+                Some(bits) => truncate(&result, bits),
+                None => Ok(result),
+            }
+        }))),
+    );
+
+    // Bit.ShiftRight(value, amount) -> rounds toward negative infinity
+    methods.insert(
+        "ShiftRight".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Bit.ShiftRight requires 2 arguments (value, amount)".to_string());
+            }
+            args[0].shift_right(&args[1])
+        }))),
+    );
+
+    // Bit.Test(value, index) -> whether bit `index` (0 is the lowest) is set
+    methods.insert(
+        "Test".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Bit.Test requires 2 arguments (value, index)".to_string());
+            }
+            let bit = args[0].shift_right(&args[1])?.bit_and(&one())?;
+            Ok(Value::Boolean(bit.equals(&one())))
+        }))),
+    );
+
+    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+}
+
+fn one() -> Value {
+    Value::Integer(BigInt::from(1))
+}
+
+// The low `bits` bits of `value`
+fn truncate(value: &Value, bits: &Value) -> Result<Value, String> {
+    let limit = one().shift_left(bits)?;
+    let mask = limit
+        .as_integer()
+        .map(|limit| limit - 1)
+        .unwrap_or_default();
+    value.bit_and(&Value::Integer(mask))
+}
@@ -0,0 +1,258 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::value::Value;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bigdecimal::ToPrimitive;
+use bigdecimal::num_bigint::BigInt;
+use bytes::Bytes;
+use indexmap::IndexMap;
+use std::sync::{Arc, RwLock};
+
+pub fn create_bytes_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Bytes.FromString("text") or Bytes.FromString("text", "utf-16le")
+    methods.insert(
+        "FromString".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Bytes.FromString requires 1 or 2 arguments (text, optional encoding)"
+                        .to_string(),
+                );
+            }
+            let text = args[0].to_display_string();
+            let encoding = args.get(1).map(|e| e.to_display_string());
+            encode(&text, encoding.as_deref().unwrap_or("utf-8")).map(bytes_value)
+        }))),
+    );
+
+    // Bytes.FromBase64("aGk=")
+    methods.insert(
+        "FromBase64".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Bytes.FromBase64 requires 1 argument (base64 text)".to_string());
+            }
+            let text = args[0].to_display_string();
+            BASE64
+                .decode(text.trim())
+                .map(bytes_value)
+                .map_err(|e| format!("Invalid base64: {}", e))
+        }))),
+    );
+
+    // Bytes.FromHex("cafe01")
+    methods.insert(
+        "FromHex".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Bytes.FromHex requires 1 argument (hex text)".to_string());
+            }
+            from_hex(&args[0].to_display_string()).map(bytes_value)
+        }))),
+    );
+
+    // Bytes.FromList([104, 105])
+    methods.insert(
+        "FromList".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err(
+                    "Bytes.FromList requires 1 argument (List of numbers 0-255)".to_string()
+                );
+            }
+            let Value::List(list) = &args[0] else {
+                return Err("Bytes.FromList expects a List of numbers 0-255".to_string());
+            };
+            let list = list.read_recover();
+            let mut data = Vec::with_capacity(list.len());
+            for item in list.iter() {
+                let byte = match item {
+                    Value::Number(n) => n.to_u8(),
+                    Value::Integer(i) => i.to_u8(),
+                    Value::FastNumber(f) if f.fract() == 0.0 && (0.0..=255.0).contains(f) => {
+                        Some(*f as u8)
+                    }
+                    _ => None,
+                };
+                data.push(byte.ok_or_else(|| {
+                    format!(
+                        "Bytes.FromList: {} is not a byte (0-255)",
+                        item.to_display_string()
+                    )
+                })?);
+            }
+            Ok(bytes_value(data))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// Methods available on a Bytes value (`Payload.Slice with 1 and 4`,
+/// `Payload.ToString`, ...). Length comes from `Value::len` like other values.
+pub fn bytes_member(bytes: &Bytes, member: &str) -> Option<Value> {
+    let bytes = bytes.clone();
+    let method: Box<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync> = match member {
+        "Slice" => Box::new(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Bytes.Slice requires 1 or 2 arguments (start, optional end)".to_string(),
+                );
+            }
+            let len = bytes.len() as i64;
+            let start = position(&args[0], len)?;
+            let end = match args.get(1) {
+                Some(end) => position(end, len)?,
+                None => len,
+            };
+            if start < 1 || end > len || start > end + 1 {
+                return Err(format!(
+                    "Bytes.Slice range {} to {} out of bounds for {} bytes",
+                    start, end, len
+                ));
+            }
+            Ok(Value::Bytes(bytes.slice(start as usize - 1..end as usize)))
+        }),
+        "ToString" => Box::new(move |args| {
+            if args.len() > 1 {
+                return Err("Bytes.ToString takes an optional encoding".to_string());
+            }
+            let encoding = args.first().map(|e| e.to_display_string());
+            decode(&bytes, encoding.as_deref().unwrap_or("utf-8")).map(Value::String)
+        }),
+        "ToBase64" => Box::new(move |_args| Ok(Value::String(BASE64.encode(&bytes)))),
+        "ToHex" => Box::new(move |_args| {
+            Ok(Value::String(
+                bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            ))
+        }),
+        "ToList" => Box::new(move |_args| {
+            let list = bytes
+                .iter()
+                .map(|b| Value::Integer(BigInt::from(*b)))
+                .collect();
+            Ok(Value::List(Arc::new(RwLock::new(list))))
+        }),
+        _ => return None,
+    };
+    Some(Value::NativeFunction(Arc::new(method)))
+}
+
+fn bytes_value(data: Vec<u8>) -> Value {
+    Value::Bytes(Bytes::from(data))
+}
+
+// 1-based position; negative counts back from the end like indexing does
+fn position(value: &Value, len: i64) -> Result<i64, String> {
+    let n = match value {
+        Value::Number(n) => n.to_i64(),
+        Value::Integer(i) => i.to_i64(),
+        Value::FastNumber(f) if f.fract() == 0.0 => Some(*f as i64),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        format!(
+            "Bytes.Slice positions must be whole numbers, got {}",
+            value.to_display_string()
+        )
+    })?;
+    match n {
+        0 => Err("SFX bytes start at 1, not 0".to_string()),
+        n if n < 0 => Ok(len + n + 1),
+        n => Ok(n),
+    }
+}
+
+fn normalize_encoding(encoding: &str) -> String {
+    encoding.to_lowercase().replace(['-', '_'], "")
+}
+
+fn encode(text: &str, encoding: &str) -> Result<Vec<u8>, String> {
+    match normalize_encoding(encoding).as_str() {
+        "utf8" => Ok(text.as_bytes().to_vec()),
+        "ascii" => narrow(text, 0x7f, encoding),
+        "latin1" | "iso88591" => narrow(text, 0xff, encoding),
+        "utf16le" => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+        "utf16be" => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+        _ => Err(unknown_encoding(encoding)),
+    }
+}
+
+// Single-byte encodings: every character must be at most `limit`
+fn narrow(text: &str, limit: u32, encoding: &str) -> Result<Vec<u8>, String> {
+    text.chars()
+        .map(|c| {
+            if c as u32 <= limit {
+                Ok(c as u8)
+            } else {
+                Err(format!(
+                    "Character '{}' cannot be encoded as {}",
+                    c, encoding
+                ))
+            }
+        })
+        .collect()
+}
+
+fn decode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    let normalized = normalize_encoding(encoding);
+    match normalized.as_str() {
+        "utf8" => String::from_utf8(bytes.to_vec())
+            .map_err(|e| format!("Bytes are not valid UTF-8: {}", e)),
+        "ascii" => match bytes.iter().position(|b| !b.is_ascii()) {
+            Some(i) => Err(format!("Byte {} (0x{:02x}) is not ASCII", i + 1, bytes[i])),
+            None => Ok(bytes.iter().map(|b| *b as char).collect()),
+        },
+        "latin1" | "iso88591" => Ok(bytes.iter().map(|b| *b as char).collect()),
+        "utf16le" | "utf16be" => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(format!(
+                    "{} data must have an even number of bytes",
+                    encoding
+                ));
+            }
+            let little = normalized == "utf16le";
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    if little {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }
+                })
+                .collect();
+            String::from_utf16(&units)
+                .map_err(|e| format!("Bytes are not valid {}: {}", encoding, e))
+        }
+        _ => Err(unknown_encoding(encoding)),
+    }
+}
+
+fn unknown_encoding(encoding: &str) -> String {
+    format!(
+        "Unknown encoding '{}' (expected utf-8, ascii, latin1, utf-16le or utf-16be)",
+        encoding
+    )
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("Hex text must have an even number of digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let high = pair[0].to_digit(16);
+            let low = pair[1].to_digit(16);
+            match (high, low) {
+                (Some(high), Some(low)) => Ok((high * 16 + low) as u8),
+                _ => Err(format!("Invalid hex digits '{}{}'", pair[0], pair[1])),
+            }
+        })
+        .collect()
+}
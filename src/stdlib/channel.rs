@@ -1,122 +1,561 @@
-use crate::runtime::interpreter::Interpreter;
+use crate::runtime::deadline;
+use crate::runtime::lock::MutexExt;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use indexmap::IndexMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, Weak};
+use std::time::{Duration, Instant};
 
-pub fn create_channel_module(interpreter: &Interpreter) -> Value {
-    let mut methods = HashMap::new();
-    let runtime = interpreter.runtime.clone();
+// A channel is a bounded queue that tasks share. Send waits while it is
+// full and Receive while it is empty. Close stops further sends, but the
+// values already queued can still be received, and `For each` over a
+// channel ends once a closed channel runs dry.
 
-    // Channel.Create(buffer_size) - Create a new channel
-    // Returns a Map with "Send" and "Receive" methods
+struct Queue {
+    values: VecDeque<Value>,
+    closed: bool,
+    // Channel.Select calls waiting on this channel among others
+    watchers: Vec<Arc<Signal>>,
+}
+
+struct Shared {
+    capacity: usize,
+    queue: Mutex<Queue>,
+    // Notified when a value arrives or the channel closes
+    filled: Condvar,
+    // Notified when a value leaves or the channel closes
+    emptied: Condvar,
+}
+
+/// Wakes a Select when any of its channels changes.
+#[derive(Default)]
+struct Signal {
+    fired: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Signal {
+    fn fire(&self) {
+        *self.fired.lock_recover() = true;
+        self.condvar.notify_all();
+    }
+}
+
+enum Received {
+    Value(Value),
+    Closed,
+    TimedOut,
+}
+
+impl Shared {
+    fn changed(&self, queue: &mut Queue, filled: bool) {
+        if filled {
+            self.filled.notify_all();
+            for watcher in &queue.watchers {
+                watcher.fire();
+            }
+        } else {
+            self.emptied.notify_all();
+        }
+    }
+
+    fn send(&self, value: Value, timeout: Option<Duration>) -> Result<bool, String> {
+        let waited_until = timeout.map(|timeout| Instant::now() + timeout);
+        let mut queue = self.queue.lock_recover();
+        loop {
+            if queue.closed {
+                return Err("Channel closed".to_string());
+            }
+            if queue.values.len() < self.capacity {
+                queue.values.push_back(value);
+                self.changed(&mut queue, true);
+                return Ok(true);
+            }
+            let Some(wait) = remaining(waited_until)? else {
+                return Ok(false);
+            };
+            queue = wait_on(&self.emptied, queue, wait);
+        }
+    }
+
+    fn try_take(&self, queue: &mut Queue) -> Option<Received> {
+        if let Some(value) = queue.values.pop_front() {
+            self.changed(queue, false);
+            Some(Received::Value(value))
+        } else if queue.closed {
+            Some(Received::Closed)
+        } else {
+            None
+        }
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> Result<Received, String> {
+        let waited_until = timeout.map(|timeout| Instant::now() + timeout);
+        let mut queue = self.queue.lock_recover();
+        loop {
+            if let Some(received) = self.try_take(&mut queue) {
+                return Ok(received);
+            }
+            let Some(wait) = remaining(waited_until)? else {
+                return Ok(Received::TimedOut);
+            };
+            queue = wait_on(&self.filled, queue, wait);
+        }
+    }
+
+    fn close(&self) -> bool {
+        let mut queue = self.queue.lock_recover();
+        let was_open = !queue.closed;
+        queue.closed = true;
+        self.changed(&mut queue, true);
+        self.changed(&mut queue, false);
+        was_open
+    }
+}
+
+/// How long the next wait may take: `None` when `until` has passed,
+/// `Some(None)` to wait indefinitely. A request deadline shortens either,
+/// and fails the call once it has passed.
+fn remaining(until: Option<Instant>) -> Result<Option<Option<Duration>>, String> {
+    deadline::check()?;
+    let left = match until {
+        Some(until) => match until.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Some(left),
+            _ => return Ok(None),
+        },
+        None => None,
+    };
+    Ok(Some(deadline::limit(left)))
+}
+
+fn wait_on<'a, T>(
+    condvar: &Condvar,
+    guard: MutexGuard<'a, T>,
+    wait: Option<Duration>,
+) -> MutexGuard<'a, T> {
+    match wait {
+        Some(wait) => condvar
+            .wait_timeout(guard, wait)
+            .map(|(guard, _)| guard)
+            .unwrap_or_else(|poisoned| poisoned.into_inner().0),
+        None => condvar
+            .wait(guard)
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    }
+}
+
+// Channel.Select finds a channel's queue through the Id in its Map, which
+// survives the copies `Do in background` makes of its variables
+fn registry() -> &'static Mutex<HashMap<u64, Weak<Shared>>> {
+    static CHANNELS: OnceLock<Mutex<HashMap<u64, Weak<Shared>>>> = OnceLock::new();
+    CHANNELS.get_or_init(Default::default)
+}
+
+fn register(shared: &Arc<Shared>) -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut channels = registry().lock_recover();
+    channels.retain(|_, shared| shared.strong_count() > 0);
+    channels.insert(id, Arc::downgrade(shared));
+    id
+}
+
+fn lookup(channel: &Value) -> Option<Arc<Shared>> {
+    let Value::Map(map) = channel else {
+        return None;
+    };
+    let id = match map.read().ok()?.get("Id")? {
+        Value::Integer(id) => {
+            use bigdecimal::ToPrimitive;
+            id.to_u64()?
+        }
+        _ => return None,
+    };
+    registry().lock_recover().get(&id)?.upgrade()
+}
+
+fn seconds(value: &Value, what: &str) -> Result<f64, String> {
+    use bigdecimal::ToPrimitive;
+    let seconds = match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    };
+    seconds
+        .filter(|s| s.is_finite() && *s >= 0.0)
+        .ok_or_else(|| format!("{} must be a number that isn't negative", what))
+}
+
+fn received_option(received: Received) -> Value {
+    match received {
+        Received::Value(value) => Value::Option(Box::new(Some(value))),
+        Received::Closed | Received::TimedOut => Value::Option(Box::new(None)),
+    }
+}
+
+pub fn create_channel_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Channel.Create(capacity) - Create a new channel
+    // Returns a Map with Send, Receive, Close and the rest
     methods.insert(
         "Create".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
-            let buffer_size = if args.is_empty() {
-                10 // Default buffer size
+            let capacity = if args.is_empty() {
+                10 // Default capacity
             } else {
                 match &args[0] {
                     Value::Number(n) => {
                         use bigdecimal::ToPrimitive;
                         n.to_usize().ok_or("Invalid buffer size")?
                     }
+                    Value::Integer(i) => {
+                        use bigdecimal::ToPrimitive;
+                        i.to_usize().ok_or("Invalid buffer size")?
+                    }
                     _ => return Err("Buffer size must be a number".to_string()),
                 }
             };
+            if capacity == 0 {
+                return Err("A channel must hold at least 1 value".to_string());
+            }
 
-            // Create the channel
-            let (tx, rx) = mpsc::channel::<Value>(buffer_size);
+            let shared = Arc::new(Shared {
+                capacity,
+                queue: Mutex::new(Queue {
+                    values: VecDeque::new(),
+                    closed: false,
+                    watchers: Vec::new(),
+                }),
+                filled: Condvar::new(),
+                emptied: Condvar::new(),
+            });
+            Ok(create_channel_object(shared))
+        }))),
+    );
 
-            // Wrap receiver in Arc Mutex so it can be shared across Receive calls
-            let rx_shared = Arc::new(tokio::sync::Mutex::new(rx));
+    // Channel.Select([Jobs, Results], timeout_ms?) - Receive from whichever
+    // channel has a value first
+    methods.insert(
+        "Select".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Channel.Select requires 1-2 arguments (List of channels, optional timeout in milliseconds)"
+                        .to_string(),
+                );
+            }
+            let Value::List(list) = &args[0] else {
+                return Err("Channel.Select needs a List of channels".to_string());
+            };
+            let channels = list.read().map_err(|e| e.to_string())?.clone();
+            if channels.is_empty() {
+                return Err("Channel.Select needs at least one channel".to_string());
+            }
+            let shared = channels
+                .iter()
+                .map(|channel| lookup(channel).ok_or("Channel.Select takes only channels"))
+                .collect::<Result<Vec<_>, _>>()?;
+            let timeout = match args.get(1) {
+                Some(ms) => Some(Duration::from_secs_f64(
+                    seconds(ms, "Channel.Select's timeout")? / 1000.0,
+                )),
+                None => None,
+            };
+            select(&channels, &shared, timeout)
+        }))),
+    );
 
-            // Create Send function
-            let tx_clone = tx.clone();
-            let send_fn = Value::NativeFunction(Arc::new(Box::new(move |args| {
-                if args.len() != 1 {
-                    return Err("Send requires 1 argument (value to send)".to_string());
-                }
+    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+}
 
-                let value = args[0].clone();
-                let tx = tx_clone.clone();
+fn select(
+    channels: &[Value],
+    shared: &[Arc<Shared>],
+    timeout: Option<Duration>,
+) -> Result<Value, String> {
+    let waited_until = timeout.map(|timeout| Instant::now() + timeout);
+    let signal = Arc::new(Signal::default());
+    for channel in shared {
+        channel.queue.lock_recover().watchers.push(signal.clone());
+    }
+    // Start from a random channel so that a busy one can't starve the rest
+    let start = rand::random_range(0..shared.len());
 
-                // Use blocking_send since we are in a sync context
-                tx.blocking_send(value)
-                    .map_err(|_| "Channel closed".to_string())?;
+    let outcome = loop {
+        *signal.fired.lock_recover() = false;
+        let ready = (0..shared.len()).find_map(|offset| {
+            let index = (start + offset) % shared.len();
+            let received = shared[index].try_take(&mut shared[index].queue.lock_recover());
+            received.map(|received| (index, received))
+        });
+        if let Some(ready) = ready {
+            break Ok(Some(ready));
+        }
+        let wait = match remaining(waited_until) {
+            Ok(Some(wait)) => wait,
+            Ok(None) => break Ok(None),
+            Err(e) => break Err(e),
+        };
+        let fired = signal.fired.lock_recover();
+        if !*fired {
+            drop(wait_on(&signal.condvar, fired, wait));
+        }
+    };
 
-                Ok(Value::Boolean(true))
-            })));
+    for channel in shared {
+        channel
+            .queue
+            .lock_recover()
+            .watchers
+            .retain(|watcher| !Arc::ptr_eq(watcher, &signal));
+    }
 
-            // Create Receive function
-            let runtime_clone = runtime.clone();
-            let rx_clone = rx_shared.clone();
-            let receive_fn = Value::NativeFunction(Arc::new(Box::new(move |args| {
-                if !args.is_empty() {
-                    return Err("Receive requires 0 arguments".to_string());
-                }
+    let Some((index, received)) = outcome? else {
+        return Ok(Value::Boolean(false));
+    };
+    let mut result = IndexMap::new();
+    result.insert("Index".to_string(), Value::Integer((index + 1).into()));
+    result.insert("Channel".to_string(), channels[index].clone());
+    let (value, closed) = match received {
+        Received::Value(value) => (value, false),
+        _ => (Value::Boolean(false), true),
+    };
+    result.insert("Value".to_string(), value);
+    result.insert("Closed".to_string(), Value::Boolean(closed));
+    Ok(Value::Map(Arc::new(std::sync::RwLock::new(result))))
+}
 
-                let rx = rx_clone.clone();
-                // Use runtime to block on async receive
-                let result = runtime_clone.block_on(async {
-                    let mut rx_guard = rx.lock().await;
-                    rx_guard.recv().await
-                });
-
-                result.ok_or("Channel closed".to_string())
-            })));
-
-            // Create TryReceive function with timeout
-            let runtime_try = runtime.clone();
-            let rx_try = rx_shared.clone();
-            let try_receive_fn = Value::NativeFunction(Arc::new(Box::new(move |args| {
-                if args.len() != 1 {
-                    return Err("TryReceive requires 1 argument (timeout in seconds)".to_string());
-                }
+fn create_channel_object(shared: Arc<Shared>) -> Value {
+    let mut channel_map = IndexMap::new();
+    channel_map.insert("Id".to_string(), Value::Integer(register(&shared).into()));
+    channel_map.insert(
+        "Capacity".to_string(),
+        Value::Integer(shared.capacity.into()),
+    );
 
-                let timeout_secs = match &args[0] {
-                    Value::Number(n) => {
-                        use bigdecimal::ToPrimitive;
-                        n.to_f64().ok_or("Invalid timeout")?
-                    }
-                    Value::FastNumber(f) => *f,
-                    _ => return Err("Timeout must be a number".to_string()),
-                };
-
-                let rx = rx_try.clone();
-                let duration = std::time::Duration::from_secs_f64(timeout_secs);
-
-                // Use runtime to block on async receive with timeout
-                let result = runtime_try.block_on(async {
-                    let mut rx_guard = rx.lock().await;
-                    tokio::time::timeout(duration, rx_guard.recv()).await
-                });
-
-                match result {
-                    Ok(Some(value)) => {
-                        // Received a value - wrap in Some
-                        Ok(Value::Option(Box::new(Some(value))))
-                    }
-                    Ok(None) => {
-                        // Channel closed - return None
-                        Ok(Value::Option(Box::new(None)))
-                    }
-                    Err(_) => {
-                        // Timeout - return None
-                        Ok(Value::Option(Box::new(None)))
-                    }
-                }
-            })));
+    // Send waits while the channel is full
+    let shared_send = shared.clone();
+    channel_map.insert(
+        "Send".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Send requires 1 argument (value to send)".to_string());
+            }
+            shared_send.send(args[0].clone(), None).map(Value::Boolean)
+        }))),
+    );
 
-            // Return a Map with Send, Receive, and TryReceive methods
-            let mut channel_map = HashMap::new();
-            channel_map.insert("Send".to_string(), send_fn);
-            channel_map.insert("Receive".to_string(), receive_fn);
-            channel_map.insert("TryReceive".to_string(), try_receive_fn);
+    // TrySend gives False instead of waiting for room
+    let shared_try_send = shared.clone();
+    channel_map.insert(
+        "TrySend".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("TrySend requires 1 argument (value to send)".to_string());
+            }
+            shared_try_send
+                .send(args[0].clone(), Some(Duration::ZERO))
+                .map(Value::Boolean)
+        }))),
+    );
 
-            Ok(Value::Map(Arc::new(std::sync::RwLock::new(channel_map))))
+    let shared_receive = shared.clone();
+    channel_map.insert(
+        "Receive".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if !args.is_empty() {
+                return Err("Receive requires 0 arguments".to_string());
+            }
+            match shared_receive.receive(None)? {
+                Received::Value(value) => Ok(value),
+                _ => Err("Channel closed".to_string()),
+            }
         }))),
     );
 
-    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+    // TryReceive() takes a value if one is waiting; TryReceive(seconds)
+    // waits up to that long. Either gives an Option.
+    let shared_try = shared.clone();
+    channel_map.insert(
+        "TryReceive".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() > 1 {
+                return Err(
+                    "TryReceive requires 0-1 arguments (optional timeout in seconds)".to_string(),
+                );
+            }
+            let timeout = match args.first() {
+                Some(secs) => Duration::from_secs_f64(seconds(secs, "Timeout")?),
+                None => Duration::ZERO,
+            };
+            shared_try.receive(Some(timeout)).map(received_option)
+        }))),
+    );
+
+    // ReceiveTimeout(ms) - TryReceive with the wait in milliseconds
+    let shared_timeout = shared.clone();
+    channel_map.insert(
+        "ReceiveTimeout".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("ReceiveTimeout requires 1 argument (milliseconds)".to_string());
+            }
+            let ms = seconds(&args[0], "ReceiveTimeout's wait")?;
+            shared_timeout
+                .receive(Some(Duration::from_secs_f64(ms / 1000.0)))
+                .map(received_option)
+        }))),
+    );
+
+    let shared_close = shared.clone();
+    channel_map.insert(
+        "Close".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            Ok(Value::Boolean(shared_close.close()))
+        }))),
+    );
+
+    let shared_closed = shared.clone();
+    channel_map.insert(
+        "IsClosed".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            Ok(Value::Boolean(shared_closed.queue.lock_recover().closed))
+        }))),
+    );
+
+    let shared_length = shared.clone();
+    channel_map.insert(
+        "Length".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let length = shared_length.queue.lock_recover().values.len();
+            Ok(Value::Integer(length.into()))
+        }))),
+    );
+
+    // Next and HasMore let `For each Message in Ch:` take values until the
+    // channel is closed and empty
+    let shared_next = shared.clone();
+    channel_map.insert(
+        "Next".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            shared_next.receive(None).map(received_option)
+        }))),
+    );
+
+    let shared_more = shared;
+    channel_map.insert(
+        "HasMore".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let queue = shared_more.queue.lock_recover();
+            Ok(Value::Boolean(!queue.closed || !queue.values.is_empty()))
+        }))),
+    );
+
+    Value::Map(Arc::new(std::sync::RwLock::new(channel_map)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(object: &Value, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let Value::Map(map) = object else {
+            panic!("not an object");
+        };
+        let function = map.read().unwrap().get(name).cloned();
+        match function {
+            Some(Value::NativeFunction(f)) => f(args),
+            _ => panic!("no member {}", name),
+        }
+    }
+
+    fn create(capacity: i64) -> Value {
+        let module = create_channel_module();
+        call(&module, "Create", vec![Value::Integer(capacity.into())]).unwrap()
+    }
+
+    #[test]
+    fn test_backpressure_and_close() {
+        let channel = create(1);
+        let one = Value::Integer(1.into());
+        assert!(
+            call(&channel, "TrySend", vec![one.clone()])
+                .unwrap()
+                .is_truthy()
+        );
+        assert!(
+            !call(&channel, "TrySend", vec![one.clone()])
+                .unwrap()
+                .is_truthy()
+        );
+
+        // A blocked Send goes through once the value ahead of it is taken
+        let sender = channel.clone();
+        let pending = std::thread::spawn(move || {
+            call(&sender, "Send", vec![Value::Integer(2.into())]).unwrap();
+            call(&sender, "Close", vec![]).unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            call(&channel, "Receive", vec![])
+                .unwrap()
+                .to_display_string(),
+            "1"
+        );
+        pending.join().unwrap();
+
+        // Closed: no more sends, but what was queued drains
+        assert!(call(&channel, "Send", vec![one]).is_err());
+        assert!(call(&channel, "HasMore", vec![]).unwrap().is_truthy());
+        assert_eq!(
+            call(&channel, "Receive", vec![])
+                .unwrap()
+                .to_display_string(),
+            "2"
+        );
+        assert!(!call(&channel, "HasMore", vec![]).unwrap().is_truthy());
+        assert!(call(&channel, "Receive", vec![]).is_err());
+        assert!(matches!(
+            call(&channel, "Next", vec![]).unwrap(),
+            Value::Option(option) if option.is_none()
+        ));
+    }
+
+    #[test]
+    fn test_select() {
+        let module = create_channel_module();
+        let (first, second) = (create(4), create(4));
+        let both = Value::List(Arc::new(std::sync::RwLock::new(vec![
+            first.clone(),
+            second.clone(),
+        ])));
+
+        let timed_out = call(
+            &module,
+            "Select",
+            vec![both.clone(), Value::Integer(20.into())],
+        );
+        assert!(matches!(timed_out, Ok(Value::Boolean(false))));
+
+        let sender = second.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(30));
+            call(&sender, "Send", vec![Value::String("hi".to_string())]).unwrap();
+        });
+        let Value::Map(fired) = call(&module, "Select", vec![both.clone()]).unwrap() else {
+            panic!("Select gives a Map");
+        };
+        assert_eq!(fired.read().unwrap()["Index"].to_display_string(), "2");
+        assert_eq!(fired.read().unwrap()["Value"].to_display_string(), "hi");
+
+        call(&first, "Close", vec![]).unwrap();
+        let Value::Map(fired) = call(&module, "Select", vec![both]).unwrap() else {
+            panic!("Select gives a Map");
+        };
+        assert_eq!(fired.read().unwrap()["Index"].to_display_string(), "1");
+        assert!(fired.read().unwrap()["Closed"].is_truthy());
+    }
 }
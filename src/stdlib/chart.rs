@@ -0,0 +1,675 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::sync::{Arc, RwLock};
+
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_HEIGHT: u32 = 400;
+// Widths and heights in pixels; a PNG takes 3 bytes per pixel, so 4096 by
+// 4096 is at most 48 MiB
+const MIN_SIZE: u32 = 100;
+const MAX_SIZE: u32 = 4096;
+const DEFAULT_COLOR: &str = "#4e79a7";
+const MARGIN_LEFT: f64 = 56.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 36.0;
+const MARGIN_BOTTOM: f64 = 44.0;
+const Y_TICKS: usize = 5;
+
+type ChartData = (Vec<(f64, f64)>, Vec<String>);
+
+#[derive(Clone, Copy, PartialEq)]
+enum ChartKind {
+    Line,
+    Bar,
+    Scatter,
+}
+
+struct ChartSpec {
+    kind: ChartKind,
+    points: Vec<(f64, f64)>,
+    labels: Vec<String>,
+    title: String,
+    width: u32,
+    height: u32,
+    color: (u8, u8, u8),
+}
+
+enum Shape {
+    Line {
+        from: (f64, f64),
+        to: (f64, f64),
+        color: (u8, u8, u8),
+        width: f64,
+    },
+    Rect {
+        x: f64,
+        y: f64,
+        w: f64,
+        h: f64,
+        color: (u8, u8, u8),
+    },
+    Circle {
+        center: (f64, f64),
+        radius: f64,
+        color: (u8, u8, u8),
+    },
+    Text {
+        at: (f64, f64),
+        text: String,
+        anchor: &'static str,
+        size: u32,
+    },
+}
+
+pub fn create_chart_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    methods.insert("Line".to_string(), chart_constructor(ChartKind::Line));
+    methods.insert("Bar".to_string(), chart_constructor(ChartKind::Bar));
+    methods.insert("Scatter".to_string(), chart_constructor(ChartKind::Scatter));
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn chart_constructor(kind: ChartKind) -> Value {
+    let name = match kind {
+        ChartKind::Line => "Chart.Line",
+        ChartKind::Bar => "Chart.Bar",
+        ChartKind::Scatter => "Chart.Scatter",
+    };
+
+    Value::NativeFunction(Arc::new(Box::new(move |args| {
+        if args.is_empty() || args.len() > 2 {
+            return Err(format!(
+                "{} requires 1-2 arguments (data, optional options map)",
+                name
+            ));
+        }
+
+        let spec = Arc::new(build_spec(kind, &args[0], args.get(1))?);
+        Ok(create_chart_object(spec))
+    })))
+}
+
+fn create_chart_object(spec: Arc<ChartSpec>) -> Value {
+    let mut methods = IndexMap::new();
+
+    let spec_svg = spec.clone();
+    methods.insert(
+        "ToSvg".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if !args.is_empty() {
+                return Err("Chart.ToSvg takes no arguments".to_string());
+            }
+            Ok(Value::String(render_svg(&spec_svg)))
+        }))),
+    );
+
+    let spec_save_svg = spec.clone();
+    methods.insert(
+        "SaveSvg".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Chart.SaveSvg requires 1 argument (path)".to_string());
+            }
+            let path = args[0].to_display_string();
+            permissions::check_write(&path)?;
+            std::fs::write(&path, render_svg(&spec_save_svg))
+                .map_err(|e| format!("Failed to write chart {}: {}", path, e))?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    let spec_save_png = spec.clone();
+    methods.insert(
+        "SavePng".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Chart.SavePng requires 1 argument (path)".to_string());
+            }
+            let path = args[0].to_display_string();
+            let bytes = render_png(&spec_save_png)?;
+            permissions::check_write(&path)?;
+            std::fs::write(&path, bytes)
+                .map_err(|e| format!("Failed to write chart {}: {}", path, e))?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    let kind = match spec.kind {
+        ChartKind::Line => "Line",
+        ChartKind::Bar => "Bar",
+        ChartKind::Scatter => "Scatter",
+    };
+    methods.insert("Kind".to_string(), Value::String(kind.to_string()));
+    methods.insert("Title".to_string(), Value::String(spec.title.clone()));
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn build_spec(kind: ChartKind, data: &Value, options: Option<&Value>) -> Result<ChartSpec, String> {
+    let (points, labels) = extract_points(data)?;
+    if points.is_empty() {
+        return Err("Chart data must contain at least one value".to_string());
+    }
+
+    let mut spec = ChartSpec {
+        kind,
+        points,
+        labels,
+        title: String::new(),
+        width: DEFAULT_WIDTH,
+        height: DEFAULT_HEIGHT,
+        color: parse_color(DEFAULT_COLOR).unwrap_or((78, 121, 167)),
+    };
+
+    if let Some(options) = options {
+        let Value::Map(map) = options else {
+            return Err("Chart options must be a Map".to_string());
+        };
+        let map = map.read_recover();
+        if let Some(title) = map.get("Title") {
+            spec.title = title.to_display_string();
+        }
+        if let Some(width) = map.get("Width") {
+            spec.width = size(width, "Width")?;
+        }
+        if let Some(height) = map.get("Height") {
+            spec.height = size(height, "Height")?;
+        }
+        if let Some(color) = map.get("Color") {
+            let color = color.to_display_string();
+            spec.color =
+                parse_color(&color).ok_or_else(|| format!("Invalid chart color '{}'", color))?;
+        }
+    }
+
+    Ok(spec)
+}
+
+fn size(value: &Value, name: &str) -> Result<u32, String> {
+    value_to_f64(value)
+        .filter(|size| (MIN_SIZE as f64..=MAX_SIZE as f64).contains(size))
+        .map(|size| size as u32)
+        .ok_or_else(|| {
+            format!(
+                "Chart {} must be a number from {} to {}",
+                name, MIN_SIZE, MAX_SIZE
+            )
+        })
+}
+
+// Lists of numbers plot against 1..N, lists of [x, y] pairs plot as given,
+// and Maps plot one labelled category per key (sorted for stable output).
+fn extract_points(data: &Value) -> Result<ChartData, String> {
+    match data {
+        Value::List(list) => {
+            let list = list.read_recover();
+            let mut points = Vec::with_capacity(list.len());
+            for (i, item) in list.iter().enumerate() {
+                match item {
+                    Value::List(pair) => {
+                        let pair = pair.read_recover();
+                        if pair.len() != 2 {
+                            return Err("Chart point pairs must be [x, y]".to_string());
+                        }
+                        let x = value_to_f64(&pair[0]).ok_or("Chart x value must be a number")?;
+                        let y = value_to_f64(&pair[1]).ok_or("Chart y value must be a number")?;
+                        points.push((x, y));
+                    }
+                    other => {
+                        let y = value_to_f64(other).ok_or_else(|| {
+                            format!("Chart value {} is not a number", other.to_display_string())
+                        })?;
+                        points.push(((i + 1) as f64, y));
+                    }
+                }
+            }
+            Ok((points, Vec::new()))
+        }
+        Value::Vector(values) => Ok((
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| ((i + 1) as f64, *v))
+                .collect(),
+            Vec::new(),
+        )),
+        Value::Map(map) => {
+            let map = map.read_recover();
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            let mut points = Vec::with_capacity(entries.len());
+            let mut labels = Vec::with_capacity(entries.len());
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                let y = value_to_f64(value)
+                    .ok_or_else(|| format!("Chart value for '{}' is not a number", key))?;
+                points.push(((i + 1) as f64, y));
+                labels.push(key.clone());
+            }
+            Ok((points, labels))
+        }
+        _ => Err("Chart data must be a List or Map of numbers".to_string()),
+    }
+}
+
+fn value_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.trim().strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+    Some((r, g, b))
+}
+
+fn layout(spec: &ChartSpec) -> Vec<Shape> {
+    let width = spec.width as f64;
+    let height = spec.height as f64;
+    let plot_w = width - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_h = height - MARGIN_TOP - MARGIN_BOTTOM;
+    let axis_color = (60, 60, 60);
+    let grid_color = (225, 225, 225);
+
+    let (mut min_x, mut max_x) = (f64::INFINITY, f64::NEG_INFINITY);
+    let (mut min_y, mut max_y) = (f64::INFINITY, f64::NEG_INFINITY);
+    for (x, y) in &spec.points {
+        min_x = min_x.min(*x);
+        max_x = max_x.max(*x);
+        min_y = min_y.min(*y);
+        max_y = max_y.max(*y);
+    }
+    if spec.kind == ChartKind::Bar {
+        min_y = min_y.min(0.0);
+        max_y = max_y.max(0.0);
+        min_x -= 0.5;
+        max_x += 0.5;
+    } else {
+        let pad_x = (max_x - min_x) * 0.05;
+        let pad_y = (max_y - min_y) * 0.05;
+        min_x -= pad_x;
+        max_x += pad_x;
+        min_y -= pad_y;
+        max_y += pad_y;
+    }
+    if (max_x - min_x).abs() < f64::EPSILON {
+        min_x -= 1.0;
+        max_x += 1.0;
+    }
+    if (max_y - min_y).abs() < f64::EPSILON {
+        min_y -= 1.0;
+        max_y += 1.0;
+    }
+
+    let map_x = |x: f64| MARGIN_LEFT + (x - min_x) / (max_x - min_x) * plot_w;
+    let map_y = |y: f64| MARGIN_TOP + plot_h - (y - min_y) / (max_y - min_y) * plot_h;
+
+    let mut shapes = Vec::new();
+
+    for i in 0..=Y_TICKS {
+        let value = min_y + (max_y - min_y) * i as f64 / Y_TICKS as f64;
+        let y = map_y(value);
+        shapes.push(Shape::Line {
+            from: (MARGIN_LEFT, y),
+            to: (MARGIN_LEFT + plot_w, y),
+            color: grid_color,
+            width: 1.0,
+        });
+        shapes.push(Shape::Text {
+            at: (MARGIN_LEFT - 6.0, y + 4.0),
+            text: format_tick(value),
+            anchor: "end",
+            size: 11,
+        });
+    }
+
+    match spec.kind {
+        ChartKind::Line => {
+            for pair in spec.points.windows(2) {
+                shapes.push(Shape::Line {
+                    from: (map_x(pair[0].0), map_y(pair[0].1)),
+                    to: (map_x(pair[1].0), map_y(pair[1].1)),
+                    color: spec.color,
+                    width: 2.0,
+                });
+            }
+            for (x, y) in &spec.points {
+                shapes.push(Shape::Circle {
+                    center: (map_x(*x), map_y(*y)),
+                    radius: 2.5,
+                    color: spec.color,
+                });
+            }
+        }
+        ChartKind::Bar => {
+            let slot = plot_w / (max_x - min_x);
+            let bar_w = (slot * 0.7).max(1.0);
+            let zero = map_y(0.0);
+            for (x, y) in &spec.points {
+                let top = map_y(*y).min(zero);
+                let bar_h = (map_y(*y) - zero).abs();
+                shapes.push(Shape::Rect {
+                    x: map_x(*x) - bar_w / 2.0,
+                    y: top,
+                    w: bar_w,
+                    h: bar_h,
+                    color: spec.color,
+                });
+            }
+        }
+        ChartKind::Scatter => {
+            for (x, y) in &spec.points {
+                shapes.push(Shape::Circle {
+                    center: (map_x(*x), map_y(*y)),
+                    radius: 3.5,
+                    color: spec.color,
+                });
+            }
+        }
+    }
+
+    if spec.labels.is_empty() {
+        for i in 0..=Y_TICKS {
+            let value = min_x + (max_x - min_x) * i as f64 / Y_TICKS as f64;
+            shapes.push(Shape::Text {
+                at: (map_x(value), MARGIN_TOP + plot_h + 18.0),
+                text: format_tick(value),
+                anchor: "middle",
+                size: 11,
+            });
+        }
+    } else {
+        for ((x, _), label) in spec.points.iter().zip(&spec.labels) {
+            shapes.push(Shape::Text {
+                at: (map_x(*x), MARGIN_TOP + plot_h + 18.0),
+                text: label.clone(),
+                anchor: "middle",
+                size: 11,
+            });
+        }
+    }
+
+    shapes.push(Shape::Line {
+        from: (MARGIN_LEFT, MARGIN_TOP),
+        to: (MARGIN_LEFT, MARGIN_TOP + plot_h),
+        color: axis_color,
+        width: 1.0,
+    });
+    shapes.push(Shape::Line {
+        from: (MARGIN_LEFT, MARGIN_TOP + plot_h),
+        to: (MARGIN_LEFT + plot_w, MARGIN_TOP + plot_h),
+        color: axis_color,
+        width: 1.0,
+    });
+
+    if !spec.title.is_empty() {
+        shapes.push(Shape::Text {
+            at: (width / 2.0, MARGIN_TOP / 2.0 + 6.0),
+            text: spec.title.clone(),
+            anchor: "middle",
+            size: 16,
+        });
+    }
+
+    shapes
+}
+
+fn format_tick(value: f64) -> String {
+    if (value - value.round()).abs() < 1e-9 {
+        format!("{}", value.round() as i64)
+    } else {
+        let formatted = format!("{:.2}", value);
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    }
+}
+
+fn svg_color(color: (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_svg(spec: &ChartSpec) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>\n",
+        w = spec.width,
+        h = spec.height
+    );
+
+    for shape in layout(spec) {
+        let element = match shape {
+            Shape::Line {
+                from,
+                to,
+                color,
+                width,
+            } => format!(
+                "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                from.0,
+                from.1,
+                to.0,
+                to.1,
+                svg_color(color),
+                width
+            ),
+            Shape::Rect { x, y, w, h, color } => format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+                x,
+                y,
+                w,
+                h,
+                svg_color(color)
+            ),
+            Shape::Circle {
+                center,
+                radius,
+                color,
+            } => format!(
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" fill=\"{}\"/>",
+                center.0,
+                center.1,
+                radius,
+                svg_color(color)
+            ),
+            Shape::Text {
+                at,
+                text,
+                anchor,
+                size,
+            } => format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\" font-family=\"sans-serif\" font-size=\"{}\" fill=\"#333333\">{}</text>",
+                at.0,
+                at.1,
+                anchor,
+                size,
+                escape_xml(&text)
+            ),
+        };
+        svg.push_str(&element);
+        svg.push('\n');
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// PNG output rasterizes the same shapes; text is skipped since no font is bundled.
+fn render_png(spec: &ChartSpec) -> Result<Vec<u8>, String> {
+    let width = spec.width as usize;
+    let height = spec.height as usize;
+    let bytes = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .filter(|_| spec.width <= MAX_SIZE && spec.height <= MAX_SIZE)
+        .ok_or_else(|| format!("Chart is too large: {}x{}", spec.width, spec.height))?;
+    let mut pixels = vec![255u8; bytes];
+
+    let mut put = |x: i64, y: i64, color: (u8, u8, u8)| {
+        if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            let offset = (y as usize * width + x as usize) * 3;
+            pixels[offset] = color.0;
+            pixels[offset + 1] = color.1;
+            pixels[offset + 2] = color.2;
+        }
+    };
+
+    for shape in layout(spec) {
+        match shape {
+            Shape::Line {
+                from,
+                to,
+                color,
+                width: stroke,
+            } => {
+                let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil() as i64;
+                let half = (stroke / 2.0).floor() as i64;
+                for step in 0..=steps.max(1) {
+                    let t = step as f64 / steps.max(1) as f64;
+                    let x = (from.0 + (to.0 - from.0) * t).round() as i64;
+                    let y = (from.1 + (to.1 - from.1) * t).round() as i64;
+                    for dx in -half..=half {
+                        for dy in -half..=half {
+                            put(x + dx, y + dy, color);
+                        }
+                    }
+                }
+            }
+            Shape::Rect { x, y, w, h, color } => {
+                let (x0, y0) = (x.round() as i64, y.round() as i64);
+                let (x1, y1) = ((x + w).round() as i64, (y + h).round() as i64);
+                for py in y0..y1.max(y0 + 1) {
+                    for px in x0..x1 {
+                        put(px, py, color);
+                    }
+                }
+            }
+            Shape::Circle {
+                center,
+                radius,
+                color,
+            } => {
+                let r = radius.ceil() as i64;
+                let (cx, cy) = (center.0.round() as i64, center.1.round() as i64);
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if ((dx * dx + dy * dy) as f64) <= radius * radius {
+                            put(cx + dx, cy + dy, color);
+                        }
+                    }
+                }
+            }
+            Shape::Text { .. } => {}
+        }
+    }
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, spec.width, spec.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| format!("PNG encode error: {}", e))?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|e| format!("PNG encode error: {}", e))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(entries: &[(&str, Value)]) -> Value {
+        Value::Map(Arc::new(RwLock::new(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )))
+    }
+
+    fn data() -> Value {
+        Value::Vector(Arc::from([1.0, 3.0, 2.0]))
+    }
+
+    #[test]
+    fn test_build_spec() {
+        let spec = build_spec(ChartKind::Line, &data(), None).unwrap();
+        assert_eq!((spec.width, spec.height), (DEFAULT_WIDTH, DEFAULT_HEIGHT));
+        assert_eq!(spec.points, [(1.0, 1.0), (2.0, 3.0), (3.0, 2.0)]);
+
+        let sized = options(&[
+            ("Title", Value::String("Sales".to_string())),
+            ("Width", Value::Integer(4096.into())),
+            ("Height", Value::FastNumber(100.0)),
+            ("Color", Value::String("#ff0000".to_string())),
+        ]);
+        let spec = build_spec(ChartKind::Bar, &data(), Some(&sized)).unwrap();
+        assert_eq!((spec.width, spec.height), (4096, 100));
+        assert_eq!((spec.title.as_str(), spec.color), ("Sales", (255, 0, 0)));
+
+        for (key, size) in [("Width", 99.0), ("Height", 4097.0), ("Width", 1e12)] {
+            let err = build_spec(
+                ChartKind::Line,
+                &data(),
+                Some(&options(&[(key, Value::FastNumber(size))])),
+            )
+            .err()
+            .unwrap();
+            assert_eq!(
+                err,
+                format!("Chart {} must be a number from 100 to 4096", key)
+            );
+        }
+        assert!(build_spec(ChartKind::Line, &Value::Vector(Arc::from([])), None).is_err());
+    }
+
+    #[test]
+    fn test_render_png() {
+        let sized = options(&[
+            ("Width", Value::Integer(200.into())),
+            ("Height", Value::Integer(120.into())),
+        ]);
+        let spec = build_spec(ChartKind::Scatter, &data(), Some(&sized)).unwrap();
+        let png = render_png(&spec).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        // IHDR: width then height, big-endian
+        assert_eq!(&png[16..24], [0, 0, 0, 200, 0, 0, 0, 120]);
+
+        let huge = ChartSpec {
+            width: u32::MAX,
+            height: u32::MAX,
+            ..spec
+        };
+        assert!(
+            render_png(&huge)
+                .unwrap_err()
+                .starts_with("Chart is too large")
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::num_bigint::BigInt;
+use indexmap::IndexMap;
+use ring::digest;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+// Checksums of Bytes, Strings (as UTF-8) and files. CRC-32 and Adler-32 are
+// the ones protocols and archive formats carry, and come back as Integers;
+// SHA-256 and SHA-512 are for verifying downloads and build artifacts, and
+// come back as lowercase hex. Files are read in chunks, so any size works.
+
+/// Algorithms `Checksum.File` and `Checksum.Verify` accept
+const ALGORITHMS: &str = "crc32, adler32, sha256, sha512";
+
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Adler32(adler2::Adler32),
+    Digest(Box<digest::Context>),
+}
+
+impl Hasher {
+    fn new(algorithm: &str) -> Result<Self, String> {
+        match algorithm.to_ascii_lowercase().replace('-', "").as_str() {
+            "crc32" => Ok(Hasher::Crc32(crc32fast::Hasher::new())),
+            "adler32" => Ok(Hasher::Adler32(adler2::Adler32::new())),
+            "sha256" => Ok(Hasher::Digest(Box::new(digest::Context::new(
+                &digest::SHA256,
+            )))),
+            "sha512" => Ok(Hasher::Digest(Box::new(digest::Context::new(
+                &digest::SHA512,
+            )))),
+            _ => Err(format!(
+                "Unknown checksum '{}', expected one of: {}",
+                algorithm, ALGORITHMS
+            )),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Adler32(hasher) => hasher.write_slice(data),
+            Hasher::Digest(context) => context.update(data),
+        }
+    }
+
+    /// The checksum in lowercase hex; 8 digits for the 32-bit ones.
+    fn finish_hex(self) -> String {
+        match self {
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+            Hasher::Adler32(hasher) => format!("{:08x}", hasher.checksum()),
+            Hasher::Digest(context) => to_hex(context.finish().as_ref()),
+        }
+    }
+}
+
+pub fn create_checksum_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Checksum.Crc32(data) -> Integer
+    methods.insert(
+        "Crc32".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Checksum.Crc32 requires 1 argument (Bytes or text)".to_string());
+            }
+            let crc = crc32fast::hash(&data_bytes(&args[0], "Crc32")?);
+            Ok(Value::Integer(BigInt::from(crc)))
+        }))),
+    );
+
+    // Checksum.Adler32(data) -> Integer
+    methods.insert(
+        "Adler32".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Checksum.Adler32 requires 1 argument (Bytes or text)".to_string());
+            }
+            let sum = adler2::adler32_slice(&data_bytes(&args[0], "Adler32")?);
+            Ok(Value::Integer(BigInt::from(sum)))
+        }))),
+    );
+
+    // Checksum.Sha256(data), Checksum.Sha512(data) -> hex
+    for (name, algorithm) in [("Sha256", &digest::SHA256), ("Sha512", &digest::SHA512)] {
+        methods.insert(
+            name.to_string(),
+            Value::NativeFunction(Arc::new(Box::new(move |args| {
+                if args.len() != 1 {
+                    return Err(format!(
+                        "Checksum.{} requires 1 argument (Bytes or text)",
+                        name
+                    ));
+                }
+                let data = data_bytes(&args[0], name)?;
+                Ok(Value::String(to_hex(
+                    digest::digest(algorithm, &data).as_ref(),
+                )))
+            }))),
+        );
+    }
+
+    // Checksum.File(path, algorithm?) -> hex, SHA-256 unless named
+    methods.insert(
+        "File".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Checksum.File requires 1 or 2 arguments (path, optional algorithm)"
+                        .to_string(),
+                );
+            }
+            let algorithm = args.get(1).map(|a| a.to_display_string());
+            file_checksum(
+                &args[0].to_display_string(),
+                algorithm.as_deref().unwrap_or("sha256"),
+            )
+            .map(Value::String)
+        }))),
+    );
+
+    // Checksum.Verify(path, expected, algorithm?) -> whether the file's
+    // checksum is `expected`, ignoring case and surrounding spaces
+    methods.insert(
+        "Verify".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "Checksum.Verify requires 2 or 3 arguments (path, expected, optional algorithm)"
+                        .to_string(),
+                );
+            }
+            let algorithm = args.get(2).map(|a| a.to_display_string());
+            let actual = file_checksum(
+                &args[0].to_display_string(),
+                algorithm.as_deref().unwrap_or("sha256"),
+            )?;
+            let expected = args[1].to_display_string();
+            Ok(Value::Boolean(actual.eq_ignore_ascii_case(expected.trim())))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn data_bytes(value: &Value, function: &str) -> Result<Vec<u8>, String> {
+    match value {
+        Value::Bytes(bytes) => Ok(bytes.to_vec()),
+        Value::String(text) => Ok(text.as_bytes().to_vec()),
+        other => Err(format!(
+            "Checksum.{} expects Bytes or text, got {}",
+            function,
+            other.type_name()
+        )),
+    }
+}
+
+fn file_checksum(path: &str, algorithm: &str) -> Result<String, String> {
+    let mut hasher = Hasher::new(algorithm)?;
+    permissions::check_read(path)?;
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        if read == 0 {
+            return Ok(hasher.finish_hex());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_checksums() {
+        let data = b"123456789";
+        assert_eq!(crc32fast::hash(data), 0xcbf43926);
+        assert_eq!(adler2::adler32_slice(b"Wikipedia"), 0x11e60398);
+
+        let path = std::env::temp_dir().join(format!("sfex-checksum-{}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let path = path.to_string_lossy().to_string();
+        assert_eq!(file_checksum(&path, "CRC-32").unwrap(), "cbf43926");
+        assert_eq!(
+            file_checksum(&path, "sha256").unwrap(),
+            "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225"
+        );
+        assert!(file_checksum(&path, "md5").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,430 @@
+// Config.Load(schema) gathers an app's settings in one place. Each key takes
+// the first value it finds in the process environment, then .env, then the
+// [config] section of sfex.toml, then the schema's default, converted to the
+// type the schema gives. What it loads stays for the whole process, so web
+// handlers, which run in interpreters of their own, read it with Config.Get.
+
+use super::env::parse_dotenv;
+use super::toml::convert_toml_to_object;
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+static LOADED: RwLock<Option<IndexMap<String, Value>>> = RwLock::new(None);
+
+/// The types a schema entry may name.
+const TYPES: &[&str] = &["String", "Integer", "Number", "Boolean", "List"];
+
+/// Where a key's value came from, for error messages.
+enum Source {
+    Environment(String),
+    Dotenv(String),
+    Manifest,
+    Default,
+}
+
+impl Source {
+    fn describe(&self) -> String {
+        match self {
+            Source::Environment(name) => format!("environment variable {}", name),
+            Source::Dotenv(name) => format!("{} in .env", name),
+            Source::Manifest => "[config] in sfex.toml".to_string(),
+            Source::Default => "the default".to_string(),
+        }
+    }
+}
+
+/// One key of a schema: `Port: 8000`, or a Map of `Type`, `Default`,
+/// `Required` and `Env`.
+struct Entry {
+    key: String,
+    kind: String,
+    default: Option<Value>,
+    required: bool,
+    env: String,
+}
+
+/// `DatabaseUrl` -> `DATABASE_URL`, `HTTPPort` -> `HTTP_PORT`
+fn env_name(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let mut name = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        let boundary = i > 0
+            && c.is_uppercase()
+            && (chars[i - 1].is_lowercase()
+                || chars[i - 1].is_ascii_digit()
+                || (chars[i - 1].is_uppercase()
+                    && chars.get(i + 1).is_some_and(|next| next.is_lowercase())));
+        if boundary && !name.ends_with('_') {
+            name.push('_');
+        }
+        name.extend(c.to_uppercase());
+    }
+    name
+}
+
+fn kind_of(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::String(_) => Some("String"),
+        Value::Integer(_) => Some("Integer"),
+        Value::Number(n) if n.is_integer() => Some("Integer"),
+        Value::Number(_) | Value::FastNumber(_) => Some("Number"),
+        Value::Boolean(_) => Some("Boolean"),
+        Value::List(_) => Some("List"),
+        _ => None,
+    }
+}
+
+fn parse_schema(schema: &Value) -> Result<Vec<Entry>, String> {
+    let Value::Map(schema) = schema else {
+        return Err("Config.Load requires a Map of settings".to_string());
+    };
+    let mut entries = Vec::new();
+    for (key, spec) in schema.read_recover().iter() {
+        let entry = match spec {
+            Value::Map(options) => {
+                let options = options.read_recover();
+                for option in options.keys() {
+                    if !["Type", "Default", "Required", "Env"].contains(&option.as_str()) {
+                        return Err(format!(
+                            "{}: unknown option {} (expected Type, Default, Required or Env)",
+                            key, option
+                        ));
+                    }
+                }
+                let default = options.get("Default").cloned();
+                let kind = match (options.get("Type"), &default) {
+                    (Some(kind), _) => kind.to_display_string(),
+                    (None, Some(default)) => kind_of(default).unwrap_or("String").to_string(),
+                    (None, None) => "String".to_string(),
+                };
+                Entry {
+                    key: key.clone(),
+                    kind,
+                    default,
+                    required: options.get("Required").is_some_and(Value::is_truthy),
+                    env: options
+                        .get("Env")
+                        .map_or_else(|| env_name(key), Value::to_display_string),
+                }
+            }
+            default => Entry {
+                key: key.clone(),
+                kind: kind_of(default)
+                    .ok_or_else(|| {
+                        format!(
+                            "{}: a default must be a String, Integer, Number, Boolean or List",
+                            key
+                        )
+                    })?
+                    .to_string(),
+                default: Some(default.clone()),
+                required: false,
+                env: env_name(key),
+            },
+        };
+        if !TYPES.contains(&entry.kind.as_str()) {
+            return Err(format!(
+                "{}: unknown type {} (expected one of {})",
+                key,
+                entry.kind,
+                TYPES.join(", ")
+            ));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Text from the environment or .env as a value of type `kind`.
+fn from_text(text: &str, kind: &str) -> Option<Value> {
+    let text = text.trim();
+    match kind {
+        "String" => Some(Value::String(text.to_string())),
+        "Integer" => BigInt::from_str(text).ok().map(Value::Integer),
+        "Number" => BigDecimal::from_str(text).ok().map(Value::Number),
+        "Boolean" => match text.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(Value::Boolean(true)),
+            "false" | "no" | "off" | "0" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+        "List" => Some(Value::List(Arc::new(RwLock::new(
+            text.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        )))),
+        _ => None,
+    }
+}
+
+/// A default or sfex.toml value as type `kind`; text is read as it would be
+/// from the environment.
+fn convert(value: &Value, kind: &str) -> Option<Value> {
+    match (value, kind) {
+        (Value::String(text), _) => from_text(text, kind),
+        (Value::Integer(_), "Integer") | (Value::Boolean(_), "Boolean") => Some(value.clone()),
+        (Value::Number(n), "Integer") if n.is_integer() => {
+            BigInt::from_str(&n.with_scale(0).to_string())
+                .ok()
+                .map(Value::Integer)
+        }
+        (Value::Integer(i), "Number") => Some(Value::Number(BigDecimal::from(i.clone()))),
+        (Value::Number(_), "Number") => Some(value.clone()),
+        (Value::FastNumber(f), "Number") => BigDecimal::try_from(*f).ok().map(Value::Number),
+        (Value::List(items), "List") => Some(Value::List(Arc::new(RwLock::new(
+            items.read_recover().clone(),
+        )))),
+        _ => None,
+    }
+}
+
+/// The settings of `schema` from the given sources, or every problem found.
+fn resolve(
+    schema: &Value,
+    manifest: &toml::Table,
+    dotenv: &HashMap<String, String>,
+    environment: &dyn Fn(&str) -> Option<String>,
+) -> Result<IndexMap<String, Value>, String> {
+    let mut settings = IndexMap::new();
+    let mut problems = Vec::new();
+    for entry in parse_schema(schema)? {
+        let found = if let Some(text) = environment(&entry.env) {
+            Some((Value::String(text), Source::Environment(entry.env.clone())))
+        } else if let Some(text) = dotenv.get(&entry.env) {
+            Some((
+                Value::String(text.clone()),
+                Source::Dotenv(entry.env.clone()),
+            ))
+        } else if let Some(value) = manifest.get(&entry.key) {
+            Some((convert_toml_to_object(value.clone()), Source::Manifest))
+        } else {
+            entry.default.clone().map(|value| (value, Source::Default))
+        };
+        match found {
+            Some((value, source)) => match convert(&value, &entry.kind) {
+                Some(value) => {
+                    settings.insert(entry.key, value);
+                }
+                None => problems.push(format!(
+                    "{} must be {} {}, but {} is {}",
+                    entry.key,
+                    if entry.kind == "Integer" { "an" } else { "a" },
+                    entry.kind,
+                    source.describe(),
+                    value.to_display_string()
+                )),
+            },
+            None if entry.required => problems.push(format!(
+                "{} is required: set {} or {} in [config]",
+                entry.key, entry.env, entry.key
+            )),
+            None => {}
+        }
+    }
+    if problems.is_empty() {
+        Ok(settings)
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+/// The [config] section of the project around the current directory, and
+/// its .env (the one in the current directory outside a project).
+fn project_sources() -> Result<(toml::Table, HashMap<String, String>), String> {
+    let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+    let root = crate::project::find_project_root(&cwd);
+    let manifest = match &root {
+        Some(root) => crate::project::load_manifest(root)?
+            .config
+            .unwrap_or_default(),
+        None => toml::Table::new(),
+    };
+    let dotenv_path = root.unwrap_or(cwd).join(".env");
+    // A script that may not read .env, or a variable, gets the next source
+    let text = permissions::check_read(&dotenv_path)
+        .ok()
+        .and_then(|()| std::fs::read_to_string(&dotenv_path).ok());
+    let dotenv = match text {
+        Some(text) => parse_dotenv(&text)
+            .map_err(|e| format!(".env {}", e))?
+            .into_iter()
+            .collect(),
+        None => HashMap::new(),
+    };
+    Ok((manifest, dotenv))
+}
+
+/// A copy of a loaded setting, so one interpreter changing a List doesn't
+/// change it for the others.
+fn copied(value: &Value) -> Value {
+    match value {
+        Value::List(items) => Value::List(Arc::new(RwLock::new(items.read_recover().clone()))),
+        other => other.clone(),
+    }
+}
+
+fn settings_map(settings: &IndexMap<String, Value>) -> Value {
+    Value::Map(Arc::new(RwLock::new(
+        settings
+            .iter()
+            .map(|(key, value)| (key.clone(), copied(value)))
+            .collect(),
+    )))
+}
+
+pub fn create_config_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Config.Load({ Port: 8000, DatabaseUrl: { Type: "String", Required: True } })
+    methods.insert(
+        "Load".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Config.Load requires 1 argument (schema)".to_string());
+            }
+            let (manifest, dotenv) =
+                project_sources().map_err(|e| format!("Config.Load: {}", e))?;
+            let settings = resolve(&args[0], &manifest, &dotenv, &|name| {
+                std::env::var(name)
+                    .ok()
+                    .filter(|_| permissions::allows_env(name))
+            })
+            .map_err(|e| format!("Config.Load: {}", e))?;
+            let loaded = settings_map(&settings);
+            *LOADED.write_recover() = Some(settings);
+            Ok(loaded)
+        }))),
+    );
+
+    // Config.Get(key, default?): a setting Config.Load found, anywhere in the
+    // process
+    methods.insert(
+        "Get".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Config.Get requires 1 or 2 arguments (key, optional default)".to_string(),
+                );
+            }
+            let key = args[0].to_display_string();
+            let loaded = LOADED.read_recover();
+            match (
+                loaded.as_ref().and_then(|settings| settings.get(&key)),
+                args.get(1),
+            ) {
+                (Some(value), _) => Ok(copied(value)),
+                (None, Some(default)) => Ok(default.clone()),
+                (None, None) if loaded.is_none() => {
+                    Err(format!("Config.Get({}): Config.Load hasn't run", key))
+                }
+                (None, None) => Err(format!("Config.Get({}): no such setting", key)),
+            }
+        }))),
+    );
+
+    methods.insert(
+        "All".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if !args.is_empty() {
+                return Err("Config.All requires no arguments".to_string());
+            }
+            Ok(settings_map(
+                LOADED.read_recover().as_ref().unwrap_or(&IndexMap::new()),
+            ))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(Arc::new(RwLock::new(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )))
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(env_name("DatabaseUrl"), "DATABASE_URL");
+        assert_eq!(env_name("HTTPPort"), "HTTP_PORT");
+        assert_eq!(env_name("port"), "PORT");
+        assert_eq!(env_name("Api_Key"), "API_KEY");
+
+        let schema = map(vec![
+            ("Port", Value::Integer(8000.into())),
+            ("Debug", Value::Boolean(false)),
+            ("Name", Value::String("shop".to_string())),
+            (
+                "Rate",
+                map(vec![("Type", Value::String("Number".to_string()))]),
+            ),
+            (
+                "Hosts",
+                map(vec![
+                    ("Type", Value::String("List".to_string())),
+                    ("Env", Value::String("ALLOWED_HOSTS".to_string())),
+                ]),
+            ),
+            (
+                "Retries",
+                map(vec![("Type", Value::String("Integer".to_string()))]),
+            ),
+        ]);
+        let manifest: toml::Table =
+            toml::from_str("Port = 9000\nName = \"from toml\"\nRate = 1.5\n").unwrap();
+        let dotenv = HashMap::from([
+            ("PORT".to_string(), "7000".to_string()),
+            ("ALLOWED_HOSTS".to_string(), "a.com, b.com,".to_string()),
+        ]);
+        let environment = |name: &str| (name == "DEBUG").then(|| "yes".to_string());
+        let settings = resolve(&schema, &manifest, &dotenv, &environment).unwrap();
+        let shown: Vec<(&str, String)> = settings
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.to_display_string()))
+            .collect();
+        assert_eq!(
+            shown,
+            vec![
+                ("Port", "7000".to_string()),
+                ("Debug", "True".to_string()),
+                ("Name", "from toml".to_string()),
+                ("Rate", "1.5".to_string()),
+                ("Hosts", "[a.com, b.com]".to_string()),
+            ]
+        );
+        assert!(matches!(settings["Port"], Value::Integer(_)));
+        assert!(matches!(settings["Rate"], Value::Number(_)));
+
+        let schema = map(vec![
+            ("Port", Value::Integer(8000.into())),
+            ("Secret", map(vec![("Required", Value::Boolean(true))])),
+        ]);
+        let environment = |name: &str| (name == "PORT").then(|| "eighty".to_string());
+        let e = resolve(&schema, &toml::Table::new(), &HashMap::new(), &environment).unwrap_err();
+        assert_eq!(
+            e,
+            "Port must be an Integer, but environment variable PORT is eighty; Secret is required: set SECRET or Secret in [config]"
+        );
+
+        let schema = map(vec![(
+            "Port",
+            map(vec![("Type", Value::String("Int".to_string()))]),
+        )]);
+        assert!(resolve(&schema, &toml::Table::new(), &HashMap::new(), &|_| None).is_err());
+    }
+}
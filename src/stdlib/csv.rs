@@ -1,57 +1,488 @@
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
-use std::io::Cursor;
-use std::sync::Arc;
+use indexmap::IndexMap;
+use std::fs::File;
+use std::io::{BufWriter, Cursor};
+use std::sync::{Arc, Mutex};
 
-pub fn parse_csv(csv_data: &str) -> Result<Value, String> {
-    let cursor = Cursor::new(csv_data);
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(cursor);
+/// How `CSV.Parse` and `CSV.OpenReader` read a table.
+struct ReadOptions {
+    delimiter: u8,
+    quote: u8,
+    headers: Headers,
+    // Columns given a type with Types; the rest are inferred or kept as text
+    types: IndexMap<String, ColumnType>,
+    infer: bool,
+}
 
-    let mut list_of_rows = Vec::new();
+enum Headers {
+    // Taken from the first row
+    FirstRow,
+    // Given, for a file without a header row
+    Named(Vec<String>),
+    // Rows are Lists instead of Maps
+    None,
+}
+
+#[derive(Clone, Copy)]
+enum ColumnType {
+    Text,
+    Number,
+    Integer,
+    Boolean,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            headers: Headers::FirstRow,
+            types: IndexMap::new(),
+            infer: true,
+        }
+    }
+}
+
+impl ColumnType {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "text" | "string" => Ok(ColumnType::Text),
+            "number" => Ok(ColumnType::Number),
+            "integer" => Ok(ColumnType::Integer),
+            "boolean" => Ok(ColumnType::Boolean),
+            _ => Err(format!(
+                "Unknown CSV column type '{}' (expected Text, Number, Integer or Boolean)",
+                name
+            )),
+        }
+    }
+
+    fn convert(self, field: &str) -> Option<Value> {
+        match self {
+            ColumnType::Text => Some(Value::String(field.to_string())),
+            ColumnType::Number => Value::from_number_string(field.trim()).ok(),
+            ColumnType::Integer => Value::from_integer_string(field.trim()).ok(),
+            ColumnType::Boolean => match field.trim().to_lowercase().as_str() {
+                "true" | "yes" | "1" => Some(Value::Boolean(true)),
+                "false" | "no" | "0" | "" => Some(Value::Boolean(false)),
+                _ => None,
+            },
+        }
+    }
 
-    // Get headers
-    let headers = match rdr.headers() {
-        Ok(h) => h.clone(),
-        Err(e) => {
-            return Err(format!("CSV Header Error: {}", e));
+    fn name(self) -> &'static str {
+        match self {
+            ColumnType::Text => "Text",
+            ColumnType::Number => "Number",
+            ColumnType::Integer => "Integer",
+            ColumnType::Boolean => "Boolean",
         }
+    }
+}
+
+/// A single byte, for Delimiter and Quote
+fn single_byte(key: &str, value: &Value) -> Result<u8, String> {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        other => other.to_display_string(),
     };
+    match text.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(format!(
+            "CSV {} must be a single character, not '{}'",
+            key, text
+        )),
+    }
+}
 
-    for result in rdr.records() {
-        let record = result.map_err(|e| format!("CSV Record Error: {}", e))?;
-        let mut row_map = HashMap::new();
-
-        for (i, field) in record.iter().enumerate() {
-            if let Some(header_name) = headers.get(i) {
-                // Try to parse as number if possible, else string
-                let val = if let Ok(num) = Value::from_number_string(field) {
-                    num
-                } else {
-                    Value::String(field.to_string())
+fn read_options(value: Option<&Value>) -> Result<ReadOptions, String> {
+    let mut options = ReadOptions::default();
+    let Some(value) = value else {
+        return Ok(options);
+    };
+    let Value::Map(map) = value else {
+        return Err(format!(
+            "CSV options must be a Map, not {}",
+            value.type_name()
+        ));
+    };
+    for (key, value) in map.read_recover().iter() {
+        match key.as_str() {
+            "Delimiter" => options.delimiter = single_byte(key, value)?,
+            "Quote" => options.quote = single_byte(key, value)?,
+            "Headers" => {
+                options.headers = match value {
+                    Value::List(names) => Headers::Named(
+                        names
+                            .read_recover()
+                            .iter()
+                            .map(Value::to_display_string)
+                            .collect(),
+                    ),
+                    other if other.is_truthy() => Headers::FirstRow,
+                    _ => Headers::None,
+                }
+            }
+            "Types" => {
+                let Value::Map(types) = value else {
+                    return Err("CSV Types must be a Map of column names to types".to_string());
                 };
-                row_map.insert(header_name.to_string(), val);
+                for (column, kind) in types.read_recover().iter() {
+                    options.types.insert(
+                        column.clone(),
+                        ColumnType::parse(&kind.to_display_string())?,
+                    );
+                }
             }
+            "Infer" => options.infer = value.is_truthy(),
+            _ => return Err(format!("CSV has no read option {}", key)),
+        }
+    }
+    Ok(options)
+}
+
+/// Reads rows from `source` the way `options` says, without the rows
+/// themselves: those are taken one at a time with `next_row`.
+struct RowReader<R: std::io::Read> {
+    reader: csv::Reader<R>,
+    // Column names; empty when rows are Lists
+    names: Vec<String>,
+    // Per column, by position
+    types: Vec<Option<ColumnType>>,
+    infer: bool,
+    record: csv::StringRecord,
+}
+
+impl<R: std::io::Read> RowReader<R> {
+    fn new(source: R, options: ReadOptions) -> Result<Self, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .quote(options.quote)
+            .has_headers(matches!(options.headers, Headers::FirstRow))
+            .flexible(true)
+            .from_reader(source);
+        let names = match options.headers {
+            Headers::FirstRow => reader
+                .headers()
+                .map_err(|e| format!("CSV Header Error: {}", e))?
+                .iter()
+                .map(str::to_string)
+                .collect(),
+            Headers::Named(names) => names,
+            Headers::None => Vec::new(),
+        };
+        let types = if names.is_empty() {
+            Vec::new()
+        } else {
+            names
+                .iter()
+                .map(|name| options.types.get(name).copied())
+                .collect()
+        };
+        if let Some(unknown) = options
+            .types
+            .keys()
+            .find(|column| !names.is_empty() && !names.contains(column))
+        {
+            return Err(format!(
+                "CSV Types names a column '{}' that isn't in the headers",
+                unknown
+            ));
+        }
+        Ok(Self {
+            reader,
+            names,
+            types,
+            infer: options.infer,
+            record: csv::StringRecord::new(),
+        })
+    }
+
+    fn next_row(&mut self) -> Result<Option<Value>, String> {
+        let more = self
+            .reader
+            .read_record(&mut self.record)
+            .map_err(|e| format!("CSV Record Error: {}", e))?;
+        if !more {
+            return Ok(None);
+        }
+        let line = self.record.position().map_or(0, |p| p.line());
+
+        let mut fields = Vec::with_capacity(self.record.len());
+        for (i, field) in self.record.iter().enumerate() {
+            let value = match self.types.get(i).copied().flatten() {
+                Some(kind) => kind.convert(field).ok_or_else(|| {
+                    format!(
+                        "CSV line {}, column {}: '{}' is not {} {}",
+                        line,
+                        self.names[i],
+                        field,
+                        if matches!(kind, ColumnType::Integer) {
+                            "an"
+                        } else {
+                            "a"
+                        },
+                        kind.name()
+                    )
+                })?,
+                None => self.infer_value(field),
+            };
+            fields.push(value);
+        }
+
+        if self.names.is_empty() {
+            return Ok(Some(Value::List(Arc::new(std::sync::RwLock::new(fields)))));
+        }
+        let mut row_map = IndexMap::new();
+        // Extra fields past the last header are dropped, missing ones are ""
+        let mut fields = fields.into_iter();
+        for name in &self.names {
+            let value = fields
+                .next()
+                .unwrap_or_else(|| Value::String(String::new()));
+            row_map.insert(name.clone(), value);
+        }
+        Ok(Some(Value::Map(Arc::new(std::sync::RwLock::new(row_map)))))
+    }
+
+    // Try to parse as number if possible, else string
+    fn infer_value(&self, field: &str) -> Value {
+        if self.infer
+            && let Ok(num) = Value::from_number_string(field)
+        {
+            return num;
         }
-        list_of_rows.push(Value::Map(Arc::new(std::sync::RwLock::new(row_map))));
+        Value::String(field.to_string())
     }
+}
 
+pub fn parse_csv(csv_data: &str) -> Result<Value, String> {
+    parse_with(csv_data, ReadOptions::default())
+}
+
+fn parse_with(csv_data: &str, options: ReadOptions) -> Result<Value, String> {
+    let mut reader = RowReader::new(Cursor::new(csv_data), options)?;
+    let mut list_of_rows = Vec::new();
+    while let Some(row) = reader.next_row()? {
+        list_of_rows.push(row);
+    }
     Ok(Value::List(Arc::new(std::sync::RwLock::new(list_of_rows))))
 }
 
+/// A CSV field for `value`
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Option(inner) => inner.as_ref().as_ref().map(field_text).unwrap_or_default(),
+        other => other.to_display_string(),
+    }
+}
+
+/// The open file behind a `CSV.Writer`, and the columns of its rows.
+struct RowWriter {
+    writer: Option<csv::Writer<BufWriter<File>>>,
+    // Set by the Headers option or from the keys of the first Map row
+    columns: Option<Vec<String>>,
+    // Whether the header row still has to be written
+    needs_header: bool,
+}
+
+impl RowWriter {
+    fn write(&mut self, row: &Value) -> Result<(), String> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Err("CSV.Writer is closed".to_string());
+        };
+        let fields: Vec<String> = match row {
+            Value::Map(map) => {
+                let map = map.read_recover();
+                let columns = self
+                    .columns
+                    .get_or_insert_with(|| map.keys().cloned().collect());
+                if let Some(extra) = map.keys().find(|key| !columns.contains(key)) {
+                    return Err(format!(
+                        "CSV.Writer: the row has a column '{}' that isn't in the headers",
+                        extra
+                    ));
+                }
+                if self.needs_header {
+                    writer
+                        .write_record(columns.iter())
+                        .map_err(|e| format!("CSV write error: {}", e))?;
+                    self.needs_header = false;
+                }
+                columns
+                    .iter()
+                    .map(|column| map.get(column).map(field_text).unwrap_or_default())
+                    .collect()
+            }
+            Value::List(list) => {
+                if self.needs_header
+                    && let Some(columns) = &self.columns
+                {
+                    writer
+                        .write_record(columns.iter())
+                        .map_err(|e| format!("CSV write error: {}", e))?;
+                }
+                self.needs_header = false;
+                list.read_recover().iter().map(field_text).collect()
+            }
+            other => {
+                return Err(format!(
+                    "CSV.Writer writes a Map or a List as a row, not {}",
+                    other.type_name()
+                ));
+            }
+        };
+        writer
+            .write_record(&fields)
+            .map_err(|e| format!("CSV write error: {}", e))
+    }
+}
+
+fn open_writer(path: &str, options: Option<&Value>) -> Result<RowWriter, String> {
+    let mut builder = csv::WriterBuilder::new();
+    let mut columns = None;
+    let mut append = false;
+    if let Some(options) = options {
+        let Value::Map(map) = options else {
+            return Err(format!(
+                "CSV.Writer options must be a Map, not {}",
+                options.type_name()
+            ));
+        };
+        for (key, value) in map.read_recover().iter() {
+            match key.as_str() {
+                "Delimiter" => {
+                    builder.delimiter(single_byte(key, value)?);
+                }
+                "Quote" => {
+                    builder.quote(single_byte(key, value)?);
+                }
+                "Headers" => match value {
+                    Value::List(names) => {
+                        columns = Some(
+                            names
+                                .read_recover()
+                                .iter()
+                                .map(Value::to_display_string)
+                                .collect(),
+                        )
+                    }
+                    _ => return Err("CSV.Writer Headers must be a List of names".to_string()),
+                },
+                "Append" => append = value.is_truthy(),
+                _ => return Err(format!("CSV.Writer has no option {}", key)),
+            }
+        }
+    }
+
+    // Appending to rows already there, the header is already written
+    permissions::check_write(path)?;
+    let has_rows = append && std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|e| format!("CSV.Writer: can't open {}: {}", path, e))?;
+    Ok(RowWriter {
+        writer: Some(builder.from_writer(BufWriter::new(file))),
+        columns,
+        needs_header: !has_rows,
+    })
+}
+
 pub fn create_csv_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
+    // CSV.Parse(text, options?) -> List of row Maps
     methods.insert(
         "Parse".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
-            if args.len() != 1 {
-                return Err("CSV.Parse requires 1 argument".to_string());
+            if args.is_empty() || args.len() > 2 {
+                return Err("CSV.Parse requires 1 or 2 arguments (text, options)".to_string());
             }
 
             let csv_data = args[0].to_display_string();
-            parse_csv(&csv_data)
+            parse_with(&csv_data, read_options(args.get(1))?)
+        }))),
+    );
+
+    // CSV.OpenReader(path, options?) -> Stream of row Maps, read as they are
+    // taken, so a file of any size can be gone through
+    methods.insert(
+        "OpenReader".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("CSV.OpenReader requires 1 or 2 arguments (path, options)".to_string());
+            }
+            let path = args[0].to_display_string();
+            let options = read_options(args.get(1))?;
+            permissions::check_read(&path)?;
+            let file = File::open(&path)
+                .map_err(|e| format!("CSV.OpenReader: can't open {}: {}", path, e))?;
+            let reader = Mutex::new(RowReader::new(file, options)?);
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                let row = reader.lock_recover().next_row()?;
+                Ok(Value::Option(Box::new(row)))
+            })));
+            Ok(crate::stdlib::stream::create_stream_object(
+                vec![],
+                Some(generator),
+            ))
+        }))),
+    );
+
+    // CSV.Writer(path, options?) -> { Write(row), Flush(), Close() }. Rows
+    // go to the file as they are written
+    methods.insert(
+        "Writer".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("CSV.Writer requires 1 or 2 arguments (path, options)".to_string());
+            }
+            let path = args[0].to_display_string();
+            let writer = Arc::new(Mutex::new(open_writer(&path, args.get(1))?));
+
+            let mut object = IndexMap::new();
+            let write = writer.clone();
+            object.insert(
+                "Write".to_string(),
+                Value::NativeFunction(Arc::new(Box::new(move |args| {
+                    if args.len() != 1 {
+                        return Err("CSV.Writer Write requires 1 argument (row)".to_string());
+                    }
+                    write.lock_recover().write(&args[0])?;
+                    Ok(Value::Option(Box::new(None)))
+                }))),
+            );
+            let flush = writer.clone();
+            object.insert(
+                "Flush".to_string(),
+                Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                    if let Some(writer) = flush.lock_recover().writer.as_mut() {
+                        writer
+                            .flush()
+                            .map_err(|e| format!("CSV write error: {}", e))?;
+                    }
+                    Ok(Value::Option(Box::new(None)))
+                }))),
+            );
+            object.insert(
+                "Close".to_string(),
+                Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                    if let Some(mut writer) = writer.lock_recover().writer.take() {
+                        writer
+                            .flush()
+                            .map_err(|e| format!("CSV write error: {}", e))?;
+                    }
+                    Ok(Value::Option(Box::new(None)))
+                }))),
+            );
+            Ok(Value::Map(Arc::new(std::sync::RwLock::new(object))))
         }))),
     );
 
@@ -67,8 +498,8 @@ pub fn create_csv_module() -> Value {
 
             let filepath = args[0].to_display_string();
             let start_row = match &args[1] {
-                Value::Number(n) => {
-                    let val = n.to_string().parse::<usize>().unwrap_or(1);
+                Value::Number(_) | Value::Integer(_) => {
+                    let val = args[1].to_display_string().parse::<usize>().unwrap_or(1);
                     if val < 1 {
                         return Err("start_row must be >= 1 (1-based indexing)".to_string());
                     }
@@ -79,70 +510,32 @@ pub fn create_csv_module() -> Value {
                 }
             };
             let count = match &args[2] {
-                Value::Number(n) => n.to_string().parse::<usize>().unwrap_or(1000),
+                Value::Number(_) | Value::Integer(_) => {
+                    args[2].to_display_string().parse::<usize>().unwrap_or(1000)
+                }
                 _ => {
                     return Err("count must be a number".to_string());
                 }
             };
 
-            use std::fs::File;
-
+            permissions::check_read(&filepath)?;
             match File::open(&filepath) {
                 Ok(file) => {
-                    let mut rdr = csv::ReaderBuilder::new()
-                        .has_headers(true)
-                        .from_reader(file);
-
-                    // Get headers
-                    let headers = match rdr.headers() {
-                        Ok(h) => h.clone(),
-                        Err(e) => {
-                            return Err(format!("CSV Header Error: {}", e));
-                        }
-                    };
-
-                    let mut list_of_rows = Vec::new();
-                    let mut row_count = 1; // 1-based row counting
-
-                    // Convert 1-based to 0-based for iteration
-                    let offset_zero_based = start_row - 1;
+                    let mut reader = RowReader::new(file, ReadOptions::default())?;
 
-                    for result in rdr.records() {
-                        // Skip until to reach start_row (1-based)
-                        if row_count - 1 < offset_zero_based {
-                            row_count += 1;
-                            continue;
-                        }
-
-                        if list_of_rows.len() >= count {
+                    // Skip until to reach start_row (1-based)
+                    for _ in 1..start_row {
+                        if reader.next_row()?.is_none() {
                             break;
                         }
+                    }
 
-                        match result {
-                            Ok(record) => {
-                                let mut row_map = HashMap::new();
-
-                                for (i, field) in record.iter().enumerate() {
-                                    if let Some(header_name) = headers.get(i) {
-                                        // Try to parse as number if possible, else string
-                                        let val = if let Ok(num) = Value::from_number_string(field)
-                                        {
-                                            num
-                                        } else {
-                                            Value::String(field.to_string())
-                                        };
-                                        row_map.insert(header_name.to_string(), val);
-                                    }
-                                }
-                                list_of_rows
-                                    .push(Value::Map(Arc::new(std::sync::RwLock::new(row_map))));
-                            }
-                            Err(e) => {
-                                return Err(format!("CSV Record Error: {}", e));
-                            }
+                    let mut list_of_rows = Vec::new();
+                    while list_of_rows.len() < count {
+                        match reader.next_row()? {
+                            Some(row) => list_of_rows.push(row),
+                            None => break,
                         }
-
-                        row_count += 1;
                     }
 
                     Ok(Value::List(Arc::new(std::sync::RwLock::new(list_of_rows))))
@@ -154,3 +547,93 @@ pub fn create_csv_module() -> Value {
 
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(value: Value) -> Vec<Vec<(String, String)>> {
+        let Value::List(list) = value else {
+            panic!("CSV.Parse gives a List");
+        };
+        list.read_recover()
+            .iter()
+            .map(|row| match row {
+                Value::Map(map) => map
+                    .read_recover()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), format!("{}:{}", v.type_name(), field_text(v))))
+                    .collect(),
+                other => vec![(String::new(), other.to_display_string())],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_options() {
+        let text = "name;age;active\n'Smith; J';42;yes\nLee;7;no\n";
+        let options = Value::Map(Arc::new(std::sync::RwLock::new(IndexMap::from([
+            ("Delimiter".to_string(), Value::String(";".to_string())),
+            ("Quote".to_string(), Value::String("'".to_string())),
+            (
+                "Types".to_string(),
+                Value::Map(Arc::new(std::sync::RwLock::new(IndexMap::from([
+                    ("age".to_string(), Value::String("Integer".to_string())),
+                    ("active".to_string(), Value::String("Boolean".to_string())),
+                ])))),
+            ),
+        ]))));
+        let parsed = rows(parse_with(text, read_options(Some(&options)).unwrap()).unwrap());
+        assert_eq!(
+            parsed[0],
+            [
+                ("name".to_string(), "String:Smith; J".to_string()),
+                ("age".to_string(), "Integer:42".to_string()),
+                ("active".to_string(), "Boolean:True".to_string()),
+            ]
+        );
+
+        let bad = "age\nold\n";
+        let options = Value::Map(Arc::new(std::sync::RwLock::new(IndexMap::from([(
+            "Types".to_string(),
+            Value::Map(Arc::new(std::sync::RwLock::new(IndexMap::from([(
+                "age".to_string(),
+                Value::String("Integer".to_string()),
+            )])))),
+        )]))));
+        let error = parse_with(bad, read_options(Some(&options)).unwrap()).unwrap_err();
+        assert!(error.contains("line 2, column age"), "{}", error);
+    }
+
+    #[test]
+    fn test_writer_appends() {
+        let dir = std::env::temp_dir().join(format!("sfex_csv_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.csv");
+        let path = path.to_str().unwrap();
+        let row = |name: &str, note: &str| {
+            Value::Map(Arc::new(std::sync::RwLock::new(IndexMap::from([
+                ("name".to_string(), Value::String(name.to_string())),
+                ("note".to_string(), Value::String(note.to_string())),
+            ]))))
+        };
+
+        let mut writer = open_writer(path, None).unwrap();
+        writer.write(&row("a", "x, y")).unwrap();
+        writer.writer.take().unwrap().flush().unwrap();
+
+        let append = Value::Map(Arc::new(std::sync::RwLock::new(IndexMap::from([(
+            "Append".to_string(),
+            Value::Boolean(true),
+        )]))));
+        let mut writer = open_writer(path, Some(&append)).unwrap();
+        writer.write(&row("b", "\"q\"")).unwrap();
+        writer.writer.take().unwrap().flush().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "name,note\na,\"x, y\"\nb,\"\"\"q\"\"\"\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use crate::stdlib::{csv, html, json, toml, xml};
+use crate::stdlib::{csv, diff, html, json, toml, xml};
 use file_format::FileFormat;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::io::Read;
 use std::path::Path;
 use std::sync::Arc;
@@ -87,7 +89,7 @@ fn get_media_type_for_format(format: &str, fallback: &str) -> String {
 }
 
 pub fn create_data_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Detect".to_string(),
@@ -96,6 +98,7 @@ pub fn create_data_module() -> Value {
                 return Err("Data.Detect requires 1 argument".to_string());
             }
             let filepath = args[0].to_display_string();
+            permissions::check_read(&filepath)?;
 
             match std::fs::File::open(&filepath) {
                 Ok(mut file) => {
@@ -125,7 +128,7 @@ pub fn create_data_module() -> Value {
                         (base_name.to_string(), base_media_type.to_string())
                     };
 
-                    let mut info = HashMap::new();
+                    let mut info = IndexMap::new();
                     info.insert("Format".to_string(), Value::String(final_format));
                     info.insert("MediaType".to_string(), Value::String(final_media_type));
                     info.insert(
@@ -165,7 +168,7 @@ pub fn create_data_module() -> Value {
             let best_guess = priorities.first().cloned().unwrap_or("Plain Text");
             let media_type = get_media_type_for_format(best_guess, "text/plain");
 
-            let mut info = HashMap::new();
+            let mut info = IndexMap::new();
             info.insert("Format".to_string(), Value::String(best_guess.to_string()));
             info.insert("MediaType".to_string(), Value::String(media_type));
             info.insert("Kind".to_string(), Value::String("Text".to_string()));
@@ -181,6 +184,7 @@ pub fn create_data_module() -> Value {
                 return Err("Data.Parse requires 1 argument".to_string());
             }
             let filepath = args[0].to_display_string();
+            permissions::check_read(&filepath)?;
 
             const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
             let file_size = match std::fs::metadata(&filepath) {
@@ -245,6 +249,7 @@ pub fn create_data_module() -> Value {
                 return Err("Data.Describe requires 1 argument".to_string());
             }
             let filepath = args[0].to_display_string();
+            permissions::check_read(&filepath)?;
 
             let size = match std::fs::metadata(&filepath) {
                 Ok(m) => m.len(),
@@ -280,7 +285,7 @@ pub fn create_data_module() -> Value {
                         (base_name.to_string(), base_media_type.to_string())
                     };
 
-                    let mut description = HashMap::new();
+                    let mut description = IndexMap::new();
                     description.insert("Format".to_string(), Value::String(final_format.clone()));
                     description.insert("MediaType".to_string(), Value::String(final_media_type));
                     description.insert(
@@ -317,7 +322,9 @@ pub fn create_data_module() -> Value {
             let data = &args[0];
             let max_depth = if args.len() == 2 {
                 match &args[1] {
-                    Value::Number(n) => n.to_string().parse::<usize>().unwrap_or(10),
+                    Value::Number(_) | Value::Integer(_) => {
+                        args[1].to_display_string().parse::<usize>().unwrap_or(10)
+                    }
                     _ => 10,
                 }
             } else {
@@ -331,17 +338,17 @@ pub fn create_data_module() -> Value {
 
                 match value {
                     Value::Map(m) => {
-                        let map = m.read().unwrap();
-                        let mut s = HashMap::new();
+                        let map = m.read_recover();
+                        let mut s = IndexMap::new();
                         for (k, v) in map.iter() {
                             s.insert(k.clone(), analyze_structure(v, depth + 1, max_depth));
                         }
                         Value::Map(Arc::new(std::sync::RwLock::new(s)))
                     }
                     Value::List(l) => {
-                        let list = l.read().unwrap();
+                        let list = l.read_recover();
                         let count = list.len();
-                        let mut s = HashMap::new();
+                        let mut s = IndexMap::new();
                         s.insert("type".to_string(), Value::String("List".to_string()));
                         s.insert(
                             "count".to_string(),
@@ -356,7 +363,8 @@ pub fn create_data_module() -> Value {
                         Value::Map(Arc::new(std::sync::RwLock::new(s)))
                     }
                     Value::String(_) => Value::String("String".to_string()),
-                    Value::Number(_) => Value::String("Number".to_string()),
+                    // Whole numbers stay "Number" here, as they were before Integer existed
+                    Value::Number(_) | Value::Integer(_) => Value::String("Number".to_string()),
                     Value::Boolean(_) => Value::String("Boolean".to_string()),
                     _ => Value::String(
                         format!("{:?}", value)
@@ -372,5 +380,81 @@ pub fn create_data_module() -> Value {
         }))),
     );
 
+    // Data.Diff(old, new) -> List of { Op, Path, Old, New, Description }
+    methods.insert(
+        "Diff".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Data.Diff requires 2 arguments (old, new)".to_string());
+            }
+            Ok(diff::diff_values(&args[0], &args[1]))
+        }))),
+    );
+
+    // Data.Patch(value, changes) -> copy of value with the changes applied
+    methods.insert(
+        "Patch".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Data.Patch requires 2 arguments (value, changes)".to_string());
+            }
+            diff::apply_patch(&args[0], &args[1])
+        }))),
+    );
+
+    // Data.DeepEqual(a, b) -> whether a and b hold the same data, even when
+    // they are different Lists or Maps
+    methods.insert(
+        "DeepEqual".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Data.DeepEqual requires 2 arguments (a, b)".to_string());
+            }
+            Ok(Value::Boolean(args[0].deep_equals(&args[1])))
+        }))),
+    );
+
+    // Data.DeepClone(value) -> a copy sharing no editable List or Map with value
+    methods.insert(
+        "DeepClone".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Data.DeepClone requires 1 argument (value)".to_string());
+            }
+            Ok(args[0].clone_deep())
+        }))),
+    );
+
+    methods.insert(
+        "Freeze".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Data.Freeze requires 1 argument (value)".to_string());
+            }
+            args[0].freeze();
+            Ok(args[0].clone())
+        }))),
+    );
+
+    methods.insert(
+        "IsFrozen".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Data.IsFrozen requires 1 argument (value)".to_string());
+            }
+            Ok(Value::Boolean(args[0].is_frozen()))
+        }))),
+    );
+
+    methods.insert(
+        "Thaw".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Data.Thaw requires 1 argument (value)".to_string());
+            }
+            Ok(args[0].thaw())
+        }))),
+    );
+
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
@@ -0,0 +1,318 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+
+/// One step in a path: a Map key or a 1-based List index.
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+struct Change {
+    op: &'static str,
+    path: Vec<Segment>,
+    old: Option<Value>,
+    new: Option<Value>,
+}
+
+/// Structural differences between `a` and `b` as a List of change Maps with
+/// Op ("add", "remove", "replace"), Path, Old, New and a readable Description.
+pub fn diff_values(a: &Value, b: &Value) -> Value {
+    let mut changes = Vec::new();
+    diff_into(a, b, &mut Vec::new(), &mut changes);
+    let list = changes.into_iter().map(change_to_value).collect();
+    Value::List(Arc::new(RwLock::new(list)))
+}
+
+fn diff_into(a: &Value, b: &Value, path: &mut Vec<Segment>, changes: &mut Vec<Change>) {
+    match (a, b) {
+        (Value::Map(left), Value::Map(right)) => {
+            if Arc::ptr_eq(left, right) {
+                return;
+            }
+            let left = left.read_recover();
+            let right = right.read_recover();
+            let keys: BTreeSet<&String> = left.keys().chain(right.keys()).collect();
+
+            for key in keys {
+                path.push(Segment::Key(key.clone()));
+                match (left.get(key), right.get(key)) {
+                    (Some(old), Some(new)) => diff_into(old, new, path, changes),
+                    (Some(old), None) => changes.push(Change {
+                        op: "remove",
+                        path: path.clone(),
+                        old: Some(old.clone_deep()),
+                        new: None,
+                    }),
+                    (None, Some(new)) => changes.push(Change {
+                        op: "add",
+                        path: path.clone(),
+                        old: None,
+                        new: Some(new.clone_deep()),
+                    }),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        (Value::List(left), Value::List(right)) => {
+            if Arc::ptr_eq(left, right) {
+                return;
+            }
+            let left = left.read_recover();
+            let right = right.read_recover();
+            let common = left.len().min(right.len());
+
+            for i in 0..common {
+                path.push(Segment::Index(i + 1));
+                diff_into(&left[i], &right[i], path, changes);
+                path.pop();
+            }
+            for (i, new) in right.iter().enumerate().skip(common) {
+                path.push(Segment::Index(i + 1));
+                changes.push(Change {
+                    op: "add",
+                    path: path.clone(),
+                    old: None,
+                    new: Some(new.clone_deep()),
+                });
+                path.pop();
+            }
+            // Removed from the end first so each index is still valid when
+            // the changes are applied in order
+            for i in (common..left.len()).rev() {
+                path.push(Segment::Index(i + 1));
+                changes.push(Change {
+                    op: "remove",
+                    path: path.clone(),
+                    old: Some(left[i].clone_deep()),
+                    new: None,
+                });
+                path.pop();
+            }
+        }
+        _ => {
+            if !same_scalar(a, b) {
+                changes.push(Change {
+                    op: "replace",
+                    path: path.clone(),
+                    old: Some(a.clone_deep()),
+                    new: Some(b.clone_deep()),
+                });
+            }
+        }
+    }
+}
+
+fn same_scalar(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Option(x), Value::Option(y)) => match (x.as_ref(), y.as_ref()) {
+            (Some(x), Some(y)) => {
+                let mut nested = Vec::new();
+                diff_into(x, y, &mut Vec::new(), &mut nested);
+                nested.is_empty()
+            }
+            (None, None) => true,
+            _ => false,
+        },
+        _ => a.equals(b) || a == b,
+    }
+}
+
+fn change_to_value(change: Change) -> Value {
+    let description = describe(&change);
+    let mut map = IndexMap::new();
+    map.insert("Op".to_string(), Value::String(change.op.to_string()));
+    let path = change
+        .path
+        .iter()
+        .map(|segment| match segment {
+            Segment::Key(key) => Value::String(key.clone()),
+            Segment::Index(index) => Value::Number(bigdecimal::BigDecimal::from(*index as u64)),
+        })
+        .collect();
+    map.insert("Path".to_string(), Value::List(Arc::new(RwLock::new(path))));
+    if let Some(old) = change.old {
+        map.insert("Old".to_string(), old);
+    }
+    if let Some(new) = change.new {
+        map.insert("New".to_string(), new);
+    }
+    map.insert("Description".to_string(), Value::String(description));
+    Value::Map(Arc::new(RwLock::new(map)))
+}
+
+fn path_text(path: &[Segment]) -> String {
+    let mut text = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) => {
+                if !text.is_empty() {
+                    text.push('.');
+                }
+                text.push_str(key);
+            }
+            Segment::Index(index) => text.push_str(&format!("[{}]", index)),
+        }
+    }
+    if text.is_empty() {
+        "(root)".to_string()
+    } else {
+        text
+    }
+}
+
+fn describe(change: &Change) -> String {
+    let show = |value: &Option<Value>| match value {
+        Some(Value::String(s)) => format!("\"{}\"", s),
+        Some(value) => value.to_display_string(),
+        None => String::new(),
+    };
+    let at = path_text(&change.path);
+    match change.op {
+        "add" => format!("add {}: {}", at, show(&change.new)),
+        "remove" => format!("remove {}: {}", at, show(&change.old)),
+        _ => format!(
+            "replace {}: {} -> {}",
+            at,
+            show(&change.old),
+            show(&change.new)
+        ),
+    }
+}
+
+/// Apply a change list from `diff_values` to a deep copy of `value`; the
+/// copy is never frozen, even when `value` is.
+pub fn apply_patch(value: &Value, diff: &Value) -> Result<Value, String> {
+    let Value::List(changes) = diff else {
+        return Err("Data.Patch expects a List of changes from Data.Diff".to_string());
+    };
+
+    let mut result = value.thaw();
+    for change in changes.read_recover().iter() {
+        let Value::Map(change) = change else {
+            return Err("Data.Patch: each change must be a Map".to_string());
+        };
+        let change = change.read_recover();
+        let op = change
+            .get("Op")
+            .map(|op| op.to_display_string().to_lowercase())
+            .ok_or("Data.Patch: change is missing Op")?;
+        let path = parse_path(change.get("Path"))?;
+        let new = change.get("New").map(|v| v.clone_deep());
+
+        let Some((last, parents)) = path.split_last() else {
+            // An empty path replaces the whole value
+            result = match (op.as_str(), new) {
+                ("replace" | "add", Some(new)) => new,
+                _ => return Err(format!("Data.Patch: cannot {} the root value", op)),
+            };
+            continue;
+        };
+
+        let parent = walk(&result, parents)?;
+        apply_change(&parent, last, &op, new, &path)?;
+    }
+    Ok(result)
+}
+
+fn parse_path(path: Option<&Value>) -> Result<Vec<Segment>, String> {
+    let Some(Value::List(path)) = path else {
+        return Err("Data.Patch: change Path must be a List".to_string());
+    };
+    path.read_recover()
+        .iter()
+        .map(|segment| match segment {
+            Value::String(key) => Ok(Segment::Key(key.clone())),
+            Value::Number(n) => n
+                .to_usize()
+                .filter(|i| *i >= 1)
+                .map(Segment::Index)
+                .ok_or_else(|| format!("Data.Patch: invalid list index {}", n)),
+            Value::Integer(n) => n
+                .to_usize()
+                .filter(|i| *i >= 1)
+                .map(Segment::Index)
+                .ok_or_else(|| format!("Data.Patch: invalid list index {}", n)),
+            Value::FastNumber(f) if *f >= 1.0 => Ok(Segment::Index(*f as usize)),
+            other => Err(format!(
+                "Data.Patch: invalid path segment {}",
+                other.to_display_string()
+            )),
+        })
+        .collect()
+}
+
+// Follow `path` through shared Maps/Lists; the returned container aliases
+// the one inside `root`, so changes made to it land in `root`.
+fn walk(root: &Value, path: &[Segment]) -> Result<Value, String> {
+    let mut current = root.clone();
+    for (depth, segment) in path.iter().enumerate() {
+        let next = match (&current, segment) {
+            (Value::Map(map), Segment::Key(key)) => map.read_recover().get(key).cloned(),
+            (Value::List(list), Segment::Index(index)) => {
+                list.read_recover().get(index - 1).cloned()
+            }
+            _ => None,
+        };
+        current = next
+            .ok_or_else(|| format!("Data.Patch: path {} not found", path_text(&path[..=depth])))?;
+    }
+    Ok(current)
+}
+
+fn apply_change(
+    parent: &Value,
+    last: &Segment,
+    op: &str,
+    new: Option<Value>,
+    path: &[Segment],
+) -> Result<(), String> {
+    let missing = || format!("Data.Patch: path {} not found", path_text(path));
+    let needs_new = || {
+        format!(
+            "Data.Patch: {} at {} needs a New value",
+            op,
+            path_text(path)
+        )
+    };
+
+    match (parent, last) {
+        (Value::Map(map), Segment::Key(key)) => {
+            let mut map = map.write_recover();
+            match op {
+                "add" | "replace" => {
+                    map.insert(key.clone(), new.ok_or_else(needs_new)?);
+                }
+                "remove" => {
+                    map.shift_remove(key).ok_or_else(missing)?;
+                }
+                _ => return Err(format!("Data.Patch: unknown Op '{}'", op)),
+            }
+        }
+        (Value::List(list), Segment::Index(index)) => {
+            let mut list = list.write_recover();
+            let i = index - 1;
+            match op {
+                "add" if i <= list.len() => list.insert(i, new.ok_or_else(needs_new)?),
+                "replace" if i < list.len() => list[i] = new.ok_or_else(needs_new)?,
+                "remove" if i < list.len() => {
+                    list.remove(i);
+                }
+                "add" | "replace" | "remove" => return Err(missing()),
+                _ => return Err(format!("Data.Patch: unknown Op '{}'", op)),
+            }
+        }
+        _ => {
+            return Err(format!(
+                "Data.Patch: {} does not match the value's shape",
+                path_text(path)
+            ));
+        }
+    }
+    Ok(())
+}
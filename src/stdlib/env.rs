@@ -1,10 +1,12 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::env;
 use std::sync::Arc;
 
 pub fn create_env_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Get".to_string(),
@@ -14,6 +16,7 @@ pub fn create_env_module() -> Value {
             }
 
             let key = args[0].to_display_string();
+            permissions::check_env(&key)?;
             let default = if args.len() == 2 {
                 args[1].to_display_string()
             } else {
@@ -35,6 +38,7 @@ pub fn create_env_module() -> Value {
             }
 
             let key = args[0].to_display_string();
+            permissions::check_env(&key)?;
             Ok(Value::Boolean(env::var(&key).is_ok()))
         }))),
     );
@@ -46,8 +50,9 @@ pub fn create_env_module() -> Value {
                 return Err("Env.All requires no arguments".to_string());
             }
 
-            let mut env_map = HashMap::new();
-            for (key, value) in env::vars() {
+            let mut env_map = IndexMap::new();
+            // Without access to every variable, only the allowed ones
+            for (key, value) in env::vars().filter(|(key, _)| permissions::allows_env(key)) {
                 env_map.insert(key, Value::String(value));
             }
 
@@ -63,6 +68,7 @@ pub fn create_env_module() -> Value {
             }
 
             let filepath = args[0].to_display_string();
+            permissions::check_read(&filepath)?;
 
             match std::fs::read_to_string(&filepath) {
                 Ok(content) => {
@@ -84,6 +90,8 @@ pub fn create_env_module() -> Value {
                                 value = &value[1..value.len() - 1];
                             }
 
+                            permissions::check_env(key)?;
+
                             unsafe {
                                 env::set_var(key, value);
                             }
@@ -99,5 +107,185 @@ pub fn create_env_module() -> Value {
         }))),
     );
 
+    // Env.LoadDotenv(path?, { Override }) -> Map of what the file sets.
+    // Variables already in the environment win unless Override is True; a
+    // missing .env is fine when no path is given
+    methods.insert(
+        "LoadDotenv".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() > 2 {
+                return Err(
+                    "Env.LoadDotenv requires 0-2 arguments (optional path, optional { Override })"
+                        .to_string(),
+                );
+            }
+            let path = args.first().map(Value::to_display_string);
+            let overwrite = match args.get(1) {
+                Some(Value::Map(options)) => options
+                    .read_recover()
+                    .get("Override")
+                    .is_some_and(Value::is_truthy),
+                Some(_) => return Err("Env.LoadDotenv options must be a Map".to_string()),
+                None => false,
+            };
+
+            let file = path.as_deref().unwrap_or(".env");
+            permissions::check_read(file)?;
+            let text = match std::fs::read_to_string(file) {
+                Ok(text) => text,
+                Err(e) if path.is_none() && e.kind() == std::io::ErrorKind::NotFound => {
+                    String::new()
+                }
+                Err(e) => return Err(format!("Env.LoadDotenv: {}", e)),
+            };
+            let entries = parse_dotenv(&text).map_err(|e| format!("Env.LoadDotenv: {}", e))?;
+
+            let mut loaded = IndexMap::new();
+            for (key, value) in entries {
+                permissions::check_env(&key)?;
+                if overwrite || env::var_os(&key).is_none() {
+                    unsafe {
+                        env::set_var(&key, &value);
+                    }
+                }
+                loaded.insert(key, Value::String(value));
+            }
+            Ok(Value::Map(Arc::new(std::sync::RwLock::new(loaded))))
+        }))),
+    );
+
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+/// The variables a .env file sets, in order. Lines are `KEY=value`, with an
+/// optional `export ` before the key. Values in double quotes may span lines
+/// and take `\n`, `\t`, `\"` and `\\`; values in single quotes are taken as
+/// they are; unquoted values end at a ` #` comment.
+pub fn parse_dotenv(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, rest)) = line.split_once('=') else {
+            return Err(format!("line {}: expected KEY=value", index + 1));
+        };
+        let key = key.trim();
+        let valid = key
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if !valid {
+            return Err(format!(
+                "line {}: '{}' is not a variable name",
+                index + 1,
+                key
+            ));
+        }
+
+        let rest = rest.trim_start();
+        let (value, after) = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut text = quoted.to_string();
+            let after = loop {
+                let mut chars = text.char_indices();
+                let mut closed = None;
+                while let Some((at, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            closed = Some(at + 1);
+                            break;
+                        }
+                        '\\' => match chars.next().map(|(_, c)| c) {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('r') => value.push('\r'),
+                            Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                            Some(c) => {
+                                value.push('\\');
+                                value.push(c);
+                            }
+                            None => value.push('\\'),
+                        },
+                        c => value.push(c),
+                    }
+                }
+                if let Some(end) = closed {
+                    break text[end..].to_string();
+                }
+                let Some((_, next)) = lines.next() else {
+                    return Err(format!("line {}: unclosed \" in {}", index + 1, key));
+                };
+                value.push('\n');
+                text = next.to_string();
+            };
+            (value, after)
+        } else if let Some(quoted) = rest.strip_prefix('\'') {
+            let Some(end) = quoted.find('\'') else {
+                return Err(format!("line {}: unclosed ' in {}", index + 1, key));
+            };
+            (quoted[..end].to_string(), quoted[end + 1..].to_string())
+        } else {
+            let end = rest
+                .char_indices()
+                .find(|&(at, c)| c == '#' && (at == 0 || rest[..at].ends_with(char::is_whitespace)))
+                .map_or(rest.len(), |(at, _)| at);
+            (rest[..end].trim_end().to_string(), String::new())
+        };
+        let after = after.trim();
+        if !after.is_empty() && !after.starts_with('#') {
+            return Err(format!(
+                "line {}: unexpected '{}' after the value of {}",
+                index + 1,
+                after,
+                key
+            ));
+        }
+        entries.push((key.to_string(), value));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv() {
+        let text = "# settings\nexport PORT=8000\nNAME = Shop # the shop\nURL=http://x/#top\nEMPTY=\nRAW='a \\n b'\nMSG=\"one\\ttwo \\\"q\\\"\"\nPEM=\"-----BEGIN\nabc\n-----END\" # key\n";
+        let entries = parse_dotenv(text).unwrap();
+        let expected = [
+            ("PORT", "8000"),
+            ("NAME", "Shop"),
+            ("URL", "http://x/#top"),
+            ("EMPTY", ""),
+            ("RAW", "a \\n b"),
+            ("MSG", "one\ttwo \"q\""),
+            ("PEM", "-----BEGIN\nabc\n-----END"),
+        ];
+        let entries: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(entries, expected);
+
+        assert!(
+            parse_dotenv("PORT 8000\n")
+                .unwrap_err()
+                .starts_with("line 1")
+        );
+        assert!(
+            parse_dotenv("A=1\n1B=2\n")
+                .unwrap_err()
+                .starts_with("line 2")
+        );
+        assert!(parse_dotenv("A=\"open\n").is_err());
+        assert!(parse_dotenv("A=\"x\" y\n").is_err());
+    }
+}
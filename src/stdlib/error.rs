@@ -1,9 +1,9 @@
 use crate::runtime::value::{ErrorInfo, Value};
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::sync::Arc;
 
 pub fn create_error_module() -> Value {
-    let mut categories = HashMap::new();
+    let mut categories = IndexMap::new();
 
     // Error.System - System-level errors
     categories.insert("System".to_string(), create_system_category());
@@ -78,7 +78,7 @@ pub fn create_error_module() -> Value {
 }
 
 fn create_system_category() -> Value {
-    let mut subtypes = HashMap::new();
+    let mut subtypes = IndexMap::new();
 
     // Error.System.FileNotFound(message)
     subtypes.insert(
@@ -120,7 +120,7 @@ fn create_system_category() -> Value {
 }
 
 fn create_logic_category() -> Value {
-    let mut subtypes = HashMap::new();
+    let mut subtypes = IndexMap::new();
 
     // Error.Logic.DivisionByZero(message)
     subtypes.insert(
@@ -162,7 +162,7 @@ fn create_logic_category() -> Value {
 }
 
 fn create_lookup_category() -> Value {
-    let mut subtypes = HashMap::new();
+    let mut subtypes = IndexMap::new();
 
     // Error.Lookup.UndefinedVariable(message)
     subtypes.insert(
@@ -198,7 +198,7 @@ fn create_lookup_category() -> Value {
 }
 
 fn create_validation_category() -> Value {
-    let mut subtypes = HashMap::new();
+    let mut subtypes = IndexMap::new();
 
     // Error.Validation.InvalidType(message)
     subtypes.insert(
@@ -234,7 +234,7 @@ fn create_validation_category() -> Value {
 }
 
 fn create_panic_category() -> Value {
-    let mut subtypes = HashMap::new();
+    let mut subtypes = IndexMap::new();
 
     // Error.Panic.TaskPanicked(message)
     subtypes.insert(
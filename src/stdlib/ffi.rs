@@ -0,0 +1,422 @@
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::num_bigint::BigInt;
+use indexmap::IndexMap;
+use libffi::middle::{Arg, Cif, CodePtr, Type};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::sync::{Arc, Mutex, RwLock};
+
+// C functions in shared libraries. A script declares each function's
+// parameter and return types, and Declare has libffi build the call for
+// that signature once, so each argument goes where the platform's C calling
+// convention puts a value of its type.
+
+/// Types a declaration may name
+const TYPES: &str = "int, int64, float, double, string, pointer, void";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CType {
+    /// C `int`, 32 bits
+    Int,
+    Int64,
+    /// C `float`, 32 bits
+    Float,
+    Double,
+    /// `const char *`, NUL-terminated UTF-8
+    String,
+    /// An address, as an Integer; 0 is NULL
+    Pointer,
+    /// Return type only
+    Void,
+}
+
+impl CType {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "int" => Ok(CType::Int),
+            "int64" | "long" => Ok(CType::Int64),
+            "float" => Ok(CType::Float),
+            "double" => Ok(CType::Double),
+            "string" => Ok(CType::String),
+            "pointer" => Ok(CType::Pointer),
+            "void" => Ok(CType::Void),
+            _ => Err(format!(
+                "Unknown FFI type '{}', expected one of: {}",
+                name, TYPES
+            )),
+        }
+    }
+
+    fn ffi_type(self) -> Type {
+        match self {
+            CType::Int => Type::i32(),
+            CType::Int64 => Type::i64(),
+            CType::Float => Type::f32(),
+            CType::Double => Type::f64(),
+            CType::String | CType::Pointer => Type::pointer(),
+            CType::Void => Type::void(),
+        }
+    }
+}
+
+/// An open shared library, closed when the last function from it is gone
+struct Library {
+    path: String,
+    library: libloading::Library,
+}
+
+struct Function {
+    name: String,
+    code: unsafe extern "C" fn(),
+    parameters: Vec<CType>,
+    returns: CType,
+    signature: Signature,
+    // Keeps the code behind `code` loaded
+    _library: Arc<Library>,
+}
+
+/// The libffi description of a declared signature
+struct Signature(Cif);
+
+// SAFETY: a Cif holds pointers only to the types it owns, and ffi_call
+// only reads it once it is prepared, so calls from several threads are fine
+unsafe impl Send for Signature {}
+unsafe impl Sync for Signature {}
+
+/// An argument converted to its C type, kept alive for the call
+enum CValue {
+    Int(i32),
+    Int64(i64),
+    Float(f32),
+    Double(f64),
+    Pointer(*const c_void),
+}
+
+impl CValue {
+    fn arg(&self) -> Arg {
+        match self {
+            CValue::Int(value) => Arg::new(value),
+            CValue::Int64(value) => Arg::new(value),
+            CValue::Float(value) => Arg::new(value),
+            CValue::Double(value) => Arg::new(value),
+            CValue::Pointer(value) => Arg::new(value),
+        }
+    }
+}
+
+pub fn create_ffi_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // FFI.Load(path) -> Library
+    methods.insert(
+        "Load".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("FFI.Load requires 1 argument (library path)".to_string());
+            }
+            // Native code can do anything a program can
+            let path = args[0].to_display_string();
+            permissions::check_run(&path)?;
+            let library = Library::open(&path)?;
+            Ok(create_library_object(Arc::new(library)))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn create_library_object(library: Arc<Library>) -> Value {
+    let declared: Arc<Mutex<HashMap<String, Arc<Function>>>> = Arc::default();
+    let mut methods = IndexMap::new();
+    methods.insert("Path".to_string(), Value::String(library.path.clone()));
+
+    // Library.Declare(name, [parameter types], return type?) -> function
+    let declare_into = declared.clone();
+    methods.insert(
+        "Declare".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "Library.Declare requires 2 or 3 arguments (name, parameter types, optional return type)"
+                        .to_string(),
+                );
+            }
+            let name = args[0].to_display_string();
+            let parameters = match &args[1] {
+                Value::List(types) => types
+                    .read_recover()
+                    .iter()
+                    .map(|t| CType::parse(&t.to_display_string()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                other => {
+                    return Err(format!(
+                        "Library.Declare expects a List of parameter types, got {}",
+                        other.type_name()
+                    ));
+                }
+            };
+            let returns = match args.get(2) {
+                Some(value) => CType::parse(&value.to_display_string())?,
+                None => CType::Void,
+            };
+            let function = Arc::new(library.function(&name, parameters, returns)?);
+            declare_into
+                .lock_recover()
+                .insert(name, function.clone());
+            Ok(Value::NativeFunction(Arc::new(Box::new(move |args| {
+                function.call(&args)
+            }))))
+        }))),
+    );
+
+    // Library.Call(name, args...) -> result of a declared function
+    methods.insert(
+        "Call".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let Some((name, args)) = args.split_first() else {
+                return Err("Library.Call requires a function name".to_string());
+            };
+            let name = name.to_display_string();
+            let function = declared.lock_recover().get(&name).cloned().ok_or_else(|| {
+                format!(
+                    "FFI function '{}' is not declared; use Library.Declare first",
+                    name
+                )
+            })?;
+            function.call(args)
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+impl Library {
+    fn open(path: &str) -> Result<Self, String> {
+        // SAFETY: loading a library may run its initializers, which is
+        // what loading it means
+        let library = unsafe { libloading::Library::new(path) }
+            .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        Ok(Self {
+            path: path.to_string(),
+            library,
+        })
+    }
+
+    fn function(
+        self: &Arc<Self>,
+        name: &str,
+        parameters: Vec<CType>,
+        returns: CType,
+    ) -> Result<Function, String> {
+        if parameters.contains(&CType::Void) {
+            return Err("void is only a return type".to_string());
+        }
+        let missing = || format!("{} has no function '{}'", self.path, name);
+        // SAFETY: the symbol is only called through the declared signature,
+        // and the Function keeps the library open
+        let code = unsafe {
+            self.library
+                .get::<Option<unsafe extern "C" fn()>>(name.as_bytes())
+                .map_err(|_| missing())?
+        };
+        let code = (*code).ok_or_else(missing)?;
+        let signature = Signature(Cif::new(
+            parameters.iter().map(|t| t.ffi_type()),
+            returns.ffi_type(),
+        ));
+        Ok(Function {
+            name: name.to_string(),
+            code,
+            parameters,
+            returns,
+            signature,
+            _library: self.clone(),
+        })
+    }
+}
+
+impl Function {
+    fn call(&self, args: &[Value]) -> Result<Value, String> {
+        if args.len() != self.parameters.len() {
+            return Err(format!(
+                "FFI function '{}' takes {} argument(s), got {}",
+                self.name,
+                self.parameters.len(),
+                args.len()
+            ));
+        }
+
+        // Strings passed in must outlive the call
+        let mut strings = Vec::new();
+        let mut values = Vec::with_capacity(args.len());
+        for (i, (parameter, arg)) in self.parameters.iter().zip(args).enumerate() {
+            let wrong = |expected: &str| {
+                format!(
+                    "Argument {} of '{}' must be {}, got {}",
+                    i + 1,
+                    self.name,
+                    expected,
+                    arg.type_name()
+                )
+            };
+            values.push(match parameter {
+                CType::Int => {
+                    let value = i64::try_from(arg.clone()).map_err(|_| wrong("a whole number"))?;
+                    CValue::Int(
+                        i32::try_from(value)
+                            .map_err(|_| wrong("a whole number that fits in 32 bits"))?,
+                    )
+                }
+                CType::Int64 => {
+                    CValue::Int64(i64::try_from(arg.clone()).map_err(|_| wrong("a whole number"))?)
+                }
+                CType::Float => {
+                    CValue::Float(f64::try_from(arg.clone()).map_err(|_| wrong("a number"))? as f32)
+                }
+                CType::Double => {
+                    CValue::Double(f64::try_from(arg.clone()).map_err(|_| wrong("a number"))?)
+                }
+                CType::String => {
+                    let text = CString::new(arg.to_display_string())
+                        .map_err(|_| wrong("text without NUL bytes"))?;
+                    let address = text.as_ptr().cast();
+                    strings.push(text);
+                    CValue::Pointer(address)
+                }
+                CType::Pointer => CValue::Pointer(match arg {
+                    Value::Option(none) if none.is_none() => std::ptr::null(),
+                    _ => usize::try_from(arg.clone()).map_err(|_| wrong("an address"))?
+                        as *const c_void,
+                }),
+                CType::Void => unreachable!("Declare refuses void parameters"),
+            });
+        }
+        let args: Vec<Arg> = values.iter().map(CValue::arg).collect();
+
+        let cif = &self.signature.0;
+        let code = CodePtr::from_fun(self.code);
+        // SAFETY: the Cif matches the types the script declared, and each
+        // Arg points at a value of its parameter's type. libffi writes an
+        // int return as a whole register, so it's read back as an isize.
+        let result = unsafe {
+            match self.returns {
+                CType::Int => Value::Integer(BigInt::from(cif.call::<isize>(code, &args) as i32)),
+                CType::Int64 => Value::Integer(BigInt::from(cif.call::<i64>(code, &args))),
+                CType::Float => Value::FastNumber(f64::from(cif.call::<f32>(code, &args))),
+                CType::Double => Value::FastNumber(cif.call::<f64>(code, &args)),
+                CType::Pointer => {
+                    Value::Integer(BigInt::from(cif.call::<*const c_void>(code, &args) as usize))
+                }
+                CType::String => {
+                    let text = cif.call::<*const c_char>(code, &args);
+                    if text.is_null() {
+                        return Err(format!("FFI function '{}' returned NULL", self.name));
+                    }
+                    // A non-NULL string return is a C string
+                    Value::String(CStr::from_ptr(text).to_string_lossy().to_string())
+                }
+                CType::Void => {
+                    cif.call::<()>(code, &args);
+                    Value::Option(Box::new(None))
+                }
+            }
+        };
+        drop(strings);
+        Ok(result)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calls() {
+        let libc = Arc::new(Library::open("libc.so.6").unwrap());
+        let libm = Arc::new(Library::open("libm.so.6").unwrap());
+        let call = |library: &Arc<Library>, name, parameters: &[&str], returns, args: &[Value]| {
+            let parameters = parameters
+                .iter()
+                .map(|t| CType::parse(t).unwrap())
+                .collect();
+            library
+                .function(name, parameters, CType::parse(returns).unwrap())
+                .unwrap()
+                .call(args)
+                .unwrap()
+                .to_display_string()
+        };
+
+        assert_eq!(call(&libc, "abs", &["int"], "int", &[Value::from(-7)]), "7");
+        assert_eq!(
+            call(
+                &libc,
+                "strlen",
+                &["string"],
+                "int64",
+                &[Value::from("héllo")]
+            ),
+            "6"
+        );
+        assert_eq!(
+            call(
+                &libm,
+                "pow",
+                &["double", "double"],
+                "double",
+                &[Value::from(2), Value::from(10)]
+            ),
+            "1024"
+        );
+        assert_eq!(
+            call(&libm, "sqrtf", &["float"], "float", &[Value::from(2.25)]),
+            "1.5"
+        );
+        // Integer and float arguments mixed
+        assert_eq!(
+            call(
+                &libm,
+                "ldexp",
+                &["double", "int"],
+                "double",
+                &[Value::from(3), Value::from(4)]
+            ),
+            "48"
+        );
+
+        // Floats travel as 32 bits, whatever else is in the signature
+        assert_eq!(
+            call(
+                &libm,
+                "fmaf",
+                &["float", "float", "float"],
+                "float",
+                &[Value::from(1.5), Value::from(2), Value::from(0.25)]
+            ),
+            "3.25"
+        );
+        assert_eq!(
+            call(
+                &libm,
+                "ldexpf",
+                &["float", "int"],
+                "float",
+                &[Value::from(0.75), Value::from(-2)]
+            ),
+            "0.1875"
+        );
+        let srand = libc.function("srand", vec![CType::Int], CType::Void);
+        assert!(srand.unwrap().call(&[Value::from(7)]).is_ok());
+
+        assert!(
+            libc.function("no_such_function", Vec::new(), CType::Void)
+                .is_err()
+        );
+        let abs = libc.function("abs", vec![CType::Int], CType::Int).unwrap();
+        assert!(abs.call(&[Value::from(1u64 << 40)]).is_err());
+        assert!(abs.call(&[]).is_err());
+        assert!(Library::open("libdoes-not-exist.so").is_err());
+    }
+}
@@ -1,10 +1,69 @@
+use crate::runtime::lock::MutexExt;
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use rand::Rng;
 use std::fs;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// Files and directories made by File.TempFile and File.TempDir. The File
+// module of each interpreter holds its own list, so they are removed when
+// the interpreter is dropped: at the end of `sfex run`, or once a request
+// handler has returned.
+#[derive(Default)]
+struct TempFiles {
+    paths: Mutex<Vec<PathBuf>>,
+}
+
+impl TempFiles {
+    /// A new empty file, or directory, in the system temp directory whose
+    /// name starts with `prefix`.
+    fn create(&self, prefix: &str, dir: bool) -> Result<PathBuf, String> {
+        if prefix.contains(['/', '\\']) {
+            return Err(format!(
+                "Temp file prefix '{}' must not contain a path separator",
+                prefix
+            ));
+        }
+        let path = loop {
+            let suffix: String = rand::rng()
+                .sample_iter(&rand::distr::Alphanumeric)
+                .take(12)
+                .map(char::from)
+                .collect();
+            let path = std::env::temp_dir().join(format!("{}{}", prefix, suffix));
+            let created = if dir {
+                fs::create_dir(&path)
+            } else {
+                fs::File::create_new(&path).map(|_| ())
+            };
+            match created {
+                Ok(()) => break path,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+            }
+        };
+        self.paths.lock_recover().push(path.clone());
+        Ok(path)
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in self.paths.lock_recover().drain(..) {
+            let _ = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+        }
+    }
+}
 
 pub fn create_file_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
+    let temp_files = Arc::new(TempFiles::default());
 
     // File.Read("path")
     methods.insert(
@@ -15,6 +74,7 @@ pub fn create_file_module() -> Value {
             }
 
             let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
 
             match fs::read_to_string(&path) {
                 Ok(content) => Ok(Value::String(content)),
@@ -32,6 +92,7 @@ pub fn create_file_module() -> Value {
             }
 
             let path = args[0].to_display_string();
+            permissions::check_write(&path)?;
             let content = args[1].to_display_string();
 
             match fs::write(&path, content) {
@@ -41,6 +102,42 @@ pub fn create_file_module() -> Value {
         }))),
     );
 
+    // File.ReadBytes("path")
+    methods.insert(
+        "ReadBytes".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("File.ReadBytes requires exactly 1 argument (path)".to_string());
+            }
+
+            let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
+
+            match fs::read(&path) {
+                Ok(content) => Ok(Value::Bytes(content.into())),
+                Err(e) => Err(format!("Failed to read file: {}", e)),
+            }
+        }))),
+    );
+
+    // File.WriteBytes("path", bytes)
+    methods.insert(
+        "WriteBytes".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("File.WriteBytes requires 2 arguments (path, bytes)".to_string());
+            }
+
+            let path = args[0].to_display_string();
+            permissions::check_write(&path)?;
+
+            match fs::write(&path, args[1].as_bytes()) {
+                Ok(_) => Ok(Value::Boolean(true)),
+                Err(e) => Err(format!("Failed to write file: {}", e)),
+            }
+        }))),
+    );
+
     // File.Exists("path")
     methods.insert(
         "Exists".to_string(),
@@ -49,6 +146,7 @@ pub fn create_file_module() -> Value {
                 return Err("File.Exists requires 1 argument".to_string());
             }
             let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
             Ok(Value::Boolean(std::path::Path::new(&path).exists()))
         }))),
     );
@@ -64,6 +162,7 @@ pub fn create_file_module() -> Value {
             }
 
             let directory = args[0].to_display_string();
+            permissions::check_read(&directory)?;
             let pattern = if args.len() == 2 {
                 Some(args[1].to_display_string())
             } else {
@@ -134,9 +233,10 @@ pub fn create_file_module() -> Value {
             }
 
             let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
             let start_line = match &args[1] {
-                Value::Number(n) => {
-                    let val = n.to_string().parse::<usize>().unwrap_or(1);
+                Value::Number(_) | Value::Integer(_) => {
+                    let val = args[1].to_display_string().parse::<usize>().unwrap_or(1);
                     if val < 1 {
                         return Err("start_line must be >= 1 (1-based indexing)".to_string());
                     }
@@ -147,7 +247,9 @@ pub fn create_file_module() -> Value {
                 }
             };
             let count = match &args[2] {
-                Value::Number(n) => n.to_string().parse::<usize>().unwrap_or(1000),
+                Value::Number(_) | Value::Integer(_) => {
+                    args[2].to_display_string().parse::<usize>().unwrap_or(1000)
+                }
                 _ => {
                     return Err("count must be a number".to_string());
                 }
@@ -196,6 +298,7 @@ pub fn create_file_module() -> Value {
             }
 
             let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
 
             use std::io::{BufRead, BufReader};
 
@@ -221,6 +324,7 @@ pub fn create_file_module() -> Value {
             }
 
             let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
 
             use std::io::{BufRead, BufReader};
             use std::sync::{Arc, Mutex};
@@ -234,7 +338,7 @@ pub fn create_file_module() -> Value {
             // Create generator function that reads next line
             let reader_clone = reader.clone();
             let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
-                let mut reader_lock = reader_clone.lock().unwrap();
+                let mut reader_lock = reader_clone.lock_recover();
                 match reader_lock.next() {
                     Some(Ok(line)) => Ok(Value::Option(Box::new(Some(Value::String(line))))),
                     Some(Err(e)) => Err(format!("Failed to read line: {}", e)),
@@ -248,5 +352,46 @@ pub fn create_file_module() -> Value {
         }))),
     );
 
+    // File.TempFile(prefix?), File.TempDir(prefix?) -> the path of a new
+    // empty file or directory, removed when the script ends
+    for (name, dir) in [("TempFile", false), ("TempDir", true)] {
+        let temp_files = temp_files.clone();
+        methods.insert(
+            name.to_string(),
+            Value::NativeFunction(Arc::new(Box::new(move |args| {
+                if args.len() > 1 {
+                    return Err(format!(
+                        "File.{} requires 0 or 1 arguments (optional prefix)",
+                        name
+                    ));
+                }
+                let prefix = args
+                    .first()
+                    .map_or("sfex-".to_string(), |prefix| prefix.to_display_string());
+                permissions::check_write(std::env::temp_dir())?;
+                let path = temp_files.create(&prefix, dir)?;
+                Ok(Value::String(path.to_string_lossy().to_string()))
+            }))),
+        );
+    }
+
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_files_removed_on_drop() {
+        let temp_files = TempFiles::default();
+        let file = temp_files.create("sfex-test-", false).unwrap();
+        let dir = temp_files.create("sfex-test-", true).unwrap();
+        fs::write(dir.join("inner.txt"), "x").unwrap();
+        assert!(file.is_file() && dir.is_dir());
+        assert!(temp_files.create("../escape-", false).is_err());
+
+        drop(temp_files);
+        assert!(!file.exists() && !dir.exists());
+    }
+}
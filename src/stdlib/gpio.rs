@@ -0,0 +1,206 @@
+use crate::runtime::lock::MutexExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+// Linux sysfs GPIO interface (Raspberry Pi OS and most SBC distributions)
+const GPIO_ROOT: &str = "/sys/class/gpio";
+
+pub fn create_gpio_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // GPIO.Available() -> True when the sysfs GPIO interface exists
+    methods.insert(
+        "Available".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|_args| {
+            Ok(Value::Boolean(Path::new(GPIO_ROOT).join("export").exists()))
+        }))),
+    );
+
+    // GPIO.Pin(17, "out") -> Pin object using BCM numbering
+    methods.insert(
+        "Pin".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "GPIO.Pin requires 1-2 arguments (pin_number, optional direction \"in\"/\"out\")"
+                        .to_string(),
+                );
+            }
+
+            let pin = match &args[0] {
+                Value::Number(n) => n.to_u32(),
+                Value::Integer(i) => i.to_u32(),
+                Value::FastNumber(f) if *f >= 0.0 => Some(*f as u32),
+                _ => None,
+            }
+            .ok_or("GPIO pin number must be a non-negative integer")?;
+
+            let direction = match args.get(1) {
+                Some(value) => value.to_display_string().to_lowercase(),
+                None => "in".to_string(),
+            };
+            if direction != "in" && direction != "out" {
+                return Err(format!(
+                    "GPIO direction must be \"in\" or \"out\", got \"{}\"",
+                    direction
+                ));
+            }
+
+            permissions::check_write(GPIO_ROOT)?;
+            let gpio = export_pin(pin + chip_base())?;
+            write_attr(&gpio, "direction", &direction)?;
+            Ok(create_pin_object(pin, gpio, direction))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+// Newer Raspberry Pi kernels register the header pins on a chip whose base is
+// not 0 (e.g. 512), so BCM pin numbers are offset by that chip's base.
+fn chip_base() -> u32 {
+    let Ok(entries) = fs::read_dir(GPIO_ROOT) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("gpiochip"))
+        .filter_map(|entry| {
+            let label = fs::read_to_string(entry.path().join("label")).ok()?;
+            let label = label.trim();
+            if !(label.starts_with("pinctrl-bcm") || label.starts_with("pinctrl-rp1")) {
+                return None;
+            }
+            fs::read_to_string(entry.path().join("base"))
+                .ok()?
+                .trim()
+                .parse::<u32>()
+                .ok()
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+fn export_pin(gpio: u32) -> Result<PathBuf, String> {
+    let path = Path::new(GPIO_ROOT).join(format!("gpio{}", gpio));
+    if !path.exists() {
+        fs::write(Path::new(GPIO_ROOT).join("export"), gpio.to_string())
+            .map_err(|e| format!("Failed to export GPIO {}: {}", gpio, e))?;
+
+        // udev may take a moment to make the new attributes writable
+        for _ in 0..20 {
+            if fs::metadata(path.join("direction"))
+                .map(|m| !m.permissions().readonly())
+                .unwrap_or(false)
+            {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    Ok(path)
+}
+
+fn write_attr(gpio: &Path, attr: &str, value: &str) -> Result<(), String> {
+    fs::write(gpio.join(attr), value).map_err(|e| format!("Failed to write GPIO {}: {}", attr, e))
+}
+
+fn read_level(gpio: &Path) -> Result<bool, String> {
+    let value = fs::read_to_string(gpio.join("value"))
+        .map_err(|e| format!("Failed to read GPIO value: {}", e))?;
+    Ok(value.trim() == "1")
+}
+
+fn level_from_value(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(b) => Ok(*b),
+        Value::Number(n) => Ok(n.to_i64().unwrap_or(0) != 0),
+        Value::Integer(i) => Ok(i.to_i64().unwrap_or(0) != 0),
+        Value::FastNumber(f) => Ok(*f != 0.0),
+        Value::String(s) => match s.to_lowercase().as_str() {
+            "high" | "1" | "on" => Ok(true),
+            "low" | "0" | "off" => Ok(false),
+            _ => Err(format!("Invalid GPIO level \"{}\"", s)),
+        },
+        _ => Err("GPIO level must be a Boolean, 0/1, or \"high\"/\"low\"".to_string()),
+    }
+}
+
+fn create_pin_object(pin: u32, gpio: PathBuf, direction: String) -> Value {
+    let gpio = Arc::new(Mutex::new(Some(gpio)));
+    let mut methods = IndexMap::new();
+
+    methods.insert(
+        "Number".to_string(),
+        Value::Number(bigdecimal::BigDecimal::from(pin)),
+    );
+    methods.insert("Direction".to_string(), Value::String(direction));
+
+    // Pin.Read() -> True when the pin is high
+    let gpio_read = gpio.clone();
+    methods.insert(
+        "Read".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let guard = gpio_read.lock_recover();
+            let path = guard.as_ref().ok_or("GPIO pin is closed")?;
+            Ok(Value::Boolean(read_level(path)?))
+        }))),
+    );
+
+    // Pin.Write(True) / Pin.Write(0) / Pin.Write("high")
+    let gpio_write = gpio.clone();
+    methods.insert(
+        "Write".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Pin.Write requires 1 argument (level)".to_string());
+            }
+            let level = level_from_value(&args[0])?;
+            let guard = gpio_write.lock_recover();
+            let path = guard.as_ref().ok_or("GPIO pin is closed")?;
+            write_attr(path, "value", if level { "1" } else { "0" })?;
+            Ok(Value::Boolean(level))
+        }))),
+    );
+
+    // Pin.Toggle() -> new level
+    let gpio_toggle = gpio.clone();
+    methods.insert(
+        "Toggle".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let guard = gpio_toggle.lock_recover();
+            let path = guard.as_ref().ok_or("GPIO pin is closed")?;
+            let level = !read_level(path)?;
+            write_attr(path, "value", if level { "1" } else { "0" })?;
+            Ok(Value::Boolean(level))
+        }))),
+    );
+
+    // Pin.Close() -> unexports the pin
+    let gpio_close = gpio.clone();
+    methods.insert(
+        "Close".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let path = gpio_close.lock_recover().take();
+            if let Some(number) = path
+                .as_ref()
+                .and_then(|path| path.file_name())
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("gpio"))
+            {
+                fs::write(Path::new(GPIO_ROOT).join("unexport"), number)
+                    .map_err(|e| format!("Failed to unexport GPIO {}: {}", number, e))?;
+            }
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
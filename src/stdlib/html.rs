@@ -1,6 +1,6 @@
 use crate::runtime::value::Value;
+use indexmap::IndexMap;
 use scraper::{Html, Selector};
-use std::collections::HashMap;
 use std::sync::Arc;
 
 pub fn parse_html(html_content: &str) -> Result<Value, String> {
@@ -8,7 +8,7 @@ pub fn parse_html(html_content: &str) -> Result<Value, String> {
 }
 
 pub fn create_html_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Parse".to_string(),
@@ -29,7 +29,7 @@ fn create_page_object(html: String) -> Value {
     let _document = Html::parse_document(&html);
 
     let doc_string = html.clone();
-    let mut page_methods = HashMap::new();
+    let mut page_methods = IndexMap::new();
 
     page_methods.insert(
         "SelectText".to_string(),
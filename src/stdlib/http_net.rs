@@ -1,14 +1,91 @@
+use crate::runtime::deadline;
 use crate::runtime::interpreter::Interpreter;
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions::{self, Kind};
 use crate::runtime::value::Value;
+use crate::stdlib::json::{convert_json_to_object, convert_object_to_json};
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
 use reqwest::Client;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_RETRY_STATUSES: [u16; 4] = [429, 502, 503, 504];
+const DEFAULT_BACKOFF_SECS: f64 = 0.5;
+
+/// reqwest keeps one connection pool per client, so every HTTP call made by an
+/// interpreter shares a client per redirect policy instead of reconnecting.
+#[derive(Clone, Default)]
+struct ClientPool {
+    clients: Arc<Mutex<HashMap<Option<usize>, Client>>>,
+}
+
+impl ClientPool {
+    fn client(&self, max_redirects: Option<usize>) -> Result<Client, String> {
+        // A script that may only reach some hosts can't be redirected to
+        // others, so its client checks each redirect with its permissions
+        let permissions = permissions::snapshot();
+        if permissions.restricts(Kind::Net) {
+            let limit = max_redirects.unwrap_or(10);
+            return Client::builder()
+                .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                    if limit == 0 {
+                        attempt.stop()
+                    } else if attempt.previous().len() > limit {
+                        attempt.error("too many redirects")
+                    } else if let Err(e) = permissions.check_url(attempt.url().as_str()) {
+                        attempt.error(e)
+                    } else {
+                        attempt.follow()
+                    }
+                }))
+                .build()
+                .map_err(|e| format!("HTTP client error: {}", e));
+        }
+
+        let mut clients = self.clients.lock_recover();
+        if let Some(client) = clients.get(&max_redirects) {
+            return Ok(client.clone());
+        }
+
+        let mut builder = Client::builder();
+        if let Some(max) = max_redirects {
+            builder = builder.redirect(match max {
+                0 => reqwest::redirect::Policy::none(),
+                n => reqwest::redirect::Policy::limited(n),
+            });
+        }
+        let client = builder
+            .build()
+            .map_err(|e| format!("HTTP client error: {}", e))?;
+        clients.insert(max_redirects, client.clone());
+        Ok(client)
+    }
+}
 
 pub fn create_http_module(interpreter: &Interpreter) -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
     let runtime = interpreter.runtime.clone();
+    let pool = ClientPool::default();
+
+    // HTTP.Request({ Url: "...", Method: "POST", Body: {...}, Timeout: 5, Retries: 3 })
+    let runtime_request = runtime.clone();
+    let pool_request = pool.clone();
+    methods.insert(
+        "Request".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("HTTP.Request requires 1 argument (options map)".to_string());
+            }
+            let options = RequestOptions::from_value(&args[0])?;
+            permissions::check_url(&options.url)?;
+            send_request(&options, &pool_request, &runtime_request)
+        }))),
+    );
 
     let runtime_get = runtime.clone();
+    let pool_get = pool.clone();
     methods.insert(
         "Get".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -19,26 +96,28 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_get.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_get.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.get(&url);
 
                 if args.len() == 2 {
                     if let Value::Map(headers_map) = &args[1] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let response_obj = runtime_get.block_on(create_response_object(response));
+                    let response_obj =
+                        deadline::block_on(&runtime_get, create_response_object(response))?;
                     Ok(response_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -47,6 +126,7 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
     );
 
     let runtime_post = runtime.clone();
+    let pool_post = pool.clone();
     methods.insert(
         "Post".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -58,10 +138,11 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_post.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_post.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.post(&url);
 
                 if args.len() >= 2 {
@@ -72,18 +153,19 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
 
                 if args.len() == 3 {
                     if let Value::Map(headers_map) = &args[2] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let response_obj = runtime_post.block_on(create_response_object(response));
+                    let response_obj =
+                        deadline::block_on(&runtime_post, create_response_object(response))?;
                     Ok(response_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -92,6 +174,7 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
     );
 
     let runtime_put = runtime.clone();
+    let pool_put = pool.clone();
     methods.insert(
         "Put".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -103,10 +186,11 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_put.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_put.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.put(&url);
 
                 if args.len() >= 2 {
@@ -117,18 +201,19 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
 
                 if args.len() == 3 {
                     if let Value::Map(headers_map) = &args[2] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let response_obj = runtime_put.block_on(create_response_object(response));
+                    let response_obj =
+                        deadline::block_on(&runtime_put, create_response_object(response))?;
                     Ok(response_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -137,6 +222,7 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
     );
 
     let runtime_delete = runtime.clone();
+    let pool_delete = pool.clone();
     methods.insert(
         "Delete".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -147,26 +233,28 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_delete.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_delete.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.delete(&url);
 
                 if args.len() == 2 {
                     if let Value::Map(headers_map) = &args[1] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let response_obj = runtime_delete.block_on(create_response_object(response));
+                    let response_obj =
+                        deadline::block_on(&runtime_delete, create_response_object(response))?;
                     Ok(response_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -175,6 +263,7 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
     );
 
     let runtime_patch = runtime.clone();
+    let pool_patch = pool.clone();
     methods.insert(
         "Patch".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -186,10 +275,11 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_patch.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_patch.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.patch(&url);
 
                 if args.len() >= 2 {
@@ -200,18 +290,19 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
 
                 if args.len() == 3 {
                     if let Value::Map(headers_map) = &args[2] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let response_obj = runtime_patch.block_on(create_response_object(response));
+                    let response_obj =
+                        deadline::block_on(&runtime_patch, create_response_object(response))?;
                     Ok(response_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -220,6 +311,7 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
     );
 
     let runtime_getstream = runtime.clone();
+    let pool_getstream = pool.clone();
     methods.insert(
         "GetStream".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -230,27 +322,30 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_getstream.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_getstream.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.get(&url);
 
                 if args.len() == 2 {
                     if let Value::Map(headers_map) = &args[1] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let stream_obj = runtime_getstream
-                        .block_on(create_stream_object(response, runtime_getstream.clone()));
+                    let stream_obj = deadline::block_on(
+                        &runtime_getstream,
+                        create_stream_object(response, runtime_getstream.clone()),
+                    )?;
                     Ok(stream_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -259,6 +354,7 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
     );
 
     let runtime_poststream = runtime.clone();
+    let pool_poststream = pool.clone();
     methods.insert(
         "PostStream".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -270,10 +366,11 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_poststream.clone();
 
-            let result = runtime.block_on(async {
-                let client = Client::new();
+            let client = pool_poststream.client(None)?;
+            let result = deadline::block_on(&runtime, async {
                 let mut request = client.post(&url);
 
                 if args.len() >= 2 {
@@ -284,19 +381,21 @@ pub fn create_http_module(interpreter: &Interpreter) -> Value {
 
                 if args.len() == 3 {
                     if let Value::Map(headers_map) = &args[2] {
-                        for (key, value) in headers_map.read().expect("lock poisoned").iter() {
+                        for (key, value) in headers_map.read_recover().iter() {
                             request = request.header(key, value.to_display_string());
                         }
                     }
                 }
 
                 request.send().await
-            });
+            })?;
 
             match result {
                 Ok(response) => {
-                    let stream_obj = runtime_poststream
-                        .block_on(create_stream_object(response, runtime_poststream.clone()));
+                    let stream_obj = deadline::block_on(
+                        &runtime_poststream,
+                        create_stream_object(response, runtime_poststream.clone()),
+                    )?;
                     Ok(stream_obj)
                 }
                 Err(e) => Err(format!("HTTP Error: {}", e)),
@@ -311,7 +410,7 @@ async fn create_stream_object(
     response: reqwest::Response,
     _runtime: Arc<tokio::runtime::Runtime>,
 ) -> Value {
-    let mut stream_map = HashMap::new();
+    let mut stream_map = IndexMap::new();
 
     let status = response.status().as_u16();
     stream_map.insert(
@@ -337,7 +436,7 @@ async fn create_stream_object(
         );
     }
 
-    let mut headers_map = HashMap::new();
+    let mut headers_map = IndexMap::new();
     for (key, value) in response.headers() {
         if let Ok(v) = value.to_str() {
             headers_map.insert(key.to_string(), Value::String(v.to_string()));
@@ -363,13 +462,14 @@ async fn create_stream_object(
 
             let size = match &args[0] {
                 Value::Number(n) => n.to_string().parse::<usize>().unwrap_or(4096),
+                Value::Integer(i) => i.to_usize().unwrap_or(4096),
                 _ => {
                     return Err("size must be a number".to_string());
                 }
             };
 
-            let body = body_ref.read().expect("lock poisoned");
-            let mut pos = pos_ref.write().expect("lock poisoned");
+            let body = body_ref.read_recover();
+            let mut pos = pos_ref.write_recover();
 
             if *pos >= body.len() {
                 return Ok(Value::String(String::new()));
@@ -393,35 +493,13 @@ async fn create_stream_object(
 }
 
 async fn create_response_object(response: reqwest::Response) -> Value {
-    let mut response_map = HashMap::new();
-
-    let status = response.status().as_u16();
-    response_map.insert(
-        "Status".to_string(),
-        Value::from_number_string(&status.to_string()).unwrap_or(Value::default_number()),
-    );
-
-    response_map.insert(
-        "StatusText".to_string(),
-        Value::String(
-            response
-                .status()
-                .canonical_reason()
-                .unwrap_or("Unknown")
-                .to_string(),
-        ),
-    );
-
-    let mut headers_map = HashMap::new();
-    for (key, value) in response.headers() {
-        if let Ok(v) = value.to_str() {
-            headers_map.insert(key.to_string(), Value::String(v.to_string()));
-        }
-    }
-    response_map.insert(
-        "Headers".to_string(),
-        Value::Map(Arc::new(std::sync::RwLock::new(headers_map))),
-    );
+    let mut response_map = response_metadata(&response);
+    let is_json = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("json"))
+        .unwrap_or(false);
 
     const WARN_SIZE: u64 = 100 * 1024 * 1024;
     if let Some(content_length) = response.content_length() {
@@ -438,6 +516,9 @@ async fn create_response_object(response: reqwest::Response) -> Value {
 
     match response.text().await {
         Ok(body) => {
+            if is_json && let Ok(json) = serde_json::from_str(&body) {
+                response_map.insert("Json".to_string(), convert_json_to_object(json));
+            }
             response_map.insert("Body".to_string(), Value::String(body));
         }
         Err(e) => {
@@ -450,3 +531,261 @@ async fn create_response_object(response: reqwest::Response) -> Value {
 
     Value::Map(Arc::new(std::sync::RwLock::new(response_map)))
 }
+
+struct RequestOptions {
+    url: String,
+    method: reqwest::Method,
+    headers: Vec<(String, String)>,
+    query: Vec<(String, String)>,
+    body: Option<RequestBody>,
+    timeout: Option<Duration>,
+    max_redirects: Option<usize>,
+    retries: u32,
+    backoff: Duration,
+    retry_on: Vec<u16>,
+    stream: bool,
+}
+
+enum RequestBody {
+    Text(String),
+    Json(serde_json::Value),
+}
+
+impl RequestOptions {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        let Value::Map(map) = value else {
+            return Err("HTTP.Request options must be a Map".to_string());
+        };
+        let map = map.read_recover();
+
+        let url = map
+            .get("Url")
+            .map(|v| v.to_display_string())
+            .ok_or("HTTP.Request options require a Url")?;
+        let method = match map.get("Method") {
+            Some(m) => reqwest::Method::from_bytes(m.to_display_string().to_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid HTTP method '{}'", m.to_display_string()))?,
+            None => reqwest::Method::GET,
+        };
+
+        let headers = pairs_from_map(map.get("Headers"));
+        let query = pairs_from_map(map.get("Query"));
+
+        // Maps and Lists are sent as JSON; `Json` forces JSON for any value.
+        let body = match (map.get("Json"), map.get("Body")) {
+            (Some(json), _) => Some(RequestBody::Json(convert_object_to_json(json))),
+            (None, Some(body @ (Value::Map(_) | Value::List(_)))) => {
+                Some(RequestBody::Json(convert_object_to_json(body)))
+            }
+            (None, Some(body)) => Some(RequestBody::Text(body.to_display_string())),
+            (None, None) => None,
+        };
+
+        let timeout = map
+            .get("Timeout")
+            .map(|v| seconds_from_value(v, "Timeout"))
+            .transpose()?;
+        let max_redirects = match map.get("Redirects") {
+            Some(Value::Boolean(false)) => Some(0),
+            Some(Value::Boolean(true)) | None => None,
+            Some(v) => Some(count_from_value(v, "Redirects")? as usize),
+        };
+        let retries = match map.get("Retries") {
+            Some(v) => count_from_value(v, "Retries")? as u32,
+            None => 0,
+        };
+        let backoff = match map.get("Backoff") {
+            Some(v) => seconds_from_value(v, "Backoff")?,
+            None => Duration::from_secs_f64(DEFAULT_BACKOFF_SECS),
+        };
+        let retry_on = match map.get("RetryOn") {
+            Some(Value::List(list)) => list
+                .read_recover()
+                .iter()
+                .map(|v| count_from_value(v, "RetryOn").map(|n| n as u16))
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(_) => {
+                return Err("HTTP.Request RetryOn must be a List of status codes".to_string());
+            }
+            None => DEFAULT_RETRY_STATUSES.to_vec(),
+        };
+        let stream = map.get("Stream").map(|v| v.is_truthy()).unwrap_or(false);
+
+        Ok(Self {
+            url,
+            method,
+            headers,
+            query,
+            body,
+            timeout,
+            max_redirects,
+            retries,
+            backoff,
+            retry_on,
+            stream,
+        })
+    }
+
+    fn build(&self, client: &Client) -> reqwest::RequestBuilder {
+        let mut request = client.request(self.method.clone(), &self.url);
+        if !self.query.is_empty() {
+            request = request.query(&self.query);
+        }
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        match &self.body {
+            Some(RequestBody::Json(json)) => request.json(json),
+            Some(RequestBody::Text(text)) => request.body(text.clone()),
+            None => request,
+        }
+    }
+}
+
+fn pairs_from_map(value: Option<&Value>) -> Vec<(String, String)> {
+    match value {
+        Some(Value::Map(map)) => map
+            .read_recover()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.to_display_string()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn seconds_from_value(value: &Value, name: &str) -> Result<Duration, String> {
+    let seconds = match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+    .filter(|s| s.is_finite() && *s >= 0.0)
+    .ok_or_else(|| format!("HTTP.Request {} must be a number of seconds", name))?;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn count_from_value(value: &Value, name: &str) -> Result<u64, String> {
+    match value {
+        Value::Number(n) => n.to_u64(),
+        Value::Integer(i) => i.to_u64(),
+        Value::FastNumber(f) if *f >= 0.0 => Some(*f as u64),
+        _ => None,
+    }
+    .ok_or_else(|| format!("HTTP.Request {} must be a non-negative integer", name))
+}
+
+/// Send with retries: connection errors, timeouts and `RetryOn` statuses are
+/// retried after `Backoff`, doubling each attempt.
+fn send_request(
+    options: &RequestOptions,
+    pool: &ClientPool,
+    runtime: &Arc<tokio::runtime::Runtime>,
+) -> Result<Value, String> {
+    let client = pool.client(options.max_redirects)?;
+    let mut attempt = 0u32;
+
+    loop {
+        attempt += 1;
+        let result = deadline::block_on(runtime, async { options.build(&client).send().await })?;
+        let retryable = match &result {
+            Ok(response) => options.retry_on.contains(&response.status().as_u16()),
+            Err(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+        };
+
+        if retryable && attempt <= options.retries {
+            let delay = options.backoff.mul_f64(2f64.powi(attempt as i32 - 1));
+            std::thread::sleep(deadline::limit(Some(delay)).unwrap_or(delay));
+            deadline::check()?;
+            continue;
+        }
+
+        let response = result.map_err(|e| {
+            if e.is_timeout() {
+                format!("HTTP Error: request to {} timed out", options.url)
+            } else {
+                format!("HTTP Error: {}", e)
+            }
+        })?;
+
+        let value = if options.stream {
+            create_download_stream(response, runtime.clone())
+        } else {
+            deadline::block_on(runtime, create_response_object(response))?
+        };
+        if let Value::Map(map) = &value {
+            map.write_recover().insert(
+                "Attempts".to_string(),
+                Value::Number(bigdecimal::BigDecimal::from(attempt)),
+            );
+        }
+        return Ok(value);
+    }
+}
+
+// Same shape as a normal response, but Body is a Stream of text chunks read
+// from the network as the script pulls them.
+fn create_download_stream(
+    response: reqwest::Response,
+    runtime: Arc<tokio::runtime::Runtime>,
+) -> Value {
+    let mut response_map = response_metadata(&response);
+    let response = Arc::new(Mutex::new(Some(response)));
+
+    let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+        let mut guard = response.lock_recover();
+        let Some(active) = guard.as_mut() else {
+            return Ok(Value::Option(Box::new(None)));
+        };
+        match deadline::block_on(&runtime, active.chunk())? {
+            Ok(Some(chunk)) => Ok(Value::Option(Box::new(Some(Value::String(
+                String::from_utf8_lossy(&chunk).into_owned(),
+            ))))),
+            Ok(None) => {
+                guard.take();
+                Ok(Value::Option(Box::new(None)))
+            }
+            Err(e) => {
+                guard.take();
+                Err(format!("HTTP download error: {}", e))
+            }
+        }
+    })));
+
+    response_map.insert(
+        "Body".to_string(),
+        crate::stdlib::stream::create_stream_object(vec![], Some(generator)),
+    );
+    Value::Map(Arc::new(std::sync::RwLock::new(response_map)))
+}
+
+fn response_metadata(response: &reqwest::Response) -> IndexMap<String, Value> {
+    let mut response_map = IndexMap::new();
+    let status = response.status();
+
+    response_map.insert(
+        "Status".to_string(),
+        Value::Number(bigdecimal::BigDecimal::from(status.as_u16())),
+    );
+    response_map.insert(
+        "StatusText".to_string(),
+        Value::String(status.canonical_reason().unwrap_or("Unknown").to_string()),
+    );
+    response_map.insert("Ok".to_string(), Value::Boolean(status.is_success()));
+    response_map.insert("Url".to_string(), Value::String(response.url().to_string()));
+
+    let mut headers_map = IndexMap::new();
+    for (key, value) in response.headers() {
+        if let Ok(v) = value.to_str() {
+            headers_map.insert(key.to_string(), Value::String(v.to_string()));
+        }
+    }
+    response_map.insert(
+        "Headers".to_string(),
+        Value::Map(Arc::new(std::sync::RwLock::new(headers_map))),
+    );
+    response_map
+}
@@ -0,0 +1,1085 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use crate::stdlib::{json, math, time, toml as toml_module};
+use chrono::{Datelike, Timelike};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+// Message catalogs, one per locale, loaded from TOML or JSON files named by
+// their locale (en.toml, pt-BR.json). Nested tables become dotted keys, and
+// a table of plural forms (one, few, other, ...) becomes one message that
+// picks its form by the Count it is given. A key missing from a locale is
+// looked up in its parent (pt-BR, then pt) and then the default locale, and
+// a key missing everywhere comes back as itself, so gaps show up on the page
+// instead of failing the request.
+
+/// CLDR plural categories, the keys a plural message may have
+const PLURAL_FORMS: [&str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
+enum Message {
+    Text(String),
+    Plural(HashMap<String, String>),
+}
+
+struct Catalog {
+    locales: IndexMap<String, HashMap<String, Message>>,
+    default: String,
+}
+
+/// "pt_br" and "PT-BR" are both "pt-BR"
+fn canonical(tag: &str) -> String {
+    tag.split(['-', '_'])
+        .enumerate()
+        .map(|(i, part)| match (i, part.len()) {
+            (0, _) => part.to_ascii_lowercase(),
+            (_, 2) => part.to_ascii_uppercase(),
+            (_, 4) => {
+                let lower = part.to_ascii_lowercase();
+                lower[..1].to_ascii_uppercase() + &lower[1..]
+            }
+            _ => part.to_ascii_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn language(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or_default()
+}
+
+/// Flatten a parsed catalog file into dotted keys
+fn flatten(prefix: &str, value: &Value, out: &mut HashMap<String, Message>) -> Result<(), String> {
+    let key = |name: &str| match prefix {
+        "" => name.to_string(),
+        _ => format!("{}.{}", prefix, name),
+    };
+    match value {
+        Value::Map(map) => {
+            let map = map.read_recover();
+            let plural = !map.is_empty()
+                && !prefix.is_empty()
+                && map.keys().all(|name| PLURAL_FORMS.contains(&name.as_str()));
+            if plural {
+                let forms = map
+                    .iter()
+                    .map(|(form, text)| (form.clone(), text.to_display_string()))
+                    .collect();
+                out.insert(prefix.to_string(), Message::Plural(forms));
+            } else {
+                for (name, value) in map.iter() {
+                    flatten(&key(name), value, out)?;
+                }
+            }
+        }
+        Value::List(_) => return Err(format!("'{}' is a List, not a message", prefix)),
+        other => {
+            out.insert(prefix.to_string(), Message::Text(other.to_display_string()));
+        }
+    }
+    Ok(())
+}
+
+fn read_catalog(path: &Path) -> Result<Value, String> {
+    permissions::check_read(path)?;
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let parsed = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => ::toml::from_str::<::toml::Table>(&text)
+            .map(|table| toml_module::convert_toml_to_object(::toml::Value::Table(table)))
+            .map_err(|e| e.to_string()),
+        Some("json") => serde_json::from_str(&text)
+            .map(json::convert_json_to_object)
+            .map_err(|e| e.to_string()),
+        _ => {
+            return Err(format!(
+                "{} is not a .toml or .json catalog",
+                path.display()
+            ));
+        }
+    };
+    parsed.map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+impl Catalog {
+    fn add(&mut self, locale: &str, messages: &Value) -> Result<(), String> {
+        let Value::Map(_) = messages else {
+            return Err(format!(
+                "The messages for '{}' must be a Map, not {}",
+                locale,
+                messages.type_name()
+            ));
+        };
+        let mut flat = HashMap::new();
+        flatten("", messages, &mut flat).map_err(|e| format!("{}: {}", locale, e))?;
+        self.locales
+            .entry(canonical(locale))
+            .or_default()
+            .extend(flat);
+        Ok(())
+    }
+
+    /// The locales to look a key up in, most specific first
+    fn chain(&self, locale: &str) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut tag = locale.to_string();
+        loop {
+            chain.push(tag.clone());
+            match tag.rfind('-') {
+                Some(cut) => tag.truncate(cut),
+                None => break,
+            }
+        }
+        chain.push(self.default.clone());
+        chain
+    }
+
+    fn lookup(&self, locale: &str, key: &str) -> Option<(&Message, String)> {
+        self.chain(locale).into_iter().find_map(|tag| {
+            let message = self.locales.get(&tag)?.get(key)?;
+            Some((message, tag))
+        })
+    }
+
+    /// The best loaded locale for an Accept-Language header such as
+    /// "da, en-GB;q=0.8, en;q=0.7"
+    fn negotiate(&self, header: &str) -> String {
+        let mut wanted: Vec<(String, f64)> = header
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (!tag.is_empty() && tag != "*" && quality > 0.0).then(|| (canonical(tag), quality))
+            })
+            .collect();
+        wanted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for (tag, _) in &wanted {
+            if let Some(found) = self
+                .chain(tag)
+                .into_iter()
+                .find(|t| t != &self.default && self.locales.contains_key(t))
+            {
+                return found;
+            }
+            // "en" asked for and only "en-US" loaded
+            if let Some(found) = self.locales.keys().find(|t| language(t) == tag) {
+                return found.clone();
+            }
+            if tag == &self.default || language(tag) == language(&self.default) {
+                return self.default.clone();
+            }
+        }
+        self.default.clone()
+    }
+}
+
+/// CLDR plural category of `count` in `locale`, for the languages whose
+/// rules differ from English's
+fn plural_category(locale: &str, count: &Value) -> Result<&'static str, String> {
+    let text = match count {
+        Value::Integer(_) | Value::Number(_) | Value::FastNumber(_) => count.to_display_string(),
+        other => {
+            return Err(format!(
+                "A plural message needs a number as Count, not {}",
+                other.type_name()
+            ));
+        }
+    };
+    let text = text.trim_start_matches('-');
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let i = whole.parse::<u64>().unwrap_or(u64::MAX);
+    let v = fraction.len();
+    let (i10, i100) = (i % 10, i % 100);
+    Ok(match language(locale) {
+        "ja" | "zh" | "ko" | "th" | "vi" | "id" | "ms" => "other",
+        "fr" | "pt" if i <= 1 => "one",
+        "ru" | "uk" | "be" if v == 0 => match (i10, i100) {
+            (1, i100) if i100 != 11 => "one",
+            (2..=4, i100) if !(12..=14).contains(&i100) => "few",
+            _ => "many",
+        },
+        "pl" if v == 0 => match (i, i10, i100) {
+            (1, _, _) => "one",
+            (_, 2..=4, i100) if !(12..=14).contains(&i100) => "few",
+            _ => "many",
+        },
+        "cs" | "sk" => match (i, v) {
+            (1, 0) => "one",
+            (2..=4, 0) => "few",
+            (_, 0) => "other",
+            _ => "many",
+        },
+        "ar" if v == 0 => match (i, i100) {
+            (0, _) => "zero",
+            (1, _) => "one",
+            (2, _) => "two",
+            (_, 3..=10) => "few",
+            (_, 11..=99) => "many",
+            _ => "other",
+        },
+        "ru" | "uk" | "be" | "pl" | "ar" | "fr" | "pt" => "other",
+        _ if i == 1 && v == 0 => "one",
+        _ => "other",
+    })
+}
+
+/// Replace {Name} with the parameter's value; {{ and }} are literal braces
+fn interpolate(
+    text: &str,
+    params: &IndexMap<String, Value>,
+    number: &dyn Fn(&Value) -> String,
+) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['{', '}']) {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        let Some(end) = tail.find('}').filter(|_| tail.starts_with('{')) else {
+            out.push_str(&tail[..1]);
+            rest = &tail[1..];
+            continue;
+        };
+        let name = tail[1..end].trim();
+        match params.get(name) {
+            Some(value @ (Value::Integer(_) | Value::Number(_) | Value::FastNumber(_))) => {
+                out.push_str(&number(value))
+            }
+            Some(value) => out.push_str(&value.to_display_string()),
+            None => out.push_str(&tail[..=end]),
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// How one language writes dates
+struct DateStyle {
+    short: &'static str,
+    long: &'static str,
+    full: &'static str,
+    months: [&'static str; 12],
+    weekdays: [&'static str; 7],
+    twelve_hour: bool,
+}
+
+const ISO_DATES: DateStyle = DateStyle {
+    short: "{y}-{mm}-{dd}",
+    long: "{y}-{mm}-{dd}",
+    full: "{y}-{mm}-{dd}",
+    months: [""; 12],
+    weekdays: [""; 7],
+    twelve_hour: false,
+};
+
+const ENGLISH_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const ENGLISH_WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+fn date_style(locale: &str) -> DateStyle {
+    match (language(locale), locale) {
+        ("en", "en" | "en-US") => DateStyle {
+            short: "{m}/{d}/{y}",
+            long: "{M} {d}, {y}",
+            full: "{W}, {M} {d}, {y}",
+            months: ENGLISH_MONTHS,
+            weekdays: ENGLISH_WEEKDAYS,
+            twelve_hour: true,
+        },
+        ("en", _) => DateStyle {
+            short: "{dd}/{mm}/{y}",
+            long: "{d} {M} {y}",
+            full: "{W} {d} {M} {y}",
+            months: ENGLISH_MONTHS,
+            weekdays: ENGLISH_WEEKDAYS,
+            twelve_hour: false,
+        },
+        ("de", _) => DateStyle {
+            short: "{dd}.{mm}.{y}",
+            long: "{d}. {M} {y}",
+            full: "{W}, {d}. {M} {y}",
+            months: [
+                "Januar",
+                "Februar",
+                "März",
+                "April",
+                "Mai",
+                "Juni",
+                "Juli",
+                "August",
+                "September",
+                "Oktober",
+                "November",
+                "Dezember",
+            ],
+            weekdays: [
+                "Montag",
+                "Dienstag",
+                "Mittwoch",
+                "Donnerstag",
+                "Freitag",
+                "Samstag",
+                "Sonntag",
+            ],
+            twelve_hour: false,
+        },
+        ("fr", _) => DateStyle {
+            short: "{dd}/{mm}/{y}",
+            long: "{d} {M} {y}",
+            full: "{W} {d} {M} {y}",
+            months: [
+                "janvier",
+                "février",
+                "mars",
+                "avril",
+                "mai",
+                "juin",
+                "juillet",
+                "août",
+                "septembre",
+                "octobre",
+                "novembre",
+                "décembre",
+            ],
+            weekdays: [
+                "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+            ],
+            twelve_hour: false,
+        },
+        ("es", _) => DateStyle {
+            short: "{d}/{m}/{y}",
+            long: "{d} de {M} de {y}",
+            full: "{W}, {d} de {M} de {y}",
+            months: [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ],
+            weekdays: [
+                "lunes",
+                "martes",
+                "miércoles",
+                "jueves",
+                "viernes",
+                "sábado",
+                "domingo",
+            ],
+            twelve_hour: false,
+        },
+        ("ru", _) => DateStyle {
+            short: "{dd}.{mm}.{y}",
+            long: "{d} {M} {y} г.",
+            full: "{W}, {d} {M} {y} г.",
+            // Genitive, as dates need
+            months: [
+                "января",
+                "февраля",
+                "марта",
+                "апреля",
+                "мая",
+                "июня",
+                "июля",
+                "августа",
+                "сентября",
+                "октября",
+                "ноября",
+                "декабря",
+            ],
+            weekdays: [
+                "понедельник",
+                "вторник",
+                "среда",
+                "четверг",
+                "пятница",
+                "суббота",
+                "воскресенье",
+            ],
+            twelve_hour: false,
+        },
+        ("mn", _) => DateStyle {
+            short: "{y}.{mm}.{dd}",
+            long: "{y} оны {M}ын {d}",
+            full: "{y} оны {M}ын {d}, {W} гараг",
+            months: [
+                "нэгдүгээр сар",
+                "хоёрдугаар сар",
+                "гуравдугаар сар",
+                "дөрөвдүгээр сар",
+                "тавдугаар сар",
+                "зургаадугаар сар",
+                "долоодугаар сар",
+                "наймдугаар сар",
+                "есдүгээр сар",
+                "аравдугаар сар",
+                "арван нэгдүгээр сар",
+                "арван хоёрдугаар сар",
+            ],
+            weekdays: [
+                "даваа",
+                "мягмар",
+                "лхагва",
+                "пүрэв",
+                "баасан",
+                "бямба",
+                "ням",
+            ],
+            twelve_hour: false,
+        },
+        ("ja", _) => DateStyle {
+            short: "{y}/{mm}/{dd}",
+            long: "{y}年{m}月{d}日",
+            full: "{y}年{m}月{d}日{W}",
+            months: [""; 12],
+            weekdays: [
+                "月曜日",
+                "火曜日",
+                "水曜日",
+                "木曜日",
+                "金曜日",
+                "土曜日",
+                "日曜日",
+            ],
+            twelve_hour: false,
+        },
+        ("zh", _) => DateStyle {
+            short: "{y}/{m}/{d}",
+            long: "{y}年{m}月{d}日",
+            full: "{y}年{m}月{d}日{W}",
+            months: [""; 12],
+            weekdays: [
+                "星期一",
+                "星期二",
+                "星期三",
+                "星期四",
+                "星期五",
+                "星期六",
+                "星期日",
+            ],
+            twelve_hour: false,
+        },
+        _ => ISO_DATES,
+    }
+}
+
+fn format_date(locale: &str, value: &Value, style: &str) -> Result<String, String> {
+    let date = time::local_datetime(value)?;
+    let styles = date_style(locale);
+    let pattern = match style {
+        "Short" => styles.short,
+        "Long" => styles.long,
+        "Full" => styles.full,
+        _ => {
+            return Err(format!(
+                "Unknown date style '{}', expected Short, Long or Full",
+                style
+            ));
+        }
+    };
+    let month = date.month0() as usize;
+    let weekday = date.weekday().num_days_from_monday() as usize;
+    Ok(pattern
+        .replace("{y}", &date.year().to_string())
+        .replace("{mm}", &format!("{:02}", date.month()))
+        .replace("{m}", &date.month().to_string())
+        .replace("{dd}", &format!("{:02}", date.day()))
+        .replace("{d}", &date.day().to_string())
+        .replace("{M}", styles.months[month])
+        .replace("{W}", styles.weekdays[weekday]))
+}
+
+fn format_time(locale: &str, value: &Value) -> Result<String, String> {
+    let time = time::local_datetime(value)?;
+    Ok(if date_style(locale).twelve_hour {
+        let (pm, hour) = time.hour12();
+        format!(
+            "{}:{:02} {}",
+            hour,
+            time.minute(),
+            if pm { "PM" } else { "AM" }
+        )
+    } else {
+        format!("{:02}:{:02}", time.hour(), time.minute())
+    })
+}
+
+/// Numbers in messages and Number(): grouped, up to three decimals, with the
+/// locale's separators (or English ones for a locale Math.Format doesn't know)
+fn format_number(locale: &str, value: &Value, pattern: &str) -> Result<String, String> {
+    let separators = math::separators(locale).unwrap_or((",", "."));
+    math::format_number(value, pattern, separators)
+}
+
+fn translate(
+    catalog: &Catalog,
+    locale: &str,
+    key: &str,
+    params: Option<&Value>,
+) -> Result<String, String> {
+    let params = match params {
+        Some(Value::Map(map)) => map.read_recover().clone(),
+        Some(other) => {
+            return Err(format!(
+                "The parameters of '{}' must be a Map, not {}",
+                key,
+                other.type_name()
+            ));
+        }
+        None => IndexMap::new(),
+    };
+    let Some((message, found_in)) = catalog.lookup(locale, key) else {
+        return Ok(key.to_string());
+    };
+    let text = match message {
+        Message::Text(text) => text,
+        Message::Plural(forms) => {
+            let count = params
+                .get("Count")
+                .ok_or_else(|| format!("'{}' has plural forms, so it needs a Count", key))?;
+            let is_zero = count.as_f64() == Some(0.0);
+            let category = plural_category(&found_in, count)?;
+            forms
+                .get("zero")
+                .filter(|_| is_zero)
+                .or_else(|| forms.get(category))
+                .or_else(|| forms.get("other"))
+                .ok_or_else(|| {
+                    format!(
+                        "'{}' in {} has no '{}' or 'other' form",
+                        key, found_in, category
+                    )
+                })?
+        }
+    };
+    let number = |value: &Value| {
+        format_number(&found_in, value, "#,##0.###").unwrap_or_else(|_| value.to_display_string())
+    };
+    Ok(interpolate(text, &params, &number))
+}
+
+fn string_arg<'a>(value: &'a Value, what: &str) -> Result<&'a str, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(format!(
+            "{} must be a String, not {}",
+            what,
+            other.type_name()
+        )),
+    }
+}
+
+/// The Accept-Language header of a web handler's Request
+fn accept_language(request: &Value) -> Option<String> {
+    let Value::Map(request) = request else {
+        return None;
+    };
+    let headers = request.read_recover().get("Headers").cloned()?;
+    let Value::Map(headers) = headers else {
+        return None;
+    };
+    let headers = headers.read_recover();
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("accept-language"))
+        .map(|(_, value)| value.to_display_string())
+}
+
+fn native(function: impl Fn(Vec<Value>) -> Result<Value, String> + Send + Sync + 'static) -> Value {
+    Value::NativeFunction(Arc::new(Box::new(function)))
+}
+
+/// A catalog bound to one locale
+fn translator_value(catalog: Arc<RwLock<Catalog>>, locale: String) -> Value {
+    let mut methods = IndexMap::new();
+    methods.insert("Locale".to_string(), Value::String(locale.clone()));
+
+    // Tr.T("greeting", { Name: "Ann" })
+    let (catalog_t, locale_t) = (catalog.clone(), locale.clone());
+    methods.insert(
+        "T".to_string(),
+        native(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("Translator.T requires 1-2 arguments (key, params)".to_string());
+            }
+            let key = string_arg(&args[0], "Translator.T's key")?;
+            translate(&catalog_t.read_recover(), &locale_t, key, args.get(1)).map(Value::String)
+        }),
+    );
+
+    let locale_number = locale.clone();
+    methods.insert(
+        "Number".to_string(),
+        native(move |args| {
+            let pattern = match args.as_slice() {
+                [_] => "#,##0.###",
+                [_, pattern] => string_arg(pattern, "Translator.Number's pattern")?,
+                _ => {
+                    return Err(
+                        "Translator.Number requires 1-2 arguments (number, pattern)".to_string()
+                    );
+                }
+            };
+            format_number(&locale_number, &args[0], pattern).map(Value::String)
+        }),
+    );
+
+    let locale_date = locale.clone();
+    methods.insert(
+        "Date".to_string(),
+        native(move |args| {
+            let style = match args.as_slice() {
+                [_] => "Long",
+                [_, style] => string_arg(style, "Translator.Date's style")?,
+                _ => {
+                    return Err("Translator.Date requires 1-2 arguments (date, style)".to_string());
+                }
+            };
+            format_date(&locale_date, &args[0], style).map(Value::String)
+        }),
+    );
+
+    let locale_time = locale;
+    methods.insert(
+        "Time".to_string(),
+        native(move |args| {
+            if args.len() != 1 {
+                return Err("Translator.Time requires 1 argument (datetime)".to_string());
+            }
+            format_time(&locale_time, &args[0]).map(Value::String)
+        }),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn catalog_value(catalog: Catalog) -> Value {
+    let catalog = Arc::new(RwLock::new(catalog));
+    let mut methods = IndexMap::new();
+
+    let catalog_locales = catalog.clone();
+    methods.insert(
+        "Locales".to_string(),
+        native(move |args| {
+            if !args.is_empty() {
+                return Err("Catalog.Locales takes no arguments".to_string());
+            }
+            let locales = catalog_locales
+                .read_recover()
+                .locales
+                .keys()
+                .map(|tag| Value::String(tag.clone()))
+                .collect();
+            Ok(Value::List(Arc::new(RwLock::new(locales))))
+        }),
+    );
+
+    // Catalog.Locale("mn") or Catalog.Locale(Request), which picks the locale from
+    // the request's Accept-Language header
+    let catalog_locale = catalog.clone();
+    methods.insert(
+        "Locale".to_string(),
+        native(move |args| {
+            if args.len() != 1 {
+                return Err("Catalog.Locale requires 1 argument (locale or request)".to_string());
+            }
+            let locale = match &args[0] {
+                Value::String(tag) => canonical(tag),
+                Value::Map(_) => {
+                    let catalog = catalog_locale.read_recover();
+                    match accept_language(&args[0]) {
+                        Some(header) => catalog.negotiate(&header),
+                        None => catalog.default.clone(),
+                    }
+                }
+                other => {
+                    return Err(format!(
+                        "Catalog.Locale needs a locale or a Request, not {}",
+                        other.type_name()
+                    ));
+                }
+            };
+            Ok(translator_value(catalog_locale.clone(), locale))
+        }),
+    );
+
+    let catalog_negotiate = catalog.clone();
+    methods.insert(
+        "Negotiate".to_string(),
+        native(move |args| {
+            if args.len() != 1 {
+                return Err("Catalog.Negotiate requires 1 argument (header or request)".to_string());
+            }
+            let header = match &args[0] {
+                Value::String(header) => header.clone(),
+                request => accept_language(request).unwrap_or_default(),
+            };
+            Ok(Value::String(
+                catalog_negotiate.read_recover().negotiate(&header),
+            ))
+        }),
+    );
+
+    // Catalog.T("greeting", { Name: "Ann" }), in the default locale
+    let catalog_t = catalog.clone();
+    methods.insert(
+        "T".to_string(),
+        native(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("Catalog.T requires 1-2 arguments (key, params)".to_string());
+            }
+            let key = string_arg(&args[0], "Catalog.T's key")?;
+            let catalog = catalog_t.read_recover();
+            translate(&catalog, &catalog.default, key, args.get(1)).map(Value::String)
+        }),
+    );
+
+    let catalog_add = catalog.clone();
+    methods.insert(
+        "Add".to_string(),
+        native(move |args| {
+            if args.len() != 2 {
+                return Err("Catalog.Add requires 2 arguments (locale, messages)".to_string());
+            }
+            let locale = string_arg(&args[0], "Catalog.Add's locale")?;
+            catalog_add.write_recover().add(locale, &args[1])?;
+            Ok(Value::Boolean(true))
+        }),
+    );
+
+    let default = catalog.read_recover().default.clone();
+    methods.insert("Default".to_string(), Value::String(default));
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// The Default option, or "en" when it was loaded, or the first locale
+fn build_catalog(
+    locales: IndexMap<String, HashMap<String, Message>>,
+    options: Option<&Value>,
+    caller: &str,
+) -> Result<Value, String> {
+    let default = match options {
+        Some(Value::Map(options)) => match options.read_recover().get("Default") {
+            Some(default) => Some(canonical(string_arg(default, "The Default option")?)),
+            None => None,
+        },
+        Some(other) => {
+            return Err(format!(
+                "{}'s options must be a Map, not {}",
+                caller,
+                other.type_name()
+            ));
+        }
+        None => None,
+    };
+    let default = default
+        .or_else(|| locales.contains_key("en").then(|| "en".to_string()))
+        .or_else(|| locales.keys().next().cloned())
+        .ok_or_else(|| format!("{} found no catalogs", caller))?;
+    Ok(catalog_value(Catalog { locales, default }))
+}
+
+pub fn create_i18n_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // I18n.Load("locales") reads every .toml and .json file in the folder;
+    // I18n.Load("locales/mn.toml") reads one
+    methods.insert(
+        "Load".to_string(),
+        native(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("I18n.Load requires 1-2 arguments (path, options)".to_string());
+            }
+            let path = Path::new(string_arg(&args[0], "I18n.Load's path")?);
+            let mut files = Vec::new();
+            permissions::check_read(path)?;
+            if path.is_dir() {
+                let entries = std::fs::read_dir(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                for entry in entries.flatten() {
+                    let file = entry.path();
+                    if matches!(
+                        file.extension().and_then(|e| e.to_str()),
+                        Some("toml" | "json")
+                    ) {
+                        files.push(file);
+                    }
+                }
+                files.sort();
+            } else {
+                files.push(path.to_path_buf());
+            }
+
+            let mut catalog = Catalog {
+                locales: IndexMap::new(),
+                default: String::new(),
+            };
+            for file in files {
+                let locale = file
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default()
+                    .to_string();
+                catalog.add(&locale, &read_catalog(&file)?)?;
+            }
+            build_catalog(catalog.locales, args.get(1), "I18n.Load")
+        }),
+    );
+
+    // I18n.Catalog({ en: {...}, mn: {...} }) for messages kept in the script
+    methods.insert(
+        "Catalog".to_string(),
+        native(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("I18n.Catalog requires 1-2 arguments (messages, options)".to_string());
+            }
+            let Value::Map(by_locale) = &args[0] else {
+                return Err(format!(
+                    "I18n.Catalog needs a Map of locales, not {}",
+                    args[0].type_name()
+                ));
+            };
+            let mut catalog = Catalog {
+                locales: IndexMap::new(),
+                default: String::new(),
+            };
+            for (locale, messages) in by_locale.read_recover().iter() {
+                catalog.add(locale, messages)?;
+            }
+            build_catalog(catalog.locales, args.get(1), "I18n.Catalog")
+        }),
+    );
+
+    // I18n.Plural("ru", 3) is "few"
+    methods.insert(
+        "Plural".to_string(),
+        native(|args| {
+            if args.len() != 2 {
+                return Err("I18n.Plural requires 2 arguments (locale, count)".to_string());
+            }
+            let locale = canonical(string_arg(&args[0], "I18n.Plural's locale")?);
+            plural_category(&locale, &args[1]).map(|form| Value::String(form.to_string()))
+        }),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+    use std::str::FromStr;
+
+    fn call(object: &Value, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let Value::Map(map) = object else {
+            panic!("not an object");
+        };
+        let function = map.read_recover().get(name).cloned();
+        match function {
+            Some(Value::NativeFunction(f)) => f(args),
+            _ => panic!("no member {}", name),
+        }
+    }
+
+    fn text(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(Arc::new(RwLock::new(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )))
+    }
+
+    fn int(n: i64) -> Value {
+        Value::Integer(n.into())
+    }
+
+    fn catalog() -> Value {
+        let module = create_i18n_module();
+        let en = map(vec![
+            ("greeting", text("Hello, {Name}!")),
+            (
+                "cart",
+                map(vec![(
+                    "items",
+                    map(vec![
+                        ("one", text("{Count} item")),
+                        ("other", text("{Count} items")),
+                    ]),
+                )]),
+            ),
+            ("only_english", text("English")),
+        ]);
+        let ru = map(vec![
+            ("greeting", text("Привет, {Name}!")),
+            (
+                "cart",
+                map(vec![(
+                    "items",
+                    map(vec![
+                        ("one", text("{Count} товар")),
+                        ("few", text("{Count} товара")),
+                        ("many", text("{Count} товаров")),
+                        ("other", text("{Count} товара")),
+                    ]),
+                )]),
+            ),
+        ]);
+        call(&module, "Catalog", vec![map(vec![("en", en), ("ru", ru)])]).unwrap()
+    }
+
+    fn t(translator: &Value, key: &str, params: Vec<(&str, Value)>) -> String {
+        call(translator, "T", vec![text(key), map(params)])
+            .unwrap()
+            .to_display_string()
+    }
+
+    #[test]
+    fn test_messages_and_plurals() {
+        let catalog = catalog();
+        let ru = call(&catalog, "Locale", vec![text("ru_RU")]).unwrap();
+        assert_eq!(
+            t(&ru, "greeting", vec![("Name", text("Аня"))]),
+            "Привет, Аня!"
+        );
+        assert_eq!(t(&ru, "cart.items", vec![("Count", int(21))]), "21 товар");
+        assert_eq!(t(&ru, "cart.items", vec![("Count", int(3))]), "3 товара");
+        assert_eq!(t(&ru, "cart.items", vec![("Count", int(12))]), "12 товаров");
+        assert_eq!(
+            t(&ru, "cart.items", vec![("Count", int(1500))]),
+            "1\u{a0}500 товаров"
+        );
+        // Falls back to the default locale, then to the key itself
+        assert_eq!(t(&ru, "only_english", vec![]), "English");
+        assert_eq!(t(&ru, "missing.key", vec![]), "missing.key");
+
+        let en = call(&catalog, "Locale", vec![text("en")]).unwrap();
+        assert_eq!(t(&en, "cart.items", vec![("Count", int(1))]), "1 item");
+        assert_eq!(
+            t(&en, "cart.items", vec![("Count", int(1000))]),
+            "1,000 items"
+        );
+        assert!(call(&en, "T", vec![text("cart.items")]).is_err());
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let catalog = catalog();
+        let negotiate = |header: &str| {
+            call(&catalog, "Negotiate", vec![text(header)])
+                .unwrap()
+                .to_display_string()
+        };
+        assert_eq!(negotiate("ru-RU,ru;q=0.9,en;q=0.8"), "ru");
+        assert_eq!(negotiate("da, en-GB;q=0.8, ru;q=0.7"), "en");
+        assert_eq!(negotiate("fr;q=1, ru;q=0.5"), "ru");
+        assert_eq!(negotiate("de"), "en");
+        let request = map(vec![(
+            "Headers",
+            map(vec![("accept-language", text("ru;q=0.9, en;q=0.1"))]),
+        )]);
+        let translator = call(&catalog, "Locale", vec![request]).unwrap();
+        assert_eq!(
+            t(&translator, "greeting", vec![("Name", text("Bo"))]),
+            "Привет, Bo!"
+        );
+    }
+
+    #[test]
+    fn test_dates_and_numbers() {
+        let date = map(vec![
+            ("Year", int(2026)),
+            ("Month", int(1)),
+            ("Day", int(31)),
+            ("Hour", int(14)),
+            ("Minute", int(5)),
+            ("Zone", text("UTC")),
+        ]);
+        let module = create_i18n_module();
+        let catalog = call(&module, "Catalog", vec![map(vec![("en", map(vec![]))])]).unwrap();
+        let format = |locale: &str, member: &str, extra: Vec<Value>| {
+            let translator = call(&catalog, "Locale", vec![text(locale)]).unwrap();
+            let mut args = vec![date.clone()];
+            args.extend(extra);
+            call(&translator, member, args).unwrap().to_display_string()
+        };
+        assert_eq!(format("en", "Date", vec![]), "January 31, 2026");
+        assert_eq!(format("en-GB", "Date", vec![text("Short")]), "31/01/2026");
+        assert_eq!(
+            format("de", "Date", vec![text("Full")]),
+            "Samstag, 31. Januar 2026"
+        );
+        assert_eq!(format("mn", "Date", vec![]), "2026 оны нэгдүгээр сарын 31");
+        assert_eq!(
+            format("ja", "Date", vec![text("Full")]),
+            "2026年1月31日土曜日"
+        );
+        assert_eq!(format("en", "Time", vec![]), "2:05 PM");
+        assert_eq!(format("de", "Time", vec![]), "14:05");
+
+        let de = call(&catalog, "Locale", vec![text("de")]).unwrap();
+        let number = BigDecimal::from_str("1234567.891").unwrap();
+        assert_eq!(
+            call(&de, "Number", vec![Value::Number(number)])
+                .unwrap()
+                .to_display_string(),
+            "1.234.567,891"
+        );
+        assert_eq!(
+            call(&module, "Plural", vec![text("pl"), int(22)])
+                .unwrap()
+                .to_display_string(),
+            "few"
+        );
+        assert_eq!(
+            call(&module, "Plural", vec![text("ar"), int(11)])
+                .unwrap()
+                .to_display_string(),
+            "many"
+        );
+    }
+}
@@ -1,11 +1,18 @@
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
-use std::sync::Arc;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use serde_json::Value as JsonValue;
+use std::sync::{Arc, Mutex};
 
 pub fn convert_json_to_object(json: serde_json::Value) -> Value {
     match json {
         serde_json::Value::Null => Value::Boolean(false),
         serde_json::Value::Bool(b) => Value::Boolean(b),
+        serde_json::Value::Number(n) if n.is_i64() || n.is_u64() => {
+            Value::from_integer_string(&n.to_string()).unwrap_or(Value::default_integer())
+        }
         serde_json::Value::Number(n) => {
             Value::from_number_string(&n.to_string()).unwrap_or(Value::default_number())
         }
@@ -15,7 +22,7 @@ pub fn convert_json_to_object(json: serde_json::Value) -> Value {
             Value::List(Arc::new(std::sync::RwLock::new(list)))
         }
         serde_json::Value::Object(obj) => {
-            let mut map = HashMap::new();
+            let mut map = IndexMap::new();
             for (k, v) in obj {
                 map.insert(k, convert_json_to_object(v));
             }
@@ -24,8 +31,97 @@ pub fn convert_json_to_object(json: serde_json::Value) -> Value {
     }
 }
 
+pub fn convert_object_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::Number(n) => {
+            // to_i64 drops a fraction, so only whole numbers take it
+            if let Some(i) = n.to_i64().filter(|_| n.is_integer()) {
+                JsonValue::Number(i.into())
+            } else if let Some(f) = n.to_f64() {
+                serde_json::Number::from_f64(f)
+                    .map(JsonValue::Number)
+                    .unwrap_or_else(|| JsonValue::String(n.to_string()))
+            } else {
+                JsonValue::String(n.to_string())
+            }
+        }
+        // Beyond 64 bits JSON numbers lose precision, so keep the digits
+        Value::Integer(i) => {
+            if let Some(n) = i.to_i64() {
+                JsonValue::Number(n.into())
+            } else if let Some(n) = i.to_u64() {
+                JsonValue::Number(n.into())
+            } else {
+                JsonValue::String(i.to_string())
+            }
+        }
+        Value::FastNumber(f) => serde_json::Number::from_f64(*f)
+            .map(JsonValue::Number)
+            .unwrap_or_else(|| JsonValue::String(f.to_string())),
+        Value::String(s) => JsonValue::String(s.clone()),
+        Value::Boolean(b) => JsonValue::Bool(*b),
+        Value::List(list) => {
+            let list = list.read_recover();
+            JsonValue::Array(list.iter().map(convert_object_to_json).collect())
+        }
+        Value::Vector(vec) => floats_to_json(vec),
+        Value::Matrix(matrix) => JsonValue::Array(
+            (0..matrix.rows)
+                .map(|row| floats_to_json(matrix.row(row)))
+                .collect(),
+        ),
+        Value::Map(map) => {
+            let map = map.read_recover();
+            let mut object = serde_json::Map::new();
+            for (key, value) in map.iter() {
+                object.insert(key.clone(), convert_object_to_json(value));
+            }
+            JsonValue::Object(object)
+        }
+        Value::Option(opt) => match opt.as_ref() {
+            Some(inner) => convert_object_to_json(inner),
+            None => JsonValue::Null,
+        },
+        Value::Result(result) => match result.as_ref() {
+            Ok(inner) => convert_object_to_json(inner),
+            Err(error) => serde_json::json!({ "error": convert_object_to_json(error) }),
+        },
+        Value::Error(err) => {
+            let mut object = serde_json::Map::new();
+            object.insert(
+                "category".to_string(),
+                JsonValue::String(err.category.clone()),
+            );
+            object.insert(
+                "subtype".to_string(),
+                JsonValue::String(err.subtype.clone()),
+            );
+            object.insert(
+                "message".to_string(),
+                JsonValue::String(err.message.clone()),
+            );
+            JsonValue::Object(object)
+        }
+        Value::Bytes(bytes) => {
+            use base64::Engine;
+            JsonValue::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+        _ => JsonValue::String(value.to_display_string()),
+    }
+}
+
+fn floats_to_json(values: &[f64]) -> JsonValue {
+    JsonValue::Array(
+        values
+            .iter()
+            .map(|v| serde_json::Number::from_f64(*v))
+            .map(|n| n.map(JsonValue::Number).unwrap_or(JsonValue::Null))
+            .collect(),
+    )
+}
+
 pub fn create_json_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Parse".to_string(),
@@ -53,5 +149,784 @@ pub fn create_json_module() -> Value {
         }))),
     );
 
+    // JSON.Pretty(value, indent?) -> JSON text over several lines. indent is
+    // a number of spaces (2 by default), a text such as "\t", or a Map of
+    // { Indent, SortKeys }
+    methods.insert(
+        "Pretty".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let (indent, sort_keys) = match args.len() {
+                1 => ("  ".to_string(), false),
+                2 => pretty_options(&args[1])?,
+                _ => {
+                    return Err("JSON.Pretty requires 1 or 2 arguments (value, indent)".to_string());
+                }
+            };
+            Ok(Value::String(pretty(&args[0], &indent, sort_keys)))
+        }))),
+    );
+
+    // JSON.Query(value, "$.items[*].name") -> List of every match
+    methods.insert(
+        "Query".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("JSON.Query requires 2 arguments (value, path)".to_string());
+            }
+            let path = parse_path(&args[1].to_display_string())
+                .map_err(|e| format!("JSON.Query: {}", e))?;
+            let found = query(&args[0], &path);
+            Ok(Value::List(Arc::new(std::sync::RwLock::new(found))))
+        }))),
+    );
+
+    // JSON.ParseStream(source) -> Stream of values, parsed as they are read.
+    // source is a file path, Bytes, or a Stream of text or Bytes chunks
+    // (such as the Body of a streamed HTTP response). A top-level array
+    // gives its items one by one; otherwise the values one after another,
+    // as in JSON Lines.
+    methods.insert(
+        "ParseStream".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("JSON.ParseStream requires 1 argument (path, Bytes or Stream)".to_string());
+            }
+            let source = match &args[0] {
+                Value::Bytes(bytes) => Source::Bytes(Some(bytes.to_vec())),
+                Value::Map(map) => match map.read_recover().get("Next") {
+                    Some(next @ Value::NativeFunction(_)) => Source::Stream(next.clone()),
+                    _ => return Err("JSON.ParseStream expects a path, Bytes or a Stream".to_string()),
+                },
+                other => {
+                    let path = other.to_display_string();
+                    permissions::check_read(&path)?;
+                    let file = std::fs::File::open(&path)
+                        .map_err(|e| format!("JSON.ParseStream: can't open {}: {}", path, e))?;
+                    Source::File(file)
+                }
+            };
+            let reader = Mutex::new(StreamReader::new(source));
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                let value = reader
+                    .lock_recover()
+                    .next_value()
+                    .map_err(|e| format!("JSON.ParseStream: {}", e))?;
+                Ok(Value::Option(Box::new(value.map(convert_json_to_object))))
+            })));
+            Ok(crate::stdlib::stream::create_stream_object(
+                vec![],
+                Some(generator),
+            ))
+        }))),
+    );
+
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+fn pretty_options(options: &Value) -> Result<(String, bool), String> {
+    match options {
+        Value::Map(map) => {
+            let map = map.read_recover();
+            let indent = match map.get("Indent") {
+                Some(indent) => indent_text(indent)?,
+                None => "  ".to_string(),
+            };
+            let sort_keys = map.get("SortKeys").is_some_and(Value::is_truthy);
+            Ok((indent, sort_keys))
+        }
+        other => Ok((indent_text(other)?, false)),
+    }
+}
+
+fn indent_text(indent: &Value) -> Result<String, String> {
+    match indent {
+        Value::String(text) => Ok(text.clone()),
+        other => match other.as_f64() {
+            Some(n) if (0.0..=16.0).contains(&n) && n.fract() == 0.0 => Ok(" ".repeat(n as usize)),
+            _ => Err(format!(
+                "JSON.Pretty: indent must be 0 to 16 spaces or a text, not {}",
+                other.to_display_string()
+            )),
+        },
+    }
+}
+
+/// `value` as JSON over several lines, each level indented by `indent`.
+pub fn pretty(value: &Value, indent: &str, sort_keys: bool) -> String {
+    let mut json = convert_object_to_json(value);
+    if sort_keys {
+        json.sort_all_objects();
+    }
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut out = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    // Writing to a Vec can't fail, and a JsonValue always serializes
+    let _ = serde::Serialize::serialize(&json, &mut serializer);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+// ---------------------------------------------------------------------------
+// JSONPath
+//
+// $.store.book[*].author     child names and wildcards
+// $..price                   every price at any depth
+// $.book[1], $.book[-1]      items counted from 1, or back from the end
+// $['a key'], $.book[1,3]    quoted names, and several names or items
+// $.book[?(@.price < 10)]    items or values whose path compares true
+// $.book[?(@.isbn)]          ... or just exists
+//
+// Indexes count from 1, as everywhere else in SFX.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    // `..`: the selector applies to the node and everything below it
+    descendants: bool,
+    selector: Selector,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Wildcard,
+    Union(Vec<Selector>),
+    Filter(Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    // Relative to `@`, the child being tested
+    path: Vec<Step>,
+    test: Option<(Comparison, Value)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+struct PathParser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+}
+
+fn parse_path(text: &str) -> Result<Vec<Step>, String> {
+    let mut parser = PathParser {
+        chars: text.trim().char_indices().peekable(),
+        text: text.trim(),
+    };
+    if parser.chars.next().map(|(_, c)| c) != Some('$') {
+        return Err(format!("'{}' must start with $", text));
+    }
+    let steps = parser.steps()?;
+    match parser.chars.next() {
+        None => Ok(steps),
+        Some((at, c)) => Err(format!("unexpected '{}' at {} in '{}'", c, at + 1, text)),
+    }
+}
+
+impl PathParser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.chars.next();
+        }
+    }
+
+    fn error(&mut self, wanted: &str) -> String {
+        match self.chars.peek() {
+            Some((at, c)) => format!(
+                "expected {} at {} in '{}', found '{}'",
+                wanted,
+                at + 1,
+                self.text,
+                c
+            ),
+            None => format!("expected {} at the end of '{}'", wanted, self.text),
+        }
+    }
+
+    /// Steps up to the end, or to whatever can't start one
+    fn steps(&mut self) -> Result<Vec<Step>, String> {
+        let mut steps = Vec::new();
+        loop {
+            let selector = if self.eat('.') {
+                let descendants = self.eat('.');
+                let selector = if self.peek() == Some('[') {
+                    self.chars.next();
+                    self.bracket()?
+                } else if self.eat('*') {
+                    Selector::Wildcard
+                } else {
+                    Selector::Name(self.name()?)
+                };
+                steps.push(Step {
+                    descendants,
+                    selector,
+                });
+                continue;
+            } else if self.eat('[') {
+                self.bracket()?
+            } else {
+                return Ok(steps);
+            };
+            steps.push(Step {
+                descendants: false,
+                selector,
+            });
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if matches!(
+                c,
+                '.' | '[' | ']' | ' ' | '=' | '!' | '<' | '>' | ')' | '&' | '|'
+            ) {
+                break;
+            }
+            name.push(c);
+            self.chars.next();
+        }
+        if name.is_empty() {
+            return Err(self.error("a name"));
+        }
+        Ok(name)
+    }
+
+    /// What follows `[`, up to and including its `]`
+    fn bracket(&mut self) -> Result<Selector, String> {
+        self.skip_spaces();
+        let selector = if self.eat('*') {
+            Selector::Wildcard
+        } else if self.eat('?') {
+            self.skip_spaces();
+            let parenthesized = self.eat('(');
+            let filter = self.filter()?;
+            self.skip_spaces();
+            if parenthesized && !self.eat(')') {
+                return Err(self.error("')'"));
+            }
+            Selector::Filter(Box::new(filter))
+        } else {
+            let mut selectors = vec![self.union_member()?];
+            self.skip_spaces();
+            while self.eat(',') {
+                self.skip_spaces();
+                selectors.push(self.union_member()?);
+                self.skip_spaces();
+            }
+            if selectors.len() == 1 {
+                selectors.remove(0)
+            } else {
+                Selector::Union(selectors)
+            }
+        };
+        self.skip_spaces();
+        if !self.eat(']') {
+            return Err(self.error("']'"));
+        }
+        Ok(selector)
+    }
+
+    fn union_member(&mut self) -> Result<Selector, String> {
+        match self.peek() {
+            Some('\'' | '"') => Ok(Selector::Name(self.quoted()?)),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let index = self.integer()?;
+                if index == 0 {
+                    return Err(format!(
+                        "index 0 in '{}': items are counted from 1",
+                        self.text
+                    ));
+                }
+                Ok(Selector::Index(index))
+            }
+            _ => Err(self.error("a quoted name, an index or *")),
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        let Some((_, quote)) = self.chars.next() else {
+            return Err(self.error("a quote"));
+        };
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) => text.push(c),
+                    None => break,
+                },
+                Some((_, c)) if c == quote => return Ok(text),
+                Some((_, c)) => text.push(c),
+                None => break,
+            }
+        }
+        Err(format!("unclosed quote in '{}'", self.text))
+    }
+
+    fn integer(&mut self) -> Result<i64, String> {
+        let mut digits = String::new();
+        if self.eat('-') {
+            digits.push('-');
+        }
+        while let Some(c) = self.peek().filter(char::is_ascii_digit) {
+            digits.push(c);
+            self.chars.next();
+        }
+        digits.parse().map_err(|_| self.error("a number"))
+    }
+
+    /// `@.path` or `@.path <op> literal`
+    fn filter(&mut self) -> Result<Filter, String> {
+        if !self.eat('@') {
+            return Err(self.error("'@'"));
+        }
+        let path = self.steps()?;
+        self.skip_spaces();
+        let comparison = if self.eat('=') {
+            self.eat('=');
+            Some(Comparison::Equal)
+        } else if self.eat('!') {
+            if !self.eat('=') {
+                return Err(self.error("'='"));
+            }
+            Some(Comparison::NotEqual)
+        } else if self.eat('<') {
+            Some(if self.eat('=') {
+                Comparison::LessOrEqual
+            } else {
+                Comparison::Less
+            })
+        } else if self.eat('>') {
+            Some(if self.eat('=') {
+                Comparison::GreaterOrEqual
+            } else {
+                Comparison::Greater
+            })
+        } else {
+            None
+        };
+        let test = match comparison {
+            Some(comparison) => {
+                self.skip_spaces();
+                Some((comparison, self.literal()?))
+            }
+            None => None,
+        };
+        Ok(Filter { path, test })
+    }
+
+    fn literal(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('\'' | '"') => Ok(Value::String(self.quoted()?)),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .peek()
+                    .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    number.push(c);
+                    self.chars.next();
+                }
+                Value::from_integer_string(&number)
+                    .or_else(|_| Value::from_number_string(&number))
+                    .map_err(|_| format!("'{}' is not a number in '{}'", number, self.text))
+            }
+            Some(c) if c.is_alphabetic() => {
+                let word = self.name()?;
+                match word.to_lowercase().as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" | "null" => Ok(Value::Boolean(false)),
+                    _ => Err(format!("'{}' is not a value in '{}'", word, self.text)),
+                }
+            }
+            _ => Err(self.error("a number, text, true or false")),
+        }
+    }
+}
+
+fn query(root: &Value, path: &[Step]) -> Vec<Value> {
+    let mut nodes = vec![root.clone()];
+    for step in path {
+        let mut next = Vec::new();
+        for node in &nodes {
+            if step.descendants {
+                let mut all = Vec::new();
+                descendants(node, &mut all);
+                for node in &all {
+                    select(node, &step.selector, &mut next);
+                }
+            } else {
+                select(node, &step.selector, &mut next);
+            }
+        }
+        nodes = next;
+    }
+    nodes
+}
+
+fn children(node: &Value) -> Vec<Value> {
+    match node {
+        Value::Map(map) => map
+            .read_recover()
+            .iter()
+            .filter(|(key, _)| key.as_str() != "_concept")
+            .map(|(_, value)| value.clone())
+            .collect(),
+        Value::List(list) => list.read_recover().clone(),
+        _ => Vec::new(),
+    }
+}
+
+// The node and everything below it, parents first
+fn descendants(node: &Value, found: &mut Vec<Value>) {
+    found.push(node.clone());
+    for child in children(node) {
+        descendants(&child, found);
+    }
+}
+
+fn select(node: &Value, selector: &Selector, found: &mut Vec<Value>) {
+    match selector {
+        Selector::Name(name) => {
+            if let Value::Map(map) = node
+                && let Some(value) = map.read_recover().get(name)
+            {
+                found.push(value.clone());
+            }
+        }
+        Selector::Index(index) => {
+            if let Value::List(list) = node {
+                let list = list.read_recover();
+                let position = if *index > 0 {
+                    usize::try_from(index - 1).ok()
+                } else {
+                    list.len().checked_sub(index.unsigned_abs() as usize)
+                };
+                if let Some(value) = position.and_then(|i| list.get(i)) {
+                    found.push(value.clone());
+                }
+            }
+        }
+        Selector::Wildcard => found.extend(children(node)),
+        Selector::Union(selectors) => {
+            for selector in selectors {
+                select(node, selector, found);
+            }
+        }
+        Selector::Filter(filter) => {
+            found.extend(
+                children(node)
+                    .into_iter()
+                    .filter(|child| matches_filter(child, filter)),
+            );
+        }
+    }
+}
+
+fn matches_filter(node: &Value, filter: &Filter) -> bool {
+    let values = query(node, &filter.path);
+    let Some((comparison, expected)) = &filter.test else {
+        return !values.is_empty();
+    };
+    values.iter().any(|value| {
+        let ordering = value.compare(expected).ok();
+        match comparison {
+            Comparison::Equal => value.equals(expected),
+            Comparison::NotEqual => !value.equals(expected),
+            Comparison::Less => ordering == Some(std::cmp::Ordering::Less),
+            Comparison::LessOrEqual => ordering.is_some_and(|o| o.is_le()),
+            Comparison::Greater => ordering == Some(std::cmp::Ordering::Greater),
+            Comparison::GreaterOrEqual => ordering.is_some_and(|o| o.is_ge()),
+        }
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Streaming parser
+// ---------------------------------------------------------------------------
+
+enum Source {
+    File(std::fs::File),
+    Bytes(Option<Vec<u8>>),
+    // The Next function of a Stream of text or Bytes chunks
+    Stream(Value),
+}
+
+impl Source {
+    /// The next chunk, or None at the end
+    fn read(&mut self) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            Source::File(file) => {
+                let mut chunk = vec![0; 64 * 1024];
+                let read = std::io::Read::read(file, &mut chunk).map_err(|e| e.to_string())?;
+                chunk.truncate(read);
+                Ok((read > 0).then_some(chunk))
+            }
+            Source::Bytes(bytes) => Ok(bytes.take()),
+            Source::Stream(Value::NativeFunction(next)) => match next(vec![])? {
+                Value::Option(chunk) => Ok(chunk.map(|chunk| match chunk {
+                    Value::Bytes(bytes) => bytes.to_vec(),
+                    other => other.to_display_string().into_bytes(),
+                })),
+                other => Ok(Some(other.to_display_string().into_bytes())),
+            },
+            Source::Stream(_) => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    // Nothing read yet
+    Start,
+    // Items of a top-level array; `first` until one has been given
+    Array { first: bool },
+    // Values one after another
+    Values,
+    Done,
+}
+
+/// Splits what `source` gives into whole JSON values, holding only the
+/// value being read in memory.
+struct StreamReader {
+    source: Source,
+    buffer: Vec<u8>,
+    // Bytes of buffer already used
+    position: usize,
+    ended: bool,
+    layout: Layout,
+    // Bytes consumed before buffer[0], for error positions
+    offset: usize,
+}
+
+impl StreamReader {
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            buffer: Vec::new(),
+            position: 0,
+            ended: false,
+            layout: Layout::Start,
+            offset: 0,
+        }
+    }
+
+    /// The byte at `position + ahead`, reading more if needed
+    fn peek_at(&mut self, ahead: usize) -> Result<Option<u8>, String> {
+        while self.position + ahead >= self.buffer.len() && !self.ended {
+            match self.source.read()? {
+                Some(chunk) => {
+                    // Drop what has been used before growing the buffer
+                    if self.position > 0 {
+                        self.buffer.drain(..self.position);
+                        self.offset += self.position;
+                        self.position = 0;
+                    }
+                    self.buffer.extend_from_slice(&chunk);
+                }
+                None => self.ended = true,
+            }
+        }
+        Ok(self.buffer.get(self.position + ahead).copied())
+    }
+
+    fn skip_whitespace(&mut self) -> Result<Option<u8>, String> {
+        loop {
+            match self.peek_at(0)? {
+                Some(b' ' | b'\t' | b'\n' | b'\r') => self.position += 1,
+                other => return Ok(other),
+            }
+        }
+    }
+
+    fn unexpected(&self, byte: u8, wanted: &str) -> String {
+        format!(
+            "expected {} at byte {}, found '{}'",
+            wanted,
+            self.offset + self.position + 1,
+            byte as char
+        )
+    }
+
+    fn next_value(&mut self) -> Result<Option<JsonValue>, String> {
+        match self.layout {
+            Layout::Done => return Ok(None),
+            Layout::Start => {
+                if self.skip_whitespace()? == Some(b'[') {
+                    self.position += 1;
+                    self.layout = Layout::Array { first: true };
+                } else {
+                    self.layout = Layout::Values;
+                }
+            }
+            _ => {}
+        }
+
+        if let Layout::Array { first } = self.layout {
+            match self.skip_whitespace()? {
+                Some(b']') => {
+                    self.position += 1;
+                    self.layout = Layout::Done;
+                    return match self.skip_whitespace()? {
+                        None => Ok(None),
+                        Some(byte) => Err(self.unexpected(byte, "the end after ']'")),
+                    };
+                }
+                Some(b',') if !first => {
+                    self.position += 1;
+                    self.skip_whitespace()?;
+                }
+                Some(byte) if !first => return Err(self.unexpected(byte, "',' or ']'")),
+                None => return Err("the array is not closed with ']'".to_string()),
+                Some(_) => {}
+            }
+            self.layout = Layout::Array { first: false };
+        } else if self.skip_whitespace()?.is_none() {
+            self.layout = Layout::Done;
+            return Ok(None);
+        }
+
+        let length = self.value_length()?;
+        let start = self.position;
+        self.position += length;
+        serde_json::from_slice(&self.buffer[start..start + length])
+            .map_err(|e| format!("{} in the value at byte {}", e, self.offset + start + 1))
+    }
+
+    /// How many bytes the value at `position` takes, reading until it ends
+    fn value_length(&mut self) -> Result<usize, String> {
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut length = 0;
+        loop {
+            let Some(byte) = self.peek_at(length)? else {
+                if depth > 0 || in_string {
+                    return Err("the input ends inside a value".to_string());
+                }
+                return Ok(length);
+            };
+            if in_string {
+                match byte {
+                    b'\\' => length += 1,
+                    b'"' => {
+                        in_string = false;
+                        if depth == 0 {
+                            return Ok(length + 1);
+                        }
+                    }
+                    _ => {}
+                }
+            } else {
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' if depth > 0 => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(length + 1);
+                        }
+                    }
+                    // A number or literal ends where the next token starts
+                    b' ' | b'\t' | b'\n' | b'\r' | b',' | b']' | b'}' if depth == 0 => {
+                        return Ok(length);
+                    }
+                    _ => {}
+                }
+            }
+            length += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Value {
+        convert_json_to_object(serde_json::from_str(text).unwrap())
+    }
+
+    fn texts(values: Vec<Value>) -> Vec<String> {
+        values.iter().map(Value::to_display_string).collect()
+    }
+
+    #[test]
+    fn test_query() {
+        let data = parse(
+            r#"{"store": {"book": [{"title": "A", "price": 8}, {"title": "B", "price": 22.5, "isbn": "1"}], "bike": {"price": 19}}}"#,
+        );
+        let run = |path: &str| texts(query(&data, &parse_path(path).unwrap()));
+        assert_eq!(run("$.store.book[*].title"), ["A", "B"]);
+        assert_eq!(run("$..price"), ["8", "22.5", "19"]);
+        assert_eq!(run("$.store.book[-1].title"), ["B"]);
+        assert_eq!(run("$['store'].book[1,2].price"), ["8", "22.5"]);
+        assert_eq!(run("$.store.book[?(@.price < 10)].title"), ["A"]);
+        assert_eq!(run("$.store.book[?@.isbn].title"), ["B"]);
+        assert_eq!(run("$.store.book[?(@.title == 'B')].price"), ["22.5"]);
+        assert!(run("$.store.missing").is_empty());
+        assert!(parse_path("$.store.book[0]").is_err());
+        assert!(parse_path("store").is_err());
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let chunks: Vec<Value> = ["[{\"a\": \"]\"}, 1", "2, [3,", " 4]]"]
+            .iter()
+            .map(|chunk| Value::String(chunk.to_string()))
+            .collect();
+        let stream = crate::stdlib::stream::create_stream_object(chunks, None);
+        let Value::Map(stream) = stream else {
+            panic!("a Stream is a Map");
+        };
+        let next = stream.read_recover().get("Next").cloned().unwrap();
+        let mut reader = StreamReader::new(Source::Stream(next));
+        let mut values = Vec::new();
+        while let Some(value) = reader.next_value().unwrap() {
+            values.push(value.to_string());
+        }
+        assert_eq!(values, [r#"{"a":"]"}"#, "12", "[3,4]"]);
+
+        let mut lines = StreamReader::new(Source::Bytes(Some(b"{\"id\": 1}\n2 \"x\"\n".to_vec())));
+        assert_eq!(
+            lines.next_value().unwrap(),
+            Some(serde_json::json!({"id": 1}))
+        );
+        assert_eq!(lines.next_value().unwrap(), Some(serde_json::json!(2)));
+        assert_eq!(lines.next_value().unwrap(), Some(serde_json::json!("x")));
+        assert_eq!(lines.next_value().unwrap(), None);
+    }
+
+    #[test]
+    fn test_pretty_keeps_key_order() {
+        let data = parse(r#"{"b": 1, "a": [true]}"#);
+        assert_eq!(
+            pretty(&data, "  ", false),
+            "{\n  \"b\": 1,\n  \"a\": [\n    true\n  ]\n}"
+        );
+        assert_eq!(
+            pretty(&data, "", true),
+            "{\n\"a\": [\ntrue\n],\n\"b\": 1\n}"
+        );
+    }
+}
@@ -1,275 +1,1283 @@
+use crate::project::LlmConfig;
+use crate::runtime::deadline;
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
+use crate::stdlib::json::{convert_json_to_object, convert_object_to_json};
 use bigdecimal::BigDecimal;
-use reqwest::blocking::Client;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::LazyLock;
+use indexmap::IndexMap;
+use reqwest::blocking::{Client, Response};
+use serde_json::{Value as JsonValue, json};
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::Duration;
 
 // 1. GLOBAL CLIENT
+const LLM_TIMEOUT: Duration = Duration::from_secs(120);
+
 static HTTP_CLIENT: LazyLock<Client> = LazyLock::new(|| {
     Client::builder()
-        .timeout(std::time::Duration::from_secs(120))
+        .timeout(LLM_TIMEOUT)
         .build()
         .expect("Failed to create HTTP client")
 });
 
-// --- Request Structs ---
+// 2. PROVIDERS
+//
+// OpenAI is called through its Responses API and Anthropic through its
+// Messages API. Ollama and `local` servers (llama.cpp, LM Studio, vLLM, ...)
+// speak the OpenAI-compatible Chat Completions API.
 
-#[derive(Serialize)]
-struct CreateResponseRequest {
-    model: String,
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Provider {
+    OpenAI,
+    Anthropic,
+    Ollama,
+    Local,
+}
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    input: Option<serde_json::Value>,
+impl Provider {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "openai" => Ok(Provider::OpenAI),
+            "anthropic" => Ok(Provider::Anthropic),
+            "ollama" => Ok(Provider::Ollama),
+            "local" => Ok(Provider::Local),
+            _ => Err(format!(
+                "Unknown LLM provider '{}' (expected {})",
+                name,
+                crate::project::LLM_PROVIDERS.join(", ")
+            )),
+        }
+    }
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    instructions: Option<String>,
+    fn label(self) -> &'static str {
+        match self {
+            Provider::OpenAI => "OpenAI Responses",
+            Provider::Anthropic => "Anthropic Messages",
+            Provider::Ollama => "Ollama",
+            Provider::Local => "Local",
+        }
+    }
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    max_output_tokens: Option<i64>,
+    fn default_model(self) -> &'static str {
+        match self {
+            Provider::OpenAI => "gpt-4o",
+            Provider::Anthropic => "claude-3-5-sonnet-latest",
+            Provider::Ollama => "llama3.2",
+            Provider::Local => "default",
+        }
+    }
+
+    fn default_base_url(self) -> String {
+        match self {
+            Provider::OpenAI => "https://api.openai.com/v1".to_string(),
+            Provider::Anthropic => "https://api.anthropic.com/v1".to_string(),
+            // OLLAMA_HOST is the server's own setting, as `0.0.0.0:11434`
+            // or a full URL
+            Provider::Ollama => match env("OLLAMA_HOST") {
+                Some(host) if host.contains("://") => {
+                    format!("{}/v1", host.trim_end_matches('/'))
+                }
+                Some(host) => format!("http://{}/v1", host.trim_end_matches('/')),
+                None => "http://localhost:11434/v1".to_string(),
+            },
+            Provider::Local => "http://localhost:8080/v1".to_string(),
+        }
+    }
+
+    fn key_env(self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAI => Some("OPENAI_API_KEY"),
+            Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            Provider::Ollama | Provider::Local => None,
+        }
+    }
+}
+
+static DEFAULT_CONFIG: RwLock<Option<LlmConfig>> = RwLock::new(None);
 
-    #[serde(skip_serializing_if = "Option::is_none")]
+/// The provider settings calls start from, e.g. from a project's sfex.toml.
+pub fn set_default(config: LlmConfig) {
+    *DEFAULT_CONFIG.write_recover() = Some(config);
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+// 3. OPTIONS
+
+struct Settings {
+    provider: Provider,
+    model: String,
+    base_url: String,
+    api_key: Option<String>,
+    max_output_tokens: Option<i64>,
     temperature: Option<f64>,
+    reasoning_effort: Option<String>,
+    stream: bool,
+    tools: Vec<Tool>,
+    max_tool_rounds: usize,
+}
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    reasoning: Option<serde_json::Value>,
+/// A function the model may call. Calls to a tool with a Handler are
+/// answered by it; calls to one without come back in the reply's ToolCalls.
+struct Tool {
+    name: String,
+    description: String,
+    parameters: JsonValue,
+    handler: Option<Value>,
+}
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    text: Option<serde_json::Value>,
+// Options given to a call come first, then SFEX_LLM_* variables, then the
+// project's [llm] table
+fn settings(options: Option<&Value>) -> Result<Settings, String> {
+    let config = DEFAULT_CONFIG.read_recover().clone().unwrap_or_default();
+    let configured = match env("SFEX_LLM_PROVIDER").or(config.provider.clone()) {
+        Some(name) => Provider::parse(&name)?,
+        None => Provider::OpenAI,
+    };
+
+    let opts = match options {
+        Some(Value::Map(options_map)) => options_map.read_recover().clone(),
+        _ => IndexMap::new(),
+    };
+    let get = |names: &[&str]| names.iter().find_map(|name| opts.get(*name));
+    let number = |names: &[&str]| match get(names) {
+        Some(value @ (Value::Number(_) | Value::Integer(_))) => Some(value.to_display_string()),
+        _ => None,
+    };
+
+    let provider = match get(&["provider", "Provider"]) {
+        Some(name) => Provider::parse(&name.to_display_string())?,
+        None => configured,
+    };
+    // The configured model, URL and key are for the configured provider
+    let (model, base_url, key_env) = if provider == configured {
+        (
+            env("SFEX_LLM_MODEL").or(config.model),
+            env("SFEX_LLM_BASE_URL").or(config.base_url),
+            config.api_key_env,
+        )
+    } else {
+        (None, None, None)
+    };
+
+    let tools = match get(&["tools", "Tools"]) {
+        Some(tools) => parse_tools(tools)?,
+        None => Vec::new(),
+    };
+
+    Ok(Settings {
+        provider,
+        model: get(&["model", "Model"])
+            .map(Value::to_display_string)
+            .or(model)
+            .unwrap_or_else(|| provider.default_model().to_string()),
+        base_url: get(&["base_url", "BaseUrl"])
+            .map(Value::to_display_string)
+            .or(base_url)
+            .unwrap_or_else(|| provider.default_base_url())
+            .trim_end_matches('/')
+            .to_string(),
+        api_key: get(&["api_key", "ApiKey"])
+            .map(Value::to_display_string)
+            .or_else(|| key_env.as_deref().and_then(env))
+            .or_else(|| provider.key_env().and_then(env)),
+        max_output_tokens: number(&["max_output_tokens", "MaxOutputTokens", "max_tokens"])
+            .and_then(|n| n.parse().ok()),
+        temperature: number(&["temperature", "Temperature"]).and_then(|n| n.parse().ok()),
+        reasoning_effort: get(&["reasoning_effort", "ReasoningEffort"])
+            .map(Value::to_display_string),
+        stream: get(&["stream", "Stream"]).is_some_and(Value::is_truthy),
+        tools,
+        max_tool_rounds: number(&["MaxToolRounds"])
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(8),
+    })
 }
 
-#[derive(Deserialize, Debug)]
-struct ResponseUsage {
-    #[serde(default)]
-    input_tokens: i64,
-    #[serde(default)]
-    output_tokens: i64,
-    #[serde(default)]
-    total_tokens: i64,
+fn parse_tools(value: &Value) -> Result<Vec<Tool>, String> {
+    let Value::List(list) = value else {
+        return Err("LLM Tools must be a List of tool Maps".to_string());
+    };
+    let mut tools = Vec::new();
+    for item in list.read_recover().iter() {
+        let Value::Map(map) = item else {
+            return Err(format!(
+                "Each LLM tool must be a Map with a Name, not {}",
+                item.type_name()
+            ));
+        };
+        let map = map.read_recover();
+        let name = map
+            .get("Name")
+            .map(Value::to_display_string)
+            .ok_or("Each LLM tool needs a Name")?;
+        let parameters = match map.get("Parameters") {
+            // Names only: each is a required text
+            Some(Value::List(names)) => {
+                let names: Vec<String> = names
+                    .read_recover()
+                    .iter()
+                    .map(Value::to_display_string)
+                    .collect();
+                let properties: serde_json::Map<String, JsonValue> = names
+                    .iter()
+                    .map(|name| (name.clone(), json!({ "type": "string" })))
+                    .collect();
+                json!({ "type": "object", "properties": properties, "required": names })
+            }
+            // A JSON Schema
+            Some(schema @ Value::Map(_)) => convert_object_to_json(schema),
+            Some(other) => {
+                return Err(format!(
+                    "The Parameters of tool {} must be a List of names or a JSON Schema Map, not {}",
+                    name,
+                    other.type_name()
+                ));
+            }
+            None => json!({ "type": "object", "properties": {} }),
+        };
+        tools.push(Tool {
+            description: map
+                .get("Description")
+                .map(Value::to_display_string)
+                .unwrap_or_default(),
+            parameters,
+            handler: map.get("Handler").cloned(),
+            name,
+        });
+    }
+    Ok(tools)
 }
 
-#[derive(Deserialize, Debug)]
-struct OutputContent {
-    #[serde(rename = "type")]
-    content_type: Option<String>,
+// 4. MESSAGES
 
-    #[serde(default)]
-    text: String,
+#[derive(Debug, Clone, PartialEq)]
+enum Message {
+    System(String),
+    User(String),
+    Assistant {
+        content: String,
+        calls: Vec<ToolCall>,
+    },
+    Tool {
+        call_id: String,
+        content: String,
+    },
 }
 
-#[derive(Deserialize, Debug)]
-struct OutputItem {
-    #[serde(rename = "type")]
-    item_type: Option<String>,
-    role: Option<String>,
-    content: Option<Vec<OutputContent>>,
-    status: Option<String>,
+#[derive(Debug, Clone, PartialEq)]
+struct ToolCall {
+    id: String,
+    name: String,
+    arguments: JsonValue,
 }
 
-#[derive(Deserialize, Debug)]
-struct OpenAIResponseObj {
-    id: Option<String>,
-    model: Option<String>,
-    output: Option<Vec<OutputItem>>,
-    usage: Option<ResponseUsage>,
+fn field<'a>(map: &'a IndexMap<String, Value>, names: &[&str]) -> Option<&'a Value> {
+    names.iter().find_map(|name| map.get(*name))
 }
 
-fn extract_string(val: &Value) -> Option<String> {
-    Some(val.to_display_string())
+fn messages_from_list(list: &[Value]) -> Result<Vec<Message>, String> {
+    let mut messages = Vec::new();
+    for item in list {
+        let Value::Map(m) = item else {
+            continue;
+        };
+        let m = m.read_recover();
+        let role = field(&m, &["role", "Role"])
+            .map(|v| v.to_display_string())
+            .unwrap_or("user".to_string());
+        let content = field(&m, &["content", "Content"])
+            .map(|v| v.to_display_string())
+            .unwrap_or_default();
+
+        messages.push(match role.as_str() {
+            "system" => Message::System(content),
+            "assistant" => Message::Assistant {
+                content,
+                calls: match field(&m, &["ToolCalls", "tool_calls"]) {
+                    Some(Value::List(calls)) => calls
+                        .read_recover()
+                        .iter()
+                        .map(tool_call_from_value)
+                        .collect::<Result<_, _>>()?,
+                    _ => Vec::new(),
+                },
+            },
+            "tool" => Message::Tool {
+                call_id: field(&m, &["ToolCallId", "tool_call_id"])
+                    .map(Value::to_display_string)
+                    .ok_or("A tool message needs the ToolCallId it answers")?,
+                content,
+            },
+            _ => Message::User(content),
+        });
+    }
+    Ok(messages)
 }
 
-// Main logic to call v1/responses
-fn call_responses_api(
-    input_payload: serde_json::Value,
-    instructions: Option<String>,
-    options: Option<&Value>,
-) -> Result<Value, String> {
-    let mut model = "gpt-4o".to_string();
-    let mut max_output_tokens: Option<i64> = None;
-    let mut api_key: Option<String> = None;
-    let mut temperature: Option<f64> = None;
-    let mut reasoning_config: Option<serde_json::Value> = None;
-    let mut text_config: Option<serde_json::Value> = None;
-
-    if let Some(opts) = options {
-        if let Value::Map(options_map) = opts {
-            let opts = options_map.read().expect("lock poisoned");
-
-            if let Some(m) = opts.get("model").or_else(|| opts.get("Model")) {
-                model = extract_string(m).unwrap_or_default();
+fn tool_call_from_value(value: &Value) -> Result<ToolCall, String> {
+    let Value::Map(map) = value else {
+        return Err("Each of ToolCalls must be a Map with an Id and a Name".to_string());
+    };
+    let map = map.read_recover();
+    let text = |name: &str| map.get(name).map(Value::to_display_string);
+    Ok(ToolCall {
+        id: text("Id").ok_or("A tool call needs an Id")?,
+        name: text("Name").ok_or("A tool call needs a Name")?,
+        arguments: map
+            .get("Arguments")
+            .map(convert_object_to_json)
+            .unwrap_or_else(|| json!({})),
+    })
+}
+
+fn new_map(entries: IndexMap<String, Value>) -> Value {
+    Value::Map(Arc::new(std::sync::RwLock::new(entries)))
+}
+
+fn new_list(items: Vec<Value>) -> Value {
+    Value::List(Arc::new(std::sync::RwLock::new(items)))
+}
+
+fn tool_call_value(call: &ToolCall) -> Value {
+    let mut map = IndexMap::new();
+    map.insert("Id".to_string(), Value::String(call.id.clone()));
+    map.insert("Name".to_string(), Value::String(call.name.clone()));
+    map.insert(
+        "Arguments".to_string(),
+        convert_json_to_object(call.arguments.clone()),
+    );
+    new_map(map)
+}
+
+fn message_value(message: &Message) -> Value {
+    let mut map = IndexMap::new();
+    let (role, content) = match message {
+        Message::System(content) => ("system", content),
+        Message::User(content) => ("user", content),
+        Message::Assistant { content, calls } => {
+            if !calls.is_empty() {
+                map.insert(
+                    "ToolCalls".to_string(),
+                    new_list(calls.iter().map(tool_call_value).collect()),
+                );
             }
-            if let Some(k) = opts.get("api_key").or_else(|| opts.get("ApiKey")) {
-                api_key = extract_string(k);
+            ("assistant", content)
+        }
+        Message::Tool { call_id, content } => {
+            map.insert("ToolCallId".to_string(), Value::String(call_id.clone()));
+            ("tool", content)
+        }
+    };
+    map.shift_insert(0, "Role".to_string(), Value::String(role.to_string()));
+    map.shift_insert(1, "Content".to_string(), Value::String(content.clone()));
+    new_map(map)
+}
+
+// 5. TOKEN COUNTING
+//
+// An estimate of about four characters to a token, which is close for
+// English text with the common tokenizers. Providers report the exact
+// counts of each call in its Usage.
+
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+fn message_tokens(message: &Message) -> usize {
+    // Each message also costs a few tokens for its role and separators
+    4 + match message {
+        Message::System(content) | Message::User(content) => estimate_tokens(content),
+        Message::Assistant { content, calls } => {
+            estimate_tokens(content)
+                + calls
+                    .iter()
+                    .map(|call| {
+                        estimate_tokens(&call.name) + estimate_tokens(&call.arguments.to_string())
+                    })
+                    .sum::<usize>()
+        }
+        Message::Tool { content, .. } => estimate_tokens(content),
+    }
+}
+
+fn count_tokens(value: &Value) -> Result<usize, String> {
+    match value {
+        Value::List(list) => Ok(messages_from_list(&list.read_recover())?
+            .iter()
+            .map(message_tokens)
+            .sum()),
+        Value::Map(map) if map.read_recover().contains_key("Messages") => {
+            Ok(conversation_messages(map)?.iter().map(message_tokens).sum())
+        }
+        other => Ok(estimate_tokens(&other.to_display_string())),
+    }
+}
+
+// 6. REQUESTS
+
+fn is_reasoning_model(model: &str) -> bool {
+    model.starts_with("o1")
+        || model.starts_with("o3")
+        || model.starts_with("o4")
+        || model.starts_with("gpt-5")
+}
+
+fn request_body(settings: &Settings, messages: &[Message]) -> JsonValue {
+    let system: Vec<&str> = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::System(content) => Some(content.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut body = match settings.provider {
+        Provider::OpenAI => responses_body(settings, messages),
+        Provider::Anthropic => anthropic_body(settings, messages),
+        Provider::Ollama | Provider::Local => chat_completions_body(settings, messages),
+    };
+    let fields = body.as_object_mut().expect("request bodies are objects");
+    if !system.is_empty() {
+        match settings.provider {
+            Provider::OpenAI => fields.insert("instructions".into(), json!(system.join("\n\n"))),
+            Provider::Anthropic => fields.insert("system".into(), json!(system.join("\n\n"))),
+            // Sent as messages of their own
+            Provider::Ollama | Provider::Local => None,
+        };
+    }
+    if settings.stream {
+        fields.insert("stream".into(), json!(true));
+    }
+    body
+}
+
+// v1/responses
+fn responses_body(settings: &Settings, messages: &[Message]) -> JsonValue {
+    let mut input = Vec::new();
+    for message in messages {
+        match message {
+            Message::System(_) => {}
+            Message::User(content) => input.push(json!({
+                "type": "message",
+                "role": "user",
+                "content": [{ "type": "input_text", "text": content }]
+            })),
+            Message::Assistant { content, calls } => {
+                if !content.is_empty() {
+                    input.push(json!({
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [{ "type": "output_text", "text": content }]
+                    }));
+                }
+                for call in calls {
+                    input.push(json!({
+                        "type": "function_call",
+                        "call_id": call.id,
+                        "name": call.name,
+                        "arguments": call.arguments.to_string()
+                    }));
+                }
             }
-            if let Some(mt) = opts
-                .get("max_output_tokens")
-                .or_else(|| opts.get("MaxOutputTokens"))
-            {
-                if let Value::Number(n) = mt {
-                    let s = n.to_string();
-                    if let Ok(parsed) = s.parse::<i64>() {
-                        max_output_tokens = Some(parsed);
-                    }
-                } else if let Some(mt_legacy) = opts.get("max_tokens") {
-                    if let Value::Number(n) = mt_legacy {
-                        let s = n.to_string();
-                        if let Ok(parsed) = s.parse::<i64>() {
-                            max_output_tokens = Some(parsed);
-                        }
-                    }
+            Message::Tool { call_id, content } => input.push(json!({
+                "type": "function_call_output",
+                "call_id": call_id,
+                "output": content
+            })),
+        }
+    }
+
+    let mut body = json!({ "model": settings.model, "input": input });
+    let fields = body.as_object_mut().expect("object");
+    if let Some(max) = settings.max_output_tokens {
+        fields.insert("max_output_tokens".into(), json!(max));
+    }
+    let mut reasoning = settings
+        .reasoning_effort
+        .as_ref()
+        .map(|effort| json!({ "effort": effort }));
+
+    // for model specific parameters
+    if is_reasoning_model(&settings.model) {
+        // Make sure reasoning block exists
+        reasoning.get_or_insert_with(|| json!({ "effort": "medium" }));
+
+        // Add text formatting config
+        fields.insert(
+            "text".into(),
+            json!({ "format": { "type": "text" }, "verbosity": "medium" }),
+        );
+    } else if let Some(temperature) = settings.temperature {
+        fields.insert("temperature".into(), json!(temperature));
+    }
+    if let Some(reasoning) = reasoning {
+        fields.insert("reasoning".into(), reasoning);
+    }
+    if !settings.tools.is_empty() {
+        let tools: Vec<JsonValue> = settings
+            .tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.parameters
+                })
+            })
+            .collect();
+        fields.insert("tools".into(), json!(tools));
+    }
+    body
+}
+
+// v1/messages
+fn anthropic_body(settings: &Settings, messages: &[Message]) -> JsonValue {
+    let mut turns: Vec<JsonValue> = Vec::new();
+    for message in messages {
+        match message {
+            Message::System(_) => {}
+            Message::User(content) => turns.push(json!({ "role": "user", "content": content })),
+            Message::Assistant { content, calls } => {
+                let mut blocks = Vec::new();
+                if !content.is_empty() {
+                    blocks.push(json!({ "type": "text", "text": content }));
+                }
+                for call in calls {
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.name,
+                        "input": call.arguments
+                    }));
                 }
+                turns.push(json!({ "role": "assistant", "content": blocks }));
             }
-            if let Some(t) = opts.get("temperature").or_else(|| opts.get("Temperature")) {
-                if let Value::Number(n) = t {
-                    let s = n.to_string();
-                    if let Ok(parsed) = s.parse::<f64>() {
-                        temperature = Some(parsed);
+            // The results of one turn's calls go together in one user turn
+            Message::Tool { call_id, content } => {
+                let result = json!({
+                    "type": "tool_result",
+                    "tool_use_id": call_id,
+                    "content": content
+                });
+                match turns.last_mut() {
+                    Some(last)
+                        if last["role"] == "user"
+                            && last["content"][0]["type"] == "tool_result" =>
+                    {
+                        if let Some(blocks) = last["content"].as_array_mut() {
+                            blocks.push(result);
+                        }
                     }
+                    _ => turns.push(json!({ "role": "user", "content": [result] })),
                 }
             }
-            if let Some(r) = opts
-                .get("reasoning_effort")
-                .or_else(|| opts.get("ReasoningEffort"))
-            {
-                let effort = extract_string(r).unwrap_or("medium".to_string());
-                reasoning_config = Some(json!({ "effort": effort }));
-            }
         }
     }
 
-    // for model specific parameters
-    let is_reasoning_model = model.starts_with("o1")
-        || model.starts_with("o3")
-        || model.starts_with("o4")
-        || model.starts_with("gpt-5");
+    let mut body = json!({
+        "model": settings.model,
+        "messages": turns,
+        "max_tokens": settings.max_output_tokens.unwrap_or(4096)
+    });
+    let fields = body.as_object_mut().expect("object");
+    if let Some(temperature) = settings.temperature {
+        fields.insert("temperature".into(), json!(temperature));
+    }
+    if !settings.tools.is_empty() {
+        let tools: Vec<JsonValue> = settings
+            .tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "input_schema": tool.parameters
+                })
+            })
+            .collect();
+        fields.insert("tools".into(), json!(tools));
+    }
+    body
+}
 
-    if is_reasoning_model {
-        // Remove temperature
-        temperature = None;
+// v1/chat/completions
+fn chat_completions_body(settings: &Settings, messages: &[Message]) -> JsonValue {
+    let turns: Vec<JsonValue> = messages
+        .iter()
+        .map(|message| match message {
+            Message::System(content) => json!({ "role": "system", "content": content }),
+            Message::User(content) => json!({ "role": "user", "content": content }),
+            Message::Assistant { content, calls } if calls.is_empty() => {
+                json!({ "role": "assistant", "content": content })
+            }
+            Message::Assistant { content, calls } => {
+                let calls: Vec<JsonValue> = calls
+                    .iter()
+                    .map(|call| {
+                        json!({
+                            "id": call.id,
+                            "type": "function",
+                            "function": {
+                                "name": call.name,
+                                "arguments": call.arguments.to_string()
+                            }
+                        })
+                    })
+                    .collect();
+                json!({ "role": "assistant", "content": content, "tool_calls": calls })
+            }
+            Message::Tool { call_id, content } => {
+                json!({ "role": "tool", "tool_call_id": call_id, "content": content })
+            }
+        })
+        .collect();
 
-        // Make sure reasoning block exists
-        if reasoning_config.is_none() {
-            reasoning_config = Some(json!({ "effort": "medium" }));
-        }
+    let mut body = json!({ "model": settings.model, "messages": turns });
+    let fields = body.as_object_mut().expect("object");
+    if let Some(max) = settings.max_output_tokens {
+        fields.insert("max_tokens".into(), json!(max));
+    }
+    if let Some(temperature) = settings.temperature {
+        fields.insert("temperature".into(), json!(temperature));
+    }
+    if !settings.tools.is_empty() {
+        let tools: Vec<JsonValue> = settings
+            .tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.parameters
+                    }
+                })
+            })
+            .collect();
+        fields.insert("tools".into(), json!(tools));
+    }
+    body
+}
 
-        // Add text formatting config
-        text_config = Some(json!({
-            "format": { "type": "text" },
-            "verbosity": "medium"
-        }));
-    }
-
-    let key = api_key
-        .or_else(|| std::env::var("OPENAI_API_KEY").ok())
-        .ok_or("OPENAI_API_KEY not found")?;
-
-    let request_body = CreateResponseRequest {
-        model,
-        input: Some(input_payload),
-        instructions,
-        max_output_tokens,
-        temperature,
-        reasoning: reasoning_config,
-        text: text_config,
+fn send(settings: &Settings, body: &JsonValue) -> Result<Response, String> {
+    let (endpoint, key_required) = match settings.provider {
+        Provider::OpenAI => ("responses", true),
+        Provider::Anthropic => ("messages", true),
+        Provider::Ollama | Provider::Local => ("chat/completions", false),
     };
+    permissions::check_url(&settings.base_url)?;
+    let mut request = HTTP_CLIENT
+        .post(format!("{}/{}", settings.base_url, endpoint))
+        .json(body)
+        .timeout(deadline::limit(Some(LLM_TIMEOUT)).unwrap_or(LLM_TIMEOUT));
+    match (&settings.api_key, settings.provider) {
+        (Some(key), Provider::Anthropic) => {
+            request = request
+                .header("x-api-key", key)
+                .header("anthropic-version", "2023-06-01");
+        }
+        (Some(key), _) => request = request.header("Authorization", format!("Bearer {}", key)),
+        (None, provider) if key_required => {
+            return Err(format!(
+                "{} not found",
+                provider.key_env().unwrap_or("API key")
+            ));
+        }
+        (None, _) => {}
+    }
 
-    let response = HTTP_CLIENT
-        .post("https://api.openai.com/v1/responses")
-        .header("Authorization", format!("Bearer {}", key))
-        .json(&request_body)
-        .send()
-        .map_err(|e| format!("Network error: {}", e))?;
+    deadline::check()?;
+    let response = request.send().map_err(|e| match deadline::check() {
+        Err(exceeded) => exceeded,
+        Ok(()) => format!("Network error: {}", e),
+    })?;
 
     let status = response.status();
-    let response_text = response.text().unwrap_or_default();
-
     if !status.is_success() {
         return Err(format!(
-            "OpenAI Responses API Error {}: {}",
-            status, response_text
+            "{} API Error {}: {}",
+            settings.provider.label(),
+            status,
+            response.text().unwrap_or_default()
         ));
     }
+    Ok(response)
+}
 
-    let api_response: OpenAIResponseObj = serde_json::from_str(&response_text).map_err(|e| {
+fn post(settings: &Settings, body: &JsonValue) -> Result<(u16, JsonValue), String> {
+    let response = send(settings, body)?;
+    let status = response.status().as_u16();
+    let response_text = response.text().unwrap_or_default();
+    let json = serde_json::from_str(&response_text).map_err(|e| {
         format!(
             "Failed to parse JSON. \nError: {} \nRaw Response: {}",
             e, response_text
         )
     })?;
+    Ok((status, json))
+}
 
-    let mut result_map = HashMap::new();
+// 7. REPLIES
 
-    result_map.insert(
-        "Status".to_string(),
-        Value::Number(BigDecimal::from(status.as_u16())),
-    );
-    result_map.insert(
-        "Id".to_string(),
-        Value::String(api_response.id.unwrap_or_default()),
-    );
-    result_map.insert(
-        "Model".to_string(),
-        Value::String(api_response.model.unwrap_or_default()),
-    );
+#[derive(Debug, Default)]
+struct Reply {
+    status: u16,
+    id: String,
+    model: String,
+    content: String,
+    role: String,
+    finish: String,
+    // Input, output and total tokens
+    usage: Option<(i64, i64, i64)>,
+    calls: Vec<ToolCall>,
+}
 
-    let mut main_content = String::new();
-    let mut role = String::new();
-    let mut finish_status = String::new();
+fn text_of(value: &JsonValue) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
 
-    if let Some(outputs) = api_response.output {
-        for item in outputs {
-            if item.item_type.as_deref().unwrap_or("message") == "message" {
-                if let Some(r) = &item.role {
-                    role = r.clone();
-                }
+// Arguments come as JSON text from OpenAI-style APIs
+fn arguments_of(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::String(text) => {
+            serde_json::from_str(text).unwrap_or_else(|_| JsonValue::String(text.clone()))
+        }
+        other => other.clone(),
+    }
+}
 
-                finish_status = item.status.unwrap_or_else(|| "unknown".to_string());
+fn parse_reply(provider: Provider, status: u16, json: &JsonValue) -> Reply {
+    let mut reply = Reply {
+        status,
+        id: text_of(&json["id"]),
+        model: text_of(&json["model"]),
+        ..Reply::default()
+    };
+    let usage = &json["usage"];
+    let count = |name: &str| usage[name].as_i64().unwrap_or(0);
 
-                if let Some(contents) = &item.content {
-                    for content in contents {
-                        if content.content_type.as_deref().unwrap_or("output_text") == "output_text"
-                        {
-                            main_content.push_str(&content.text);
+    match provider {
+        Provider::OpenAI => {
+            for item in json["output"].as_array().into_iter().flatten() {
+                match item["type"].as_str().unwrap_or("message") {
+                    "message" => {
+                        if let Some(role) = item["role"].as_str() {
+                            reply.role = role.to_string();
+                        }
+                        reply.finish = item["status"].as_str().unwrap_or("unknown").to_string();
+                        for content in item["content"].as_array().into_iter().flatten() {
+                            if content["type"].as_str().unwrap_or("output_text") == "output_text" {
+                                reply
+                                    .content
+                                    .push_str(content["text"].as_str().unwrap_or(""));
+                            }
                         }
                     }
+                    "function_call" => reply.calls.push(ToolCall {
+                        id: text_of(&item["call_id"]),
+                        name: text_of(&item["name"]),
+                        arguments: arguments_of(&item["arguments"]),
+                    }),
+                    _ => {}
                 }
             }
+            if !reply.calls.is_empty() {
+                reply.role = "assistant".to_string();
+                reply.finish = "tool_calls".to_string();
+            }
+            if usage.is_object() {
+                reply.usage = Some((
+                    count("input_tokens"),
+                    count("output_tokens"),
+                    count("total_tokens"),
+                ));
+            }
+        }
+        Provider::Anthropic => {
+            reply.role = text_of(&json["role"]);
+            reply.finish = text_of(&json["stop_reason"]);
+            for block in json["content"].as_array().into_iter().flatten() {
+                match block["type"].as_str() {
+                    Some("text") => reply.content.push_str(block["text"].as_str().unwrap_or("")),
+                    Some("tool_use") => reply.calls.push(ToolCall {
+                        id: text_of(&block["id"]),
+                        name: text_of(&block["name"]),
+                        arguments: block["input"].clone(),
+                    }),
+                    _ => {}
+                }
+            }
+            if usage.is_object() {
+                let (input, output) = (count("input_tokens"), count("output_tokens"));
+                reply.usage = Some((input, output, input + output));
+            }
+        }
+        Provider::Ollama | Provider::Local => {
+            let choice = &json["choices"][0];
+            let message = &choice["message"];
+            reply.role = text_of(&message["role"]);
+            reply.content = text_of(&message["content"]);
+            reply.finish = text_of(&choice["finish_reason"]);
+            for call in message["tool_calls"].as_array().into_iter().flatten() {
+                reply.calls.push(ToolCall {
+                    id: text_of(&call["id"]),
+                    name: text_of(&call["function"]["name"]),
+                    arguments: arguments_of(&call["function"]["arguments"]),
+                });
+            }
+            if usage.is_object() {
+                reply.usage = Some((
+                    count("prompt_tokens"),
+                    count("completion_tokens"),
+                    count("total_tokens"),
+                ));
+            }
         }
     }
+    reply
+}
 
-    result_map.insert("Content".to_string(), Value::String(main_content));
-    result_map.insert("Role".to_string(), Value::String(role));
-    result_map.insert(
-        "FinishStatus".to_string(),
-        Value::String(finish_status.clone()),
-    );
-    result_map.insert("FinishReason".to_string(), Value::String(finish_status));
+fn usage_value((input, output, total): (i64, i64, i64)) -> Value {
+    let mut usage_map = IndexMap::new();
+    let input = BigDecimal::from(input);
+    let output = BigDecimal::from(output);
+    let total = BigDecimal::from(total);
 
-    if let Some(usage) = api_response.usage {
-        let mut usage_map = HashMap::new();
-        let input = BigDecimal::from(usage.input_tokens);
-        let output = BigDecimal::from(usage.output_tokens);
-        let total = BigDecimal::from(usage.total_tokens);
+    usage_map.insert("InputTokens".to_string(), Value::Number(input.clone()));
+    usage_map.insert("OutputTokens".to_string(), Value::Number(output.clone()));
+    usage_map.insert("PromptTokens".to_string(), Value::Number(input));
+    usage_map.insert("CompletionTokens".to_string(), Value::Number(output));
+    usage_map.insert("TotalTokens".to_string(), Value::Number(total));
+    new_map(usage_map)
+}
 
-        usage_map.insert("InputTokens".to_string(), Value::Number(input.clone()));
-        usage_map.insert("OutputTokens".to_string(), Value::Number(output.clone()));
-        usage_map.insert("PromptTokens".to_string(), Value::Number(input));
-        usage_map.insert("CompletionTokens".to_string(), Value::Number(output));
-        usage_map.insert("TotalTokens".to_string(), Value::Number(total));
+impl Reply {
+    fn to_value(&self, message: &Message) -> Value {
+        let mut result_map = IndexMap::new();
 
         result_map.insert(
-            "Usage".to_string(),
-            Value::Map(Arc::new(std::sync::RwLock::new(usage_map))),
+            "Status".to_string(),
+            Value::Number(BigDecimal::from(self.status)),
+        );
+        result_map.insert("Id".to_string(), Value::String(self.id.clone()));
+        result_map.insert("Model".to_string(), Value::String(self.model.clone()));
+        result_map.insert("Content".to_string(), Value::String(self.content.clone()));
+        result_map.insert("Role".to_string(), Value::String(self.role.clone()));
+        result_map.insert(
+            "FinishStatus".to_string(),
+            Value::String(self.finish.clone()),
+        );
+        result_map.insert(
+            "FinishReason".to_string(),
+            Value::String(self.finish.clone()),
         );
+        if let Some(usage) = self.usage {
+            result_map.insert("Usage".to_string(), usage_value(usage));
+        }
+        result_map.insert(
+            "ToolCalls".to_string(),
+            new_list(self.calls.iter().map(tool_call_value).collect()),
+        );
+        result_map.insert("Message".to_string(), message_value(message));
+        new_map(result_map)
+    }
+}
+
+// 8. TOOL CALLS
+
+/// Runs a tool's Handler with the arguments Map the model gave it, by the
+/// tool's name. The interpreter passes one that can call concept methods.
+pub type ToolDispatch<'a> = dyn FnMut(&Value, &str, Value) -> Result<Value, String> + 'a;
+
+fn native_dispatch(handler: &Value, name: &str, arguments: Value) -> Result<Value, String> {
+    match handler {
+        Value::NativeFunction(function) => function(vec![arguments]),
+        _ => Err(format!(
+            "The Handler of tool {} can only be run by calling LLM.Chat(...) directly",
+            name
+        )),
+    }
+}
+
+/// What a tool gives back, as the text the model reads
+fn tool_output(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => convert_object_to_json(other).to_string(),
     }
+}
+
+type Post<'a> = dyn FnMut(&Settings, &JsonValue) -> Result<(u16, JsonValue), String> + 'a;
+
+/// Ask the model, answering the tool calls it makes with their handlers
+/// until it replies without any, or with a call the script answers itself.
+/// The replies and tool results are added to `messages`.
+fn converse(
+    settings: &Settings,
+    messages: &mut Vec<Message>,
+    dispatch: &mut ToolDispatch,
+    post: &mut Post,
+) -> Result<Reply, String> {
+    let mut total: Option<(i64, i64, i64)> = None;
+    let mut round = 0;
+    loop {
+        let (status, json) = post(settings, &request_body(settings, messages))?;
+        let mut reply = parse_reply(settings.provider, status, &json);
+        if let Some((input, output, sum)) = reply.usage {
+            let (i, o, s) = total.get_or_insert((0, 0, 0));
+            *i += input;
+            *o += output;
+            *s += sum;
+        }
+        reply.usage = total;
+        messages.push(Message::Assistant {
+            content: reply.content.clone(),
+            calls: reply.calls.clone(),
+        });
+
+        let handlers: Option<Vec<&Value>> = reply
+            .calls
+            .iter()
+            .map(|call| {
+                settings
+                    .tools
+                    .iter()
+                    .find(|tool| tool.name == call.name)
+                    .and_then(|tool| tool.handler.as_ref())
+            })
+            .collect();
+        let handlers = match handlers {
+            Some(handlers) if !handlers.is_empty() => handlers,
+            _ => return Ok(reply),
+        };
+        if round == settings.max_tool_rounds {
+            return Err(format!(
+                "LLM.Chat: the model was still calling tools after {} rounds (see MaxToolRounds)",
+                round
+            ));
+        }
+        round += 1;
 
-    Ok(Value::Map(Arc::new(std::sync::RwLock::new(result_map))))
+        for (call, handler) in reply.calls.iter().zip(handlers) {
+            let output = dispatch(
+                handler,
+                &call.name,
+                convert_json_to_object(call.arguments.clone()),
+            )
+            .map_err(|e| format!("LLM tool {}: {}", call.name, e))?;
+            messages.push(Message::Tool {
+                call_id: call.id.clone(),
+                content: tool_output(&output),
+            });
+        }
+    }
+}
+
+// 9. STREAMING
+//
+// With Stream, the reply comes back as a Stream of text pieces, read from
+// the provider's server-sent events as the script takes them.
+
+#[derive(Debug)]
+enum Event {
+    Text(String),
+    Done,
+    Skip,
+}
+
+fn stream_event(provider: Provider, data: &str) -> Result<Event, String> {
+    if data == "[DONE]" {
+        return Ok(Event::Done);
+    }
+    let json: JsonValue = serde_json::from_str(data)
+        .map_err(|e| format!("LLM stream: bad event ({}): {}", e, data))?;
+    let error = |json: &JsonValue| {
+        let message = &json["error"]["message"];
+        Err(format!(
+            "{} API Error: {}",
+            provider.label(),
+            message.as_str().unwrap_or(data)
+        ))
+    };
+    match provider {
+        Provider::OpenAI => match json["type"].as_str().unwrap_or("") {
+            "response.output_text.delta" => Ok(Event::Text(text_of(&json["delta"]))),
+            "response.completed" => Ok(Event::Done),
+            "response.failed" => error(&json["response"]),
+            "error" => Err(format!(
+                "{} API Error: {}",
+                provider.label(),
+                json["message"].as_str().unwrap_or(data)
+            )),
+            _ => Ok(Event::Skip),
+        },
+        Provider::Anthropic => match json["type"].as_str().unwrap_or("") {
+            "content_block_delta" if json["delta"]["type"] == "text_delta" => {
+                Ok(Event::Text(text_of(&json["delta"]["text"])))
+            }
+            "message_stop" => Ok(Event::Done),
+            "error" => error(&json),
+            _ => Ok(Event::Skip),
+        },
+        Provider::Ollama | Provider::Local => {
+            if json["error"].is_object() {
+                return error(&json);
+            }
+            match json["choices"][0]["delta"]["content"].as_str() {
+                Some(text) => Ok(Event::Text(text.to_string())),
+                None => Ok(Event::Skip),
+            }
+        }
+    }
+}
+
+struct StreamState {
+    reader: BufReader<Response>,
+    // The reply so far, added to the conversation at the end
+    text: String,
+}
+
+fn open_stream(
+    settings: &Settings,
+    messages: &[Message],
+    conversation: Option<Arc<std::sync::RwLock<IndexMap<String, Value>>>>,
+) -> Result<Value, String> {
+    let response = send(settings, &request_body(settings, messages))?;
+    let provider = settings.provider;
+    let state = Mutex::new(Some(StreamState {
+        reader: BufReader::new(response),
+        text: String::new(),
+    }));
+
+    let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+        let mut guard = state.lock_recover();
+        loop {
+            let Some(active) = guard.as_mut() else {
+                return Ok(Value::Option(Box::new(None)));
+            };
+            let mut line = String::new();
+            let event = match active.reader.read_line(&mut line) {
+                Ok(0) => Ok(Event::Done),
+                Ok(_) => match line.trim().strip_prefix("data:") {
+                    Some(data) => stream_event(provider, data.trim()),
+                    None => Ok(Event::Skip),
+                },
+                Err(e) => Err(format!("LLM stream error: {}", e)),
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    guard.take();
+                    return Err(e);
+                }
+            };
+            match event {
+                Event::Text(text) if !text.is_empty() => {
+                    active.text.push_str(&text);
+                    return Ok(Value::Option(Box::new(Some(Value::String(text)))));
+                }
+                Event::Done => {
+                    if let Some(finished) = guard.take()
+                        && let Some(conversation) = &conversation
+                    {
+                        let reply = Message::Assistant {
+                            content: finished.text,
+                            calls: Vec::new(),
+                        };
+                        record(conversation, std::slice::from_ref(&reply), None);
+                    }
+                    return Ok(Value::Option(Box::new(None)));
+                }
+                _ => {}
+            }
+        }
+    })));
+    Ok(crate::stdlib::stream::create_stream_object(
+        vec![],
+        Some(generator),
+    ))
+}
+
+// 10. CONVERSATIONS
+//
+// A conversation is a Map with the System prompt, the Messages so far and
+// the Usage of every call. LLM.Chat sends its messages and adds the reply;
+// with ContextTokens set, the oldest messages are dropped first so the
+// conversation stays under that many tokens.
+
+fn conversation_messages(
+    conversation: &Arc<std::sync::RwLock<IndexMap<String, Value>>>,
+) -> Result<Vec<Message>, String> {
+    let map = conversation.read_recover();
+    let mut messages = Vec::new();
+    if let Some(system) = map.get("System").map(Value::to_display_string)
+        && !system.is_empty()
+    {
+        messages.push(Message::System(system));
+    }
+    if let Some(Value::List(list)) = map.get("Messages") {
+        messages.extend(messages_from_list(&list.read_recover())?);
+    }
+    Ok(messages)
+}
+
+/// Drop the oldest messages of a conversation until it fits its
+/// ContextTokens, keeping the latest; the first kept is a user message, so
+/// no tool result is left without the call it answers.
+fn trim_conversation(conversation: &Arc<std::sync::RwLock<IndexMap<String, Value>>>) {
+    let map = conversation.read_recover();
+    let limit = match map.get("ContextTokens") {
+        Some(limit @ (Value::Number(_) | Value::Integer(_))) => {
+            limit.to_display_string().parse::<usize>().ok()
+        }
+        _ => None,
+    };
+    let (Some(limit), Some(Value::List(list))) = (limit, map.get("Messages")) else {
+        return;
+    };
+    let system = map
+        .get("System")
+        .map(|system| message_tokens(&Message::System(system.to_display_string())))
+        .unwrap_or(0);
+
+    let mut list = list.write_recover();
+    let Ok(messages) = messages_from_list(&list) else {
+        return;
+    };
+    let mut used = system + messages.iter().map(message_tokens).sum::<usize>();
+    let mut start = 0;
+    while used > limit && start + 1 < messages.len() {
+        used -= message_tokens(&messages[start]);
+        start += 1;
+    }
+    while start + 1 < messages.len() && !matches!(messages[start], Message::User(_)) {
+        start += 1;
+    }
+    list.drain(..start);
+}
+
+/// Add new messages to a conversation, and the tokens they used to its Usage.
+fn record(
+    conversation: &Arc<std::sync::RwLock<IndexMap<String, Value>>>,
+    messages: &[Message],
+    usage: Option<(i64, i64, i64)>,
+) {
+    let mut map = conversation.write_recover();
+    match map.get("Messages") {
+        Some(Value::List(list)) => list
+            .write_recover()
+            .extend(messages.iter().map(message_value)),
+        _ => {
+            map.insert(
+                "Messages".to_string(),
+                new_list(messages.iter().map(message_value).collect()),
+            );
+        }
+    }
+    let Some((input, output, total)) = usage else {
+        return;
+    };
+    let previous = match map.get("Usage") {
+        Some(Value::Map(usage)) => {
+            let usage = usage.read_recover();
+            let count = |name: &str| {
+                usage
+                    .get(name)
+                    .and_then(|n| n.to_display_string().parse::<i64>().ok())
+                    .unwrap_or(0)
+            };
+            (
+                count("InputTokens"),
+                count("OutputTokens"),
+                count("TotalTokens"),
+            )
+        }
+        _ => (0, 0, 0),
+    };
+    map.insert(
+        "Usage".to_string(),
+        usage_value((previous.0 + input, previous.1 + output, previous.2 + total)),
+    );
+}
+
+fn create_conversation(system: String, context_tokens: Option<Value>) -> Value {
+    let mut map = IndexMap::new();
+    map.insert("System".to_string(), Value::String(system));
+    map.insert("Messages".to_string(), new_list(Vec::new()));
+    map.insert("Usage".to_string(), usage_value((0, 0, 0)));
+    if let Some(limit) = context_tokens {
+        map.insert("ContextTokens".to_string(), limit);
+    }
+    new_map(map)
+}
+
+/// `LLM.Chat(messages, options?)`. `messages` is a List of message Maps, a
+/// conversation, or a text; a conversation can be followed by the text of
+/// the next user message. Tool calls are answered through `dispatch`.
+pub fn chat(args: &[Value], dispatch: &mut ToolDispatch) -> Result<Value, String> {
+    if args.is_empty() {
+        return Err("Requires messages list".to_string());
+    }
+
+    let conversation = match &args[0] {
+        Value::Map(map) if map.read_recover().contains_key("Messages") => Some(map.clone()),
+        _ => None,
+    };
+    let next = match (&conversation, args.get(1)) {
+        (Some(_), Some(Value::String(text))) => Some(Message::User(text.clone())),
+        _ => None,
+    };
+    let settings = settings(args.get(if next.is_some() { 2 } else { 1 }))?;
+    if let (Some(conversation), Some(next)) = (&conversation, next) {
+        record(conversation, &[next], None);
+    }
+
+    let messages = match (&args[0], &conversation) {
+        (_, Some(conversation)) => {
+            trim_conversation(conversation);
+            conversation_messages(conversation)?
+        }
+        (Value::List(list), None) => messages_from_list(&list.read_recover())?,
+        (Value::String(text), None) => vec![Message::User(text.clone())],
+        _ => {
+            return Err("LLM.Chat takes a List of messages, a conversation or a text".to_string());
+        }
+    };
+    respond(&settings, messages, conversation, dispatch)
+}
+
+fn respond(
+    settings: &Settings,
+    mut messages: Vec<Message>,
+    conversation: Option<Arc<std::sync::RwLock<IndexMap<String, Value>>>>,
+    dispatch: &mut ToolDispatch,
+) -> Result<Value, String> {
+    if settings.stream {
+        if !settings.tools.is_empty() {
+            return Err(
+                "LLM.Chat can't stream a reply that may call Tools; leave out Stream or Tools"
+                    .to_string(),
+            );
+        }
+        return open_stream(settings, &messages, conversation);
+    }
+
+    let sent = messages.len();
+    let reply = converse(settings, &mut messages, dispatch, &mut post)?;
+    if let Some(conversation) = &conversation {
+        record(conversation, &messages[sent..], reply.usage);
+    }
+    Ok(reply.to_value(messages.last().expect("converse adds the reply")))
 }
 
 pub fn create_llm_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Simple".to_string(),
@@ -278,8 +1286,13 @@ pub fn create_llm_module() -> Value {
                 return Err("Requires prompt string".to_string());
             }
             let prompt = args[0].to_display_string();
-            let options = args.get(1);
-            call_responses_api(json!(prompt), None, options)
+            let settings = settings(args.get(1))?;
+            respond(
+                &settings,
+                vec![Message::User(prompt)],
+                None,
+                &mut native_dispatch,
+            )
         }))),
     );
 
@@ -291,62 +1304,241 @@ pub fn create_llm_module() -> Value {
             }
             let system_prompt = args[0].to_display_string();
             let user_prompt = args[1].to_display_string();
-            let options = args.get(2);
-            call_responses_api(json!(user_prompt), Some(system_prompt), options)
+            let settings = settings(args.get(2))?;
+            respond(
+                &settings,
+                vec![Message::System(system_prompt), Message::User(user_prompt)],
+                None,
+                &mut native_dispatch,
+            )
         }))),
     );
 
+    // Called directly, LLM.Chat is run by the interpreter, so tool handlers
+    // can be concept instances
     methods.insert(
         "Chat".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| chat(&args, &mut native_dispatch)))),
+    );
+
+    // LLM.Conversation(system?, context_tokens?)
+    methods.insert(
+        "Conversation".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
-            if args.is_empty() {
-                return Err("Requires messages list".to_string());
+            if args.len() > 2 {
+                return Err(
+                    "LLM.Conversation takes up to 2 arguments (system, optional context_tokens)"
+                        .to_string(),
+                );
             }
+            let system = args
+                .first()
+                .map(Value::to_display_string)
+                .unwrap_or_default();
+            Ok(create_conversation(system, args.get(1).cloned()))
+        }))),
+    );
 
-            let msgs_list = match &args[0] {
-                Value::List(l) => l.read().expect("lock poisoned").clone(),
-                _ => return Err("First arg must be list".to_string()),
-            };
-
-            let mut input_array = Vec::new();
-            let mut instruction_text = None;
-
-            for item in msgs_list {
-                if let Value::Map(m) = item {
-                    let m = m.read().expect("lock poisoned");
-                    let role = m
-                        .get("role")
-                        .or_else(|| m.get("Role"))
-                        .map(|v| v.to_display_string())
-                        .unwrap_or("user".to_string());
-
-                    let content = m
-                        .get("content")
-                        .or_else(|| m.get("Content"))
-                        .map(|v| v.to_display_string())
-                        .unwrap_or_default();
-
-                    if role == "system" {
-                        instruction_text = Some(content);
-                    } else {
-                        let content_type = if role == "assistant" {
-                            "output_text"
-                        } else {
-                            "input_text"
-                        };
-                        input_array.push(json!({
-                            "type": "message",
-                            "role": role,
-                            "content": [{ "type": content_type, "text": content }]
-                        }));
-                    }
-                }
+    methods.insert(
+        "CountTokens".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err(
+                    "LLM.CountTokens requires 1 argument (text, messages or conversation)"
+                        .to_string(),
+                );
             }
-
-            let options = args.get(1);
-            call_responses_api(json!(input_array), instruction_text, options)
+            Ok(Value::Integer(count_tokens(&args[0])?.into()))
         }))),
     );
 
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_settings(tools: Vec<Tool>) -> Settings {
+        Settings {
+            provider: Provider::Local,
+            model: "test".to_string(),
+            base_url: "http://localhost:8080/v1".to_string(),
+            api_key: None,
+            max_output_tokens: None,
+            temperature: None,
+            reasoning_effort: None,
+            stream: false,
+            tools,
+            max_tool_rounds: 8,
+        }
+    }
+
+    #[test]
+    fn test_tool_calls() {
+        let weather = Value::NativeFunction(Arc::new(Box::new(|args| {
+            let Value::Map(arguments) = &args[0] else {
+                return Err("arguments".to_string());
+            };
+            let city = arguments.read_recover()["city"].to_display_string();
+            Ok(Value::String(format!("Sunny in {}", city)))
+        })));
+        let settings = local_settings(vec![Tool {
+            name: "Weather".to_string(),
+            description: String::new(),
+            parameters: json!({}),
+            handler: Some(weather),
+        }]);
+
+        let mut bodies = Vec::new();
+        let mut post = |_: &Settings, body: &JsonValue| {
+            bodies.push(body.clone());
+            let reply = if bodies.len() == 1 {
+                json!({ "choices": [{ "finish_reason": "tool_calls", "message": { "role": "assistant", "content": null, "tool_calls": [{ "id": "c1", "type": "function", "function": { "name": "Weather", "arguments": "{\"city\":\"Ulaanbaatar\"}" } }] } }], "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 } })
+            } else {
+                json!({ "choices": [{ "finish_reason": "stop", "message": { "role": "assistant", "content": "It is sunny." } }], "usage": { "prompt_tokens": 20, "completion_tokens": 4, "total_tokens": 24 } })
+            };
+            Ok((200, reply))
+        };
+
+        let mut messages = vec![Message::User("Weather?".to_string())];
+        let reply = converse(&settings, &mut messages, &mut native_dispatch, &mut post).unwrap();
+        assert_eq!(reply.content, "It is sunny.");
+        assert_eq!(reply.usage, Some((30, 9, 39)));
+        assert_eq!(
+            messages[2],
+            Message::Tool {
+                call_id: "c1".to_string(),
+                content: "Sunny in Ulaanbaatar".to_string()
+            }
+        );
+        assert_eq!(bodies[1]["messages"][2]["role"], "tool");
+        assert_eq!(
+            bodies[1]["messages"][1]["tool_calls"][0]["function"]["name"],
+            "Weather"
+        );
+    }
+
+    #[test]
+    fn test_request_bodies() {
+        let mut settings = local_settings(Vec::new());
+        let messages = vec![
+            Message::System("Be brief.".to_string()),
+            Message::User("Hi".to_string()),
+            Message::Assistant {
+                content: String::new(),
+                calls: vec![ToolCall {
+                    id: "t1".to_string(),
+                    name: "Time".to_string(),
+                    arguments: json!({}),
+                }],
+            },
+            Message::Tool {
+                call_id: "t1".to_string(),
+                content: "noon".to_string(),
+            },
+        ];
+
+        settings.provider = Provider::Anthropic;
+        let body = request_body(&settings, &messages);
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(body["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(body["messages"][2]["content"][0]["tool_use_id"], "t1");
+
+        settings.provider = Provider::OpenAI;
+        settings.stream = true;
+        let body = request_body(&settings, &messages);
+        assert_eq!(body["instructions"], "Be brief.");
+        assert_eq!(body["input"][1]["type"], "function_call");
+        assert_eq!(body["input"][2]["output"], "noon");
+        assert_eq!(body["stream"], true);
+    }
+
+    #[test]
+    fn test_stream_events() {
+        let text = |provider, data| match stream_event(provider, data).unwrap() {
+            Event::Text(text) => Some(text),
+            _ => None,
+        };
+        assert_eq!(
+            text(
+                Provider::OpenAI,
+                r#"{"type":"response.output_text.delta","delta":"Hel"}"#
+            ),
+            Some("Hel".to_string())
+        );
+        assert_eq!(
+            text(
+                Provider::Anthropic,
+                r#"{"type":"content_block_delta","delta":{"type":"text_delta","text":"lo"}}"#
+            ),
+            Some("lo".to_string())
+        );
+        assert_eq!(
+            text(
+                Provider::Ollama,
+                r#"{"choices":[{"delta":{"content":"!"}}]}"#
+            ),
+            Some("!".to_string())
+        );
+        assert!(matches!(
+            stream_event(Provider::Local, "[DONE]"),
+            Ok(Event::Done)
+        ));
+        assert!(
+            stream_event(
+                Provider::Anthropic,
+                r#"{"type":"error","error":{"message":"Overloaded"}}"#
+            )
+            .unwrap_err()
+            .contains("Overloaded")
+        );
+    }
+
+    #[test]
+    fn test_conversation() {
+        let conversation = create_conversation("Be brief.".to_string(), None);
+        let Value::Map(map) = &conversation else {
+            panic!("a conversation is a Map");
+        };
+        record(
+            map,
+            &[
+                Message::User("first question".to_string()),
+                Message::User("second question".to_string()),
+            ],
+            None,
+        );
+        // Each message costs 4 tokens besides its text
+        assert_eq!(count_tokens(&conversation).unwrap(), 7 + 8 + 8);
+
+        map.write_recover()
+            .insert("ContextTokens".to_string(), Value::Integer(16.into()));
+        trim_conversation(map);
+        let messages = conversation_messages(map).unwrap();
+        assert_eq!(
+            messages,
+            [
+                Message::System("Be brief.".to_string()),
+                Message::User("second question".to_string())
+            ]
+        );
+
+        record(
+            map,
+            &[Message::Assistant {
+                content: "Yes.".to_string(),
+                calls: Vec::new(),
+            }],
+            Some((10, 2, 12)),
+        );
+        let map = map.read_recover();
+        let Value::Map(usage) = &map["Usage"] else {
+            panic!("Usage is a Map");
+        };
+        assert_eq!(
+            usage.read_recover()["TotalTokens"].to_display_string(),
+            "12"
+        );
+    }
+}
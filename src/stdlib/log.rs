@@ -0,0 +1,431 @@
+use crate::runtime::config;
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use crate::stdlib::json::convert_object_to_json;
+use bigdecimal::ToPrimitive;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use indexmap::IndexMap;
+use serde_json::{Map as JsonMap, Value as JsonValue, json};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+// One logger for the whole process: scripts, web handlers and the server
+// itself write through it, so their entries share a level, a format and a
+// destination. The level starts as Runtime.Config.LogLevel (--log-level or
+// SFEX_LOG_LEVEL) and Log.Configure changes all three.
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEEP: usize = 5;
+
+/// From most to least severe, in the order of `config::LOG_LEVELS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!(
+                "Unknown log level '{}', expected one of: {}",
+                name,
+                config::LOG_LEVELS.join(", ")
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        config::LOG_LEVELS[self as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    /// `2026-01-02 15:04:05.123 INFO  user login user=42`, in local time
+    Pretty,
+    /// One JSON object per line, with a UTC `time`
+    Json,
+}
+
+struct Logger {
+    level: Level,
+    format: Format,
+    /// Entries go to stderr unless there is a file
+    file: Option<RotatingFile>,
+}
+
+impl Logger {
+    fn from_config() -> Self {
+        Self {
+            level: Level::parse(&config::current().log_level).unwrap_or(Level::Info),
+            format: Format::Pretty,
+            file: None,
+        }
+    }
+
+    fn write(&mut self, level: Level, message: &str, fields: &[(String, JsonValue)]) {
+        if level > self.level {
+            return;
+        }
+        let line = match self.format {
+            Format::Pretty => format_pretty(Local::now(), level, message, fields),
+            Format::Json => format_json(Utc::now(), level, message, fields),
+        };
+        let written = match &mut self.file {
+            Some(file) => file.write_line(&line),
+            None => Err(io::Error::other("no log file")),
+        };
+        // With no file, or one that can't be written, the entry isn't lost
+        if written.is_err() {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+/// A log file that is renamed to `app.log.1` once it reaches `max_bytes`,
+/// shifting older ones up to `app.log.<keep>`.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> Result<Self, String> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        }
+        let file = append(&path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_bytes > 0 && self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(numbered(self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(numbered(n), numbered(n + 1));
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+fn with_logger<T>(f: impl FnOnce(&mut Logger) -> T) -> T {
+    let mut logger = LOGGER.lock_recover();
+    f(logger.get_or_insert_with(Logger::from_config))
+}
+
+/// Write an entry with `fields` if `level` is enabled.
+pub fn write(level: Level, message: &str, fields: &[(&str, &str)]) {
+    let fields: Vec<(String, JsonValue)> = fields
+        .iter()
+        .map(|(key, value)| (key.to_string(), json!(value)))
+        .collect();
+    with_logger(|logger| logger.write(level, message, &fields));
+}
+
+/// Like `write`, but only when entries go to a file: for errors already
+/// printed to the terminal, such as a script's uncaught error, that a log
+/// file should still have.
+pub fn write_to_file(level: Level, message: &str, fields: &[(&str, &str)]) {
+    if with_logger(|logger| logger.file.is_some()) {
+        write(level, message, fields);
+    }
+}
+
+pub fn info(message: &str, fields: &[(&str, &str)]) {
+    write(Level::Info, message, fields);
+}
+
+pub fn warn(message: &str, fields: &[(&str, &str)]) {
+    write(Level::Warn, message, fields);
+}
+
+pub fn error(message: &str, fields: &[(&str, &str)]) {
+    write(Level::Error, message, fields);
+}
+
+fn format_pretty(
+    time: DateTime<Local>,
+    level: Level,
+    message: &str,
+    fields: &[(String, JsonValue)],
+) -> String {
+    let mut line = format!(
+        "{} {:<5} {}",
+        time.format("%Y-%m-%d %H:%M:%S%.3f"),
+        level.as_str().to_ascii_uppercase(),
+        message
+    );
+    for (key, value) in fields {
+        let value = match value {
+            // Quoted only when needed to tell where the value ends
+            JsonValue::String(s) if !s.is_empty() && !s.contains([' ', '"', '=', '\n']) => {
+                s.clone()
+            }
+            other => other.to_string(),
+        };
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    line
+}
+
+fn format_json(
+    time: DateTime<Utc>,
+    level: Level,
+    message: &str,
+    fields: &[(String, JsonValue)],
+) -> String {
+    let mut entry = JsonMap::new();
+    entry.insert(
+        "time".to_string(),
+        json!(time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    entry.insert("level".to_string(), json!(level.as_str()));
+    entry.insert("message".to_string(), json!(message));
+    for (key, value) in fields {
+        // A field can't replace the entry's own keys
+        entry.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+    JsonValue::Object(entry).to_string()
+}
+
+pub fn create_log_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Log.Info("user login", { User: Id }), and the same for each level
+    for level in [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ] {
+        let name = match level {
+            Level::Error => "Error",
+            Level::Warn => "Warn",
+            Level::Info => "Info",
+            Level::Debug => "Debug",
+            Level::Trace => "Trace",
+        };
+        methods.insert(
+            name.to_string(),
+            Value::NativeFunction(Arc::new(Box::new(move |args| {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(format!(
+                        "Log.{} requires 1-2 arguments (message, optional fields)",
+                        name
+                    ));
+                }
+                let fields = match args.get(1) {
+                    Some(Value::Map(map)) => {
+                        let mut fields: Vec<(String, JsonValue)> = map
+                            .read_recover()
+                            .iter()
+                            .map(|(key, value)| (key.clone(), convert_object_to_json(value)))
+                            .collect();
+                        fields.sort_by(|a, b| a.0.cmp(&b.0));
+                        fields
+                    }
+                    Some(other) => {
+                        return Err(format!(
+                            "Log.{} fields must be a Map, got {}",
+                            name,
+                            other.type_name()
+                        ));
+                    }
+                    None => Vec::new(),
+                };
+                let message = args[0].to_display_string();
+                with_logger(|logger| logger.write(level, &message, &fields));
+                Ok(Value::Option(Box::new(None)))
+            }))),
+        );
+    }
+
+    // Log.Configure({ Level: "debug", Format: "json", File: "logs/app.log", MaxBytes: 1048576, Keep: 3 })
+    methods.insert(
+        "Configure".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let options = match args.first() {
+                Some(Value::Map(map)) if args.len() == 1 => map.read_recover().clone(),
+                _ => return Err("Log.Configure requires 1 argument (options Map)".to_string()),
+            };
+            configure(&options)?;
+            Ok(Value::Option(Box::new(None)))
+        }))),
+    );
+
+    // Log.Enabled("debug") -> whether entries at that level are written
+    methods.insert(
+        "Enabled".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Log.Enabled requires 1 argument (level)".to_string());
+            }
+            let level = Level::parse(&args[0].to_display_string())?;
+            Ok(Value::Boolean(with_logger(|logger| level <= logger.level)))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn configure(options: &IndexMap<String, Value>) -> Result<(), String> {
+    let mut level = None;
+    let mut format = None;
+    let mut file = None;
+    let mut max_bytes = DEFAULT_MAX_BYTES;
+    let mut keep = DEFAULT_KEEP;
+    for (key, value) in options {
+        match key.as_str() {
+            "Level" => level = Some(Level::parse(&value.to_display_string())?),
+            "Format" => {
+                format = Some(match value.to_display_string().to_lowercase().as_str() {
+                    "pretty" => Format::Pretty,
+                    "json" => Format::Json,
+                    other => {
+                        return Err(format!(
+                            "Unknown log format '{}' (expected pretty or json)",
+                            other
+                        ));
+                    }
+                })
+            }
+            // None goes back to stderr
+            "File" => {
+                file = Some(match value {
+                    Value::Option(none) if none.is_none() => None,
+                    other => Some(PathBuf::from(other.to_display_string())),
+                })
+            }
+            "MaxBytes" => max_bytes = whole_number(key, value)?,
+            "Keep" => keep = whole_number(key, value)? as usize,
+            _ => return Err(format!("Log.Configure has no option {}", key)),
+        }
+    }
+    // Opened before anything changes, so a bad path leaves the logger as it was
+    let file = match file {
+        Some(Some(path)) => {
+            permissions::check_write(&path)?;
+            Some(Some(RotatingFile::open(path, max_bytes, keep)?))
+        }
+        other => other.map(|_| None),
+    };
+
+    with_logger(|logger| {
+        if let Some(level) = level {
+            logger.level = level;
+        }
+        if let Some(format) = format {
+            logger.format = format;
+        }
+        if let Some(file) = file {
+            logger.file = file;
+        }
+    });
+    Ok(())
+}
+
+fn whole_number(key: &str, value: &Value) -> Result<u64, String> {
+    let number = match value {
+        Value::Number(n) => n.to_u64().filter(|_| n.is_integer()),
+        Value::Integer(i) => i.to_u64(),
+        _ => None,
+    };
+    number.ok_or_else(|| format!("Log {} must be a whole number", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_formats_and_rotation() {
+        let fields = vec![
+            ("ip".to_string(), json!("10.0.0.1")),
+            ("name".to_string(), json!("Ada Lovelace")),
+            ("user".to_string(), json!(42)),
+            ("level".to_string(), json!("mine")),
+        ];
+        let local = Local.with_ymd_and_hms(2026, 1, 2, 15, 4, 5).unwrap();
+        assert_eq!(
+            format_pretty(local, Level::Warn, "user login", &fields),
+            "2026-01-02 15:04:05.000 WARN  user login ip=10.0.0.1 name=\"Ada Lovelace\" user=42 level=mine"
+        );
+        let utc = Utc.with_ymd_and_hms(2026, 1, 2, 15, 4, 5).unwrap();
+        assert_eq!(
+            format_json(utc, Level::Info, "user login", &fields),
+            r#"{"time":"2026-01-02T15:04:05.000Z","level":"info","message":"user login","ip":"10.0.0.1","name":"Ada Lovelace","user":42}"#
+        );
+
+        let dir = std::env::temp_dir().join(format!("sfex-log-{}", std::process::id()));
+        let path = dir.join("app.log");
+        let mut logger = Logger {
+            level: Level::Info,
+            format: Format::Json,
+            file: Some(RotatingFile::open(path.clone(), 150, 2).unwrap()),
+        };
+        for n in 0..5 {
+            logger.write(Level::Info, &format!("entry {}", n), &[]);
+        }
+        logger.write(Level::Debug, "too detailed", &[]);
+        let read = |suffix: &str| {
+            let text = fs::read_to_string(format!("{}{}", path.display(), suffix)).unwrap();
+            text.lines()
+                .map(|line| serde_json::from_str::<JsonValue>(line).unwrap()["message"].clone())
+                .collect::<Vec<_>>()
+        };
+        // Two entries fit in 150 bytes; the oldest file beyond Keep is gone
+        assert_eq!(read(""), [json!("entry 4")]);
+        assert_eq!(read(".1"), [json!("entry 2"), json!("entry 3")]);
+        assert_eq!(read(".2"), [json!("entry 0"), json!("entry 1")]);
+        assert!(!dir.join("app.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
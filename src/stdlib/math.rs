@@ -1,10 +1,401 @@
+use crate::runtime::lock::RwLockExt;
 use crate::runtime::value::Value;
-use rand::Rng;
-use std::collections::HashMap;
+use bigdecimal::num_bigint::{BigInt, ToBigInt};
+use bigdecimal::{
+    BigDecimal, Context, FromPrimitive, One, RoundingMode, Signed, ToPrimitive, Zero,
+};
+use indexmap::IndexMap;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Exact Integers and Numbers stay exact where the answer can be: Pow with a
+// whole exponent, Gcd, Mean and Percentile. Where it can't (Sqrt, Log, a
+// fractional Pow, StdDev) the answer has Math.Precision significant digits.
+// FastNumbers and Vectors always get f64 arithmetic.
+
+/// Significant digits for answers that can't be exact
+static PRECISION: AtomicU64 = AtomicU64::new(50);
+
+const ROUNDING_MODES: &str = "HalfUp, HalfEven, HalfDown, Up, Down, Ceiling, Floor";
+
+fn context() -> Context {
+    Context::default()
+        .with_prec(PRECISION.load(Ordering::Relaxed))
+        .unwrap_or_default()
+}
+
+fn exact(value: &Value) -> Option<BigDecimal> {
+    match value {
+        Value::Number(n) => Some(n.clone()),
+        Value::Integer(i) => Some(BigDecimal::from(i.clone())),
+        _ => None,
+    }
+}
+
+fn float(value: &Value, caller: &str) -> Result<f64, String> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("{} needs a number, not {}", caller, value.type_name()))
+}
+
+/// Any number as a decimal. A FastNumber becomes the decimal it prints as,
+/// so 2.675 rounds the way it reads rather than the way it is stored.
+fn decimal(value: &Value, caller: &str) -> Result<BigDecimal, String> {
+    if let Some(n) = exact(value) {
+        return Ok(n);
+    }
+    match value {
+        Value::FastNumber(f) if f.is_finite() => {
+            BigDecimal::from_str(&f.to_string()).map_err(|e| e.to_string())
+        }
+        Value::FastNumber(f) => Err(format!("{} can't take {}", caller, f)),
+        other => Err(format!(
+            "{} needs a number, not {}",
+            caller,
+            other.type_name()
+        )),
+    }
+}
+
+fn whole(value: &Value, what: &str) -> Result<BigInt, String> {
+    match value {
+        Value::Integer(i) => Some(i.clone()),
+        Value::Number(n) if n.is_integer() => n.to_bigint(),
+        Value::FastNumber(f) if f.fract() == 0.0 => BigInt::from_f64(*f),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{} must be a whole number", what))
+}
+
+fn small_whole(value: &Value, what: &str) -> Result<i64, String> {
+    whole(value, what)?
+        .to_i64()
+        .ok_or_else(|| format!("{} is too large", what))
+}
+
+fn rounding_mode(value: &Value) -> Result<RoundingMode, String> {
+    match value {
+        Value::String(name) => match name.as_str() {
+            "HalfUp" => Ok(RoundingMode::HalfUp),
+            "HalfEven" => Ok(RoundingMode::HalfEven),
+            "HalfDown" => Ok(RoundingMode::HalfDown),
+            "Up" => Ok(RoundingMode::Up),
+            "Down" => Ok(RoundingMode::Down),
+            "Ceiling" => Ok(RoundingMode::Ceiling),
+            "Floor" => Ok(RoundingMode::Floor),
+            _ => Err(format!(
+                "Unknown rounding mode '{}', expected one of: {}",
+                name, ROUNDING_MODES
+            )),
+        },
+        other => Err(format!(
+            "The rounding mode must be a String, not {}",
+            other.type_name()
+        )),
+    }
+}
+
+/// ln(x) for x > 0. Powers of ten come out first, so Newton's method on exp
+/// only ever sees arguments between 0 and ln(10).
+fn ln(x: &BigDecimal, ctx: &Context) -> BigDecimal {
+    let work = ctx
+        .with_prec(ctx.precision().get() + 10)
+        .unwrap_or_default();
+    let magnitude = x.order_of_magnitude();
+    let mantissa = x * BigDecimal::new(BigInt::one(), magnitude);
+    let mut result = ln_near_one(&mantissa, &work);
+    if magnitude != 0 {
+        result += ln_near_one(&BigDecimal::from(10), &work) * BigDecimal::from(magnitude);
+    }
+    ctx.round_decimal(result)
+}
+
+fn ln_near_one(x: &BigDecimal, work: &Context) -> BigDecimal {
+    let start = x.to_f64().map(f64::ln).unwrap_or_default();
+    let mut y = BigDecimal::from_f64(start).unwrap_or_default();
+    let tolerance = BigDecimal::new(BigInt::one(), work.precision().get() as i64);
+    // Halley's iteration triples the correct digits each time
+    for _ in 0..64 {
+        let e = y.exp_with_context(work);
+        let step = work.round_decimal((x - &e).double() * (x + &e).inverse_with_context(work));
+        y = work.round_decimal(y + &step);
+        if step.abs() < tolerance {
+            break;
+        }
+    }
+    y
+}
+
+fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while !b.is_zero() {
+        let r = &a % &b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// The arguments of Gcd and Lcm: either several whole numbers or one List
+fn whole_arguments(args: &[Value], caller: &str) -> Result<Vec<BigInt>, String> {
+    let items = match args {
+        [Value::List(list)] => list.read_recover().clone(),
+        _ => args.to_vec(),
+    };
+    if items.len() < 2 {
+        return Err(format!("{} requires at least 2 whole numbers", caller));
+    }
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| whole(item, &format!("{}'s argument {}", caller, i + 1)))
+        .collect()
+}
+
+/// The numbers of a List or Vector, for the statistics
+enum Samples {
+    Exact(Vec<BigDecimal>),
+    Fast(Vec<f64>),
+}
+
+impl Samples {
+    fn from(value: &Value, caller: &str) -> Result<Samples, String> {
+        let samples = match value {
+            Value::Vector(v) => Samples::Fast(v.to_vec()),
+            Value::List(list) => {
+                let items = list.read_recover();
+                match items.iter().map(exact).collect::<Option<Vec<_>>>() {
+                    Some(exact) => Samples::Exact(exact),
+                    None => Samples::Fast(
+                        items
+                            .iter()
+                            .enumerate()
+                            .map(|(i, item)| {
+                                item.as_f64().ok_or_else(|| {
+                                    format!(
+                                        "{}: item {} is a {}, not a number",
+                                        caller,
+                                        i + 1,
+                                        item.type_name()
+                                    )
+                                })
+                            })
+                            .collect::<Result<_, _>>()?,
+                    ),
+                }
+            }
+            other => {
+                return Err(format!(
+                    "{} needs a List or Vector, not {}",
+                    caller,
+                    other.type_name()
+                ));
+            }
+        };
+        let empty = match &samples {
+            Samples::Exact(values) => values.is_empty(),
+            Samples::Fast(values) => values.is_empty(),
+        };
+        if empty {
+            return Err(format!("{} needs at least one number", caller));
+        }
+        Ok(samples)
+    }
+
+    fn mean(&self) -> Value {
+        match self {
+            Samples::Exact(values) => {
+                let sum: BigDecimal = values.iter().sum();
+                Value::Number(
+                    context()
+                        .round_decimal(sum / BigDecimal::from(values.len() as u64))
+                        .normalized(),
+                )
+            }
+            Samples::Fast(values) => {
+                Value::FastNumber(values.iter().sum::<f64>() / values.len() as f64)
+            }
+        }
+    }
+
+    /// Linear interpolation between the two closest ranks, as spreadsheets
+    /// and NumPy do
+    fn percentile(&self, p: &BigDecimal) -> Value {
+        match self {
+            Samples::Exact(values) => {
+                let mut sorted = values.clone();
+                sorted.sort();
+                let rank = p * BigDecimal::from((sorted.len() - 1) as u64) / BigDecimal::from(100);
+                let below = rank.with_scale_round(0, RoundingMode::Floor);
+                let index = below.to_usize().unwrap_or_default();
+                let low = &sorted[index];
+                let result = match sorted.get(index + 1) {
+                    Some(high) => low + (high - low) * (rank - below),
+                    None => low.clone(),
+                };
+                Value::Number(result.normalized())
+            }
+            Samples::Fast(values) => {
+                let mut sorted = values.clone();
+                sorted.sort_by(f64::total_cmp);
+                let rank = p.to_f64().unwrap_or_default() / 100.0 * (sorted.len() - 1) as f64;
+                let index = rank.floor() as usize;
+                let low = sorted[index];
+                Value::FastNumber(match sorted.get(index + 1) {
+                    Some(high) => low + (high - low) * (rank - rank.floor()),
+                    None => low,
+                })
+            }
+        }
+    }
+
+    fn std_dev(&self, sample: bool, caller: &str) -> Result<Value, String> {
+        let count = match self {
+            Samples::Exact(values) => values.len(),
+            Samples::Fast(values) => values.len(),
+        };
+        let divisor = if sample { count - 1 } else { count };
+        if divisor == 0 {
+            return Err(format!("{} of a Sample needs at least 2 numbers", caller));
+        }
+        Ok(match (self, self.mean()) {
+            (Samples::Exact(values), Value::Number(mean)) => {
+                let squares: BigDecimal = values.iter().map(|x| (x - &mean).square()).sum();
+                let variance = squares / BigDecimal::from(divisor as u64);
+                let ctx = context();
+                Value::Number(
+                    variance
+                        .sqrt_with_context(&ctx)
+                        .unwrap_or_default()
+                        .normalized(),
+                )
+            }
+            (Samples::Fast(values), Value::FastNumber(mean)) => {
+                let squares: f64 = values.iter().map(|x| (x - mean).powi(2)).sum();
+                Value::FastNumber((squares / divisor as f64).sqrt())
+            }
+            _ => unreachable!("the mean has the samples' kind"),
+        })
+    }
+}
+
+/// Grouping and decimal separators, by language and a few regional tags
+pub(crate) fn separators(locale: &str) -> Option<(&'static str, &'static str)> {
+    let tag = locale.replace('_', "-").to_ascii_lowercase();
+    let language = tag.split('-').next().unwrap_or_default();
+    Some(match (tag.as_str(), language) {
+        ("de-ch" | "it-ch", _) => ("\u{2019}", "."),
+        ("en-za", _) => ("\u{a0}", ","),
+        (_, "en" | "ja" | "ko" | "zh" | "mn" | "th" | "he") => (",", "."),
+        (_, "de" | "es" | "it" | "nl" | "pt" | "id" | "tr" | "da" | "el") => (".", ","),
+        (_, "fr") => ("\u{202f}", ","),
+        (_, "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "kk") => ("\u{a0}", ","),
+        _ => return None,
+    })
+}
+
+/// `number` as text by a Math.Format pattern, with the given grouping and
+/// decimal separators
+pub(crate) fn format_number(
+    number: &Value,
+    pattern: &str,
+    separators: (&str, &str),
+) -> Result<String, String> {
+    let number = decimal(number, "Math.Format")?;
+    Ok(Pattern::parse(pattern)?.format(&number, separators))
+}
+
+/// A Math.Format pattern such as "0,0.00", "#,##0.###" or "$0,0.00"
+struct Pattern {
+    prefix: String,
+    suffix: String,
+    grouping: bool,
+    min_whole: usize,
+    min_fraction: usize,
+    max_fraction: usize,
+    percent: bool,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Pattern, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let is_body = |c: &char| matches!(c, '0' | '#' | ',' | '.');
+        let first_digit = chars
+            .iter()
+            .position(|c| matches!(c, '0' | '#'))
+            .ok_or_else(|| format!("Math.Format pattern '{}' has no 0 or # digits", pattern))?;
+        let mut start = first_digit;
+        while start > 0 && is_body(&chars[start - 1]) {
+            start -= 1;
+        }
+        let end = start + chars[start..].iter().take_while(|c| is_body(c)).count();
+        let body: String = chars[start..end].iter().collect();
+        let (whole, fraction) = body.split_once('.').unwrap_or((&body, ""));
+        if fraction.contains(['.', ',']) || fraction.trim_start_matches('0').contains('0') {
+            return Err(format!(
+                "Math.Format pattern '{}' has a malformed fraction '.{}'",
+                pattern, fraction
+            ));
+        }
+        let prefix: String = chars[..start].iter().collect();
+        let suffix: String = chars[end..].iter().collect();
+        Ok(Pattern {
+            percent: prefix.contains('%') || suffix.contains('%'),
+            prefix,
+            suffix,
+            grouping: whole.contains(','),
+            // "0,0" groups with at least one digit; "000" pads to three
+            min_whole: whole
+                .rsplit(',')
+                .next()
+                .unwrap_or_default()
+                .matches('0')
+                .count(),
+            min_fraction: fraction.matches('0').count(),
+            max_fraction: fraction.len(),
+        })
+    }
+
+    fn format(&self, n: &BigDecimal, (group, point): (&str, &str)) -> String {
+        let n = if self.percent {
+            n * BigDecimal::from(100)
+        } else {
+            n.clone()
+        };
+        let rounded = n.with_scale_round(self.max_fraction as i64, RoundingMode::HalfUp);
+        let plain = rounded.abs().to_plain_string();
+        let (digits, fraction) = plain.split_once('.').unwrap_or((&plain, ""));
+        let mut fraction = fraction.to_string();
+        while fraction.len() > self.min_fraction && fraction.ends_with('0') {
+            fraction.pop();
+        }
+        let digits = digits.trim_start_matches('0');
+        let digits = format!("{:0>width$}", digits, width = self.min_whole);
+        let mut whole = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if self.grouping && i > 0 && (digits.len() - i) % 3 == 0 {
+                whole.push_str(group);
+            }
+            whole.push(digit);
+        }
+        let mut out = String::new();
+        if rounded.is_negative() {
+            out.push('-');
+        }
+        out.push_str(&self.prefix);
+        out.push_str(&whole);
+        if !fraction.is_empty() {
+            out.push_str(point);
+            out.push_str(&fraction);
+        }
+        out.push_str(&self.suffix);
+        out
+    }
+}
 
 pub fn create_math_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Random".to_string(),
@@ -13,35 +404,31 @@ pub fn create_math_module() -> Value {
                 return Err("Math.Random requires 0 arguments".to_string());
             }
 
-            let mut rng = rand::rng();
-            let random_value: f64 = rng.random_range(0.0..1.0);
-
-            Ok(Value::FastNumber(random_value))
+            // Follows Random.Seed, for reproducible runs
+            Ok(Value::FastNumber(crate::stdlib::random::unit()))
         }))),
     );
 
+    // Math.Round(2.675, 2) - HalfUp unless a mode is given, and places may
+    // be negative to round to tens or hundreds
     methods.insert(
         "Round".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
-            if args.len() != 1 {
-                return Err("Math.Round requires 1 argument (number)".to_string());
+            if args.is_empty() || args.len() > 3 {
+                return Err("Math.Round requires 1-3 arguments (number, places, mode)".to_string());
             }
 
-            let number = match &args[0] {
-                Value::Number(n) => n
-                    .to_string()
-                    .parse::<f64>()
-                    .map_err(|_| "Invalid number".to_string())?,
-                Value::FastNumber(f) => *f,
-                _ => {
-                    return Err("Argument must be a number".to_string());
-                }
+            let number = decimal(&args[0], "Math.Round")?;
+            let places = match args.get(1) {
+                Some(places) => small_whole(places, "Math.Round's places")?,
+                None => 0,
+            };
+            let mode = match args.get(2) {
+                Some(mode) => rounding_mode(mode)?,
+                None => RoundingMode::HalfUp,
             };
 
-            let rounded = number.round();
-
-            use bigdecimal::BigDecimal;
-            Ok(Value::Number(BigDecimal::from(rounded as i64)))
+            Ok(Value::Number(number.with_scale_round(places, mode)))
         }))),
     );
 
@@ -57,6 +444,7 @@ pub fn create_math_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid number".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid number")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Argument must be a number".to_string());
@@ -82,6 +470,7 @@ pub fn create_math_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid number".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid number")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Argument must be a number".to_string());
@@ -107,6 +496,7 @@ pub fn create_math_module() -> Value {
                     let abs_n = n.abs();
                     Ok(Value::Number(abs_n))
                 }
+                Value::Integer(i) => Ok(Value::Integer(i.abs())),
                 Value::FastNumber(f) => Ok(Value::FastNumber(f.abs())),
                 _ => Err("Argument must be a number".to_string()),
             }
@@ -125,6 +515,7 @@ pub fn create_math_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid number".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid number")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Arguments must be numbers".to_string());
@@ -136,6 +527,7 @@ pub fn create_math_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid number".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid number")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Arguments must be numbers".to_string());
@@ -159,6 +551,7 @@ pub fn create_math_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid number".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid number")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Arguments must be numbers".to_string());
@@ -170,6 +563,7 @@ pub fn create_math_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid number".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid number")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Arguments must be numbers".to_string());
@@ -188,30 +582,44 @@ pub fn create_math_module() -> Value {
                 return Err("Math.Pow requires 2 arguments (base, exponent)".to_string());
             }
 
-            let base = match &args[0] {
-                Value::Number(n) => n
-                    .to_string()
-                    .parse::<f64>()
-                    .map_err(|_| "Invalid number".to_string())?,
-                Value::FastNumber(f) => *f,
-                _ => {
-                    return Err("Arguments must be numbers".to_string());
-                }
+            let (Some(base), Some(exponent)) = (exact(&args[0]), exact(&args[1])) else {
+                let base = float(&args[0], "Math.Pow")?;
+                let exponent = float(&args[1], "Math.Pow")?;
+                return Ok(Value::FastNumber(base.powf(exponent)));
             };
 
-            let exponent = match &args[1] {
-                Value::Number(n) => n
-                    .to_string()
-                    .parse::<f64>()
-                    .map_err(|_| "Invalid number".to_string())?,
-                Value::FastNumber(f) => *f,
-                _ => {
-                    return Err("Arguments must be numbers".to_string());
+            if exponent.is_integer() {
+                let power = exponent
+                    .to_i64()
+                    .ok_or("Math.Pow: the exponent is too large")?;
+                if let (Value::Integer(base), Ok(power)) = (&args[0], u32::try_from(power)) {
+                    return Ok(Value::Integer(base.pow(power)));
                 }
-            };
+                if base.is_zero() && power < 0 {
+                    return Err("Math.Pow: 0 can't be raised to a negative power".to_string());
+                }
+                return Ok(Value::Number(
+                    base.powi_with_context(power, &context()).normalized(),
+                ));
+            }
 
-            let result = base.powf(exponent);
-            Ok(Value::FastNumber(result))
+            // A fractional power is exp(exponent * ln(base))
+            if base.is_negative() {
+                return Err("Math.Pow: a negative base needs a whole exponent".to_string());
+            }
+            if base.is_zero() {
+                return if exponent.is_positive() {
+                    Ok(Value::Number(BigDecimal::zero()))
+                } else {
+                    Err("Math.Pow: 0 can't be raised to a negative power".to_string())
+                };
+            }
+            let ctx = context();
+            let guard = ctx
+                .with_prec(ctx.precision().get() + 10)
+                .unwrap_or_default();
+            let power = (exponent * ln(&base, &guard)).exp_with_context(&ctx);
+            Ok(Value::Number(power.normalized()))
         }))),
     );
 
@@ -222,17 +630,14 @@ pub fn create_math_module() -> Value {
                 return Err("Math.Sqrt requires 1 argument (number)".to_string());
             }
 
-            let number = match &args[0] {
-                Value::Number(n) => n
-                    .to_string()
-                    .parse::<f64>()
-                    .map_err(|_| "Invalid number".to_string())?,
-                Value::FastNumber(f) => *f,
-                _ => {
-                    return Err("Argument must be a number".to_string());
-                }
-            };
+            if let Some(number) = exact(&args[0]) {
+                return number
+                    .sqrt_with_context(&context())
+                    .map(|root| Value::Number(root.normalized()))
+                    .ok_or_else(|| "Cannot take square root of negative number".to_string());
+            }
 
+            let number = float(&args[0], "Math.Sqrt")?;
             if number < 0.0 {
                 return Err("Cannot take square root of negative number".to_string());
             }
@@ -242,5 +647,285 @@ pub fn create_math_module() -> Value {
         }))),
     );
 
+    // Math.Log(x) is the natural logarithm, Math.Log(x, 10) the common one
+    methods.insert(
+        "Log".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("Math.Log requires 1-2 arguments (number, base)".to_string());
+            }
+
+            let number = decimal(&args[0], "Math.Log")?;
+            if !number.is_positive() {
+                return Err("Math.Log needs a number above 0".to_string());
+            }
+            let base = match args.get(1) {
+                Some(base) => Some(decimal(base, "Math.Log")?),
+                None => None,
+            };
+            if let Some(base) = &base
+                && (!base.is_positive() || base.is_one())
+            {
+                return Err("Math.Log's base must be above 0 and not 1".to_string());
+            }
+
+            if args.iter().any(|arg| exact(arg).is_none()) {
+                let number = float(&args[0], "Math.Log")?;
+                return Ok(Value::FastNumber(match args.get(1) {
+                    Some(base) => number.log(float(base, "Math.Log")?),
+                    None => number.ln(),
+                }));
+            }
+
+            let ctx = context();
+            let guard = ctx
+                .with_prec(ctx.precision().get() + 10)
+                .unwrap_or_default();
+            let log = match base {
+                Some(base) => ln(&number, &guard) * ln(&base, &guard).inverse_with_context(&guard),
+                None => ln(&number, &guard),
+            };
+            Ok(Value::Number(ctx.round_decimal(log).normalized()))
+        }))),
+    );
+
+    // Math.Precision(100) - significant digits for Sqrt, Log, fractional
+    // powers and StdDev of exact numbers; gives the previous setting
+    methods.insert(
+        "Precision".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let previous = PRECISION.load(Ordering::Relaxed);
+            match args.as_slice() {
+                [] => {}
+                [digits] => {
+                    let digits = small_whole(digits, "Math.Precision's digits")?;
+                    if !(1..=10_000).contains(&digits) {
+                        return Err("Math.Precision's digits must be from 1 to 10000".to_string());
+                    }
+                    PRECISION.store(digits as u64, Ordering::Relaxed);
+                }
+                _ => return Err("Math.Precision requires 0-1 arguments (digits)".to_string()),
+            }
+            Ok(Value::Integer(previous.into()))
+        }))),
+    );
+
+    methods.insert(
+        "Gcd".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let numbers = whole_arguments(&args, "Math.Gcd")?;
+            let result = numbers.iter().fold(BigInt::zero(), |acc, n| gcd(&acc, n));
+            Ok(Value::Integer(result))
+        }))),
+    );
+
+    methods.insert(
+        "Lcm".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let numbers = whole_arguments(&args, "Math.Lcm")?;
+            let result = numbers.iter().fold(BigInt::one(), |acc, n| {
+                if acc.is_zero() || n.is_zero() {
+                    BigInt::zero()
+                } else {
+                    (&acc * n).abs() / gcd(&acc, n)
+                }
+            });
+            Ok(Value::Integer(result))
+        }))),
+    );
+
+    methods.insert(
+        "Mean".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Math.Mean requires 1 argument (list)".to_string());
+            }
+            Ok(Samples::from(&args[0], "Math.Mean")?.mean())
+        }))),
+    );
+
+    methods.insert(
+        "Median".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Math.Median requires 1 argument (list)".to_string());
+            }
+            Ok(Samples::from(&args[0], "Math.Median")?.percentile(&BigDecimal::from(50)))
+        }))),
+    );
+
+    // Math.Percentile(Times, 95), with the percentile from 0 to 100
+    methods.insert(
+        "Percentile".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Math.Percentile requires 2 arguments (list, percentile)".to_string());
+            }
+            let samples = Samples::from(&args[0], "Math.Percentile")?;
+            let p = decimal(&args[1], "Math.Percentile")?;
+            if p.is_negative() || p > 100 {
+                return Err("Math.Percentile's percentile must be from 0 to 100".to_string());
+            }
+            Ok(samples.percentile(&p))
+        }))),
+    );
+
+    // Math.StdDev(values) treats the values as the whole population;
+    // Math.StdDev(values, "Sample") divides by n - 1
+    methods.insert(
+        "StdDev".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err("Math.StdDev requires 1-2 arguments (list, kind)".to_string());
+            }
+            let sample = match args.get(1) {
+                None => false,
+                Some(Value::String(kind)) if kind == "Population" => false,
+                Some(Value::String(kind)) if kind == "Sample" => true,
+                Some(_) => {
+                    return Err(
+                        "Math.StdDev's second argument must be \"Population\" or \"Sample\""
+                            .to_string(),
+                    );
+                }
+            };
+            Samples::from(&args[0], "Math.StdDev")?.std_dev(sample, "Math.StdDev")
+        }))),
+    );
+
+    // Math.Format(1234.5, "0,0.00") is "1,234.50"; Math.Format(1234.5,
+    // "0,0.00", "de") is "1.234,50"
+    methods.insert(
+        "Format".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "Math.Format requires 2-3 arguments (number, pattern, locale)".to_string(),
+                );
+            }
+            let Value::String(pattern) = &args[1] else {
+                return Err("Math.Format's pattern must be a String".to_string());
+            };
+            let locale = match args.get(2) {
+                Some(Value::String(locale)) => locale.as_str(),
+                Some(other) => {
+                    return Err(format!(
+                        "Math.Format's locale must be a String, not {}",
+                        other.type_name()
+                    ));
+                }
+                None => "en",
+            };
+            let separators = separators(locale)
+                .ok_or_else(|| format!("Math.Format doesn't know the locale '{}'", locale))?;
+            format_number(&args[0], pattern, separators).map(Value::String)
+        }))),
+    );
+
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: Vec<Value>) -> Result<String, String> {
+        let Value::Map(map) = create_math_module() else {
+            panic!("not an object");
+        };
+        let function = map.read_recover().get(name).cloned();
+        match function {
+            Some(Value::NativeFunction(f)) => f(args).map(|value| value.to_display_string()),
+            _ => panic!("no member {}", name),
+        }
+    }
+
+    fn int(n: i64) -> Value {
+        Value::Integer(n.into())
+    }
+
+    fn num(s: &str) -> Value {
+        Value::Number(BigDecimal::from_str(s).unwrap())
+    }
+
+    fn list(values: Vec<Value>) -> Value {
+        Value::List(Arc::new(std::sync::RwLock::new(values)))
+    }
+
+    #[test]
+    fn test_exact_arithmetic() {
+        assert_eq!(
+            call("Pow", vec![int(2), int(70)]).unwrap(),
+            "1180591620717411303424"
+        );
+        assert_eq!(
+            call("Pow", vec![num("1.5"), int(-2)]).unwrap(),
+            "0.4444444444"
+        );
+        assert_eq!(call("Pow", vec![int(9), num("0.5")]).unwrap(), "3");
+        assert_eq!(call("Log", vec![int(1000), int(10)]).unwrap(), "3");
+        assert_eq!(call("Log", vec![num("0.5")]).unwrap(), "-0.6931471805");
+        assert_eq!(call("Sqrt", vec![int(2)]).unwrap(), "1.4142135623");
+        assert!(call("Log", vec![int(0)]).is_err());
+        assert_eq!(call("Gcd", vec![int(84), int(-36), int(60)]).unwrap(), "12");
+        assert_eq!(
+            call("Lcm", vec![list(vec![int(4), int(6), int(10)])]).unwrap(),
+            "60"
+        );
+        assert_eq!(call("Round", vec![num("2.675"), int(2)]).unwrap(), "2.68");
+        assert_eq!(
+            call(
+                "Round",
+                vec![num("2.5"), int(0), Value::String("HalfEven".into())]
+            )
+            .unwrap(),
+            "2"
+        );
+        assert_eq!(
+            call("Round", vec![Value::FastNumber(2.675), int(2)]).unwrap(),
+            "2.68"
+        );
+    }
+
+    #[test]
+    fn test_statistics() {
+        let values = list([2, 4, 4, 4, 5, 5, 7, 9].map(int).to_vec());
+        assert_eq!(call("Mean", vec![values.clone()]).unwrap(), "5");
+        assert_eq!(call("Median", vec![values.clone()]).unwrap(), "4.5");
+        assert_eq!(call("StdDev", vec![values.clone()]).unwrap(), "2");
+        assert_eq!(
+            call(
+                "Percentile",
+                vec![list((1..=5).map(int).collect()), int(90)]
+            )
+            .unwrap(),
+            "4.6"
+        );
+        let fast = Value::Vector(Arc::from([1.0, 2.0, 4.0]));
+        assert_eq!(call("Median", vec![fast]).unwrap(), "2");
+        assert!(call("Mean", vec![list(vec![])]).is_err());
+        assert!(call("Mean", vec![list(vec![int(1), Value::String("x".into())])]).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let format = |n: Value, pattern: &str, locale: Option<&str>| {
+            let mut args = vec![n, Value::String(pattern.to_string())];
+            args.extend(locale.map(|l| Value::String(l.to_string())));
+            call("Format", args).unwrap()
+        };
+        assert_eq!(format(num("1234567.891"), "0,0.00", None), "1,234,567.89");
+        assert_eq!(
+            format(num("1234567.891"), "0,0.00", Some("de-DE")),
+            "1.234.567,89"
+        );
+        assert_eq!(
+            format(num("1234.5"), "#,##0.##", Some("de-CH")),
+            "1\u{2019}234.5"
+        );
+        assert_eq!(format(num("-0.5"), "$#,##0.###", None), "-$0.5");
+        assert_eq!(format(num("0.256"), "0.0%", None), "25.6%");
+        assert_eq!(format(int(7), "000", None), "007");
+        assert!(call("Format", vec![int(1), Value::String("abc".into())]).is_err());
+    }
+}
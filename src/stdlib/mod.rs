@@ -1,31 +1,70 @@
+#[cfg(feature = "tls")]
+pub mod acme;
+pub mod archive;
+#[cfg(feature = "web")]
+pub mod assets;
+pub mod bit;
+pub mod bytes;
 pub mod channel;
+pub mod chart;
+pub mod checksum;
+pub mod config;
 pub mod csv;
 pub mod data;
+pub mod diff;
 pub mod env;
 pub mod error;
+pub mod ffi;
 pub mod file;
+pub mod gpio;
 pub mod html;
+#[cfg(feature = "web")]
 pub mod http_net;
+pub mod i18n;
 pub mod json;
+#[cfg(feature = "llm")]
 pub mod llm;
+pub mod log;
 pub mod math;
+#[cfg(feature = "web")]
+pub mod mqtt;
+pub mod page;
+pub mod path;
+pub mod process;
+pub mod random;
+pub mod redis;
+pub mod reflect;
+pub mod serial;
+pub mod sql;
 pub mod stream;
 pub mod system;
 pub mod task;
 pub mod tcp;
+pub mod template;
+pub mod text;
 pub mod time;
 pub mod toml;
 pub mod udp;
+pub mod vector;
+pub mod watch;
+#[cfg(feature = "web")]
 pub mod web;
+#[cfg(feature = "web")]
 pub mod websocket;
 pub mod xml;
+pub mod yaml;
 
 use crate::runtime::interpreter::Interpreter;
+use crate::runtime::value::Value;
+use std::sync::Arc;
 
 pub fn register_stdlib(interpreter: &mut Interpreter) {
     let file_module = file::create_file_module();
     interpreter.define_global("File", file_module);
 
+    let path_module = path::create_path_module();
+    interpreter.define_global("Path", path_module);
+
     let json_module = json::create_json_module();
     interpreter.define_global("JSON", json_module);
 
@@ -38,14 +77,35 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
     let toml_module = toml::create_toml_module();
     interpreter.define_global("TOML", toml_module);
 
+    let yaml_module = yaml::create_yaml_module();
+    interpreter.define_global("YAML", yaml_module);
+
     let csv_module = csv::create_csv_module();
     interpreter.define_global("CSV", csv_module);
 
-    let http_module = http_net::create_http_module(interpreter);
-    interpreter.define_global("HTTP", http_module);
+    #[cfg(feature = "web")]
+    {
+        let http_module = http_net::create_http_module(interpreter);
+        interpreter.define_global("HTTP", http_module);
+    }
+
+    #[cfg(feature = "web")]
+    {
+        let websocket_module = websocket::create_websocket_module(interpreter);
+        interpreter.define_global("WebSocket", websocket_module);
+    }
 
-    let websocket_module = websocket::create_websocket_module(interpreter);
-    interpreter.define_global("WebSocket", websocket_module);
+    #[cfg(feature = "web")]
+    {
+        let mqtt_module = mqtt::create_mqtt_module(interpreter);
+        interpreter.define_global("MQTT", mqtt_module);
+    }
+
+    let redis_module = redis::create_redis_module();
+    interpreter.define_global("Redis", redis_module);
+
+    let sql_module = sql::create_sql_module();
+    interpreter.define_global("SQL", sql_module);
 
     let tcp_module = tcp::create_tcp_module();
     interpreter.define_global("TCP", tcp_module);
@@ -56,17 +116,42 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
     let env_module = env::create_env_module();
     interpreter.define_global("Env", env_module);
 
+    let config_module = config::create_config_module();
+    interpreter.define_global("Config", config_module);
+
+    let bytes_module = bytes::create_bytes_module();
+    interpreter.define_global("Bytes", bytes_module);
+
+    let checksum_module = checksum::create_checksum_module();
+    interpreter.define_global("Checksum", checksum_module);
+
     let data_module = data::create_data_module();
     interpreter.define_global("Data", data_module);
 
     let system_module = system::create_system_module();
     interpreter.define_global("System", system_module);
 
+    // The interpreter answers Runtime.Config itself (see runtime_config),
+    // so sandboxed scripts see their own allowed modules
+    let runtime_module = Value::Map(Arc::new(std::sync::RwLock::new(
+        [("Config".to_string(), interpreter.runtime_config())].into(),
+    )));
+    interpreter.define_global("Runtime", runtime_module);
+
     let time_module = time::create_time_module();
     interpreter.define_global("Time", time_module);
 
-    let llm_module = llm::create_llm_module();
-    interpreter.define_global("LLM", llm_module);
+    let i18n_module = i18n::create_i18n_module();
+    interpreter.define_global("I18n", i18n_module);
+
+    let log_module = log::create_log_module();
+    interpreter.define_global("Log", log_module);
+
+    #[cfg(feature = "llm")]
+    {
+        let llm_module = llm::create_llm_module();
+        interpreter.define_global("LLM", llm_module);
+    }
 
     let stream_module = stream::create_stream_module();
     interpreter.define_global("Stream", stream_module);
@@ -74,7 +159,7 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
     let task_module = task::create_task_module(interpreter);
     interpreter.define_global("Task", task_module);
 
-    let channel_module = channel::create_channel_module(interpreter);
+    let channel_module = channel::create_channel_module();
     interpreter.define_global("Channel", channel_module);
 
     let error_module = error::create_error_module();
@@ -83,12 +168,58 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
     let math_module = math::create_math_module();
     interpreter.define_global("Math", math_module);
 
-    let web_module = web::create_web_module();
-    interpreter.define_global("Web", web_module);
+    let random_module = random::create_random_module();
+    interpreter.define_global("Random", random_module);
+
+    let bit_module = bit::create_bit_module();
+    interpreter.define_global("Bit", bit_module);
+
+    let vector_module = vector::create_vector_module();
+    interpreter.define_global("Vector", vector_module);
+
+    let matrix_module = vector::create_matrix_module();
+    interpreter.define_global("Matrix", matrix_module);
+
+    #[cfg(feature = "web")]
+    {
+        let web_module = web::create_web_module();
+        interpreter.define_global("Web", web_module);
+    }
+
+    let template_module = template::create_template_module();
+    interpreter.define_global("Template", template_module);
+
+    let chart_module = chart::create_chart_module();
+    interpreter.define_global("Chart", chart_module);
+
+    let serial_module = serial::create_serial_module();
+    interpreter.define_global("Serial", serial_module);
+
+    let gpio_module = gpio::create_gpio_module();
+    interpreter.define_global("GPIO", gpio_module);
+
+    let ffi_module = ffi::create_ffi_module();
+    interpreter.define_global("FFI", ffi_module);
+
+    let process_module = process::create_process_module();
+    interpreter.define_global("Process", process_module);
+
+    let watch_module = watch::create_watch_module();
+    interpreter.define_global("Watch", watch_module);
+
+    let reflect_module = reflect::create_reflect_module();
+    interpreter.define_global("Reflect", reflect_module);
+
+    let zip_module = archive::create_zip_module();
+    interpreter.define_global("Zip", zip_module);
+
+    let tar_module = archive::create_tar_module();
+    interpreter.define_global("Tar", tar_module);
+
+    let gzip_module = archive::create_gzip_module();
+    interpreter.define_global("Gzip", gzip_module);
 
     // FastNumber() creates fast floating-point numbers
-    use crate::runtime::value::Value;
-    use std::sync::Arc;
     let fast_number_fn = Value::NativeFunction(Arc::new(Box::new(|args| {
         if args.len() != 1 {
             return Err("FastNumber requires 1 argument (number to convert)".to_string());
@@ -100,6 +231,11 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
                 let f64_val = n.to_f64().ok_or("Number too large for FastNumber")?;
                 Ok(Value::FastNumber(f64_val))
             }
+            Value::Integer(i) => {
+                use bigdecimal::ToPrimitive;
+                let f64_val = i.to_f64().ok_or("Integer too large for FastNumber")?;
+                Ok(Value::FastNumber(f64_val))
+            }
             Value::FastNumber(f) => Ok(Value::FastNumber(*f)),
             Value::String(s) => {
                 let f64_val = s
@@ -115,6 +251,33 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
     })));
     interpreter.define_global("FastNumber", fast_number_fn);
 
+    // Integer() converts to an exact whole number, dropping any fraction
+    let integer_fn = Value::NativeFunction(Arc::new(Box::new(|args| {
+        if args.len() != 1 {
+            return Err("Integer requires 1 argument (number or text to convert)".to_string());
+        }
+
+        use bigdecimal::num_bigint::ToBigInt;
+        use bigdecimal::{BigDecimal, FromPrimitive};
+        let whole = match &args[0] {
+            Value::Integer(i) => Some(i.clone()),
+            Value::Number(n) => n.to_bigint(),
+            Value::FastNumber(f) if f.is_finite() => {
+                BigDecimal::from_f64(f.trunc()).and_then(|d| d.to_bigint())
+            }
+            Value::String(s) => s
+                .trim()
+                .parse::<BigDecimal>()
+                .ok()
+                .and_then(|d| d.to_bigint()),
+            _ => None,
+        };
+        whole
+            .map(Value::Integer)
+            .ok_or_else(|| format!("Cannot convert {} to Integer", args[0].to_display_string()))
+    })));
+    interpreter.define_global("Integer", integer_fn);
+
     // WeakRef() constructor creates weak references to Lists/Maps
     let weak_ref_fn = Value::NativeFunction(Arc::new(Box::new(|args| {
         if args.len() != 1 {
@@ -138,4 +301,23 @@ pub fn register_stdlib(interpreter: &mut Interpreter) {
     // None - singleton value representing absence
     let none_value = Value::Option(Box::new(None));
     interpreter.define_global("None", none_value);
+
+    // Ok() and Err() constructors - create a Result holding a value or an error
+    let ok_fn = Value::NativeFunction(Arc::new(Box::new(|args| {
+        if args.len() != 1 {
+            return Err("Ok requires 1 argument (value to wrap)".to_string());
+        }
+
+        Ok(Value::Result(Box::new(Ok(args[0].clone()))))
+    })));
+    interpreter.define_global("Ok", ok_fn);
+
+    let err_fn = Value::NativeFunction(Arc::new(Box::new(|args| {
+        if args.len() != 1 {
+            return Err("Err requires 1 argument (the error)".to_string());
+        }
+
+        Ok(Value::Result(Box::new(Err(args[0].clone()))))
+    })));
+    interpreter.define_global("Err", err_fn);
 }
@@ -0,0 +1,970 @@
+// MQTT 3.1.1 client
+//
+// MQTT.Connect opens the connection and hands it to a task on the shared
+// tokio runtime, which writes what the script sends, answers the broker's
+// acknowledgements and pings, and passes each PUBLISH on to the Streams
+// whose Subscribe filter matches its topic. Script calls block on the
+// runtime until the broker has acknowledged them.
+
+use crate::runtime::deadline;
+use crate::runtime::interpreter::Interpreter;
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const UNSUBSCRIBE: u8 = 0xA2;
+const UNSUBACK: u8 = 0xB0;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+
+/// The largest packet taken from a broker. MQTT allows 256 MB, so the
+/// length a broker claims is checked before anything is allocated for it.
+const MAX_PACKET: usize = 64 << 20;
+
+pub fn create_mqtt_module(interpreter: &Interpreter) -> Value {
+    let mut methods = IndexMap::new();
+    let runtime = interpreter.runtime.clone();
+
+    // MQTT.Connect("mqtt://broker.local", { Username: "sensor", Password: "..." })
+    methods.insert(
+        "Connect".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "MQTT.Connect requires 1-2 arguments (broker, optional options)".to_string(),
+                );
+            }
+            let options = Options::parse(&args[0].to_display_string(), args.get(1))?;
+            let client = connect(runtime.clone(), options)?;
+            Ok(create_client_object(client))
+        }))),
+    );
+
+    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+}
+
+struct Options {
+    host: String,
+    port: u16,
+    tls: bool,
+    #[cfg(feature = "tls")]
+    tls_options: crate::stdlib::tcp::TlsOptions,
+    client_id: String,
+    username: Option<String>,
+    password: Option<String>,
+    keep_alive: u16,
+    clean_session: bool,
+    timeout: Duration,
+}
+
+impl Options {
+    /// `broker` is "host", "host:port", or a mqtt:// or mqtts:// URL.
+    fn parse(broker: &str, options: Option<&Value>) -> Result<Self, String> {
+        let (tls, address) = if let Some(rest) = broker.strip_prefix("mqtts://") {
+            (true, rest)
+        } else if let Some(rest) = broker.strip_prefix("mqtt://") {
+            (false, rest)
+        } else if broker.contains("://") {
+            return Err(format!(
+                "MQTT.Connect: '{}' is not a mqtt:// or mqtts:// broker",
+                broker
+            ));
+        } else {
+            (false, broker)
+        };
+        let address = address.trim_end_matches('/');
+
+        let mut parsed = Options {
+            host: address.to_string(),
+            port: 0,
+            tls,
+            #[cfg(feature = "tls")]
+            tls_options: Default::default(),
+            client_id: format!("sfex-{:08x}", rand::random::<u32>()),
+            username: None,
+            password: None,
+            keep_alive: 60,
+            clean_session: true,
+            timeout: Duration::from_secs(30),
+        };
+        // The last colon, unless it's inside an IPv6 address in brackets
+        if let Some((host, port)) = address.rsplit_once(':')
+            && !port.contains(']')
+        {
+            parsed.host = host.to_string();
+            parsed.port = port
+                .parse()
+                .map_err(|_| format!("MQTT.Connect: '{}' is not a port", port))?;
+        }
+        parsed.host = parsed
+            .host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+
+        if let Some(options) = options {
+            let Value::Map(map) = options else {
+                return Err(format!(
+                    "MQTT.Connect options must be a Map, not {}",
+                    options.type_name()
+                ));
+            };
+            for (key, value) in map.read_recover().iter() {
+                match key.as_str() {
+                    "ClientId" => parsed.client_id = value.to_display_string(),
+                    "Username" => parsed.username = Some(value.to_display_string()),
+                    "Password" => parsed.password = Some(value.to_display_string()),
+                    "KeepAlive" => {
+                        parsed.keep_alive = whole_number(value)
+                            .and_then(|n| u16::try_from(n).ok())
+                            .ok_or("MQTT.Connect KeepAlive must be a number of seconds")?
+                    }
+                    "CleanSession" => parsed.clean_session = value.is_truthy(),
+                    "Timeout" => {
+                        parsed.timeout = seconds(value)
+                            .ok_or("MQTT.Connect Timeout must be a number of seconds")?
+                    }
+                    "Tls" => parsed.tls = value.is_truthy(),
+                    #[cfg(feature = "tls")]
+                    _ if parsed.tls_options.set(key, value) => parsed.tls = true,
+                    _ => return Err(format!("MQTT.Connect has no option {}", key)),
+                }
+            }
+        }
+        if parsed.port == 0 {
+            parsed.port = if parsed.tls { 8883 } else { 1883 };
+        }
+        if parsed.password.is_some() && parsed.username.is_none() {
+            return Err("MQTT.Connect: a Password needs a Username".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+fn whole_number(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.to_u64(),
+        Value::Integer(i) => i.to_u64(),
+        _ => None,
+    }
+}
+
+fn seconds(value: &Value) -> Option<Duration> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+    .filter(|s| s.is_finite() && *s > 0.0)
+    .map(Duration::from_secs_f64)
+}
+
+fn qos(value: &Value) -> Result<u8, String> {
+    match whole_number(value) {
+        Some(qos @ 0..=2) => Ok(qos as u8),
+        _ => Err(format!("MQTT QoS must be 0, 1 or 2, not {}", value)),
+    }
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind];
+    // The remaining length, seven bits at a time
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn put_string(out: &mut Vec<u8>, text: &[u8]) {
+    out.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    out.extend_from_slice(text);
+}
+
+fn connect_packet(options: &Options) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, b"MQTT");
+    body.push(4); // protocol level 3.1.1
+    let mut flags = 0u8;
+    if options.clean_session {
+        flags |= 0x02;
+    }
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&options.keep_alive.to_be_bytes());
+    put_string(&mut body, options.client_id.as_bytes());
+    if let Some(username) = &options.username {
+        put_string(&mut body, username.as_bytes());
+    }
+    if let Some(password) = &options.password {
+        put_string(&mut body, password.as_bytes());
+    }
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8], qos: u8, retain: bool, id: u16) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, topic.as_bytes());
+    if qos > 0 {
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    packet(PUBLISH | (qos << 1) | retain as u8, &body)
+}
+
+fn subscription_packet(kind: u8, id: u16, filter: &str, qos: Option<u8>) -> Vec<u8> {
+    let mut body = id.to_be_bytes().to_vec();
+    put_string(&mut body, filter.as_bytes());
+    body.extend(qos);
+    packet(kind, &body)
+}
+
+fn id_packet(kind: u8, id: u16) -> Vec<u8> {
+    packet(kind, &id.to_be_bytes())
+}
+
+#[derive(Debug)]
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    qos: u8,
+    retain: bool,
+}
+
+#[derive(Debug)]
+enum Incoming {
+    ConnAck(u8),
+    Publish(Message, u16),
+    PubAck(u16),
+    PubRec(u16),
+    PubRel(u16),
+    PubComp(u16),
+    SubAck(u16, Vec<u8>),
+    UnsubAck(u16),
+    PingResp,
+}
+
+fn decode(header: u8, body: &[u8]) -> Result<Incoming, String> {
+    let id = |at: usize| -> Result<u16, String> {
+        body.get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| "MQTT packet is too short".to_string())
+    };
+    Ok(match header & 0xF0 {
+        CONNACK => Incoming::ConnAck(*body.get(1).ok_or("MQTT CONNACK is too short")?),
+        PUBLISH => {
+            let qos = (header >> 1) & 0x03;
+            let topic_length = id(0)? as usize;
+            let topic = body
+                .get(2..2 + topic_length)
+                .ok_or("MQTT PUBLISH is too short")?;
+            let topic = String::from_utf8(topic.to_vec())
+                .map_err(|_| "MQTT PUBLISH topic is not UTF-8".to_string())?;
+            let mut at = 2 + topic_length;
+            let packet_id = if qos > 0 {
+                at += 2;
+                id(at - 2)?
+            } else {
+                0
+            };
+            let message = Message {
+                topic,
+                payload: body[at..].to_vec(),
+                qos,
+                retain: header & 0x01 != 0,
+            };
+            Incoming::Publish(message, packet_id)
+        }
+        PUBACK => Incoming::PubAck(id(0)?),
+        PUBREC => Incoming::PubRec(id(0)?),
+        0x60 => Incoming::PubRel(id(0)?),
+        PUBCOMP => Incoming::PubComp(id(0)?),
+        SUBACK => Incoming::SubAck(id(0)?, body[2..].to_vec()),
+        UNSUBACK => Incoming::UnsubAck(id(0)?),
+        PINGRESP => Incoming::PingResp,
+        other => return Err(format!("Unexpected MQTT packet type {}", other >> 4)),
+    })
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Incoming, String> {
+    let closed = |e: std::io::Error| format!("MQTT connection closed: {}", e);
+    let header = reader.read_u8().await.map_err(closed)?;
+    let mut length = 0usize;
+    for shift in (0..28).step_by(7) {
+        let byte = reader.read_u8().await.map_err(closed)?;
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if length > MAX_PACKET {
+        return Err(format!(
+            "MQTT broker sent a {} byte packet, more than {}",
+            length, MAX_PACKET
+        ));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await.map_err(closed)?;
+    decode(header, &body)
+}
+
+/// Whether `topic` matches a Subscribe filter, with `+` for one level and
+/// `#` for any number of levels at the end.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    // Wildcards at the start don't match the broker's own $SYS topics
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(topic_level)) if level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+struct Subscription {
+    filter: String,
+    messages: mpsc::UnboundedSender<Message>,
+}
+
+struct Client {
+    runtime: Arc<Runtime>,
+    client_id: String,
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    // Callers waiting for the broker to acknowledge a packet id; SUBACK
+    // gives its return codes, the others nothing
+    acks: Mutex<HashMap<u16, oneshot::Sender<Vec<u8>>>>,
+    subscriptions: Mutex<Vec<Subscription>>,
+    next_id: AtomicU16,
+    timeout: Duration,
+}
+
+impl Client {
+    fn packet_id(&self) -> u16 {
+        loop {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            if id != 0 {
+                return id;
+            }
+        }
+    }
+
+    fn send(&self, packet: Vec<u8>) -> Result<(), String> {
+        self.outgoing
+            .send(packet)
+            .map_err(|_| "MQTT connection is closed".to_string())
+    }
+
+    /// Send a packet and wait for the broker to acknowledge `id`.
+    fn request(&self, id: u16, packet: Vec<u8>) -> Result<Vec<u8>, String> {
+        let (done, acknowledged) = oneshot::channel();
+        self.acks.lock_recover().insert(id, done);
+        self.send(packet)?;
+        let waited = deadline::block_on(
+            &self.runtime,
+            // The timer has to be made on the runtime
+            async { tokio::time::timeout(self.timeout, acknowledged).await },
+        );
+        match waited {
+            Ok(Ok(Ok(reply))) => Ok(reply),
+            Ok(Ok(Err(_))) => Err("MQTT connection is closed".to_string()),
+            Ok(Err(_)) => {
+                self.acks.lock_recover().remove(&id);
+                Err(format!(
+                    "MQTT broker didn't answer within {} seconds",
+                    self.timeout.as_secs_f64()
+                ))
+            }
+            Err(exceeded) => {
+                self.acks.lock_recover().remove(&id);
+                Err(exceeded)
+            }
+        }
+    }
+
+    fn acknowledged(&self, id: u16, reply: Vec<u8>) {
+        if let Some(done) = self.acks.lock_recover().remove(&id) {
+            let _ = done.send(reply);
+        }
+    }
+
+    fn deliver(&self, message: Message) {
+        let mut subscriptions = self.subscriptions.lock_recover();
+        // Drop the Streams the script has let go of
+        subscriptions.retain(|subscription| !subscription.messages.is_closed());
+        let matching: Vec<_> = subscriptions
+            .iter()
+            .filter(|subscription| topic_matches(&subscription.filter, &message.topic))
+            .collect();
+        let Some((last, others)) = matching.split_last() else {
+            return;
+        };
+        for subscription in others {
+            let _ = subscription.messages.send(Message {
+                topic: message.topic.clone(),
+                payload: message.payload.clone(),
+                qos: message.qos,
+                retain: message.retain,
+            });
+        }
+        let _ = last.messages.send(message);
+    }
+}
+
+fn connect(runtime: Arc<Runtime>, options: Options) -> Result<Arc<Client>, String> {
+    permissions::check_host(&options.host, Some(options.port))?;
+    let timeout = options.timeout;
+    let opened = deadline::block_on(&runtime, async {
+        tokio::time::timeout(timeout, handshake(&options)).await
+    })?;
+    let transport = opened.unwrap_or_else(|_| {
+        Err(format!(
+            "MQTT.Connect to {}:{} timed out",
+            options.host, options.port
+        ))
+    })?;
+
+    let (outgoing, outgoing_packets) = mpsc::unbounded_channel();
+    let client = Arc::new(Client {
+        runtime: runtime.clone(),
+        client_id: options.client_id,
+        outgoing,
+        acks: Mutex::new(HashMap::new()),
+        subscriptions: Mutex::new(Vec::new()),
+        next_id: AtomicU16::new(1),
+        timeout,
+    });
+    runtime.spawn(run(
+        transport,
+        client.clone(),
+        outgoing_packets,
+        options.keep_alive,
+    ));
+    Ok(client)
+}
+
+async fn handshake(options: &Options) -> Result<Box<dyn Transport>, String> {
+    let tcp = tokio::net::TcpStream::connect((options.host.as_str(), options.port))
+        .await
+        .map_err(|e| {
+            format!(
+                "MQTT connection to {}:{} failed: {}",
+                options.host, options.port, e
+            )
+        })?;
+    let mut transport: Box<dyn Transport> = if options.tls {
+        tls(options, tcp).await?
+    } else {
+        Box::new(tcp)
+    };
+
+    transport
+        .write_all(&connect_packet(options))
+        .await
+        .map_err(|e| format!("MQTT connection failed: {}", e))?;
+    match read_packet(&mut transport).await? {
+        Incoming::ConnAck(0) => Ok(transport),
+        Incoming::ConnAck(code) => Err(format!(
+            "MQTT broker refused the connection: {}",
+            match code {
+                1 => "it doesn't speak MQTT 3.1.1",
+                2 => "the ClientId was rejected",
+                3 => "the server is unavailable",
+                4 => "bad Username or Password",
+                5 => "not authorized",
+                _ => "unknown reason",
+            }
+        )),
+        other => Err(format!("MQTT broker sent {:?} instead of CONNACK", other)),
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn tls(options: &Options, tcp: tokio::net::TcpStream) -> Result<Box<dyn Transport>, String> {
+    let config = options.tls_options.client_config("MQTT.Connect")?;
+    let name = options
+        .tls_options
+        .server_name(&options.host, "MQTT.Connect")?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .map_err(|e| format!("TLS handshake with {} failed: {}", options.host, e))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(feature = "tls"))]
+async fn tls(
+    _options: &Options,
+    _tcp: tokio::net::TcpStream,
+) -> Result<Box<dyn Transport>, String> {
+    Err("sfex was built without the tls feature, so it can't use mqtts://".to_string())
+}
+
+/// The connection's task: writes what the script sends, pings the broker
+/// every KeepAlive seconds, and answers what the broker sends, until either
+/// side disconnects.
+async fn run(
+    transport: Box<dyn Transport>,
+    client: Arc<Client>,
+    mut outgoing: mpsc::UnboundedReceiver<Vec<u8>>,
+    keep_alive: u16,
+) {
+    let (mut reader, mut writer) = tokio::io::split(transport);
+    // Packets are read on their own task, since a read cut short by select!
+    // would lose the bytes it had
+    let (incoming_packets, mut incoming) = mpsc::unbounded_channel();
+    let read_task = tokio::spawn(async move {
+        while let Ok(packet) = read_packet(&mut reader).await {
+            if incoming_packets.send(packet).is_err() {
+                break;
+            }
+        }
+    });
+
+    let ping_every = Duration::from_secs(keep_alive.max(1) as u64);
+    let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + ping_every, ping_every);
+    // QoS 2 ids whose message has been delivered but not yet released
+    let mut received = HashSet::new();
+    loop {
+        let reply = tokio::select! {
+            packet = outgoing.recv() => match packet {
+                Some(packet) if packet[0] == DISCONNECT => {
+                    let _ = writer.write_all(&packet).await;
+                    let _ = writer.shutdown().await;
+                    break;
+                }
+                Some(packet) => Some(packet),
+                None => break,
+            },
+            _ = ping.tick(), if keep_alive > 0 => Some(vec![PINGREQ, 0]),
+            packet = incoming.recv() => match packet {
+                None => break,
+                Some(Incoming::Publish(message, id)) => match message.qos {
+                    0 => {
+                        client.deliver(message);
+                        None
+                    }
+                    1 => {
+                        client.deliver(message);
+                        Some(id_packet(PUBACK, id))
+                    }
+                    _ => {
+                        // Deliver once, even if the broker sends it again
+                        if received.insert(id) {
+                            client.deliver(message);
+                        }
+                        Some(id_packet(PUBREC, id))
+                    }
+                },
+                Some(Incoming::PubRel(id)) => {
+                    received.remove(&id);
+                    Some(id_packet(PUBCOMP, id))
+                }
+                Some(Incoming::PubRec(id)) => Some(id_packet(PUBREL, id)),
+                Some(Incoming::SubAck(id, codes)) => {
+                    client.acknowledged(id, codes);
+                    None
+                }
+                Some(Incoming::PubAck(id) | Incoming::PubComp(id) | Incoming::UnsubAck(id)) => {
+                    client.acknowledged(id, Vec::new());
+                    None
+                }
+                Some(Incoming::ConnAck(_) | Incoming::PingResp) => None,
+            },
+        };
+        if let Some(reply) = reply
+            && writer.write_all(&reply).await.is_err()
+        {
+            break;
+        }
+    }
+
+    read_task.abort();
+    outgoing.close();
+    // Ending the Streams and failing the calls still waiting
+    client.subscriptions.lock_recover().clear();
+    client.acks.lock_recover().clear();
+}
+
+fn create_client_object(client: Arc<Client>) -> Value {
+    let mut methods = IndexMap::new();
+
+    methods.insert(
+        "ClientId".to_string(),
+        Value::String(client.client_id.clone()),
+    );
+
+    // Client.Publish("home/lamp", "on", 1, True)
+    let client_publish = client.clone();
+    methods.insert(
+        "Publish".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() < 2 || args.len() > 4 {
+                return Err(
+                    "Client.Publish requires 2-4 arguments (topic, payload, optional qos, optional retain)"
+                        .to_string(),
+                );
+            }
+            let topic = args[0].to_display_string();
+            if topic.is_empty() || topic.contains(['+', '#']) {
+                return Err(format!(
+                    "Client.Publish: '{}' can't be published to; topics have no wildcards",
+                    topic
+                ));
+            }
+            let qos = args.get(2).map(qos).transpose()?.unwrap_or(0);
+            let retain = args.get(3).is_some_and(Value::is_truthy);
+            let payload = args[1].as_bytes();
+
+            if qos == 0 {
+                client_publish.send(publish_packet(&topic, &payload, 0, retain, 0))?;
+            } else {
+                let id = client_publish.packet_id();
+                client_publish.request(id, publish_packet(&topic, &payload, qos, retain, id))?;
+            }
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Client.Subscribe("sensors/+/temperature", { Qos: 1 }) -> Stream of messages
+    let client_subscribe = client.clone();
+    methods.insert(
+        "Subscribe".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Client.Subscribe requires 1-2 arguments (topic, optional qos or options)"
+                        .to_string(),
+                );
+            }
+            let filter = args[0].to_display_string();
+            let mut requested_qos = 0;
+            let mut bytes = false;
+            match args.get(1) {
+                None => {}
+                Some(Value::Map(map)) => {
+                    for (key, value) in map.read_recover().iter() {
+                        match key.as_str() {
+                            "Qos" => requested_qos = qos(value)?,
+                            "Bytes" => bytes = value.is_truthy(),
+                            _ => return Err(format!("Client.Subscribe has no option {}", key)),
+                        }
+                    }
+                }
+                Some(value) => requested_qos = qos(value)?,
+            }
+
+            // Listen before subscribing, so retained messages the broker
+            // sends straight after SUBACK aren't missed
+            let (messages, receiver) = mpsc::unbounded_channel();
+            client_subscribe
+                .subscriptions
+                .lock_recover()
+                .push(Subscription {
+                    filter: filter.clone(),
+                    messages,
+                });
+            let id = client_subscribe.packet_id();
+            let packet = subscription_packet(SUBSCRIBE, id, &filter, Some(requested_qos));
+            let codes = client_subscribe.request(id, packet)?;
+            if codes.first().is_none_or(|code| *code > 2) {
+                return Err(format!(
+                    "MQTT broker refused the subscription to {}",
+                    filter
+                ));
+            }
+
+            let runtime = client_subscribe.runtime.clone();
+            let receiver = Mutex::new(receiver);
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                let Some(message) = deadline::block_on(&runtime, receiver.lock_recover().recv())?
+                else {
+                    return Ok(Value::Option(Box::new(None)));
+                };
+                Ok(Value::Option(Box::new(Some(message_value(
+                    message, bytes,
+                )?))))
+            })));
+            let stream = crate::stdlib::stream::create_stream_object(vec![], Some(generator));
+            if let Value::Map(map) = &stream {
+                map.write_recover()
+                    .insert("Topic".to_string(), Value::String(filter));
+            }
+            Ok(stream)
+        }))),
+    );
+
+    // Client.Unsubscribe("sensors/+/temperature") - ends its Streams
+    let client_unsubscribe = client.clone();
+    methods.insert(
+        "Unsubscribe".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Client.Unsubscribe requires 1 argument (topic)".to_string());
+            }
+            let filter = args[0].to_display_string();
+            let id = client_unsubscribe.packet_id();
+            client_unsubscribe.request(id, subscription_packet(UNSUBSCRIBE, id, &filter, None))?;
+            client_unsubscribe
+                .subscriptions
+                .lock_recover()
+                .retain(|subscription| subscription.filter != filter);
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Client.IsConnected()
+    let client_connected = client.clone();
+    methods.insert(
+        "IsConnected".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            Ok(Value::Boolean(!client_connected.outgoing.is_closed()))
+        }))),
+    );
+
+    // Client.Close()
+    let client_close = client;
+    methods.insert(
+        "Close".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            // Already closed is fine
+            if client_close.send(vec![DISCONNECT, 0]).is_ok() {
+                // Wait for the task to write everything queued before it, so
+                // a script can publish, Close and end without losing messages
+                let timeout = client_close.timeout;
+                let outgoing = &client_close.outgoing;
+                deadline::block_on(&client_close.runtime, async {
+                    tokio::time::timeout(timeout, outgoing.closed()).await
+                })?
+                .map_err(|_| "MQTT broker didn't take the disconnect in time".to_string())?;
+            }
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+}
+
+fn message_value(message: Message, bytes: bool) -> Result<Value, String> {
+    let payload = if bytes {
+        Value::Bytes(message.payload.into())
+    } else {
+        Value::String(String::from_utf8(message.payload).map_err(|_| {
+            format!(
+                "MQTT message on {} is not UTF-8 (subscribe with {{ Bytes: True }} for binary payloads)",
+                message.topic
+            )
+        })?)
+    };
+    let mut map = IndexMap::new();
+    map.insert("Topic".to_string(), Value::String(message.topic));
+    map.insert("Payload".to_string(), payload);
+    map.insert("Qos".to_string(), Value::Integer(message.qos.into()));
+    map.insert("Retain".to_string(), Value::Boolean(message.retain));
+    Ok(Value::Map(Arc::new(std::sync::RwLock::new(map))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("home/+/temp", "home/kitchen/temp"));
+        assert!(!topic_matches("home/+/temp", "home/kitchen/humidity"));
+        assert!(!topic_matches("home/+", "home/kitchen/temp"));
+        assert!(topic_matches("home/#", "home"));
+        assert!(topic_matches("home/#", "home/kitchen/temp"));
+        assert!(topic_matches("#", "anything/at/all"));
+        assert!(!topic_matches("#", "$SYS/uptime"));
+        assert!(topic_matches("$SYS/#", "$SYS/uptime"));
+        assert!(!topic_matches("home/kitchen", "home/kitchen/temp"));
+    }
+
+    #[test]
+    fn test_packet_cap() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        // A PUBLISH claiming the most MQTT allows, 256 MB
+        let mut input: &[u8] = &[PUBLISH, 0xFF, 0xFF, 0xFF, 0x7F];
+        let Err(e) = runtime.block_on(read_packet(&mut input)) else {
+            panic!("a 256 MB packet was read");
+        };
+        assert!(e.contains("more than"), "{}", e);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::parse("mqtt://broker.local", None).unwrap();
+        assert_eq!(
+            (options.host.as_str(), options.port),
+            ("broker.local", 1883)
+        );
+        let options = Options::parse("[::1]:1884", None).unwrap();
+        assert_eq!((options.host.as_str(), options.port), ("::1", 1884));
+        assert!(Options::parse("http://broker.local", None).is_err());
+        #[cfg(feature = "tls")]
+        {
+            let options = Options::parse("mqtts://broker.local", None).unwrap();
+            assert!(options.tls);
+            assert_eq!(options.port, 8883);
+        }
+    }
+
+    fn read_raw(stream: &mut std::net::TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            length |= ((byte[0] & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).unwrap();
+        (header, body)
+    }
+
+    fn call(object: &Value, name: &str, args: Vec<Value>) -> Value {
+        let Value::Map(map) = object else {
+            panic!("{} is called on a Map", name);
+        };
+        let function = map.read_recover()[name].clone();
+        let Value::NativeFunction(function) = function else {
+            panic!("{} is a function", name);
+        };
+        function(args).unwrap()
+    }
+
+    #[test]
+    fn test_client_against_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (header, body) = read_raw(&mut stream);
+            assert_eq!(header, CONNECT);
+            assert!(body.ends_with(b"\x00\x06sensor\x00\x04user\x00\x04pass"));
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+
+            let (header, body) = read_raw(&mut stream);
+            assert_eq!(header, SUBSCRIBE);
+            assert_eq!(&body[2..], b"\x00\x0bhome/+/temp\x01");
+            stream.write_all(&[SUBACK, 3, body[0], body[1], 1]).unwrap();
+            stream
+                .write_all(&publish_packet("home/kitchen/temp", b"21.5", 1, true, 7))
+                .unwrap();
+            assert_eq!(read_raw(&mut stream), (PUBACK, vec![0, 7]));
+
+            let (header, body) = read_raw(&mut stream);
+            assert_eq!(header, PUBLISH | 2 << 1);
+            assert_eq!(&body[..7], b"\x00\x05alarm");
+            assert_eq!(&body[9..], b"ring");
+            stream.write_all(&id_packet(PUBREC, 2)).unwrap();
+            assert_eq!(read_raw(&mut stream), (PUBREL, vec![0, 2]));
+            stream.write_all(&id_packet(PUBCOMP, 2)).unwrap();
+
+            assert_eq!(read_raw(&mut stream).0, DISCONNECT);
+        });
+
+        let options = Value::Map(Arc::new(std::sync::RwLock::new(
+            [
+                ("ClientId", "sensor"),
+                ("Username", "user"),
+                ("Password", "pass"),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+            .collect(),
+        )));
+        let options = Options::parse(&address, Some(&options)).unwrap();
+        let runtime = crate::runtime::executor::shared_runtime();
+        let client = create_client_object(connect(runtime, options).unwrap());
+
+        let messages = call(
+            &client,
+            "Subscribe",
+            vec![
+                Value::String("home/+/temp".to_string()),
+                Value::Integer(1.into()),
+            ],
+        );
+        let Value::Option(message) = call(&messages, "Next", vec![]) else {
+            panic!("Next gives an Option");
+        };
+        let Some(Value::Map(message)) = *message else {
+            panic!("a message is a Map");
+        };
+        let message = message.read_recover();
+        assert_eq!(message["Topic"].to_display_string(), "home/kitchen/temp");
+        assert_eq!(message["Payload"].to_display_string(), "21.5");
+        assert!(matches!(message["Retain"], Value::Boolean(true)));
+
+        call(
+            &client,
+            "Publish",
+            vec![
+                Value::String("alarm".to_string()),
+                Value::String("ring".to_string()),
+                Value::Integer(2.into()),
+            ],
+        );
+        call(&client, "Close", vec![]);
+        broker.join().unwrap();
+
+        // The connection's task ends the Stream once the broker has gone
+        let Value::Option(message) = call(&messages, "Next", vec![]) else {
+            panic!("Next gives an Option");
+        };
+        assert!(message.is_none());
+    }
+}
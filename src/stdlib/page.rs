@@ -0,0 +1,298 @@
+use crate::compiler::ast::{Program, Story};
+use crate::compiler::edition::Edition;
+use crate::compiler::lexer::Lexer;
+use crate::compiler::parser::Parser;
+use std::fs;
+use std::path::Path;
+
+// An .sfexhtml page is a whole web app in one file: HTML parts, each under a
+// front-matter route, with an optional ```sfex block holding its handler.
+//
+//   ---
+//   route: GET /hello/:name
+//   ---
+//   ```sfex
+//   Story:
+//       Name is Params["name"]
+//   ```
+//   <h1>Hello {{ Name }}</h1>
+//
+// `sfex serve app.sfexhtml` registers a route per part. A request runs the
+// part's block, then renders its HTML with the template engine using the
+// block's variables as data, unless the block set Response itself. A file
+// without front matter is a single page served at `/`.
+
+pub const PAGE_EXTENSION: &str = "sfexhtml";
+
+const ROUTE_METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE"];
+
+pub struct PageRoute {
+    /// None matches any method
+    pub method: Option<String>,
+    pub path: String,
+    /// Line of the route's front matter (1 for a page without one)
+    pub line: usize,
+    pub program: Program,
+    pub template: String,
+}
+
+impl PageRoute {
+    /// `GET /users/:id`, as written in the front matter.
+    pub fn describe(&self) -> String {
+        format!("{} {}", self.method.as_deref().unwrap_or("ANY"), self.path)
+    }
+}
+
+pub fn is_page(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == PAGE_EXTENSION)
+}
+
+pub fn load_page(path: &Path) -> Result<Vec<PageRoute>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read page '{}': {}", path.display(), e))?;
+    let edition = crate::project::edition_for(path)?;
+    parse_page(&source, &path.display().to_string(), edition)
+}
+
+struct Part<'a> {
+    method: Option<String>,
+    path: String,
+    line: usize,
+    // Index of the block's first line, and its source
+    code: Option<(usize, String)>,
+    html: Vec<&'a str>,
+}
+
+impl Part<'_> {
+    fn new(method: Option<String>, path: String, line: usize) -> Self {
+        Self {
+            method,
+            path,
+            line,
+            code: None,
+            html: Vec::new(),
+        }
+    }
+}
+
+/// Split a page into its routes, compiling each ```sfex block. Blocks are
+/// parsed with their lines in place, so errors point at lines of the page.
+pub fn parse_page(source: &str, name: &str, edition: Edition) -> Result<Vec<PageRoute>, String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut parts: Vec<Part> = Vec::new();
+    let mut implicit = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let number = i + 1;
+
+        if line.trim_end() == "---" {
+            if implicit {
+                return Err(page_error(
+                    name,
+                    number,
+                    "front matter must come before any content",
+                ));
+            }
+            let mut route = None;
+            i += 1;
+            loop {
+                let Some(line) = lines.get(i) else {
+                    return Err(page_error(
+                        name,
+                        number,
+                        "front matter is not closed with ---",
+                    ));
+                };
+                i += 1;
+                if line.trim_end() == "---" {
+                    break;
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let Some((key, value)) = line.split_once(':') else {
+                    return Err(page_error(name, i, "expected `key: value` in front matter"));
+                };
+                match key.trim() {
+                    "route" => {
+                        route =
+                            Some(parse_route(value.trim()).map_err(|e| page_error(name, i, &e))?)
+                    }
+                    other => {
+                        return Err(page_error(
+                            name,
+                            i,
+                            &format!("unknown front matter key '{}'", other),
+                        ));
+                    }
+                }
+            }
+            let (method, path) = route.ok_or_else(|| {
+                page_error(
+                    name,
+                    number,
+                    "front matter needs a route, e.g. `route: GET /`",
+                )
+            })?;
+            if let Some(earlier) = parts
+                .iter()
+                .find(|part| part.method == method && part.path == path)
+            {
+                return Err(page_error(
+                    name,
+                    number,
+                    &format!("route is already defined on line {}", earlier.line),
+                ));
+            }
+            parts.push(Part::new(method, path, number));
+            continue;
+        }
+
+        if parts.is_empty() {
+            if line.trim().is_empty() {
+                i += 1;
+                continue;
+            }
+            parts.push(Part::new(None, "/".to_string(), 1));
+            implicit = true;
+        }
+        let Some(part) = parts.last_mut() else {
+            break;
+        };
+
+        if line.trim() == "```sfex" {
+            if part.code.is_some() {
+                return Err(page_error(
+                    name,
+                    number,
+                    "a route can have only one ```sfex block",
+                ));
+            }
+            let start = i + 1;
+            let end = lines[start..]
+                .iter()
+                .position(|line| line.trim() == "```")
+                .map(|offset| start + offset)
+                .ok_or_else(|| page_error(name, number, "```sfex block is not closed with ```"))?;
+            part.code = Some((start, lines[start..end].join("\n")));
+            i = end + 1;
+            continue;
+        }
+
+        part.html.push(line);
+        i += 1;
+    }
+
+    parts
+        .into_iter()
+        .map(|part| {
+            let program = match &part.code {
+                Some((start, code)) => compile_block(*start, code, name, edition)?,
+                None => empty_program(),
+            };
+            Ok(PageRoute {
+                method: part.method,
+                path: part.path,
+                line: part.line,
+                program,
+                template: part.html.join("\n").trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `GET /path`, or just `/path` for any method.
+fn parse_route(route: &str) -> Result<(Option<String>, String), String> {
+    let (method, path) = match route.split_once(char::is_whitespace) {
+        Some((method, path)) => (Some(method.to_ascii_uppercase()), path.trim()),
+        None => (None, route),
+    };
+    let method = match method.as_deref() {
+        None | Some("ANY") => None,
+        Some(m) if ROUTE_METHODS.contains(&m) => method,
+        Some(m) => {
+            return Err(format!(
+                "unknown method '{}' (use {} or ANY)",
+                m,
+                ROUTE_METHODS.join(", ")
+            ));
+        }
+    };
+    if !path.starts_with('/') {
+        return Err(format!("route path must start with '/', got '{}'", path));
+    }
+    Ok((method, path.to_string()))
+}
+
+fn compile_block(
+    start: usize,
+    code: &str,
+    name: &str,
+    edition: Edition,
+) -> Result<Program, String> {
+    let source = "\n".repeat(start) + code;
+    let tokens = Lexer::with_edition(&source, edition)
+        .tokenize()
+        .map_err(|e| format!("Lexer error in '{}': {}", name, e))?;
+    Parser::with_edition(tokens, edition)
+        .parse()
+        .map_err(|e| format!("Parser error in '{}': {}", name, e))
+}
+
+fn empty_program() -> Program {
+    Program {
+        story: Story { body: Vec::new() },
+        concepts: Vec::new(),
+        situations: Vec::new(),
+        tracked_concepts: Vec::new(),
+        includes: Vec::new(),
+    }
+}
+
+fn page_error(name: &str, line: usize, message: &str) -> String {
+    format!("Page error in '{}' line {}: {}", name, line, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_page() {
+        let source = "---\nroute: GET /hello/:name\n---\n```sfex\nStory:\n    Name is Params[\"name\"]\n```\n<h1>Hello {{ Name }}</h1>\n\n---\nroute: /about\n---\n<p>About</p>\n";
+        let routes = parse_page(source, "app.sfexhtml", Edition::default()).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].describe(), "GET /hello/:name");
+        assert_eq!(routes[0].template, "<h1>Hello {{ Name }}</h1>");
+        assert_eq!(routes[0].program.story.body.len(), 1);
+        assert_eq!(
+            (routes[1].describe(), routes[1].line),
+            ("ANY /about".to_string(), 10)
+        );
+        assert!(routes[1].program.story.body.is_empty());
+
+        let single = parse_page("<p>Hi</p>", "index.sfexhtml", Edition::default()).unwrap();
+        assert_eq!(single[0].describe(), "ANY /");
+
+        // Errors in a block point at the page's own lines
+        let error = parse_page(
+            "---\nroute: /\n---\n```sfex\nStory:\n    X is (\n```",
+            "bad.sfexhtml",
+            Edition::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(error.contains("line 6"), "{}", error);
+        assert!(parse_page("---\nroute: FETCH /\n---", "x", Edition::default()).is_err());
+        assert!(
+            parse_page(
+                "---\nroute: /\n---\n---\nroute: /\n---",
+                "x",
+                Edition::default()
+            )
+            .is_err()
+        );
+    }
+}
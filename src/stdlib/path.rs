@@ -0,0 +1,388 @@
+use crate::runtime::lock::MutexExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::BigDecimal;
+use indexmap::IndexMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+pub fn create_path_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Path.Join("src", "lib", "main.sfex") -> "src/lib/main.sfex"
+    methods.insert(
+        "Join".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() {
+                return Err("Path.Join requires at least 1 argument (path parts)".to_string());
+            }
+            let joined: PathBuf = args.iter().map(Value::to_display_string).collect();
+            Ok(path_value(&joined))
+        }))),
+    );
+
+    // Path.Basename("src/main.sfex") -> "main.sfex"
+    insert_part(&mut methods, "Basename", |path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    });
+    // Path.Dirname("src/main.sfex") -> "src"
+    insert_part(&mut methods, "Dirname", |path| {
+        path.parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+    });
+    // Path.Extension("src/main.sfex") -> "sfex"
+    insert_part(&mut methods, "Extension", |path| {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+    });
+
+    // Path.Absolute("notes.txt") -> "/home/me/notes.txt", whether or not it exists
+    methods.insert(
+        "Absolute".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let path = single_path("Path.Absolute", &args)?;
+            let absolute = std::path::absolute(&path)
+                .map_err(|e| format!("Failed to make {} absolute: {}", path.display(), e))?;
+            // `a/../b` is `b`, without looking at what `a` is on disk
+            let mut normal = PathBuf::new();
+            for component in absolute.components() {
+                match component {
+                    Component::ParentDir => {
+                        normal.pop();
+                    }
+                    Component::CurDir => {}
+                    other => normal.push(other),
+                }
+            }
+            Ok(path_value(&normal))
+        }))),
+    );
+
+    insert_check(&mut methods, "Exists", Path::exists);
+    insert_check(&mut methods, "IsDir", Path::is_dir);
+    insert_check(&mut methods, "IsFile", Path::is_file);
+
+    // Path.Walk("src") -> Stream of every entry below src, parents first
+    methods.insert(
+        "Walk".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let root = single_path("Path.Walk", &args)?;
+            permissions::check_read(&root)?;
+            if !root.is_dir() {
+                return Err(format!("Path.Walk: {} is not a directory", root.display()));
+            }
+            let pending = Mutex::new(children(&root, 1)?);
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                let mut pending = pending.lock_recover();
+                let Some((path, depth, is_dir)) = pending.pop() else {
+                    return Ok(Value::Option(Box::new(None)));
+                };
+                if is_dir {
+                    pending.extend(children(&path, depth + 1)?);
+                }
+                Ok(Value::Option(Box::new(Some(entry_value(
+                    &path, depth, is_dir,
+                )))))
+            })));
+            Ok(crate::stdlib::stream::create_stream_object(
+                vec![],
+                Some(generator),
+            ))
+        }))),
+    );
+
+    // Path.Glob("src/**/*.sfex") -> sorted List of matching paths
+    methods.insert(
+        "Glob".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Path.Glob requires 1 argument (pattern)".to_string());
+            }
+            let mut found = glob(&args[0].to_display_string())?;
+            found.sort();
+            found.dedup();
+            let paths = found.iter().map(|path| path_value(path)).collect();
+            Ok(Value::List(Arc::new(RwLock::new(paths))))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn path_value(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+fn single_path(name: &str, args: &[Value]) -> Result<PathBuf, String> {
+    if args.len() != 1 {
+        return Err(format!("{} requires 1 argument (path)", name));
+    }
+    Ok(PathBuf::from(args[0].to_display_string()))
+}
+
+/// `Path.<name>(path)`: one part of the path, or "" when it has none.
+fn insert_part(
+    methods: &mut IndexMap<String, Value>,
+    name: &str,
+    part: fn(&Path) -> Option<String>,
+) {
+    let full_name = format!("Path.{}", name);
+    methods.insert(
+        name.to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let path = single_path(&full_name, &args)?;
+            Ok(Value::String(part(&path).unwrap_or_default()))
+        }))),
+    );
+}
+
+fn insert_check(methods: &mut IndexMap<String, Value>, name: &str, check: fn(&Path) -> bool) {
+    let full_name = format!("Path.{}", name);
+    methods.insert(
+        name.to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let path = single_path(&full_name, &args)?;
+            permissions::check_read(&path)?;
+            Ok(Value::Boolean(check(&path)))
+        }))),
+    );
+}
+
+/// The entries of `dir` at `depth`, last name first so they can be popped in
+/// order. Links to directories are listed but not followed, so a link back up
+/// the tree can't make a walk endless.
+fn children(dir: &Path, depth: usize) -> Result<Vec<(PathBuf, usize, bool)>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut children = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+        children.push((entry.path(), depth, is_dir));
+    }
+    children.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(children)
+}
+
+fn entry_value(path: &Path, depth: usize, is_dir: bool) -> Value {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let entry = IndexMap::from([
+        ("Path".to_string(), path_value(path)),
+        ("Name".to_string(), Value::String(name)),
+        ("IsDir".to_string(), Value::Boolean(is_dir)),
+        (
+            "Depth".to_string(),
+            Value::Number(BigDecimal::from(depth as u64)),
+        ),
+    ]);
+    Value::Map(Arc::new(RwLock::new(entry)))
+}
+
+/// Paths matching `pattern`: `*` and `?` match within one name, `[a-z]` and
+/// `[!a-z]` match one character, and a `**` part matches any number of
+/// directories. Wildcards don't match names starting with `.` unless the
+/// pattern part does too.
+fn glob(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern = pattern.replace('\\', "/");
+    let mut parts: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+    let mut base = PathBuf::new();
+    if pattern.starts_with('/') {
+        base.push("/");
+    }
+    // Literal leading parts are where the search starts
+    while parts.len() > 1 && !has_wildcard(parts[0]) {
+        base.push(parts.remove(0));
+    }
+    if parts.is_empty() {
+        return Err("Path.Glob pattern is empty".to_string());
+    }
+    permissions::check_read(if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &base
+    })?;
+
+    let mut found = Vec::new();
+    expand(&base, &parts, &mut found);
+    Ok(found)
+}
+
+fn expand(dir: &Path, parts: &[&str], found: &mut Vec<PathBuf>) {
+    let Some((&part, rest)) = parts.split_first() else {
+        found.push(dir.to_path_buf());
+        return;
+    };
+    if part == "**" {
+        expand(dir, rest, found);
+        for (name, path) in names(dir) {
+            if name.starts_with('.') {
+                continue;
+            }
+            // Not through links, which could lead back up the tree
+            if fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir()) {
+                expand(&path, parts, found);
+            } else if rest.is_empty() {
+                found.push(path);
+            }
+        }
+    } else if !has_wildcard(part) {
+        let path = dir.join(part);
+        if (rest.is_empty() && path.exists()) || path.is_dir() {
+            expand(&path, rest, found);
+        }
+    } else {
+        let pattern: Vec<char> = part.chars().collect();
+        for (name, path) in names(dir) {
+            if name.starts_with('.') && !part.starts_with('.') {
+                continue;
+            }
+            let name: Vec<char> = name.chars().collect();
+            if matches(&pattern, &name) && (rest.is_empty() || path.is_dir()) {
+                expand(&path, rest, found);
+            }
+        }
+    }
+}
+
+/// The names in `dir` ("" is the current directory); none if it can't be read.
+fn names(dir: &Path) -> Vec<(String, PathBuf)> {
+    let read = if dir.as_os_str().is_empty() {
+        fs::read_dir(".")
+    } else {
+        fs::read_dir(dir)
+    };
+    read.into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = dir.join(&name);
+            (name, path)
+        })
+        .collect()
+}
+
+fn has_wildcard(part: &str) -> bool {
+    part.contains(['*', '?', '['])
+}
+
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        Some('[') => match (class_end(pattern), name.first()) {
+            (Some(end), Some(&c)) => {
+                in_class(&pattern[1..end], c) && matches(&pattern[end + 1..], &name[1..])
+            }
+            // An unclosed `[` is an ordinary character
+            (None, Some('[')) => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some(&c) => name.first() == Some(&c) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Index of the `]` closing the class that starts `pattern`.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let start = if pattern.get(1) == Some(&'!') { 3 } else { 2 };
+    (start..pattern.len()).find(|&i| pattern[i] == ']')
+}
+
+fn in_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut i = 0;
+    let mut found = false;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_and_walk() {
+        let matches = |pattern: &str, name: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let name: Vec<char> = name.chars().collect();
+            super::matches(&pattern, &name)
+        };
+        assert!(matches("*.sfex", "main.sfex"));
+        assert!(!matches("*.sfex", "main.sfexhtml"));
+        assert!(matches("test_?.sfex", "test_1.sfex"));
+        assert!(matches("[a-c]*", "beta") && !matches("[!a-c]*", "beta"));
+        assert!(matches("[]x]", "]"));
+
+        let root = std::env::temp_dir().join(format!("sfex-path-{}", std::process::id()));
+        fs::create_dir_all(root.join("src/lib")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        for file in [
+            "main.sfex",
+            "src/app.sfex",
+            "src/lib/util.sfex",
+            "src/notes.md",
+            ".git/x.sfex",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let pattern = format!("{}/**/*.sfex", root.display());
+        let mut found = glob(&pattern).unwrap();
+        found.sort();
+        let relative: Vec<_> = found
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(relative, ["main.sfex", "src/app.sfex", "src/lib/util.sfex"]);
+
+        let mut walk = children(&root, 1).unwrap();
+        let mut order = Vec::new();
+        while let Some((path, depth, is_dir)) = walk.pop() {
+            if is_dir {
+                walk.extend(children(&path, depth + 1).unwrap());
+            }
+            order.push(
+                path.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        assert_eq!(
+            order,
+            [
+                ".git",
+                ".git/x.sfex",
+                "main.sfex",
+                "src",
+                "src/app.sfex",
+                "src/lib",
+                "src/lib/util.sfex",
+                "src/notes.md"
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::BigDecimal;
+use indexmap::IndexMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Unlike System.Execute, no shell is involved: the program gets its arguments
+// exactly as given, so text a user typed can't run a second command.
+
+pub fn create_process_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Process.Run("git", ["status"], { Cwd: "repo" }) -> waits for it to finish
+    methods.insert(
+        "Run".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let (mut command, options) = build_command("Process.Run", &args)?;
+            let input = options.get("Stdin").map(Value::to_display_string);
+            command
+                .stdin(if input.is_some() {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+
+            let program = args[0].to_display_string();
+            let mut child = command
+                .spawn()
+                .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+            if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+                // Written from a thread so a child that fills its stdout first can't deadlock us
+                std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+            }
+            let output = child
+                .wait_with_output()
+                .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+
+            let mut result = IndexMap::new();
+            result.insert("ExitCode".to_string(), exit_code(output.status));
+            result.insert(
+                "Success".to_string(),
+                Value::Boolean(output.status.success()),
+            );
+            result.insert(
+                "Stdout".to_string(),
+                Value::String(String::from_utf8_lossy(&output.stdout).into_owned()),
+            );
+            result.insert(
+                "Stderr".to_string(),
+                Value::String(String::from_utf8_lossy(&output.stderr).into_owned()),
+            );
+            Ok(Value::Map(Arc::new(RwLock::new(result))))
+        }))),
+    );
+
+    // Process.Spawn("python3", ["-u", "worker.py"]) -> a handle to the running process
+    methods.insert(
+        "Spawn".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            let (mut command, _options) = build_command("Process.Spawn", &args)?;
+            command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+
+            let child = command
+                .spawn()
+                .map_err(|e| format!("Failed to start {}: {}", args[0].to_display_string(), e))?;
+            Ok(create_child_object(child))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// The command for `program, args?, options?`, and the options Map.
+fn build_command(name: &str, args: &[Value]) -> Result<(Command, IndexMap<String, Value>), String> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(format!(
+            "{} requires 1-3 arguments (program, optional arguments, optional options)",
+            name
+        ));
+    }
+
+    let program = args[0].to_display_string();
+    permissions::check_run(&program)?;
+    let mut command = Command::new(program);
+    match args.get(1) {
+        Some(Value::List(list)) => {
+            command.args(list.read_recover().iter().map(Value::to_display_string));
+        }
+        Some(Value::Option(none)) if none.is_none() => {}
+        Some(other) => {
+            return Err(format!(
+                "{} arguments must be a List, got {}",
+                name,
+                other.type_name()
+            ));
+        }
+        None => {}
+    }
+
+    let options = match args.get(2) {
+        Some(Value::Map(map)) => map.read_recover().clone(),
+        Some(other) => {
+            return Err(format!(
+                "{} options must be a Map, got {}",
+                name,
+                other.type_name()
+            ));
+        }
+        None => IndexMap::new(),
+    };
+    // Cleared before Env is applied, whatever order the Map gives
+    if options.get("ClearEnv").is_some_and(Value::is_truthy) {
+        command.env_clear();
+    }
+    for (key, value) in &options {
+        match key.as_str() {
+            "Cwd" => {
+                command.current_dir(value.to_display_string());
+            }
+            "Env" => match value {
+                Value::Map(env) => {
+                    for (var, value) in env.read_recover().iter() {
+                        command.env(var, value.to_display_string());
+                    }
+                }
+                _ => return Err(format!("{} Env must be a Map", name)),
+            },
+            "ClearEnv" => {}
+            "Stdin" if name == "Process.Run" => {}
+            _ => return Err(format!("{} has no option {}", name, key)),
+        }
+    }
+
+    Ok((command, options))
+}
+
+fn exit_code(status: ExitStatus) -> Value {
+    // Killed by a signal: no code, like System.Execute
+    Value::Number(BigDecimal::from(status.code().unwrap_or(-1) as i64))
+}
+
+fn create_child_object(mut child: Child) -> Value {
+    let mut methods = IndexMap::new();
+    methods.insert(
+        "Id".to_string(),
+        Value::Number(BigDecimal::from(child.id())),
+    );
+
+    let stdin = Arc::new(Mutex::new(child.stdin.take()));
+    methods.insert("Stdin".to_string(), create_stdin_object(stdin.clone()));
+    if let Some(stdout) = child.stdout.take() {
+        methods.insert("Stdout".to_string(), lines_stream(stdout));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        methods.insert("Stderr".to_string(), lines_stream(stderr));
+    }
+
+    let child = Arc::new(Mutex::new(child));
+
+    // Child.Wait() -> exit code, once it has finished
+    let child_wait = child.clone();
+    methods.insert(
+        "Wait".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            // Without this, a child reading until end of input would never finish
+            stdin.lock_recover().take();
+            let status = child_wait
+                .lock_recover()
+                .wait()
+                .map_err(|e| format!("Failed to wait for process: {}", e))?;
+            Ok(exit_code(status))
+        }))),
+    );
+
+    // Child.Running() -> True until it has finished
+    let child_running = child.clone();
+    methods.insert(
+        "Running".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let finished = child_running
+                .lock_recover()
+                .try_wait()
+                .map_err(|e| format!("Failed to check process: {}", e))?;
+            Ok(Value::Boolean(finished.is_none()))
+        }))),
+    );
+
+    // Child.Kill()
+    let child_kill = child.clone();
+    methods.insert(
+        "Kill".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let mut child = child_kill.lock_recover();
+            // Killing a process that already finished isn't an error
+            if child.try_wait().ok().flatten().is_none() {
+                child
+                    .kill()
+                    .map_err(|e| format!("Failed to kill process: {}", e))?;
+                let _ = child.wait();
+            }
+            Ok(Value::Option(Box::new(None)))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn create_stdin_object(stdin: Arc<Mutex<Option<ChildStdin>>>) -> Value {
+    let mut methods = IndexMap::new();
+
+    // Stdin.Write("data") -> number of bytes written
+    let stdin_write = stdin.clone();
+    methods.insert(
+        "Write".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Stdin.Write requires 1 argument (data)".to_string());
+            }
+            write_stdin(&stdin_write, args[0].to_display_string().as_bytes())
+        }))),
+    );
+
+    // Stdin.WriteLine("data") -> writes data followed by a newline
+    let stdin_write_line = stdin.clone();
+    methods.insert(
+        "WriteLine".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Stdin.WriteLine requires 1 argument (data)".to_string());
+            }
+            let data = format!("{}\n", args[0].to_display_string());
+            write_stdin(&stdin_write_line, data.as_bytes())
+        }))),
+    );
+
+    // Stdin.Close() -> the process sees the end of its input
+    let stdin_close = stdin.clone();
+    methods.insert(
+        "Close".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            stdin_close.lock_recover().take();
+            Ok(Value::Option(Box::new(None)))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn write_stdin(stdin: &Mutex<Option<ChildStdin>>, data: &[u8]) -> Result<Value, String> {
+    let mut guard = stdin.lock_recover();
+    let stdin = guard.as_mut().ok_or("Process stdin is closed")?;
+    stdin
+        .write_all(data)
+        .and_then(|_| stdin.flush())
+        .map_err(|e| format!("Failed to write to process: {}", e))?;
+    Ok(Value::Number(BigDecimal::from(data.len() as u64)))
+}
+
+/// A Stream of the lines `pipe` produces, without their newlines, ending
+/// when the process closes it.
+fn lines_stream(pipe: impl Read + Send + Sync + 'static) -> Value {
+    let reader = Mutex::new(BufReader::new(pipe));
+    let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+        let mut line = String::new();
+        let read = reader
+            .lock_recover()
+            .read_line(&mut line)
+            .map_err(|e| format!("Failed to read from process: {}", e))?;
+        if read == 0 {
+            return Ok(Value::Option(Box::new(None)));
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Value::Option(Box::new(Some(Value::String(line)))))
+    })));
+    crate::stdlib::stream::create_stream_object(vec![], Some(generator))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn call(target: &Value, name: &str, args: Vec<Value>) -> Value {
+        let Value::Map(map) = target else {
+            panic!("not a Map")
+        };
+        let function = map.read_recover().get(name).cloned().unwrap();
+        match function {
+            Value::NativeFunction(f) => f(args).unwrap(),
+            other => other,
+        }
+    }
+
+    fn list(items: &[&str]) -> Value {
+        Value::List(Arc::new(RwLock::new(
+            items.iter().map(|s| Value::String(s.to_string())).collect(),
+        )))
+    }
+
+    #[test]
+    fn test_run_and_spawn() {
+        let process = create_process_module();
+        let options = Value::Map(Arc::new(RwLock::new(IndexMap::from([
+            ("Cwd".to_string(), Value::String("/".to_string())),
+            ("Stdin".to_string(), Value::String("in".to_string())),
+            (
+                "Env".to_string(),
+                Value::Map(Arc::new(RwLock::new(IndexMap::from([(
+                    "GREETING".to_string(),
+                    Value::String("hi".to_string()),
+                )])))),
+            ),
+        ]))));
+        let script = "echo $GREETING $(pwd) $(cat); echo oops >&2; exit 3";
+        let result = call(
+            &process,
+            "Run",
+            vec![
+                Value::String("sh".to_string()),
+                list(&["-c", script]),
+                options,
+            ],
+        );
+        assert_eq!(
+            call(&result, "Stdout", vec![]).to_display_string(),
+            "hi / in\n"
+        );
+        assert_eq!(
+            call(&result, "Stderr", vec![]).to_display_string(),
+            "oops\n"
+        );
+        assert_eq!(call(&result, "ExitCode", vec![]).to_display_string(), "3");
+
+        let child = call(&process, "Spawn", vec![Value::String("cat".to_string())]);
+        let stdin = call(&child, "Stdin", vec![]);
+        call(&stdin, "WriteLine", vec![Value::String("one".to_string())]);
+        call(&stdin, "Write", vec![Value::String("two\n".to_string())]);
+        let stdout = call(&child, "Stdout", vec![]);
+        let first = call(&stdout, "Next", vec![]);
+        assert_eq!(first.to_display_string(), "Some(one)");
+        assert_eq!(call(&child, "Wait", vec![]).to_display_string(), "0");
+        assert_eq!(
+            call(&stdout, "Next", vec![]).to_display_string(),
+            "Some(two)"
+        );
+        assert_eq!(call(&stdout, "Next", vec![]).to_display_string(), "None");
+
+        let sleeper = call(
+            &process,
+            "Spawn",
+            vec![Value::String("sleep".to_string()), list(&["10"])],
+        );
+        call(&sleeper, "Kill", vec![]);
+        assert!(matches!(
+            call(&sleeper, "Running", vec![]),
+            Value::Boolean(false)
+        ));
+        assert_eq!(call(&sleeper, "Wait", vec![]).to_display_string(), "-1");
+    }
+}
@@ -0,0 +1,356 @@
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{Rng, RngCore, SeedableRng};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// Random numbers come from the system's generator until Random.Seed(n) is
+// called. From then on they come from one generator seeded with n, which
+// Math.Random uses too, so a run gives the same numbers every time as long
+// as the calls happen in the same order.
+
+fn seeded() -> &'static Mutex<Option<StdRng>> {
+    static SEEDED: OnceLock<Mutex<Option<StdRng>>> = OnceLock::new();
+    SEEDED.get_or_init(Default::default)
+}
+
+/// Where one Random object takes its numbers from.
+enum Source {
+    /// Random.Seed's generator, or the system's when there is none
+    Shared,
+    /// A generator of its own, from Random.New(seed)
+    Own(Box<Mutex<StdRng>>),
+}
+
+impl Source {
+    fn with<T>(&self, draw: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match self {
+            Source::Shared => match seeded().lock_recover().as_mut() {
+                Some(rng) => draw(rng),
+                None => draw(&mut rand::rng()),
+            },
+            Source::Own(rng) => draw(&mut *rng.lock_recover()),
+        }
+    }
+}
+
+/// A number in [0, 1), for Math.Random.
+pub fn unit() -> f64 {
+    Source::Shared.with(|rng| rng.random_range(0.0..1.0))
+}
+
+fn seed_value(value: &Value, caller: &str) -> Result<u64, String> {
+    match value {
+        // Negative seeds are as good as any other
+        Value::Integer(i) => i
+            .to_i64()
+            .map(|n| n as u64)
+            .or_else(|| i.to_u64())
+            .ok_or_else(|| format!("{}: the seed is too large", caller)),
+        Value::Number(n) if n.is_integer() => n
+            .to_i64()
+            .map(|n| n as u64)
+            .ok_or_else(|| format!("{}: the seed is too large", caller)),
+        other => Err(format!(
+            "{} needs a whole number as the seed, not {}",
+            caller,
+            other.type_name()
+        )),
+    }
+}
+
+fn integer(value: &Value, what: &str) -> Result<i64, String> {
+    match value {
+        Value::Integer(i) => i.to_i64(),
+        Value::Number(n) if n.is_integer() => n.to_i64(),
+        Value::FastNumber(f) if f.fract() == 0.0 => Some(*f as i64),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{} must be a whole number", what))
+}
+
+fn float(value: &Value, what: &str) -> Result<f64, String> {
+    match value {
+        Value::Integer(i) => i.to_f64(),
+        Value::Number(n) => n.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+    .filter(|f| f.is_finite())
+    .ok_or_else(|| format!("{} must be a number", what))
+}
+
+fn list_items(value: &Value, caller: &str) -> Result<Vec<Value>, String> {
+    match value {
+        Value::List(list) => Ok(list.read_recover().clone()),
+        other => Err(format!(
+            "{} needs a List, not {}",
+            caller,
+            other.type_name()
+        )),
+    }
+}
+
+fn new_list(values: Vec<Value>) -> Value {
+    Value::List(Arc::new(std::sync::RwLock::new(values)))
+}
+
+fn native(function: impl Fn(Vec<Value>) -> Result<Value, String> + Send + Sync + 'static) -> Value {
+    Value::NativeFunction(Arc::new(Box::new(function)))
+}
+
+/// Int, Float, Choice, Shuffle, Sample and Gaussian, drawing from `source`.
+fn insert_draws(methods: &mut IndexMap<String, Value>, name: &str, source: Arc<Source>) {
+    // Random.Int(1, 6) - a whole number from min to max, both included
+    let source_int = source.clone();
+    let caller = format!("{}.Int", name);
+    methods.insert(
+        "Int".to_string(),
+        native(move |args| {
+            if args.len() != 2 {
+                return Err(format!("{} requires 2 arguments (min, max)", caller));
+            }
+            let min = integer(&args[0], &format!("{}'s min", caller))?;
+            let max = integer(&args[1], &format!("{}'s max", caller))?;
+            if min > max {
+                return Err(format!("{}: min {} is above max {}", caller, min, max));
+            }
+            let n = source_int.with(|rng| rng.random_range(min..=max));
+            Ok(Value::Integer(n.into()))
+        }),
+    );
+
+    // Random.Float() in [0, 1), or Random.Float(min, max) in [min, max)
+    let source_float = source.clone();
+    let caller = format!("{}.Float", name);
+    methods.insert(
+        "Float".to_string(),
+        native(move |args| {
+            let (min, max) = match args.as_slice() {
+                [] => (0.0, 1.0),
+                [min, max] => (
+                    float(min, &format!("{}'s min", caller))?,
+                    float(max, &format!("{}'s max", caller))?,
+                ),
+                _ => {
+                    return Err(format!("{} requires 0 or 2 arguments (min, max)", caller));
+                }
+            };
+            if min >= max {
+                return Err(format!("{}: min {} must be below max {}", caller, min, max));
+            }
+            Ok(Value::FastNumber(
+                source_float.with(|rng| rng.random_range(min..max)),
+            ))
+        }),
+    );
+
+    // Random.Choice(list) - one item
+    let source_choice = source.clone();
+    let caller = format!("{}.Choice", name);
+    methods.insert(
+        "Choice".to_string(),
+        native(move |args| {
+            if args.len() != 1 {
+                return Err(format!("{} requires 1 argument (list)", caller));
+            }
+            let items = list_items(&args[0], &caller)?;
+            source_choice
+                .with(|rng| items.choose(rng).cloned())
+                .ok_or_else(|| format!("{} can't choose from an empty List", caller))
+        }),
+    );
+
+    // Random.Shuffle(list) - a copy in random order
+    let source_shuffle = source.clone();
+    let caller = format!("{}.Shuffle", name);
+    methods.insert(
+        "Shuffle".to_string(),
+        native(move |args| {
+            if args.len() != 1 {
+                return Err(format!("{} requires 1 argument (list)", caller));
+            }
+            let mut items = list_items(&args[0], &caller)?;
+            source_shuffle.with(|rng| items.shuffle(rng));
+            Ok(new_list(items))
+        }),
+    );
+
+    // Random.Sample(list, k) - k different items, in random order
+    let source_sample = source.clone();
+    let caller = format!("{}.Sample", name);
+    methods.insert(
+        "Sample".to_string(),
+        native(move |args| {
+            if args.len() != 2 {
+                return Err(format!("{} requires 2 arguments (list, count)", caller));
+            }
+            let items = list_items(&args[0], &caller)?;
+            let count = integer(&args[1], &format!("{}'s count", caller))?;
+            if count < 0 || count as usize > items.len() {
+                return Err(format!(
+                    "{} can't take {} items from a List of {}",
+                    caller,
+                    count,
+                    items.len()
+                ));
+            }
+            let picked = source_sample.with(|rng| {
+                items
+                    .choose_multiple(rng, count as usize)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            });
+            Ok(new_list(picked))
+        }),
+    );
+
+    // Random.Gaussian(mean?, deviation?) - normally distributed, 0 and 1
+    // by default
+    let caller = format!("{}.Gaussian", name);
+    methods.insert(
+        "Gaussian".to_string(),
+        native(move |args| {
+            let (mean, deviation) = match args.as_slice() {
+                [] => (0.0, 1.0),
+                [mean] => (float(mean, &format!("{}'s mean", caller))?, 1.0),
+                [mean, deviation] => (
+                    float(mean, &format!("{}'s mean", caller))?,
+                    float(deviation, &format!("{}'s deviation", caller))?,
+                ),
+                _ => {
+                    return Err(format!(
+                        "{} requires 0-2 arguments (mean, deviation)",
+                        caller
+                    ));
+                }
+            };
+            if deviation < 0.0 {
+                return Err(format!("{}: the deviation can't be negative", caller));
+            }
+            // Box-Muller; 1 - u keeps the logarithm away from 0
+            let (u, v): (f64, f64) = source.with(|rng| (rng.random(), rng.random()));
+            let normal = (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+            Ok(Value::FastNumber(mean + deviation * normal))
+        }),
+    );
+}
+
+pub fn create_random_module() -> Value {
+    let mut methods = IndexMap::new();
+    insert_draws(&mut methods, "Random", Arc::new(Source::Shared));
+
+    // Random.Seed(42) - the same numbers on every run from here on;
+    // Random.Seed() goes back to the system's generator
+    methods.insert(
+        "Seed".to_string(),
+        native(|args| {
+            let rng = match args.as_slice() {
+                [] => None,
+                [seed] => Some(StdRng::seed_from_u64(seed_value(seed, "Random.Seed")?)),
+                _ => return Err("Random.Seed requires 0-1 arguments (seed)".to_string()),
+            };
+            *seeded().lock_recover() = rng;
+            Ok(Value::Boolean(true))
+        }),
+    );
+
+    // Random.New(seed?) - a generator of its own, which other code's draws
+    // don't disturb
+    methods.insert(
+        "New".to_string(),
+        native(|args| {
+            let rng = match args.as_slice() {
+                [] => StdRng::from_os_rng(),
+                [seed] => StdRng::seed_from_u64(seed_value(seed, "Random.New")?),
+                _ => return Err("Random.New requires 0-1 arguments (seed)".to_string()),
+            };
+            let mut generator = IndexMap::new();
+            insert_draws(
+                &mut generator,
+                "Generator",
+                Arc::new(Source::Own(Box::new(Mutex::new(rng)))),
+            );
+            Ok(Value::Map(Arc::new(std::sync::RwLock::new(generator))))
+        }),
+    );
+
+    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(object: &Value, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let Value::Map(map) = object else {
+            panic!("not an object");
+        };
+        let function = map.read_recover().get(name).cloned();
+        match function {
+            Some(Value::NativeFunction(f)) => f(args),
+            _ => panic!("no member {}", name),
+        }
+    }
+
+    fn int(n: i64) -> Value {
+        Value::Integer(n.into())
+    }
+
+    fn draws(generator: &Value) -> String {
+        let items = new_list((1..=10).map(int).collect());
+        [
+            call(generator, "Int", vec![int(1), int(1000)]),
+            call(generator, "Float", vec![]),
+            call(generator, "Choice", vec![items.clone()]),
+            call(generator, "Shuffle", vec![items.clone()]),
+            call(generator, "Sample", vec![items, int(3)]),
+            call(generator, "Gaussian", vec![int(10), int(2)]),
+        ]
+        .map(|value| value.unwrap().to_display_string())
+        .join(" ")
+    }
+
+    #[test]
+    fn test_seeded_generators_repeat() {
+        let module = create_random_module();
+        let first = call(&module, "New", vec![int(7)]).unwrap();
+        let second = call(&module, "New", vec![int(7)]).unwrap();
+        assert_eq!(draws(&first), draws(&second));
+        let other = call(&module, "New", vec![int(8)]).unwrap();
+        assert_ne!(draws(&first), draws(&other));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let generator = call(&create_random_module(), "New", vec![int(1)]).unwrap();
+        for _ in 0..200 {
+            let Value::Integer(n) = call(&generator, "Int", vec![int(-2), int(2)]).unwrap() else {
+                panic!("Int gives an Integer");
+            };
+            assert!((-2..=2).contains(&n.to_i64().unwrap()));
+        }
+        let Value::List(sample) = call(
+            &generator,
+            "Sample",
+            vec![new_list((1..=5).map(int).collect()), int(5)],
+        )
+        .unwrap() else {
+            panic!("Sample gives a List");
+        };
+        let mut picked: Vec<i64> = sample
+            .read_recover()
+            .iter()
+            .map(|value| value.to_display_string().parse().unwrap())
+            .collect();
+        picked.sort();
+        assert_eq!(picked, [1, 2, 3, 4, 5]);
+
+        assert!(call(&generator, "Int", vec![int(3), int(1)]).is_err());
+        assert!(call(&generator, "Choice", vec![new_list(vec![])]).is_err());
+        assert!(call(&generator, "Sample", vec![new_list(vec![int(1)]), int(2)]).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::compiler::ast::{Concept, Expression};
+use crate::compiler::lexer::Lexer;
+use crate::compiler::parser::Parser;
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::value::Value;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+pub fn create_reflect_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Reflect.DefineConcept("User", ["Name", "Email"], "To Greet: ...") adds
+    // a concept to the running program; the interpreter answers it, since
+    // the concepts are its own
+    methods.insert(
+        "DefineConcept".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|_args| {
+            Err(
+                "Reflect.DefineConcept must be called directly, as Reflect.DefineConcept(name, fields, methods)"
+                    .to_string(),
+            )
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// The concept `Reflect.DefineConcept(name, fields, source)` defines, and the
+/// concepts its methods query with `Instances of`. `fields` is a List of
+/// names or a Map of names to defaults; `source` is what would follow the
+/// fields in a `Concept:` block, written without its indentation.
+pub fn build_concept(
+    name: &str,
+    fields: &Value,
+    source: &str,
+) -> Result<(Concept, Vec<String>), String> {
+    if !is_identifier(name) {
+        return Err(format!(
+            "Reflect.DefineConcept: '{}' is not a concept name",
+            name
+        ));
+    }
+    let fields = field_list(fields)?;
+
+    let (mut concept, tracked) = if source.trim().is_empty() {
+        let concept = Concept {
+            name: name.to_string(),
+            parent: None,
+            fields: Vec::new(),
+            defaults: HashMap::new(),
+            requirements: Vec::new(),
+            methods: Vec::new(),
+            when_observers: HashMap::new(),
+            observed_on_create: Vec::new(),
+            when_created: Vec::new(),
+            when_destroyed: Vec::new(),
+            file: None,
+            doc: None,
+            field_docs: Default::default(),
+        };
+        (concept, Vec::new())
+    } else {
+        parse_body(name, source)?
+    };
+
+    // Fields the source declares too come after the given ones
+    let declared = std::mem::take(&mut concept.fields);
+    for (field, default) in fields {
+        if let Some(default) = default {
+            concept.defaults.entry(field.clone()).or_insert(default);
+        }
+        concept.fields.push(field);
+    }
+    for field in declared {
+        if !concept.fields.contains(&field) {
+            concept.fields.push(field);
+        }
+    }
+    Ok((concept, tracked))
+}
+
+fn parse_body(name: &str, source: &str) -> Result<(Concept, Vec<String>), String> {
+    let mut wrapped = format!("Concept: {}\n", name);
+    for line in source.lines() {
+        wrapped.push_str("    ");
+        wrapped.push_str(line);
+        wrapped.push('\n');
+    }
+    let mut program = Parser::from_lexer(Lexer::new(&wrapped))
+        .parse()
+        .map_err(|e| {
+            // Positions in the source as given, without the added header
+            // and indentation
+            let (line, column) = e.location();
+            format!(
+                "Reflect.DefineConcept: line {}, column {} of the methods of {}: {}",
+                line.saturating_sub(1).max(1),
+                column.saturating_sub(4).max(1),
+                name,
+                e.reason()
+            )
+        })?;
+    let concept = program
+        .concepts
+        .pop()
+        .ok_or_else(|| format!("Reflect.DefineConcept: no concept {} in the source", name))?;
+    Ok((concept, program.tracked_concepts))
+}
+
+/// Names, with defaults for those given a value in a Map
+fn field_list(fields: &Value) -> Result<Vec<(String, Option<Expression>)>, String> {
+    let mut names = Vec::new();
+    match fields {
+        Value::List(list) => {
+            for field in list.read_recover().iter() {
+                names.push((field.to_display_string(), None));
+            }
+        }
+        Value::Map(map) => {
+            for (field, value) in map.read_recover().iter() {
+                let default = literal(value).ok_or_else(|| {
+                    format!(
+                        "Reflect.DefineConcept: the default for {} must be a number, text, boolean, List or Map, not {}",
+                        field,
+                        value.type_name()
+                    )
+                })?;
+                names.push((field.clone(), Some(default)));
+            }
+        }
+        other => {
+            return Err(format!(
+                "Reflect.DefineConcept expects a List of field names or a Map of defaults, got {}",
+                other.type_name()
+            ));
+        }
+    }
+    if let Some((bad, _)) = names.iter().find(|(name, _)| !is_identifier(name)) {
+        return Err(format!(
+            "Reflect.DefineConcept: '{}' is not a field name",
+            bad
+        ));
+    }
+    Ok(names)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// A default as the expression that evaluates to it on each Create, so
+/// instances don't share a List or Map
+fn literal(value: &Value) -> Option<Expression> {
+    match value {
+        Value::Number(n) => Some(Expression::Number(n.to_string())),
+        Value::Integer(i) => Some(Expression::Integer(i.to_string())),
+        Value::FastNumber(f) => Some(Expression::Number(f.to_string())),
+        Value::String(text) => Some(Expression::String(text.clone())),
+        Value::Boolean(b) => Some(Expression::Boolean(*b)),
+        Value::List(list) => list
+            .read_recover()
+            .iter()
+            .map(literal)
+            .collect::<Option<Vec<_>>>()
+            .map(Expression::List),
+        Value::Map(map) => map
+            .read_recover()
+            .iter()
+            .map(|(key, value)| Some((key.clone(), literal(value)?)))
+            .collect::<Option<Vec<_>>>()
+            .map(Expression::Map),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_concept() {
+        let fields = Value::List(Arc::new(RwLock::new(vec![Value::String(
+            "Name".to_string(),
+        )])));
+        let (concept, _) = build_concept(
+            "User",
+            &fields,
+            "Email is \"\"\n\nTo Greet:\n    Return \"Hi \" + This.Name",
+        )
+        .unwrap();
+        assert_eq!(concept.fields, ["Name", "Email"]);
+        assert_eq!(concept.methods.len(), 1);
+        assert!(concept.defaults.contains_key("Email"));
+
+        let error = build_concept("User", &fields, "To Greet:\n    Return +").unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(build_concept("Not a name", &fields, "").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::runtime::lock::MutexExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use serialport::SerialPort;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+const DEFAULT_BAUD_RATE: u32 = 9600;
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+pub fn create_serial_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Serial.Ports() -> List of available port names
+    methods.insert(
+        "Ports".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if !args.is_empty() {
+                return Err("Serial.Ports requires no arguments".to_string());
+            }
+
+            let ports = serialport::available_ports()
+                .map_err(|e| format!("Failed to list serial ports: {}", e))?;
+            let names = ports
+                .into_iter()
+                .map(|port| Value::String(port.port_name))
+                .collect();
+            Ok(Value::List(Arc::new(RwLock::new(names))))
+        }))),
+    );
+
+    // Serial.Open("/dev/ttyUSB0", 115200, 500)
+    methods.insert(
+        "Open".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 3 {
+                return Err(
+                    "Serial.Open requires 1-3 arguments (port, optional baud_rate, optional timeout_ms)"
+                        .to_string(),
+                );
+            }
+
+            let name = args[0].to_display_string();
+            let baud_rate = match args.get(1) {
+                Some(value) => number_arg(value, "baud rate")? as u32,
+                None => DEFAULT_BAUD_RATE,
+            };
+            let timeout_ms = match args.get(2) {
+                Some(value) => number_arg(value, "timeout")?,
+                None => DEFAULT_TIMEOUT_MS,
+            };
+
+            permissions::check_read(&name)?;
+            permissions::check_write(&name)?;
+            let port = serialport::new(&name, baud_rate)
+                .timeout(Duration::from_millis(timeout_ms))
+                .open()
+                .map_err(|e| format!("Failed to open serial port {}: {}", name, e))?;
+
+            Ok(create_port_object(name, baud_rate, port))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn number_arg(value: &Value, what: &str) -> Result<u64, String> {
+    match value {
+        Value::Number(n) => n
+            .to_u64()
+            .ok_or_else(|| format!("Serial {} must be a positive integer", what)),
+        Value::Integer(i) => i
+            .to_u64()
+            .ok_or_else(|| format!("Serial {} must be a positive integer", what)),
+        Value::FastNumber(f) if *f >= 0.0 => Ok(*f as u64),
+        _ => Err(format!("Serial {} must be a number", what)),
+    }
+}
+
+fn create_port_object(name: String, baud_rate: u32, port: Box<dyn SerialPort>) -> Value {
+    let port_arc = Arc::new(Mutex::new(Some(port)));
+    let mut methods = IndexMap::new();
+
+    methods.insert("Name".to_string(), Value::String(name));
+    methods.insert(
+        "BaudRate".to_string(),
+        Value::Number(bigdecimal::BigDecimal::from(baud_rate)),
+    );
+
+    // Port.Write("data") -> number of bytes written
+    let port_write = port_arc.clone();
+    methods.insert(
+        "Write".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Port.Write requires 1 argument (data)".to_string());
+            }
+            let data = args[0].to_display_string();
+            write_port(&port_write, data.as_bytes())
+        }))),
+    );
+
+    // Port.WriteLine("data") -> writes data followed by a newline
+    let port_write_line = port_arc.clone();
+    methods.insert(
+        "WriteLine".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Port.WriteLine requires 1 argument (data)".to_string());
+            }
+            let data = format!("{}\n", args[0].to_display_string());
+            write_port(&port_write_line, data.as_bytes())
+        }))),
+    );
+
+    // Port.Read(buffer_size) -> whatever arrived before the timeout ("" if nothing)
+    let port_read = port_arc.clone();
+    methods.insert(
+        "Read".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let buffer_size = match args.first() {
+                Some(value) => number_arg(value, "buffer size")? as usize,
+                None => 1024,
+            };
+
+            let mut guard = port_read.lock_recover();
+            let port = guard.as_mut().ok_or("Serial port is closed")?;
+            let mut buffer = vec![0u8; buffer_size.max(1)];
+            match port.read(&mut buffer) {
+                Ok(n) => {
+                    buffer.truncate(n);
+                    Ok(Value::String(String::from_utf8_lossy(&buffer).into_owned()))
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => Ok(Value::String(String::new())),
+                Err(e) => Err(format!("Failed to read serial port: {}", e)),
+            }
+        }))),
+    );
+
+    // Port.ReadLine() -> next line without the trailing newline, None on timeout
+    let port_read_line = port_arc.clone();
+    methods.insert(
+        "ReadLine".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if !args.is_empty() {
+                return Err("Port.ReadLine requires no arguments".to_string());
+            }
+            let line = read_line(&port_read_line)?;
+            Ok(Value::Option(Box::new(line.map(Value::String))))
+        }))),
+    );
+
+    // Port.ReadStream() -> Stream of incoming lines, waiting through timeouts
+    let port_stream = port_arc.clone();
+    methods.insert(
+        "ReadStream".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if !args.is_empty() {
+                return Err("Port.ReadStream requires no arguments".to_string());
+            }
+
+            let port = port_stream.clone();
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                loop {
+                    if port.lock_recover().is_none() {
+                        return Ok(Value::Option(Box::new(None)));
+                    }
+                    if let Some(line) = read_line(&port)? {
+                        return Ok(Value::Option(Box::new(Some(Value::String(line)))));
+                    }
+                }
+            })));
+
+            Ok(crate::stdlib::stream::create_stream_object(
+                vec![],
+                Some(generator),
+            ))
+        }))),
+    );
+
+    // Port.SetBaudRate(115200)
+    let port_baud = port_arc.clone();
+    methods.insert(
+        "SetBaudRate".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Port.SetBaudRate requires 1 argument (baud_rate)".to_string());
+            }
+            let baud_rate = number_arg(&args[0], "baud rate")? as u32;
+            let mut guard = port_baud.lock_recover();
+            let port = guard.as_mut().ok_or("Serial port is closed")?;
+            port.set_baud_rate(baud_rate)
+                .map_err(|e| format!("Failed to set baud rate: {}", e))?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Port.Close()
+    let port_close = port_arc.clone();
+    methods.insert(
+        "Close".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            port_close.lock_recover().take();
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+fn write_port(port: &Mutex<Option<Box<dyn SerialPort>>>, data: &[u8]) -> Result<Value, String> {
+    let mut guard = port.lock_recover();
+    let port = guard.as_mut().ok_or("Serial port is closed")?;
+    port.write_all(data)
+        .and_then(|_| port.flush())
+        .map_err(|e| format!("Failed to write serial port: {}", e))?;
+    Ok(Value::Number(bigdecimal::BigDecimal::from(
+        data.len() as u64
+    )))
+}
+
+// Reads byte-by-byte so nothing past the newline is consumed from the port.
+fn read_line(port: &Mutex<Option<Box<dyn SerialPort>>>) -> Result<Option<String>, String> {
+    let mut guard = port.lock_recover();
+    let port = guard.as_mut().ok_or("Serial port is closed")?;
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        match port.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                if line.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+            Err(e) => return Err(format!("Failed to read serial port: {}", e)),
+        }
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
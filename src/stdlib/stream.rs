@@ -1,6 +1,7 @@
+use crate::runtime::lock::RwLockExt;
 use crate::runtime::value::Value;
 use bigdecimal::BigDecimal;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::sync::Arc;
 
 struct StreamState {
@@ -11,7 +12,7 @@ struct StreamState {
 }
 
 pub fn create_stream_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Create".to_string(),
@@ -29,7 +30,7 @@ pub fn create_stream_module() -> Value {
 
             match &args[0] {
                 Value::List(list) => {
-                    let items = list.read().expect("lock poisoned").clone();
+                    let items = list.read_recover().clone();
                     Ok(create_stream_object(items, None))
                 }
                 _ => Err("Argument must be a List".to_string()),
@@ -47,6 +48,7 @@ pub fn create_stream_module() -> Value {
             use bigdecimal::ToPrimitive;
             let start = match &args[0] {
                 Value::Number(n) => n.to_i64().ok_or("Start must be an integer")?,
+                Value::Integer(i) => i.to_i64().ok_or("Start must be an integer")?,
                 Value::FastNumber(f) => *f as i64,
                 _ => {
                     return Err("Start must be a number".to_string());
@@ -55,6 +57,7 @@ pub fn create_stream_module() -> Value {
 
             let end = match &args[1] {
                 Value::Number(n) => n.to_i64().ok_or("End must be an integer")?,
+                Value::Integer(i) => i.to_i64().ok_or("End must be an integer")?,
                 Value::FastNumber(f) => *f as i64,
                 _ => {
                     return Err("End must be a number".to_string());
@@ -65,7 +68,7 @@ pub fn create_stream_module() -> Value {
             let current_clone = current.clone();
 
             let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
-                let mut curr = current_clone.write().expect("lock poisoned");
+                let mut curr = current_clone.write_recover();
                 if *curr > end {
                     Ok(Value::Option(Box::new(None)))
                 } else {
@@ -92,13 +95,13 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
         generator,
     }));
 
-    let mut stream_map = HashMap::new();
+    let mut stream_map = IndexMap::new();
 
     let state_next = state.clone();
     stream_map.insert(
         "Next".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let mut s = state_next.write().expect("lock poisoned");
+            let mut s = state_next.write_recover();
 
             if s.index < s.items.len() {
                 let item = s.items[s.index].clone();
@@ -141,7 +144,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     stream_map.insert(
         "HasMore".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let s = state_has.read().expect("lock poisoned");
+            let s = state_has.read_recover();
             let has_buffered = s.index < s.items.len();
             let has_generator = !s.exhausted && s.generator.is_some();
             Ok(Value::Boolean(has_buffered || has_generator))
@@ -155,7 +158,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
             let mut result = Vec::new();
 
             let next_fn = {
-                let s = state_list.read().expect("lock poisoned");
+                let s = state_list.read_recover();
                 if let Some(generator_fn) = &s.generator {
                     Some(generator_fn.clone())
                 } else {
@@ -165,7 +168,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
 
             loop {
                 let buffered_item = {
-                    let mut s = state_list.write().expect("lock poisoned");
+                    let mut s = state_list.write_recover();
                     if s.index < s.items.len() {
                         let item = s.items[s.index].clone();
                         s.index += 1;
@@ -181,7 +184,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
                 }
 
                 if let Some(ref generator_fn) = next_fn {
-                    let mut s = state_list.write().expect("lock poisoned");
+                    let mut s = state_list.write_recover();
                     if s.exhausted {
                         break;
                     }
@@ -221,7 +224,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     stream_map.insert(
         "Close".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let mut s = state_close.write().expect("lock poisoned");
+            let mut s = state_close.write_recover();
             s.exhausted = true;
             s.items.clear();
             s.generator = None;
@@ -239,7 +242,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
 
             match &args[0] {
                 Value::NativeFunction(_) => {
-                    let mut s = state_gen.write().expect("lock poisoned");
+                    let mut s = state_gen.write_recover();
                     s.generator = Some(args[0].clone());
                     s.exhausted = false;
                     Ok(Value::Boolean(true))
@@ -253,7 +256,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     stream_map.insert(
         "Reset".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let mut s = state_reset.write().expect("lock poisoned");
+            let mut s = state_reset.write_recover();
             s.index = 0;
             s.exhausted = false;
             Ok(Value::Boolean(true))
@@ -264,7 +267,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     let stream_value = Value::Map(stream_rc.clone());
 
     let stream_for_map = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "Map".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
             if args.len() != 1 {
@@ -276,7 +279,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     );
 
     let stream_for_filter = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "Filter".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
             if args.len() != 1 {
@@ -288,7 +291,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     );
 
     let stream_for_take = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "Take".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
             if args.len() != 1 {
@@ -297,6 +300,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
             use bigdecimal::ToPrimitive;
             let count = match &args[0] {
                 Value::Number(n) => n.to_usize().ok_or("Count must be a positive integer")?,
+                Value::Integer(i) => i.to_usize().ok_or("Count must be a positive integer")?,
                 Value::FastNumber(f) => *f as usize,
                 _ => {
                     return Err("Count must be a number".to_string());
@@ -307,7 +311,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
     );
 
     let stream_for_skip = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "Skip".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
             if args.len() != 1 {
@@ -316,6 +320,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
             use bigdecimal::ToPrimitive;
             let count = match &args[0] {
                 Value::Number(n) => n.to_usize().ok_or("Count must be a positive integer")?,
+                Value::Integer(i) => i.to_usize().ok_or("Count must be a positive integer")?,
                 Value::FastNumber(f) => *f as usize,
                 _ => {
                     return Err("Count must be a number".to_string());
@@ -329,7 +334,7 @@ pub fn create_stream_object(items: Vec<Value>, generator: Option<Value>) -> Valu
 }
 
 fn create_map_stream(parent_stream: Value, map_fn: Value) -> Result<Value, String> {
-    let mut stream_map = HashMap::new();
+    let mut stream_map = IndexMap::new();
 
     let parent_next = parent_stream.clone();
     let map_fn_next = map_fn.clone();
@@ -337,7 +342,7 @@ fn create_map_stream(parent_stream: Value, map_fn: Value) -> Result<Value, Strin
         "Next".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             if let Value::Map(map) = &parent_next {
-                if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                if let Some(next_method) = map.read_recover().get("Next") {
                     if let Value::NativeFunction(f) = next_method {
                         match f(vec![]) {
                             Ok(Value::Option(opt)) => {
@@ -376,7 +381,7 @@ fn create_map_stream(parent_stream: Value, map_fn: Value) -> Result<Value, Strin
         "HasMore".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             if let Value::Map(map) = &parent_has {
-                if let Some(has_more_method) = map.read().expect("lock poisoned").get("HasMore") {
+                if let Some(has_more_method) = map.read_recover().get("HasMore") {
                     if let Value::NativeFunction(f) = has_more_method {
                         return f(vec![]);
                     }
@@ -395,7 +400,7 @@ fn create_map_stream(parent_stream: Value, map_fn: Value) -> Result<Value, Strin
 
             loop {
                 if let Value::Map(map) = &parent_list {
-                    if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                    if let Some(next_method) = map.read_recover().get("Next") {
                         if let Value::NativeFunction(f) = next_method {
                             match f(vec![]) {
                                 Ok(Value::Option(opt)) => {
@@ -437,7 +442,7 @@ fn create_map_stream(parent_stream: Value, map_fn: Value) -> Result<Value, Strin
 }
 
 fn create_filter_stream(parent_stream: Value, filter_fn: Value) -> Result<Value, String> {
-    let mut stream_map = HashMap::new();
+    let mut stream_map = IndexMap::new();
 
     let parent_next = parent_stream.clone();
     let filter_fn_next = filter_fn.clone();
@@ -446,7 +451,7 @@ fn create_filter_stream(parent_stream: Value, filter_fn: Value) -> Result<Value,
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             loop {
                 if let Value::Map(map) = &parent_next {
-                    if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                    if let Some(next_method) = map.read_recover().get("Next") {
                         if let Value::NativeFunction(f) = next_method {
                             match f(vec![]) {
                                 Ok(Value::Option(opt)) => {
@@ -505,7 +510,7 @@ fn create_filter_stream(parent_stream: Value, filter_fn: Value) -> Result<Value,
         "HasMore".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             if let Value::Map(map) = &parent_has {
-                if let Some(has_more_method) = map.read().expect("lock poisoned").get("HasMore") {
+                if let Some(has_more_method) = map.read_recover().get("HasMore") {
                     if let Value::NativeFunction(f) = has_more_method {
                         return f(vec![]);
                     }
@@ -519,14 +524,14 @@ fn create_filter_stream(parent_stream: Value, filter_fn: Value) -> Result<Value,
     let stream_value = Value::Map(stream_rc.clone());
 
     let stream_for_list = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "ToList".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             let mut result = Vec::new();
 
             loop {
                 if let Value::Map(map) = &stream_for_list {
-                    if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                    if let Some(next_method) = map.read_recover().get("Next") {
                         if let Value::NativeFunction(f) = next_method {
                             match f(vec![]) {
                                 Ok(Value::Option(opt)) => {
@@ -555,35 +560,29 @@ fn create_filter_stream(parent_stream: Value, filter_fn: Value) -> Result<Value,
         }))),
     );
 
-    add_close_method(
-        &mut *stream_rc.write().expect("lock poisoned"),
-        parent_stream.clone(),
-    );
-    add_transform_methods(
-        &mut *stream_rc.write().expect("lock poisoned"),
-        parent_stream.clone(),
-    );
+    add_close_method(&mut *stream_rc.write_recover(), parent_stream.clone());
+    add_transform_methods(&mut *stream_rc.write_recover(), parent_stream.clone());
 
     Ok(stream_value)
 }
 
 fn create_take_stream(parent_stream: Value, count: usize) -> Result<Value, String> {
     let taken = Arc::new(std::sync::RwLock::new(0usize));
-    let mut stream_map = HashMap::new();
+    let mut stream_map = IndexMap::new();
 
     let parent_next = parent_stream.clone();
     let taken_next = taken.clone();
     stream_map.insert(
         "Next".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let mut taken_count = taken_next.write().expect("lock poisoned");
+            let mut taken_count = taken_next.write_recover();
 
             if *taken_count >= count {
                 return Ok(Value::Option(Box::new(None)));
             }
 
             if let Value::Map(map) = &parent_next {
-                if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                if let Some(next_method) = map.read_recover().get("Next") {
                     if let Value::NativeFunction(f) = next_method {
                         match f(vec![]) {
                             Ok(Value::Option(opt)) => {
@@ -611,14 +610,14 @@ fn create_take_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
     stream_map.insert(
         "HasMore".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let taken_count = taken_has.read().expect("lock poisoned");
+            let taken_count = taken_has.read_recover();
 
             if *taken_count >= count {
                 return Ok(Value::Boolean(false));
             }
 
             if let Value::Map(map) = &parent_has {
-                if let Some(has_more_method) = map.read().expect("lock poisoned").get("HasMore") {
+                if let Some(has_more_method) = map.read_recover().get("HasMore") {
                     if let Value::NativeFunction(f) = has_more_method {
                         return f(vec![]);
                     }
@@ -632,14 +631,14 @@ fn create_take_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
     let stream_value = Value::Map(stream_rc.clone());
 
     let stream_for_list = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "ToList".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             let mut result = Vec::new();
 
             loop {
                 if let Value::Map(map) = &stream_for_list {
-                    if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                    if let Some(next_method) = map.read_recover().get("Next") {
                         if let Value::NativeFunction(f) = next_method {
                             match f(vec![]) {
                                 Ok(Value::Option(opt)) => {
@@ -668,32 +667,26 @@ fn create_take_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
         }))),
     );
 
-    add_close_method(
-        &mut *stream_rc.write().expect("lock poisoned"),
-        parent_stream.clone(),
-    );
-    add_transform_methods(
-        &mut *stream_rc.write().expect("lock poisoned"),
-        parent_stream.clone(),
-    );
+    add_close_method(&mut *stream_rc.write_recover(), parent_stream.clone());
+    add_transform_methods(&mut *stream_rc.write_recover(), parent_stream.clone());
 
     Ok(stream_value)
 }
 
 fn create_skip_stream(parent_stream: Value, count: usize) -> Result<Value, String> {
     let skipped = Arc::new(std::sync::RwLock::new(0usize));
-    let mut stream_map = HashMap::new();
+    let mut stream_map = IndexMap::new();
 
     let parent_next = parent_stream.clone();
     let skipped_next = skipped.clone();
     stream_map.insert(
         "Next".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            let mut skipped_count = skipped_next.write().expect("lock poisoned");
+            let mut skipped_count = skipped_next.write_recover();
 
             while *skipped_count < count {
                 if let Value::Map(map) = &parent_next {
-                    if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                    if let Some(next_method) = map.read_recover().get("Next") {
                         if let Value::NativeFunction(f) = next_method {
                             match f(vec![]) {
                                 Ok(Value::Option(opt)) => {
@@ -724,7 +717,7 @@ fn create_skip_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
             }
 
             if let Value::Map(map) = &parent_next {
-                if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                if let Some(next_method) = map.read_recover().get("Next") {
                     if let Value::NativeFunction(f) = next_method {
                         return f(vec![]);
                     }
@@ -739,7 +732,7 @@ fn create_skip_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
         "HasMore".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             if let Value::Map(map) = &parent_has {
-                if let Some(has_more_method) = map.read().expect("lock poisoned").get("HasMore") {
+                if let Some(has_more_method) = map.read_recover().get("HasMore") {
                     if let Value::NativeFunction(f) = has_more_method {
                         return f(vec![]);
                     }
@@ -753,14 +746,14 @@ fn create_skip_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
     let stream_value = Value::Map(stream_rc.clone());
 
     let stream_for_list = stream_value.clone();
-    stream_rc.write().expect("lock poisoned").insert(
+    stream_rc.write_recover().insert(
         "ToList".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             let mut result = Vec::new();
 
             loop {
                 if let Value::Map(map) = &stream_for_list {
-                    if let Some(next_method) = map.read().expect("lock poisoned").get("Next") {
+                    if let Some(next_method) = map.read_recover().get("Next") {
                         if let Value::NativeFunction(f) = next_method {
                             match f(vec![]) {
                                 Ok(Value::Option(opt)) => {
@@ -789,25 +782,19 @@ fn create_skip_stream(parent_stream: Value, count: usize) -> Result<Value, Strin
         }))),
     );
 
-    add_close_method(
-        &mut *stream_rc.write().expect("lock poisoned"),
-        parent_stream.clone(),
-    );
-    add_transform_methods(
-        &mut *stream_rc.write().expect("lock poisoned"),
-        parent_stream.clone(),
-    );
+    add_close_method(&mut *stream_rc.write_recover(), parent_stream.clone());
+    add_transform_methods(&mut *stream_rc.write_recover(), parent_stream.clone());
 
     Ok(stream_value)
 }
 
-fn add_close_method(stream_map: &mut HashMap<String, Value>, parent_stream: Value) {
+fn add_close_method(stream_map: &mut IndexMap<String, Value>, parent_stream: Value) {
     let parent_close = parent_stream.clone();
     stream_map.insert(
         "Close".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             if let Value::Map(map) = &parent_close {
-                if let Some(close_method) = map.read().expect("lock poisoned").get("Close") {
+                if let Some(close_method) = map.read_recover().get("Close") {
                     if let Value::NativeFunction(f) = close_method {
                         return f(vec![]);
                     }
@@ -818,7 +805,7 @@ fn add_close_method(stream_map: &mut HashMap<String, Value>, parent_stream: Valu
     );
 }
 
-fn add_transform_methods(stream_map: &mut HashMap<String, Value>, parent_stream: Value) {
+fn add_transform_methods(stream_map: &mut IndexMap<String, Value>, parent_stream: Value) {
     let stream_value = Value::Map(Arc::new(std::sync::RwLock::new(stream_map.clone())));
 
     let stream_for_map = stream_value.clone();
@@ -853,6 +840,7 @@ fn add_transform_methods(stream_map: &mut HashMap<String, Value>, parent_stream:
             use bigdecimal::ToPrimitive;
             let count = match &args[0] {
                 Value::Number(n) => n.to_usize().ok_or("Count must be a positive integer")?,
+                Value::Integer(i) => i.to_usize().ok_or("Count must be a positive integer")?,
                 Value::FastNumber(f) => *f as usize,
                 _ => {
                     return Err("Count must be a number".to_string());
@@ -872,6 +860,7 @@ fn add_transform_methods(stream_map: &mut HashMap<String, Value>, parent_stream:
             use bigdecimal::ToPrimitive;
             let count = match &args[0] {
                 Value::Number(n) => n.to_usize().ok_or("Count must be a positive integer")?,
+                Value::Integer(i) => i.to_usize().ok_or("Count must be a positive integer")?,
                 Value::FastNumber(f) => *f as usize,
                 _ => {
                     return Err("Count must be a number".to_string());
@@ -886,7 +875,7 @@ fn add_transform_methods(stream_map: &mut HashMap<String, Value>, parent_stream:
         "Reset".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
             if let Value::Map(map) = &parent_reset {
-                if let Some(reset_method) = map.read().expect("lock poisoned").get("Reset") {
+                if let Some(reset_method) = map.read_recover().get("Reset") {
                     if let Value::NativeFunction(f) = reset_method {
                         return f(vec![]);
                     }
@@ -1,10 +1,50 @@
+use crate::runtime::memory::MemoryReport;
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
-use std::sync::Arc;
+use indexmap::IndexMap;
+use std::sync::{Arc, LazyLock};
 use system::system_output;
 
+type NativeFunction = Arc<Box<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync>>;
+
+// System.MemoryStats(Value) -> counts, sizes and reference counts for one
+// value. Every System module shares this one function, so the interpreter
+// can tell it apart however a script reaches it (see `reports_memory`)
+static MEMORY_STATS: LazyLock<NativeFunction> = LazyLock::new(|| {
+    Arc::new(Box::new(|args| match args.as_slice() {
+        [value] => Ok(MemoryReport::collect_argument(value).to_value()),
+        [] => Err("System.MemoryStats() reports on script variables, \
+                   so only a running script can call it"
+            .to_string()),
+        _ => {
+            Err("System.MemoryStats requires 0-1 arguments (optional value to inspect)".to_string())
+        }
+    }))
+});
+
+// System.MemoryReport() -> the same for every script variable, with live
+// instances per concept and reference cycles; the interpreter answers it
+static MEMORY_REPORT: LazyLock<NativeFunction> = LazyLock::new(|| {
+    Arc::new(Box::new(|args| {
+        if args.is_empty() {
+            Err("System.MemoryReport() reports on script variables, \
+                 so only a running script can call it"
+                .to_string())
+        } else {
+            Err("System.MemoryReport takes no arguments".to_string())
+        }
+    }))
+});
+
+/// Whether `function` is one the interpreter answers itself when it's
+/// called with no arguments, since it reports on every script variable:
+/// System.MemoryStats or System.MemoryReport.
+pub fn reports_memory(function: &NativeFunction) -> bool {
+    Arc::ptr_eq(function, &MEMORY_STATS) || Arc::ptr_eq(function, &MEMORY_REPORT)
+}
+
 pub fn create_system_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
     methods.insert(
         // Dangerious
         "Execute".to_string(),
@@ -14,9 +54,10 @@ pub fn create_system_module() -> Value {
             }
 
             let command_str = args[0].to_display_string();
+            permissions::check_shell(&command_str)?;
             match system_output(&command_str) {
                 Ok(output) => {
-                    let mut result = HashMap::new();
+                    let mut result = IndexMap::new();
 
                     // Exit code
                     let exit_code = output.status.code().unwrap_or(-1);
@@ -56,9 +97,10 @@ pub fn create_system_module() -> Value {
 
             let script_path = args[0].to_display_string();
             let command = format!("cargo run --quiet -- run {}", script_path);
+            permissions::check_shell(&command)?;
             match system_output(&command) {
                 Ok(output) => {
-                    let mut result = HashMap::new();
+                    let mut result = IndexMap::new();
 
                     let exit_code = output.status.code().unwrap_or(-1);
                     use bigdecimal::BigDecimal;
@@ -91,7 +133,7 @@ pub fn create_system_module() -> Value {
                 return Err("System.Info takes no arguments".to_string());
             }
 
-            let mut info = HashMap::new();
+            let mut info = IndexMap::new();
 
             // OS Type
             let os_type = if cfg!(target_os = "windows") {
@@ -139,5 +181,16 @@ pub fn create_system_module() -> Value {
         }))),
     );
 
+    // Called with no argument, the interpreter reports on all script variables
+    methods.insert(
+        "MemoryStats".to_string(),
+        Value::NativeFunction(MEMORY_STATS.clone()),
+    );
+
+    methods.insert(
+        "MemoryReport".to_string(),
+        Value::NativeFunction(MEMORY_REPORT.clone()),
+    );
+
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
@@ -1,10 +1,317 @@
-use crate::runtime::interpreter::Interpreter;
+use crate::runtime::deadline;
+use crate::runtime::interpreter::{Interpreter, RuntimeError};
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
-use std::sync::Arc;
+use crate::stdlib::log;
+use indexmap::IndexMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::runtime::Runtime;
+
+/// Calls a handler with its arguments. A worker is made on the thread it
+/// runs on and may be reused there for many calls.
+pub type Worker = Box<dyn FnMut(&Value, Vec<Value>) -> Result<Value, RuntimeError>>;
+
+/// Makes the workers of Task.Pool and Task.ParallelMap. The interpreter
+/// passes one whose workers can call concept methods.
+pub type WorkerFactory = Arc<dyn Fn() -> Worker + Send + Sync>;
+
+fn native_workers() -> WorkerFactory {
+    Arc::new(|| {
+        Box::new(|handler, args| {
+            match handler {
+            Value::NativeFunction(function) => function(args).map_err(RuntimeError::Custom),
+            _ => Err(RuntimeError::Custom(
+                "A concept method can only be a handler when Task.Pool or Task.ParallelMap is called directly"
+                    .to_string(),
+            )),
+        }
+        })
+    })
+}
+
+/// A handler is a function, or a List of a concept instance and the name of
+/// one of its methods.
+pub fn check_handler(handler: &Value, caller: &str) -> Result<(), String> {
+    let valid = match handler {
+        Value::NativeFunction(_) => true,
+        Value::List(pair) => matches!(
+            pair.read_recover().as_slice(),
+            [Value::Map(instance), Value::String(_)] if instance.read_recover().contains_key("_concept")
+        ),
+        _ => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} needs a handler: a function, or a List of an instance and a method name such as [Links, \"Check\"]",
+            caller
+        ))
+    }
+}
+
+fn whole_number(value: &Value, what: &str) -> Result<usize, String> {
+    use bigdecimal::ToPrimitive;
+    match value {
+        Value::Number(n) => n.to_usize(),
+        Value::Integer(i) => i.to_usize(),
+        _ => None,
+    }
+    .filter(|n| *n > 0)
+    .ok_or_else(|| format!("{} must be a positive whole number", what))
+}
+
+/// One error for every handler call that failed, listing the first few.
+fn aggregate_failures(caller: &str, total: usize, failures: &[(usize, String)]) -> String {
+    const LISTED: usize = 5;
+    let mut message = format!("{}: {} of {} failed", caller, failures.len(), total);
+    for (number, error) in failures.iter().take(LISTED) {
+        message.push_str(&format!("\n  #{}: {}", number, error));
+    }
+    if failures.len() > LISTED {
+        message.push_str(&format!("\n  and {} more", failures.len() - LISTED));
+    }
+    message
+}
+
+fn failure_message(error: &RuntimeError) -> String {
+    error.to_error_info().message.clone()
+}
+
+// Where a worker leaves the result for one item
+type Slot = Mutex<Option<Result<Value, String>>>;
+
+/// `Task.ParallelMap(list, handler, workers?)`: the handler's result for
+/// each item, in order. Items are handed out to the workers as they become
+/// free, and every item is tried even when some fail.
+pub fn parallel_map(
+    args: &[Value],
+    runtime: &Arc<Runtime>,
+    workers: WorkerFactory,
+) -> Result<Value, String> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(
+            "Task.ParallelMap requires 2-3 arguments (list, handler, optional number of workers)"
+                .to_string(),
+        );
+    }
+    let items = match &args[0] {
+        Value::List(list) => Arc::new(list.read_recover().clone()),
+        other => {
+            return Err(format!(
+                "Task.ParallelMap needs a List, not {}",
+                other.type_name()
+            ));
+        }
+    };
+    let handler = args[1].clone();
+    check_handler(&handler, "Task.ParallelMap")?;
+    let count = match args.get(2) {
+        Some(count) => whole_number(count, "Task.ParallelMap's number of workers")?,
+        None => std::thread::available_parallelism().map_or(4, |n| n.get()),
+    }
+    .min(items.len());
+    if items.is_empty() {
+        return Ok(Value::List(Arc::new(std::sync::RwLock::new(Vec::new()))));
+    }
+
+    let next = Arc::new(AtomicUsize::new(0));
+    let results: Arc<Vec<Slot>> = Arc::new(items.iter().map(|_| Mutex::new(None)).collect());
+    let task_deadline = deadline::current();
+    let task_permissions = permissions::current();
+    let handles: Vec<_> = (0..count)
+        .map(|_| {
+            let (items, results, next) = (items.clone(), results.clone(), next.clone());
+            let (handler, workers) = (handler.clone(), workers.clone());
+            let task_permissions = task_permissions.clone();
+            runtime.spawn_blocking(move || {
+                deadline::scope(task_deadline, || {
+                    permissions::scope(task_permissions, || {
+                        let mut worker = workers();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(item) = items.get(index) else {
+                                break;
+                            };
+                            let result = worker(&handler, vec![item.clone()]);
+                            *results[index].lock_recover() =
+                                Some(result.map_err(|e| failure_message(&e)));
+                        }
+                    })
+                })
+            })
+        })
+        .collect();
+    deadline::block_on(runtime, async move {
+        for handle in handles {
+            let _ = handle.await;
+        }
+    })?;
+
+    let mut values = Vec::with_capacity(items.len());
+    let mut failures = Vec::new();
+    for (index, result) in results.iter().enumerate() {
+        match result.lock_recover().take() {
+            Some(Ok(value)) => values.push(value),
+            Some(Err(error)) => failures.push((index + 1, error)),
+            None => failures.push((index + 1, "The worker stopped before it".to_string())),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(aggregate_failures(
+            "Task.ParallelMap",
+            items.len(),
+            &failures,
+        ));
+    }
+    Ok(Value::List(Arc::new(std::sync::RwLock::new(values))))
+}
+
+/// Tasks a pool has been given, so Wait can tell when they have all finished.
+#[derive(Default)]
+struct Tally {
+    submitted: usize,
+    finished: usize,
+    // Numbered by submission, since Wait was last called
+    failures: Vec<(usize, String)>,
+    waited: usize,
+}
+
+/// `Task.Pool(size)`: runs submitted handlers at most `size` at a time.
+pub fn pool(
+    args: &[Value],
+    runtime: &Arc<Runtime>,
+    workers: WorkerFactory,
+) -> Result<Value, String> {
+    if args.len() != 1 {
+        return Err("Task.Pool requires 1 argument (number of workers)".to_string());
+    }
+    let size = whole_number(&args[0], "Task.Pool's size")?;
+    let permits = Arc::new(tokio::sync::Semaphore::new(size));
+    let tally = Arc::new((Mutex::new(Tally::default()), Condvar::new()));
+    let mut methods = IndexMap::new();
+    methods.insert("Workers".to_string(), Value::Integer(size.into()));
+
+    // Pool.Submit(handler, args...) -> task handle
+    let runtime_submit = runtime.clone();
+    let (permits_submit, tally_submit) = (permits.clone(), tally.clone());
+    methods.insert(
+        "Submit".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let Some(handler) = args.first().cloned() else {
+                return Err("Pool.Submit requires a handler, then its arguments".to_string());
+            };
+            check_handler(&handler, "Pool.Submit")?;
+            let handler_args = args[1..].to_vec();
+            let number = {
+                let mut tally = tally_submit.0.lock_recover();
+                tally.submitted += 1;
+                tally.submitted
+            };
+            let cancel_token = Arc::new(AtomicBool::new(false));
+            let cancelled = cancel_token.clone();
+            let (permits, tally, workers) = (
+                permits_submit.clone(),
+                tally_submit.clone(),
+                workers.clone(),
+            );
+            let task_deadline = deadline::current();
+            let task_permissions = permissions::current();
+
+            let handle = runtime_submit.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let result = if cancelled.load(Ordering::Relaxed) {
+                    // Cancelled while it waited for a worker
+                    Ok(Value::Boolean(false))
+                } else {
+                    tokio::task::spawn_blocking(move || {
+                        deadline::scope(task_deadline, || {
+                            permissions::scope(task_permissions, || {
+                                workers()(&handler, handler_args)
+                            })
+                        })
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(RuntimeError::Custom(format!("Task panicked: {}", e))))
+                };
+
+                let (lock, finished) = &*tally;
+                let mut tally = lock.lock_recover();
+                tally.finished += 1;
+                let value = match result {
+                    Ok(value) => value,
+                    Err(error) => {
+                        tally.failures.push((number, failure_message(&error)));
+                        Value::Error(error.to_error_info())
+                    }
+                };
+                finished.notify_all();
+                value
+            });
+
+            Ok(Value::TaskHandle(
+                Arc::new(std::sync::Mutex::new(Some(handle))),
+                cancel_token,
+            ))
+        }))),
+    );
+
+    // Pool.Wait() - waits for everything submitted so far, and fails with
+    // the errors of the tasks that failed since the last Wait
+    let tally_wait = tally.clone();
+    methods.insert(
+        "Wait".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let (lock, finished) = &*tally_wait;
+            let mut tally = lock.lock_recover();
+            let target = tally.submitted;
+            while tally.finished < target {
+                deadline::check()?;
+                let wait = deadline::limit(None).unwrap_or(std::time::Duration::from_secs(3600));
+                tally = finished
+                    .wait_timeout(tally, wait)
+                    .map(|(tally, _)| tally)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+            }
+            let total = target - tally.waited;
+            tally.waited = target;
+            let failures = std::mem::take(&mut tally.failures);
+            if failures.is_empty() {
+                Ok(Value::Integer(total.into()))
+            } else {
+                Err(aggregate_failures("Task.Pool", total, &failures))
+            }
+        }))),
+    );
+
+    // Pool.Running() - how many handlers are running now
+    let permits_running = permits;
+    methods.insert(
+        "Running".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            Ok(Value::Integer(
+                (size - permits_running.available_permits()).into(),
+            ))
+        }))),
+    );
+
+    // Pool.Pending() - how many submitted tasks haven't finished
+    let tally_pending = tally;
+    methods.insert(
+        "Pending".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |_args| {
+            let tally = tally_pending.0.lock_recover();
+            Ok(Value::Integer((tally.submitted - tally.finished).into()))
+        }))),
+    );
+
+    Ok(Value::Map(Arc::new(std::sync::RwLock::new(methods))))
+}
 
 pub fn create_task_module(interpreter: &Interpreter) -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
     let runtime = interpreter.runtime.clone();
 
     // The function receives no arguments and runs in the background
@@ -27,16 +334,19 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
             let cancel_token = Arc::new(std::sync::atomic::AtomicBool::new(false));
 
             // Spawn the task on the Tokio runtime
+            let task_permissions = permissions::current();
             let handle = runtime_spawn.spawn(async move {
                 // Call the function
                 match &func {
-                    Value::NativeFunction(f) => match f(vec![]) {
-                        Ok(result) => result,
-                        Err(e) => {
-                            eprintln!("Task error: {}", e);
-                            Value::Boolean(false)
+                    Value::NativeFunction(f) => {
+                        match permissions::scope(task_permissions, || f(vec![])) {
+                            Ok(result) => result,
+                            Err(e) => {
+                                log::error("Task failed", &[("error", &e)]);
+                                Value::Boolean(false)
+                            }
                         }
-                    },
+                    }
                     _ => Value::Boolean(false),
                 }
             });
@@ -59,7 +369,7 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
             }
 
             let tasks = match &args[0] {
-                Value::List(l) => l.read().expect("lock poisoned").clone(),
+                Value::List(l) => l.read_recover().clone(),
                 _ => return Err("Argument must be a list of TaskHandles".to_string()),
             };
 
@@ -68,13 +378,13 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
 
             for task in tasks {
                 if let Value::TaskHandle(handle_mutex, _cancel_token) = task {
-                    let mut handle_lock = handle_mutex.lock().unwrap();
+                    let mut handle_lock = handle_mutex.lock_recover();
                     if let Some(handle) = handle_lock.take() {
                         let result = runtime.block_on(async move {
                             match handle.await {
                                 Ok(value) => value,
                                 Err(e) => {
-                                    eprintln!("Task panicked: {}", e);
+                                    log::error("Task panicked", &[("error", &e.to_string())]);
                                     Value::Boolean(false)
                                 }
                             }
@@ -102,7 +412,7 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
             }
 
             let tasks = match &args[0] {
-                Value::List(l) => l.read().expect("lock poisoned").clone(),
+                Value::List(l) => l.read_recover().clone(),
                 _ => return Err("Argument must be a list of TaskHandles".to_string()),
             };
 
@@ -114,7 +424,7 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
             let mut handles = Vec::new();
             for task in tasks {
                 if let Value::TaskHandle(handle_mutex, _cancel_token) = task {
-                    let mut handle_lock = handle_mutex.lock().unwrap();
+                    let mut handle_lock = handle_mutex.lock_recover();
                     if let Some(handle) = handle_lock.take() {
                         handles.push(handle);
                     } else {
@@ -137,7 +447,7 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
                     match result {
                         Ok(v) => v,
                         Err(e) => {
-                            eprintln!("Task panicked: {}", e);
+                            log::error("Task panicked", &[("error", &e.to_string())]);
                             Value::Boolean(false)
                         }
                     }
@@ -150,6 +460,24 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
         }))),
     );
 
+    // Task.Pool(size) and Task.ParallelMap(list, handler, workers?). The
+    // interpreter runs these itself when a handler is a concept method.
+    let runtime_pool = runtime.clone();
+    methods.insert(
+        "Pool".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            pool(&args, &runtime_pool, native_workers())
+        }))),
+    );
+
+    let runtime_map = runtime.clone();
+    methods.insert(
+        "ParallelMap".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            parallel_map(&args, &runtime_map, native_workers())
+        }))),
+    );
+
     // Task.Cancel(task_handle) - Signal task to cancel
     methods.insert(
         "Cancel".to_string(),
@@ -190,3 +518,64 @@ pub fn create_task_module(interpreter: &Interpreter) -> Value {
 
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(values: Vec<Value>) -> Value {
+        Value::List(Arc::new(std::sync::RwLock::new(values)))
+    }
+
+    fn halve() -> Value {
+        Value::NativeFunction(Arc::new(Box::new(|args| match &args[0] {
+            Value::Integer(n) if n % 2 == 0.into() => Ok(Value::Integer(n / 2)),
+            other => Err(format!("{} is odd", other.to_display_string())),
+        })))
+    }
+
+    #[test]
+    fn test_parallel_map() {
+        let runtime = crate::runtime::executor::shared_runtime();
+        let numbers = list((1..=8).map(|n| Value::Integer((n * 2).into())).collect());
+        let halves = parallel_map(
+            &[numbers, halve(), Value::Integer(3.into())],
+            &runtime,
+            native_workers(),
+        )
+        .unwrap();
+        assert_eq!(halves.to_display_string(), "[1, 2, 3, 4, 5, 6, 7, 8]");
+
+        // Every item is tried, and the failures are reported together
+        let mixed = list((1..=4).map(|n| Value::Integer(n.into())).collect());
+        let error = parallel_map(&[mixed, halve()], &runtime, native_workers()).unwrap_err();
+        assert_eq!(
+            error,
+            "Task.ParallelMap: 2 of 4 failed\n  #1: 1 is odd\n  #3: 3 is odd"
+        );
+    }
+
+    #[test]
+    fn test_pool_wait() {
+        let runtime = crate::runtime::executor::shared_runtime();
+        let pool = pool(&[Value::Integer(2.into())], &runtime, native_workers()).unwrap();
+        let Value::Map(pool) = pool else {
+            panic!("Task.Pool gives a Map");
+        };
+        let member = |name: &str| match pool.read_recover().get(name) {
+            Some(Value::NativeFunction(function)) => function.clone(),
+            _ => panic!("no member {}", name),
+        };
+        for n in [2, 4, 5] {
+            member("Submit")(vec![halve(), Value::Integer(n.into())]).unwrap();
+        }
+        assert_eq!(
+            member("Wait")(vec![]).unwrap_err(),
+            "Task.Pool: 1 of 3 failed\n  #3: 5 is odd"
+        );
+        // Failures are only reported once
+        member("Submit")(vec![halve(), Value::Integer(6.into())]).unwrap();
+        assert_eq!(member("Wait")(vec![]).unwrap().to_display_string(), "1");
+        assert_eq!(member("Pending")(vec![]).unwrap().to_display_string(), "0");
+    }
+}
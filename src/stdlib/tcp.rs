@@ -1,11 +1,14 @@
+use crate::runtime::deadline;
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
 use std::sync::{Arc, Mutex};
 
 pub fn create_tcp_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     // TCP.Connect("127.0.0.1:8080")
     methods.insert(
@@ -16,15 +19,35 @@ pub fn create_tcp_module() -> Value {
             }
 
             let addr = args[0].to_display_string();
+            permissions::check_net(&addr)?;
 
-            match TcpStream::connect(&addr) {
-                Ok(stream) => Ok(create_tcp_connection_object(stream)),
-                Err(e) => Err(format!("TCP connection failed: {}", e)),
+            match connect(&addr) {
+                Ok(stream) => Ok(create_tcp_connection_object(Socket::Plain(stream))),
+                Err(e) => {
+                    deadline::check()?;
+                    Err(format!("TCP connection failed: {}", e))
+                }
             }
         }))),
     );
 
-    // TCP.Listen("127.0.0.1:8080")
+    // TCP.ConnectTls("example.com", 443, { CaFile: "ca.pem" })
+    methods.insert(
+        "ConnectTls".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "TCP.ConnectTls requires 2-3 arguments (host, port, optional options)"
+                        .to_string(),
+                );
+            }
+            let (host, port) = (args[0].to_display_string(), args[1].to_display_string());
+            permissions::check_net(&format!("{}:{}", host, port))?;
+            connect_tls(&host, &port, args.get(2)).map(create_tcp_connection_object)
+        }))),
+    );
+
+    // TCP.Listen("127.0.0.1:8080") -> a Stream of the connections made to it
     methods.insert(
         "Listen".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
@@ -33,6 +56,7 @@ pub fn create_tcp_module() -> Value {
             }
 
             let addr = args[0].to_display_string();
+            permissions::check_net(&addr)?;
 
             match TcpListener::bind(&addr) {
                 Ok(listener) => Ok(create_tcp_listener_object(listener)),
@@ -44,71 +68,147 @@ pub fn create_tcp_module() -> Value {
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
 
-fn create_tcp_connection_object(stream: TcpStream) -> Value {
-    let stream_arc = Arc::new(Mutex::new(stream));
-    let mut methods = HashMap::new();
+/// A connection, over TLS or not.
+enum Socket {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
 
-    // Connection.Send("data")
-    let stream_send = stream_arc.clone();
-    methods.insert(
-        "Send".to_string(),
-        Value::NativeFunction(Arc::new(Box::new(move |args| {
-            if args.len() != 1 {
-                return Err("Connection.Send requires 1 argument (data)".to_string());
+impl Socket {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Socket::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Socket::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    fn close(self) {
+        match self {
+            Socket::Plain(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            #[cfg(feature = "tls")]
+            Socket::Tls(mut stream) => {
+                // Tell the server it's a clean end, not a cut connection
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+                let _ = stream.sock.shutdown(Shutdown::Both);
             }
+        }
+    }
+}
 
-            let data = args[0].to_display_string();
-            let mut stream_guard = stream_send.lock().unwrap();
+impl Read for Socket {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Plain(stream) => stream.read(buffer),
+            #[cfg(feature = "tls")]
+            Socket::Tls(stream) => match stream.read(buffer) {
+                // A server that closes without close_notify has still ended
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(0),
+                other => other,
+            },
+        }
+    }
+}
 
-            match stream_guard.write_all(data.as_bytes()) {
-                Ok(_) => {
-                    stream_guard.flush().ok();
-                    Ok(Value::Boolean(true))
-                }
-                Err(e) => Err(format!("Failed to send data: {}", e)),
-            }
-        }))),
-    );
+impl Write for Socket {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Plain(stream) => stream.write(data),
+            #[cfg(feature = "tls")]
+            Socket::Tls(stream) => stream.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Socket::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Socket::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+// The socket is None once closed
+type SharedSocket = Arc<Mutex<Option<Socket>>>;
+
+fn create_tcp_connection_object(socket: Socket) -> Value {
+    let peer = socket.tcp().peer_addr().map(|addr| addr.to_string());
+    let local = socket.tcp().local_addr().map(|addr| addr.to_string());
+    let stream_arc: SharedSocket = Arc::new(Mutex::new(Some(socket)));
+    let mut methods = IndexMap::new();
 
-    // Connection.Receive(buffer_size)
-    let stream_recv = stream_arc.clone();
     methods.insert(
-        "Receive".to_string(),
-        Value::NativeFunction(Arc::new(Box::new(move |args| {
-            let buffer_size = if args.is_empty() {
-                1024
-            } else {
-                match &args[0] {
-                    Value::Number(n) => {
-                        use bigdecimal::ToPrimitive;
-                        n.to_usize().unwrap_or(1024)
-                    }
-                    _ => 1024,
-                }
-            };
-
-            let mut stream_guard = stream_recv.lock().unwrap();
-            let mut buffer = vec![0u8; buffer_size];
-
-            match stream_guard.read(&mut buffer) {
-                Ok(n) => {
-                    buffer.truncate(n);
-                    match String::from_utf8(buffer) {
-                        Ok(s) => Ok(Value::String(s)),
-                        Err(_) => Err("Received non-UTF8 data".to_string()),
-                    }
-                }
-                Err(e) => Err(format!("Failed to receive data: {}", e)),
-            }
-        }))),
+        "PeerAddress".to_string(),
+        Value::String(peer.unwrap_or_default()),
     );
+    methods.insert(
+        "LocalAddress".to_string(),
+        Value::String(local.unwrap_or_default()),
+    );
+
+    // Connection.Write("data"), or Send
+    let stream_send = stream_arc.clone();
+    let send = Value::NativeFunction(Arc::new(Box::new(move |args| {
+        if args.len() != 1 {
+            return Err("Connection.Write requires 1 argument (data)".to_string());
+        }
+
+        let data = args[0].as_bytes();
+        let mut stream_guard = stream_send.lock_recover();
+        let stream = stream_guard.as_mut().ok_or("Connection is closed")?;
+        deadline::check()?;
+        stream
+            .tcp()
+            .set_write_timeout(deadline::limit(None))
+            .map_err(|e| format!("Failed to send data: {}", e))?;
+
+        match stream.write_all(&data) {
+            Ok(_) => {
+                stream.flush().ok();
+                Ok(Value::Boolean(true))
+            }
+            Err(e) => {
+                deadline::check()?;
+                Err(format!("Failed to send data: {}", e))
+            }
+        }
+    })));
+    methods.insert("Write".to_string(), send.clone());
+    methods.insert("Send".to_string(), send);
+
+    // Connection.Read(buffer_size), or Receive
+    let stream_recv = stream_arc.clone();
+    let read = Value::NativeFunction(Arc::new(Box::new(move |args| {
+        let buffer = receive(&stream_recv, &args)?;
+        match String::from_utf8(buffer) {
+            Ok(s) => Ok(Value::String(s)),
+            Err(_) => Err("Received non-UTF8 data (use ReadBytes for binary data)".to_string()),
+        }
+    })));
+    methods.insert("Read".to_string(), read.clone());
+    methods.insert("Receive".to_string(), read);
+
+    // Connection.ReadBytes(buffer_size), or ReceiveBytes
+    let stream_recv_bytes = stream_arc.clone();
+    let read_bytes = Value::NativeFunction(Arc::new(Box::new(move |args| {
+        let buffer = receive(&stream_recv_bytes, &args)?;
+        Ok(Value::Bytes(buffer.into()))
+    })));
+    methods.insert("ReadBytes".to_string(), read_bytes.clone());
+    methods.insert("ReceiveBytes".to_string(), read_bytes);
 
     // Connection.Close()
     let stream_close = stream_arc.clone();
     methods.insert(
         "Close".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            drop(stream_close.lock().unwrap());
+            if let Some(socket) = stream_close.lock_recover().take() {
+                socket.close();
+            }
             Ok(Value::Boolean(true))
         }))),
     );
@@ -116,9 +216,31 @@ fn create_tcp_connection_object(stream: TcpStream) -> Value {
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
 
+/// A Stream of accepted connections, which also has the Accept and Close
+/// it had before it was a Stream, and the Address it listens on.
 fn create_tcp_listener_object(listener: TcpListener) -> Value {
-    let listener_arc = Arc::new(Mutex::new(listener));
-    let mut methods = HashMap::new();
+    let address = listener.local_addr().map(|addr| addr.to_string());
+    // Taken out on Close; an Accept already waiting keeps its own handle
+    let listener_arc = Arc::new(Mutex::new(Some(Arc::new(listener))));
+
+    let listener_next = listener_arc.clone();
+    let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+        let Some(listener) = listener_next.lock_recover().clone() else {
+            return Ok(Value::Option(Box::new(None)));
+        };
+        let connection = accept(&listener)?;
+        Ok(Value::Option(Box::new(Some(connection))))
+    })));
+    let stream = crate::stdlib::stream::create_stream_object(vec![], Some(generator));
+    let Value::Map(methods) = &stream else {
+        unreachable!("streams are Maps");
+    };
+    let mut methods = methods.write_recover();
+
+    methods.insert(
+        "Address".to_string(),
+        Value::String(address.unwrap_or_default()),
+    );
 
     // Listener.Accept() -> returns Connection object
     let listener_accept = listener_arc.clone();
@@ -129,11 +251,11 @@ fn create_tcp_listener_object(listener: TcpListener) -> Value {
                 return Err("Listener.Accept requires no arguments".to_string());
             }
 
-            let listener_guard = listener_accept.lock().unwrap();
-            match listener_guard.accept() {
-                Ok((stream, _addr)) => Ok(create_tcp_connection_object(stream)),
-                Err(e) => Err(format!("Failed to accept connection: {}", e)),
-            }
+            let listener = listener_accept
+                .lock_recover()
+                .clone()
+                .ok_or("Listener is closed")?;
+            accept(&listener)
         }))),
     );
 
@@ -142,10 +264,290 @@ fn create_tcp_listener_object(listener: TcpListener) -> Value {
     methods.insert(
         "Close".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |_args| {
-            drop(listener_close.lock().unwrap());
+            listener_close.lock_recover().take();
             Ok(Value::Boolean(true))
         }))),
     );
 
-    Value::Map(Arc::new(std::sync::RwLock::new(methods)))
+    drop(methods);
+    stream
+}
+
+fn accept(listener: &TcpListener) -> Result<Value, String> {
+    match listener.accept() {
+        Ok((stream, _addr)) => Ok(create_tcp_connection_object(Socket::Plain(stream))),
+        Err(e) => Err(format!("Failed to accept connection: {}", e)),
+    }
+}
+
+fn receive(stream: &Mutex<Option<Socket>>, args: &[Value]) -> Result<Vec<u8>, String> {
+    let buffer_size = match args.first() {
+        Some(Value::Number(n)) => {
+            use bigdecimal::ToPrimitive;
+            n.to_usize().unwrap_or(1024)
+        }
+        Some(Value::Integer(i)) => {
+            use bigdecimal::ToPrimitive;
+            i.to_usize().unwrap_or(1024)
+        }
+        _ => 1024,
+    };
+
+    let mut stream_guard = stream.lock_recover();
+    let stream = stream_guard.as_mut().ok_or("Connection is closed")?;
+    deadline::check()?;
+    stream
+        .tcp()
+        .set_read_timeout(deadline::limit(None))
+        .map_err(|e| format!("Failed to receive data: {}", e))?;
+
+    let mut buffer = vec![0u8; buffer_size];
+    match stream.read(&mut buffer) {
+        Ok(n) => {
+            buffer.truncate(n);
+            Ok(buffer)
+        }
+        Err(e) => {
+            deadline::check()?;
+            Err(format!("Failed to receive data: {}", e))
+        }
+    }
+}
+
+// Inside a request handler the connect attempt is bounded by its deadline
+fn connect(addr: &str) -> std::io::Result<TcpStream> {
+    let Some(timeout) = deadline::limit(None) else {
+        return TcpStream::connect(addr);
+    };
+
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "address resolved to nothing",
+        )
+    }))
+}
+
+#[cfg(not(feature = "tls"))]
+fn connect_tls(_host: &str, _port: &str, _options: Option<&Value>) -> Result<Socket, String> {
+    Err("sfex was built without the tls feature, so it can't make TLS connections".to_string())
+}
+
+#[cfg(feature = "tls")]
+fn connect_tls(host: &str, port: &str, options: Option<&Value>) -> Result<Socket, String> {
+    let mut tls = TlsOptions::default();
+    if let Some(options) = options {
+        let Value::Map(map) = options else {
+            return Err(format!(
+                "TCP.ConnectTls options must be a Map, not {}",
+                options.type_name()
+            ));
+        };
+        for (key, value) in map.read_recover().iter() {
+            if !tls.set(key, value) {
+                return Err(format!("TCP.ConnectTls has no option {}", key));
+            }
+        }
+    }
+
+    let config = tls.client_config("TCP.ConnectTls")?;
+    let name = tls.server_name(host, "TCP.ConnectTls")?;
+    let connection = rustls::ClientConnection::new(Arc::new(config), name)
+        .map_err(|e| format!("TLS error: {}", e))?;
+    let stream = match connect(&format!("{}:{}", host, port)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            deadline::check()?;
+            return Err(format!("TCP connection failed: {}", e));
+        }
+    };
+
+    // Handshake now, so a certificate that isn't trusted fails here rather
+    // than at the first Read or Write
+    let mut tls = rustls::StreamOwned::new(connection, stream);
+    tls.sock
+        .set_read_timeout(deadline::limit(None))
+        .map_err(|e| format!("TLS error: {}", e))?;
+    while tls.conn.is_handshaking() {
+        tls.conn
+            .complete_io(&mut tls.sock)
+            .map_err(|e| match deadline::check() {
+                Err(exceeded) => exceeded,
+                Ok(()) => format!("TLS handshake with {} failed: {}", host, e),
+            })?;
+    }
+    Ok(Socket::Tls(Box::new(tls)))
+}
+
+/// The TLS options TCP.ConnectTls takes, which MQTT.Connect takes too.
+///
+/// The server's certificate is checked against the system's root
+/// certificates, or only those in CaFile. There's no option to skip the
+/// check: a self-signed server is trusted by giving its certificate as CaFile.
+#[cfg(feature = "tls")]
+#[derive(Default)]
+pub(crate) struct TlsOptions {
+    ca_file: Option<String>,
+    server_name: Option<String>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+}
+
+#[cfg(feature = "tls")]
+impl TlsOptions {
+    /// Take one option, or return false when `key` isn't a TLS option.
+    pub(crate) fn set(&mut self, key: &str, value: &Value) -> bool {
+        let slot = match key {
+            "CaFile" => &mut self.ca_file,
+            "ServerName" => &mut self.server_name,
+            "ClientCert" => &mut self.client_cert,
+            "ClientKey" => &mut self.client_key,
+            _ => return false,
+        };
+        *slot = Some(value.to_display_string());
+        true
+    }
+
+    pub(crate) fn client_config(&self, caller: &str) -> Result<rustls::ClientConfig, String> {
+        let mut roots = rustls::RootCertStore::empty();
+        match &self.ca_file {
+            Some(path) => {
+                for cert in pem_certs(path)? {
+                    roots
+                        .add(&cert)
+                        .map_err(|e| format!("{}: bad certificate in {}: {}", caller, path, e))?;
+                }
+            }
+            None => {
+                for cert in rustls_native_certs::load_native_certs().certs {
+                    // Skip the odd certificate rustls can't use, as browsers do
+                    let _ = roots.add(&rustls::Certificate(cert.to_vec()));
+                }
+                if roots.is_empty() {
+                    return Err(format!(
+                        "{}: no system root certificates found; give a CaFile",
+                        caller
+                    ));
+                }
+            }
+        }
+
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(pem_certs(cert)?, pem_key(key)?)
+                .map_err(|e| format!("{}: client certificate: {}", caller, e)),
+            (None, None) => Ok(builder.with_no_client_auth()),
+            _ => Err(format!("{} needs both ClientCert and ClientKey", caller)),
+        }
+    }
+
+    /// The name the certificate must have: ServerName, or else `host`.
+    pub(crate) fn server_name(
+        &self,
+        host: &str,
+        caller: &str,
+    ) -> Result<rustls::ServerName, String> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        rustls::ServerName::try_from(name)
+            .map_err(|_| format!("{}: '{}' is not a server name", caller, name))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn pem_certs(path: &str) -> Result<Vec<rustls::Certificate>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut std::io::Cursor::new(pem))
+        .map_err(|_| format!("Failed to parse certificate in {}", path))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path));
+    }
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+#[cfg(feature = "tls")]
+fn pem_key(path: &str) -> Result<rustls::PrivateKey, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut std::io::Cursor::new(&pem))
+        .map_err(|_| format!("Failed to parse private key in {}", path))?;
+    if keys.is_empty() {
+        keys = rustls_pemfile::rsa_private_keys(&mut std::io::Cursor::new(&pem))
+            .map_err(|_| format!("Failed to parse RSA key in {}", path))?;
+    }
+    keys.into_iter()
+        .next()
+        .map(rustls::PrivateKey)
+        .ok_or_else(|| format!("No private keys found in {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(object: &Value, name: &str, args: Vec<Value>) -> Value {
+        let Value::Map(map) = object else {
+            panic!("{} is called on a Map", name);
+        };
+        let function = map.read_recover()[name].clone();
+        let Value::NativeFunction(function) = function else {
+            panic!("{} is a function", name);
+        };
+        function(args).unwrap()
+    }
+
+    #[test]
+    fn test_listen_stream() {
+        let listener = create_tcp_listener_object(TcpListener::bind("127.0.0.1:0").unwrap());
+        let Value::Map(map) = &listener else {
+            panic!("a listener is a Map");
+        };
+        let address = map.read_recover()["Address"].to_display_string();
+
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"ping").unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            reply
+        });
+
+        let Value::Option(next) = call(&listener, "Next", vec![]) else {
+            panic!("Next gives an Option");
+        };
+        let connection = next.expect("a connection");
+        let Value::Map(fields) = &connection else {
+            panic!("a connection is a Map");
+        };
+        assert!(
+            fields.read_recover()["PeerAddress"]
+                .to_display_string()
+                .starts_with("127.0.0.1:")
+        );
+        assert_eq!(
+            call(&connection, "Read", vec![]).to_display_string(),
+            "ping"
+        );
+        call(
+            &connection,
+            "Write",
+            vec![Value::String("pong".to_string())],
+        );
+        call(&connection, "Close", vec![]);
+        assert_eq!(client.join().unwrap(), "pong");
+
+        call(&listener, "Close", vec![]);
+        let Value::Option(next) = call(&listener, "Next", vec![]) else {
+            panic!("Next gives an Option");
+        };
+        assert!(next.is_none());
+    }
 }
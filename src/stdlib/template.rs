@@ -0,0 +1,751 @@
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::SystemTime;
+
+// Guards against partials that include themselves
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+// Compiled templates keyed by path, reloaded when the file's mtime changes
+static TEMPLATE_CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedTemplate>>> = OnceLock::new();
+
+struct CachedTemplate {
+    modified: Option<SystemTime>,
+    nodes: Arc<Vec<Node>>,
+}
+
+pub fn create_template_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Template.Render("views/page.html", { title: "Home", items: [...] })
+    methods.insert(
+        "Render".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Template.Render requires 1-2 arguments (path, optional data)".to_string(),
+                );
+            }
+
+            let path = resolve_path(&args[0].to_display_string());
+            let data = args.get(1).cloned().unwrap_or_else(empty_map);
+            let nodes = load_template(&path)?;
+            let mut renderer = Renderer::new(data);
+            renderer.render(&nodes, base_dir(&path), 0)?;
+            Ok(Value::String(renderer.output))
+        }))),
+    );
+
+    // Template.RenderString("<h1>{{ title }}</h1>", { title: "Home" })
+    methods.insert(
+        "RenderString".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Template.RenderString requires 1-2 arguments (source, optional data)"
+                        .to_string(),
+                );
+            }
+
+            let data = args.get(1).cloned().unwrap_or_else(empty_map);
+            let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+            render_source(&args[0].to_display_string(), "<string>", &cwd, data).map(Value::String)
+        }))),
+    );
+
+    // Template.Escape("<b>") -> "&lt;b&gt;"
+    methods.insert(
+        "Escape".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Template.Escape requires 1 argument (text)".to_string());
+            }
+            Ok(Value::String(escape_html(&args[0].to_display_string())))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// Render template text, naming it `name` in errors and resolving includes
+/// against `dir`.
+pub fn render_source(source: &str, name: &str, dir: &Path, data: Value) -> Result<String, String> {
+    let nodes = compile(source, name)?;
+    let mut renderer = Renderer::new(data);
+    renderer.render(&nodes, dir, 0)?;
+    Ok(renderer.output)
+}
+
+fn empty_map() -> Value {
+    Value::Map(Arc::new(RwLock::new(IndexMap::new())))
+}
+
+fn resolve_path(path: &str) -> PathBuf {
+    let candidate = PathBuf::from(path);
+    if candidate.is_absolute() {
+        candidate
+    } else {
+        std::env::current_dir()
+            .unwrap_or_else(|_| PathBuf::from("."))
+            .join(candidate)
+    }
+}
+
+fn base_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+fn load_template(path: &Path) -> Result<Arc<Vec<Node>>, String> {
+    permissions::check_read(path)?;
+    let modified = fs::metadata(path)
+        .map_err(|e| format!("Failed to read template '{}': {}", path.display(), e))?
+        .modified()
+        .ok();
+
+    let cache = TEMPLATE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(cached) = cache.lock_recover().get(path)
+        && modified.is_some()
+        && cached.modified == modified
+    {
+        return Ok(cached.nodes.clone());
+    }
+
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read template '{}': {}", path.display(), e))?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let nodes = Arc::new(compile(&source, &name)?);
+
+    cache.lock_recover().insert(
+        path.to_path_buf(),
+        CachedTemplate {
+            modified,
+            nodes: nodes.clone(),
+        },
+    );
+    Ok(nodes)
+}
+
+// ---------------------------------------------------------------------------
+// Compilation
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    // {{ path }} is escaped, {{{ path }}} is written as-is
+    Output {
+        expr: Operand,
+        raw: bool,
+    },
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        key: Option<String>,
+        item: String,
+        source: Vec<String>,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+    },
+    Include(String),
+}
+
+#[derive(Debug)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Value),
+}
+
+#[derive(Debug)]
+enum Condition {
+    Truthy(Operand),
+    Not(Box<Condition>),
+    Compare(Operand, &'static str, Operand),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+enum Segment {
+    Text(String),
+    Output { source: String, raw: bool },
+    Tag(String),
+}
+
+// A block's closing tag and the line it was on
+type EndTag = (String, usize);
+
+struct Compiler<'a> {
+    name: &'a str,
+    segments: Vec<(Segment, usize)>,
+    position: usize,
+}
+
+fn compile(source: &str, name: &str) -> Result<Vec<Node>, String> {
+    let segments = split_segments(source, name)?;
+    let mut compiler = Compiler {
+        name,
+        segments,
+        position: 0,
+    };
+    let (nodes, end) = compiler.block(&[])?;
+    if let Some((tag, line)) = end {
+        return Err(template_error(
+            name,
+            line,
+            &format!("unexpected {{% {} %}}", tag),
+        ));
+    }
+    Ok(nodes)
+}
+
+fn template_error(name: &str, line: usize, message: &str) -> String {
+    format!("Template error in {} line {}: {}", name, line, message)
+}
+
+fn split_segments(source: &str, name: &str) -> Result<Vec<(Segment, usize)>, String> {
+    let mut segments = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start..];
+        let (open, close) = if after.starts_with("{{{") {
+            ("{{{", "}}}")
+        } else if after.starts_with("{{") {
+            ("{{", "}}")
+        } else if after.starts_with("{%") {
+            ("{%", "%}")
+        } else if after.starts_with("{#") {
+            ("{#", "#}")
+        } else {
+            let text = &rest[..start + 1];
+            push_text(&mut segments, text, line);
+            line += text.matches('\n').count();
+            rest = &rest[start + 1..];
+            continue;
+        };
+
+        let text = &rest[..start];
+        push_text(&mut segments, text, line);
+        line += text.matches('\n').count();
+
+        let body_start = start + open.len();
+        let Some(end) = rest[body_start..].find(close) else {
+            return Err(template_error(name, line, &format!("unclosed {}", open)));
+        };
+        let body = rest[body_start..body_start + end].trim().to_string();
+        match open {
+            "{{{" => segments.push((
+                Segment::Output {
+                    source: body,
+                    raw: true,
+                },
+                line,
+            )),
+            "{{" => segments.push((
+                Segment::Output {
+                    source: body,
+                    raw: false,
+                },
+                line,
+            )),
+            "{%" => segments.push((Segment::Tag(body), line)),
+            _ => {}
+        }
+        line += rest[start..body_start + end].matches('\n').count();
+        rest = &rest[body_start + end + close.len()..];
+
+        // Like Jinja's trim_blocks: a newline right after a tag or comment is
+        // dropped so block tags on their own lines leave no blank lines
+        if open == "{%" || open == "{#" {
+            for newline in ["\r\n", "\n"] {
+                if let Some(stripped) = rest.strip_prefix(newline) {
+                    rest = stripped;
+                    line += 1;
+                    break;
+                }
+            }
+        }
+    }
+    push_text(&mut segments, rest, line);
+    Ok(segments)
+}
+
+fn push_text(segments: &mut Vec<(Segment, usize)>, text: &str, line: usize) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some((Segment::Text(previous), _)) = segments.last_mut() {
+        previous.push_str(text);
+    } else {
+        segments.push((Segment::Text(text.to_string()), line));
+    }
+}
+
+impl Compiler<'_> {
+    /// Compile nodes until one of `terminators` (or the end); returns the
+    /// terminating tag so the caller can tell `else` from `endif`.
+    fn block(&mut self, terminators: &[&str]) -> Result<(Vec<Node>, Option<EndTag>), String> {
+        let mut nodes = Vec::new();
+
+        while self.position < self.segments.len() {
+            let index = self.position;
+            self.position += 1;
+            let line = self.segments[index].1;
+
+            match &self.segments[index].0 {
+                Segment::Text(text) => nodes.push(Node::Text(text.clone())),
+                Segment::Output { source, raw } => {
+                    let raw = *raw;
+                    let expr =
+                        parse_operand(source).map_err(|e| template_error(self.name, line, &e))?;
+                    nodes.push(Node::Output { expr, raw });
+                }
+                Segment::Tag(tag) => {
+                    let tag = tag.clone();
+                    let keyword = tag.split_whitespace().next().unwrap_or("");
+                    if terminators.contains(&keyword) {
+                        return Ok((nodes, Some((tag, line))));
+                    }
+                    let node = match keyword {
+                        "if" => self.if_node(&tag["if".len()..], line)?,
+                        "for" => self.for_node(&tag["for".len()..], line)?,
+                        "include" => Node::Include(
+                            parse_string_literal(tag["include".len()..].trim()).ok_or_else(
+                                || template_error(self.name, line, "include needs a quoted path"),
+                            )?,
+                        ),
+                        _ => {
+                            return Err(template_error(
+                                self.name,
+                                line,
+                                &format!("unexpected {{% {} %}}", tag),
+                            ));
+                        }
+                    };
+                    nodes.push(node);
+                }
+            }
+        }
+
+        if terminators.is_empty() {
+            Ok((nodes, None))
+        } else {
+            Err(template_error(
+                self.name,
+                self.segments.last().map(|(_, line)| *line).unwrap_or(1),
+                &format!("missing {{% {} %}}", terminators.last().unwrap_or(&"end")),
+            ))
+        }
+    }
+
+    fn if_node(&mut self, condition: &str, line: usize) -> Result<Node, String> {
+        let mut branches = Vec::new();
+        let mut condition =
+            parse_condition(condition).map_err(|e| template_error(self.name, line, &e))?;
+
+        loop {
+            let (body, end) = self.block(&["elif", "else", "endif"])?;
+            branches.push((condition, body));
+            let (tag, line) = end.expect("block returns its terminator");
+
+            if let Some(rest) = tag.strip_prefix("elif") {
+                condition =
+                    parse_condition(rest).map_err(|e| template_error(self.name, line, &e))?;
+                continue;
+            }
+            if tag.trim() == "else" {
+                let (otherwise, _) = self.block(&["endif"])?;
+                return Ok(Node::If {
+                    branches,
+                    otherwise,
+                });
+            }
+            return Ok(Node::If {
+                branches,
+                otherwise: Vec::new(),
+            });
+        }
+    }
+
+    // {% for item in items %}, {% for key, value in map %}, optional {% else %}
+    fn for_node(&mut self, header: &str, line: usize) -> Result<Node, String> {
+        let Some((vars, source)) = header.split_once(" in ") else {
+            return Err(template_error(self.name, line, "for needs 'item in list'"));
+        };
+        let vars: Vec<String> = vars.split(',').map(|v| v.trim().to_string()).collect();
+        let (key, item) = match vars.as_slice() {
+            [item] if is_name(item) => (None, item.clone()),
+            [key, item] if is_name(key) && is_name(item) => (Some(key.clone()), item.clone()),
+            _ => {
+                return Err(template_error(
+                    self.name,
+                    line,
+                    "invalid for loop variables",
+                ));
+            }
+        };
+        let source = parse_path(source.trim()).map_err(|e| template_error(self.name, line, &e))?;
+
+        let (body, end) = self.block(&["else", "endfor"])?;
+        let empty = match end {
+            Some((tag, _)) if tag.trim() == "else" => self.block(&["endfor"])?.0,
+            _ => Vec::new(),
+        };
+
+        Ok(Node::For {
+            key,
+            item,
+            source,
+            body,
+            empty,
+        })
+    }
+}
+
+fn is_name(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse_path(source: &str) -> Result<Vec<String>, String> {
+    let parts: Vec<String> = source.split('.').map(|p| p.trim().to_string()).collect();
+    if parts.iter().all(|p| is_name(p)) {
+        Ok(parts)
+    } else {
+        Err(format!("invalid variable '{}'", source))
+    }
+}
+
+fn parse_string_literal(source: &str) -> Option<String> {
+    let quote = source.chars().next()?;
+    if (quote == '"' || quote == '\'') && source.len() >= 2 && source.ends_with(quote) {
+        Some(source[1..source.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+fn parse_operand(source: &str) -> Result<Operand, String> {
+    let source = source.trim();
+    if let Some(text) = parse_string_literal(source) {
+        return Ok(Operand::Literal(Value::String(text)));
+    }
+    match source {
+        "true" | "True" => return Ok(Operand::Literal(Value::Boolean(true))),
+        "false" | "False" => return Ok(Operand::Literal(Value::Boolean(false))),
+        _ => {}
+    }
+    if let Ok(number) = source.parse::<bigdecimal::BigDecimal>() {
+        return Ok(Operand::Literal(Value::Number(number)));
+    }
+    parse_path(source).map(Operand::Path)
+}
+
+// Conditions: `a`, `not a`, `a == "x"`, `a > 3`, joined with `and` / `or`
+fn parse_condition(source: &str) -> Result<Condition, String> {
+    let source = source.trim();
+    if source.is_empty() {
+        return Err("if needs a condition".to_string());
+    }
+
+    if let Some((left, right)) = split_keyword(source, " or ") {
+        return Ok(Condition::Or(
+            Box::new(parse_condition(left)?),
+            Box::new(parse_condition(right)?),
+        ));
+    }
+    if let Some((left, right)) = split_keyword(source, " and ") {
+        return Ok(Condition::And(
+            Box::new(parse_condition(left)?),
+            Box::new(parse_condition(right)?),
+        ));
+    }
+    if let Some(rest) = source.strip_prefix("not ") {
+        return Ok(Condition::Not(Box::new(parse_condition(rest)?)));
+    }
+
+    for op in ["==", "!=", ">=", "<=", ">", "<"] {
+        if let Some((left, right)) = split_keyword(source, op) {
+            return Ok(Condition::Compare(
+                parse_operand(left)?,
+                op,
+                parse_operand(right)?,
+            ));
+        }
+    }
+    Ok(Condition::Truthy(parse_operand(source)?))
+}
+
+// First occurrence of `keyword` outside a quoted string
+fn split_keyword<'a>(source: &'a str, keyword: &str) -> Option<(&'a str, &'a str)> {
+    let mut quote = None;
+    for (i, c) in source.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if source[i..].starts_with(keyword) => {
+                return Some((&source[..i], &source[i + keyword.len()..]));
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+// ---------------------------------------------------------------------------
+// Rendering
+// ---------------------------------------------------------------------------
+
+struct Renderer {
+    data: Value,
+    // Loop variables, innermost last
+    scopes: Vec<HashMap<String, Value>>,
+    output: String,
+}
+
+impl Renderer {
+    fn new(data: Value) -> Self {
+        Self {
+            data,
+            scopes: Vec::new(),
+            output: String::new(),
+        }
+    }
+
+    fn render(&mut self, nodes: &[Node], dir: &Path, depth: usize) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.output.push_str(text),
+                Node::Output { expr, raw } => {
+                    let text = self
+                        .operand(expr)
+                        .map(|v| v.to_display_string())
+                        .unwrap_or_default();
+                    if *raw {
+                        self.output.push_str(&text);
+                    } else {
+                        self.output.push_str(&escape_html(&text));
+                    }
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let mut matched = None;
+                    for (condition, body) in branches {
+                        if self.condition(condition) {
+                            matched = Some(body);
+                            break;
+                        }
+                    }
+                    self.render(matched.unwrap_or(otherwise), dir, depth)?;
+                }
+                Node::For {
+                    key,
+                    item,
+                    source,
+                    body,
+                    empty,
+                } => {
+                    let entries = self.loop_entries(source);
+                    if entries.is_empty() {
+                        self.render(empty, dir, depth)?;
+                        continue;
+                    }
+
+                    let count = entries.len();
+                    for (i, (entry_key, entry_value)) in entries.into_iter().enumerate() {
+                        let mut scope = HashMap::new();
+                        if let Some(key) = key {
+                            scope.insert(key.clone(), entry_key);
+                        }
+                        scope.insert(item.clone(), entry_value);
+                        scope.insert("loop".to_string(), loop_info(i, count));
+                        self.scopes.push(scope);
+                        let result = self.render(body, dir, depth);
+                        self.scopes.pop();
+                        result?;
+                    }
+                }
+                Node::Include(path) => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(format!("Template include depth exceeded at '{}'", path));
+                    }
+                    let path = dir.join(path);
+                    let nodes = load_template(&path)?;
+                    self.render(&nodes, base_dir(&path), depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn operand(&self, operand: &Operand) -> Option<Value> {
+        match operand {
+            Operand::Literal(value) => Some(value.clone()),
+            Operand::Path(path) => self.lookup(path),
+        }
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<Value> {
+        let (first, rest) = path.split_first()?;
+        let mut current = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(first).cloned())
+            .or_else(|| field(&self.data, first))?;
+
+        for part in rest {
+            current = field(&current, part)?;
+        }
+        Some(current)
+    }
+
+    fn condition(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Truthy(operand) => self.operand(operand).is_some_and(|v| truthy(&v)),
+            Condition::Not(inner) => !self.condition(inner),
+            Condition::And(left, right) => self.condition(left) && self.condition(right),
+            Condition::Or(left, right) => self.condition(left) || self.condition(right),
+            Condition::Compare(left, op, right) => {
+                let left = self
+                    .operand(left)
+                    .unwrap_or_else(|| Value::String(String::new()));
+                let right = self
+                    .operand(right)
+                    .unwrap_or_else(|| Value::String(String::new()));
+                compare(&left, op, &right)
+            }
+        }
+    }
+
+    fn loop_entries(&self, source: &[String]) -> Vec<(Value, Value)> {
+        match self.lookup(source) {
+            Some(Value::List(list)) => list
+                .read_recover()
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (number(i + 1), v.clone()))
+                .collect(),
+            Some(Value::Map(map)) => {
+                let map = map.read_recover();
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                keys.into_iter()
+                    .map(|k| (Value::String(k.clone()), map[k].clone()))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// `loop.Index` (1-based like SFX lists), `loop.First`, `loop.Last`
+fn loop_info(index: usize, count: usize) -> Value {
+    let mut info = IndexMap::new();
+    info.insert("Index".to_string(), number(index + 1));
+    info.insert("First".to_string(), Value::Boolean(index == 0));
+    info.insert("Last".to_string(), Value::Boolean(index + 1 == count));
+    info.insert("Length".to_string(), number(count));
+    Value::Map(Arc::new(RwLock::new(info)))
+}
+
+fn number(n: usize) -> Value {
+    Value::Number(bigdecimal::BigDecimal::from(n as u64))
+}
+
+// Map keys match exactly first, then case-insensitively so templates can use
+// `title` for an SFX `Title` field
+fn field(value: &Value, name: &str) -> Option<Value> {
+    match value {
+        Value::Map(map) => {
+            let map = map.read_recover();
+            map.get(name).cloned().or_else(|| {
+                map.iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.clone())
+            })
+        }
+        Value::List(list) => {
+            let list = list.read_recover();
+            if name.eq_ignore_ascii_case("length") {
+                return Some(number(list.len()));
+            }
+            let index = name.parse::<usize>().ok()?;
+            list.get(index.checked_sub(1)?).cloned()
+        }
+        Value::String(s) if name.eq_ignore_ascii_case("length") => Some(number(s.chars().count())),
+        Value::Option(inner) => inner.as_ref().as_ref().and_then(|v| field(v, name)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::String(s) => !s.is_empty(),
+        Value::List(list) => !list.read_recover().is_empty(),
+        Value::Map(map) => !map.read_recover().is_empty(),
+        Value::Option(inner) => inner.is_some(),
+        other => other.is_truthy(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn compare(left: &Value, op: &str, right: &Value) -> bool {
+    if let (Some(l), Some(r)) = (as_number(left), as_number(right)) {
+        return match op {
+            "==" => l == r,
+            "!=" => l != r,
+            ">" => l > r,
+            "<" => l < r,
+            ">=" => l >= r,
+            _ => l <= r,
+        };
+    }
+
+    let (l, r) = (left.to_display_string(), right.to_display_string());
+    match op {
+        "==" => l == r,
+        "!=" => l != r,
+        ">" => l > r,
+        "<" => l < r,
+        ">=" => l >= r,
+        _ => l <= r,
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,262 @@
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use bytes::Bytes;
+use icu_normalizer::{ComposingNormalizerBorrowed, DecomposingNormalizerBorrowed};
+use std::sync::{Arc, RwLock};
+use unicode_segmentation::UnicodeSegmentation;
+
+// Strings count, index and slice by grapheme cluster, what a reader sees as
+// one character: "é" written as e plus an accent, a flag, or a family emoji
+// is one. Bytes and Chars give the UTF-8 bytes and the code points for code
+// that needs them, Normalize picks one of the ways Unicode can spell the same
+// text, and Fold gives a form for comparing without case.
+
+/// Members on a String value. Bytes, ByteSize, Chars and Graphemes are
+/// values; the rest are methods. Length comes from `Value::len` and single
+/// characters from indexing.
+pub fn string_member(text: &str, member: &str) -> Option<Value> {
+    let method: Box<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync> = match member {
+        "Bytes" => return Some(Value::Bytes(Bytes::copy_from_slice(text.as_bytes()))),
+        "ByteSize" => return Some(Value::Integer(text.len().into())),
+        "Chars" => return Some(list(text.chars().map(|c| c.to_string()))),
+        "Graphemes" => return Some(list(text.graphemes(true).map(str::to_string))),
+        // Text.Slice(1, 7) - graphemes 1 to 7, both included
+        "Slice" => {
+            let text = text.to_string();
+            Box::new(move |args| {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(
+                        "Text.Slice requires 1 or 2 arguments (start, optional end)".to_string()
+                    );
+                }
+                let graphemes: Vec<&str> = text.graphemes(true).collect();
+                let len = graphemes.len() as i64;
+                let start = position(&args[0], len)?;
+                let end = match args.get(1) {
+                    Some(end) => position(end, len)?,
+                    None => len,
+                };
+                if start < 1 || end > len || start > end + 1 {
+                    return Err(format!(
+                        "Text.Slice range {} to {} out of bounds for {} characters",
+                        start, end, len
+                    ));
+                }
+                Ok(Value::String(
+                    graphemes[start as usize - 1..end as usize].concat(),
+                ))
+            })
+        }
+        "Normalize" => {
+            let text = text.to_string();
+            Box::new(move |args| {
+                let form = match args.as_slice() {
+                    [] => "NFC".to_string(),
+                    [form] => form.to_display_string().to_ascii_uppercase(),
+                    _ => {
+                        return Err(
+                            "Text.Normalize takes an optional form (NFC, NFD, NFKC, NFKD)"
+                                .to_string(),
+                        );
+                    }
+                };
+                normalize(&text, &form).map(Value::String)
+            })
+        }
+        "Fold" => {
+            let text = text.to_string();
+            Box::new(move |args| {
+                if !args.is_empty() {
+                    return Err("Text.Fold takes no arguments".to_string());
+                }
+                Ok(Value::String(nfc(&fold(&text))))
+            })
+        }
+        "ToUpper" | "ToLower" | "Trim" => {
+            let text = text.to_string();
+            let member = member.to_string();
+            Box::new(move |args| {
+                if !args.is_empty() {
+                    return Err(format!("Text.{} takes no arguments", member));
+                }
+                Ok(Value::String(match member.as_str() {
+                    "ToUpper" => text.to_uppercase(),
+                    "ToLower" => text.to_lowercase(),
+                    _ => text.trim().to_string(),
+                }))
+            })
+        }
+        "Contains" => {
+            let text = text.to_string();
+            Box::new(move |args| {
+                let [Value::String(part)] = args.as_slice() else {
+                    return Err("Text.Contains requires 1 argument (String)".to_string());
+                };
+                Ok(Value::Boolean(contains(&text, part)))
+            })
+        }
+        "EqualsIgnoreCase" => {
+            let text = text.to_string();
+            Box::new(move |args| {
+                let [Value::String(other)] = args.as_slice() else {
+                    return Err("Text.EqualsIgnoreCase requires 1 argument (String)".to_string());
+                };
+                Ok(Value::Boolean(fold(&text) == fold(other)))
+            })
+        }
+        _ => return None,
+    };
+    Some(Value::NativeFunction(Arc::new(method)))
+}
+
+/// Whether `part` occurs in `text` as whole graphemes, so "e" isn't found
+/// in an "é" spelled as e plus an accent
+fn contains(text: &str, part: &str) -> bool {
+    let boundaries: Vec<usize> = text
+        .grapheme_indices(true)
+        .map(|(i, _)| i)
+        .chain([text.len()])
+        .collect();
+    text.match_indices(part).any(|(start, found)| {
+        boundaries.binary_search(&start).is_ok()
+            && boundaries.binary_search(&(start + found.len())).is_ok()
+    })
+}
+
+fn list(items: impl Iterator<Item = String>) -> Value {
+    Value::List(Arc::new(RwLock::new(items.map(Value::String).collect())))
+}
+
+fn position(value: &Value, len: i64) -> Result<i64, String> {
+    let n = match value {
+        Value::Number(n) => n.to_i64(),
+        Value::Integer(i) => i.to_i64(),
+        Value::FastNumber(f) if f.fract() == 0.0 => Some(*f as i64),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        format!(
+            "Text.Slice positions must be whole numbers, got {}",
+            value.to_display_string()
+        )
+    })?;
+    match n {
+        0 => Err("SFX strings start at 1, not 0".to_string()),
+        n if n < 0 => Ok(len + n + 1),
+        n => Ok(n),
+    }
+}
+
+fn nfc(text: &str) -> String {
+    ComposingNormalizerBorrowed::new_nfc()
+        .normalize(text)
+        .into_owned()
+}
+
+fn nfd(text: &str) -> String {
+    DecomposingNormalizerBorrowed::new_nfd()
+        .normalize(text)
+        .into_owned()
+}
+
+fn normalize(text: &str, form: &str) -> Result<String, String> {
+    Ok(match form {
+        "NFC" => nfc(text),
+        "NFD" => nfd(text),
+        "NFKC" => ComposingNormalizerBorrowed::new_nfkc()
+            .normalize(text)
+            .into_owned(),
+        "NFKD" => DecomposingNormalizerBorrowed::new_nfkd()
+            .normalize(text)
+            .into_owned(),
+        _ => {
+            return Err(format!(
+                "Unknown normalization form '{}', expected NFC, NFD, NFKC or NFKD",
+                form
+            ));
+        }
+    })
+}
+
+/// Unicode's canonical caseless form, NFD(fold(NFD(text))). Upper- then
+/// lowercasing matches full case folding for nearly all text, and unlike
+/// lowercasing alone it turns "ß" and "SS" both into "ss".
+fn fold(text: &str) -> String {
+    nfd(&nfd(text).to_uppercase().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(text: &str, member: &str, args: Vec<Value>) -> Value {
+        match string_member(text, member) {
+            Some(Value::NativeFunction(f)) => f(args).unwrap(),
+            _ => panic!("no method {}", member),
+        }
+    }
+
+    fn s(text: &str) -> Value {
+        Value::String(text.to_string())
+    }
+
+    fn int(n: i64) -> Value {
+        Value::Integer(n.into())
+    }
+
+    #[test]
+    fn test_views_and_slice() {
+        let text = "e\u{301}👨\u{200d}👩\u{200d}👧!";
+        let items = |member| match string_member(text, member) {
+            Some(Value::List(items)) => items.read().unwrap().len(),
+            _ => panic!("{} is a List", member),
+        };
+        assert_eq!(items("Graphemes"), 3);
+        assert_eq!(items("Chars"), 8);
+        assert_eq!(
+            string_member(text, "ByteSize").unwrap().to_display_string(),
+            "22"
+        );
+
+        let slice = |args| call("Hello 👋 world", "Slice", args).to_display_string();
+        assert_eq!(slice(vec![int(1), int(7)]), "Hello 👋");
+        assert_eq!(slice(vec![int(-5)]), "world");
+        assert_eq!(slice(vec![int(8), int(7)]), "");
+        let Some(Value::NativeFunction(f)) = string_member("abc", "Slice") else {
+            panic!("Slice is a method");
+        };
+        assert!(f(vec![int(2), int(4)]).is_err());
+        assert!(f(vec![int(0)]).is_err());
+    }
+
+    #[test]
+    fn test_normalize_and_fold() {
+        let composed = "\u{e9}";
+        let decomposed = "e\u{301}";
+        assert_eq!(
+            call(decomposed, "Normalize", vec![]).to_display_string(),
+            composed
+        );
+        assert_eq!(
+            call(composed, "Normalize", vec![s("NFD")]).to_display_string(),
+            decomposed
+        );
+        assert_eq!(
+            call("ﬁ", "Normalize", vec![s("NFKC")]).to_display_string(),
+            "fi"
+        );
+        assert_eq!(
+            call("Straße", "Fold", vec![]).to_display_string(),
+            "strasse"
+        );
+        let same = |a: &str, b: &str| call(a, "EqualsIgnoreCase", vec![s(b)]).is_truthy();
+        assert!(same("STRASSE", "straße"));
+        assert!(same("Ὀδυσσεύς", "ὈΔΥΣΣΕΎΣ"));
+        assert!(same("Caf\u{e9}", "CAFE\u{301}"));
+        assert!(!same("cafe", "café"));
+
+        let contains = |text: &str, part: &str| call(text, "Contains", vec![s(part)]).is_truthy();
+        assert!(contains("caf\u{e9} au lait", "\u{e9} au"));
+        assert!(!contains("cafe\u{301}", "cafe"));
+    }
+}
@@ -1,10 +1,19 @@
+use crate::runtime::deadline;
+use crate::runtime::lock::RwLockExt;
 use crate::runtime::value::Value;
-use chrono::{DateTime, Datelike, Local, TimeZone, Timelike, Utc};
-use std::collections::HashMap;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, Months, NaiveDate, NaiveDateTime, NaiveTime,
+    SecondsFormat, TimeDelta, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use indexmap::IndexMap;
+use std::cmp::Ordering;
 use std::sync::Arc;
 
 pub fn create_time_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Now".to_string(),
@@ -49,27 +58,11 @@ pub fn create_time_module() -> Value {
                     "Time.LocalTime requires 0-1 arguments (optional timestamp)".to_string()
                 );
             }
-
-            let dt: DateTime<Local> = if args.is_empty() {
-                Local::now()
-            } else {
-                let timestamp = match &args[0] {
-                    Value::Number(n) => n
-                        .to_string()
-                        .parse::<i64>()
-                        .map_err(|_| "Invalid timestamp".to_string())?,
-                    _ => {
-                        return Err("Timestamp must be a number".to_string());
-                    }
-                };
-
-                Local
-                    .timestamp_opt(timestamp, 0)
-                    .single()
-                    .ok_or("Invalid timestamp")?
+            let instant = match args.first() {
+                Some(timestamp) => instant_from_timestamp(timestamp)?,
+                None => Utc::now(),
             };
-
-            Ok(create_datetime_map(dt))
+            Ok(datetime_value(instant, Zone::Local))
         }))),
     );
 
@@ -79,61 +72,194 @@ pub fn create_time_module() -> Value {
             if args.len() > 1 {
                 return Err("Time.GMTime requires 0-1 arguments (optional timestamp)".to_string());
             }
+            let instant = match args.first() {
+                Some(timestamp) => instant_from_timestamp(timestamp)?,
+                None => Utc::now(),
+            };
+            Ok(datetime_value(instant, Zone::Utc))
+        }))),
+    );
 
-            let dt: DateTime<Utc> = if args.is_empty() {
-                Utc::now()
-            } else {
-                let timestamp = match &args[0] {
-                    Value::Number(n) => n
-                        .to_string()
-                        .parse::<i64>()
-                        .map_err(|_| "Invalid timestamp".to_string())?,
-                    _ => {
-                        return Err("Timestamp must be a number".to_string());
-                    }
-                };
-
-                Utc.timestamp_opt(timestamp, 0)
-                    .single()
-                    .ok_or("Invalid timestamp")?
+    // Time.Current("Asia/Ulaanbaatar") - now, in the given zone (default Local)
+    methods.insert(
+        "Current".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() > 1 {
+                return Err("Time.Current requires 0-1 arguments (optional zone)".to_string());
+            }
+            let zone = match args.first() {
+                Some(zone) => Zone::parse(&zone.to_display_string())?,
+                None => Zone::Local,
             };
+            Ok(datetime_value(Utc::now(), zone))
+        }))),
+    );
 
-            Ok(create_datetime_map(dt))
+    // Time.Date(2024, 3, 15) or Time.Date(2024, 3, 15, "UTC")
+    methods.insert(
+        "Date".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if !(3..=4).contains(&args.len()) {
+                return Err(
+                    "Time.Date requires 3-4 arguments (year, month, day, optional zone)"
+                        .to_string(),
+                );
+            }
+            build_datetime(&args[..3], args.get(3))
+        }))),
+    );
+
+    // Time.DateTime(2024, 3, 15, 14, 30, 0) with an optional trailing zone
+    methods.insert(
+        "DateTime".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if !(6..=7).contains(&args.len()) {
+                return Err("Time.DateTime requires 6-7 arguments (year, month, day, hour, minute, second, optional zone)".to_string());
+            }
+            build_datetime(&args[..6], args.get(6))
         }))),
     );
 
+    // Time.Parse("2024-03-15T14:30:00Z") or Time.Parse(text, "%d/%m/%Y %H:%M", zone)
+    methods.insert(
+        "Parse".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 3 {
+                return Err(
+                    "Time.Parse requires 1-3 arguments (text, optional pattern, optional zone)"
+                        .to_string(),
+                );
+            }
+            let text = args[0].to_display_string();
+            let pattern = args
+                .get(1)
+                .map(|p| p.to_display_string())
+                .filter(|p| !p.is_empty());
+            let zone = args
+                .get(2)
+                .map(|zone| Zone::parse(&zone.to_display_string()))
+                .transpose()?;
+            parse_datetime(text.trim(), pattern.as_deref(), zone)
+        }))),
+    );
+
+    // Time.Format(datetime) gives ISO 8601; Time.Format(datetime, "%Y-%m-%d %H:%M")
     methods.insert(
         "Format".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Time.Format requires 1-2 arguments (datetime, optional format)".to_string(),
+                );
+            }
+            let (dt, zone) = datetime_from_value(&args[0])?;
+            match args.get(1) {
+                Some(pattern) => {
+                    let pattern = pattern.to_display_string();
+                    let items = strftime_items(&pattern)?;
+                    Ok(Value::String(zone.format(&dt, &items)))
+                }
+                None => Ok(Value::String(
+                    dt.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                )),
+            }
+        }))),
+    );
+
+    // Time.InZone(datetime, "America/New_York") - same instant, other wall clock
+    methods.insert(
+        "InZone".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
             if args.len() != 2 {
-                return Err("Time.Format requires 2 arguments (datetime, format)".to_string());
+                return Err("Time.InZone requires 2 arguments (datetime, zone)".to_string());
             }
+            let (dt, _) = datetime_from_value(&args[0])?;
+            let zone = Zone::parse(&args[1].to_display_string())?;
+            Ok(datetime_value(dt.with_timezone(&Utc), zone))
+        }))),
+    );
 
-            let dt_map = match &args[0] {
-                Value::Map(m) => m,
-                _ => {
-                    return Err("First argument must be a datetime map".to_string());
+    for (name, unit) in [
+        ("AddYears", Unit::Years),
+        ("AddMonths", Unit::Months),
+        ("AddDays", Unit::Days),
+        ("AddHours", Unit::Hours),
+        ("AddMinutes", Unit::Minutes),
+        ("AddSeconds", Unit::Seconds),
+    ] {
+        methods.insert(
+            name.to_string(),
+            Value::NativeFunction(Arc::new(Box::new(move |args| {
+                if args.len() != 2 {
+                    return Err(format!(
+                        "Time.{} requires 2 arguments (datetime, amount)",
+                        name
+                    ));
                 }
-            };
-
-            let format = args[1].to_display_string();
+                add_to_datetime(&args[0], unit, &args[1])
+                    .map_err(|e| format!("Time.{}: {}", name, e))
+            }))),
+        );
+    }
 
-            let map_borrow = dt_map.read().expect("lock poisoned");
+    // Time.Add(datetime, duration) - exact elapsed time
+    methods.insert(
+        "Add".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Time.Add requires 2 arguments (datetime, duration)".to_string());
+            }
+            let (dt, zone) = datetime_from_value(&args[0])?;
+            let duration = duration_from_value(&args[1])?;
+            let shifted = dt
+                .checked_add_signed(duration)
+                .ok_or("Time.Add: result is out of range")?;
+            Ok(datetime_value(shifted.with_timezone(&Utc), zone))
+        }))),
+    );
 
-            let year = get_number_field(&map_borrow, "Year")? as i32;
-            let month = get_number_field(&map_borrow, "Month")? as u32;
-            let day = get_number_field(&map_borrow, "Day")? as u32;
-            let hour = get_number_field(&map_borrow, "Hour")? as u32;
-            let minute = get_number_field(&map_borrow, "Minute")? as u32;
-            let second = get_number_field(&map_borrow, "Second")? as u32;
+    // Time.Diff(from, to) -> Duration from `from` until `to`
+    methods.insert(
+        "Diff".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Time.Diff requires 2 arguments (from, to)".to_string());
+            }
+            let (from, _) = datetime_from_value(&args[0])?;
+            let (to, _) = datetime_from_value(&args[1])?;
+            Ok(duration_value(to.signed_duration_since(from)))
+        }))),
+    );
 
-            let dt = Local
-                .with_ymd_and_hms(year, month, day, hour, minute, second)
-                .single()
-                .ok_or("Invalid datetime components")?;
+    // Time.Compare(a, b) -> -1, 0 or 1
+    methods.insert(
+        "Compare".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Time.Compare requires 2 arguments (a, b)".to_string());
+            }
+            let (a, _) = datetime_from_value(&args[0])?;
+            let (b, _) = datetime_from_value(&args[1])?;
+            let order = match a.cmp(&b) {
+                Ordering::Less => -1,
+                Ordering::Equal => 0,
+                Ordering::Greater => 1,
+            };
+            Ok(Value::Number(BigDecimal::from(order)))
+        }))),
+    );
 
-            let formatted = dt.format(&format).to_string();
-            Ok(Value::String(formatted))
+    // Time.Duration(90) or Time.Duration({ Hours: 1, Minutes: 30 })
+    methods.insert(
+        "Duration".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err(
+                    "Time.Duration requires 1 argument (seconds or Map of Days/Hours/Minutes/Seconds/Milliseconds)"
+                        .to_string(),
+                );
+            }
+            duration_from_value(&args[0]).map(duration_value)
         }))),
     );
 
@@ -149,6 +275,7 @@ pub fn create_time_module() -> Value {
                     .to_string()
                     .parse::<f64>()
                     .map_err(|_| "Invalid sleep duration".to_string())?,
+                Value::Integer(i) => i.to_f64().ok_or("Invalid sleep duration")?,
                 Value::FastNumber(f) => *f,
                 _ => {
                     return Err("Sleep duration must be a number".to_string());
@@ -160,7 +287,9 @@ pub fn create_time_module() -> Value {
             }
 
             let millis = (seconds * 1000.0) as u64;
-            std::thread::sleep(std::time::Duration::from_millis(millis));
+            let duration = std::time::Duration::from_millis(millis);
+            std::thread::sleep(deadline::limit(Some(duration)).unwrap_or(duration));
+            deadline::check()?;
 
             Ok(Value::Boolean(true))
         }))),
@@ -169,64 +298,478 @@ pub fn create_time_module() -> Value {
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
 
-fn create_datetime_map<Tz: TimeZone>(dt: DateTime<Tz>) -> Value
-where
-    Tz::Offset: std::fmt::Display,
-{
-    let mut dt_map = HashMap::new();
+/// Where a DateTime's wall clock is read: UTC, the machine's local zone, a
+/// fixed offset like "+08:00", or an IANA zone like "Europe/Berlin".
+#[derive(Debug, Clone, Copy)]
+enum Zone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
 
-    use bigdecimal::BigDecimal;
+impl Zone {
+    fn parse(name: &str) -> Result<Zone, String> {
+        let trimmed = name.trim();
+        match trimmed.to_ascii_uppercase().as_str() {
+            "UTC" | "GMT" | "Z" => return Ok(Zone::Utc),
+            "LOCAL" => return Ok(Zone::Local),
+            _ => {}
+        }
+        if trimmed.starts_with(['+', '-']) {
+            return parse_offset(trimmed)
+                .map(Zone::Fixed)
+                .ok_or_else(|| format!("Invalid UTC offset '{}'", trimmed));
+        }
+        Tz::from_str_insensitive(trimmed)
+            .map(Zone::Named)
+            .map_err(|_| format!("Unknown time zone '{}'", trimmed))
+    }
 
+    fn name(&self) -> String {
+        match self {
+            Zone::Utc => "UTC".to_string(),
+            Zone::Local => "Local".to_string(),
+            Zone::Fixed(offset) => offset.to_string(),
+            Zone::Named(tz) => tz.name().to_string(),
+        }
+    }
+
+    fn at(&self, instant: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Utc => instant.fixed_offset(),
+            Zone::Local => instant.with_timezone(&Local).fixed_offset(),
+            Zone::Fixed(offset) => instant.with_timezone(offset),
+            Zone::Named(tz) => instant.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    // Rendered in the zone itself so %Z gives "UTC" or "CET" rather than
+    // a bare offset
+    fn format(&self, dt: &DateTime<FixedOffset>, items: &[Item]) -> String {
+        match self {
+            Zone::Utc => dt
+                .with_timezone(&Utc)
+                .format_with_items(items.iter())
+                .to_string(),
+            Zone::Named(tz) => dt
+                .with_timezone(tz)
+                .format_with_items(items.iter())
+                .to_string(),
+            Zone::Local | Zone::Fixed(_) => dt.format_with_items(items.iter()).to_string(),
+        }
+    }
+
+    // Wall-clock time in this zone; in a DST fold the earlier reading wins,
+    // and times skipped by a DST gap are an error
+    fn resolve(&self, local: NaiveDateTime) -> Result<DateTime<FixedOffset>, String> {
+        let resolved = match self {
+            Zone::Utc => Some(Utc.from_utc_datetime(&local).fixed_offset()),
+            Zone::Local => Local
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| dt.fixed_offset()),
+            Zone::Fixed(offset) => offset.from_local_datetime(&local).single(),
+            Zone::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| dt.fixed_offset()),
+        };
+        resolved.ok_or_else(|| format!("{} does not exist in zone {}", local, self.name()))
+    }
+}
+
+// "+08:00", "+0800" or "+08"
+fn parse_offset(text: &str) -> Option<FixedOffset> {
+    let sign = if text.starts_with('-') { -1 } else { 1 };
+    let digits: String = text[1..].chars().filter(|c| *c != ':').collect();
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i32>().ok()?,
+            digits[2..].parse::<i32>().ok()?,
+        ),
+        _ => return None,
+    };
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    Years,
+    Months,
+    Days,
+    Hours,
+    Minutes,
+    Seconds,
+}
+
+/// A DateTime as a Map: the calendar fields plus Timestamp, Offset (seconds
+/// east of UTC) and Zone. Functions read the calendar fields back, so editing
+/// them moves the date.
+fn datetime_value(instant: DateTime<Utc>, zone: Zone) -> Value {
+    let dt = zone.at(instant);
+    let mut dt_map = IndexMap::new();
+    let number = |n: i64| Value::Number(BigDecimal::from(n));
+
+    dt_map.insert("Year".to_string(), number(dt.year() as i64));
+    dt_map.insert("Month".to_string(), number(dt.month() as i64));
+    dt_map.insert("Day".to_string(), number(dt.day() as i64));
+    dt_map.insert("Hour".to_string(), number(dt.hour() as i64));
+    dt_map.insert("Minute".to_string(), number(dt.minute() as i64));
+    dt_map.insert("Second".to_string(), number(dt.second() as i64));
     dt_map.insert(
-        "Year".to_string(),
-        Value::Number(BigDecimal::from(dt.year())),
-    );
-    dt_map.insert(
-        "Month".to_string(),
-        Value::Number(BigDecimal::from(dt.month() as i32)),
-    );
-    dt_map.insert(
-        "Day".to_string(),
-        Value::Number(BigDecimal::from(dt.day() as i32)),
-    );
-    dt_map.insert(
-        "Hour".to_string(),
-        Value::Number(BigDecimal::from(dt.hour() as i32)),
+        "Millisecond".to_string(),
+        number((dt.nanosecond() / 1_000_000).min(999) as i64),
     );
     dt_map.insert(
-        "Minute".to_string(),
-        Value::Number(BigDecimal::from(dt.minute() as i32)),
+        "Weekday".to_string(),
+        number(dt.weekday().number_from_monday() as i64),
     );
+    dt_map.insert("YearDay".to_string(), number(dt.ordinal() as i64));
+    dt_map.insert("Timestamp".to_string(), number(dt.timestamp()));
     dt_map.insert(
-        "Second".to_string(),
-        Value::Number(BigDecimal::from(dt.second() as i32)),
+        "Offset".to_string(),
+        number(dt.offset().local_minus_utc() as i64),
     );
+    dt_map.insert("Zone".to_string(), Value::String(zone.name()));
 
-    let weekday = dt.weekday().number_from_monday();
-    dt_map.insert(
-        "Weekday".to_string(),
-        Value::Number(BigDecimal::from(weekday as i32)),
-    );
+    Value::Map(Arc::new(std::sync::RwLock::new(dt_map)))
+}
 
-    dt_map.insert(
-        "YearDay".to_string(),
-        Value::Number(BigDecimal::from(dt.ordinal() as i32)),
+/// Read a DateTime Map (or a bare Unix timestamp, taken as local time) back
+/// into a chrono value and the zone it should stay in.
+fn datetime_from_value(value: &Value) -> Result<(DateTime<FixedOffset>, Zone), String> {
+    let map = match value {
+        Value::Map(map) => map,
+        Value::Number(_) | Value::Integer(_) | Value::FastNumber(_) => {
+            let instant = instant_from_timestamp(value)?;
+            return Ok((Zone::Local.at(instant), Zone::Local));
+        }
+        other => {
+            return Err(format!(
+                "Expected a DateTime or timestamp, got {}",
+                other.type_name()
+            ));
+        }
+    };
+    let map = map.read_recover();
+
+    let zone = match (map.get("Zone"), map.get("Offset")) {
+        (Some(zone), _) => Zone::parse(&zone.to_display_string())?,
+        (None, Some(offset)) => {
+            let seconds = number_to_i64(offset).ok_or("Offset must be a whole number")?;
+            Zone::Fixed(FixedOffset::east_opt(seconds as i32).ok_or("Offset is out of range")?)
+        }
+        (None, None) => Zone::Local,
+    };
+
+    if map.contains_key("Year") {
+        let year = get_number_field(&map, "Year")?;
+        let field = |name: &str, default: i64| match map.get(name) {
+            Some(_) => get_number_field(&map, name),
+            None => Ok(default),
+        };
+        let month = field("Month", 1)?;
+        let day = field("Day", 1)?;
+        let date = NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)
+            .ok_or_else(|| format!("Invalid date {}-{}-{}", year, month, day))?;
+        let (hour, minute, second) = (field("Hour", 0)?, field("Minute", 0)?, field("Second", 0)?);
+        let millisecond = field("Millisecond", 0)?;
+        let time = NaiveTime::from_hms_milli_opt(
+            hour as u32,
+            minute as u32,
+            second as u32,
+            millisecond as u32,
+        )
+        .ok_or_else(|| format!("Invalid time {}:{}:{}", hour, minute, second))?;
+        return Ok((zone.resolve(date.and_time(time))?, zone));
+    }
+
+    let timestamp = map
+        .get("Timestamp")
+        .ok_or("DateTime needs Year/Month/Day fields or a Timestamp")?;
+    Ok((zone.at(instant_from_timestamp(timestamp)?), zone))
+}
+
+/// The local date and time a DateTime Map (or timestamp) stands for, for
+/// other modules that format dates
+pub(crate) fn local_datetime(value: &Value) -> Result<DateTime<FixedOffset>, String> {
+    datetime_from_value(value).map(|(datetime, _)| datetime)
+}
+
+fn instant_from_timestamp(value: &Value) -> Result<DateTime<Utc>, String> {
+    let seconds = match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+    .ok_or("Timestamp must be a number")?;
+    let millis = (seconds * 1000.0).round() as i64;
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| "Invalid timestamp".to_string())
+}
+
+fn build_datetime(parts: &[Value], zone: Option<&Value>) -> Result<Value, String> {
+    let mut numbers = Vec::with_capacity(parts.len());
+    for part in parts {
+        numbers.push(number_to_i64(part).ok_or_else(|| {
+            format!(
+                "Date and time parts must be whole numbers, got {}",
+                part.to_display_string()
+            )
+        })?);
+    }
+    let zone = match zone {
+        Some(zone) => Zone::parse(&zone.to_display_string())?,
+        None => Zone::Local,
+    };
+    let date = NaiveDate::from_ymd_opt(numbers[0] as i32, numbers[1] as u32, numbers[2] as u32)
+        .ok_or_else(|| format!("Invalid date {}-{}-{}", numbers[0], numbers[1], numbers[2]))?;
+    let (hour, minute, second) = match numbers[..] {
+        [_, _, _, hour, minute, second] => (hour, minute, second),
+        _ => (0, 0, 0),
+    };
+    let time = NaiveTime::from_hms_opt(hour as u32, minute as u32, second as u32)
+        .ok_or_else(|| format!("Invalid time {}:{}:{}", hour, minute, second))?;
+    let dt = zone.resolve(date.and_time(time))?;
+    Ok(datetime_value(dt.with_timezone(&Utc), zone))
+}
+
+// Without a pattern: RFC 3339 / ISO 8601, RFC 2822, then the common
+// "2024-03-15 14:30" and date-only forms
+const ISO_LOCAL_PATTERNS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+fn parse_datetime(text: &str, pattern: Option<&str>, zone: Option<Zone>) -> Result<Value, String> {
+    let with_offset = match pattern {
+        Some(pattern) => {
+            strftime_items(pattern)?;
+            DateTime::parse_from_str(text, pattern).ok()
+        }
+        None => DateTime::parse_from_rfc3339(text)
+            .or_else(|_| DateTime::parse_from_rfc2822(text))
+            .ok(),
+    };
+    if let Some(dt) = with_offset {
+        // Keep the offset written in the text unless a zone was asked for
+        let zone = zone.unwrap_or_else(|| {
+            if dt.offset().local_minus_utc() == 0 && text.ends_with(['Z', 'z']) {
+                Zone::Utc
+            } else {
+                Zone::Fixed(*dt.offset())
+            }
+        });
+        return Ok(datetime_value(dt.with_timezone(&Utc), zone));
+    }
+
+    let local = match pattern {
+        Some(pattern) => NaiveDateTime::parse_from_str(text, pattern)
+            .or_else(|_| NaiveDate::parse_from_str(text, pattern).map(at_midnight))
+            .map_err(|e| {
+                format!(
+                    "Time.Parse: cannot read '{}' with pattern '{}': {}",
+                    text, pattern, e
+                )
+            })?,
+        None => ISO_LOCAL_PATTERNS
+            .iter()
+            .find_map(|pattern| NaiveDateTime::parse_from_str(text, pattern).ok())
+            .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().map(at_midnight))
+            .ok_or_else(|| {
+                format!(
+                    "Time.Parse: '{}' is not an ISO 8601 or RFC 2822 date (pass a pattern like \"%d/%m/%Y\")",
+                    text
+                )
+            })?,
+    };
+    let zone = zone.unwrap_or(Zone::Local);
+    let dt = zone.resolve(local)?;
+    Ok(datetime_value(dt.with_timezone(&Utc), zone))
+}
+
+fn at_midnight(date: NaiveDate) -> NaiveDateTime {
+    date.and_time(NaiveTime::MIN)
+}
+
+// chrono panics when asked to render an invalid pattern, so check it first
+fn strftime_items(pattern: &str) -> Result<Vec<Item<'_>>, String> {
+    let items: Vec<Item> = StrftimeItems::new(pattern).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid time format pattern '{}'", pattern));
+    }
+    Ok(items)
+}
+
+fn add_to_datetime(value: &Value, unit: Unit, amount: &Value) -> Result<Value, String> {
+    let (dt, zone) = datetime_from_value(value)?;
+    let out_of_range = || "result is out of range".to_string();
+
+    let shifted = match unit {
+        // Calendar units keep the wall-clock time (and clamp to the end of
+        // shorter months), even across daylight saving changes
+        Unit::Years | Unit::Months | Unit::Days => {
+            let count = number_to_i64(amount).ok_or("amount must be a whole number")?;
+            let local = dt.naive_local();
+            let moved = match unit {
+                Unit::Days => {
+                    local.checked_add_signed(TimeDelta::try_days(count).ok_or_else(out_of_range)?)
+                }
+                _ => {
+                    let months = if matches!(unit, Unit::Years) {
+                        count * 12
+                    } else {
+                        count
+                    };
+                    let magnitude = Months::new(
+                        u32::try_from(months.unsigned_abs()).map_err(|_| out_of_range())?,
+                    );
+                    if months >= 0 {
+                        local.checked_add_months(magnitude)
+                    } else {
+                        local.checked_sub_months(magnitude)
+                    }
+                }
+            }
+            .ok_or_else(out_of_range)?;
+            zone.resolve(moved)?
+        }
+        Unit::Hours | Unit::Minutes | Unit::Seconds => {
+            let count = number_to_f64(amount).ok_or("amount must be a number")?;
+            let scale = match unit {
+                Unit::Hours => 3_600_000.0,
+                Unit::Minutes => 60_000.0,
+                _ => 1_000.0,
+            };
+            let delta = TimeDelta::try_milliseconds((count * scale).round() as i64)
+                .ok_or_else(out_of_range)?;
+            dt.checked_add_signed(delta).ok_or_else(out_of_range)?
+        }
+    };
+    Ok(datetime_value(shifted.with_timezone(&Utc), zone))
+}
+
+/// A Duration as a Map: TotalSeconds (may be fractional or negative) and its
+/// Days/Hours/Minutes/Seconds/Milliseconds breakdown, plus readable Text.
+fn duration_value(duration: TimeDelta) -> Value {
+    let total_millis = duration.num_milliseconds();
+    let sign = if total_millis < 0 { "-" } else { "" };
+    let millis = total_millis.unsigned_abs();
+    let (days, hours, minutes, seconds, milliseconds) = (
+        millis / 86_400_000,
+        millis / 3_600_000 % 24,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
     );
 
-    dt_map.insert(
-        "Timestamp".to_string(),
-        Value::Number(BigDecimal::from(dt.timestamp())),
+    let mut text = Vec::new();
+    for (amount, suffix) in [(days, "d"), (hours, "h"), (minutes, "m")] {
+        if amount > 0 {
+            text.push(format!("{}{}", amount, suffix));
+        }
+    }
+    if seconds > 0 || milliseconds > 0 || text.is_empty() {
+        if milliseconds > 0 {
+            text.push(format!("{}.{:03}s", seconds, milliseconds));
+        } else {
+            text.push(format!("{}s", seconds));
+        }
+    }
+
+    let signed = |n: u64| {
+        let n = n as i64;
+        Value::Number(BigDecimal::from(if total_millis < 0 { -n } else { n }))
+    };
+    let mut map = IndexMap::new();
+    map.insert(
+        "TotalSeconds".to_string(),
+        Value::Number(BigDecimal::from(total_millis) / BigDecimal::from(1000)),
+    );
+    map.insert("Days".to_string(), signed(days));
+    map.insert("Hours".to_string(), signed(hours));
+    map.insert("Minutes".to_string(), signed(minutes));
+    map.insert("Seconds".to_string(), signed(seconds));
+    map.insert("Milliseconds".to_string(), signed(milliseconds));
+    map.insert(
+        "Text".to_string(),
+        Value::String(format!("{}{}", sign, text.join(" "))),
     );
+    Value::Map(Arc::new(std::sync::RwLock::new(map)))
+}
 
-    Value::Map(Arc::new(std::sync::RwLock::new(dt_map)))
+/// Seconds, a Duration Map from `duration_value`, or a Map of any of
+/// Days/Hours/Minutes/Seconds/Milliseconds.
+fn duration_from_value(value: &Value) -> Result<TimeDelta, String> {
+    let seconds = match value {
+        Value::Number(_) | Value::Integer(_) | Value::FastNumber(_) => {
+            number_to_f64(value).ok_or("Duration must be a number of seconds")?
+        }
+        Value::Map(map) => {
+            let map = map.read_recover();
+            if let Some(total) = map.get("TotalSeconds") {
+                number_to_f64(total).ok_or("TotalSeconds must be a number")?
+            } else {
+                let mut seconds = 0.0;
+                for (field, scale) in [
+                    ("Days", 86_400.0),
+                    ("Hours", 3_600.0),
+                    ("Minutes", 60.0),
+                    ("Seconds", 1.0),
+                    ("Milliseconds", 0.001),
+                ] {
+                    if let Some(amount) = map.get(field) {
+                        seconds += number_to_f64(amount)
+                            .ok_or_else(|| format!("Duration {} must be a number", field))?
+                            * scale;
+                    }
+                }
+                seconds
+            }
+        }
+        other => {
+            return Err(format!(
+                "Expected a Duration or number of seconds, got {}",
+                other.type_name()
+            ));
+        }
+    };
+    TimeDelta::try_milliseconds((seconds * 1000.0).round() as i64)
+        .ok_or_else(|| "Duration is out of range".to_string())
+}
+
+fn number_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn number_to_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) if n.is_integer() => n.to_i64(),
+        Value::Integer(i) => i.to_i64(),
+        Value::FastNumber(f) if f.fract() == 0.0 => Some(*f as i64),
+        _ => None,
+    }
 }
 
-fn get_number_field(map: &HashMap<String, Value>, field: &str) -> Result<i64, String> {
+fn get_number_field(map: &IndexMap<String, Value>, field: &str) -> Result<i64, String> {
     match map.get(field) {
-        Some(Value::Number(n)) => n
-            .to_string()
-            .parse::<i64>()
-            .map_err(|_| format!("Invalid {} value", field)),
+        Some(value @ (Value::Number(_) | Value::Integer(_) | Value::FastNumber(_))) => {
+            number_to_i64(value).ok_or_else(|| format!("Invalid {} value", field))
+        }
         Some(_) => Err(format!("{} must be a number", field)),
         None => Err(format!("Missing {} field", field)),
     }
@@ -1,5 +1,5 @@
 use crate::runtime::value::Value;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::sync::Arc;
 use toml::{Table, Value as TomlValue};
 
@@ -19,7 +19,7 @@ pub fn convert_toml_to_object(toml: TomlValue) -> Value {
             Value::List(Arc::new(std::sync::RwLock::new(list)))
         }
         TomlValue::Table(table) => {
-            let mut map = HashMap::new();
+            let mut map = IndexMap::new();
             for (k, v) in table {
                 map.insert(k, convert_toml_to_object(v));
             }
@@ -29,7 +29,7 @@ pub fn convert_toml_to_object(toml: TomlValue) -> Value {
 }
 
 pub fn create_toml_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Parse".to_string(),
@@ -1,34 +1,74 @@
+use crate::runtime::deadline;
+use crate::runtime::lock::{MutexExt, RwLockExt};
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
-use std::collections::HashMap;
-use std::net::UdpSocket;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub fn create_udp_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
-    // UDP.Bind("127.0.0.1:8080")
+    // UDP.Bind("0.0.0.0:5353", { Broadcast: True, Timeout: 2 })
     methods.insert(
         "Bind".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
-            if args.len() != 1 {
-                return Err("UDP.Bind requires 1 argument (address:port)".to_string());
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "UDP.Bind requires 1-2 arguments (address:port, optional options)".to_string(),
+                );
             }
 
             let addr = args[0].to_display_string();
+            permissions::check_net(&addr)?;
 
-            match UdpSocket::bind(&addr) {
-                Ok(socket) => Ok(create_udp_socket_object(socket)),
-                Err(e) => Err(format!("UDP bind failed: {}", e)),
+            let socket = match UdpSocket::bind(&addr) {
+                Ok(socket) => Socket {
+                    socket,
+                    timeout: None,
+                },
+                Err(e) => return Err(format!("UDP bind failed: {}", e)),
+            };
+            let socket = Arc::new(Mutex::new(socket));
+            if let Some(options) = args.get(1) {
+                let Value::Map(options) = options else {
+                    return Err(format!(
+                        "UDP.Bind options must be a Map, not {}",
+                        options.type_name()
+                    ));
+                };
+                let mut guard = socket.lock_recover();
+                for (key, value) in options.read_recover().iter() {
+                    configure(&mut guard, key, value)?;
+                }
             }
+            Ok(create_udp_socket_object(socket))
         }))),
     );
 
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
 
-fn create_udp_socket_object(socket: UdpSocket) -> Value {
-    let socket_arc = Arc::new(Mutex::new(socket));
-    let mut methods = HashMap::new();
+/// A bound socket and the read timeout set on it, which a request
+/// handler's deadline can shorten for a single receive.
+struct Socket {
+    socket: UdpSocket,
+    timeout: Option<Duration>,
+}
+
+type SharedSocket = Arc<Mutex<Socket>>;
+
+fn create_udp_socket_object(socket_arc: SharedSocket) -> Value {
+    let mut methods = IndexMap::new();
+
+    let local = socket_arc.lock_recover().socket.local_addr();
+    methods.insert(
+        "LocalAddress".to_string(),
+        Value::String(local.map(|addr| addr.to_string()).unwrap_or_default()),
+    );
 
     // Socket.SendTo("data", "127.0.0.1:8081")
     let socket_send = socket_arc.clone();
@@ -39,11 +79,12 @@ fn create_udp_socket_object(socket: UdpSocket) -> Value {
                 return Err("Socket.SendTo requires 2 arguments (data, target_address)".to_string());
             }
 
-            let data = args[0].to_display_string();
+            let data = args[0].as_bytes();
             let target = args[1].to_display_string();
+            permissions::check_net(&target)?;
 
-            let socket_guard = socket_send.lock().unwrap();
-            match socket_guard.send_to(data.as_bytes(), &target) {
+            let socket_guard = socket_send.lock_recover();
+            match socket_guard.socket.send_to(&data, &target) {
                 Ok(bytes_sent) => Ok(Value::from_number_string(&bytes_sent.to_string())
                     .unwrap_or(Value::default_number())),
                 Err(e) => Err(format!("Failed to send data: {}", e)),
@@ -56,37 +97,21 @@ fn create_udp_socket_object(socket: UdpSocket) -> Value {
     methods.insert(
         "ReceiveFrom".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
-            let buffer_size = if args.is_empty() {
-                1024
-            } else {
-                match &args[0] {
-                    Value::Number(n) => {
-                        use bigdecimal::ToPrimitive;
-                        n.to_usize().unwrap_or(1024)
-                    }
-                    _ => 1024,
-                }
-            };
-
-            let socket_guard = socket_recv.lock().unwrap();
-            let mut buffer = vec![0u8; buffer_size];
-
-            match socket_guard.recv_from(&mut buffer) {
-                Ok((n, from_addr)) => {
-                    buffer.truncate(n);
-                    let data_str = match String::from_utf8(buffer) {
-                        Ok(s) => s,
-                        Err(_) => return Err("Received non-UTF8 data".to_string()),
-                    };
-
-                    let mut result = HashMap::new();
-                    result.insert("Data".to_string(), Value::String(data_str));
-                    result.insert("From".to_string(), Value::String(from_addr.to_string()));
+            let (buffer, from_addr) = receive_from(&socket_recv, buffer_size(&args))?;
+            let data_str = String::from_utf8(buffer).map_err(|_| {
+                "Received non-UTF8 data (use ReceiveFromBytes for binary data)".to_string()
+            })?;
+            Ok(datagram(Value::String(data_str), from_addr))
+        }))),
+    );
 
-                    Ok(Value::Map(Arc::new(std::sync::RwLock::new(result))))
-                }
-                Err(e) => Err(format!("Failed to receive data: {}", e)),
-            }
+    // Socket.ReceiveFromBytes(buffer_size) -> returns Map { Data: Bytes, From: "..." }
+    let socket_recv_bytes = socket_arc.clone();
+    methods.insert(
+        "ReceiveFromBytes".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let (buffer, from_addr) = receive_from(&socket_recv_bytes, buffer_size(&args))?;
+            Ok(datagram(Value::Bytes(buffer.into()), from_addr))
         }))),
     );
 
@@ -100,9 +125,10 @@ fn create_udp_socket_object(socket: UdpSocket) -> Value {
             }
 
             let addr = args[0].to_display_string();
-            let socket_guard = socket_connect.lock().unwrap();
+            permissions::check_net(&addr)?;
+            let socket_guard = socket_connect.lock_recover();
 
-            match socket_guard.connect(&addr) {
+            match socket_guard.socket.connect(&addr) {
                 Ok(_) => Ok(Value::Boolean(true)),
                 Err(e) => Err(format!("Failed to connect: {}", e)),
             }
@@ -118,10 +144,10 @@ fn create_udp_socket_object(socket: UdpSocket) -> Value {
                 return Err("Socket.Send requires 1 argument (data)".to_string());
             }
 
-            let data = args[0].to_display_string();
-            let socket_guard = socket_send_connected.lock().unwrap();
+            let data = args[0].as_bytes();
+            let socket_guard = socket_send_connected.lock_recover();
 
-            match socket_guard.send(data.as_bytes()) {
+            match socket_guard.socket.send(&data) {
                 Ok(bytes_sent) => Ok(Value::from_number_string(&bytes_sent.to_string())
                     .unwrap_or(Value::default_number())),
                 Err(e) => Err(format!("Failed to send data: {}", e)),
@@ -134,33 +160,375 @@ fn create_udp_socket_object(socket: UdpSocket) -> Value {
     methods.insert(
         "Receive".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
-            let buffer_size = if args.is_empty() {
-                1024
-            } else {
-                match &args[0] {
-                    Value::Number(n) => {
-                        use bigdecimal::ToPrimitive;
-                        n.to_usize().unwrap_or(1024)
-                    }
-                    _ => 1024,
+            let buffer = receive(&socket_recv_connected, &args)?;
+            match String::from_utf8(buffer) {
+                Ok(s) => Ok(Value::String(s)),
+                Err(_) => {
+                    Err("Received non-UTF8 data (use ReceiveBytes for binary data)".to_string())
                 }
-            };
+            }
+        }))),
+    );
 
-            let socket_guard = socket_recv_connected.lock().unwrap();
-            let mut buffer = vec![0u8; buffer_size];
+    // Socket.ReceiveBytes(buffer_size) - binary datagram from connected address
+    let socket_recv_connected_bytes = socket_arc.clone();
+    methods.insert(
+        "ReceiveBytes".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let buffer = receive(&socket_recv_connected_bytes, &args)?;
+            Ok(Value::Bytes(buffer.into()))
+        }))),
+    );
 
-            match socket_guard.recv(&mut buffer) {
-                Ok(n) => {
-                    buffer.truncate(n);
-                    match String::from_utf8(buffer) {
-                        Ok(s) => Ok(Value::String(s)),
-                        Err(_) => Err("Received non-UTF8 data".to_string()),
-                    }
-                }
-                Err(e) => Err(format!("Failed to receive data: {}", e)),
+    // Socket.SetBroadcast(True) - allows sending to broadcast addresses
+    let socket_broadcast = socket_arc.clone();
+    methods.insert(
+        "SetBroadcast".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Socket.SetBroadcast requires 1 argument (True or False)".to_string());
+            }
+            configure(&mut socket_broadcast.lock_recover(), "Broadcast", &args[0])?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Socket.SetTimeout(2.5) - receives fail after waiting this many seconds
+    let socket_timeout = socket_arc.clone();
+    methods.insert(
+        "SetTimeout".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err(
+                    "Socket.SetTimeout requires 1 argument (seconds, or False to wait forever)"
+                        .to_string(),
+                );
             }
+            configure(&mut socket_timeout.lock_recover(), "Timeout", &args[0])?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Socket.JoinMulticast("239.255.0.1", "192.168.1.10")
+    let socket_join = socket_arc.clone();
+    methods.insert(
+        "JoinMulticast".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            multicast(&socket_join, &args, true)?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Socket.LeaveMulticast("239.255.0.1")
+    let socket_leave = socket_arc.clone();
+    methods.insert(
+        "LeaveMulticast".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            multicast(&socket_leave, &args, false)?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Socket.Packets({ Size: 2048, Bytes: True }) -> Stream of { Data, From }
+    let socket_packets = socket_arc.clone();
+    methods.insert(
+        "Packets".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            let (size, bytes) = packet_options(args.first())?;
+            let socket = socket_packets.clone();
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                let (buffer, from_addr) = match receive_from(&socket, size) {
+                    Ok(packet) => packet,
+                    // A quiet socket ends the Stream once its Timeout has passed
+                    Err(Received::TimedOut) => return Ok(Value::Option(Box::new(None))),
+                    Err(Received::Failed(e)) => return Err(e),
+                };
+                let data = if bytes {
+                    Value::Bytes(buffer.into())
+                } else {
+                    Value::String(String::from_utf8(buffer).map_err(|_| {
+                        "Received non-UTF8 data (use Packets({ Bytes: True }) for binary data)"
+                            .to_string()
+                    })?)
+                };
+                Ok(Value::Option(Box::new(Some(datagram(data, from_addr)))))
+            })));
+            Ok(crate::stdlib::stream::create_stream_object(
+                vec![],
+                Some(generator),
+            ))
         }))),
     );
 
     Value::Map(Arc::new(std::sync::RwLock::new(methods)))
 }
+
+fn buffer_size(args: &[Value]) -> usize {
+    match args.first() {
+        Some(Value::Number(n)) => n.to_usize().unwrap_or(1024),
+        Some(Value::Integer(i)) => i.to_usize().unwrap_or(1024),
+        _ => 1024,
+    }
+}
+
+/// Set one of the options UDP.Bind takes.
+fn configure(socket: &mut Socket, key: &str, value: &Value) -> Result<(), String> {
+    let applied = match key {
+        "Broadcast" => socket.socket.set_broadcast(value.is_truthy()),
+        "Timeout" => {
+            socket.timeout = match value {
+                Value::Boolean(false) => None,
+                _ => Some(seconds(value, key)?).filter(|timeout| !timeout.is_zero()),
+            };
+            Ok(())
+        }
+        "MulticastTtl" => {
+            let ttl = match value {
+                Value::Number(n) => n.to_u32(),
+                Value::Integer(i) => i.to_u32(),
+                _ => None,
+            }
+            .ok_or_else(|| format!("UDP MulticastTtl must be a whole number, not {}", value))?;
+            socket.socket.set_multicast_ttl_v4(ttl)
+        }
+        "MulticastLoop" => match socket.socket.local_addr() {
+            Ok(SocketAddr::V6(_)) => socket.socket.set_multicast_loop_v6(value.is_truthy()),
+            _ => socket.socket.set_multicast_loop_v4(value.is_truthy()),
+        },
+        _ => return Err(format!("UDP.Bind has no option {}", key)),
+    };
+    applied.map_err(|e| format!("Failed to set {}: {}", key, e))
+}
+
+fn seconds(value: &Value, name: &str) -> Result<Duration, String> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) => Some(*f),
+        _ => None,
+    }
+    .filter(|s| s.is_finite() && *s >= 0.0)
+    .map(Duration::from_secs_f64)
+    .ok_or_else(|| format!("UDP {} must be a number, not {}", name, value.type_name()))
+}
+
+// Socket.JoinMulticast(group, interface?) and LeaveMulticast. The interface
+// is an IPv4 address for an IPv4 group, or an interface number for IPv6.
+fn multicast(socket: &Mutex<Socket>, args: &[Value], join: bool) -> Result<(), String> {
+    let name = if join {
+        "JoinMulticast"
+    } else {
+        "LeaveMulticast"
+    };
+    if args.is_empty() || args.len() > 2 {
+        return Err(format!(
+            "Socket.{} requires 1-2 arguments (group, optional interface)",
+            name
+        ));
+    }
+    let group: IpAddr = args[0]
+        .to_display_string()
+        .parse()
+        .map_err(|_| format!("Socket.{}: '{}' is not an IP address", name, args[0]))?;
+    if join {
+        permissions::check_host(&group.to_string(), None)?;
+    }
+    let guard = socket.lock_recover();
+    let result = match group {
+        IpAddr::V4(group) => {
+            let interface = match args.get(1) {
+                Some(interface) => interface.to_display_string().parse().map_err(|_| {
+                    format!(
+                        "Socket.{}: interface '{}' is not an IPv4 address",
+                        name, interface
+                    )
+                })?,
+                None => Ipv4Addr::UNSPECIFIED,
+            };
+            if join {
+                guard.socket.join_multicast_v4(&group, &interface)
+            } else {
+                guard.socket.leave_multicast_v4(&group, &interface)
+            }
+        }
+        IpAddr::V6(group) => {
+            let interface = match args.get(1) {
+                Some(interface) => interface.to_display_string().parse().map_err(|_| {
+                    format!(
+                        "Socket.{}: interface '{}' is not an interface number",
+                        name, interface
+                    )
+                })?,
+                None => 0,
+            };
+            if join {
+                guard.socket.join_multicast_v6(&group, interface)
+            } else {
+                guard.socket.leave_multicast_v6(&group, interface)
+            }
+        }
+    };
+    result.map_err(|e| format!("Socket.{} {} failed: {}", name, group, e))
+}
+
+fn packet_options(options: Option<&Value>) -> Result<(usize, bool), String> {
+    let mut size = 65536;
+    let mut bytes = false;
+    match options {
+        None => {}
+        Some(Value::Map(map)) => {
+            for (key, value) in map.read_recover().iter() {
+                match key.as_str() {
+                    "Size" => size = buffer_size(std::slice::from_ref(value)),
+                    "Bytes" => bytes = value.is_truthy(),
+                    _ => return Err(format!("Socket.Packets has no option {}", key)),
+                }
+            }
+        }
+        Some(other) => {
+            return Err(format!(
+                "Socket.Packets options must be a Map, not {}",
+                other.type_name()
+            ));
+        }
+    }
+    Ok((size, bytes))
+}
+
+enum Received {
+    TimedOut,
+    Failed(String),
+}
+
+impl From<Received> for String {
+    fn from(received: Received) -> String {
+        match received {
+            Received::TimedOut => "Receive timed out".to_string(),
+            Received::Failed(e) => e,
+        }
+    }
+}
+
+// Waits no longer than the socket's Timeout, or a request handler's deadline
+fn wait_for<T>(
+    socket: &Socket,
+    receive: impl FnOnce(&UdpSocket) -> std::io::Result<T>,
+) -> Result<T, Received> {
+    deadline::check().map_err(Received::Failed)?;
+    socket
+        .socket
+        .set_read_timeout(deadline::limit(socket.timeout))
+        .map_err(|e| Received::Failed(format!("Failed to receive data: {}", e)))?;
+    match receive(&socket.socket) {
+        Ok(received) => Ok(received),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            deadline::check().map_err(Received::Failed)?;
+            Err(Received::TimedOut)
+        }
+        Err(e) => Err(Received::Failed(format!("Failed to receive data: {}", e))),
+    }
+}
+
+fn receive_from(socket: &Mutex<Socket>, size: usize) -> Result<(Vec<u8>, SocketAddr), Received> {
+    let socket_guard = socket.lock_recover();
+    let mut buffer = vec![0u8; size];
+    let (n, from_addr) = wait_for(&socket_guard, |socket| socket.recv_from(&mut buffer))?;
+    buffer.truncate(n);
+    Ok((buffer, from_addr))
+}
+
+fn receive(socket: &Mutex<Socket>, args: &[Value]) -> Result<Vec<u8>, String> {
+    let socket_guard = socket.lock_recover();
+    let mut buffer = vec![0u8; buffer_size(args)];
+    let n = wait_for(&socket_guard, |socket| socket.recv(&mut buffer))?;
+    buffer.truncate(n);
+    Ok(buffer)
+}
+
+fn datagram(data: Value, from_addr: SocketAddr) -> Value {
+    let mut result = IndexMap::new();
+    result.insert("Data".to_string(), data);
+    result.insert("From".to_string(), Value::String(from_addr.to_string()));
+    Value::Map(Arc::new(std::sync::RwLock::new(result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(object: &Value, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let Value::Map(map) = object else {
+            panic!("{} is called on a Map", name);
+        };
+        let function = map.read_recover()[name].clone();
+        let Value::NativeFunction(function) = function else {
+            panic!("{} is a function", name);
+        };
+        function(args)
+    }
+
+    fn options(pairs: &[(&str, Value)]) -> Value {
+        let map = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        Value::Map(Arc::new(std::sync::RwLock::new(map)))
+    }
+
+    #[test]
+    fn test_packets_until_timeout() {
+        let udp = create_udp_module();
+        let timeout = Value::from_number_string("0.2").unwrap();
+        let server = call(
+            &udp,
+            "Bind",
+            vec![
+                Value::String("127.0.0.1:0".to_string()),
+                options(&[("Timeout", timeout), ("Broadcast", Value::Boolean(true))]),
+            ],
+        )
+        .unwrap();
+        let Value::Map(fields) = &server else {
+            panic!("a socket is a Map");
+        };
+        let address = fields.read_recover()["LocalAddress"].clone();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address_text = address.to_display_string();
+        client.send_to(b"one", &address_text).unwrap();
+        client.send_to(b"two", &address_text).unwrap();
+
+        let packets = call(&server, "Packets", vec![]).unwrap();
+        let mut received = Vec::new();
+        loop {
+            let Value::Option(next) = call(&packets, "Next", vec![]).unwrap() else {
+                panic!("Next gives an Option");
+            };
+            let Some(Value::Map(packet)) = *next else {
+                break;
+            };
+            let packet = packet.read_recover();
+            assert_eq!(
+                packet["From"].to_display_string(),
+                client.local_addr().unwrap().to_string()
+            );
+            received.push(packet["Data"].to_display_string());
+        }
+        assert_eq!(received, ["one", "two"]);
+
+        let error = call(&server, "ReceiveFrom", vec![]).unwrap_err();
+        assert_eq!(error, "Receive timed out");
+        assert!(
+            call(
+                &udp,
+                "Bind",
+                vec![
+                    Value::String("127.0.0.1:0".to_string()),
+                    options(&[("Timeot", Value::Boolean(true))]),
+                ]
+            )
+            .unwrap_err()
+            .contains("no option Timeot")
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use crate::runtime::lock::RwLockExt;
+use crate::runtime::numeric::{self, Matrix};
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use std::sync::{Arc, RwLock};
+
+// Vectors and Matrices hold plain f64s in one contiguous buffer, for numeric
+// work where Lists of exact Numbers are too slow. +, -, * and / work element
+// by element (with a plain number applying to every element); the methods
+// below cover the rest.
+
+type Method = Box<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync>;
+
+pub fn create_vector_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Vector.From([1, 2, 3])
+    methods.insert(
+        "From".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Vector.From requires 1 argument (List of numbers)".to_string());
+            }
+            floats_from_list(&args[0], "Vector.From").map(vector_value)
+        }))),
+    );
+
+    // Vector.Zeros(3)
+    methods.insert(
+        "Zeros".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Vector.Zeros requires 1 argument (length)".to_string());
+            }
+            let len = count(&args[0], "Vector.Zeros")?;
+            Ok(vector_value(vec![0.0; len]))
+        }))),
+    );
+
+    // Vector.Fill(3, 0.5)
+    methods.insert(
+        "Fill".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Vector.Fill requires 2 arguments (length, value)".to_string());
+            }
+            let len = count(&args[0], "Vector.Fill")?;
+            let value = float(&args[1], "Vector.Fill")?;
+            Ok(vector_value(vec![value; len]))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+pub fn create_matrix_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Matrix.From([[1, 2], [3, 4]]), one List per row
+    methods.insert(
+        "From".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Matrix.From requires 1 argument (List of rows)".to_string());
+            }
+            let Value::List(rows) = &args[0] else {
+                return Err("Matrix.From expects a List of rows".to_string());
+            };
+            let rows = rows.read_recover();
+            let mut data = Vec::new();
+            let mut cols = None;
+            for (i, row) in rows.iter().enumerate() {
+                let row = floats_from_list(row, "Matrix.From")?;
+                if *cols.get_or_insert(row.len()) != row.len() {
+                    return Err(format!(
+                        "Matrix.From: row {} has {} values, expected {}",
+                        i + 1,
+                        row.len(),
+                        cols.unwrap_or_default()
+                    ));
+                }
+                data.extend(row);
+            }
+            Matrix::new(rows.len(), cols.unwrap_or(0), data).map(matrix_value)
+        }))),
+    );
+
+    // Matrix.Zeros(2, 3)
+    methods.insert(
+        "Zeros".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 2 {
+                return Err("Matrix.Zeros requires 2 arguments (rows, columns)".to_string());
+            }
+            let rows = count(&args[0], "Matrix.Zeros")?;
+            let cols = count(&args[1], "Matrix.Zeros")?;
+            Matrix::filled(rows, cols, 0.0).map(matrix_value)
+        }))),
+    );
+
+    // Matrix.Identity(3)
+    methods.insert(
+        "Identity".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("Matrix.Identity requires 1 argument (size)".to_string());
+            }
+            let size = count(&args[0], "Matrix.Identity")?;
+            Matrix::identity(size).map(matrix_value)
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// Methods available on a Vector value (`Weights.Dot(Inputs)`, `V.Sum()`,
+/// ...). Length comes from `Value::len` and elements from indexing.
+pub fn vector_member(values: &Arc<[f64]>, member: &str) -> Option<Value> {
+    let values = values.clone();
+    let method: Method = match member {
+        "ToList" => Box::new(move |_args| Ok(float_list(&values))),
+        "Dot" => Box::new(move |args| {
+            let [Value::Vector(other)] = args.as_slice() else {
+                return Err("Vector.Dot requires 1 argument (Vector)".to_string());
+            };
+            if other.len() != values.len() {
+                return Err(format!(
+                    "Vector.Dot needs vectors of the same length, got {} and {}",
+                    values.len(),
+                    other.len()
+                ));
+            }
+            Ok(Value::FastNumber(numeric::dot(&values, other)))
+        }),
+        "Sum" => Box::new(move |_args| Ok(Value::FastNumber(numeric::sum(&values)))),
+        "Mean" => Box::new(move |_args| {
+            if values.is_empty() {
+                return Err("Vector.Mean of an empty vector".to_string());
+            }
+            Ok(Value::FastNumber(
+                numeric::sum(&values) / values.len() as f64,
+            ))
+        }),
+        "Min" => Box::new(move |_args| extreme(&values, "Vector.Min", f64::min)),
+        "Max" => Box::new(move |_args| extreme(&values, "Vector.Max", f64::max)),
+        "Norm" => {
+            Box::new(move |_args| Ok(Value::FastNumber(numeric::dot(&values, &values).sqrt())))
+        }
+        "Map" => Box::new(move |args| {
+            let function = callback(&args, "Vector.Map")?;
+            map_floats(&values, function, "Vector.Map").map(vector_value)
+        }),
+        // V.Reduce(Fn, Initial): Fn is called with (accumulator, element)
+        "Reduce" => Box::new(move |args| {
+            let [Value::NativeFunction(function), initial] = args.as_slice() else {
+                return Err(
+                    "Vector.Reduce requires 2 arguments (function, initial value)".to_string(),
+                );
+            };
+            values.iter().try_fold(initial.clone(), |acc, x| {
+                function(vec![acc, Value::FastNumber(*x)])
+            })
+        }),
+        _ => return None,
+    };
+    Some(Value::NativeFunction(Arc::new(method)))
+}
+
+/// Members of a Matrix value: `Rows` and `Cols`, plus methods such as
+/// `M.Transpose()` and `M.Multiply(Other)`. `M[2]` gives row 2 as a Vector.
+pub fn matrix_member(matrix: &Arc<Matrix>, member: &str) -> Option<Value> {
+    let matrix = matrix.clone();
+    let method: Method = match member {
+        "Rows" => return Some(Value::Integer(matrix.rows.into())),
+        "Cols" => return Some(Value::Integer(matrix.cols.into())),
+        "ToList" => Box::new(move |_args| {
+            let rows = (0..matrix.rows)
+                .map(|row| float_list(matrix.row(row)))
+                .collect();
+            Ok(Value::List(Arc::new(RwLock::new(rows))))
+        }),
+        "Transpose" => Box::new(move |_args| Ok(matrix_value(matrix.transpose()))),
+        // Matrix product; a Vector argument is treated as a column
+        "Multiply" => Box::new(move |args| match args.as_slice() {
+            [Value::Matrix(other)] => matrix.matmul(other).map(matrix_value),
+            [Value::Vector(vector)] => matrix.matvec(vector).map(vector_value),
+            _ => Err("Matrix.Multiply requires 1 argument (Matrix or Vector)".to_string()),
+        }),
+        "Sum" => Box::new(move |_args| Ok(Value::FastNumber(numeric::sum(&matrix.data)))),
+        "Map" => Box::new(move |args| {
+            let function = callback(&args, "Matrix.Map")?;
+            let data = map_floats(&matrix.data, function, "Matrix.Map")?;
+            Matrix::new(matrix.rows, matrix.cols, data).map(matrix_value)
+        }),
+        _ => return None,
+    };
+    Some(Value::NativeFunction(Arc::new(method)))
+}
+
+fn vector_value(values: Vec<f64>) -> Value {
+    Value::Vector(values.into())
+}
+
+fn matrix_value(matrix: Matrix) -> Value {
+    Value::Matrix(Arc::new(matrix))
+}
+
+fn float_list(values: &[f64]) -> Value {
+    let list = values.iter().map(|x| Value::FastNumber(*x)).collect();
+    Value::List(Arc::new(RwLock::new(list)))
+}
+
+fn float(value: &Value, function: &str) -> Result<f64, String> {
+    value.as_f64().ok_or_else(|| {
+        format!(
+            "{}: {} is not a number",
+            function,
+            value.to_display_string()
+        )
+    })
+}
+
+fn count(value: &Value, function: &str) -> Result<usize, String> {
+    value
+        .as_integer()
+        .and_then(|n| n.to_usize())
+        .ok_or_else(|| {
+            format!(
+                "{}: size must be a whole number of 0 or more, got {}",
+                function,
+                value.to_display_string()
+            )
+        })
+}
+
+fn floats_from_list(value: &Value, function: &str) -> Result<Vec<f64>, String> {
+    match value {
+        Value::List(list) => list
+            .read_recover()
+            .iter()
+            .map(|item| float(item, function))
+            .collect(),
+        Value::Vector(values) => Ok(values.to_vec()),
+        _ => Err(format!(
+            "{} expects a List of numbers, got {}",
+            function,
+            value.to_display_string()
+        )),
+    }
+}
+
+fn extreme(values: &[f64], function: &str, pick: fn(f64, f64) -> f64) -> Result<Value, String> {
+    values
+        .iter()
+        .copied()
+        .reduce(pick)
+        .map(Value::FastNumber)
+        .ok_or_else(|| format!("{} of an empty vector", function))
+}
+
+type Callback = Arc<Box<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync>>;
+
+fn callback<'a>(args: &'a [Value], function: &str) -> Result<&'a Callback, String> {
+    match args {
+        [Value::NativeFunction(f)] => Ok(f),
+        _ => Err(format!(
+            "{} requires 1 argument (function, e.g. Math.Sqrt)",
+            function
+        )),
+    }
+}
+
+fn map_floats(values: &[f64], f: &Callback, function: &str) -> Result<Vec<f64>, String> {
+    values
+        .iter()
+        .map(|x| float(&f(vec![Value::FastNumber(*x)])?, function))
+        .collect()
+}
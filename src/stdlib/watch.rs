@@ -0,0 +1,434 @@
+use crate::runtime::deadline;
+use crate::runtime::lock::MutexExt;
+use crate::runtime::permissions;
+use crate::runtime::value::Value;
+use bigdecimal::ToPrimitive;
+use indexmap::IndexMap;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+// The OS tells the watcher which paths changed (inotify, FSEvents,
+// ReadDirectoryChangesW through `notify`), and it looks at just those paths
+// to turn the notifications into create, modify and delete events. The last
+// state it saw is kept so deleted paths can still say whether they were
+// directories, and so a new directory's contents are reported even when
+// they were written before the OS started watching it.
+
+/// How long a watcher waits after a change for the ones that come with it,
+/// such as the several writes of an editor saving a file
+pub const SETTLE: Duration = Duration::from_millis(50);
+
+/// How often a waiting watcher checks whether it should stop, and how often
+/// `sfex serve` checks its certificate files
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Create => "create",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    is_dir: bool,
+}
+
+type Notification = notify::Result<Event>;
+
+/// Everything below `root`. Names starting with `.` (`.git`, `.sfex`) are
+/// left out, and links to directories aren't followed.
+pub struct Watcher {
+    root: PathBuf,
+    /// `root` as the OS spells it in notifications
+    watched: PathBuf,
+    seen: HashMap<PathBuf, Stamp>,
+    notifications: Receiver<Notification>,
+    // Notifications stop when this is dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, String> {
+        let root = root.into();
+        let cannot =
+            |err: &dyn std::fmt::Display| format!("Cannot watch {}: {}", root.display(), err);
+        if !root.exists() {
+            return Err(cannot(&"it does not exist"));
+        }
+        let watched = root.canonicalize().map_err(|e| cannot(&e))?;
+        let (sender, notifications) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(|e| cannot(&e))?;
+        // A single file is watched through its directory, so an editor that
+        // saves by replacing the file doesn't end the watch
+        let result = match watched.parent() {
+            Some(dir) if !watched.is_dir() => watcher.watch(dir, RecursiveMode::NonRecursive),
+            _ => watcher.watch(&watched, RecursiveMode::Recursive),
+        };
+        result.map_err(|e| cannot(&e))?;
+        // Scanned after watching starts, so nothing falls in between
+        let seen = scan(&root);
+        Ok(Self {
+            root,
+            watched,
+            seen,
+            notifications,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// What the OS has reported changed so far, in path order, without
+    /// waiting. A directory's own time changes whenever something in it
+    /// does, so directories are only reported when created or deleted.
+    pub fn changes(&mut self) -> Vec<Change> {
+        self.changes_after(None)
+    }
+
+    /// Wait until something changes.
+    pub fn wait(&mut self) -> Vec<Change> {
+        self.wait_while(|| true)
+    }
+
+    /// Like `wait`, but give up with no changes once `keep_waiting` is false,
+    /// for watchers that stop with a server.
+    pub fn wait_while(&mut self, keep_waiting: impl Fn() -> bool) -> Vec<Change> {
+        loop {
+            let changes = self.wait_for(DEFAULT_INTERVAL, SETTLE);
+            if !changes.is_empty() || !keep_waiting() {
+                return changes;
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for a change, then `settle` longer for the ones
+    /// that come with it, and return them all; no changes if none came.
+    pub fn wait_for(&mut self, timeout: Duration, settle: Duration) -> Vec<Change> {
+        match self.notifications.recv_timeout(timeout) {
+            Ok(first) => {
+                std::thread::sleep(settle);
+                self.changes_after(Some(first))
+            }
+            Err(RecvTimeoutError::Timeout) => Vec::new(),
+            // The OS watch is gone; only the timeout is left to wait
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(timeout);
+                Vec::new()
+            }
+        }
+    }
+
+    fn changes_after(&mut self, first: Option<Notification>) -> Vec<Change> {
+        // Each path the OS reported, and whether its contents were written
+        let mut touched = BTreeMap::new();
+        let mut rescan = false;
+        for notification in first.into_iter().chain(self.notifications.try_iter()) {
+            match notification {
+                Ok(event) if event.need_rescan() => rescan = true,
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                Ok(event) => {
+                    let written = matches!(
+                        event.kind,
+                        EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any)
+                    );
+                    for path in event.paths.iter().filter_map(|path| self.local(path)) {
+                        *touched.entry(path).or_insert(false) |= written;
+                    }
+                }
+                // Notifications were lost, so compare everything
+                Err(_) => rescan = true,
+            }
+        }
+        if rescan {
+            touched.entry(self.root.clone()).or_insert(false);
+        }
+
+        let mut changes = BTreeMap::new();
+        for (path, written) in &touched {
+            self.compare(path, *written, &mut changes);
+        }
+        changes.into_values().collect()
+    }
+
+    /// `path` as it appears below `root`, unless it is outside it or hidden
+    fn local(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.watched).ok()?;
+        if relative
+            .components()
+            .any(|part| part.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return None;
+        }
+        if relative.as_os_str().is_empty() {
+            Some(self.root.clone())
+        } else {
+            Some(self.root.join(relative))
+        }
+    }
+
+    /// Compare `path` and everything below it with what was seen there. A
+    /// file the OS reported `written` is modified even if its time and size
+    /// are the same, since a quick rewrite can keep both.
+    fn compare(&mut self, path: &Path, written: bool, changes: &mut BTreeMap<PathBuf, Change>) {
+        let current = if path == self.root {
+            scan(path)
+        } else {
+            match fs::symlink_metadata(path) {
+                Ok(meta) => {
+                    let mut found = if meta.is_dir() {
+                        scan(path)
+                    } else {
+                        HashMap::new()
+                    };
+                    found.insert(path.to_path_buf(), stamp(&meta));
+                    found
+                }
+                Err(_) => HashMap::new(),
+            }
+        };
+        // A path already compared with a directory above it keeps that
+        // change: a file created and then written is a create
+        let mut change = |kind, path: &PathBuf, stamp: &Stamp| {
+            changes.entry(path.clone()).or_insert(Change {
+                kind,
+                path: path.clone(),
+                is_dir: stamp.is_dir,
+            });
+        };
+        for (found, stamp) in &current {
+            match self.seen.get(found) {
+                None => change(ChangeKind::Create, found, stamp),
+                Some(old) if old.is_dir != stamp.is_dir => change(ChangeKind::Create, found, stamp),
+                Some(old) if !stamp.is_dir && (old != stamp || written && found == path) => {
+                    change(ChangeKind::Modify, found, stamp)
+                }
+                Some(_) => {}
+            }
+        }
+        let gone: Vec<PathBuf> = self
+            .seen
+            .keys()
+            .filter(|seen| seen.starts_with(path) && !current.contains_key(*seen))
+            .cloned()
+            .collect();
+        for path in gone {
+            if let Some(stamp) = self.seen.remove(&path) {
+                change(ChangeKind::Delete, &path, &stamp);
+            }
+        }
+        self.seen.extend(current);
+    }
+}
+
+/// Whether `path` is an SFX script, the files `--watch` reruns or reloads for.
+pub fn is_script(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("sfex" | "sfexhtml")
+    )
+}
+
+/// The directory `--watch` watches for a script: the one it lives in.
+pub fn script_dir(script: &Path) -> PathBuf {
+    match script.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn scan(root: &Path) -> HashMap<PathBuf, Stamp> {
+    let mut seen = HashMap::new();
+    if let Ok(meta) = fs::metadata(root)
+        && !meta.is_dir()
+    {
+        seen.insert(root.to_path_buf(), stamp(&meta));
+        return seen;
+    }
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let path = entry.path();
+            if meta.is_dir() {
+                pending.push(path.clone());
+            }
+            seen.insert(path, stamp(&meta));
+        }
+    }
+    seen
+}
+
+fn stamp(meta: &fs::Metadata) -> Stamp {
+    Stamp {
+        modified: meta.modified().ok(),
+        len: meta.len(),
+        is_dir: meta.is_dir(),
+    }
+}
+
+pub fn create_watch_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    // Watch.Directory("src") or Watch.Directory("src", wait_ms) -> Stream of changes
+    methods.insert(
+        "Directory".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Watch.Directory requires 1-2 arguments (path, optional wait_ms)".to_string(),
+                );
+            }
+            let settle = match args.get(1) {
+                Some(value) => settle_arg(value)?,
+                None => SETTLE,
+            };
+            let path = args[0].to_display_string();
+            permissions::check_read(&path)?;
+            let watcher = Watcher::new(path)?;
+
+            let state = Mutex::new((watcher, VecDeque::new()));
+            let generator = Value::NativeFunction(Arc::new(Box::new(move |_args| {
+                let mut guard = state.lock_recover();
+                let (watcher, queued) = &mut *guard;
+                // Like Time.Sleep, a web handler's wait ends at its deadline
+                while queued.is_empty() {
+                    let timeout =
+                        deadline::limit(Some(DEFAULT_INTERVAL)).unwrap_or(DEFAULT_INTERVAL);
+                    let settle = deadline::limit(Some(settle)).unwrap_or(settle);
+                    queued.extend(watcher.wait_for(timeout, settle));
+                    if queued.is_empty() {
+                        deadline::check()?;
+                    }
+                }
+                let change = queued.pop_front().map(change_value);
+                Ok(Value::Option(Box::new(change)))
+            })));
+            Ok(crate::stdlib::stream::create_stream_object(
+                vec![],
+                Some(generator),
+            ))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+/// The wait after a change for the ones that come with it: whole
+/// milliseconds, 0 for none
+fn settle_arg(value: &Value) -> Result<Duration, String> {
+    let ms = match value {
+        Value::Number(n) if n.is_integer() => n.to_u64(),
+        Value::Integer(i) => i.to_u64(),
+        Value::FastNumber(f) if *f >= 0.0 && f.fract() == 0.0 => Some(*f as u64),
+        _ => None,
+    };
+    match ms {
+        Some(ms) => Ok(Duration::from_millis(ms)),
+        _ => Err(
+            "Watch.Directory's wait must be a whole number of milliseconds, 0 or more".to_string(),
+        ),
+    }
+}
+
+fn change_value(change: Change) -> Value {
+    let map = IndexMap::from([
+        (
+            "Kind".to_string(),
+            Value::String(change.kind.as_str().to_string()),
+        ),
+        (
+            "Path".to_string(),
+            Value::String(change.path.to_string_lossy().into_owned()),
+        ),
+        ("IsDir".to_string(), Value::Boolean(change.is_dir)),
+    ]);
+    Value::Map(Arc::new(RwLock::new(map)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        // Fails rather than hangs when a notification never comes
+        const TIMEOUT: Duration = Duration::from_secs(5);
+        let root = std::env::temp_dir().join(format!("sfex-watch-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.sfex"), "Story:\n").unwrap();
+        let mut watcher = Watcher::new(&root).unwrap();
+        assert!(watcher.changes().is_empty());
+
+        fs::write(root.join("src/main.sfex"), "Story:\n    Print 1\n").unwrap();
+        fs::create_dir(root.join("lib")).unwrap();
+        fs::write(root.join("lib/util.sfex"), "").unwrap();
+        fs::create_dir(root.join(".sfex")).unwrap();
+        let kinds = |changes: Vec<Change>| {
+            changes
+                .into_iter()
+                .map(|change| {
+                    let path = change.path.strip_prefix(&root).unwrap().to_path_buf();
+                    (change.kind, path.to_string_lossy().into_owned())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            kinds(watcher.wait_for(TIMEOUT, SETTLE)),
+            [
+                (ChangeKind::Create, "lib".to_string()),
+                (ChangeKind::Create, "lib/util.sfex".to_string()),
+                (ChangeKind::Modify, "src/main.sfex".to_string()),
+            ]
+        );
+
+        fs::remove_dir_all(root.join("lib")).unwrap();
+        assert_eq!(
+            kinds(watcher.wait_for(TIMEOUT, SETTLE)),
+            [
+                (ChangeKind::Delete, "lib".to_string()),
+                (ChangeKind::Delete, "lib/util.sfex".to_string()),
+            ]
+        );
+        assert!(is_script(Path::new("src/main.sfex")) && !is_script(Path::new("notes.md")));
+        assert_eq!(settle_arg(&Value::Integer(0.into())), Ok(Duration::ZERO));
+        assert!(settle_arg(&Value::FastNumber(1.5)).is_err());
+        assert!(settle_arg(&Value::Integer((-1).into())).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
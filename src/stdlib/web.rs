@@ -11,39 +11,115 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::compiler::ast::Program;
+use crate::compiler::ast::{Expression, Program, Statement};
 use crate::compiler::lexer::Lexer;
 use crate::compiler::parser::Parser;
+use crate::runtime::deadline::{self, Deadline};
+use crate::runtime::executor;
 use crate::runtime::interpreter::Interpreter;
+use crate::runtime::limits::Limits;
+use crate::runtime::lock::{MutexExt, RwLockExt, panic_message};
+use crate::runtime::permissions::{self, Kind, Permissions};
 use crate::runtime::value::Value;
+#[cfg(feature = "tls")]
+use crate::stdlib::acme::{self, AcmeConfig, Challenges, IssuedCert};
+use crate::stdlib::json::convert_object_to_json;
+use crate::stdlib::watch::{self, Watcher};
+use crate::stdlib::{assets, log, page, template};
 use bigdecimal::ToPrimitive;
 use bytes::Bytes;
 use futures_util::StreamExt;
-use hyper::body::to_bytes;
+use hyper::body::HttpBody;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server};
+use indexmap::IndexMap;
+#[cfg(feature = "tls")]
+use rustls::server::{ClientHello, ResolvesServerCert};
+#[cfg(feature = "tls")]
+use rustls::sign::{CertifiedKey, any_supported_type};
+#[cfg(feature = "tls")]
 use rustls::{Certificate, PrivateKey, ServerConfig};
+#[cfg(feature = "tls")]
 use rustls_pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
-use serde_json::Value as JsonValue;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::io;
 use std::net::SocketAddr;
 use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::task::{Context, Poll};
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpListener;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio_io_timeout::TimeoutStream;
+#[cfg(feature = "tls")]
 use tokio_rustls::TlsAcceptor;
+#[cfg(feature = "tls")]
 use tokio_stream::wrappers::TcpListenerStream;
+use unicode_width::UnicodeWidthChar;
 
 const DEFAULT_ADDR: &str = "127.0.0.1:8000";
-const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+// hyper rejects HTTP/1 read buffers smaller than this
+const MIN_HEADER_SIZE: usize = 8192;
+// TLS handshakes in progress at once, so one slow client can't hold up accepts
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKES: usize = 64;
+
+// Static files smaller than this are not worth compressing
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+// How often a certificate's renewal date is re-checked, and how soon a
+// failed renewal is retried
+#[cfg(feature = "tls")]
+const ACME_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+#[cfg(feature = "tls")]
+const ACME_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const METRICS_PATH: &str = "/metrics";
+const INSPECT_PATH: &str = "/__sfex/inspect";
+// Handler errors kept for the inspect page
+const RECENT_ERRORS: usize = 20;
+const SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Shared by every Router.Proxy route so upstream connections are pooled
+static PROXY_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+// Script re-executed by `sfex serve --watch` whenever it changes on disk
+static WATCH_SCRIPT: OnceLock<Mutex<Option<PathBuf>>> = OnceLock::new();
+
+// Access log and metrics settings from `sfex serve --log-format/--metrics`
+static TELEMETRY_OPTIONS: OnceLock<Mutex<TelemetryOptions>> = OnceLock::new();
+
+// Connection limits and protocol settings from `sfex serve`; Router.Serve
+// options override them per server
+static SERVER_OPTIONS: OnceLock<Mutex<ServerOptions>> = OnceLock::new();
+
+// Set by `sfex serve --dev`; requests to /__sfex/inspect must carry this token
+static DEV_TOKEN: OnceLock<String> = OnceLock::new();
+
+// Set by `sfex serve --acme-domain`; servers started without explicit cert
+// files then get their certificate from the ACME CA
+#[cfg(feature = "tls")]
+static ACME_CONFIG: OnceLock<AcmeConfig> = OnceLock::new();
+
+// `[serve] on_start` and `on_stop` from sfex.toml, run around each server
+static SERVE_HOOKS: OnceLock<ServeHooks> = OnceLock::new();
+
+thread_local! {
+    // Live router that a reloading script hands its routes to instead of binding again
+    static RELOAD_TARGET: std::cell::RefCell<Option<Arc<Mutex<RouterState>>>> =
+        const { std::cell::RefCell::new(None) };
+}
 
 pub fn create_web_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Serve".to_string(),
@@ -144,6 +220,52 @@ pub fn create_web_module() -> Value {
         }))),
     );
 
+    // Web.Sse(stream, { KeepAlive: 15, Retry: 3000, Headers: {...} })
+    methods.insert(
+        "Sse".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Web.Sse requires 1-2 arguments (stream, optional options map)".to_string(),
+                );
+            }
+
+            let stream = args[0].clone();
+            if !is_stream_value(&stream) {
+                return Err("Web.Sse requires a Stream value".to_string());
+            }
+
+            let mut event_stream = IndexMap::new();
+            let mut headers: Option<Value> = None;
+            match args.get(1) {
+                Some(Value::Map(options)) => {
+                    let options = options.read_recover();
+                    for key in ["KeepAlive", "Retry"] {
+                        if let Some(value) = options.get(key) {
+                            event_stream.insert(key.to_string(), value.clone());
+                        }
+                    }
+                    headers = options.get("Headers").cloned();
+                }
+                Some(_) => return Err("Web.Sse options must be a Map".to_string()),
+                None => {}
+            }
+
+            let mut headers = merge_headers(headers, "Content-Type", "text/event-stream");
+            headers = merge_headers(Some(headers), "Cache-Control", "no-cache");
+            headers = merge_headers(Some(headers), "X-Accel-Buffering", "no");
+
+            let response = build_stream_response_map(stream, 200, Some(headers));
+            if let Value::Map(map) = &response {
+                map.write_recover().insert(
+                    "EventStream".to_string(),
+                    Value::Map(Arc::new(RwLock::new(event_stream))),
+                );
+            }
+            Ok(response)
+        }))),
+    );
+
     methods.insert(
         "Response".to_string(),
         Value::NativeFunction(Arc::new(Box::new(|args| {
@@ -201,7 +323,7 @@ pub fn create_web_module() -> Value {
                 headers = Some(args[2].clone());
             }
 
-            let json_value = value_to_json(&args[0]);
+            let json_value = convert_object_to_json(&args[0]);
             let json_body = serde_json::to_string(&json_value).unwrap_or_else(|_| "{}".to_string());
 
             Ok(build_response_map(
@@ -232,7 +354,7 @@ pub fn create_web_module() -> Value {
                 302
             };
 
-            let mut headers = HashMap::new();
+            let mut headers = IndexMap::new();
             headers.insert("Location".to_string(), Value::String(url));
             Ok(build_response_map(
                 Value::String(String::new()),
@@ -252,7 +374,8 @@ pub fn create_web_module() -> Value {
             }
 
             let path = args[0].to_display_string();
-            let mut response = HashMap::new();
+            permissions::check_read(&path)?;
+            let mut response = IndexMap::new();
             response.insert("FilePath".to_string(), Value::String(path));
             if args.len() == 2 {
                 response.insert(
@@ -276,7 +399,7 @@ pub fn serve(addr: &str, handler_path: &str, static_dir: Option<&str>) -> Result
         state.static_mounts.push(StaticMount::new("/", dir));
     }
 
-    start_server(addr, Arc::new(Mutex::new(state)), None)
+    start_server(addr, Arc::new(Mutex::new(state)), None, server_options())
 }
 
 pub fn serve_tls(
@@ -300,12 +423,46 @@ pub fn serve_tls(
             cert_path: cert_path.to_string(),
             key_path: key_path.to_string(),
         }),
+        server_options(),
     )
 }
 
-fn create_router_object() -> Value {
-    let state = Arc::new(Mutex::new(RouterState::new()));
-    let mut methods = HashMap::new();
+/// Serve an .sfexhtml page with a route per front-matter part. Each part's
+/// code and HTML reload when the file changes; adding or removing routes
+/// needs a restart.
+pub fn serve_page(
+    addr: &str,
+    page_path: &str,
+    static_dir: Option<&str>,
+    tls: Option<(&str, &str)>,
+) -> Result<(), String> {
+    let path = resolve_path(page_path);
+    let mut state = RouterState::new();
+    for route in page::load_page(&path)? {
+        let handler = Arc::new(ScriptHandler::page(&path, route.describe()));
+        state
+            .routes
+            .push(Route::new(route.method, &route.path, handler));
+    }
+
+    if let Some(dir) = static_dir {
+        state.static_mounts.push(StaticMount::new("/", dir));
+    }
+
+    start_server(
+        addr,
+        Arc::new(Mutex::new(state)),
+        tls.map(|(cert_path, key_path)| TlsPaths {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+        }),
+        server_options(),
+    )
+}
+
+/// Route registration shared by the server's router and its tenants'.
+fn router_methods(state: &Arc<Mutex<RouterState>>) -> IndexMap<String, Value> {
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Get".to_string(),
@@ -338,7 +495,7 @@ fn create_router_object() -> Value {
             }
 
             let handler = ScriptHandler::new(&args[0].to_display_string());
-            let mut state = state_use.lock().expect("lock poisoned");
+            let mut state = state_use.lock_recover();
             state.middleware.push(Arc::new(handler));
             Ok(Value::Boolean(true))
         }))),
@@ -348,16 +505,53 @@ fn create_router_object() -> Value {
     methods.insert(
         "Static".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
-            if args.len() != 2 {
-                return Err("Router.Static requires 2 arguments (mount_path, dir)".to_string());
+            if args.len() < 2 || args.len() > 3 {
+                return Err(
+                    "Router.Static requires 2-3 arguments (mount_path, dir, optional {MaxAge, Compress, Fingerprint})"
+                        .to_string(),
+                );
             }
 
             let mount_path = args[0].to_display_string();
             let dir = args[1].to_display_string();
-            let mut state = state_static.lock().expect("lock poisoned");
-            state
-                .static_mounts
-                .push(StaticMount::new(&mount_path, &dir));
+            permissions::check_read(&dir)?;
+            let mut mount = StaticMount::new(&mount_path, &dir);
+            if let Some(Value::Map(options)) = args.get(2) {
+                let options = options.read_recover();
+                if let Some(max_age) = options.get("MaxAge").and_then(value_to_f64) {
+                    mount.max_age = Some(max_age.max(0.0) as u64);
+                }
+                if let Some(compress) = options.get("Compress") {
+                    mount.compress = compress.is_truthy();
+                }
+                if let Some(fingerprint) = options.get("Fingerprint") {
+                    mount.fingerprint = fingerprint.is_truthy();
+                }
+            }
+            if mount.fingerprint {
+                assets::warm(&mount.dir);
+            }
+            let mut state = state_static.lock_recover();
+            state.static_mounts.push(mount);
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    // Router.Proxy("/api/*", "http://localhost:9000") forwards matching requests upstream
+    let state_proxy = state.clone();
+    methods.insert(
+        "Proxy".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 2 {
+                return Err("Router.Proxy requires 2 arguments (path, upstream_url)".to_string());
+            }
+
+            let path = args[0].to_display_string();
+            let upstream_url = args[1].to_display_string();
+            permissions::check_url(&upstream_url)?;
+            let upstream = ProxyRoute::new(&path, &upstream_url)?;
+            let mut state = state_proxy.lock_recover();
+            state.proxies.push(upstream);
             Ok(Value::Boolean(true))
         }))),
     );
@@ -371,18 +565,73 @@ fn create_router_object() -> Value {
             }
 
             let handler = ScriptHandler::new(&args[0].to_display_string());
-            let mut state = state_nf.lock().expect("lock poisoned");
+            let mut state = state_nf.lock_recover();
             state.not_found = Some(Arc::new(handler));
             Ok(Value::Boolean(true))
         }))),
     );
 
+    let state_ce = state.clone();
+    methods.insert(
+        "OnCompileError".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.len() != 1 {
+                return Err("Router.OnCompileError requires 1 argument (handler_path)".to_string());
+            }
+
+            let handler = ScriptHandler::new(&args[0].to_display_string());
+            let mut state = state_ce.lock_recover();
+            state.on_compile_error = Some(Arc::new(handler));
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    methods
+}
+
+fn create_router_object() -> Value {
+    let state = Arc::new(Mutex::new(RouterState::new()));
+    let mut methods = router_methods(&state);
+
+    // Router.Tenant("shop.example.com", {Modules, MaxConcurrent, ...}) gives
+    // requests for that host a router of their own
+    let state_tenant = state.clone();
+    methods.insert(
+        "Tenant".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(
+                    "Router.Tenant requires 1-2 arguments (host, optional {Modules, MaxConcurrent, MaxBodySize, RequestTimeout})"
+                        .to_string(),
+                );
+            }
+
+            let host = args[0].to_display_string().to_ascii_lowercase();
+            let mut tenant = Tenant::new(&host);
+            if let Some(Value::Map(options)) = args.get(1) {
+                tenant.apply_options(&options.read_recover())?;
+            }
+
+            let mut state = state_tenant.lock_recover();
+            if state.tenants.iter().any(|existing| existing.host == host) {
+                return Err(format!("Tenant '{}' is already defined", host));
+            }
+            tenant.inherit_limits(&state);
+            let router = Value::Map(Arc::new(RwLock::new(router_methods(&tenant.state))));
+            state.tenants.push(tenant);
+            Ok(router)
+        }))),
+    );
+
     let state_serve = state.clone();
     methods.insert(
         "Serve".to_string(),
         Value::NativeFunction(Arc::new(Box::new(move |args| {
-            if args.len() > 1 {
-                return Err("Router.Serve requires 0-1 arguments (addr)".to_string());
+            if args.len() > 2 {
+                return Err(
+                    "Router.Serve requires 0-2 arguments (addr, optional {MaxConnections, MaxHeaderSize, MaxBodySize, ReadTimeout, WriteTimeout, RequestTimeout, KeepAlive, Http2})"
+                        .to_string(),
+                );
             }
 
             let addr = if args.is_empty() {
@@ -390,8 +639,17 @@ fn create_router_object() -> Value {
             } else {
                 args[0].to_display_string()
             };
+            let mut options = server_options();
+            if let Some(Value::Map(map)) = args.get(1) {
+                options.apply_map(&map.read_recover())?;
+            }
 
-            start_server(&addr, state_serve.clone(), None)?;
+            if hand_off_reload(&state_serve) {
+                return Ok(Value::Boolean(true));
+            }
+
+            check_server(&addr, &state_serve, None)?;
+            start_server(&addr, state_serve.clone(), None, options)?;
             Ok(Value::Boolean(true))
         }))),
     );
@@ -417,20 +675,36 @@ fn create_router_object() -> Value {
             };
 
             if let Some(dir) = static_dir {
-                let mut state = state_serve_tls.lock().expect("lock poisoned");
+                let mut state = state_serve_tls.lock_recover();
                 state
                     .static_mounts
                     .push(StaticMount::new("/", &dir));
             }
 
-            start_server(
-                &addr,
-                state_serve_tls.clone(),
-                Some(TlsPaths {
-                    cert_path,
-                    key_path,
-                }),
-            )?;
+            if hand_off_reload(&state_serve_tls) {
+                return Ok(Value::Boolean(true));
+            }
+
+            let tls = TlsPaths {
+                cert_path,
+                key_path,
+            };
+            check_server(&addr, &state_serve_tls, Some(&tls))?;
+            start_server(&addr, state_serve_tls.clone(), Some(tls), server_options())?;
+            Ok(Value::Boolean(true))
+        }))),
+    );
+
+    let state_stop = state.clone();
+    methods.insert(
+        "Stop".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if !args.is_empty() {
+                return Err("Router.Stop takes no arguments".to_string());
+            }
+
+            let shutdown = state_stop.lock_recover().shutdown.clone();
+            shutdown.notify_one();
             Ok(Value::Boolean(true))
         }))),
     );
@@ -438,6 +712,38 @@ fn create_router_object() -> Value {
     Value::Map(Arc::new(RwLock::new(methods)))
 }
 
+fn hand_off_reload(state: &Arc<Mutex<RouterState>>) -> bool {
+    RELOAD_TARGET.with(|target| {
+        let Some(live) = target.borrow().clone() else {
+            return false;
+        };
+
+        let fresh = state.lock_recover();
+        let mut live = live.lock_recover();
+        live.routes = fresh.routes.clone();
+        live.middleware = fresh.middleware.clone();
+        live.static_mounts = fresh.static_mounts.clone();
+        live.proxies = fresh.proxies.clone();
+        live.not_found = fresh.not_found.clone();
+        live.fallback = fresh.fallback.clone();
+        live.on_compile_error = fresh.on_compile_error.clone();
+
+        // A tenant that was there before keeps its App.State and errors
+        let mut tenants = fresh.tenants.clone();
+        for tenant in &mut tenants {
+            tenant.inherit_limits(&live);
+            if let Some(old) = live.tenants.iter().find(|old| old.host == tenant.host) {
+                let old = old.state.lock_recover();
+                let mut state = tenant.state.lock_recover();
+                state.app = old.app.clone();
+                state.recent_errors = old.recent_errors.clone();
+            }
+        }
+        live.tenants = tenants;
+        true
+    })
+}
+
 fn route_register(method: Option<&'static str>, state: Arc<Mutex<RouterState>>) -> Value {
     let method_string = method.map(|m| m.to_string());
     Value::NativeFunction(Arc::new(Box::new(move |args| {
@@ -452,7 +758,7 @@ fn route_register(method: Option<&'static str>, state: Arc<Mutex<RouterState>>)
         let handler = Arc::new(ScriptHandler::new(&handler_path));
         let route = Route::new(method_string.clone(), &path, handler);
 
-        let mut state = state.lock().expect("lock poisoned");
+        let mut state = state.lock_recover();
         state.routes.push(route);
         Ok(Value::Boolean(true))
     })))
@@ -461,6 +767,7 @@ fn route_register(method: Option<&'static str>, state: Arc<Mutex<RouterState>>)
 #[derive(Clone)]
 struct Route {
     method: Option<String>,
+    path: String,
     pattern: RoutePattern,
     handler: Arc<ScriptHandler>,
 }
@@ -469,6 +776,7 @@ impl Route {
     fn new(method: Option<String>, pattern: &str, handler: Arc<ScriptHandler>) -> Self {
         Self {
             method,
+            path: normalize_path(pattern),
             pattern: RoutePattern::new(pattern),
             handler,
         }
@@ -479,6 +787,11 @@ impl Route {
 struct StaticMount {
     mount_path: String,
     dir: PathBuf,
+    // Cache-Control max-age in seconds; None means revalidate every time
+    max_age: Option<u64>,
+    compress: bool,
+    // Also serve files under content-hashed names and link to those from HTML
+    fingerprint: bool,
 }
 
 impl StaticMount {
@@ -486,91 +799,405 @@ impl StaticMount {
         Self {
             mount_path: normalize_path(mount_path),
             dir: resolve_path(dir),
+            max_age: None,
+            compress: true,
+            fingerprint: false,
+        }
+    }
+}
+
+/// Requests matching `pattern` are forwarded to `upstream` with their original
+/// path and query appended (`/api/users` -> `http://localhost:9000/api/users`).
+#[derive(Clone)]
+struct ProxyRoute {
+    path: String,
+    pattern: RoutePattern,
+    upstream: reqwest::Url,
+}
+
+impl ProxyRoute {
+    fn new(pattern: &str, upstream: &str) -> Result<Self, String> {
+        let upstream = reqwest::Url::parse(upstream)
+            .map_err(|e| format!("Invalid proxy upstream '{}': {}", upstream, e))?;
+        if upstream.scheme() != "http" && upstream.scheme() != "https" {
+            return Err(format!(
+                "Proxy upstream must be an http:// or https:// URL, got '{}'",
+                upstream
+            ));
         }
+
+        Ok(Self {
+            path: normalize_path(pattern),
+            pattern: RoutePattern::new(pattern),
+            upstream,
+        })
+    }
+
+    fn target_url(&self, raw_path: &str) -> String {
+        let base = self.upstream.as_str().trim_end_matches('/');
+        format!("{}{}", base, raw_path)
     }
 }
 
 struct RouterState {
     routes: Vec<Route>,
+    proxies: Vec<ProxyRoute>,
     middleware: Vec<Arc<ScriptHandler>>,
     static_mounts: Vec<StaticMount>,
     not_found: Option<Arc<ScriptHandler>>,
     fallback: Option<Arc<ScriptHandler>>,
-    runtime: Arc<tokio::runtime::Runtime>,
+    // Answers requests whose handler doesn't compile, with CompileError set
+    on_compile_error: Option<Arc<ScriptHandler>>,
+    shutdown: Arc<Notify>,
+    max_body_size: usize,
+    request_timeout: Option<Duration>,
+    handler_limits: Limits,
+    // `App` global shared by every handler of this server; holds App.State
+    app: Value,
+    recent_errors: VecDeque<HandlerError>,
+    tenants: Vec<Tenant>,
+    // Host this router serves as a tenant of the server's router
+    tenant_host: Option<String>,
+    // Stdlib modules its handlers may use; None allows every module
+    modules: Option<Vec<String>>,
+    // What its handlers may reach, from the script that started the server
+    // or the tenant's own
+    permissions: Option<Permissions>,
+}
+
+/// Requests whose Host matches `host` (`shop.example.com`, or
+/// `*.example.com` for its subdomains) go to a router of their own, with its
+/// own routes, App.State and limits. Its handlers run in interpreters that
+/// see only the allowed stdlib modules and can't stop the server.
+#[derive(Clone)]
+struct Tenant {
+    host: String,
+    state: Arc<Mutex<RouterState>>,
+    max_body_size: Option<usize>,
+    request_timeout: Option<Duration>,
+    // Handlers of this tenant running at once; more wait for a free slot
+    pool: Option<Arc<Semaphore>>,
+    max_concurrent: Option<usize>,
+    permissions: Option<Permissions>,
+}
+
+impl Tenant {
+    fn new(host: &str) -> Self {
+        let mut state = RouterState::new();
+        state.tenant_host = Some(host.to_string());
+        Self {
+            host: host.to_string(),
+            state: Arc::new(Mutex::new(state)),
+            max_body_size: None,
+            request_timeout: None,
+            pool: None,
+            max_concurrent: None,
+            permissions: None,
+        }
+    }
+
+    fn apply_options(&mut self, options: &IndexMap<String, Value>) -> Result<(), String> {
+        for (key, value) in options {
+            match key.as_str() {
+                "Modules" => {
+                    let Value::List(list) = value else {
+                        return Err("Tenant Modules must be a list of module names".to_string());
+                    };
+                    let modules = list
+                        .read_recover()
+                        .iter()
+                        .map(|module| module.to_display_string())
+                        .collect();
+                    self.state.lock_recover().modules = Some(modules);
+                }
+                "Permissions" => self.permissions = Some(tenant_permissions(value)?),
+                "MaxConcurrent" => {
+                    let limit = positive_option(key, value)? as usize;
+                    self.max_concurrent = Some(limit);
+                    self.pool = Some(Arc::new(Semaphore::new(limit)));
+                }
+                "MaxBodySize" => self.max_body_size = Some(positive_option(key, value)? as usize),
+                "RequestTimeout" => {
+                    self.request_timeout =
+                        Some(Duration::from_secs_f64(positive_option(key, value)?))
+                }
+                other => return Err(format!("Unknown tenant option '{}'", other)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Use the server's body size and timeout where the tenant sets none,
+    /// and its handler limits. Its permissions can only narrow the server's.
+    fn inherit_limits(&self, server: &RouterState) {
+        let mut state = self.state.lock_recover();
+        state.max_body_size = self.max_body_size.unwrap_or(server.max_body_size);
+        state.request_timeout = self.request_timeout.or(server.request_timeout);
+        state.handler_limits = server.handler_limits;
+        state.permissions = match &self.permissions {
+            Some(own) => Some(own.clone().within(server.permissions.clone().map(Arc::new))),
+            None => server.permissions.clone(),
+        };
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self.host.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == self.host,
+        }
+    }
+}
+
+/// `Permissions: { Read: ["tenants/shop"], Net: ["api.stripe.com"], Env: False }`:
+/// a tenant may reach only what it lists; `True` allows all of a kind.
+fn tenant_permissions(value: &Value) -> Result<Permissions, String> {
+    let Value::Map(options) = value else {
+        return Err("Tenant Permissions must be a Map such as { Read: [\"data\"] }".to_string());
+    };
+    let mut permissions = Permissions::none();
+    for (key, value) in options.read_recover().iter() {
+        let Some(kind) = Kind::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(key))
+        else {
+            return Err(format!(
+                "Unknown tenant permission '{}' (expected Read, Write, Net, Env or Run)",
+                key
+            ));
+        };
+        match value {
+            Value::Boolean(true) => permissions.allow(kind, &[]),
+            Value::Boolean(false) => {}
+            Value::List(items) => {
+                let items: Vec<String> = items
+                    .read_recover()
+                    .iter()
+                    .map(Value::to_display_string)
+                    .collect();
+                if !items.is_empty() {
+                    permissions.allow(kind, &items);
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Tenant permission {} must be True, False or a List",
+                    key
+                ));
+            }
+        }
+    }
+    Ok(permissions)
+}
+
+fn positive_option(key: &str, value: &Value) -> Result<f64, String> {
+    match value_to_f64(value) {
+        Some(number) if number > 0.0 => Ok(number),
+        _ => Err(format!("Tenant {} must be a positive number", key)),
+    }
+}
+
+/// The Host a request was sent to, lowercased and without its port.
+fn request_host(req: &Request<Body>) -> Option<String> {
+    let host = req
+        .headers()
+        .get("host")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().host())?;
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(ipv6),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+#[derive(Clone)]
+struct HandlerError {
+    time: chrono::DateTime<chrono::Local>,
+    route: String,
+    message: String,
 }
 
 impl RouterState {
     fn new() -> Self {
-        let runtime = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create web runtime");
         Self {
             routes: Vec::new(),
+            proxies: Vec::new(),
             middleware: Vec::new(),
             static_mounts: Vec::new(),
             not_found: None,
             fallback: None,
-            runtime: Arc::new(runtime),
+            on_compile_error: None,
+            shutdown: Arc::new(Notify::new()),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            request_timeout: None,
+            handler_limits: Limits::default(),
+            app: build_app_value(),
+            recent_errors: VecDeque::new(),
+            tenants: Vec::new(),
+            tenant_host: None,
+            modules: None,
+            permissions: None,
+        }
+    }
+
+    fn record_error(&mut self, route: &str, message: &str) {
+        if self.recent_errors.len() == RECENT_ERRORS {
+            self.recent_errors.pop_front();
         }
+        self.recent_errors.push_back(HandlerError {
+            time: chrono::Local::now(),
+            route: route.to_string(),
+            message: message.to_string(),
+        });
     }
 }
 
+struct ScriptRuntime {
+    runtime: Arc<tokio::runtime::Runtime>,
+    shutdown: Option<Arc<Notify>>,
+    app: Value,
+    modules: Option<Vec<String>>,
+    permissions: Option<Permissions>,
+    limits: Limits,
+}
+
 struct ScriptHandler {
     path: PathBuf,
+    // The route (`GET /about`) this serves when `path` is an .sfexhtml page
+    page_route: Option<String>,
     state: Mutex<ScriptState>,
 }
 
 struct ScriptState {
     modified: Option<SystemTime>,
     program: Option<Program>,
-    last_error: Option<String>,
+    // HTML rendered after the program runs, for page routes
+    template: Option<String>,
+    last_error: Option<CompileFailure>,
+    // Situations still switched on when the handler last finished
+    situations: Vec<String>,
 }
 
 impl ScriptHandler {
     fn new(path: &str) -> Self {
+        Self::with_route(resolve_path(path), None)
+    }
+
+    fn page(path: &Path, route: String) -> Self {
+        Self::with_route(path.to_path_buf(), Some(route))
+    }
+
+    fn with_route(path: PathBuf, page_route: Option<String>) -> Self {
         Self {
-            path: resolve_path(path),
+            path,
+            page_route,
             state: Mutex::new(ScriptState {
                 modified: None,
                 program: None,
+                template: None,
                 last_error: None,
+                situations: Vec::new(),
             }),
         }
     }
 
-    fn ensure_current(&self) -> Result<Program, String> {
-        let mut state = self.state.lock().expect("lock poisoned");
-        let metadata = fs::metadata(&self.path)
-            .map_err(|e| format!("Failed to read handler '{}': {}", self.path.display(), e))?;
-        let modified = metadata.modified().ok();
-        let needs_reload = match (modified, state.modified) {
-            (Some(new_time), Some(old_time)) => new_time > old_time,
-            (Some(_), None) => true,
-            (None, _) => state.program.is_none(),
+    fn load(&self) -> Result<(Program, Option<String>), CompileFailure> {
+        let Some(route) = &self.page_route else {
+            return compile_handler(&self.path).map(|program| (program, None));
         };
+        page::load_page(&self.path)
+            .map_err(CompileFailure::new)?
+            .into_iter()
+            .find(|part| part.describe() == *route)
+            .map(|part| (part.program, Some(part.template)))
+            .ok_or_else(|| {
+                CompileFailure::new(format!(
+                    "Page '{}' no longer has route {}; restart the server after adding or removing routes",
+                    self.path.display(),
+                    route
+                ))
+            })
+    }
+
+    /// The handler file, with the page route for .sfexhtml parts.
+    fn describe(&self) -> String {
+        match &self.page_route {
+            Some(route) => format!("{} ({})", self.path.display(), route),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    fn inspect(&self) -> serde_json::Value {
+        let state = self.state.lock_recover();
+        serde_json::json!({
+            "handler": self.describe(),
+            "loaded": state.program.is_some(),
+            "modified": state.modified.map(|time| chrono::DateTime::<chrono::Local>::from(time).to_rfc3339()),
+            "statements": state.program.as_ref().map(|program| program.story.body.len()),
+            "active_situations": state.situations,
+            "last_error": state.last_error.as_ref().map(|failure| &failure.message),
+        })
+    }
+
+    /// The handler's program, recompiled if the file changed. A handler that
+    /// failed to compile is tried again on every request, so a fix is picked
+    /// up even when it keeps the file's modification time. Outside `--dev` a
+    /// handler that compiled before keeps running its last good version.
+    fn ensure_current(&self) -> Result<(Program, Option<String>), CompileFailure> {
+        let mut state = self.state.lock_recover();
+        let metadata = fs::metadata(&self.path).map_err(|e| {
+            CompileFailure::new(format!(
+                "Failed to read handler '{}': {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+        let modified = metadata.modified().ok();
+        let needs_reload = state.program.is_none()
+            || state.last_error.is_some()
+            || (modified.is_some() && modified != state.modified);
 
-        if needs_reload || state.program.is_none() {
-            match load_program(&self.path) {
-                Ok(program) => {
+        if needs_reload {
+            match self.load() {
+                Ok((program, template)) => {
                     state.program = Some(program);
+                    state.template = template;
                     state.modified = modified;
                     state.last_error = None;
                 }
-                Err(err) => {
-                    state.last_error = Some(err);
+                Err(failure) => {
+                    let repeated = state
+                        .last_error
+                        .as_ref()
+                        .is_some_and(|last| last.message == failure.message);
+                    if !repeated && state.program.is_some() && DEV_TOKEN.get().is_none() {
+                        log::warn(
+                            &format!(
+                                "{}\nStill serving the last version of {} that compiled",
+                                failure.message,
+                                self.describe()
+                            ),
+                            &[],
+                        );
+                    }
+                    state.last_error = Some(failure);
                 }
             }
         }
 
-        if let Some(err) = &state.last_error {
-            return Err(err.clone());
+        if let Some(failure) = &state.last_error
+            && (state.program.is_none() || DEV_TOKEN.get().is_some())
+        {
+            return Err(failure.clone());
         }
 
         state
             .program
             .clone()
-            .ok_or_else(|| "Handler script not loaded".to_string())
+            .map(|program| (program, state.template.clone()))
+            .ok_or_else(|| CompileFailure::new("Handler script not loaded".to_string()))
     }
 }
 
@@ -684,214 +1311,1688 @@ impl ResponseData {
 enum ResponseBody {
     Bytes(Vec<u8>),
     Stream(Value),
+    EventStream(Value, SseOptions),
+    File(StaticFile),
 }
 
-struct TlsPaths {
-    cert_path: String,
-    key_path: String,
+/// A byte range of a file on disk, read (and optionally compressed) as it is
+/// sent rather than loaded into memory up front.
+struct StaticFile {
+    path: PathBuf,
+    start: u64,
+    len: u64,
+    encoding: Option<ContentEncoding>,
 }
 
-struct PlainStreamWithAddr {
-    addr: SocketAddr,
-    stream: tokio::net::TcpStream,
+#[derive(Clone, Copy, PartialEq)]
+enum ContentEncoding {
+    Gzip,
+    Brotli,
 }
 
-impl PlainStreamWithAddr {
-    fn remote_addr(&self) -> SocketAddr {
-        self.addr
+impl ContentEncoding {
+    fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Brotli => "br",
+        }
     }
 }
 
-impl AsyncRead for PlainStreamWithAddr {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>,
-    ) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+struct SseOptions {
+    keep_alive: Option<Duration>,
+    retry: Option<u64>,
+}
+
+/// Connection and protocol limits for the HTTP server.
+#[derive(Debug, Clone, Copy)]
+pub struct ServerOptions {
+    /// Connections served at once; further ones wait to be accepted
+    pub max_connections: Option<usize>,
+    pub max_header_size: Option<usize>,
+    pub max_body_size: usize,
+    /// Longest wait for data from the client, idle keep-alive time included
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    /// Longest a handler may run before the client gets a 503
+    pub request_timeout: Option<Duration>,
+    /// Statements one handler may run before it fails with a 500
+    pub max_steps: Option<u64>,
+    /// Bytes one handler may allocate before it fails with a 500
+    pub max_memory: Option<usize>,
+    pub keep_alive: bool,
+    /// HTTP/2 via ALPN over TLS and prior-knowledge h2c over plain TCP
+    pub http2: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_header_size: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            read_timeout: None,
+            write_timeout: None,
+            request_timeout: None,
+            max_steps: None,
+            max_memory: None,
+            keep_alive: true,
+            http2: true,
+        }
     }
 }
 
-impl AsyncWrite for PlainStreamWithAddr {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+impl ServerOptions {
+    /// What each handler's interpreter may use; the request timeout is
+    /// enforced apart, with a 503
+    fn handler_limits(&self) -> Limits {
+        Limits {
+            max_steps: self.max_steps,
+            max_memory: self.max_memory,
+            wall_timeout: None,
+        }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_connections == Some(0) {
+            return Err("Max connections must be at least 1".to_string());
+        }
+        if let Some(size) = self.max_header_size
+            && size < MIN_HEADER_SIZE
+        {
+            return Err(format!(
+                "Max header size must be at least {} bytes",
+                MIN_HEADER_SIZE
+            ));
+        }
+        Ok(())
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    fn apply_map(&mut self, options: &IndexMap<String, Value>) -> Result<(), String> {
+        let number = |key: &str| match options.get(key) {
+            None => Ok(None),
+            Some(value) => value_to_f64(value)
+                .filter(|n| *n >= 0.0)
+                .map(Some)
+                .ok_or_else(|| format!("Router.Serve option {} must be a positive number", key)),
+        };
+
+        if let Some(n) = number("MaxConnections")? {
+            self.max_connections = Some(n as usize);
+        }
+        if let Some(n) = number("MaxHeaderSize")? {
+            self.max_header_size = Some(n as usize);
+        }
+        if let Some(n) = number("MaxBodySize")? {
+            self.max_body_size = n as usize;
+        }
+        if let Some(secs) = number("ReadTimeout")? {
+            self.read_timeout = Some(Duration::from_secs_f64(secs));
+        }
+        if let Some(secs) = number("WriteTimeout")? {
+            self.write_timeout = Some(Duration::from_secs_f64(secs));
+        }
+        if let Some(secs) = number("RequestTimeout")? {
+            self.request_timeout = Some(Duration::from_secs_f64(secs));
+        }
+        if let Some(n) = number("MaxSteps")? {
+            self.max_steps = Some(n as u64);
+        }
+        if let Some(n) = number("MaxMemory")? {
+            self.max_memory = Some(n as usize);
+        }
+        if let Some(keep_alive) = options.get("KeepAlive") {
+            self.keep_alive = keep_alive.is_truthy();
+        }
+        if let Some(http2) = options.get("Http2") {
+            self.http2 = http2.is_truthy();
+        }
+        self.validate()
     }
 }
 
-struct TlsStreamWithAddr {
+// Only checked for, without the tls feature
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+struct TlsPaths {
+    cert_path: String,
+    key_path: String,
+}
+
+/// Hands every TLS handshake the current certificate, which can be swapped
+/// while the server runs (rotated cert files or an ACME renewal).
+#[cfg(feature = "tls")]
+struct ReloadableCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+#[cfg(feature = "tls")]
+impl ReloadableCert {
+    fn new(key: CertifiedKey) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(key)),
+        }
+    }
+
+    fn replace(&self, key: CertifiedKey) {
+        *self.current.write_recover() = Arc::new(key);
+    }
+}
+
+#[cfg(feature = "tls")]
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read_recover().clone())
+    }
+}
+
+type TimedTcpStream = Pin<Box<TimeoutStream<tokio::net::TcpStream>>>;
+
+/// A connection taken from the listener, holding its slot while it is open
+/// when the number of connections is limited.
+struct Accepted {
+    addr: SocketAddr,
+    stream: TimedTcpStream,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+struct PlainStreamWithAddr {
     addr: SocketAddr,
-    stream: tokio_rustls::server::TlsStream<tokio::net::TcpStream>,
+    stream: TimedTcpStream,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl PlainStreamWithAddr {
+    fn remote_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl AsyncRead for PlainStreamWithAddr {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for PlainStreamWithAddr {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "tls")]
+struct TlsStreamWithAddr {
+    addr: SocketAddr,
+    stream: tokio_rustls::server::TlsStream<TimedTcpStream>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+#[cfg(feature = "tls")]
+impl TlsStreamWithAddr {
+    fn remote_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[cfg(feature = "tls")]
+impl AsyncRead for TlsStreamWithAddr {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl AsyncWrite for TlsStreamWithAddr {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// A script may only listen where it may connect, and serve files it may
+/// read. `sfex serve` is started by whoever runs it and isn't checked.
+fn check_server(
+    addr: &str,
+    state: &Mutex<RouterState>,
+    tls: Option<&TlsPaths>,
+) -> Result<(), String> {
+    permissions::check_net(addr)?;
+    if let Some(tls) = tls {
+        permissions::check_read(&tls.cert_path)?;
+        permissions::check_read(&tls.key_path)?;
+    }
+    for mount in &state.lock_recover().static_mounts {
+        permissions::check_read(&mount.dir)?;
+    }
+    Ok(())
 }
 
-impl TlsStreamWithAddr {
-    fn remote_addr(&self) -> SocketAddr {
-        self.addr
+fn start_server(
+    addr: &str,
+    state: Arc<Mutex<RouterState>>,
+    tls: Option<TlsPaths>,
+    options: ServerOptions,
+) -> Result<(), String> {
+    let addr = addr.to_string();
+    let app = {
+        let mut state = state.lock_recover();
+        // Handlers run on other threads, with the permissions of the
+        // script that started the server
+        state.permissions = permissions::current().map(|permissions| (*permissions).clone());
+        state.max_body_size = options.max_body_size;
+        state.request_timeout = options.request_timeout;
+        state.handler_limits = options.handler_limits();
+        for tenant in &state.tenants {
+            tenant.inherit_limits(&state);
+        }
+        state.app.clone()
+    };
+    let lifecycle = Lifecycle::start(SERVE_HOOKS.get(), &app)?;
+    let runtime = executor::shared_runtime();
+
+    let running = Arc::new(AtomicBool::new(true));
+    if let Some(script) = watch_script() {
+        spawn_route_watcher(script, state.clone(), running.clone());
+    }
+
+    let server_state = state.clone();
+    let telemetry = Arc::new(Telemetry::new(telemetry_options()));
+    #[cfg(feature = "tls")]
+    let watching = running.clone();
+    let result = runtime.block_on(async move {
+        #[cfg(feature = "tls")]
+        if let Some(tls_paths) = tls {
+            let cert = Arc::new(ReloadableCert::new(load_cert_files(&tls_paths)?));
+            spawn_cert_watcher(tls_paths, cert.clone(), watching);
+            let tls_config = tls_server_config(cert, options.http2);
+            return run_server_tls(&addr, server_state, tls_config, telemetry, options).await;
+        } else if let Some(config) = ACME_CONFIG.get() {
+            let cert = start_acme(&addr, config.clone()).await?;
+            let tls_config = tls_server_config(cert, options.http2);
+            return run_server_tls(&addr, server_state, tls_config, telemetry, options).await;
+        }
+        #[cfg(not(feature = "tls"))]
+        if tls.is_some() {
+            return Err(
+                "sfex was built without the tls feature, so it can't serve HTTPS".to_string(),
+            );
+        }
+        run_server_plain(&addr, server_state, telemetry, options).await
+    });
+
+    running.store(false, Ordering::SeqCst);
+    log::info("SFX web server stopped", &[]);
+    if let Some(lifecycle) = lifecycle {
+        lifecycle.stop();
+    }
+    result
+}
+
+async fn shutdown_signal(shutdown: Arc<Notify>) {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut stream) => {
+                stream.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+        _ = shutdown.notified() => {}
+    }
+
+    log::info(
+        "SFX web server shutting down, draining in-flight requests...",
+        &[],
+    );
+}
+
+fn watch_script() -> Option<PathBuf> {
+    WATCH_SCRIPT
+        .get()
+        .and_then(|slot| slot.lock_recover().clone())
+}
+
+fn spawn_route_watcher(script: PathBuf, state: Arc<Mutex<RouterState>>, running: Arc<AtomicBool>) {
+    // The whole directory, so a module the router script uses reloads it too
+    let mut watcher = match Watcher::new(watch::script_dir(&script)) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            log::warn("Route watching disabled", &[("error", &err)]);
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        loop {
+            let changes = watcher.wait_while(|| running.load(Ordering::SeqCst));
+            if changes.is_empty() {
+                break;
+            }
+            let Some(change) = changes.iter().find(|change| watch::is_script(&change.path)) else {
+                continue;
+            };
+            if !script.exists() {
+                continue;
+            }
+
+            match reload_routes(&script, &state) {
+                Ok(()) => log::info(
+                    &format!("Reloaded routes from {}", script.display()),
+                    &[("changed", &change.path.to_string_lossy())],
+                ),
+                Err(err) => log::error(
+                    "Route reload failed, keeping previous routes",
+                    &[("error", &err)],
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(feature = "tls")]
+fn spawn_cert_watcher(paths: TlsPaths, cert: Arc<ReloadableCert>, running: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        let modified = || {
+            [&paths.cert_path, &paths.key_path]
+                .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        };
+        let mut last_modified = modified();
+
+        while running.load(Ordering::SeqCst) {
+            std::thread::sleep(watch::DEFAULT_INTERVAL);
+
+            let current = modified();
+            if current == last_modified || current.contains(&None) {
+                continue;
+            }
+            // Rotation tools write the cert and key one after the other, so
+            // wait for both to settle before loading the pair
+            std::thread::sleep(watch::DEFAULT_INTERVAL);
+            if modified() != current {
+                continue;
+            }
+            last_modified = current;
+
+            match load_cert_files(&paths) {
+                Ok(key) => {
+                    cert.replace(key);
+                    log::info(
+                        &format!("Reloaded TLS certificate from {}", paths.cert_path),
+                        &[],
+                    );
+                }
+                Err(err) => log::error(
+                    "TLS reload failed, keeping previous certificate",
+                    &[("error", &err)],
+                ),
+            }
+        }
+    });
+}
+
+/// Start answering ACME HTTP-01 challenges, load the cached certificate (or
+/// order one) and keep it renewed in the background.
+#[cfg(feature = "tls")]
+async fn start_acme(https_addr: &str, config: AcmeConfig) -> Result<Arc<ReloadableCert>, String> {
+    let challenges = Challenges::default();
+    let listener = TcpListener::bind(&config.http_addr).await.map_err(|e| {
+        format!(
+            "Failed to bind ACME challenge listener {}: {} (see --acme-http-addr)",
+            config.http_addr, e
+        )
+    })?;
+    let https_port = https_addr
+        .parse::<SocketAddr>()
+        .map(|addr| addr.port())
+        .unwrap_or(443);
+    tokio::spawn(serve_acme_challenges(
+        listener,
+        challenges.clone(),
+        https_port,
+    ));
+
+    let issued = match acme::load_cached(&config) {
+        Some(cached) => cached,
+        None => {
+            log::info(
+                &format!("Requesting certificate for {}", config.domains.join(", ")),
+                &[],
+            );
+            acme::obtain(&config, &challenges).await?
+        }
+    };
+    let cert = Arc::new(ReloadableCert::new(certified_key(
+        issued.cert_pem.as_bytes(),
+        issued.key_pem.as_bytes(),
+    )?));
+    tokio::spawn(renew_acme_cert(config, challenges, cert.clone(), issued));
+    Ok(cert)
+}
+
+#[cfg(feature = "tls")]
+async fn renew_acme_cert(
+    config: AcmeConfig,
+    challenges: Challenges,
+    cert: Arc<ReloadableCert>,
+    mut issued: IssuedCert,
+) {
+    loop {
+        let wait = issued
+            .renew_at()
+            .ok()
+            .and_then(|at| at.duration_since(SystemTime::now()).ok())
+            .unwrap_or_default();
+        // Sleep in bounded steps so a suspended machine or a changed clock
+        // doesn't push the renewal past expiry
+        if !wait.is_zero() {
+            tokio::time::sleep(wait.min(ACME_CHECK_INTERVAL)).await;
+            continue;
+        }
+
+        let renewed = acme::obtain(&config, &challenges)
+            .await
+            .and_then(|renewed| {
+                let key = certified_key(renewed.cert_pem.as_bytes(), renewed.key_pem.as_bytes())?;
+                Ok((renewed, key))
+            });
+        match renewed {
+            Ok((renewed, key)) => {
+                cert.replace(key);
+                issued = renewed;
+                log::info(
+                    &format!("Renewed certificate for {}", config.domains.join(", ")),
+                    &[],
+                );
+            }
+            Err(err) => {
+                log::error(
+                    "Certificate renewal failed, retrying in an hour",
+                    &[("error", &err)],
+                );
+                tokio::time::sleep(ACME_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Plain HTTP listener for the ACME CA; everything else is redirected to
+/// the HTTPS server.
+#[cfg(feature = "tls")]
+async fn serve_acme_challenges(listener: TcpListener, challenges: Challenges, https_port: u16) {
+    let make_svc = make_service_fn(move |_| {
+        let challenges = challenges.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                let response = acme_challenge_response(&req, &challenges, https_port);
+                async move { Ok::<_, hyper::Error>(response) }
+            }))
+        }
+    });
+
+    let incoming = hyper::server::accept::from_stream(TcpListenerStream::new(listener));
+    if let Err(err) = Server::builder(incoming).serve(make_svc).await {
+        log::error(
+            "ACME challenge listener stopped",
+            &[("error", &err.to_string())],
+        );
+    }
+}
+
+#[cfg(feature = "tls")]
+fn acme_challenge_response(
+    req: &Request<Body>,
+    challenges: &Challenges,
+    https_port: u16,
+) -> Response<Body> {
+    let path = req.uri().path();
+    if let Some(token) = path.strip_prefix(acme::CHALLENGE_PREFIX) {
+        let answer = challenges.read_recover().get(token).cloned();
+        let mut response = match answer {
+            Some(answer) => Response::new(Body::from(answer)),
+            None => {
+                let mut response = Response::new(Body::from("Not Found"));
+                *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+                response
+            }
+        };
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("text/plain"),
+        );
+        return response;
+    }
+
+    let host = req
+        .headers()
+        .get(hyper::header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| match h.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => h,
+        })
+        .unwrap_or("localhost");
+    let port = if https_port == 443 {
+        String::new()
+    } else {
+        format!(":{}", https_port)
+    };
+    let target = format!(
+        "https://{}{}{}",
+        host,
+        port,
+        req.uri().path_and_query().map_or("/", |pq| pq.as_str())
+    );
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = hyper::StatusCode::MOVED_PERMANENTLY;
+    if let Ok(location) = hyper::header::HeaderValue::from_str(&target) {
+        response
+            .headers_mut()
+            .insert(hyper::header::LOCATION, location);
+    }
+    response
+}
+
+fn reload_routes(script: &Path, state: &Arc<Mutex<RouterState>>) -> Result<(), String> {
+    let program = load_program(script)?;
+
+    RELOAD_TARGET.with(|target| *target.borrow_mut() = Some(state.clone()));
+    let mut interpreter = Interpreter::new();
+    let result = interpreter
+        .run(program)
+        .map_err(|e| format!("Runtime error: {}", e));
+    RELOAD_TARGET.with(|target| *target.borrow_mut() = None);
+
+    result
+}
+
+/// Serve a script with `--watch`. Router definition scripts (those calling
+/// `Web.Router()`) are executed and re-executed whenever they change; plain
+/// handler scripts are served as usual since handlers already reload.
+pub fn serve_watch(
+    addr: &str,
+    script_path: &str,
+    static_dir: Option<&str>,
+    tls: Option<(&str, &str)>,
+) -> Result<(), String> {
+    let script = resolve_path(script_path);
+    let program = load_program(&script)?;
+
+    if !program_uses_router(&program, &mut Vec::new()) {
+        log::info(
+            &format!(
+                "No Web.Router() in {} or the modules it uses, so it is served as a handler script without route reloading",
+                script.display()
+            ),
+            &[],
+        );
+        return match tls {
+            Some((cert, key)) => serve_tls(addr, script_path, cert, key, static_dir),
+            None => serve(addr, script_path, static_dir),
+        };
+    }
+
+    *WATCH_SCRIPT.get_or_init(|| Mutex::new(None)).lock_recover() = Some(script.clone());
+    log::info(
+        &format!("Watching {} for route changes", script.display()),
+        &[],
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(program)
+        .map_err(|e| format!("Runtime error: {}", e))
+}
+
+/// Whether `program` builds a router: in its story, a concept method or a
+/// module it uses (each module is looked at once).
+fn program_uses_router(program: &Program, seen: &mut Vec<PathBuf>) -> bool {
+    if statements_use_router(&program.story.body)
+        || program
+            .concepts
+            .iter()
+            .flat_map(|concept| &concept.methods)
+            .any(|method| statements_use_router(&method.body))
+    {
+        return true;
+    }
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    crate::compiler::cache::used_modules(program)
+        .into_iter()
+        .filter_map(|module| crate::project::resolve_module_path(module, &cwd))
+        .any(|path| {
+            if seen.contains(&path) {
+                return false;
+            }
+            seen.push(path.clone());
+            load_program(&path).is_ok_and(|module| program_uses_router(&module, seen))
+        })
+}
+
+fn statements_use_router(statements: &[Statement]) -> bool {
+    statements.iter().any(|stmt| match stmt {
+        Statement::Assignment { value, .. } | Statement::Print { value, .. } => {
+            expression_uses_router(value)
+        }
+        Statement::Set { target, value, .. } => {
+            expression_uses_router(target) || expression_uses_router(value)
+        }
+        Statement::Expression { expr, .. } => expression_uses_router(expr),
+        Statement::Create { initial_fields, .. } => initial_fields
+            .iter()
+            .any(|(_, expr)| expression_uses_router(expr)),
+        Statement::If {
+            condition,
+            then_body,
+            else_body,
+            ..
+        } => {
+            expression_uses_router(condition)
+                || statements_use_router(then_body)
+                || else_body.as_deref().is_some_and(statements_use_router)
+        }
+        Statement::When {
+            value,
+            cases,
+            otherwise,
+            ..
+        } => {
+            expression_uses_router(value)
+                || cases.iter().any(|case| statements_use_router(&case.body))
+                || otherwise.as_deref().is_some_and(statements_use_router)
+        }
+        Statement::TryCatch {
+            try_body,
+            catches,
+            always_body,
+            ..
+        } => {
+            statements_use_router(try_body)
+                || catches
+                    .iter()
+                    .any(|catch| statements_use_router(&catch.body))
+                || always_body.as_deref().is_some_and(statements_use_router)
+        }
+        Statement::RepeatTimes { body, .. }
+        | Statement::RepeatWhile { body, .. }
+        | Statement::ForEach { body, .. }
+        | Statement::WithSituation { body, .. }
+        | Statement::Batch { body, .. }
+        | Statement::Using { body, .. } => statements_use_router(body),
+        _ => false,
+    })
+}
+
+fn expression_uses_router(expr: &Expression) -> bool {
+    match expr {
+        Expression::MemberAccess { object, member } => {
+            (member == "Router"
+                && matches!(object.as_ref(), Expression::Identifier(name) if name == "Web"))
+                || expression_uses_router(object)
+        }
+        Expression::Call { callee, arguments } => {
+            expression_uses_router(callee) || arguments.iter().any(expression_uses_router)
+        }
+        Expression::MethodCall {
+            object, arguments, ..
+        } => {
+            expression_uses_router(object)
+                || arguments.iter().any(|(_, arg)| expression_uses_router(arg))
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            expression_uses_router(left) || expression_uses_router(right)
+        }
+        Expression::DoInBackground { body, .. } => statements_use_router(body),
+        Expression::CopyOf(value) => expression_uses_router(value),
+        Expression::TryOtherwise {
+            expression,
+            fallback,
+        } => expression_uses_router(expression) || expression_uses_router(fallback),
+        Expression::Interpolation(parts) => parts.iter().any(expression_uses_router),
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogFormat {
+    Common,
+    Json,
+}
+
+#[derive(Clone, Copy, Default)]
+struct TelemetryOptions {
+    log_format: Option<LogFormat>,
+    metrics: bool,
+}
+
+/// Configure access logging (`json` or `common`) and the Prometheus
+/// `/metrics` endpoint for servers started afterwards in this process.
+pub fn configure_telemetry(log_format: Option<&str>, metrics: bool) -> Result<(), String> {
+    let log_format = match log_format.map(|f| f.to_lowercase()) {
+        None => None,
+        Some(f) if f == "common" => Some(LogFormat::Common),
+        Some(f) if f == "json" => Some(LogFormat::Json),
+        Some(other) => {
+            return Err(format!(
+                "Unknown log format '{}' (expected json or common)",
+                other
+            ));
+        }
+    };
+
+    *TELEMETRY_OPTIONS
+        .get_or_init(|| Mutex::new(TelemetryOptions::default()))
+        .lock_recover() = TelemetryOptions {
+        log_format,
+        metrics,
+    };
+    Ok(())
+}
+
+fn telemetry_options() -> TelemetryOptions {
+    TELEMETRY_OPTIONS
+        .get()
+        .map(|slot| *slot.lock_recover())
+        .unwrap_or_default()
+}
+
+/// Set the connection limits and protocol options used by servers started
+/// afterwards in this process.
+pub fn configure_server(options: ServerOptions) -> Result<(), String> {
+    options.validate()?;
+    *SERVER_OPTIONS
+        .get_or_init(|| Mutex::new(ServerOptions::default()))
+        .lock_recover() = options;
+    Ok(())
+}
+
+fn server_options() -> ServerOptions {
+    SERVER_OPTIONS
+        .get()
+        .map(|slot| *slot.lock_recover())
+        .unwrap_or_default()
+}
+
+/// Serve HTTPS with certificates ordered from an ACME CA (Let's Encrypt)
+/// whenever no cert/key files are given.
+#[cfg(feature = "tls")]
+pub fn configure_acme(config: AcmeConfig) -> Result<(), String> {
+    if config.domains.is_empty() {
+        return Err("--acme-domain needs at least one domain".to_string());
+    }
+    ACME_CONFIG
+        .set(config)
+        .map_err(|_| "ACME is already configured".to_string())
+}
+
+#[derive(Default)]
+struct ServeHooks {
+    on_start: Option<PathBuf>,
+    on_stop: Option<PathBuf>,
+}
+
+pub fn configure_serve_hooks(
+    on_start: Option<PathBuf>,
+    on_stop: Option<PathBuf>,
+) -> Result<(), String> {
+    SERVE_HOOKS
+        .set(ServeHooks { on_start, on_stop })
+        .map_err(|_| "Serve hooks are already configured".to_string())
+}
+
+/// The interpreter that runs the `on_start` script before a server takes
+/// requests and the `on_stop` script once it has stopped. Both see the
+/// server's `App`, and `on_stop` also sees what `on_start` defined.
+struct Lifecycle {
+    interpreter: Interpreter,
+    on_stop: Option<PathBuf>,
+}
+
+impl Lifecycle {
+    fn start(hooks: Option<&ServeHooks>, app: &Value) -> Result<Option<Self>, String> {
+        let Some(hooks) = hooks else {
+            return Ok(None);
+        };
+        if hooks.on_start.is_none() && hooks.on_stop.is_none() {
+            return Ok(None);
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.define_global("App", app.clone());
+        let mut lifecycle = Self {
+            interpreter,
+            on_stop: hooks.on_stop.clone(),
+        };
+        if let Some(script) = &hooks.on_start {
+            lifecycle
+                .run(script)
+                .map_err(|e| format!("on_start failed, not serving: {}", e))?;
+        }
+        Ok(Some(lifecycle))
+    }
+
+    fn stop(mut self) {
+        if let Some(script) = self.on_stop.take()
+            && let Err(e) = self.run(&script)
+        {
+            log::error("on_stop failed", &[("error", &e)]);
+        }
+    }
+
+    fn run(&mut self, script: &Path) -> Result<(), String> {
+        let program = load_program(script)?;
+        self.interpreter
+            .run(program)
+            .map_err(|e| format!("Runtime error in {}: {}", script.display(), e))
+    }
+}
+
+/// Serve the dev inspect page at `/__sfex/inspect` from servers started
+/// afterwards in this process. Returns the token a request for it must give,
+/// as `?token=` or an `X-Sfex-Token` header.
+pub fn configure_dev() -> Result<String, String> {
+    let token = format!("{:032x}", rand::random::<u128>());
+    DEV_TOKEN
+        .set(token.clone())
+        .map_err(|_| "Dev mode is already configured".to_string())?;
+    Ok(token)
+}
+
+fn inspect_token(req: &Request<Body>) -> Option<String> {
+    if let Some(token) = req
+        .headers()
+        .get("x-sfex-token")
+        .and_then(|v| v.to_str().ok())
+    {
+        return Some(token.to_string());
+    }
+    parse_query(req.uri().query().unwrap_or("")).remove("token")
+}
+
+/// App.State, the route table, each handler's cached program and recent
+/// handler errors, newest first, and the same for each tenant.
+fn inspect(state: &Arc<Mutex<RouterState>>) -> serde_json::Value {
+    let tenants = state.lock_recover().tenants.clone();
+    let tenants: Vec<serde_json::Value> = tenants
+        .iter()
+        .map(|tenant| {
+            serde_json::json!({
+                "host": tenant.host,
+                "modules": tenant.state.lock_recover().modules,
+                "max_concurrent": tenant.max_concurrent,
+                "running": tenant.pool.as_ref().zip(tenant.max_concurrent).map(|(pool, limit)| limit - pool.available_permits()),
+                "router": inspect(&tenant.state),
+            })
+        })
+        .collect();
+
+    let (
+        app,
+        routes,
+        proxies,
+        static_mounts,
+        middleware,
+        fallback,
+        not_found,
+        on_compile_error,
+        errors,
+    ) = {
+        let state = state.lock_recover();
+        let errors: Vec<serde_json::Value> = state
+            .recent_errors
+            .iter()
+            .rev()
+            .map(|error| {
+                serde_json::json!({
+                    "time": error.time.to_rfc3339(),
+                    "route": error.route,
+                    "message": error.message,
+                })
+            })
+            .collect();
+        (
+            state.app.clone(),
+            state.routes.clone(),
+            state.proxies.clone(),
+            state.static_mounts.clone(),
+            state.middleware.clone(),
+            state.fallback.clone(),
+            state.not_found.clone(),
+            state.on_compile_error.clone(),
+            errors,
+        )
+    };
+
+    let app_state = match &app {
+        Value::Map(map) => map.read_recover().get("State").cloned(),
+        _ => None,
+    }
+    .map(|value| convert_object_to_json(&value))
+    .unwrap_or(serde_json::Value::Null);
+
+    // The same handler file can serve several routes
+    let mut handlers: Vec<&Arc<ScriptHandler>> = Vec::new();
+    let all = routes
+        .iter()
+        .map(|route| &route.handler)
+        .chain(&middleware)
+        .chain(&fallback)
+        .chain(&not_found)
+        .chain(&on_compile_error);
+    for handler in all {
+        if !handlers.iter().any(|seen| Arc::ptr_eq(seen, handler)) {
+            handlers.push(handler);
+        }
+    }
+
+    serde_json::json!({
+        "app_state": app_state,
+        "routes": routes.iter().map(|route| serde_json::json!({
+            "method": route.method.as_deref().unwrap_or("ANY"),
+            "path": route.path,
+            "handler": route.handler.describe(),
+        })).collect::<Vec<_>>(),
+        "proxies": proxies.iter().map(|proxy| serde_json::json!({
+            "path": proxy.path,
+            "upstream": proxy.upstream.as_str(),
+        })).collect::<Vec<_>>(),
+        "static": static_mounts.iter().map(|mount| serde_json::json!({
+            "path": mount.mount_path,
+            "dir": mount.dir.display().to_string(),
+        })).collect::<Vec<_>>(),
+        "middleware": middleware.iter().map(|handler| handler.describe()).collect::<Vec<_>>(),
+        "fallback": fallback.as_ref().map(|handler| handler.describe()),
+        "not_found": not_found.as_ref().map(|handler| handler.describe()),
+        "on_compile_error": on_compile_error.as_ref().map(|handler| handler.describe()),
+        "handlers": handlers.iter().map(|handler| handler.inspect()).collect::<Vec<_>>(),
+        "recent_errors": errors,
+        "tenants": tenants,
+    })
+}
+
+struct AccessEntry<'a> {
+    remote_addr: &'a str,
+    method: &'a str,
+    path: &'a str,
+    version: &'a str,
+    route: &'a str,
+    status: u16,
+    bytes: Option<usize>,
+    duration: Duration,
+}
+
+struct Telemetry {
+    log_format: Option<LogFormat>,
+    metrics: Option<Mutex<Metrics>>,
+}
+
+struct Metrics {
+    started: Instant,
+    requests: HashMap<(String, String, u16), u64>,
+    latencies: HashMap<String, LatencyStats>,
+}
+
+#[derive(Default)]
+struct LatencyStats {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Telemetry {
+    fn new(options: TelemetryOptions) -> Self {
+        Self {
+            log_format: options.log_format,
+            metrics: options.metrics.then(|| {
+                Mutex::new(Metrics {
+                    started: Instant::now(),
+                    requests: HashMap::new(),
+                    latencies: HashMap::new(),
+                })
+            }),
+        }
+    }
+
+    fn serves_metrics(&self, method: &str, path: &str) -> bool {
+        self.metrics.is_some() && (method == "GET" || method == "HEAD") && path == METRICS_PATH
+    }
+
+    fn record(&self, entry: &AccessEntry) {
+        match self.log_format {
+            Some(LogFormat::Common) => println!("{}", format_common_log(entry)),
+            Some(LogFormat::Json) => println!("{}", format_json_log(entry)),
+            None => {}
+        }
+
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock_recover();
+            *metrics
+                .requests
+                .entry((
+                    entry.method.to_string(),
+                    entry.route.to_string(),
+                    entry.status,
+                ))
+                .or_insert(0) += 1;
+
+            let seconds = entry.duration.as_secs_f64();
+            let stats = metrics
+                .latencies
+                .entry(entry.route.to_string())
+                .or_default();
+            for (bucket, bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                if seconds <= bound {
+                    *bucket += 1;
+                }
+            }
+            stats.count += 1;
+            stats.sum += seconds;
+        }
+    }
+
+    fn render_metrics(&self) -> String {
+        let Some(metrics) = &self.metrics else {
+            return String::new();
+        };
+        let metrics = metrics.lock_recover();
+        let mut out = String::new();
+
+        out.push_str("# HELP sfex_uptime_seconds Seconds since the server started.\n");
+        out.push_str("# TYPE sfex_uptime_seconds gauge\n");
+        out.push_str(&format!(
+            "sfex_uptime_seconds {:.3}\n",
+            metrics.started.elapsed().as_secs_f64()
+        ));
+
+        out.push_str("# HELP sfex_http_requests_total Total HTTP requests handled.\n");
+        out.push_str("# TYPE sfex_http_requests_total counter\n");
+        let mut requests: Vec<_> = metrics.requests.iter().collect();
+        requests.sort_by(|a, b| a.0.cmp(b.0));
+        for ((method, route, status), count) in requests {
+            out.push_str(&format!(
+                "sfex_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}\n",
+                escape_label(method),
+                escape_label(route),
+                status,
+                count
+            ));
+        }
+
+        out.push_str("# HELP sfex_http_request_duration_seconds Request latency per route.\n");
+        out.push_str("# TYPE sfex_http_request_duration_seconds histogram\n");
+        let mut latencies: Vec<_> = metrics.latencies.iter().collect();
+        latencies.sort_by(|a, b| a.0.cmp(b.0));
+        for (route, stats) in latencies {
+            let route = escape_label(route);
+            for (bucket, bound) in stats.buckets.iter().zip(LATENCY_BUCKETS) {
+                out.push_str(&format!(
+                    "sfex_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}\n",
+                    route, bound, bucket
+                ));
+            }
+            out.push_str(&format!(
+                "sfex_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}\n",
+                route, stats.count
+            ));
+            out.push_str(&format!(
+                "sfex_http_request_duration_seconds_sum{{route=\"{}\"}} {:.6}\n",
+                route, stats.sum
+            ));
+            out.push_str(&format!(
+                "sfex_http_request_duration_seconds_count{{route=\"{}\"}} {}\n",
+                route, stats.count
+            ));
+        }
+
+        out
     }
 }
 
-impl AsyncRead for TlsStreamWithAddr {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>,
-    ) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
-    }
+fn format_common_log(entry: &AccessEntry) -> String {
+    let host = entry
+        .remote_addr
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|_| entry.remote_addr.to_string());
+    let bytes = entry
+        .bytes
+        .map(|b| b.to_string())
+        .unwrap_or_else(|| "-".to_string());
+
+    format!(
+        "{} - - [{}] \"{} {} {}\" {} {} {:.3}ms",
+        host,
+        chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        entry.method,
+        entry.path,
+        entry.version,
+        entry.status,
+        bytes,
+        entry.duration.as_secs_f64() * 1000.0
+    )
 }
 
-impl AsyncWrite for TlsStreamWithAddr {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
-    }
+fn format_json_log(entry: &AccessEntry) -> String {
+    serde_json::json!({
+        "time": chrono::Local::now().to_rfc3339(),
+        "remote_addr": entry.remote_addr,
+        "method": entry.method,
+        "path": entry.path,
+        "route": entry.route,
+        "status": entry.status,
+        "bytes": entry.bytes,
+        "duration_ms": (entry.duration.as_secs_f64() * 1_000_000.0).round() / 1000.0,
+    })
+    .to_string()
+}
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
-    }
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
 
-    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+/// Accept connections, first waiting for a free slot when the number of
+/// open connections is limited.
+fn accept_connections(
+    listener: TcpListener,
+    options: ServerOptions,
+) -> impl futures_util::Stream<Item = io::Result<Accepted>> {
+    let slots = options
+        .max_connections
+        .map(|limit| Arc::new(Semaphore::new(limit)));
+
+    futures_util::stream::unfold((listener, slots), move |(listener, slots)| async move {
+        let permit = match &slots {
+            Some(slots) => slots.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        let accepted = listener.accept().await.map(|(stream, addr)| {
+            let mut stream = TimeoutStream::new(stream);
+            stream.set_read_timeout(options.read_timeout);
+            stream.set_write_timeout(options.write_timeout);
+            Accepted {
+                addr,
+                stream: Box::pin(stream),
+                permit,
+            }
+        });
+        Some((accepted, (listener, slots)))
+    })
+}
+
+fn tune_server<I>(
+    builder: hyper::server::Builder<I>,
+    options: &ServerOptions,
+) -> hyper::server::Builder<I> {
+    let mut builder = builder
+        .http1_keepalive(options.keep_alive)
+        .http1_only(!options.http2);
+    if let Some(size) = options.max_header_size {
+        builder = builder
+            .http1_max_buf_size(size)
+            .http2_max_header_list_size(u32::try_from(size).unwrap_or(u32::MAX));
     }
+    builder
 }
 
-fn start_server(
+async fn run_server_plain(
     addr: &str,
     state: Arc<Mutex<RouterState>>,
-    tls: Option<TlsPaths>,
+    telemetry: Arc<Telemetry>,
+    options: ServerOptions,
 ) -> Result<(), String> {
-    let addr = addr.to_string();
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .map_err(|e| format!("Failed to start async runtime: {}", e))?;
-
-    runtime.block_on(async move {
-        if let Some(tls_paths) = tls {
-            let config = load_tls_config(&tls_paths)?;
-            run_server_tls(&addr, state, config).await
-        } else {
-            run_server_plain(&addr, state).await
-        }
-    })
-}
-
-async fn run_server_plain(addr: &str, state: Arc<Mutex<RouterState>>) -> Result<(), String> {
     let listener = TcpListener::bind(addr)
         .await
         .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
-    println!("SFX web server listening on http://{}", addr);
-
-    let incoming = TcpListenerStream::new(listener).map(|conn| {
-        conn.map(|stream| {
-            let addr = stream
-                .peer_addr()
-                .unwrap_or_else(|_| "0.0.0.0:0".parse().unwrap());
-            PlainStreamWithAddr { addr, stream }
+    log::info(&format!("SFX web server listening on http://{}", addr), &[]);
+
+    let incoming = accept_connections(listener, options).map(|conn| {
+        conn.map(|conn| PlainStreamWithAddr {
+            addr: conn.addr,
+            stream: conn.stream,
+            _permit: conn.permit,
         })
     });
 
+    let shutdown = state.lock_recover().shutdown.clone();
     let make_svc = make_service_fn(move |conn: &PlainStreamWithAddr| {
         let state = state.clone();
+        let telemetry = telemetry.clone();
         let remote = conn.remote_addr().to_string();
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
-                handle_http_request(req, state.clone(), remote.clone())
+                handle_http_request(req, state.clone(), telemetry.clone(), remote.clone())
             }))
         }
     });
 
-    Server::builder(hyper::server::accept::from_stream(incoming))
-        .serve(make_svc)
-        .await
-        .map_err(|e| format!("Server error: {}", e))
+    tune_server(
+        Server::builder(hyper::server::accept::from_stream(incoming)),
+        &options,
+    )
+    .serve(make_svc)
+    .with_graceful_shutdown(shutdown_signal(shutdown))
+    .await
+    .map_err(|e| format!("Server error: {}", e))
 }
 
+#[cfg(feature = "tls")]
 async fn run_server_tls(
     addr: &str,
     state: Arc<Mutex<RouterState>>,
     tls_config: Arc<ServerConfig>,
+    telemetry: Arc<Telemetry>,
+    options: ServerOptions,
 ) -> Result<(), String> {
     let listener = TcpListener::bind(addr)
         .await
         .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
-    println!("SFX web server listening on https://{}", addr);
+    log::info(
+        &format!("SFX web server listening on https://{}", addr),
+        &[],
+    );
 
     let acceptor = TlsAcceptor::from(tls_config);
-    let incoming = TcpListenerStream::new(listener).then(move |conn| {
-        let acceptor = acceptor.clone();
-        async move {
-            let stream = conn?;
-            let addr = stream
-                .peer_addr()
-                .unwrap_or_else(|_| "0.0.0.0:0".parse().unwrap());
-            let tls_stream = acceptor
-                .accept(stream)
-                .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            Ok::<_, io::Error>(TlsStreamWithAddr {
-                addr,
-                stream: tls_stream,
-            })
-        }
-    });
+    let incoming = accept_connections(listener, options)
+        .map(move |conn| {
+            let acceptor = acceptor.clone();
+            async move {
+                let conn = match conn {
+                    Ok(conn) => conn,
+                    Err(err) => return Some(Err(err)),
+                };
+                // A client that fails the handshake (or times out in it) only
+                // loses its own connection
+                let stream = acceptor.accept(conn.stream).await.ok()?;
+                Some(Ok::<_, io::Error>(TlsStreamWithAddr {
+                    addr: conn.addr,
+                    stream,
+                    _permit: conn.permit,
+                }))
+            }
+        })
+        .buffer_unordered(TLS_HANDSHAKES)
+        .filter_map(std::future::ready);
 
+    let shutdown = state.lock_recover().shutdown.clone();
     let make_svc = make_service_fn(move |conn: &TlsStreamWithAddr| {
         let state = state.clone();
+        let telemetry = telemetry.clone();
         let remote = conn.remote_addr().to_string();
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
-                handle_http_request(req, state.clone(), remote.clone())
+                handle_http_request(req, state.clone(), telemetry.clone(), remote.clone())
             }))
         }
     });
 
-    Server::builder(hyper::server::accept::from_stream(incoming))
-        .http2_only(false)
-        .serve(make_svc)
-        .await
-        .map_err(|e| format!("Server error: {}", e))
+    tune_server(
+        Server::builder(hyper::server::accept::from_stream(incoming)),
+        &options,
+    )
+    .serve(make_svc)
+    .with_graceful_shutdown(shutdown_signal(shutdown))
+    .await
+    .map_err(|e| format!("Server error: {}", e))
 }
 
 async fn handle_http_request(
     req: Request<Body>,
     state: Arc<Mutex<RouterState>>,
+    telemetry: Arc<Telemetry>,
     remote_addr: String,
 ) -> Result<Response<Body>, hyper::Error> {
-    let request = match build_request_context(req, remote_addr).await {
-        Ok(request) => request,
-        Err(err) => {
-            let response = ResponseData::new(400, format!("Bad Request: {}", err).into_bytes());
-            return Ok(build_hyper_response(response));
-        }
+    let started = Instant::now();
+    let method = req.method().as_str().to_uppercase();
+    let raw_path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let version = format!("{:?}", req.version());
+
+    if telemetry.serves_metrics(&method, req.uri().path()) {
+        let mut response = ResponseData::new(200, telemetry.render_metrics().into_bytes());
+        response.headers.insert(
+            "Content-Type".to_string(),
+            "text/plain; version=0.0.4; charset=utf-8".to_string(),
+        );
+        return Ok(build_hyper_response(response));
+    }
+
+    if let Some(token) = DEV_TOKEN.get()
+        && req.uri().path() == INSPECT_PATH
+    {
+        let response = if inspect_token(&req).as_deref() == Some(token.as_str()) {
+            let body = serde_json::to_vec_pretty(&inspect(&state)).unwrap_or_default();
+            let mut response = ResponseData::new(200, body);
+            response.headers.insert(
+                "Content-Type".to_string(),
+                "application/json; charset=utf-8".to_string(),
+            );
+            response
+                .headers
+                .insert("Cache-Control".to_string(), "no-store".to_string());
+            response
+        } else {
+            ResponseData::new(403, b"Forbidden".to_vec())
+        };
+        return Ok(build_hyper_response(response));
+    }
+
+    // A request for a tenant's host is served by the tenant's router alone
+    let tenant = request_host(&req).and_then(|host| {
+        let state = state.lock_recover();
+        state
+            .tenants
+            .iter()
+            .find(|tenant| tenant.matches(&host))
+            .cloned()
+    });
+    let (state, pool) = match tenant {
+        Some(tenant) => (tenant.state, tenant.pool),
+        None => (state, None),
+    };
+
+    let proxy = {
+        let state = state.lock_recover();
+        state
+            .proxies
+            .iter()
+            .find(|proxy| proxy.pattern.matches(req.uri().path()).is_some())
+            .cloned()
+    };
+    if let Some(proxy) = proxy {
+        let response = proxy_request(req, &proxy, &remote_addr).await;
+        telemetry.record(&AccessEntry {
+            remote_addr: &remote_addr,
+            method: &method,
+            path: &raw_path,
+            version: &version,
+            route: &proxy.path,
+            status: response.status().as_u16(),
+            bytes: None,
+            duration: started.elapsed(),
+        });
+        return Ok(response);
+    }
+
+    let (max_body_size, request_timeout) = {
+        let state = state.lock_recover();
+        (state.max_body_size, state.request_timeout)
+    };
+    let context = build_request_context(req, remote_addr.clone(), max_body_size).await;
+    let (mut response, route) = match context {
+        Ok(request) => run_handler(request, state, request_timeout, pool).await,
+        Err(RequestError::TooLarge) => (
+            ResponseData::new(413, b"Payload Too Large".to_vec()),
+            "payload_too_large".to_string(),
+        ),
+        Err(RequestError::Invalid(err)) => (
+            ResponseData::new(400, format!("Bad Request: {}", err).into_bytes()),
+            "bad_request".to_string(),
+        ),
     };
 
-    let is_head = request.method == "HEAD";
-    let mut response = handle_request(&request, state);
-    if is_head {
+    if method == "HEAD" {
         response.body = ResponseBody::Bytes(Vec::new());
     }
+
+    telemetry.record(&AccessEntry {
+        remote_addr: &remote_addr,
+        method: &method,
+        path: &raw_path,
+        version: &version,
+        route: &route,
+        status: response.status,
+        bytes: match &response.body {
+            ResponseBody::Bytes(body) => Some(body.len()),
+            ResponseBody::File(file) if file.encoding.is_none() => Some(file.len as usize),
+            ResponseBody::Stream(_) | ResponseBody::EventStream(..) | ResponseBody::File(_) => None,
+        },
+        duration: started.elapsed(),
+    });
+
     Ok(build_hyper_response(response))
 }
 
+/// Scripts block (HTTP calls, Time.Sleep, Task.Await), so they run on the
+/// blocking pool instead of stalling an async worker. Past the request
+/// timeout the client gets a 503, and the deadline set on the handler thread
+/// makes the script and its stdlib calls give up rather than linger.
+///
+/// A tenant with a `pool` runs only as many handlers at once as it has
+/// slots. A request waits for a free slot, and the wait counts towards its
+/// timeout; the slot is held until the script has actually finished.
+async fn run_handler(
+    request: RequestContext,
+    state: Arc<Mutex<RouterState>>,
+    timeout: Option<Duration>,
+    pool: Option<Arc<Semaphore>>,
+) -> (ResponseData, String) {
+    let Some(timeout) = timeout else {
+        let slot = match pool {
+            Some(pool) => pool.acquire_owned().await.ok(),
+            None => None,
+        };
+        return tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            handle_request(&request, state)
+        })
+        .await
+        .unwrap_or_else(handler_failed);
+    };
+
+    let handler_deadline = Deadline::after(timeout);
+    let slot = match pool {
+        Some(pool) => match tokio::time::timeout(timeout, pool.acquire_owned()).await {
+            Ok(slot) => slot.ok(),
+            Err(_) => return timed_out(),
+        },
+        None => None,
+    };
+    let handler = tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        deadline::scope(Some(handler_deadline), || handle_request(&request, state))
+    });
+    match tokio::time::timeout(handler_deadline.remaining(), handler).await {
+        // A handler cut short by the deadline may finish with an error just
+        // before the timer fires; it still timed out
+        Ok(_) if handler_deadline.is_expired() => timed_out(),
+        Ok(joined) => joined.unwrap_or_else(handler_failed),
+        Err(_) => timed_out(),
+    }
+}
+
+fn timed_out() -> (ResponseData, String) {
+    (
+        ResponseData::new(503, b"Service Unavailable".to_vec()),
+        "timeout".to_string(),
+    )
+}
+
+fn handler_failed(err: tokio::task::JoinError) -> (ResponseData, String) {
+    (
+        ResponseData::new(500, format!("Handler failed: {}", err).into_bytes()),
+        "error".to_string(),
+    )
+}
+
+// Connection-level headers that must not be forwarded by a proxy (RFC 9110 7.6.1)
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Forward a request upstream, streaming the request body out and the
+/// response body back without buffering either.
+async fn proxy_request(
+    req: Request<Body>,
+    proxy: &ProxyRoute,
+    remote_addr: &str,
+) -> Response<Body> {
+    let client = PROXY_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            // Redirects are passed through to the browser untouched
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to create proxy client")
+    });
+
+    let (parts, body) = req.into_parts();
+    let raw_path = parts
+        .uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let url = proxy.target_url(raw_path);
+
+    // hyper 0.14 and reqwest use different `http` crate versions
+    let method = reqwest::Method::from_bytes(parts.method.as_str().as_bytes())
+        .unwrap_or(reqwest::Method::GET);
+    let mut upstream = client.request(method, &url);
+    for (name, value) in parts.headers.iter() {
+        if name == hyper::header::HOST || HOP_BY_HOP_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        upstream = upstream.header(name.as_str(), value.as_bytes());
+    }
+
+    let client_ip = remote_addr
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|_| remote_addr.to_string());
+    let forwarded_for = match parts
+        .headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(existing) => format!("{}, {}", existing, client_ip),
+        None => client_ip,
+    };
+    upstream = upstream.header("X-Forwarded-For", forwarded_for);
+    if let Some(host) = parts.headers.get(hyper::header::HOST) {
+        upstream = upstream.header("X-Forwarded-Host", host.as_bytes());
+    }
+    upstream = upstream.header(
+        "X-Forwarded-Proto",
+        parts.uri.scheme_str().unwrap_or("http"),
+    );
+
+    let response = match upstream.body(reqwest::Body::wrap_stream(body)).send().await {
+        Ok(response) => response,
+        Err(err) => {
+            let mut response = ResponseData::new(
+                502,
+                format!("Bad Gateway: failed to reach {}: {}", proxy.upstream, err).into_bytes(),
+            );
+            response.headers.insert(
+                "Content-Type".to_string(),
+                "text/plain; charset=utf-8".to_string(),
+            );
+            return build_hyper_response(response);
+        }
+    };
+
+    let mut builder = Response::builder().status(response.status().as_u16());
+    for (name, value) in response.headers().iter() {
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+
+    let stream = response
+        .bytes_stream()
+        .map(|chunk| chunk.map_err(io::Error::other));
+    builder
+        .body(Body::wrap_stream(stream))
+        .unwrap_or_else(|_| Response::new(Body::from("Response build error")))
+}
+
+enum RequestError {
+    TooLarge,
+    Invalid(String),
+}
+
+/// Collect a request body, giving up as soon as it passes `limit` instead of
+/// buffering all of it first.
+async fn read_body(mut body: Body, limit: usize) -> Result<Bytes, RequestError> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(RequestError::TooLarge);
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk =
+            chunk.map_err(|e| RequestError::Invalid(format!("Failed to read body: {}", e)))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(RequestError::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(bytes))
+}
+
 async fn build_request_context(
     req: Request<Body>,
     remote_addr: String,
-) -> Result<RequestContext, String> {
+    max_body_size: usize,
+) -> Result<RequestContext, RequestError> {
     let (parts, body) = req.into_parts();
     let method = parts.method.as_str().to_uppercase();
     let raw_path = parts
@@ -911,12 +3012,7 @@ async fn build_request_context(
         headers.insert(key.to_lowercase(), value_str);
     }
 
-    let body_bytes = to_bytes(body)
-        .await
-        .map_err(|e| format!("Failed to read body: {}", e))?;
-    if body_bytes.len() > MAX_BODY_SIZE {
-        return Err("Body too large".to_string());
-    }
+    let body_bytes = read_body(body, max_body_size).await?;
 
     let query_map = parse_query(query);
     let cookies = headers
@@ -955,9 +3051,42 @@ fn build_hyper_response(response: ResponseData) -> Response<Body> {
                 .body(body)
                 .unwrap_or_else(|_| Response::new(Body::from("Response build error")))
         }
+        ResponseBody::EventStream(stream_value, options) => {
+            let body = build_event_stream_body(stream_value, options);
+            builder
+                .body(body)
+                .unwrap_or_else(|_| Response::new(Body::from("Response build error")))
+        }
+        ResponseBody::File(file) => builder
+            .body(build_file_body(file))
+            .unwrap_or_else(|_| Response::new(Body::from("Response build error"))),
     }
 }
 
+fn build_file_body(file: StaticFile) -> Body {
+    use async_compression::Level;
+    use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder};
+    use futures_util::TryStreamExt;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+    let chunks = futures_util::stream::once(async move {
+        let mut handle = tokio::fs::File::open(&file.path).await?;
+        handle.seek(io::SeekFrom::Start(file.start)).await?;
+        let reader = BufReader::new(handle.take(file.len));
+        let reader: Pin<Box<dyn AsyncRead + Send>> = match file.encoding {
+            Some(ContentEncoding::Gzip) => Box::pin(GzipEncoder::new(reader)),
+            // Brotli's default level is far too slow to run per request
+            Some(ContentEncoding::Brotli) => {
+                Box::pin(BrotliEncoder::with_quality(reader, Level::Precise(4)))
+            }
+            None => Box::pin(reader),
+        };
+        Ok::<_, io::Error>(tokio_util::io::ReaderStream::new(reader))
+    })
+    .try_flatten();
+    Body::wrap_stream(chunks)
+}
+
 fn normalize_response_headers(response: &ResponseData) -> HashMap<String, String> {
     let mut headers = response.headers.clone();
 
@@ -968,7 +3097,9 @@ fn normalize_response_headers(response: &ResponseData) -> HashMap<String, String
         );
     }
 
-    if matches!(response.body, ResponseBody::Bytes(_)) {
+    if matches!(response.body, ResponseBody::File(_)) {
+        // try_static sets Content-Length itself unless the body is compressed
+    } else if matches!(response.body, ResponseBody::Bytes(_)) {
         if !header_exists(&headers, "Content-Length") {
             if let ResponseBody::Bytes(body) = &response.body {
                 headers.insert("Content-Length".to_string(), body.len().to_string());
@@ -981,6 +3112,9 @@ fn normalize_response_headers(response: &ResponseData) -> HashMap<String, String
     headers
 }
 
+// Each chunk is handed to hyper as its own body frame, and hyper flushes the
+// connection whenever the producer has nothing ready, so chunks are never held
+// back waiting for later ones.
 fn build_stream_body(stream_value: Value) -> Body {
     let (sender, receiver) = tokio::sync::mpsc::channel::<Result<Bytes, io::Error>>(8);
     tokio::task::spawn_blocking(move || {
@@ -991,70 +3125,231 @@ fn build_stream_body(stream_value: Value) -> Body {
     Body::wrap_stream(stream)
 }
 
-fn handle_request(request: &RequestContext, state: Arc<Mutex<RouterState>>) -> ResponseData {
-    let (routes, middleware, static_mounts, not_found, fallback, runtime) = {
-        let state = state.lock().expect("lock poisoned");
+fn build_event_stream_body(stream_value: Value, options: SseOptions) -> Body {
+    let (sender, receiver) = tokio::sync::mpsc::channel::<Bytes>(8);
+    if let Some(retry) = options.retry {
+        let _ = sender.try_send(Bytes::from(format!("retry: {}\n\n", retry)));
+    }
+    tokio::task::spawn_blocking(move || {
+        let _ = send_sse_events(stream_value, sender);
+    });
+
+    // Interleave ": ping" comments while the script stream is idle so proxies
+    // and browsers keep the connection open.
+    let keep_alive = options.keep_alive;
+    let frames = futures_util::stream::unfold(receiver, move |mut receiver| async move {
+        let next = match keep_alive {
+            Some(interval) => match tokio::time::timeout(interval, receiver.recv()).await {
+                Ok(frame) => frame,
+                Err(_) => Some(Bytes::from_static(b": ping\n\n")),
+            },
+            None => receiver.recv().await,
+        };
+        next.map(|frame| (Ok::<_, io::Error>(frame), receiver))
+    });
+    Body::wrap_stream(frames)
+}
+
+fn send_sse_events(
+    stream_value: Value,
+    sender: tokio::sync::mpsc::Sender<Bytes>,
+) -> Result<(), String> {
+    loop {
+        let Some(value) = stream_next_value(&stream_value)? else {
+            break;
+        };
+        if sender
+            .blocking_send(Bytes::from(format_sse_event(value)))
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Frame a stream item as an SSE event. Maps with `Data`, `Event`, `Id` or
+/// `Retry` keys set those fields; anything else becomes the `data:` payload.
+fn format_sse_event(value: Value) -> String {
+    let mut frame = String::new();
+
+    let data = match &value {
+        Value::Map(map) => {
+            let map = map.read_recover();
+            let is_event = ["Data", "Event", "Id", "Retry"]
+                .iter()
+                .any(|key| map.contains_key(*key));
+            if is_event {
+                if let Some(id) = map.get("Id") {
+                    frame.push_str(&format!("id: {}\n", single_line(&id.to_display_string())));
+                }
+                if let Some(event) = map.get("Event") {
+                    frame.push_str(&format!(
+                        "event: {}\n",
+                        single_line(&event.to_display_string())
+                    ));
+                }
+                if let Some(retry) = map.get("Retry") {
+                    frame.push_str(&format!("retry: {}\n", retry.to_display_string()));
+                }
+                map.get("Data").cloned().map(chunk_bytes_from_value)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let data = data.unwrap_or_else(|| {
+        if frame.is_empty() {
+            chunk_bytes_from_value(value)
+        } else {
+            Vec::new()
+        }
+    });
+
+    let data = String::from_utf8_lossy(&data);
+    if !data.is_empty() || frame.is_empty() {
+        for line in data.split('\n') {
+            frame.push_str("data: ");
+            frame.push_str(line.strip_suffix('\r').unwrap_or(line));
+            frame.push('\n');
+        }
+    }
+    frame.push('\n');
+    frame
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// Dispatch a request, returning the response along with the route label it
+/// was served by (the route pattern, or "static", "fallback", "not_found").
+fn handle_request(
+    request: &RequestContext,
+    state: Arc<Mutex<RouterState>>,
+) -> (ResponseData, String) {
+    let (mut response, route) = dispatch_request(request, state.clone());
+    if route != "static" {
+        let mounts = state.lock_recover().static_mounts.clone();
+        fingerprint_response(&mut response, &mounts);
+    }
+    (response, route)
+}
+
+fn dispatch_request(
+    request: &RequestContext,
+    state: Arc<Mutex<RouterState>>,
+) -> (ResponseData, String) {
+    let (routes, middleware, static_mounts, not_found, fallback, on_compile_error, runtime) = {
+        let state = state.lock_recover();
+        let runtime = ScriptRuntime {
+            runtime: executor::shared_runtime(),
+            // Tenants share the server, so their handlers can't stop it
+            shutdown: match state.tenant_host {
+                Some(_) => None,
+                None => Some(state.shutdown.clone()),
+            },
+            app: state.app.clone(),
+            modules: state.modules.clone(),
+            permissions: state.permissions.clone(),
+            limits: state.handler_limits,
+        };
         (
             state.routes.clone(),
             state.middleware.clone(),
             state.static_mounts.clone(),
             state.not_found.clone(),
             state.fallback.clone(),
-            state.runtime.clone(),
+            state.on_compile_error.clone(),
+            runtime,
         )
     };
+    let failed = |route: &str, params: &HashMap<String, String>, err: ScriptError| match err {
+        ScriptError::Runtime(message) => {
+            log::error(
+                "Handler failed",
+                &[
+                    ("method", &request.method),
+                    ("path", &request.path),
+                    ("route", route),
+                    ("error", &message),
+                ],
+            );
+            state.lock_recover().record_error(route, &message);
+            ResponseData::new(500, message.into_bytes())
+        }
+        ScriptError::Compile(handler, failure) => {
+            state.lock_recover().record_error(route, &failure.message);
+            compile_error_response(
+                &handler,
+                &failure,
+                on_compile_error.as_ref(),
+                request,
+                params,
+                &runtime,
+            )
+        }
+    };
 
-    if let Some(response) = try_static(&request.path, &static_mounts) {
-        return response;
+    if let Some(response) = try_static(request, &static_mounts) {
+        return (response, "static".to_string());
     }
 
-    if let Some((handler, params)) = find_route(&routes, &request.method, &request.path) {
+    if let Some((route, params)) = find_route(&routes, &request.method, &request.path) {
+        let label = route.path.clone();
         if let Ok(Some(response)) = run_middleware(&middleware, request, &params, &runtime) {
-            return response;
+            return (response, label);
         }
 
-        return match execute_script(&handler, request, &params, &runtime) {
+        let response = match execute_script(&route.handler, request, &params, &runtime) {
             Ok(Some(response)) => response,
             Ok(None) => ResponseData::new(204, Vec::new()),
-            Err(err) => ResponseData::new(500, err.into_bytes()),
+            Err(err) => failed(&label, &params, err),
         };
+        return (response, label);
     }
 
     if let Some(handler) = fallback {
         let empty_params = HashMap::new();
         if let Ok(Some(response)) = run_middleware(&middleware, request, &empty_params, &runtime) {
-            return response;
+            return (response, "fallback".to_string());
         }
 
-        return match execute_script(&handler, request, &empty_params, &runtime) {
+        let response = match execute_script(&handler, request, &empty_params, &runtime) {
             Ok(Some(response)) => response,
             Ok(None) => ResponseData::new(204, Vec::new()),
-            Err(err) => ResponseData::new(500, err.into_bytes()),
+            Err(err) => failed("fallback", &empty_params, err),
         };
+        return (response, "fallback".to_string());
     }
 
     if let Some(handler) = not_found {
         let empty_params = HashMap::new();
         if let Ok(Some(response)) = run_middleware(&middleware, request, &empty_params, &runtime) {
-            return response;
+            return (response, "not_found".to_string());
         }
 
-        return match execute_script(&handler, request, &empty_params, &runtime) {
+        let response = match execute_script(&handler, request, &empty_params, &runtime) {
             Ok(Some(response)) => response,
             Ok(None) => ResponseData::new(404, b"Not Found".to_vec()),
-            Err(err) => ResponseData::new(500, err.into_bytes()),
+            Err(err) => failed("not_found", &empty_params, err),
         };
+        return (response, "not_found".to_string());
     }
 
-    ResponseData::new(404, b"Not Found".to_vec())
+    (
+        ResponseData::new(404, b"Not Found".to_vec()),
+        "not_found".to_string(),
+    )
 }
 
 fn run_middleware(
     middleware: &[Arc<ScriptHandler>],
     request: &RequestContext,
     params: &HashMap<String, String>,
-    runtime: &Arc<tokio::runtime::Runtime>,
-) -> Result<Option<ResponseData>, String> {
+    runtime: &ScriptRuntime,
+) -> Result<Option<ResponseData>, ScriptError> {
     for handler in middleware {
         if let Some(response) = execute_script(handler, request, params, runtime)? {
             return Ok(Some(response));
@@ -1063,11 +3358,11 @@ fn run_middleware(
     Ok(None)
 }
 
-fn find_route(
-    routes: &[Route],
+fn find_route<'a>(
+    routes: &'a [Route],
     method: &str,
     path: &str,
-) -> Option<(Arc<ScriptHandler>, HashMap<String, String>)> {
+) -> Option<(&'a Route, HashMap<String, String>)> {
     let method = method.to_uppercase();
     let method_lookup = if method == "HEAD" {
         "GET"
@@ -1083,42 +3378,197 @@ fn find_route(
         }
 
         if let Some(params) = route.pattern.matches(path) {
-            return Some((route.handler.clone(), params));
+            return Some((route, params));
         }
     }
 
     None
 }
 
+enum ScriptError {
+    /// The handler file doesn't compile
+    Compile(Arc<ScriptHandler>, CompileFailure),
+    Runtime(String),
+}
+
+impl From<String> for ScriptError {
+    fn from(message: String) -> Self {
+        ScriptError::Runtime(message)
+    }
+}
+
 fn execute_script(
     handler: &Arc<ScriptHandler>,
     request: &RequestContext,
     params: &HashMap<String, String>,
-    runtime: &Arc<tokio::runtime::Runtime>,
-) -> Result<Option<ResponseData>, String> {
-    let program = handler.ensure_current()?;
-    let mut interpreter = Interpreter::new_with_shared_runtime(runtime.clone());
+    runtime: &ScriptRuntime,
+) -> Result<Option<ResponseData>, ScriptError> {
+    execute_script_with(handler, request, params, runtime, None)
+}
+
+/// Run a handler, with `global` defined as well when given.
+fn execute_script_with(
+    handler: &Arc<ScriptHandler>,
+    request: &RequestContext,
+    params: &HashMap<String, String>,
+    runtime: &ScriptRuntime,
+    global: Option<(&str, Value)>,
+) -> Result<Option<ResponseData>, ScriptError> {
+    let (program, template) = handler
+        .ensure_current()
+        .map_err(|failure| ScriptError::Compile(handler.clone(), failure))?;
+    let mut interpreter = Interpreter::new_with_shared_runtime(runtime.runtime.clone());
+    if let Some(modules) = &runtime.modules {
+        interpreter.restrict_modules(modules);
+    }
+    if let Some(permissions) = &runtime.permissions {
+        interpreter.set_permissions(permissions.clone());
+    }
+    interpreter.set_limits(runtime.limits);
+    if let Some((name, value)) = global {
+        interpreter.define_global(name, value);
+    }
 
     interpreter.define_global("Request", build_request_value(request, params));
     interpreter.define_global("Params", build_params_value(params));
     interpreter.define_global("Response", Value::Boolean(false));
+    interpreter.define_global("Server", build_server_value(runtime.shutdown.clone()));
+    interpreter.define_global("App", runtime.app.clone());
+
+    // A panicking handler fails only its own request; the server keeps going
+    let result =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| interpreter.run(program)));
+    handler.state.lock_recover().situations = interpreter.active_situations.clone();
+    result
+        .map_err(|payload| {
+            format!(
+                "Runtime error: handler panicked: {}",
+                panic_message(payload.as_ref())
+            )
+        })?
+        .map_err(|e| format!("Runtime error: {}", e))?;
+
+    if let Some(response) = interpreter.get_global("Response")
+        && !matches!(response, Value::Boolean(false))
+    {
+        return Ok(Some(response_from_value(&response)?));
+    }
+
+    match template {
+        Some(template) => Ok(Some(render_page(handler, &template, &interpreter)?)),
+        None => Ok(None),
+    }
+}
+
+/// The response to a request whose handler doesn't compile: the
+/// OnCompileError handler's, else a page showing the error under `--dev`,
+/// else a plain 500.
+fn compile_error_response(
+    handler: &ScriptHandler,
+    failure: &CompileFailure,
+    on_compile_error: Option<&Arc<ScriptHandler>>,
+    request: &RequestContext,
+    params: &HashMap<String, String>,
+    runtime: &ScriptRuntime,
+) -> ResponseData {
+    if let Some(hook) = on_compile_error {
+        let global = ("CompileError", failure.to_value(handler));
+        match execute_script_with(hook, request, params, runtime, Some(global)) {
+            Ok(Some(response)) => return response,
+            Ok(None) => {}
+            Err(ScriptError::Runtime(message)) => {
+                log::error("OnCompileError handler failed", &[("error", &message)])
+            }
+            Err(ScriptError::Compile(_, hook_failure)) => log::error(
+                "OnCompileError handler failed",
+                &[("error", &hook_failure.message)],
+            ),
+        }
+    }
+
+    if DEV_TOKEN.get().is_none() {
+        return ResponseData::new(500, failure.message.clone().into_bytes());
+    }
 
-    interpreter
-        .run(program)
-        .map_err(|e| format!("Runtime error: {}", e))?;
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Compile error</title></head>\n<body>\n<h1>{} does not compile</h1>\n<p>{}</p>\n",
+        template::escape_html(&handler.describe()),
+        template::escape_html(&failure.message)
+    );
+    if let Some(snippet) = &failure.snippet {
+        html.push_str(&format!("<pre>{}</pre>\n", template::escape_html(snippet)));
+    }
+    html.push_str("<p>Save a fix and reload; the handler is compiled again on every request until it works.</p>\n</body>\n</html>\n");
 
-    if let Some(response) = interpreter.get_global("Response") {
-        if matches!(response, Value::Boolean(false)) {
-            return Ok(None);
-        }
-        return Ok(Some(response_from_value(&response)?));
+    let mut response = ResponseData::new(500, html.into_bytes());
+    response.headers.insert(
+        "Content-Type".to_string(),
+        "text/html; charset=utf-8".to_string(),
+    );
+    response
+}
+
+/// Render a page route's HTML with the variables its code block left behind.
+fn render_page(
+    handler: &ScriptHandler,
+    template: &str,
+    interpreter: &Interpreter,
+) -> Result<ResponseData, String> {
+    let mut data = IndexMap::new();
+    for (name, value) in interpreter.env.bindings() {
+        data.entry(name.to_string())
+            .or_insert_with(|| value.clone());
     }
+    let name = format!(
+        "{} ({})",
+        handler.path.display(),
+        handler.page_route.as_deref().unwrap_or("/")
+    );
+    let dir = handler.path.parent().unwrap_or_else(|| Path::new("."));
+    let html = template::render_source(
+        template,
+        &name,
+        dir,
+        Value::Map(Arc::new(RwLock::new(data))),
+    )?;
+
+    let mut response = ResponseData::new(200, html.into_bytes());
+    response.headers.insert(
+        "Content-Type".to_string(),
+        "text/html; charset=utf-8".to_string(),
+    );
+    Ok(response)
+}
 
-    Ok(None)
+fn build_app_value() -> Value {
+    let mut app = IndexMap::new();
+    app.insert(
+        "State".to_string(),
+        Value::Map(Arc::new(RwLock::new(IndexMap::new()))),
+    );
+    Value::Map(Arc::new(RwLock::new(app)))
+}
+
+fn build_server_value(shutdown: Option<Arc<Notify>>) -> Value {
+    let mut server_map = IndexMap::new();
+    server_map.insert(
+        "Stop".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(move |args| {
+            if !args.is_empty() {
+                return Err("Server.Stop takes no arguments".to_string());
+            }
+            let Some(shutdown) = &shutdown else {
+                return Err("Server.Stop is not available to a tenant's handlers".to_string());
+            };
+            shutdown.notify_one();
+            Ok(Value::Boolean(true))
+        }))),
+    );
+    Value::Map(Arc::new(RwLock::new(server_map)))
 }
 
 fn build_request_value(request: &RequestContext, params: &HashMap<String, String>) -> Value {
-    let mut request_map = HashMap::new();
+    let mut request_map = IndexMap::new();
 
     request_map.insert("Method".to_string(), Value::String(request.method.clone()));
     request_map.insert("Path".to_string(), Value::String(request.path.clone()));
@@ -1138,7 +3588,7 @@ fn build_request_value(request: &RequestContext, params: &HashMap<String, String
     let headers_value = build_headers_value(&request.headers_raw, &request.headers);
     request_map.insert("Headers".to_string(), headers_value);
 
-    let mut query_map = HashMap::new();
+    let mut query_map = IndexMap::new();
     for (key, value) in &request.query {
         query_map.insert(key.clone(), Value::String(value.clone()));
     }
@@ -1147,7 +3597,7 @@ fn build_request_value(request: &RequestContext, params: &HashMap<String, String
         Value::Map(Arc::new(RwLock::new(query_map))),
     );
 
-    let mut cookies_map = HashMap::new();
+    let mut cookies_map = IndexMap::new();
     for (key, value) in &request.cookies {
         cookies_map.insert(key.clone(), Value::String(value.clone()));
     }
@@ -1158,6 +3608,10 @@ fn build_request_value(request: &RequestContext, params: &HashMap<String, String
 
     let body = String::from_utf8_lossy(&request.body).to_string();
     request_map.insert("Body".to_string(), Value::String(body));
+    request_map.insert(
+        "BodyBytes".to_string(),
+        Value::Bytes(Bytes::copy_from_slice(&request.body)),
+    );
 
     request_map.insert("Params".to_string(), build_params_value(params));
 
@@ -1178,7 +3632,7 @@ fn build_headers_value(
     headers_raw: &HashMap<String, String>,
     headers_lower: &HashMap<String, String>,
 ) -> Value {
-    let mut headers_map = HashMap::new();
+    let mut headers_map = IndexMap::new();
     for (key, value) in headers_raw {
         headers_map.insert(key.clone(), Value::String(value.clone()));
     }
@@ -1192,7 +3646,7 @@ fn build_headers_value(
 }
 
 fn build_params_value(params: &HashMap<String, String>) -> Value {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     for (key, value) in params {
         map.insert(key.clone(), Value::String(value.clone()));
     }
@@ -1210,8 +3664,8 @@ fn response_from_value(value: &Value) -> Result<ResponseData, String> {
 
     match value {
         Value::Map(map) => response_from_map(map),
-        Value::List(_) | Value::Vector(_) => {
-            let json_value = value_to_json(value);
+        Value::List(_) | Value::Vector(_) | Value::Matrix(_) => {
+            let json_value = convert_object_to_json(value);
             let json_body = serde_json::to_string(&json_value).unwrap_or_else(|_| "[]".to_string());
             let mut response = ResponseData::new(200, json_body.into_bytes());
             response.headers.insert(
@@ -1221,8 +3675,17 @@ fn response_from_value(value: &Value) -> Result<ResponseData, String> {
             Ok(response)
         }
         Value::String(s) => Ok(ResponseData::new(200, s.as_bytes().to_vec())),
+        Value::Bytes(bytes) => {
+            let mut response = ResponseData::new(200, bytes.to_vec());
+            response.headers.insert(
+                "Content-Type".to_string(),
+                "application/octet-stream".to_string(),
+            );
+            Ok(response)
+        }
         Value::Boolean(b) => Ok(ResponseData::new(200, b.to_string().into_bytes())),
         Value::Number(n) => Ok(ResponseData::new(200, n.to_string().into_bytes())),
+        Value::Integer(i) => Ok(ResponseData::new(200, i.to_string().into_bytes())),
         Value::FastNumber(f) => Ok(ResponseData::new(200, f.to_string().into_bytes())),
         Value::Error(err) => Ok(ResponseData::new(
             500,
@@ -1235,8 +3698,28 @@ fn response_from_value(value: &Value) -> Result<ResponseData, String> {
     }
 }
 
-fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<ResponseData, String> {
-    let map = map.read().expect("lock poisoned");
+fn sse_options_from_value(value: &Value) -> SseOptions {
+    let mut options = SseOptions {
+        keep_alive: Some(SSE_KEEP_ALIVE),
+        retry: None,
+    };
+    if let Value::Map(map) = value {
+        let map = map.read_recover();
+        if let Some(seconds) = map.get("KeepAlive") {
+            options.keep_alive = match seconds {
+                Value::Boolean(false) => None,
+                other => value_to_f64(other)
+                    .filter(|s| *s > 0.0)
+                    .map(Duration::from_secs_f64),
+            };
+        }
+        options.retry = map.get("Retry").and_then(value_to_f64).map(|ms| ms as u64);
+    }
+    options
+}
+
+fn response_from_map(map: &Arc<RwLock<IndexMap<String, Value>>>) -> Result<ResponseData, String> {
+    let map = map.read_recover();
     if is_stream_map(&map) {
         return Ok(ResponseData {
             status: 200,
@@ -1253,7 +3736,7 @@ fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<Respon
         || map.contains_key("Stream");
 
     if !is_response_map {
-        let json_value = value_to_json(&Value::Map(Arc::new(RwLock::new(map.clone()))));
+        let json_value = convert_object_to_json(&Value::Map(Arc::new(RwLock::new(map.clone()))));
         let json_body = serde_json::to_string(&json_value).unwrap_or_else(|_| "{}".to_string());
         let mut response = ResponseData::new(200, json_body.into_bytes());
         response.headers.insert(
@@ -1271,7 +3754,7 @@ fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<Respon
 
     let mut headers = HashMap::new();
     if let Some(Value::Map(header_map)) = map.get("Headers") {
-        let header_map = header_map.read().expect("lock poisoned");
+        let header_map = header_map.read_recover();
         for (key, value) in header_map.iter() {
             headers.insert(key.clone(), value.to_display_string());
         }
@@ -1302,10 +3785,16 @@ fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<Respon
 
     if let Some(stream_value) = map.get("Stream") {
         if is_stream_value(stream_value) {
+            let body = match map.get("EventStream") {
+                Some(options) => {
+                    ResponseBody::EventStream(stream_value.clone(), sse_options_from_value(options))
+                }
+                None => ResponseBody::Stream(stream_value.clone()),
+            };
             return Ok(ResponseData {
                 status,
                 headers,
-                body: ResponseBody::Stream(stream_value.clone()),
+                body,
             });
         }
     }
@@ -1323,8 +3812,8 @@ fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<Respon
     }
 
     let mut response = match body_value {
-        Value::List(_) | Value::Vector(_) | Value::Map(_) => {
-            let json_value = value_to_json(&body_value);
+        Value::List(_) | Value::Vector(_) | Value::Matrix(_) | Value::Map(_) => {
+            let json_value = convert_object_to_json(&body_value);
             let json_body = serde_json::to_string(&json_value).unwrap_or_else(|_| "{}".to_string());
             let response = ResponseData::new(status, json_body.into_bytes());
             if !header_exists(&headers, "Content-Type") {
@@ -1336,8 +3825,18 @@ fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<Respon
             response
         }
         Value::String(s) => ResponseData::new(status, s.into_bytes()),
+        Value::Bytes(bytes) => {
+            if !header_exists(&headers, "Content-Type") {
+                headers.insert(
+                    "Content-Type".to_string(),
+                    "application/octet-stream".to_string(),
+                );
+            }
+            ResponseData::new(status, bytes.to_vec())
+        }
         Value::Boolean(b) => ResponseData::new(status, b.to_string().into_bytes()),
         Value::Number(n) => ResponseData::new(status, n.to_string().into_bytes()),
+        Value::Integer(i) => ResponseData::new(status, i.to_string().into_bytes()),
         Value::FastNumber(f) => ResponseData::new(status, f.to_string().into_bytes()),
         other => ResponseData::new(status, other.to_display_string().into_bytes()),
     };
@@ -1349,14 +3848,14 @@ fn response_from_map(map: &Arc<RwLock<HashMap<String, Value>>>) -> Result<Respon
 fn is_stream_value(value: &Value) -> bool {
     match value {
         Value::Map(map) => {
-            let map = map.read().expect("lock poisoned");
+            let map = map.read_recover();
             map.get("Next").map(is_native_fn).unwrap_or(false)
         }
         _ => false,
     }
 }
 
-fn is_stream_map(map: &HashMap<String, Value>) -> bool {
+fn is_stream_map(map: &IndexMap<String, Value>) -> bool {
     map.get("Next").map(is_native_fn).unwrap_or(false)
 }
 
@@ -1387,7 +3886,7 @@ fn stream_next_value(stream_value: &Value) -> Result<Option<Value>, String> {
         return Err("Stream value must be a Map".to_string());
     };
     let next_fn = {
-        let map = map.read().expect("lock poisoned");
+        let map = map.read_recover();
         map.get("Next").cloned()
     }
     .ok_or_else(|| "Stream is missing Next".to_string())?;
@@ -1403,14 +3902,15 @@ fn stream_next_value(stream_value: &Value) -> Result<Option<Value>, String> {
 
 fn chunk_bytes_from_value(value: Value) -> Vec<u8> {
     match value {
-        Value::List(_) | Value::Vector(_) | Value::Map(_) => {
-            serde_json::to_string(&value_to_json(&value))
+        Value::List(_) | Value::Vector(_) | Value::Matrix(_) | Value::Map(_) => {
+            serde_json::to_string(&convert_object_to_json(&value))
                 .unwrap_or_else(|_| value.to_display_string())
                 .into_bytes()
         }
         Value::Error(err) => {
             format!("Error.{}.{}: {}", err.category, err.subtype, err.message).into_bytes()
         }
+        Value::Bytes(bytes) => bytes.to_vec(),
         other => other.to_display_string().into_bytes(),
     }
 }
@@ -1484,24 +3984,330 @@ fn hex_value(byte: u8) -> Option<u8> {
     }
 }
 
-fn try_static(path: &str, mounts: &[StaticMount]) -> Option<ResponseData> {
+fn try_static(request: &RequestContext, mounts: &[StaticMount]) -> Option<ResponseData> {
     for mount in mounts {
-        if let Some(relative_path) = strip_mount(path, &mount.mount_path) {
-            if let Some(file_path) = resolve_static_path(&mount.dir, &relative_path) {
-                if let Ok(bytes) = fs::read(&file_path) {
-                    let mut response = ResponseData::new(200, bytes);
-                    response.headers.insert(
-                        "Content-Type".to_string(),
-                        guess_mime_type(&file_path).to_string(),
-                    );
-                    return Some(response);
-                }
+        let Some(relative_path) = strip_mount(&request.path, &mount.mount_path) else {
+            continue;
+        };
+        if let Some(file_path) = resolve_static_path(&mount.dir, &relative_path) {
+            if mount.fingerprint && guess_mime_type(&file_path).starts_with("text/html") {
+                return fingerprinted_html(request, &file_path, mounts);
+            }
+            if let Ok(metadata) = fs::metadata(&file_path) {
+                return Some(static_response(request, mount, file_path, &metadata));
+            }
+        }
+
+        // `app.3fa2c1d9.css` is `app.css`. A stale hash still gets the
+        // current file, just without the far-future cache headers
+        if mount.fingerprint
+            && let Some((plain, hash)) = assets::split_fingerprint(&relative_path)
+            && let Some(file_path) = resolve_static_path(&mount.dir, &plain)
+            && let Ok(metadata) = fs::metadata(&file_path)
+        {
+            let current = assets::fingerprint(&file_path).is_some_and(|current| current == hash);
+            let mut response = static_response(request, mount, file_path, &metadata);
+            if current {
+                response.headers.insert(
+                    "Cache-Control".to_string(),
+                    assets::IMMUTABLE_CACHE.to_string(),
+                );
             }
+            return Some(response);
         }
     }
     None
 }
 
+/// An HTML file from a fingerprinted mount, with its links to fingerprinted
+/// assets rewritten. The page itself keeps its name, so it is always
+/// revalidated; its ETag follows the rewritten contents.
+fn fingerprinted_html(
+    request: &RequestContext,
+    path: &Path,
+    mounts: &[StaticMount],
+) -> Option<ResponseData> {
+    let html = fs::read_to_string(path).ok()?;
+    let body = fingerprint_links(&html, mounts).into_bytes();
+    let etag = format!("\"{}\"", assets::content_hash(&body));
+
+    let mut response = if etag_matches(request, &etag) {
+        ResponseData::new(304, Vec::new())
+    } else {
+        let mut response = ResponseData::new(200, body);
+        response.headers.insert(
+            "Content-Type".to_string(),
+            guess_mime_type(path).to_string(),
+        );
+        response
+    };
+    response.headers.insert("ETag".to_string(), etag);
+    response
+        .headers
+        .insert("Cache-Control".to_string(), "no-cache".to_string());
+    Some(response)
+}
+
+/// Point the `src` and `href` links in `html` that name a file under a
+/// fingerprinted mount at the file's fingerprinted name.
+fn fingerprint_links(html: &str, mounts: &[StaticMount]) -> String {
+    assets::rewrite_urls(html, |url| {
+        if !url.starts_with('/') || url.ends_with('/') {
+            return None;
+        }
+        mounts
+            .iter()
+            .filter(|mount| mount.fingerprint)
+            .find_map(|mount| {
+                let relative = strip_mount(url, &mount.mount_path)?;
+                let file = resolve_static_path(&mount.dir, &relative)?;
+                Some(assets::fingerprinted_path(
+                    url,
+                    &assets::fingerprint(&file)?,
+                ))
+            })
+    })
+}
+
+/// Rewrite the links in an HTML page a handler returned, when some static
+/// mount is fingerprinted.
+fn fingerprint_response(response: &mut ResponseData, mounts: &[StaticMount]) {
+    if !mounts.iter().any(|mount| mount.fingerprint) {
+        return;
+    }
+    let is_html = response.headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("content-type") && value.starts_with("text/html")
+    });
+    let ResponseBody::Bytes(body) = &response.body else {
+        return;
+    };
+    if !is_html {
+        return;
+    }
+    if let Ok(html) = std::str::from_utf8(body) {
+        response.body = ResponseBody::Bytes(fingerprint_links(html, mounts).into_bytes());
+        response
+            .headers
+            .retain(|name, _| !name.eq_ignore_ascii_case("content-length"));
+    }
+}
+
+fn static_response(
+    request: &RequestContext,
+    mount: &StaticMount,
+    path: PathBuf,
+    metadata: &fs::Metadata,
+) -> ResponseData {
+    let size = metadata.len();
+    let modified = metadata.modified().ok();
+    let mtime = modified
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let etag = format!("W/\"{:x}-{:x}\"", size, mtime.as_nanos());
+    let mime = guess_mime_type(&path);
+
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), mime.to_string());
+    headers.insert("ETag".to_string(), etag.clone());
+    headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+    headers.insert(
+        "Cache-Control".to_string(),
+        match mount.max_age {
+            Some(seconds) => format!("public, max-age={}", seconds),
+            None => "no-cache".to_string(),
+        },
+    );
+    if modified.is_some() {
+        headers.insert("Last-Modified".to_string(), http_date(mtime.as_secs()));
+    }
+
+    if is_not_modified(request, &etag, mtime.as_secs()) {
+        let mut response = ResponseData::new(304, Vec::new());
+        headers.remove("Content-Type");
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        response.headers = headers;
+        return response;
+    }
+
+    // If-Range: only honour the Range when the client's copy is current
+    let range_header = request.headers.get("range").filter(|_| {
+        request
+            .headers
+            .get("if-range")
+            .is_none_or(|validator| validator == &etag)
+    });
+    if let Some(range) = range_header {
+        match parse_byte_range(range, size) {
+            Some(Ok((start, end))) => {
+                headers.insert(
+                    "Content-Range".to_string(),
+                    format!("bytes {}-{}/{}", start, end, size),
+                );
+                headers.insert("Content-Length".to_string(), (end - start + 1).to_string());
+                return ResponseData {
+                    status: 206,
+                    headers,
+                    body: ResponseBody::File(StaticFile {
+                        path,
+                        start,
+                        len: end - start + 1,
+                        encoding: None,
+                    }),
+                };
+            }
+            Some(Err(())) => {
+                let mut response = ResponseData::new(416, Vec::new());
+                headers.insert("Content-Range".to_string(), format!("bytes */{}", size));
+                headers.remove("Content-Type");
+                response.headers = headers;
+                return response;
+            }
+            // Multiple ranges or a malformed header: serve the whole file
+            None => {}
+        }
+    }
+
+    let compressible = mount.compress && size >= MIN_COMPRESS_SIZE && is_compressible(mime);
+    let encoding = if compressible {
+        headers.insert("Vary".to_string(), "Accept-Encoding".to_string());
+        request
+            .headers
+            .get("accept-encoding")
+            .and_then(|accept| negotiate_encoding(accept))
+    } else {
+        None
+    };
+    match encoding {
+        Some(encoding) => {
+            headers.insert("Content-Encoding".to_string(), encoding.name().to_string());
+        }
+        None => {
+            headers.insert("Content-Length".to_string(), size.to_string());
+        }
+    }
+
+    ResponseData {
+        status: 200,
+        headers,
+        body: ResponseBody::File(StaticFile {
+            path,
+            start: 0,
+            len: size,
+            encoding,
+        }),
+    }
+}
+
+// If-None-Match wins over If-Modified-Since when both are sent (RFC 9110 13.2.2)
+fn is_not_modified(request: &RequestContext, etag: &str, mtime_secs: u64) -> bool {
+    if request.headers.contains_key("if-none-match") {
+        return etag_matches(request, etag);
+    }
+
+    request
+        .headers
+        .get("if-modified-since")
+        .and_then(|since| chrono::DateTime::parse_from_rfc2822(since).ok())
+        .is_some_and(|since| since.timestamp() >= mtime_secs as i64)
+}
+
+// Weak comparison against If-None-Match; false when it wasn't sent
+fn etag_matches(request: &RequestContext, etag: &str) -> bool {
+    let weak_tag = etag.trim_start_matches("W/");
+    request
+        .headers
+        .get("if-none-match")
+        .is_some_and(|candidates| {
+            candidates
+                .split(',')
+                .map(str::trim)
+                .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == weak_tag)
+        })
+}
+
+/// Parse a single `bytes=` range against a file of `size` bytes.
+/// None means the header should be ignored; Some(Err) means unsatisfiable.
+fn parse_byte_range(header: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // bytes=-500 is the last 500 bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return Some(Err(()));
+        }
+        (size.saturating_sub(suffix), size.checked_sub(1)?)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => size.saturating_sub(1),
+            end => end.parse::<u64>().ok()?.min(size.saturating_sub(1)),
+        };
+        (start, end)
+    };
+
+    if size == 0 || range.0 >= size || range.0 > range.1 {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
+
+fn is_compressible(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || ["json", "javascript", "xml", "svg", "wasm"]
+            .iter()
+            .any(|kind| mime.contains(kind))
+}
+
+// Prefer brotli, then gzip, skipping anything the client gave q=0
+fn negotiate_encoding(accept: &str) -> Option<ContentEncoding> {
+    let mut gzip = false;
+    let mut brotli = false;
+    for entry in accept.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let refused = parts.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        if refused {
+            continue;
+        }
+        match name.as_str() {
+            "br" => brotli = true,
+            "gzip" | "x-gzip" => gzip = true,
+            "*" => {
+                brotli = true;
+                gzip = true;
+            }
+            _ => {}
+        }
+    }
+
+    if brotli {
+        Some(ContentEncoding::Brotli)
+    } else if gzip {
+        Some(ContentEncoding::Gzip)
+    } else {
+        None
+    }
+}
+
+fn http_date(unix_secs: u64) -> String {
+    chrono::DateTime::from_timestamp(unix_secs as i64, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
 fn strip_mount(path: &str, mount_path: &str) -> Option<String> {
     if mount_path == "/" {
         return Some(path.trim_start_matches('/').to_string());
@@ -1538,16 +4344,18 @@ fn resolve_static_path(base: &Path, relative: &str) -> Option<PathBuf> {
     }
 }
 
-fn load_tls_config(paths: &TlsPaths) -> Result<Arc<ServerConfig>, String> {
+#[cfg(feature = "tls")]
+fn load_cert_files(paths: &TlsPaths) -> Result<CertifiedKey, String> {
     let cert_file = fs::read(&paths.cert_path)
         .map_err(|e| format!("Failed to read cert {}: {}", paths.cert_path, e))?;
     let key_file = fs::read(&paths.key_path)
         .map_err(|e| format!("Failed to read key {}: {}", paths.key_path, e))?;
+    certified_key(&cert_file, &key_file)
+}
 
-    let mut cert_reader = std::io::Cursor::new(cert_file);
-    let mut key_reader = std::io::Cursor::new(key_file);
-
-    let certs = certs(&mut cert_reader)
+#[cfg(feature = "tls")]
+fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> Result<CertifiedKey, String> {
+    let certs = certs(&mut std::io::Cursor::new(cert_pem))
         .map_err(|_| "Failed to parse certificate".to_string())?
         .into_iter()
         .map(Certificate)
@@ -1556,18 +4364,14 @@ fn load_tls_config(paths: &TlsPaths) -> Result<Arc<ServerConfig>, String> {
         return Err("No certificates found".to_string());
     }
 
-    let mut keys = pkcs8_private_keys(&mut key_reader)
+    let mut keys = pkcs8_private_keys(&mut std::io::Cursor::new(key_pem))
         .map_err(|_| "Failed to parse private key".to_string())?
         .into_iter()
         .map(PrivateKey)
         .collect::<Vec<_>>();
 
     if keys.is_empty() {
-        let mut key_reader = std::io::Cursor::new(
-            fs::read(&paths.key_path)
-                .map_err(|e| format!("Failed to read key {}: {}", paths.key_path, e))?,
-        );
-        keys = rsa_private_keys(&mut key_reader)
+        keys = rsa_private_keys(&mut std::io::Cursor::new(key_pem))
             .map_err(|_| "Failed to parse RSA key".to_string())?
             .into_iter()
             .map(PrivateKey)
@@ -1578,29 +4382,145 @@ fn load_tls_config(paths: &TlsPaths) -> Result<Arc<ServerConfig>, String> {
         .into_iter()
         .next()
         .ok_or_else(|| "No private keys found".to_string())?;
+    let signing_key = any_supported_type(&key).map_err(|e| format!("TLS config error: {}", e))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
 
+#[cfg(feature = "tls")]
+fn tls_server_config(cert: Arc<ReloadableCert>, http2: bool) -> Arc<ServerConfig> {
     let mut config = ServerConfig::builder()
         .with_safe_defaults()
         .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|e| format!("TLS config error: {}", e))?;
+        .with_cert_resolver(cert);
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
 
-    Ok(Arc::new(config))
+    Arc::new(config)
 }
 
 fn load_program(path: &Path) -> Result<Program, String> {
-    let source = fs::read_to_string(path)
-        .map_err(|e| format!("Failed to read handler '{}': {}", path.display(), e))?;
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer
-        .tokenize()
-        .map_err(|e| format!("Lexer error in '{}': {}", path.display(), e))?;
-    let mut parser = Parser::new(tokens);
-    parser
-        .parse()
-        .map_err(|e| format!("Parser error in '{}': {}", path.display(), e))
+    compile_handler(path).map_err(|failure| failure.message)
+}
+
+fn compile_handler(path: &Path) -> Result<Program, CompileFailure> {
+    let source = fs::read_to_string(path).map_err(|e| {
+        CompileFailure::new(format!(
+            "Failed to read handler '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let edition = crate::project::edition_for(path).map_err(CompileFailure::new)?;
+    if let Some(program) = crate::compiler::cache::lookup(path, &source, edition) {
+        return Ok(program);
+    }
+    let mut lexer = Lexer::with_edition(&source, edition);
+    let tokens = lexer.tokenize().map_err(|e| {
+        CompileFailure::at(
+            format!("Lexer error in '{}': {}", path.display(), e),
+            &source,
+            e.line,
+            e.column,
+        )
+    })?;
+    let mut parser = Parser::with_edition(tokens, edition);
+    parser.parse().map_err(|e| {
+        let (line, column) = e.location();
+        CompileFailure::at(
+            format!("Parser error in '{}': {}", path.display(), e),
+            &source,
+            line,
+            column,
+        )
+    })
+}
+
+/// Why a handler file could not be turned into a program.
+#[derive(Clone)]
+struct CompileFailure {
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+    // The lines around the error, with a caret under its column
+    snippet: Option<String>,
+}
+
+impl CompileFailure {
+    fn new(message: String) -> Self {
+        Self {
+            message,
+            line: None,
+            column: None,
+            snippet: None,
+        }
+    }
+
+    fn at(message: String, source: &str, line: usize, column: usize) -> Self {
+        Self {
+            message,
+            line: Some(line),
+            column: Some(column),
+            snippet: source_snippet(source, line, column),
+        }
+    }
+
+    /// The `CompileError` map an OnCompileError handler gets.
+    fn to_value(&self, handler: &ScriptHandler) -> Value {
+        let mut map = IndexMap::new();
+        map.insert("Message".to_string(), Value::String(self.message.clone()));
+        map.insert("File".to_string(), Value::String(handler.describe()));
+        for (key, value) in [("Line", self.line), ("Column", self.column)] {
+            if let Some(value) = value {
+                map.insert(
+                    key.to_string(),
+                    Value::Number(bigdecimal::BigDecimal::from(value as u64)),
+                );
+            }
+        }
+        if let Some(snippet) = &self.snippet {
+            map.insert("Snippet".to_string(), Value::String(snippet.clone()));
+        }
+        Value::Map(Arc::new(RwLock::new(map)))
+    }
+}
+
+/// Up to two lines before `line` and the line itself, numbered, with a caret
+/// under `column`.
+fn source_snippet(source: &str, line: usize, column: usize) -> Option<String> {
+    let lines: Vec<&str> = source.lines().collect();
+    if line == 0 || line > lines.len() {
+        return None;
+    }
+    let first = line.saturating_sub(2).max(1);
+    let width = line.to_string().len();
+    let mut snippet = String::new();
+    for number in first..=line {
+        snippet.push_str(&format!(
+            "{:>width$} | {}\n",
+            number,
+            lines[number - 1],
+            width = width
+        ));
+    }
+    // Tabs are kept and wide characters such as 日 take two cells, so the
+    // caret lines up in a terminal
+    let text = lines[line - 1];
+    let mut pad: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| match c {
+            '\t' => "\t".to_string(),
+            c => " ".repeat(c.width().unwrap_or(0)),
+        })
+        .collect();
+    pad.push_str(&" ".repeat(column.saturating_sub(1 + text.chars().count())));
+    snippet.push_str(&format!("{:>width$} | {}^", "", pad, width = width));
+    Some(snippet)
 }
 
 fn resolve_path(path: &str) -> PathBuf {
@@ -1643,6 +4563,7 @@ fn split_path(path: &str) -> Vec<String> {
 fn value_to_status(value: &Value) -> Option<u16> {
     match value {
         Value::Number(n) => n.to_u16(),
+        Value::Integer(i) => i.to_u16(),
         Value::FastNumber(f) => {
             if f.is_finite() && *f >= 0.0 {
                 Some(*f as u16)
@@ -1655,8 +4576,18 @@ fn value_to_status(value: &Value) -> Option<u16> {
     }
 }
 
+fn value_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.to_f64(),
+        Value::Integer(i) => i.to_f64(),
+        Value::FastNumber(f) if f.is_finite() => Some(*f),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
 fn build_response_map(body: Value, status: u16, headers: Option<Value>) -> Value {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     map.insert(
         "Status".to_string(),
         Value::from_number_string(&status.to_string()).unwrap_or(Value::default_number()),
@@ -1669,7 +4600,7 @@ fn build_response_map(body: Value, status: u16, headers: Option<Value>) -> Value
 }
 
 fn build_stream_response_map(stream: Value, status: u16, headers: Option<Value>) -> Value {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     map.insert(
         "Status".to_string(),
         Value::from_number_string(&status.to_string()).unwrap_or(Value::default_number()),
@@ -1682,9 +4613,9 @@ fn build_stream_response_map(stream: Value, status: u16, headers: Option<Value>)
 }
 
 fn merge_headers(headers: Option<Value>, key: &str, value: &str) -> Value {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     if let Some(Value::Map(existing)) = headers {
-        let existing = existing.read().expect("lock poisoned");
+        let existing = existing.read_recover();
         for (k, v) in existing.iter() {
             map.insert(k.clone(), v.clone());
         }
@@ -1724,62 +4655,355 @@ fn guess_mime_type(path: &Path) -> &'static str {
     }
 }
 
-fn value_to_json(value: &Value) -> JsonValue {
-    match value {
-        Value::Number(n) => {
-            if let Some(i) = n.to_i64() {
-                JsonValue::Number(i.into())
-            } else if let Some(f) = n.to_f64() {
-                serde_json::Number::from_f64(f)
-                    .map(JsonValue::Number)
-                    .unwrap_or_else(|| JsonValue::String(n.to_string()))
-            } else {
-                JsonValue::String(n.to_string())
-            }
-        }
-        Value::FastNumber(f) => serde_json::Number::from_f64(*f)
-            .map(JsonValue::Number)
-            .unwrap_or_else(|| JsonValue::String(f.to_string())),
-        Value::String(s) => JsonValue::String(s.clone()),
-        Value::Boolean(b) => JsonValue::Bool(*b),
-        Value::List(list) => {
-            let list = list.read().expect("lock poisoned");
-            JsonValue::Array(list.iter().map(value_to_json).collect())
-        }
-        Value::Vector(vec) => JsonValue::Array(
-            vec.iter()
-                .map(|v| serde_json::Number::from_f64(*v as f64))
-                .map(|n| n.map(JsonValue::Number).unwrap_or(JsonValue::Null))
-                .collect(),
-        ),
-        Value::Map(map) => {
-            let map = map.read().expect("lock poisoned");
-            let mut object = serde_json::Map::new();
-            for (key, value) in map.iter() {
-                object.insert(key.clone(), value_to_json(value));
-            }
-            JsonValue::Object(object)
-        }
-        Value::Option(opt) => match opt.as_ref() {
-            Some(inner) => value_to_json(inner),
-            None => JsonValue::Null,
-        },
-        Value::Error(err) => {
-            let mut object = serde_json::Map::new();
-            object.insert(
-                "category".to_string(),
-                JsonValue::String(err.category.clone()),
-            );
-            object.insert(
-                "subtype".to_string(),
-                JsonValue::String(err.subtype.clone()),
-            );
-            object.insert(
-                "message".to_string(),
-                JsonValue::String(err.message.clone()),
-            );
-            JsonValue::Object(object)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_snippet() {
+        let source = "Story:\n    X is 1\n    Y is (\n    Z is 2\n";
+        assert_eq!(
+            source_snippet(source, 3, 10).unwrap(),
+            "1 | Story:\n2 |     X is 1\n3 |     Y is (\n  |          ^"
+        );
+        assert_eq!(source_snippet(source, 1, 1).unwrap(), "1 | Story:\n  | ^");
+        assert!(source_snippet(source, 9, 1).is_none());
+        // 名前 takes four cells but two columns
+        assert_eq!(
+            source_snippet("Story:\n\t名前 is ?\n", 2, 7).unwrap(),
+            "1 | Story:\n2 | \t名前 is ?\n  | \t       ^"
+        );
+    }
+
+    #[test]
+    fn test_tenant_hosts() {
+        let host = |value: &str| {
+            let req = Request::builder()
+                .uri("/")
+                .header("host", value)
+                .body(Body::empty())
+                .unwrap();
+            request_host(&req).unwrap()
+        };
+        assert_eq!(host("Shop.Example.com:8080"), "shop.example.com");
+        assert_eq!(host("[::1]:8080"), "::1");
+
+        let shop = Tenant::new("shop.example.com");
+        assert!(shop.matches("shop.example.com"));
+        assert!(!shop.matches("www.shop.example.com"));
+
+        let blogs = Tenant::new("*.example.com");
+        assert!(blogs.matches("news.example.com"));
+        assert!(!blogs.matches("example.com"));
+        assert!(!blogs.matches("badexample.com"));
+    }
+
+    #[test]
+    fn test_telemetry() {
+        let entry = |route, status, ms| AccessEntry {
+            remote_addr: "10.0.0.7:51234",
+            method: "GET",
+            path: "/users/7?full=1",
+            version: "HTTP/1.1",
+            route,
+            status,
+            bytes: Some(512),
+            duration: Duration::from_millis(ms),
+        };
+
+        let common = format_common_log(&entry("/users/:id", 200, 12));
+        let (host, rest) = common.split_once(" - - [").unwrap();
+        assert_eq!(host, "10.0.0.7");
+        let (_time, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(rest, "\"GET /users/7?full=1 HTTP/1.1\" 200 512 12.000ms");
+        let unsent = AccessEntry {
+            bytes: None,
+            ..entry("/", 304, 1)
+        };
+        assert!(format_common_log(&unsent).contains("\" 304 - 1.000ms"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_json_log(&entry("/users/:id", 404, 3))).unwrap();
+        assert_eq!(json["remote_addr"], "10.0.0.7:51234");
+        assert_eq!(json["path"], "/users/7?full=1");
+        assert_eq!(json["route"], "/users/:id");
+        assert_eq!(json["status"], 404);
+        assert_eq!(json["bytes"], 512);
+        assert_eq!(json["duration_ms"], 3.0);
+        assert!(json["time"].is_string());
+
+        let telemetry = Telemetry::new(TelemetryOptions {
+            log_format: None,
+            metrics: true,
+        });
+        assert!(telemetry.serves_metrics("GET", "/metrics"));
+        assert!(!telemetry.serves_metrics("POST", "/metrics"));
+        telemetry.record(&entry("/users/:id", 200, 3));
+        telemetry.record(&entry("/users/:id", 200, 300));
+        telemetry.record(&entry("/a\"b", 500, 20));
+        let text = telemetry.render_metrics();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "# TYPE sfex_uptime_seconds gauge");
+        assert!(lines[2].starts_with("sfex_uptime_seconds "));
+        for line in [
+            "# TYPE sfex_http_requests_total counter",
+            "sfex_http_requests_total{method=\"GET\",route=\"/a\\\"b\",status=\"500\"} 1",
+            "sfex_http_requests_total{method=\"GET\",route=\"/users/:id\",status=\"200\"} 2",
+            "# TYPE sfex_http_request_duration_seconds histogram",
+            "sfex_http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"0.005\"} 1",
+            "sfex_http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"0.25\"} 1",
+            "sfex_http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"0.5\"} 2",
+            "sfex_http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"+Inf\"} 2",
+            "sfex_http_request_duration_seconds_sum{route=\"/users/:id\"} 0.303000",
+            "sfex_http_request_duration_seconds_count{route=\"/users/:id\"} 2",
+        ] {
+            assert!(lines.contains(&line), "missing {}", line);
         }
-        _ => JsonValue::String(value.to_display_string()),
+        // Routes in order, so scrapes diff cleanly
+        let first = text.find("route=\"/a").unwrap();
+        assert!(first < text.find("route=\"/users").unwrap());
+
+        let off = Telemetry::new(TelemetryOptions::default());
+        assert!(!off.serves_metrics("GET", "/metrics"));
+        assert_eq!(off.render_metrics(), "");
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_cert_reload() {
+        let dir = std::env::temp_dir().join(format!("sfex-cert-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = TlsPaths {
+            cert_path: dir.join("cert.pem").to_string_lossy().into_owned(),
+            key_path: dir.join("key.pem").to_string_lossy().into_owned(),
+        };
+        // Writes a new certificate and key, returning the certificate's DER
+        let issue = |name: &str| {
+            let key = rcgen::KeyPair::generate().unwrap();
+            let cert = rcgen::CertificateParams::new(vec![name.to_string()])
+                .unwrap()
+                .self_signed(&key)
+                .unwrap();
+            fs::write(&paths.cert_path, cert.pem()).unwrap();
+            fs::write(&paths.key_path, key.serialize_pem()).unwrap();
+            cert.der().to_vec()
+        };
+        let served = |cert: &ReloadableCert| cert.current.read_recover().cert[0].0.clone();
+
+        let first = issue("one.example.com");
+        let cert = Arc::new(ReloadableCert::new(load_cert_files(&paths).unwrap()));
+        assert_eq!(served(&cert), first);
+
+        let running = Arc::new(AtomicBool::new(true));
+        spawn_cert_watcher(
+            TlsPaths {
+                cert_path: paths.cert_path.clone(),
+                key_path: paths.key_path.clone(),
+            },
+            cert.clone(),
+            running.clone(),
+        );
+        let wait_for = |expected: &[u8]| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while served(&cert) != expected && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            served(&cert)
+        };
+
+        // A rotated pair is picked up without a restart
+        std::thread::sleep(Duration::from_millis(20));
+        let second = issue("two.example.com");
+        assert_eq!(wait_for(&second), second);
+
+        // A broken certificate is ignored and the last good one kept
+        fs::write(&paths.cert_path, "not a certificate").unwrap();
+        std::thread::sleep(watch::DEFAULT_INTERVAL * 4);
+        assert_eq!(served(&cert), second);
+
+        running.store(false, Ordering::SeqCst);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_options() {
+        let map = |entries: Vec<(&str, Value)>| {
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect::<IndexMap<_, _>>()
+        };
+        let number = |n: i64| Value::Number(bigdecimal::BigDecimal::from(n));
+
+        let mut options = ServerOptions::default();
+        options
+            .apply_map(&map(vec![
+                ("MaxConnections", number(2)),
+                ("MaxHeaderSize", number(16384)),
+                ("ReadTimeout", Value::FastNumber(1.5)),
+                ("RequestTimeout", number(5)),
+                ("MaxSteps", number(1000)),
+                ("KeepAlive", Value::Boolean(false)),
+                ("Http2", Value::Boolean(false)),
+            ]))
+            .unwrap();
+        assert_eq!(options.max_connections, Some(2));
+        assert_eq!(options.max_header_size, Some(16384));
+        assert_eq!(options.read_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(options.request_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(options.handler_limits().max_steps, Some(1000));
+        assert!(!options.keep_alive && !options.http2);
+        // Options not given keep their defaults
+        assert_eq!(options.max_body_size, DEFAULT_MAX_BODY_SIZE);
+        assert_eq!(options.write_timeout, None);
+
+        let fails = |entries| {
+            ServerOptions::default()
+                .apply_map(&map(entries))
+                .unwrap_err()
+        };
+        assert!(fails(vec![("MaxConnections", number(0))]).contains("at least 1"));
+        assert!(fails(vec![("MaxHeaderSize", number(1024))]).contains("8192"));
+        assert!(fails(vec![("ReadTimeout", number(-1))]).contains("positive"));
+        assert!(fails(vec![("MaxBodySize", Value::String("big".into()))]).contains("MaxBodySize"));
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let runtime = executor::shared_runtime();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let options = ServerOptions {
+                max_connections: Some(1),
+                ..ServerOptions::default()
+            };
+            let mut accepted = Box::pin(accept_connections(listener, options));
+            let wait = Duration::from_millis(300);
+
+            let _first_client = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let first = accepted.next().await.unwrap().unwrap();
+            assert!(first.permit.is_some());
+
+            // The second client waits while the only slot is taken...
+            let _second_client = tokio::net::TcpStream::connect(addr).await.unwrap();
+            assert!(tokio::time::timeout(wait, accepted.next()).await.is_err());
+
+            // ...and is accepted once the first connection closes
+            drop(first);
+            let second = tokio::time::timeout(wait, accepted.next()).await;
+            assert!(second.unwrap().unwrap().is_ok());
+        });
+    }
+
+    #[test]
+    fn test_route_reload() {
+        let dir = std::env::temp_dir().join(format!("sfex-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("app.sfex");
+        let routes = |state: &Arc<Mutex<RouterState>>| {
+            state
+                .lock_recover()
+                .routes
+                .iter()
+                .map(|route| route.path.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Reloading runs the script again and swaps in the routes it
+        // registers instead of binding a second server
+        let state = Arc::new(Mutex::new(RouterState::new()));
+        fs::write(
+            &script,
+            "Story:\n    Router is Web.Router()\n    Router.Get(\"/a\", \"a.sfex\")\n    Router.Serve(\"127.0.0.1:1\")\n",
+        )
+        .unwrap();
+        reload_routes(&script, &state).unwrap();
+        assert_eq!(routes(&state), ["/a"]);
+
+        fs::write(
+            &script,
+            "Story:\n    Router is Web.Router()\n    Router.Get(\"/b\", \"b.sfex\")\n    Router.Post(\"/c\", \"c.sfex\")\n    Router.Serve(\"127.0.0.1:1\")\n",
+        )
+        .unwrap();
+        reload_routes(&script, &state).unwrap();
+        assert_eq!(routes(&state), ["/b", "/c"]);
+
+        // A broken script keeps the routes there were
+        fs::write(&script, "Story:\n    Router is (\n").unwrap();
+        assert!(reload_routes(&script, &state).is_err());
+        assert_eq!(routes(&state), ["/b", "/c"]);
+
+        // A router built in a concept method counts, a handler doesn't
+        fs::write(
+            &script,
+            "Concept: App\n    To Start:\n        Router is Web.Router()\n        Router.Serve()\n\nStory:\n    Create App Called Site\n    Site.Start\n",
+        )
+        .unwrap();
+        assert!(program_uses_router(
+            &load_program(&script).unwrap(),
+            &mut Vec::new()
+        ));
+        fs::write(&script, "Story:\n    Response is Web.Text(\"hi\")\n").unwrap();
+        assert!(!program_uses_router(
+            &load_program(&script).unwrap(),
+            &mut Vec::new()
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_serve_hooks() {
+        let dir = std::env::temp_dir().join(format!("sfex-serve-hooks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (boot, shutdown) = (dir.join("boot.sfex"), dir.join("shutdown.sfex"));
+        let app = build_app_value();
+        let state = || {
+            let Value::Map(app) = &app else {
+                panic!("App is not a Map")
+            };
+            match app.read_recover().get("State") {
+                Some(Value::Map(state)) => state.read_recover().clone(),
+                _ => panic!("App has no State"),
+            }
+        };
+
+        // on_start runs before serving and on_stop sees what it defined
+        fs::write(
+            &boot,
+            "Story:\n    Greeting is \"ready\"\n    Set App.State.Started to Greeting\n",
+        )
+        .unwrap();
+        fs::write(&shutdown, "Story:\n    Set App.State.Stopped to Greeting\n").unwrap();
+        let hooks = ServeHooks {
+            on_start: Some(boot.clone()),
+            on_stop: Some(shutdown),
+        };
+        let lifecycle = Lifecycle::start(Some(&hooks), &app).unwrap().unwrap();
+        assert_eq!(state()["Started"].to_display_string(), "ready");
+        assert!(!state().contains_key("Stopped"));
+        lifecycle.stop();
+        assert_eq!(state()["Stopped"].to_display_string(), "ready");
+
+        // Without hooks there is nothing to run, and a failing on_start
+        // keeps the server from starting
+        assert!(Lifecycle::start(None, &app).unwrap().is_none());
+        assert!(
+            Lifecycle::start(Some(&ServeHooks::default()), &app)
+                .unwrap()
+                .is_none()
+        );
+        fs::write(&boot, "Story:\n    Fail is Missing.Call()\n").unwrap();
+        let hooks = ServeHooks {
+            on_start: Some(boot),
+            on_stop: None,
+        };
+        let error = Lifecycle::start(Some(&hooks), &app).err().unwrap();
+        assert!(
+            error.starts_with("on_start failed, not serving"),
+            "{}",
+            error
+        );
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,12 +1,14 @@
 use crate::runtime::interpreter::Interpreter;
+use crate::runtime::lock::MutexExt;
+use crate::runtime::permissions;
 use crate::runtime::value::Value;
 use futures_util::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::sync::{Arc, Mutex};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 
 pub fn create_websocket_module(interpreter: &Interpreter) -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
     let runtime = interpreter.runtime.clone();
 
     // WebSocket.Connect("wss://echo.websocket.org")
@@ -19,6 +21,7 @@ pub fn create_websocket_module(interpreter: &Interpreter) -> Value {
             }
 
             let url = args[0].to_display_string();
+            permissions::check_url(&url)?;
             let runtime = runtime_connect.clone();
 
             let runtime_clone = runtime.clone();
@@ -70,7 +73,7 @@ fn create_websocket_object(
     >,
     runtime: Arc<tokio::runtime::Runtime>,
 ) -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     // Connection.Send("message")
     let write_clone = write.clone();
@@ -86,7 +89,7 @@ fn create_websocket_object(
             let write_clone2 = write_clone.clone();
 
             runtime_send.block_on(async {
-                let mut write_guard = write_clone2.lock().unwrap();
+                let mut write_guard = write_clone2.lock_recover();
                 match write_guard.send(Message::Text(message.into())).await {
                     Ok(_) => Ok(Value::Boolean(true)),
                     Err(e) => Err(format!("Failed to send message: {}", e)),
@@ -108,7 +111,7 @@ fn create_websocket_object(
             let read_clone2 = read_clone.clone();
 
             runtime_recv.block_on(async {
-                let mut read_guard = read_clone2.lock().unwrap();
+                let mut read_guard = read_clone2.lock_recover();
                 match read_guard.next().await {
                     Some(Ok(msg)) => match msg {
                         Message::Text(text) => Ok(Value::String(text.to_string())),
@@ -138,7 +141,7 @@ fn create_websocket_object(
             let write_clone3 = write_close.clone();
 
             runtime_close.block_on(async {
-                let mut write_guard = write_clone3.lock().unwrap();
+                let mut write_guard = write_clone3.lock_recover();
                 match write_guard.send(Message::Close(None)).await {
                     Ok(_) => Ok(Value::Boolean(true)),
                     Err(e) => Err(format!("Failed to close connection: {}", e)),
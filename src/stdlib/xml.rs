@@ -1,5 +1,5 @@
 use crate::runtime::value::Value;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::sync::Arc;
 use sxd_document::parser;
 use sxd_xpath::{Value as XPathValue, evaluate_xpath};
@@ -12,7 +12,7 @@ pub fn parse_xml(xml_content: &str) -> Result<Value, String> {
 }
 
 pub fn create_xml_module() -> Value {
-    let mut methods = HashMap::new();
+    let mut methods = IndexMap::new();
 
     methods.insert(
         "Parse".to_string(),
@@ -31,7 +31,7 @@ pub fn create_xml_module() -> Value {
 
 fn create_document_object(xml: String) -> Value {
     let doc_string = xml.clone();
-    let mut doc_methods = HashMap::new();
+    let mut doc_methods = IndexMap::new();
 
     doc_methods.insert(
         "XPath".to_string(),
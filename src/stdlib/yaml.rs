@@ -0,0 +1,1228 @@
+use crate::runtime::value::Value;
+use crate::stdlib::json::convert_object_to_json;
+use bigdecimal::num_bigint::BigInt;
+use indexmap::IndexMap;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+// The YAML that config files are written in: block and flow collections,
+// plain, quoted and block (`|`, `>`) scalars, comments, anchors, aliases,
+// `<<` merge keys and `---` documents. Plain scalars resolve with the YAML
+// 1.2 core schema, so `yes` and `on` stay text. Complex (`?`) keys aren't
+// supported, and tags other than `!!str` are ignored.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Null,
+    Bool(bool),
+    Int(BigInt),
+    // Decimal digits, kept exact like JSON numbers
+    Float(String),
+    // .inf and .nan, which have no decimal
+    Special(f64),
+    Str(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    number: usize,
+    // Column where `text` starts
+    indent: usize,
+    text: &'a str,
+    raw: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn new(number: usize, raw: &'a str) -> Self {
+        let text = raw.trim_start_matches(' ');
+        Line {
+            number,
+            indent: raw.len() - text.len(),
+            text,
+            raw,
+        }
+    }
+}
+
+fn is_blank(text: &str) -> bool {
+    let text = text.trim_start();
+    text.is_empty() || text.starts_with('#')
+}
+
+fn is_seq_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ") || text.starts_with("-\t")
+}
+
+/// The documents in `text`, split at `---` and `...` lines
+fn documents(text: &str) -> Vec<Vec<Line<'_>>> {
+    let mut documents = Vec::new();
+    let mut current = Vec::new();
+    let mut started = false;
+    let mut ended = false;
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        if raw == "---" || raw.starts_with("--- ") || raw.starts_with("---\t") {
+            if started || current.iter().any(|line: &Line| !is_blank(line.text)) {
+                documents.push(std::mem::take(&mut current));
+            }
+            started = true;
+            ended = false;
+            // `--- |` and `--- value` start the document on the marker line
+            let rest = raw[3..].trim_start();
+            if !is_blank(rest) {
+                current.push(Line {
+                    number,
+                    indent: 0,
+                    text: rest,
+                    raw: rest,
+                });
+            }
+        } else if raw == "..." || raw.starts_with("... ") {
+            if started || current.iter().any(|line: &Line| !is_blank(line.text)) {
+                documents.push(std::mem::take(&mut current));
+            }
+            started = false;
+            ended = true;
+        } else if ended || (raw.starts_with('%') && !started && current.is_empty()) {
+            // Directives, and anything between `...` and the next `---`
+        } else {
+            current.push(Line::new(number, raw));
+        }
+    }
+    if started || current.iter().any(|line| !is_blank(line.text)) {
+        documents.push(current);
+    }
+    documents
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    pos: usize,
+    anchors: HashMap<String, Node>,
+}
+
+impl<'a> Parser<'a> {
+    fn document(lines: Vec<Line<'a>>) -> Result<Node, String> {
+        let mut parser = Parser {
+            lines,
+            pos: 0,
+            anchors: HashMap::new(),
+        };
+        let node = parser.block(0)?;
+        parser.skip_blank();
+        if let Some(line) = parser.lines.get(parser.pos) {
+            return Err(format!(
+                "line {}: unexpected indentation or text",
+                line.number
+            ));
+        }
+        Ok(node)
+    }
+
+    fn skip_blank(&mut self) {
+        while self
+            .lines
+            .get(self.pos)
+            .is_some_and(|line| is_blank(line.text))
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// The node starting at the next line, if it is indented at least `min`
+    fn block(&mut self, min: usize) -> Result<Node, String> {
+        self.skip_blank();
+        let Some(line) = self.lines.get(self.pos).copied() else {
+            return Ok(Node::Null);
+        };
+        if line.indent < min {
+            return Ok(Node::Null);
+        }
+        if line.text.starts_with('\t') {
+            return Err(format!(
+                "line {}: tabs can't be used for indentation",
+                line.number
+            ));
+        }
+        if is_seq_item(line.text) {
+            self.sequence(line.indent)
+        } else if split_key(line.text)?.is_some() {
+            self.mapping(line.indent)
+        } else {
+            self.inline(line.text, min)
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Node, String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            let Some(line) = self.lines.get(self.pos).copied() else {
+                break;
+            };
+            if line.indent != indent || !is_seq_item(line.text) {
+                break;
+            }
+            let rest = line.text[1..].trim_start();
+            if is_blank(rest) {
+                self.pos += 1;
+                items.push(self.block(indent + 1)?);
+                continue;
+            }
+            // `- key: value` and `- - item` start a collection on this line
+            let column = indent + line.text.len() - rest.len();
+            self.lines[self.pos] = Line {
+                indent: column,
+                text: rest,
+                ..line
+            };
+            let item = if is_seq_item(rest) {
+                self.sequence(column)?
+            } else if split_key(rest)?.is_some() {
+                self.mapping(column)?
+            } else {
+                self.inline(rest, indent + 1)?
+            };
+            items.push(item);
+        }
+        Ok(Node::Seq(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Node, String> {
+        let mut entries: Vec<(String, Node)> = Vec::new();
+        let mut merges = Vec::new();
+        loop {
+            self.skip_blank();
+            let Some(line) = self.lines.get(self.pos).copied() else {
+                break;
+            };
+            if line.indent != indent || is_seq_item(line.text) {
+                break;
+            }
+            let Some((key, after)) = split_key(line.text)? else {
+                return Err(format!(
+                    "line {}: expected `key: value` like the lines above",
+                    line.number
+                ));
+            };
+            let rest = after.trim_start();
+            let value = if is_blank(rest) {
+                self.pos += 1;
+                self.skip_blank();
+                match self.lines.get(self.pos) {
+                    Some(next) if next.indent > indent => self.block(indent + 1)?,
+                    // A list may sit at the same indentation as its key
+                    Some(next) if next.indent == indent && is_seq_item(next.text) => {
+                        self.sequence(indent)?
+                    }
+                    _ => Node::Null,
+                }
+            } else {
+                self.lines[self.pos] = Line {
+                    indent: line.indent + line.text.len() - rest.len(),
+                    text: rest,
+                    ..line
+                };
+                self.inline(rest, indent + 1)?
+            };
+            if key == "<<" {
+                merges.push(value);
+            } else if let Some(entry) = entries.iter_mut().find(|(k, _)| *k == key) {
+                entry.1 = value;
+            } else {
+                entries.push((key, value));
+            }
+        }
+        // `<<: *defaults` copies the keys not given here
+        for merge in merges {
+            let maps = match merge {
+                Node::Seq(items) => items,
+                other => vec![other],
+            };
+            for map in maps {
+                let Node::Map(merged) = map else {
+                    return Err("`<<` must merge a mapping or a list of mappings".to_string());
+                };
+                for (key, value) in merged {
+                    if !entries.iter().any(|(k, _)| *k == key) {
+                        entries.push((key, value));
+                    }
+                }
+            }
+        }
+        Ok(Node::Map(entries))
+    }
+
+    /// A value that starts on the current line at `text`; lines that
+    /// continue it are indented at least `min`.
+    fn inline(&mut self, text: &str, min: usize) -> Result<Node, String> {
+        let number = self.lines[self.pos].number;
+        let (anchor, tag, rest) = properties(text);
+        let node = if is_blank(rest) {
+            self.pos += 1;
+            self.block(min)?
+        } else if let Some(name) = rest.strip_prefix('*') {
+            self.pos += 1;
+            let name = strip_comment(name).trim_end();
+            if name.contains(char::is_whitespace) {
+                return Err(format!("line {}: unexpected text after *{}", number, name));
+            }
+            self.anchors
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("line {}: unknown alias *{}", number, name))?
+        } else if rest.starts_with('|') || rest.starts_with('>') {
+            self.block_scalar(rest, min)?
+        } else if rest.starts_with('[') || rest.starts_with('{') {
+            self.flow(rest)?
+        } else if rest.starts_with('"') || rest.starts_with('\'') {
+            self.quoted(rest)?
+        } else {
+            let plain = self.plain(rest, min);
+            if tag == Some("!!str") {
+                Node::Str(plain)
+            } else {
+                resolve(&plain)
+            }
+        };
+        if let Some(anchor) = anchor {
+            self.anchors.insert(anchor.to_string(), node.clone());
+        }
+        Ok(node)
+    }
+
+    fn plain(&mut self, text: &str, min: usize) -> String {
+        let mut value = strip_comment(text).trim_end().to_string();
+        self.pos += 1;
+        // Lines that continue a plain scalar fold into spaces
+        let mut newlines = 0;
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.text.trim().is_empty() {
+                newlines += 1;
+                self.pos += 1;
+                continue;
+            }
+            // A comment ends the scalar, and `key:` or `- ` lines are
+            // misplaced rather than more text
+            if line.indent < min
+                || line.text.starts_with('#')
+                || is_seq_item(line.text)
+                || split_key(line.text).ok().flatten().is_some()
+            {
+                break;
+            }
+            value.push_str(&if newlines == 0 {
+                " ".to_string()
+            } else {
+                "\n".repeat(newlines)
+            });
+            value.push_str(strip_comment(line.text.trim()).trim_end());
+            newlines = 0;
+            self.pos += 1;
+        }
+        value
+    }
+
+    fn quoted(&mut self, text: &str) -> Result<Node, String> {
+        let number = self.lines[self.pos].number;
+        let quote = text.chars().next().unwrap_or('"');
+        let mut body = text[1..].to_string();
+        self.pos += 1;
+        loop {
+            if let Some(end) = find_close(&body, quote) {
+                if !is_blank(&body[end + 1..]) {
+                    return Err(format!(
+                        "line {}: unexpected text after a quoted string",
+                        number
+                    ));
+                }
+                return unquote(&body[..end], quote)
+                    .map(Node::Str)
+                    .map_err(|e| format!("line {}: {}", number, e));
+            }
+            let Some(line) = self.lines.get(self.pos) else {
+                return Err(format!("line {}: the quoted string never ends", number));
+            };
+            body.push('\n');
+            body.push_str(line.raw.trim());
+            self.pos += 1;
+        }
+    }
+
+    fn flow(&mut self, text: &str) -> Result<Node, String> {
+        let number = self.lines[self.pos].number;
+        let mut body = text.to_string();
+        self.pos += 1;
+        while !flow_closed(&body) {
+            let Some(line) = self.lines.get(self.pos) else {
+                return Err(format!("line {}: the [ or {{ is never closed", number));
+            };
+            body.push('\n');
+            body.push_str(line.raw);
+            self.pos += 1;
+        }
+        let mut flow = Flow {
+            chars: body.chars().collect(),
+            at: 0,
+            anchors: &mut self.anchors,
+        };
+        let node = flow
+            .value()
+            .map_err(|e| format!("line {}: {}", number, e))?;
+        flow.space();
+        if flow.at < flow.chars.len() {
+            return Err(format!(
+                "line {}: unexpected text after a flow collection",
+                number
+            ));
+        }
+        Ok(node)
+    }
+
+    fn block_scalar(&mut self, header: &str, min: usize) -> Result<Node, String> {
+        let number = self.lines[self.pos].number;
+        let literal = header.starts_with('|');
+        let mut chomp = ' ';
+        let mut explicit = None;
+        let header = strip_comment(&header[1..]);
+        for c in header.trim().chars() {
+            match c {
+                '-' | '+' => chomp = c,
+                '1'..='9' => explicit = c.to_digit(10).map(|d| d as usize),
+                _ => {
+                    return Err(format!(
+                        "line {}: expected `|` or `>` with `-`, `+` or an indentation",
+                        number
+                    ));
+                }
+            }
+        }
+        self.pos += 1;
+
+        let indent = match explicit {
+            Some(digits) => min.saturating_sub(1) + digits,
+            None => self.lines[self.pos..]
+                .iter()
+                .find(|line| !line.raw.trim().is_empty())
+                .map(|line| line.indent)
+                .unwrap_or(min),
+        }
+        .max(min);
+        let mut lines = Vec::new();
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.raw.trim().is_empty() {
+                lines.push(line.raw.get(indent..).unwrap_or(""));
+            } else if line.indent >= indent {
+                lines.push(&line.raw[indent..]);
+            } else {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        let trailing = lines
+            .iter()
+            .rev()
+            .take_while(|line| line.is_empty())
+            .count();
+        let body = &lines[..lines.len() - trailing];
+        let mut text = if literal { body.join("\n") } else { fold(body) };
+        match chomp {
+            '-' => {}
+            '+' => text.push_str(&"\n".repeat(trailing + usize::from(!body.is_empty()))),
+            _ if !body.is_empty() => text.push('\n'),
+            _ => {}
+        }
+        Ok(Node::Str(text))
+    }
+}
+
+/// `&anchor` and `!tag` in front of a value, in either order
+fn properties(text: &str) -> (Option<&str>, Option<&str>, &str) {
+    let mut anchor = None;
+    let mut tag = None;
+    let mut rest = text;
+    loop {
+        let property = if rest.starts_with('&') {
+            &mut anchor
+        } else if rest.starts_with('!') {
+            &mut tag
+        } else {
+            break;
+        };
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        *property = Some(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    (anchor.map(|a| &a[1..]), tag, rest)
+}
+
+/// The key of a `key: value` line, and the text after its colon
+fn split_key(text: &str) -> Result<Option<(String, &str)>, String> {
+    if text.starts_with('"') || text.starts_with('\'') {
+        let quote = text.chars().next().unwrap_or('"');
+        let Some(end) = find_close(&text[1..], quote) else {
+            return Ok(None);
+        };
+        let after = text[end + 2..].trim_start();
+        return match after.strip_prefix(':') {
+            Some(rest) if rest.is_empty() || rest.starts_with([' ', '\t']) => {
+                Ok(Some((unquote(&text[1..end + 1], quote)?, rest)))
+            }
+            _ => Ok(None),
+        };
+    }
+    if text.starts_with(['[', '{', '#', '|', '>', '*']) {
+        return Ok(None);
+    }
+    if text.starts_with("? ") {
+        return Err("complex keys (`? key`) aren't supported".to_string());
+    }
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'#' && (i == 0 || bytes[i - 1] == b' ' || bytes[i - 1] == b'\t') {
+            return Ok(None);
+        }
+        if b == b':'
+            && bytes
+                .get(i + 1)
+                .is_none_or(|&next| next == b' ' || next == b'\t')
+        {
+            let (_, _, key) = properties(text[..i].trim_end());
+            return Ok(Some((key.to_string(), &text[i + 1..])));
+        }
+    }
+    Ok(None)
+}
+
+fn strip_comment(text: &str) -> &str {
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'#' && (i == 0 || bytes[i - 1] == b' ' || bytes[i - 1] == b'\t') {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// Where the closing quote is, skipping `\"` or `''`
+fn find_close(body: &str, quote: char) -> Option<usize> {
+    let mut chars = body.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if quote == '"' && c == '\\' {
+            chars.next();
+        } else if c == quote {
+            if quote == '\'' && chars.peek().is_some_and(|&(_, next)| next == '\'') {
+                chars.next();
+            } else {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// The text of a quoted scalar: line breaks fold into spaces (a blank line
+/// is a newline), then escapes are applied
+fn unquote(body: &str, quote: char) -> Result<String, String> {
+    let segments: Vec<&str> = body.split('\n').collect();
+    let last = segments.len() - 1;
+    let mut folded = String::new();
+    let mut empties = 0;
+    let mut joined = false;
+    for (i, segment) in segments.iter().enumerate() {
+        let segment = match (i == 0, i == last) {
+            (true, true) => segment,
+            (true, false) => segment.trim_end(),
+            (false, true) => segment.trim_start(),
+            (false, false) => segment.trim(),
+        };
+        if i > 0 && i < last && segment.is_empty() {
+            empties += 1;
+            continue;
+        }
+        if i > 0 && !joined {
+            if empties > 0 {
+                folded.push_str(&"\n".repeat(empties));
+            } else {
+                folded.push(' ');
+            }
+        }
+        empties = 0;
+        // A `\` at the end of a line joins it to the next without a space
+        let backslashes = segment.len() - segment.trim_end_matches('\\').len();
+        joined = quote == '"' && i < last && backslashes % 2 == 1;
+        folded.push_str(if joined {
+            &segment[..segment.len() - 1]
+        } else {
+            segment
+        });
+    }
+    if quote == '\'' {
+        return Ok(folded.replace("''", "'"));
+    }
+
+    let mut text = String::new();
+    let mut chars = folded.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('0') => '\0',
+            Some('a') => '\u{7}',
+            Some('b') => '\u{8}',
+            Some('t') | Some('\t') => '\t',
+            Some('n') => '\n',
+            Some('v') => '\u{b}',
+            Some('f') => '\u{c}',
+            Some('r') => '\r',
+            Some('e') => '\u{1b}',
+            Some(' ') => ' ',
+            Some('"') => '"',
+            Some('/') => '/',
+            Some('\\') => '\\',
+            Some('N') => '\u{85}',
+            Some('_') => '\u{a0}',
+            Some('L') => '\u{2028}',
+            Some('P') => '\u{2029}',
+            Some(kind @ ('x' | 'u' | 'U')) => {
+                let len = match kind {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let digits: String = chars.by_ref().take(len).collect();
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() == len)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid escape \\{}{}", kind, digits))?
+            }
+            Some(other) => return Err(format!("invalid escape \\{}", other)),
+            None => return Err("a quoted string can't end with \\".to_string()),
+        };
+        text.push(escaped);
+    }
+    Ok(text)
+}
+
+/// Folded (`>`) block text: lines join with spaces, blank lines are
+/// newlines, and more indented lines keep their line breaks
+fn fold(lines: &[&str]) -> String {
+    let mut text = String::new();
+    let mut empties = 0;
+    let mut previous: Option<bool> = None;
+    for line in lines {
+        if line.is_empty() {
+            empties += 1;
+            continue;
+        }
+        let indented = line.starts_with([' ', '\t']);
+        match previous {
+            None => text.push_str(&"\n".repeat(empties)),
+            Some(was_indented) if empties == 0 => {
+                text.push(if indented || was_indented { '\n' } else { ' ' });
+            }
+            Some(was_indented) => {
+                let extra = usize::from(indented || was_indented);
+                text.push_str(&"\n".repeat(empties + extra));
+            }
+        }
+        text.push_str(line);
+        previous = Some(indented);
+        empties = 0;
+    }
+    text
+}
+
+/// Whether every `[` and `{` in `text` is closed
+fn flow_closed(text: &str) -> bool {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut previous = ' ';
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some('"') if c == '\\' => {
+                chars.next();
+            }
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth = depth.saturating_sub(1),
+                '#' if previous.is_whitespace() => {
+                    for skipped in chars.by_ref() {
+                        if skipped == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+        }
+        previous = c;
+    }
+    depth == 0
+}
+
+/// `[a, b]` and `{a: 1}`, which may span lines
+struct Flow<'p> {
+    chars: Vec<char>,
+    at: usize,
+    anchors: &'p mut HashMap<String, Node>,
+}
+
+impl Flow<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.at += 1;
+            } else if c == '#' && (self.at == 0 || self.chars[self.at - 1].is_whitespace()) {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.at += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.at;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !",[]{}".contains(c))
+        {
+            self.at += 1;
+        }
+        self.chars[start..self.at].iter().collect()
+    }
+
+    fn value(&mut self) -> Result<Node, String> {
+        self.space();
+        let mut anchor = None;
+        let mut tag = None;
+        loop {
+            match self.peek() {
+                Some('&') => {
+                    self.at += 1;
+                    anchor = Some(self.word());
+                }
+                Some('!') => tag = Some(self.word()),
+                _ => break,
+            }
+            self.space();
+        }
+        let node = match self.peek() {
+            Some('[') => self.sequence()?,
+            Some('{') => self.mapping()?,
+            Some(quote @ ('"' | '\'')) => Node::Str(self.quoted(quote)?),
+            Some('*') => {
+                self.at += 1;
+                let name = self.word();
+                self.anchors
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| format!("unknown alias *{}", name))?
+            }
+            _ => {
+                let plain = self.plain();
+                if tag.as_deref() == Some("!!str") {
+                    Node::Str(plain)
+                } else {
+                    resolve(&plain)
+                }
+            }
+        };
+        if let Some(anchor) = anchor {
+            self.anchors.insert(anchor, node.clone());
+        }
+        Ok(node)
+    }
+
+    fn quoted(&mut self, quote: char) -> Result<String, String> {
+        let rest: String = self.chars[self.at + 1..].iter().collect();
+        let end = find_close(&rest, quote).ok_or("the quoted string never ends")?;
+        self.at += 1 + rest[..end].chars().count() + 1;
+        unquote(&rest[..end], quote)
+    }
+
+    fn plain(&mut self) -> String {
+        let start = self.at;
+        while let Some(c) = self.peek() {
+            let next = self.chars.get(self.at + 1).copied();
+            let ends_key =
+                c == ':' && next.is_none_or(|n| n.is_whitespace() || ",[]{}".contains(n));
+            let comment = c == '#' && self.at > start && self.chars[self.at - 1].is_whitespace();
+            if ",[]{}".contains(c) || ends_key || comment {
+                break;
+            }
+            self.at += 1;
+        }
+        let text: String = self.chars[start..self.at].iter().collect();
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn expect_separator(&mut self, close: char) -> Result<bool, String> {
+        self.space();
+        match self.peek() {
+            Some(',') => {
+                self.at += 1;
+                Ok(false)
+            }
+            Some(c) if c == close => {
+                self.at += 1;
+                Ok(true)
+            }
+            Some(c) => Err(format!("expected `,` or `{}`, found `{}`", close, c)),
+            None => Err(format!("expected `{}`", close)),
+        }
+    }
+
+    fn sequence(&mut self) -> Result<Node, String> {
+        self.at += 1;
+        let mut items = Vec::new();
+        loop {
+            self.space();
+            if self.peek() == Some(']') {
+                self.at += 1;
+                break;
+            }
+            let item = self.value()?;
+            self.space();
+            // [a: 1, b: 2] is a list of single-key mappings
+            let item = if self.peek() == Some(':') {
+                self.at += 1;
+                let value = self.pair_value()?;
+                Node::Map(vec![(key_text(&item), value)])
+            } else {
+                item
+            };
+            items.push(item);
+            if self.expect_separator(']')? {
+                break;
+            }
+        }
+        Ok(Node::Seq(items))
+    }
+
+    fn mapping(&mut self) -> Result<Node, String> {
+        self.at += 1;
+        let mut entries: Vec<(String, Node)> = Vec::new();
+        loop {
+            self.space();
+            if self.peek() == Some('}') {
+                self.at += 1;
+                break;
+            }
+            let key = key_text(&self.value()?);
+            self.space();
+            let value = if self.peek() == Some(':') {
+                self.at += 1;
+                self.pair_value()?
+            } else {
+                Node::Null
+            };
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+            if self.expect_separator('}')? {
+                break;
+            }
+        }
+        Ok(Node::Map(entries))
+    }
+
+    fn pair_value(&mut self) -> Result<Node, String> {
+        self.space();
+        match self.peek() {
+            Some(',' | ']' | '}') | None => Ok(Node::Null),
+            _ => self.value(),
+        }
+    }
+}
+
+/// A key as text, the way it was written for numbers and booleans
+fn key_text(node: &Node) -> String {
+    match node {
+        Node::Null => String::new(),
+        Node::Bool(b) => b.to_string(),
+        Node::Int(i) => i.to_string(),
+        Node::Float(f) => f.clone(),
+        Node::Special(f) => f.to_string(),
+        Node::Str(s) => s.clone(),
+        Node::Seq(_) | Node::Map(_) => format!("{:?}", node),
+    }
+}
+
+/// What a plain scalar means under the YAML 1.2 core schema
+fn resolve(text: &str) -> Node {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Node::Null,
+        "true" | "True" | "TRUE" => return Node::Bool(true),
+        "false" | "False" | "FALSE" => return Node::Bool(false),
+        ".inf" | ".Inf" | ".INF" | "+.inf" | "+.Inf" | "+.INF" => {
+            return Node::Special(f64::INFINITY);
+        }
+        "-.inf" | "-.Inf" | "-.INF" => return Node::Special(f64::NEG_INFINITY),
+        ".nan" | ".NaN" | ".NAN" => return Node::Special(f64::NAN),
+        _ => {}
+    }
+    if let Some(hex) = text.strip_prefix("0x")
+        && let Some(i) = BigInt::parse_bytes(hex.as_bytes(), 16)
+    {
+        return Node::Int(i);
+    }
+    if let Some(octal) = text.strip_prefix("0o")
+        && let Some(i) = BigInt::parse_bytes(octal.as_bytes(), 8)
+    {
+        return Node::Int(i);
+    }
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    if !unsigned.is_empty()
+        && unsigned.bytes().all(|b| b.is_ascii_digit())
+        && let Ok(i) = BigInt::from_str(text.trim_start_matches('+'))
+    {
+        return Node::Int(i);
+    }
+    if is_float(unsigned) {
+        let sign = if text.starts_with('-') { "-" } else { "" };
+        // BigDecimal wants a digit on each side of the point
+        let mut digits = unsigned.to_string();
+        if digits.starts_with('.') {
+            digits.insert(0, '0');
+        }
+        if let Some(point) = digits.find(".e").or(digits.find(".E")) {
+            digits.insert(point + 1, '0');
+        } else if digits.ends_with('.') {
+            digits.push('0');
+        }
+        return Node::Float(format!("{}{}", sign, digits));
+    }
+    Node::Str(text.to_string())
+}
+
+// [0-9]+(\.[0-9]*)?([eE][-+]?[0-9]+)? or \.[0-9]+([eE][-+]?[0-9]+)?
+fn is_float(text: &str) -> bool {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(at) => (&text[..at], Some(&text[at + 1..])),
+        None => (text, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let mantissa_ok = digits(whole)
+        && fraction.is_none_or(digits)
+        && (!whole.is_empty() || fraction.is_some_and(|f| !f.is_empty()));
+    let exponent_ok = exponent.is_none_or(|e| {
+        let e = e.strip_prefix(['-', '+']).unwrap_or(e);
+        !e.is_empty() && digits(e)
+    });
+    mantissa_ok && exponent_ok && (fraction.is_some() || exponent.is_some())
+}
+
+/// Nodes become values the way JSON.Parse makes them: null is False,
+/// whole numbers are Integers and other numbers are exact Numbers
+fn to_value(node: Node) -> Value {
+    match node {
+        Node::Null => Value::Boolean(false),
+        Node::Bool(b) => Value::Boolean(b),
+        Node::Int(i) => Value::Integer(i),
+        Node::Float(digits) => {
+            Value::from_number_string(&digits).unwrap_or(Value::default_number())
+        }
+        Node::Special(f) => Value::FastNumber(f),
+        Node::Str(s) => Value::String(s),
+        Node::Seq(items) => {
+            let list = items.into_iter().map(to_value).collect();
+            Value::List(Arc::new(RwLock::new(list)))
+        }
+        Node::Map(entries) => {
+            let map = entries
+                .into_iter()
+                .map(|(key, value)| (key, to_value(value)))
+                .collect();
+            Value::Map(Arc::new(RwLock::new(map)))
+        }
+    }
+}
+
+pub fn parse_all(text: &str) -> Result<Vec<Value>, String> {
+    documents(text)
+        .into_iter()
+        .map(|lines| Parser::document(lines).map(to_value))
+        .collect()
+}
+
+/// The one document in `text`; an empty text is False, like `null`
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut documents = parse_all(text)?;
+    match documents.len() {
+        0 => Ok(Value::Boolean(false)),
+        1 => Ok(documents.remove(0)),
+        n => Err(format!(
+            "found {} documents separated by ---; use YAML.ParseAll",
+            n
+        )),
+    }
+}
+
+/// Block-style YAML for `value`, with the conversions of JSON.Stringify
+/// and Map keys in sorted order
+pub fn stringify(value: &Value) -> String {
+    let json = convert_object_to_json(value);
+    let mut out = String::new();
+    match &json {
+        JsonValue::Object(map) if !map.is_empty() => emit_map(map, 0, false, &mut out),
+        JsonValue::Array(items) if !items.is_empty() => emit_seq(items, 0, false, &mut out),
+        other => {
+            out.push_str(&scalar(other, 0));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn emit_map(
+    map: &serde_json::Map<String, JsonValue>,
+    indent: usize,
+    inline_first: bool,
+    out: &mut String,
+) {
+    for (i, key) in map.keys().enumerate() {
+        if i > 0 || !inline_first {
+            out.push_str(&" ".repeat(indent));
+        }
+        out.push_str(&quote_if_needed(key));
+        out.push(':');
+        match &map[key] {
+            JsonValue::Object(inner) if !inner.is_empty() => {
+                out.push('\n');
+                emit_map(inner, indent + 2, false, out);
+            }
+            JsonValue::Array(items) if !items.is_empty() => {
+                out.push('\n');
+                emit_seq(items, indent + 2, false, out);
+            }
+            other => {
+                out.push(' ');
+                out.push_str(&scalar(other, indent + 2));
+                out.push('\n');
+            }
+        }
+    }
+}
+
+fn emit_seq(items: &[JsonValue], indent: usize, inline_first: bool, out: &mut String) {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 || !inline_first {
+            out.push_str(&" ".repeat(indent));
+        }
+        out.push_str("- ");
+        match item {
+            JsonValue::Object(inner) if !inner.is_empty() => emit_map(inner, indent + 2, true, out),
+            JsonValue::Array(inner) if !inner.is_empty() => emit_seq(inner, indent + 2, true, out),
+            other => {
+                out.push_str(&scalar(other, indent + 2));
+                out.push('\n');
+            }
+        }
+    }
+}
+
+/// A value on one line, or a `|` block indented by `indent` for text with
+/// line breaks
+fn scalar(value: &JsonValue, indent: usize) -> String {
+    match value {
+        JsonValue::Null => "null".to_string(),
+        JsonValue::Bool(b) => b.to_string(),
+        JsonValue::Number(n) => n.to_string(),
+        JsonValue::String(s) if literal_block(s) => {
+            let (body, header) = match s.strip_suffix('\n') {
+                Some(body) => (body, "|"),
+                None => (s.as_str(), "|-"),
+            };
+            let mut block = header.to_string();
+            for line in body.split('\n') {
+                block.push('\n');
+                if !line.is_empty() {
+                    block.push_str(&" ".repeat(indent.max(2)));
+                    block.push_str(line);
+                }
+            }
+            block
+        }
+        JsonValue::String(s) => quote_if_needed(s),
+        JsonValue::Array(_) => "[]".to_string(),
+        JsonValue::Object(_) => "{}".to_string(),
+    }
+}
+
+fn literal_block(s: &str) -> bool {
+    s.contains('\n')
+        && !s.starts_with([' ', '\t', '\n'])
+        && !s.ends_with("\n\n")
+        && s.chars().all(|c| c == '\n' || c == '\t' || !c.is_control())
+        && s.split('\n')
+            .all(|line| line.is_empty() || !line.trim().is_empty())
+}
+
+fn quote_if_needed(s: &str) -> String {
+    let plain = !s.is_empty()
+        && matches!(resolve(s), Node::Str(_))
+        && !s.starts_with([
+            '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%',
+            '@', '`', ' ',
+        ])
+        && !s.ends_with([' ', ':'])
+        && !s.contains(": ")
+        && !s.contains(" #")
+        && s.chars().all(|c| !c.is_control());
+    if plain {
+        return s.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn create_yaml_module() -> Value {
+    let mut methods = IndexMap::new();
+
+    methods.insert(
+        "Parse".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("YAML.Parse requires 1 argument".to_string());
+            }
+            parse(&args[0].to_display_string()).map_err(|e| format!("YAML Parse Error: {}", e))
+        }))),
+    );
+
+    // YAML.ParseAll(text) -> List with a value per `---` document
+    methods.insert(
+        "ParseAll".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("YAML.ParseAll requires 1 argument".to_string());
+            }
+            let documents = parse_all(&args[0].to_display_string())
+                .map_err(|e| format!("YAML Parse Error: {}", e))?;
+            Ok(Value::List(Arc::new(RwLock::new(documents))))
+        }))),
+    );
+
+    methods.insert(
+        "Stringify".to_string(),
+        Value::NativeFunction(Arc::new(Box::new(|args| {
+            if args.len() != 1 {
+                return Err("YAML.Stringify requires 1 argument".to_string());
+            }
+            Ok(Value::String(stringify(&args[0])))
+        }))),
+    );
+
+    Value::Map(Arc::new(RwLock::new(methods)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(text: &str) -> JsonValue {
+        convert_object_to_json(&parse(text).unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+# Deployment
+defaults: &defaults
+  image: "app:1.2"   # pinned
+  replicas: 2
+services:
+  web:
+    <<: *defaults
+    replicas: 3
+    ports: [80, 443]
+    env: {DEBUG: false, RATIO: 0.5}
+  jobs:
+  - name: backup
+    schedule: '0 3 * * *'
+  - name: report
+    enabled: ~
+script: |
+  echo one
+  echo two
+summary: >-
+  folded
+  text
+
+  next
+"#;
+        assert_eq!(
+            json(text),
+            serde_json::json!({
+                "defaults": {"image": "app:1.2", "replicas": 2},
+                "services": {
+                    "web": {
+                        "replicas": 3,
+                        "ports": [80, 443],
+                        "env": {"DEBUG": false, "RATIO": 0.5},
+                        "image": "app:1.2",
+                    },
+                    "jobs": [
+                        {"name": "backup", "schedule": "0 3 * * *"},
+                        {"name": "report", "enabled": false},
+                    ],
+                },
+                "script": "echo one\necho two\n",
+                "summary": "folded text\nnext",
+            })
+        );
+        assert_eq!(
+            json("- yes\n- \"a\\tb\"\n- - 1\n  - 2"),
+            serde_json::json!(["yes", "a\tb", [1, 2]])
+        );
+        assert_eq!(parse_all("a: 1\n---\nb: 2\n").unwrap().len(), 2);
+        assert!(parse("a: 1\n  b: 2").is_err());
+        assert!(parse("a: [1, 2").is_err());
+    }
+
+    #[test]
+    fn test_stringify_round_trip() {
+        let text = "name: SFX\nnotes: |\n  line one\n  line two\nports:\n  - 80\n  - 443\nservers:\n  - host: a.example\n    tags: []\nversion: \"1.0\"\nwho: \"yes: no\"\n";
+        let value = parse(text).unwrap();
+        assert_eq!(stringify(&value), text);
+        assert_eq!(
+            convert_object_to_json(&parse(&stringify(&value)).unwrap()),
+            convert_object_to_json(&value)
+        );
+    }
+}
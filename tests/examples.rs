@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Every examples/**/*.sfex with a sibling .out file is a golden test: the
+// script is run with `sfex run --expect` and its stdout must match the .out
+// file line for line. Regenerate a golden with
+// `sfex run --quiet examples/foo.sfex > examples/foo.out`. Markdown files
+// with a .out are literate scripts and run with `--literate`.
+
+fn collect_examples(dir: &Path, found: &mut Vec<PathBuf>) {
+    let entries = std::fs::read_dir(dir).expect("examples directory is readable");
+    for entry in entries {
+        let path = entry.expect("examples entry is readable").path();
+        if path.is_dir() {
+            collect_examples(&path, found);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "sfex" || ext == "md")
+            && path.with_extension("out").exists()
+        {
+            found.push(path);
+        }
+    }
+}
+
+#[test]
+fn golden_examples() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut examples = Vec::new();
+    collect_examples(&root, &mut examples);
+    examples.sort();
+    assert!(
+        !examples.is_empty(),
+        "no examples found in {}",
+        root.display()
+    );
+
+    let mut failed = Vec::new();
+    for example in &examples {
+        let output = Command::new(env!("CARGO_BIN_EXE_sfex"))
+            .arg("run")
+            .args(
+                example
+                    .extension()
+                    .filter(|ext| *ext == "md")
+                    .map(|_| "--literate"),
+            )
+            .arg(example)
+            .arg("--expect")
+            .arg(example.with_extension("out"))
+            .output()
+            .expect("failed to launch sfex");
+
+        if !output.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&output.stdout));
+            eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+            failed.push(example.display().to_string());
+        }
+    }
+
+    assert!(
+        failed.is_empty(),
+        "{} of {} examples failed:\n  {}",
+        failed.len(),
+        examples.len(),
+        failed.join("\n  ")
+    );
+}